@@ -1,12 +1,17 @@
 #!/usr/bin/env python3
+import logging
+
 from landlab.components.overland_flow import OverlandFlow
 from landlab.components import SoilInfiltrationGreenAmpt
 from meillionen.meillionen import server_respond_from_cli
 from meillionen.handlers import LandLabGridHandler, PandasHandler, NetCDFSliceHandler
 from meillionen.function import FuncInterfaceServer, FuncRequest
+from meillionen import transforms
 import pandas as pd
 import xarray as xr
 
+logger = logging.getLogger(__name__)
+
 model = FuncInterfaceServer(
     sources={
         'weather': PandasHandler.from_kwargs(
@@ -74,6 +79,7 @@ def run_year(mg, weather: pd.DataFrame, swid):
     :param swid: reference to data cube with x, y, time dimensions used to incrementally save model results
     """
     for t, row in weather.iterrows():
+        logger.debug('day %s rainfall %s', t, row.rainfall__depth)
         infiltration_depth = run_day(mg, row.rainfall__depth)
         swid.set({'time': t}, infiltration_depth)
 
@@ -84,14 +90,14 @@ def run_year_cli():
     in response to a request on the command line
     """
     rb = server_respond_from_cli('overlandflow', model.to_recordbatch('overlandflow'))
-    print(rb.to_pandas()[['resource', 'payload']])
+    logger.debug('request %s', rb.to_pandas()[['resource', 'payload']])
     args = FuncRequest.from_recordbatch(rb)
     weather = args.source('weather')
     elevation = args.source('elevation')
     surface_water_infiltation__depth = args.sink('soil_water_infiltration__depth')
 
     model_grid = model.source('elevation').load(elevation)
-    weather = model.source('weather').load(weather)
+    weather = transforms.apply('weather', model.source('weather').load(weather))
     swid_size = {'x': model_grid.shape[0], 'y': model_grid.shape[1], 'time': weather.shape[0]}
     with model.sink('soil_water_infiltration__depth').save(surface_water_infiltation__depth, swid_size) as swid:
         run_year(
@@ -0,0 +1,30 @@
+"""Fail if any benchmark got slower than its saved baseline
+
+Run the benchmarks against a baseline first, for example
+
+    cargo bench -p simplecrop-omf --benches -- --baseline main
+    python benches/check_regressions.py target/criterion 0.10
+"""
+import json
+import pathlib
+import sys
+
+
+def regressions(criterion_dir: pathlib.Path, threshold: float):
+    for estimates in sorted(criterion_dir.glob('**/change/estimates.json')):
+        change = json.loads(estimates.read_text())['mean']['point_estimate']
+        if change > threshold:
+            yield estimates.parent.parent.relative_to(criterion_dir), change
+
+
+def main():
+    criterion_dir = pathlib.Path(sys.argv[1] if len(sys.argv) > 1 else 'target/criterion')
+    threshold = float(sys.argv[2]) if len(sys.argv) > 2 else 0.10
+    slower = list(regressions(criterion_dir, threshold))
+    for name, change in slower:
+        print(f'{name}: {change:+.1%}')
+    sys.exit(1 if slower else 0)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,38 @@
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use simplecrop_omf::model::load_output_data;
+
+/// Results reach pandas as Arrow IPC streams, see `to_table` in the python
+/// package
+fn to_ipc(rb: &RecordBatch) -> Vec<u8> {
+    let mut sink = Vec::new();
+    {
+        let mut writer = StreamWriter::try_new(&mut sink, rb.schema().as_ref()).unwrap();
+        writer.write(rb).unwrap();
+        writer.finish().unwrap();
+    }
+    sink
+}
+
+fn from_ipc(bytes: &[u8]) -> RecordBatch {
+    StreamReader::try_new(bytes)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+}
+
+fn conversion(c: &mut Criterion) {
+    let (plant, soil) = load_output_data("data").unwrap();
+    let soil_ipc = to_ipc(&soil);
+
+    c.bench_function("to_ipc", |b| {
+        b.iter(|| (to_ipc(black_box(&plant)), to_ipc(black_box(&soil))))
+    });
+    c.bench_function("from_ipc", |b| b.iter(|| from_ipc(black_box(&soil_ipc))));
+}
+
+criterion_group!(benches, conversion);
+criterion_main!(benches);
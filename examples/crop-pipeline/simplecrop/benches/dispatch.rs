@@ -0,0 +1,46 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use simplecrop_omf::model::{DailyData, YearlyData};
+use simplecrop_omf::native::run_cells;
+
+const DAYS: usize = 365;
+
+fn column(offset: f32, scale: f32) -> Vec<f32> {
+    (0..DAYS)
+        .map(|i| offset + (i as f32 * 0.37).sin() * scale)
+        .collect()
+}
+
+fn dispatch(c: &mut Criterion) {
+    let irrigation = vec![0f32; DAYS];
+    let (temp_max, temp_min) = (column(25.0, 8.0), column(12.0, 6.0));
+    let (par, srad) = (column(20.0, 5.0), column(15.0, 5.0));
+    let rainfall = column(4.0, 4.0);
+    let daily = DailyData {
+        irrigation: &irrigation,
+        temp_max: &temp_max,
+        temp_min: &temp_min,
+        rainfall: &rainfall,
+        photosynthetic_energy_flux: &par,
+        energy_flux: &srad,
+    };
+    let yearly = YearlyData::default();
+
+    let mut group = c.benchmark_group("run_cells");
+    for cells in [1usize, 100].iter() {
+        let rain: Vec<Vec<f32>> = (0..*cells)
+            .map(|i| {
+                rainfall
+                    .iter()
+                    .map(|r| r * (1.0 + i as f32 * 0.01))
+                    .collect()
+            })
+            .collect();
+        group.bench_with_input(BenchmarkId::from_parameter(cells), &rain, |b, rain| {
+            b.iter(|| run_cells(&daily, &yearly, rain.iter().map(|r| r.as_slice())))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
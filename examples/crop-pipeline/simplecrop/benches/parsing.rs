@@ -0,0 +1,11 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use simplecrop_omf::model::load_output_data;
+
+fn parsing(c: &mut Criterion) {
+    c.bench_function("load_output_data", |b| {
+        b.iter(|| load_output_data("data").unwrap())
+    });
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
@@ -0,0 +1,69 @@
+use std::io::{self, Write};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use simplecrop_omf::model::DailyData;
+
+/// Thirty years of daily weather
+const DAYS: usize = 30 * 365;
+
+fn column(offset: f32) -> Vec<f32> {
+    (0..DAYS)
+        .map(|i| offset + (i as f32 * 0.37).sin() * 10.0)
+        .collect()
+}
+
+/// The weather writer as it was, allocating a `String` per row with `format!`
+fn save_weather_format<W: Write>(daily: &DailyData, buf: &mut W) -> io::Result<()> {
+    for i in 0..daily.temp_max.len() {
+        let row = format!(
+            "{:5}  {:>4.1}  {:>4.1}  {:>4.1}{:>6.1}              {:>4.1}\n",
+            i + 1,
+            daily.energy_flux[i],
+            daily.temp_max[i],
+            daily.temp_min[i],
+            daily.rainfall[i],
+            daily.photosynthetic_energy_flux[i]
+        );
+        buf.write_all(row.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn writers(c: &mut Criterion) {
+    let (irrigation, temp_max, temp_min) = (column(1.0), column(25.0), column(10.0));
+    let (rainfall, par, srad) = (column(12.0), column(20.0), column(15.0));
+    let daily = DailyData {
+        irrigation: &irrigation,
+        temp_max: &temp_max,
+        temp_min: &temp_min,
+        rainfall: &rainfall,
+        photosynthetic_energy_flux: &par,
+        energy_flux: &srad,
+    };
+    let mut buf = Vec::with_capacity(DAYS * 64);
+
+    let mut group = c.benchmark_group("save_weather");
+    group.bench_function("format", |b| {
+        b.iter(|| {
+            buf.clear();
+            save_weather_format(black_box(&daily), &mut buf).unwrap();
+        })
+    });
+    group.bench_function("buffered", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&daily).save_weather(&mut buf).unwrap();
+        })
+    });
+    group.finish();
+
+    c.bench_function("save_irrigation", |b| {
+        b.iter(|| {
+            buf.clear();
+            black_box(&daily).save_irrigation(&mut buf).unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, writers);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Compile the array based SimpleCrop Fortran into a static library when the
+/// `fortran` feature is on. `FC` picks the compiler (gfortran by default).
+fn main() {
+    println!("cargo:rerun-if-changed=fortran/simplecrop_c.f90");
+    println!("cargo:rerun-if-env-changed=FC");
+    if env::var_os("CARGO_FEATURE_FORTRAN").is_none() {
+        return;
+    }
+    let out = PathBuf::from(env::var("OUT_DIR").expect("cargo to set OUT_DIR"));
+    let compiler = env::var("FC").unwrap_or_else(|_| "gfortran".to_string());
+    let object = out.join("simplecrop_c.o");
+    let status = Command::new(&compiler)
+        .args(["-c", "-O2", "-fPIC", "-J"])
+        .arg(&out)
+        .arg("fortran/simplecrop_c.f90")
+        .arg("-o")
+        .arg(&object)
+        .status()
+        .unwrap_or_else(|e| panic!("could not run Fortran compiler {}: {}", compiler, e));
+    assert!(
+        status.success(),
+        "could not compile fortran/simplecrop_c.f90"
+    );
+    let status = Command::new("ar")
+        .arg("crs")
+        .arg(out.join("libsimplecrop.a"))
+        .arg(&object)
+        .status()
+        .expect("ar to be installed");
+    assert!(status.success(), "could not archive libsimplecrop.a");
+    println!("cargo:rustc-link-search=native={}", out.display());
+    println!("cargo:rustc-link-lib=static=simplecrop");
+    println!("cargo:rustc-link-lib=dylib=gfortran");
+}
@@ -0,0 +1,188 @@
+! SimpleCrop's main loop, PLANT and SW routines adapted to take their inputs
+! and return their outputs as arrays through ISO_C_BINDING instead of reading
+! and writing files, so the model can be linked into a library.
+!
+! The equations are unchanged, including PAR being taken as half of SRAD and
+! the specific leaf area being read from the 16th plant parameter.
+module simplecrop_c
+  use iso_c_binding, only: c_float, c_int
+  implicit none
+
+  integer, parameter :: PLANT_COLUMNS = 7, SOIL_COLUMNS = 10
+  real, parameter :: STRESS_DEPTH = 250.0, ROWSPC = 60.0
+
+contains
+
+  ! yearly holds the 17 values of plant.inp followed by the 7 of soil.inp.
+  ! Returns 0 on success and 1 if more than max_rows rows would be written.
+  integer(c_int) function simplecrop_run(n_days, irrigation, srad, tmax, tmin, rain, &
+      yearly, doyp, frop, max_rows, plant_day, plant, soil_day, soil, n_plant, n_soil) &
+      bind(C, name="simplecrop_run")
+    integer(c_int), value, intent(in) :: n_days, doyp, frop, max_rows
+    real(c_float), intent(in) :: irrigation(n_days), srad(n_days), tmax(n_days), &
+        tmin(n_days), rain(n_days), yearly(24)
+    integer(c_int), intent(out) :: plant_day(max_rows), soil_day(max_rows), n_plant, n_soil
+    real(c_float), intent(out) :: plant(PLANT_COLUMNS, max_rows), soil(SOIL_COLUMNS, max_rows)
+
+    ! plant
+    real :: lfmax, emp2, emp1, pd, nb, rm, fc, tb, intot, n, lai, w, wr, wc, p1, sla
+    real :: wf, int, dn, dlai, dw, dwc, dwr, dwf, pt, pg, di, tmn, a, y1, swfac
+    ! soil
+    real :: wpp, fcp, stp, dp, drnp, cn, swc, wp, fcap, st, s, the
+    real :: rof, inf, drn, etp, esp, epp, esa, epa, potinf, swfac1, swfac2
+    real :: alb, tmed, eeq, f, avail, dwt, par
+    integer :: doy, endsim
+
+    lfmax = yearly(1); emp2 = yearly(2); emp1 = yearly(3); pd = yearly(4)
+    nb = yearly(5); rm = yearly(6); fc = yearly(7); tb = yearly(8)
+    intot = yearly(9); n = yearly(10); lai = yearly(11); w = yearly(12)
+    wr = yearly(13); wc = yearly(14); p1 = yearly(15); sla = yearly(16)
+    wpp = yearly(18); fcp = yearly(19); stp = yearly(20); dp = yearly(21)
+    drnp = yearly(22); cn = yearly(23); swc = yearly(24)
+
+    wf = 0.0; int = 0.0; endsim = 0
+    rof = 0.0; inf = 0.0; drn = 0.0; etp = 0.0; esa = 0.0; epa = 0.0
+    wp = dp * wpp * 10.0
+    fcap = dp * fcp * 10.0
+    st = dp * stp * 10.0
+    s = 254.0 * (100.0 / cn - 1.0)
+    the = wp + 0.75 * (fcap - wp)
+    call stress()
+
+    n_plant = 0
+    n_soil = 0
+    simplecrop_run = 0
+    do doy = 0, min(1000, n_days)
+      if (doy /= 0) then
+        ! SW rate
+        potinf = rain(doy) + irrigation(doy)
+        if (swc > fcap) then
+          drn = (swc - fcap) * drnp
+        else
+          drn = 0.0
+        end if
+        if (potinf > 0.0) then
+          if (potinf > 0.2 * s) then
+            rof = ((potinf - 0.2 * s)**2) / (potinf + 0.8 * s)
+          else
+            rof = 0.0
+          end if
+          inf = potinf - rof
+        else
+          rof = 0.0
+          inf = 0.0
+        end if
+        alb = 0.1 * exp(-0.7 * lai) + 0.2 * (1 - exp(-0.7 * lai))
+        tmed = 0.6 * tmax(doy) + 0.4 * tmin(doy)
+        eeq = srad(doy) * (4.88e-03 - 4.37e-03 * alb) * (tmed + 29)
+        if (tmax(doy) < 5) then
+          f = 0.01 * exp(0.18 * (tmax(doy) + 20))
+        else if (tmax(doy) > 35) then
+          f = 1.1 + 0.05 * (tmax(doy) - 35)
+        else
+          f = 1.1
+        end if
+        etp = f * eeq
+        esp = etp * exp(-0.7 * lai)
+        epp = etp * (1 - exp(-0.7 * lai))
+        if (swc < wp) then
+          avail = 0.0
+        else if (swc > fcap) then
+          avail = 1.0
+        else
+          avail = (swc - wp) / (fcap - wp)
+        end if
+        esa = esp * avail
+        epa = epp * min(swfac1, swfac2)
+
+        ! PLANT rate
+        if (doy > doyp) then
+          par = 0.5 * srad(doy)
+          tmn = 0.5 * (tmax(doy) + tmin(doy))
+          pt = 1.0 - 0.0025 * ((0.25 * tmin(doy) + 0.75 * tmax(doy)) - 26.0)**2
+          swfac = min(swfac1, swfac2)
+          y1 = 1.5 - 0.768 * ((ROWSPC * 0.01)**2 * pd)**0.1
+          pg = pt * swfac * 2.1 * par / pd * (1.0 - exp(-y1 * lai))
+          dw = pg * pd
+          if (n < lfmax) then
+            dn = rm * pt
+            a = exp(emp2 * (n - nb))
+            dlai = swfac * pd * emp1 * pt * (a / (1 + a)) * dn
+            dwc = fc * dw
+            dwr = (1 - fc) * dw
+            dwf = 0.0
+          else
+            if (tmn >= tb .and. tmn <= 25) then
+              di = tmn - tb
+            else
+              di = 0.0
+            end if
+            int = int + di
+            dlai = -pd * di * p1 * sla
+            dwf = dw
+            dwc = 0.0
+            dwr = 0.0
+            dn = 0.0
+          end if
+        end if
+
+        ! SW integration
+        swc = swc + (inf - esa - epa - drn)
+        if (swc > st) then
+          rof = rof + (swc - st)
+          swc = st
+        end if
+        if (swc < 0.0) swc = 0.0
+        call stress()
+
+        ! PLANT integration
+        if (doy > doyp) then
+          lai = max(lai + dlai, 0.0)
+          w = max(w + dw, 0.0)
+          wc = max(wc + dwc, 0.0)
+          wr = max(wr + dwr, 0.0)
+          wf = max(wf + dwf, 0.0)
+          n = n + dn
+          if (int > intot) endsim = 1
+        end if
+      end if
+
+      if (mod(doy, frop) == 0 .or. endsim == 1 .or. doy == doyp) then
+        if (n_soil >= max_rows) then
+          simplecrop_run = 1
+          return
+        end if
+        n_soil = n_soil + 1
+        soil_day(n_soil) = doy
+        soil(:, n_soil) = [rof, inf, drn, etp, esa, epa, swc, swc / dp, swfac1, swfac2]
+        if (doy >= doyp) then
+          n_plant = n_plant + 1
+          plant_day(n_plant) = doy
+          plant(:, n_plant) = [n, int, w, wc, wr, wf, lai]
+        end if
+      end if
+      if (endsim == 1) exit
+    end do
+
+  contains
+
+    subroutine stress()
+      if (swc < wp) then
+        swfac1 = 0.0
+      else if (swc > the) then
+        swfac1 = 1.0
+      else
+        swfac1 = max(min((swc - wp) / (the - wp), 1.0), 0.0)
+      end if
+      dwt = dp * 10.0 - (swc - fcap) / (st - fcap) * dp * 10.0
+      if (dwt > STRESS_DEPTH) then
+        swfac2 = 1.0
+      else
+        swfac2 = dwt / STRESS_DEPTH
+      end if
+      swfac2 = max(min(swfac2, 1.0), 0.0)
+    end subroutine stress
+
+  end function simplecrop_run
+
+end module simplecrop_c
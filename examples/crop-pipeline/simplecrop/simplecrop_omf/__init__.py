@@ -1,11 +1,19 @@
+import json
 import os
 import pathlib
+import tempfile
 
 from meillionen.meillionen import server_respond_from_cli
+from meillionen.config import config_class, handler_fields
 from meillionen.function import FuncInterfaceServer, FuncRequest
 from meillionen.handlers import PandasHandler
 from meillionen.resource import Schemaless
-from .simplecrop_omf import run
+from meillionen import strictness, transforms, verbosity
+from .simplecrop_omf import run, run_in_process, yearly_defaults, yearly_parameters, yearly_parameter_docs, yearly_schema, yearly_stream, Workspace, quality_control as _quality_control
+from . import simplecrop_omf
+
+verbosity.register_native(simplecrop_omf.set_verbosity)
+strictness.register_native(simplecrop_omf.set_strict)
 from io import BytesIO
 import pyarrow as pa
 import pandas as pd
@@ -45,53 +53,11 @@ interface = FuncInterfaceServer(
                 ]
             }
         ),
+        # the units, range and description of each parameter come from the
+        # Rust parameter table so `meillionen interface` can show them
         'yearly': PandasHandler.from_kwargs(
             description='Yearly parameters influencing crop growth',
-            columns={
-                'fields': [
-                    {
-                        'name': name,
-                        'data_type': 'Float32'
-                    } for name in
-                    [
-                        'plant_leaves_max_number',
-                        'plant_emp2',
-                        'plant_emp1',
-                        'plant_density',
-                        'plant_nb',
-                        'plant_leaf_max_appearance_rate',
-                        'plant_growth_canopy_fraction',
-                        'plant_min_repro_growth_temp',
-                        'plant_repro_phase_duration',
-                        'plant_leaves_number_of',
-                        'plant_leaf_area_index',
-                        'plant_matter',
-                        'plant_matter_root',
-                        'plant_matter_canopy',
-                        'plant_matter_leaves_removed',
-                        'plant_development_phase',
-                        'plant_leaf_specific_area',
-
-                        'soil_water_content_wilting_point',
-                        'soil_water_content_field_capacity',
-                        'soil_water_content_saturation',
-                        'soil_profile_depth',
-                        'soil_drainage_daily_percent',
-                        'soil_runoff_curve_number',
-                        'soil_water_storage'
-                    ]
-                ] + [
-                    {
-                        'name': 'day_of_planting',
-                        'data_type': 'Int32'
-    ,
-                    },
-                    {
-                        'name': 'printout_freq',
-                        'data_type': 'Int32'
-                    }
-                ]
-            }
+            **json.loads(yearly_schema())
         )
     },
     sinks={
@@ -150,6 +116,72 @@ def to_table(ipc_message: bytes) -> pd.DataFrame:
     return pa.ipc.open_stream(stream).read_pandas()
 
 
+_SimpleCropParameters = config_class(
+    'SimpleCrop',
+    handler_fields(interface.source('yearly')),
+    docs=yearly_parameter_docs(),
+    aliases={
+        'planting_day': 'day_of_planting',
+        'leaves_max': 'plant_leaves_max_number',
+        'curve_number': 'soil_runoff_curve_number',
+        'drainage': 'soil_drainage_daily_percent',
+    }
+)
+
+
+class SimpleCrop(_SimpleCropParameters):
+    """
+    Yearly SimpleCrop parameters, such as ``SimpleCrop(planting_day=130, soil_profile_depth=120)``
+
+    Parameters not given take the defaults of the model. ``to_dataframe`` gives the
+    yearly source of a run and ``describe_parameters`` the units, valid range and
+    description of each parameter.
+
+    Each instance runs the executable in its own workspace, a new directory under
+    ``workspace_base`` unless ``use_workspace`` picks one. Two instances can only
+    use the same directory if both share it explicitly.
+    """
+    workspace_base = os.path.join(tempfile.gettempdir(), 'simplecrop')
+
+    @property
+    def workspace(self) -> Workspace:
+        """The working directory of this instance, created the first time it is needed"""
+        if self.__dict__.get('_workspace') is None:
+            self._workspace = Workspace.unique(self.workspace_base)
+        return self._workspace
+
+    def use_workspace(self, dir: str, shared: bool = False):
+        """Run in ``dir``, which fails if another instance holds it unless ``shared``"""
+        self.close()
+        self._workspace = Workspace(dir, shared)
+        return self
+
+    def close(self):
+        """Release the workspace so another instance can use its directory"""
+        workspace = self.__dict__.pop('_workspace', None)
+        if workspace is not None:
+            workspace.close()
+
+    def run(self, daily: pd.DataFrame, cli_path=None, piped=None):
+        """
+        Run the model with these parameters, returning the plant and soil tables
+
+        ``piped`` streams the inputs of the executable through named pipes, by
+        default when ``SIMPLECROP_INPUTS`` is ``pipes``.
+        """
+        cli_path = cli_path or os.environ.get('SIMPLECROP', 'simplecrop')
+        dir = None if cli_path in (BUILTIN, LINKED) else self.workspace.path
+        return simplecrop_mock_ipc_run(cli_path, dir, daily, self.to_dataframe(), piped)
+
+    def logs(self):
+        """The stdout and stderr of the last run of the executable in this workspace"""
+        workspace = pathlib.Path(self.workspace.path)
+        return {
+            stream: (workspace / f'{stream}.log').read_text() if (workspace / f'{stream}.log').exists() else ''
+            for stream in ['stdout', 'stderr']
+        }
+
+
 def to_ipc(df: pd.DataFrame) -> pd.DataFrame:
     sink = pa.BufferOutputStream()
     batch = pa.RecordBatch.from_pandas(df)
@@ -158,11 +190,48 @@ def to_ipc(df: pd.DataFrame) -> pd.DataFrame:
     return sink.getvalue().to_pybytes()
 
 
-def simplecrop_mock_ipc_run(cli_path, dir, daily: pd.DataFrame, yearly: pd.DataFrame):
+def quality_control(daily: pd.DataFrame, correct: bool = False):
+    """
+    Check daily weather for temperature spikes, maximum below minimum
+    temperature, impossible radiation and repeated days
+
+    Returns a frame of the issues found, with a row per day and check, and
+    with ``correct`` the daily inputs with spikes smoothed, swapped
+    temperatures swapped back and radiation clamped, otherwise ``None``.
+    Issues are validation warnings, errors in strict mode.
+    """
+    issues, fixed = _quality_control(to_ipc(daily), correct)
+    issues = pd.DataFrame(issues, columns=['day', 'check', 'column', 'value'])
+    return issues, None if fixed is None else to_table(fixed)
+
+
+# Set SIMPLECROP to this to run the Rust version of the SimpleCrop equations
+# in process instead of the Fortran executable
+BUILTIN = 'builtin'
+# Set SIMPLECROP to this to call SimpleCrop compiled as a Fortran library, only
+# available when built with the fortran feature
+LINKED = 'linked'
+# Set SIMPLECROP_INPUTS to this to stream the inputs of the executable through
+# named pipes instead of writing them to its workspace, only available on unix
+PIPES = 'pipes'
+
+
+def piped_inputs() -> bool:
+    return os.environ.get('SIMPLECROP_INPUTS', 'files') == PIPES
+
+
+def simplecrop_mock_ipc_run(cli_path, dir, daily: pd.DataFrame, yearly: pd.DataFrame, piped=None):
     """Run the simplecrop model as if you were sending and receiving ipc messages"""
+    if piped is None:
+        piped = piped_inputs()
     daily_ipc = to_ipc(daily)
     yearly_ipc = to_ipc(yearly)
-    plant_ref, soil_ref = run(cli_path, dir, daily_ipc, yearly_ipc)
+    if cli_path == BUILTIN:
+        plant_ref, soil_ref = run_in_process(daily_ipc, yearly_ipc)
+    elif cli_path == LINKED:
+        plant_ref, soil_ref = simplecrop_omf.run_linked(daily_ipc, yearly_ipc)
+    else:
+        plant_ref, soil_ref = run(cli_path, dir, daily_ipc, yearly_ipc, piped)
     return to_table(plant_ref), to_table(soil_ref)
 
 
@@ -170,9 +239,13 @@ def run_cli():
     cli_path = os.environ.get('SIMPLECROP', 'simplecrop')
     rb = server_respond_from_cli(cli_path, interface.to_recordbatch(cli_path))
     args = FuncRequest.from_recordbatch(rb)
-    daily: pd.DataFrame = interface.source('daily').load(args.source('daily'))
-    yearly: pd.DataFrame = interface.source('yearly').load(args.source('yearly'))
+    daily: pd.DataFrame = transforms.apply('daily', interface.source('daily').load(args.source('daily')))
+    yearly: pd.DataFrame = transforms.apply('yearly', interface.source('yearly').load(args.source('yearly')))
     tempdir = interface.sink('tempdir').save(args.sink('tempdir'))
-    plant, soil = simplecrop_mock_ipc_run(cli_path, tempdir, daily, yearly)
+    workspace = Workspace(tempdir)
+    try:
+        plant, soil = simplecrop_mock_ipc_run(cli_path, workspace.path, daily, yearly)
+    finally:
+        workspace.close()
     interface.sink('plant').save(args.sink('plant'), plant)
     interface.sink('soil').save(args.sink('soil'), soil)
\ No newline at end of file
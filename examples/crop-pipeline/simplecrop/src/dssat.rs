@@ -0,0 +1,555 @@
+//! Reading and writing DSSAT files so SimpleCrop runs can be compared with
+//! DSSAT and its tools
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use arrow::datatypes::Float32Type;
+use arrow::record_batch::RecordBatch;
+use meillionen_mt::strictness;
+
+use crate::get_column;
+use stable_eyre::eyre::{eyre, WrapErr};
+
+use crate::model::{DailyData, YearlyData};
+
+/// DSSAT's missing value
+pub const MISSING: f32 = -99.0;
+
+/// Two digit years below this are read as years of the 2000s
+const CENTURY_PIVOT: i32 = 30;
+
+/// PAR (mol/m2/d) per unit of solar radiation (MJ/m2/d), used when a file
+/// has no PAR column as DSSAT does
+const PAR_PER_SRAD: f32 = 2.0;
+
+/// The site line of a DSSAT weather file
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeatherStation {
+    /// The four letter institute and site code, like `UFGA`
+    pub insi: String,
+    pub name: String,
+    pub latitude: f32,
+    pub longitude: f32,
+    /// Metres
+    pub elevation: f32,
+    /// Mean annual temperature (°C), computed from the data when not given
+    pub tav: Option<f32>,
+    /// Half the difference between the warmest and coldest mean monthly
+    /// temperatures (°C), computed from the data when not given
+    pub amp: Option<f32>,
+    /// Heights of the temperature and wind measurements (m)
+    pub refht: Option<f32>,
+    pub wndht: Option<f32>,
+}
+
+fn is_leap(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_year(year: i32) -> u32 {
+    if is_leap(year) {
+        366
+    } else {
+        365
+    }
+}
+
+/// The month, from 0, of a day of the year
+fn month_of(year: i32, doy: u32) -> usize {
+    let mut ends = [31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334, 365];
+    if is_leap(year) {
+        ends.iter_mut().skip(1).for_each(|e| *e += 1);
+    }
+    ends.iter().position(|&e| doy <= e).unwrap_or(11)
+}
+
+/// The year and day of the year of each day of a series starting on the
+/// first of January of `year`
+pub(crate) fn dates(year: i32, n: usize) -> impl Iterator<Item = (i32, u32)> {
+    let mut date = (year, 1);
+    std::iter::repeat_with(move || {
+        let today = date;
+        date = if date.1 == days_in_year(date.0) {
+            (date.0 + 1, 1)
+        } else {
+            (date.0, date.1 + 1)
+        };
+        today
+    })
+    .take(n)
+}
+
+/// Mean annual temperature and its amplitude from daily extremes, as DSSAT
+/// computes `TAV` and `AMP`
+fn tav_amp(year: i32, temp_max: &[f32], temp_min: &[f32]) -> (f32, f32) {
+    let mut sums = [0.0f64; 12];
+    let mut counts = [0usize; 12];
+    for ((y, doy), (tmax, tmin)) in dates(year, temp_max.len()).zip(temp_max.iter().zip(temp_min)) {
+        let m = month_of(y, doy);
+        sums[m] += f64::from(tmax + tmin) / 2.0;
+        counts[m] += 1;
+    }
+    let means: Vec<f64> = sums
+        .iter()
+        .zip(counts.iter())
+        .filter(|(_, &c)| c > 0)
+        .map(|(s, &c)| s / c as f64)
+        .collect();
+    if means.is_empty() {
+        return (MISSING, MISSING);
+    }
+    let total: f64 = sums.iter().sum();
+    let tav = total / counts.iter().sum::<usize>() as f64;
+    let max = means.iter().cloned().fold(f64::MIN, f64::max);
+    let min = means.iter().cloned().fold(f64::MAX, f64::min);
+    (tav as f32, ((max - min) / 2.0) as f32)
+}
+
+/// Write daily weather as a DSSAT `.WTH` file starting on the first of
+/// January of `year`
+///
+/// Dates are written as `YYDDD` like the SimpleCrop inputs.
+pub fn save_wth<W: Write>(
+    daily: &DailyData,
+    station: &WeatherStation,
+    year: i32,
+    buf: &mut W,
+) -> io::Result<()> {
+    let (tav, amp) = match (station.tav, station.amp) {
+        (Some(tav), Some(amp)) => (tav, amp),
+        (tav, amp) => {
+            let computed = tav_amp(year, daily.temp_max, daily.temp_min);
+            (tav.unwrap_or(computed.0), amp.unwrap_or(computed.1))
+        }
+    };
+    writeln!(buf, "*WEATHER DATA : {}", station.name)?;
+    writeln!(buf)?;
+    writeln!(
+        buf,
+        "@ INSI      LAT     LONG  ELEV   TAV   AMP REFHT WNDHT"
+    )?;
+    writeln!(
+        buf,
+        "  {:<4}{:>9.3}{:>9.3}{:>6.0}{:>6.1}{:>6.1}{:>6.1}{:>6.1}",
+        station.insi,
+        station.latitude,
+        station.longitude,
+        station.elevation,
+        tav,
+        amp,
+        station.refht.unwrap_or(MISSING),
+        station.wndht.unwrap_or(MISSING)
+    )?;
+    writeln!(buf, "@DATE  SRAD  TMAX  TMIN  RAIN   PAR")?;
+    for (i, (y, doy)) in dates(year, daily.temp_max.len()).enumerate() {
+        writeln!(
+            buf,
+            "{:02}{:03}{:>6.1}{:>6.1}{:>6.1}{:>6.1}{:>6.1}",
+            y.rem_euclid(100),
+            doy,
+            daily.energy_flux[i],
+            daily.temp_max[i],
+            daily.temp_min[i],
+            daily.rainfall[i],
+            daily.photosynthetic_energy_flux[i]
+        )?;
+    }
+    Ok(())
+}
+
+/// Daily weather read from a DSSAT `.WTH` file
+///
+/// Missing values stay `-99.0` except PAR, which is estimated from solar
+/// radiation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeatherData {
+    pub station: Option<WeatherStation>,
+    /// Year and day of the year
+    pub dates: Vec<(i32, u32)>,
+    pub irrigation: Vec<f32>,
+    pub temp_max: Vec<f32>,
+    pub temp_min: Vec<f32>,
+    pub rainfall: Vec<f32>,
+    pub photosynthetic_energy_flux: Vec<f32>,
+    pub energy_flux: Vec<f32>,
+}
+
+fn parse_date(s: &str) -> Option<(i32, u32)> {
+    let (year, doy) = match s.len() {
+        5 => {
+            let yy: i32 = s[..2].parse().ok()?;
+            let century = if yy < CENTURY_PIVOT { 2000 } else { 1900 };
+            (century + yy, s[2..].parse().ok()?)
+        }
+        7 => (s[..4].parse().ok()?, s[4..].parse().ok()?),
+        _ => return None,
+    };
+    if doy >= 1 && doy <= days_in_year(year) {
+        Some((year, doy))
+    } else {
+        None
+    }
+}
+
+/// A value of a whitespace separated row by its `@` header name, `None` if
+/// the column is absent or missing
+fn field(header: &[String], values: &[&str], name: &str) -> stable_eyre::Result<Option<f32>> {
+    let value = match header.iter().position(|h| h == name) {
+        Some(i) => values.get(i).copied(),
+        None => return Ok(None),
+    };
+    match value {
+        None => Ok(None),
+        Some(v) => {
+            let x: f32 = v
+                .parse()
+                .map_err(|_| eyre!("{} value {} is not a number", name, v))?;
+            Ok(if x == MISSING { None } else { Some(x) })
+        }
+    }
+}
+
+impl WeatherData {
+    /// Read a weather file
+    ///
+    /// Lines starting with `!` are comments, `*` lines hold the station name
+    /// and each `@` line names the columns of the rows after it. Only the
+    /// station and daily sections are read so files with extra sections
+    /// still load. Skipped lines, missing values and PAR estimated from SRAD
+    /// are validation warnings, see [`meillionen_mt::strictness`].
+    pub fn read<R: BufRead>(rdr: R) -> stable_eyre::Result<Self> {
+        let mut data = WeatherData::default();
+        let mut name = String::new();
+        let mut header: Vec<String> = vec![];
+        let mut skipped = 0;
+        let mut par_filled = 0;
+        for (n, line) in rdr.lines().enumerate() {
+            let line = line?;
+            let record = line.trim();
+            if record.is_empty() || record.starts_with('!') {
+                continue;
+            }
+            if let Some(title) = record.strip_prefix('*') {
+                name = title.split_once(':').map_or("", |t| t.1).trim().to_string();
+                continue;
+            }
+            if let Some(h) = record.strip_prefix('@') {
+                header = h.split_whitespace().map(|s| s.to_uppercase()).collect();
+                continue;
+            }
+            let values: Vec<&str> = record.split_whitespace().collect();
+            let context = || format!("line {}", n + 1);
+            if header.first().map(String::as_str) == Some("INSI") {
+                let number = |col| field(&header, &values, col).wrap_err_with(context);
+                data.station = Some(WeatherStation {
+                    insi: values[0].to_string(),
+                    name: name.clone(),
+                    latitude: number("LAT")?.unwrap_or(MISSING),
+                    longitude: number("LONG")?.unwrap_or(MISSING),
+                    elevation: number("ELEV")?.unwrap_or(MISSING),
+                    tav: number("TAV")?,
+                    amp: number("AMP")?,
+                    refht: number("REFHT")?,
+                    wndht: number("WNDHT")?,
+                });
+            } else if header.first().map(String::as_str) == Some("DATE") {
+                let date = parse_date(values[0])
+                    .ok_or_else(|| eyre!("{} is not a YYDDD or YYYYDDD date", values[0]))
+                    .wrap_err_with(context)?;
+                let required = |col| {
+                    if !header.iter().any(|h| h == col) {
+                        return Err(eyre!("no {} column", col)).wrap_err_with(context);
+                    }
+                    Ok(field(&header, &values, col)
+                        .wrap_err_with(context)?
+                        .unwrap_or(MISSING))
+                };
+                let optional = |col| field(&header, &values, col).wrap_err_with(context);
+                let srad = optional("SRAD")?;
+                let par = optional("PAR")?;
+                if par.is_none() && srad.is_some() {
+                    par_filled += 1;
+                }
+                let par = par.or_else(|| srad.map(|s| s * PAR_PER_SRAD));
+                data.dates.push(date);
+                data.irrigation.push(0.0);
+                data.temp_max.push(required("TMAX")?);
+                data.temp_min.push(required("TMIN")?);
+                data.rainfall.push(optional("RAIN")?.unwrap_or(MISSING));
+                data.energy_flux.push(srad.unwrap_or(MISSING));
+                data.photosynthetic_energy_flux.push(par.unwrap_or(MISSING));
+            } else {
+                skipped += 1;
+            }
+        }
+        if data.dates.is_empty() {
+            return Err(eyre!("no daily weather found"));
+        }
+        if skipped > 0 {
+            strictness::warn(format!(
+                "skipped {} lines outside the daily weather",
+                skipped
+            ))?;
+        }
+        if par_filled > 0 {
+            strictness::warn(format!(
+                "estimated PAR from SRAD on {} of {} days",
+                par_filled,
+                data.dates.len()
+            ))?;
+        }
+        for (column, values) in [
+            ("TMAX", &data.temp_max),
+            ("TMIN", &data.temp_min),
+            ("RAIN", &data.rainfall),
+            ("SRAD", &data.energy_flux),
+        ] {
+            let missing = values.iter().filter(|&&v| v == MISSING).count();
+            if missing > 0 {
+                strictness::warn(format!("{} is missing on {} days", column, missing))?;
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
+        let f = File::open(&p)
+            .wrap_err_with(|| format!("Could not open {}", p.as_ref().to_string_lossy()))?;
+        Self::read(BufReader::new(f))
+            .wrap_err_with(|| format!("Could not read {}", p.as_ref().to_string_lossy()))
+    }
+
+    /// Model inputs borrowing the weather, with no irrigation unless
+    /// `irrigation` is set first
+    pub fn daily(&self) -> DailyData<'_> {
+        DailyData {
+            irrigation: &self.irrigation,
+            temp_max: &self.temp_max,
+            temp_min: &self.temp_min,
+            rainfall: &self.rainfall,
+            photosynthetic_energy_flux: &self.photosynthetic_energy_flux,
+            energy_flux: &self.energy_flux,
+        }
+    }
+}
+
+/// The season totals DSSAT writes to `Summary.OUT` for one run
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeasonSummary {
+    pub run: u32,
+    pub treatment: u32,
+    /// Dates as `YYYYDDD`
+    pub planting: i32,
+    pub maturity: i32,
+    /// Tops (canopy and fruit) and harvest (fruit) dry weight at maturity (kg/ha)
+    pub tops_weight: f32,
+    pub harvest_weight: f32,
+    pub lai_max: f32,
+    /// Season totals (mm)
+    pub precipitation: f32,
+    pub irrigation: f32,
+    pub runoff: f32,
+    pub drainage: f32,
+    pub evapotranspiration: f32,
+}
+
+fn last(column: &[f32]) -> f32 {
+    column.last().copied().unwrap_or(MISSING)
+}
+
+fn int_column<'a>(rb: &'a RecordBatch, name: &str) -> stable_eyre::Result<&'a [i32]> {
+    let i = rb.schema().index_of(name)?;
+    rb.column(i)
+        .as_any()
+        .downcast_ref::<arrow::array::Int32Array>()
+        .map(|a| a.values())
+        .ok_or_else(|| stable_eyre::eyre::eyre!("column {} must be Int32", name))
+}
+
+impl SeasonSummary {
+    /// Summarise a SimpleCrop run of a season starting on the first of
+    /// January of `year`
+    ///
+    /// `plant` and `soil` are the record batches a run returns. Weights are
+    /// converted from g/m2 to kg/ha.
+    pub fn from_outputs(
+        run: u32,
+        year: i32,
+        daily: &DailyData,
+        yearly: &YearlyData,
+        plant: &RecordBatch,
+        soil: &RecordBatch,
+    ) -> stable_eyre::Result<Self> {
+        let date = |doy: i32| year * 1000 + doy;
+        let canopy = get_column::<Float32Type>(plant, "plant_matter_canopy")?;
+        let fruit = get_column::<Float32Type>(plant, "plant_matter_fruit")?;
+        let lai = get_column::<Float32Type>(plant, "plant_leaf_area_index")?;
+        let days = int_column(plant, "day")?;
+        let total = |rb: &RecordBatch, name: &str| -> stable_eyre::Result<f32> {
+            Ok(get_column::<Float32Type>(rb, name)?.iter().sum())
+        };
+        // only the weather of the season counts towards its totals
+        let season = (yearly.day_of_planting.max(1) as usize - 1)..daily.rainfall.len();
+        let season_total = |xs: &[f32]| xs.get(season.clone()).map_or(0.0, |s| s.iter().sum());
+        Ok(Self {
+            run,
+            treatment: 1,
+            planting: date(yearly.day_of_planting),
+            maturity: days.last().map_or(-99, |&d| date(d)),
+            tops_weight: (last(canopy) + last(fruit)) * 10.0,
+            harvest_weight: last(fruit) * 10.0,
+            lai_max: lai.iter().cloned().fold(MISSING, f32::max),
+            precipitation: season_total(daily.rainfall),
+            irrigation: season_total(daily.irrigation),
+            runoff: total(soil, "soil_daily_runoff")?,
+            drainage: total(soil, "soil_daily_drainage")?,
+            evapotranspiration: total(soil, "soil_evapotranspiration")?,
+        })
+    }
+}
+
+/// Write season summaries as a DSSAT `Summary.OUT` file
+///
+/// Only the columns SimpleCrop can fill are written, tools that read the
+/// `@` header line find them by name.
+pub fn save_summary_out<W: Write>(runs: &[SeasonSummary], buf: &mut W) -> io::Result<()> {
+    writeln!(buf, "*SUMMARY : SIMPLECROP")?;
+    writeln!(buf)?;
+    writeln!(
+        buf,
+        "@   RUNNO   TRNO    PDAT    MDAT   CWAM   HWAM   LAIX   PRCP   IRCM   ROCM   DRCM   ETCM"
+    )?;
+    for r in runs {
+        writeln!(
+            buf,
+            "{:>8}{:>7}{:>8}{:>8}{:>7.0}{:>7.0}{:>7.2}{:>7.0}{:>7.0}{:>7.0}{:>7.0}{:>7.0}",
+            r.run,
+            r.treatment,
+            r.planting,
+            r.maturity,
+            r.tops_weight,
+            r.harvest_weight,
+            r.lai_max,
+            r.precipitation,
+            r.irrigation,
+            r.runoff,
+            r.drainage,
+            r.evapotranspiration
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::str;
+
+    use crate::dssat::{
+        dates, save_summary_out, save_wth, SeasonSummary, WeatherData, WeatherStation,
+    };
+    use crate::model::DailyData;
+
+    #[test]
+    fn write_wth_and_summary() {
+        let n = 366;
+        let tmax: Vec<f32> = (0..n).map(|i| 20.0 + (i % 30) as f32 / 10.0).collect();
+        let tmin = vec![4.4f32; n];
+        let other = vec![5.1f32; n];
+        let daily = DailyData {
+            irrigation: &other,
+            temp_max: &tmax,
+            temp_min: &tmin,
+            rainfall: &other,
+            photosynthetic_energy_flux: &other,
+            energy_flux: &other,
+        };
+        let station = WeatherStation {
+            insi: "UFGA".to_string(),
+            name: "Gainesville, FL".to_string(),
+            latitude: 29.63,
+            longitude: -82.37,
+            elevation: 10.0,
+            tav: None,
+            amp: Some(7.4),
+            refht: None,
+            wndht: None,
+        };
+        let mut cur = Cursor::new(Vec::new());
+        save_wth(&daily, &station, 1987, &mut cur).unwrap();
+        let text = str::from_utf8(cur.get_ref()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "*WEATHER DATA : Gainesville, FL");
+        assert_eq!(
+            lines[3],
+            "  UFGA   29.630  -82.370    10  12.9   7.4 -99.0 -99.0"
+        );
+        assert_eq!(lines[5], "87001   5.1  20.0   4.4   5.1   5.1");
+        // 1987 is not a leap year
+        assert_eq!(lines[5 + 365], "88001   5.1  20.5   4.4   5.1   5.1");
+        assert_eq!(dates(2000, 367).last(), Some((2001, 1)));
+
+        let mut cur = Cursor::new(Vec::new());
+        let summary = SeasonSummary {
+            run: 1,
+            treatment: 1,
+            planting: 1987121,
+            maturity: 1987250,
+            tops_weight: 3000.0,
+            harvest_weight: 1200.0,
+            lai_max: 3.25,
+            precipitation: 550.0,
+            irrigation: 0.0,
+            runoff: 12.0,
+            drainage: 80.0,
+            evapotranspiration: 400.0,
+        };
+        save_summary_out(&[summary], &mut cur).unwrap();
+        assert_eq!(
+            str::from_utf8(cur.get_ref()).unwrap().lines().nth(3),
+            Some("       1      1 1987121 1987250   3000   1200   3.25    550      0     12     80    400")
+        );
+    }
+
+    const WTH: &str = "\
+*WEATHER DATA : Gainesville, FL
+
+! from the DSSAT distribution
+@ INSI      LAT     LONG  ELEV   TAV   AMP REFHT WNDHT
+  UFGA   29.630  -82.370    10  20.9   7.4 -99.0 -99.0
+@  DATE  SRAD  TMAX  TMIN  RAIN  RHUM
+2000366   5.1  20.0   4.4  23.9  80.0
+2001001  10.8  13.3   1.1 -99.0  75.0
+";
+
+    #[test]
+    fn read_wth() {
+        let data = WeatherData::read(Cursor::new(WTH)).unwrap();
+        let station = data.station.clone().unwrap();
+        assert_eq!(station.insi, "UFGA");
+        assert_eq!(station.name, "Gainesville, FL");
+        assert_eq!((station.tav, station.refht), (Some(20.9), None));
+        assert_eq!(data.dates, vec![(2000, 366), (2001, 1)]);
+        assert_eq!(data.rainfall, vec![23.9, -99.0]);
+        // PAR is estimated from solar radiation
+        assert_eq!(data.photosynthetic_energy_flux, vec![10.2, 21.6]);
+
+        // files written by save_wth read back
+        let mut cur = Cursor::new(Vec::new());
+        save_wth(&data.daily(), &station, 2000, &mut cur).unwrap();
+        let again = WeatherData::read(Cursor::new(cur.into_inner())).unwrap();
+        assert_eq!(again.dates[..2], [(2000, 1), (2000, 2)]);
+        assert_eq!(again.temp_max, data.temp_max);
+        assert_eq!(again.station, Some(station));
+
+        assert!(WeatherData::read(Cursor::new("@DATE SRAD TMAX\n87001 5.1 20.0\n")).is_err());
+
+        // the gaps are errors in strict mode
+        meillionen_mt::strictness::set_strict(true);
+        let strict = WeatherData::read(Cursor::new(WTH));
+        meillionen_mt::strictness::set_strict(false);
+        assert!(format!("{:?}", strict.unwrap_err()).contains("estimated PAR from SRAD"));
+    }
+}
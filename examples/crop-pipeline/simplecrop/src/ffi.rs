@@ -0,0 +1,196 @@
+//! SimpleCrop compiled from Fortran into a static library and called through
+//! its C interface, passing arrays in memory instead of writing input files
+//! and starting a process
+
+use std::os::raw::c_int;
+
+use arrow::record_batch::RecordBatch;
+use stable_eyre::eyre::eyre;
+
+use crate::model::{to_recordbatches, DailyData, PlantDataSet, SoilDataSet, YearlyData};
+
+const PLANT_COLUMNS: usize = 7;
+const SOIL_COLUMNS: usize = 10;
+
+extern "C" {
+    /// See `fortran/simplecrop_c.f90`
+    fn simplecrop_run(
+        n_days: c_int,
+        irrigation: *const f32,
+        srad: *const f32,
+        tmax: *const f32,
+        tmin: *const f32,
+        rain: *const f32,
+        yearly: *const f32,
+        doyp: c_int,
+        frop: c_int,
+        max_rows: c_int,
+        plant_day: *mut c_int,
+        plant: *mut f32,
+        soil_day: *mut c_int,
+        soil: *mut f32,
+        n_plant: *mut c_int,
+        n_soil: *mut c_int,
+    ) -> c_int;
+}
+
+impl YearlyData {
+    /// The values of `plant.inp` followed by those of `soil.inp`
+    fn to_fortran(self) -> [f32; 24] {
+        [
+            self.plant_leaves_max_number,
+            self.plant_emp2,
+            self.plant_emp1,
+            self.plant_density,
+            self.plant_nb,
+            self.plant_leaf_max_appearance_rate,
+            self.plant_growth_canopy_fraction,
+            self.plant_min_repro_growth_temp,
+            self.plant_repro_phase_duration,
+            self.plant_leaves_number_of,
+            self.plant_leaf_area_index,
+            self.plant_matter,
+            self.plant_matter_root,
+            self.plant_matter_canopy,
+            self.plant_matter_leaves_removed,
+            self.plant_development_phase,
+            self.plant_leaf_specific_area,
+            self.soil_water_content_wilting_point,
+            self.soil_water_content_field_capacity,
+            self.soil_water_content_saturation,
+            self.soil_profile_depth,
+            self.soil_drainage_daily_percent,
+            self.soil_runoff_curve_number,
+            self.soil_water_storage,
+        ]
+    }
+}
+
+/// The number of days of the daily inputs passed to the Fortran, which reads
+/// that many values from each of them
+fn checked_days(daily: &DailyData) -> stable_eyre::Result<c_int> {
+    let days = daily.days();
+    let inputs = [
+        ("irrigation", daily.irrigation.len()),
+        ("energy_flux", daily.energy_flux.len()),
+        ("temp_max", daily.temp_max.len()),
+        ("temp_min", daily.temp_min.len()),
+        ("rainfall", daily.rainfall.len()),
+    ];
+    if let Some((name, len)) = inputs.iter().find(|(_, len)| *len != days) {
+        return Err(eyre!(
+            "daily input {} has {} values but the others have {}",
+            name,
+            len,
+            days
+        ));
+    }
+    // a row every day plus the initial one must also fit
+    if days >= c_int::MAX as usize {
+        return Err(eyre!("{} days are too many for SimpleCrop", days));
+    }
+    Ok(days as c_int)
+}
+
+/// Run the linked Fortran SimpleCrop
+pub fn run(
+    daily: &DailyData,
+    yearly: &YearlyData,
+) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+    let n_days = checked_days(daily)?;
+    let days = n_days as usize;
+    // a row every day plus the initial one
+    let max_rows = days + 1;
+    let mut plant_day = vec![0 as c_int; max_rows];
+    let mut plant = vec![0f32; PLANT_COLUMNS * max_rows];
+    let mut soil_day = vec![0 as c_int; max_rows];
+    let mut soil = vec![0f32; SOIL_COLUMNS * max_rows];
+    let (mut n_plant, mut n_soil): (c_int, c_int) = (0, 0);
+    let params = yearly.to_fortran();
+    // the Fortran reads `days` values of each daily input, which
+    // `checked_days` made sure they have, and writes at most `max_rows` rows
+    // to each output
+    let status = unsafe {
+        simplecrop_run(
+            n_days,
+            daily.irrigation.as_ptr(),
+            daily.energy_flux.as_ptr(),
+            daily.temp_max.as_ptr(),
+            daily.temp_min.as_ptr(),
+            daily.rainfall.as_ptr(),
+            params.as_ptr(),
+            yearly.day_of_planting,
+            yearly.printout_freq,
+            max_rows as c_int,
+            plant_day.as_mut_ptr(),
+            plant.as_mut_ptr(),
+            soil_day.as_mut_ptr(),
+            soil.as_mut_ptr(),
+            &mut n_plant,
+            &mut n_soil,
+        )
+    };
+    if status != 0 {
+        return Err(eyre!("SimpleCrop wrote more than {} output rows", max_rows));
+    }
+
+    let mut po = PlantDataSet {
+        day_of_year: plant_day[..n_plant as usize].to_vec(),
+        ..PlantDataSet::default()
+    };
+    for row in plant.chunks(PLANT_COLUMNS).take(n_plant as usize) {
+        if let [n, int, w, wc, wr, wf, lai] = *row {
+            po.plant_leaf_count.push(n);
+            po.air_accumulated_temp.push(int);
+            po.plant_matter.push(w);
+            po.plant_matter_canopy.push(wc);
+            po.plant_matter_root.push(wr);
+            po.plant_matter_fruit.push(wf);
+            po.plant_leaf_area_index.push(lai);
+        }
+    }
+    let mut so = SoilDataSet {
+        day_of_year: soil_day[..n_soil as usize].to_vec(),
+        ..SoilDataSet::default()
+    };
+    for row in soil.chunks(SOIL_COLUMNS).take(n_soil as usize) {
+        if let [rof, inf, drn, etp, esa, epa, swc, swc_dp, swfac1, swfac2] = *row {
+            so.soil_daily_runoff.push(rof);
+            so.soil_daily_infiltration.push(inf);
+            so.soil_daily_drainage.push(drn);
+            so.soil_evapotranspiration.push(etp);
+            so.soil_evaporation.push(esa);
+            so.plant_potential_transpiration.push(epa);
+            so.soil_water_storage_depth.push(swc);
+            so.soil_water_profile_ratio.push(swc_dp);
+            so.soil_water_deficit_stress.push(swfac1);
+            so.soil_water_excess_stress.push(swfac2);
+        }
+    }
+    to_recordbatches(po, so)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::checked_days;
+    use crate::model::DailyData;
+
+    #[test]
+    fn short_daily_input() {
+        let values = [1.0f32; 3];
+        let daily = DailyData {
+            irrigation: &values,
+            energy_flux: &values,
+            temp_max: &values,
+            temp_min: &values,
+            rainfall: &values[..2],
+            photosynthetic_energy_flux: &values,
+        };
+        assert!(checked_days(&daily).is_err());
+        let daily = DailyData {
+            rainfall: &values,
+            ..daily
+        };
+        assert_eq!(checked_days(&daily).unwrap(), 3);
+    }
+}
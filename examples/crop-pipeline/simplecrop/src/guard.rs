@@ -0,0 +1,223 @@
+//! Guards against rainfall and radiation the model can't use
+//!
+//! Regridding and upstream models can hand on NaN or negative rainfall and
+//! radiation. The Fortran SimpleCrop reads them without complaint and
+//! produces nonsense, so they are caught before its inputs are written. The
+//! policy is picked with `SIMPLECROP_INPUT_GUARD`: `error`, the default,
+//! fails the run, `clamp` sets them to zero and `fill` replaces them with the
+//! mean of the nearest valid days either side. Clamped and filled days are
+//! validation warnings, errors in strict mode.
+
+use std::fmt;
+use std::str::FromStr;
+
+use meillionen_mt::strictness;
+use stable_eyre::eyre::eyre;
+
+use crate::model::{DailyData, DailyValue};
+
+/// The environment variable picking the [`GuardPolicy`]
+pub const GUARD_ENV: &str = "SIMPLECROP_INPUT_GUARD";
+
+/// What to do with NaN or negative rainfall and radiation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GuardPolicy {
+    #[default]
+    Error,
+    Clamp,
+    Fill,
+}
+
+impl FromStr for GuardPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "error" => Ok(GuardPolicy::Error),
+            "clamp" => Ok(GuardPolicy::Clamp),
+            "fill" => Ok(GuardPolicy::Fill),
+            other => Err(format!(
+                "unknown input guard {}, expected error, clamp or fill",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GuardPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GuardPolicy::Error => "error",
+            GuardPolicy::Clamp => "clamp",
+            GuardPolicy::Fill => "fill",
+        })
+    }
+}
+
+impl GuardPolicy {
+    /// The policy set in [`GUARD_ENV`], [`GuardPolicy::Error`] if it is unset
+    pub fn from_env() -> stable_eyre::Result<Self> {
+        match std::env::var(GUARD_ENV) {
+            Ok(value) if !value.trim().is_empty() => value.parse().map_err(|e: String| eyre!(e)),
+            _ => Ok(GuardPolicy::default()),
+        }
+    }
+}
+
+fn valid(x: f64) -> bool {
+    x >= 0.0
+}
+
+/// The guarded columns of daily inputs where any were changed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GuardedDaily<F> {
+    pub rainfall: Vec<F>,
+    pub photosynthetic_energy_flux: Vec<F>,
+    pub energy_flux: Vec<F>,
+}
+
+impl<F: DailyValue> GuardedDaily<F> {
+    /// `daily` with the guarded columns in place of its own
+    pub fn daily<'a>(&'a self, daily: &DailyData<'a, F>) -> DailyData<'a, F> {
+        DailyData {
+            rainfall: &self.rainfall,
+            photosynthetic_energy_flux: &self.photosynthetic_energy_flux,
+            energy_flux: &self.energy_flux,
+            ..*daily
+        }
+    }
+}
+
+/// A copy of a column with its invalid values replaced by `policy`, `None`
+/// if it has none
+fn guard_column<F: DailyValue>(
+    column: &'static str,
+    values: &[F],
+    policy: GuardPolicy,
+) -> stable_eyre::Result<Option<Vec<F>>> {
+    let bad: Vec<usize> = (0..values.len())
+        .filter(|i| !valid(values[*i].into()))
+        .collect();
+    let first = match bad.first() {
+        Some(day) => *day,
+        None => return Ok(None),
+    };
+    let replacement = |day: usize| -> f64 {
+        match policy {
+            GuardPolicy::Fill => {
+                let before = values[..day]
+                    .iter()
+                    .rev()
+                    .map(|x| (*x).into())
+                    .find(|x| valid(*x));
+                let after = values[day + 1..]
+                    .iter()
+                    .map(|x| (*x).into())
+                    .find(|x| valid(*x));
+                match (before, after) {
+                    (Some(b), Some(a)) => (b + a) / 2.0,
+                    (Some(x), None) | (None, Some(x)) => x,
+                    (None, None) => 0.0,
+                }
+            }
+            _ => 0.0,
+        }
+    };
+    if policy == GuardPolicy::Error {
+        return Err(eyre!(
+            "{} on day {} is {}, set {} to clamp or fill to run anyway",
+            column,
+            first + 1,
+            values[first].into(),
+            GUARD_ENV
+        ));
+    }
+    let mut guarded = values.to_vec();
+    for day in bad.iter() {
+        guarded[*day] = F::from_f64(replacement(*day));
+    }
+    let days: Vec<String> = bad.iter().map(|d| (d + 1).to_string()).collect();
+    strictness::warn(format!(
+        "{} was NaN or negative on days {}, {} with {}",
+        column,
+        days.join(", "),
+        if policy == GuardPolicy::Fill {
+            "filled"
+        } else {
+            "clamped"
+        },
+        GUARD_ENV
+    ))
+    .map_err(|e| eyre!(e))?;
+    Ok(Some(guarded))
+}
+
+/// Check rainfall and radiation for NaN or negative values, returning the
+/// columns with them replaced by `policy` if there were any
+pub fn guard<F: DailyValue>(
+    daily: &DailyData<F>,
+    policy: GuardPolicy,
+) -> stable_eyre::Result<Option<GuardedDaily<F>>> {
+    let rainfall = guard_column("rainfall", daily.rainfall, policy)?;
+    let par = guard_column(
+        "photosynthetic_energy_flux",
+        daily.photosynthetic_energy_flux,
+        policy,
+    )?;
+    let srad = guard_column("energy_flux", daily.energy_flux, policy)?;
+    if rainfall.is_none() && par.is_none() && srad.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(GuardedDaily {
+        rainfall: rainfall.unwrap_or_else(|| daily.rainfall.to_vec()),
+        photosynthetic_energy_flux: par
+            .unwrap_or_else(|| daily.photosynthetic_energy_flux.to_vec()),
+        energy_flux: srad.unwrap_or_else(|| daily.energy_flux.to_vec()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guard::{guard, GuardPolicy};
+    use crate::model::DailyData;
+
+    #[test]
+    fn guard_policies() {
+        let rainfall = [1.0f64, f64::NAN, 3.0, -1.0];
+        let energy_flux = [10.0f64, 12.0, -5.0, 11.0];
+        let other = [0.0f64; 4];
+        let daily = DailyData {
+            irrigation: &other,
+            temp_max: &other,
+            temp_min: &other,
+            rainfall: &rainfall,
+            photosynthetic_energy_flux: &other,
+            energy_flux: &energy_flux,
+        };
+
+        assert_eq!(
+            guard(&daily, GuardPolicy::Error).unwrap_err().to_string(),
+            "rainfall on day 2 is NaN, set SIMPLECROP_INPUT_GUARD to clamp or fill to run anyway"
+        );
+
+        let clamped = guard(&daily, GuardPolicy::Clamp).unwrap().unwrap();
+        assert_eq!(clamped.rainfall, vec![1.0, 0.0, 3.0, 0.0]);
+        assert_eq!(clamped.energy_flux, vec![10.0, 12.0, 0.0, 11.0]);
+
+        let filled = guard(&daily, GuardPolicy::Fill).unwrap().unwrap();
+        assert_eq!(filled.rainfall, vec![1.0, 2.0, 3.0, 3.0]);
+        assert_eq!(filled.energy_flux, vec![10.0, 12.0, 11.5, 11.0]);
+        let d = filled.daily(&daily);
+        assert_eq!(d.rainfall, &[1.0, 2.0, 3.0, 3.0]);
+        assert!(std::ptr::eq(d.temp_max, daily.temp_max));
+
+        let fine = DailyData {
+            rainfall: &other,
+            energy_flux: &other,
+            ..daily
+        };
+        assert_eq!(guard(&fine, GuardPolicy::Error).unwrap(), None);
+        assert_eq!("fill".parse::<GuardPolicy>().unwrap(), GuardPolicy::Fill);
+        assert!("zero".parse::<GuardPolicy>().is_err());
+    }
+}
@@ -1,40 +1,84 @@
-use arrow::array::{Float32Array};
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, PrimitiveArray};
+use arrow::datatypes::{ArrowPrimitiveType, DataType, Field, Schema};
 use arrow::ipc::reader::StreamReader;
 use arrow::record_batch::RecordBatch;
-use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError, PyRuntimeError};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyBytes;
+use pyo3::types::{PyBytes, PyDict};
 
-use model::{DailyData, SimpleCropConfig, YearlyData};
+use guard::GuardPolicy;
+use model::{DailyData, DailyValue, SimpleCropConfig, YearlyData, YEARLY_PARAMETERS};
 
 use stable_eyre::eyre::WrapErr;
 
-mod model;
+pub mod dssat;
+#[cfg(feature = "fortran")]
+pub mod ffi;
+pub mod guard;
+pub mod model;
+pub mod native;
+pub mod pedotransfer;
+#[cfg(unix)]
+pub mod pipes;
+pub mod qc;
+#[cfg(feature = "soil-lookup")]
+pub mod soil;
+pub mod workspace;
 
-fn get_column<'a>(batch: &'a RecordBatch, name: &str) -> stable_eyre::Result<&'a [f32]> {
+fn get_column<'a, T: ArrowPrimitiveType>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> stable_eyre::Result<&'a [T::Native]> {
     let schema = batch.schema();
-    let col_ind = schema.index_of(name).map_err(|e| stable_eyre::eyre::eyre!(e))?;
+    let col_ind = schema
+        .index_of(name)
+        .map_err(|e| stable_eyre::eyre::eyre!(e))?;
     let col = batch.column(col_ind);
-    use arrow::datatypes::DataType::Float32;
     col.as_any()
-        .downcast_ref::<Float32Array>()
+        .downcast_ref::<PrimitiveArray<T>>()
         .ok_or(stable_eyre::eyre::eyre!(
             "column {} type mismatch: expected {:?} got {:?}",
             name,
-            col.data_type(),
-            Float32
+            T::DATA_TYPE,
+            col.data_type()
         ))
         .map(|a| a.values())
 }
 
-fn run_record_batch(
-    cli_path: String,
-    dir: String,
-    daily_batch: &RecordBatch,
-    yearly_batch: &RecordBatch,
-) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
-    let get_col =
-        |name: &str| get_column(&daily_batch, name).map_err(|e| PyKeyError::new_err(e.to_string()));
+const DAILY_COLUMNS: [&str; 6] = [
+    "irrigation",
+    "temp_max",
+    "temp_min",
+    "rainfall",
+    "photosynthetic_energy_flux",
+    "energy_flux",
+];
+
+/// Daily inputs in the float type they were given in
+enum Daily<'a> {
+    F32(DailyData<'a, f32>),
+    F64(DailyData<'a, f64>),
+}
+
+/// The daily inputs of a batch, `f64` if its temperatures are and `f32`
+/// otherwise, with every column of the same type
+fn daily(daily_batch: &RecordBatch) -> stable_eyre::Result<Daily<'_>> {
+    let temp_max = daily_batch
+        .schema()
+        .column_with_name("temp_max")
+        .map(|(_, f)| f.data_type().clone());
+    match temp_max {
+        Some(DataType::Float64) => daily_data(daily_batch).map(Daily::F64),
+        _ => daily_data(daily_batch).map(Daily::F32),
+    }
+}
+
+fn daily_data<F: DailyValue>(daily_batch: &RecordBatch) -> stable_eyre::Result<DailyData<'_, F>> {
+    let get_col = |name: &str| {
+        get_column::<F::Arrow>(daily_batch, name).map_err(|e| PyKeyError::new_err(e.to_string()))
+    };
 
     let irrigation = get_col("irrigation")?;
     let temp_max = get_col("temp_max")?;
@@ -43,25 +87,57 @@ fn run_record_batch(
     let photosynthetic_energy_flux = get_col("photosynthetic_energy_flux")?;
     let energy_flux = get_col("energy_flux")?;
 
-    let daily = DailyData {
+    Ok(DailyData {
         irrigation,
         temp_max,
         temp_min,
         rainfall,
         photosynthetic_energy_flux,
         energy_flux,
-    };
-
-    let yearly = YearlyData::from_recordbatch_row(yearly_batch, 0)?;
+    })
+}
 
-    let config = SimpleCropConfig { daily, yearly };
+/// The daily inputs as a batch, the inverse of `daily_data`
+fn daily_recordbatch<F: DailyValue>(daily: &DailyData<F>) -> stable_eyre::Result<RecordBatch> {
+    let columns = [
+        daily.irrigation,
+        daily.temp_max,
+        daily.temp_min,
+        daily.rainfall,
+        daily.photosynthetic_energy_flux,
+        daily.energy_flux,
+    ];
+    let fields = DAILY_COLUMNS
+        .iter()
+        .map(|name| Field::new(name, F::Arrow::DATA_TYPE, false))
+        .collect();
+    let arrays = columns
+        .iter()
+        .map(|c| {
+            Arc::new(PrimitiveArray::<F::Arrow>::from_iter_values(
+                c.iter().copied(),
+            )) as ArrayRef
+        })
+        .collect();
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
 
-    config.run(&cli_path, &dir)
+/// Where to run SimpleCrop: the Fortran executable at `cli_path` in `dir`,
+/// given its inputs through named pipes if `piped`, in process, or the linked
+/// Fortran library
+enum Runner {
+    Cli {
+        cli_path: String,
+        dir: String,
+        piped: bool,
+    },
+    InProcess,
+    #[cfg(feature = "fortran")]
+    Linked,
 }
 
 fn run(
-    cli_path: String,
-    dir: String,
+    runner: Runner,
     daily_stream: StreamReader<&[u8]>,
     yearly_stream: StreamReader<&[u8]>,
 ) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
@@ -76,39 +152,468 @@ fn run(
     };
     let daily_batch = stream_convert(daily_stream)?;
     let yearly_batch = stream_convert(yearly_stream)?;
-    run_record_batch(cli_path, dir, &daily_batch, &yearly_batch)
+    let yearly = YearlyData::from_recordbatch_row(&yearly_batch, 0)?;
+    let guard = GuardPolicy::from_env()?;
+    match daily(&daily_batch)? {
+        Daily::F32(daily) => run_config(
+            runner,
+            SimpleCropConfig {
+                daily,
+                yearly,
+                guard,
+            },
+        ),
+        Daily::F64(daily) => run_config(
+            runner,
+            SimpleCropConfig {
+                daily,
+                yearly,
+                guard,
+            },
+        ),
+    }
+}
+
+fn run_config<F: DailyValue>(
+    runner: Runner,
+    config: SimpleCropConfig<F>,
+) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+    tracing::trace!(rainfall = ?config.daily.rainfall, "daily inputs");
+    match runner {
+        Runner::Cli {
+            cli_path,
+            dir,
+            piped: false,
+        } => {
+            tracing::debug!(%cli_path, %dir, "running simplecrop executable");
+            config.run(&cli_path, &dir)
+        }
+        #[cfg(unix)]
+        Runner::Cli {
+            cli_path,
+            dir,
+            piped: true,
+        } => {
+            tracing::debug!(%cli_path, %dir, "running simplecrop executable with piped inputs");
+            config.run_piped(&cli_path, &dir)
+        }
+        #[cfg(not(unix))]
+        Runner::Cli { piped: true, .. } => Err(stable_eyre::eyre::eyre!(
+            "piping inputs needs named pipes, which are only available on unix"
+        )),
+        Runner::InProcess => config.run_in_process(),
+        #[cfg(feature = "fortran")]
+        Runner::Linked => config.run_linked(),
+    }
+}
+
+/// A weather problem as (day, check, column, value)
+type QcIssue = (usize, &'static str, &'static str, f32);
+
+fn quality_control<'a>(
+    py: Python<'a>,
+    daily: &DailyData,
+    correct: bool,
+) -> PyResult<(Vec<QcIssue>, Option<&'a PyBytes>)> {
+    let config = qc::QcConfig::default();
+    let report = qc::check(daily, &config);
+    report
+        .warn()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let issues = report
+        .issues
+        .iter()
+        .map(|i| (i.day, i.check.as_str(), i.column, i.value))
+        .collect();
+    if !correct {
+        return Ok((issues, None));
+    }
+    let fixed = qc::correct(daily, &report, &config);
+    let rb =
+        daily_recordbatch(&fixed.daily()).map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+    Ok((issues, Some(to_pybytes(py, rb)?)))
+}
+
+fn to_pybytes(py: Python<'_>, rb: RecordBatch) -> PyResult<&PyBytes> {
+    let mut sink = Vec::<u8>::new();
+    {
+        let mut writer = arrow::ipc::writer::StreamWriter::try_new(&mut sink, rb.schema().as_ref())
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        writer
+            .write(&rb)
+            .wrap_err("Cannot write record batch")
+            .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+    }
+    Ok(PyBytes::new(py, sink.as_ref()))
+}
+
+/// Conversion of a struct of scalar parameters to and from a Python dict
+/// keyed by field name
+///
+/// Keys left out of a dict keep their default, so a calibration or sweep
+/// only gives the parameters it changes. Keys that are not fields are an
+/// error rather than silently ignored.
+macro_rules! impl_py_dict {
+    ($T: ty, $($field: ident), *) => {
+        impl $T {
+            pub fn to_py_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+                let dict = PyDict::new(py);
+                $(dict.set_item(stringify!($field), self.$field)?;)*
+                Ok(dict)
+            }
+
+            pub fn from_py_dict(dict: &PyDict) -> PyResult<Self> {
+                let mut value = Self::default();
+                for (key, item) in dict.iter() {
+                    let key: &str = key.extract()?;
+                    match key {
+                        $(stringify!($field) => value.$field = item.extract()?,)*
+                        _ => {
+                            return Err(PyKeyError::new_err(format!(
+                                "{} is not a parameter of {}",
+                                key,
+                                stringify!($T)
+                            )))
+                        }
+                    }
+                }
+                Ok(value)
+            }
+        }
+    };
+}
+
+impl_py_dict![
+    YearlyData,
+    plant_leaves_max_number,
+    plant_emp2,
+    plant_emp1,
+    plant_density,
+    plant_nb,
+    plant_leaf_max_appearance_rate,
+    plant_growth_canopy_fraction,
+    plant_min_repro_growth_temp,
+    plant_repro_phase_duration,
+    plant_leaves_number_of,
+    plant_leaf_area_index,
+    plant_matter,
+    plant_matter_root,
+    plant_matter_canopy,
+    plant_matter_leaves_removed,
+    plant_development_phase,
+    plant_leaf_specific_area,
+    soil_water_content_wilting_point,
+    soil_water_content_field_capacity,
+    soil_water_content_saturation,
+    soil_profile_depth,
+    soil_drainage_daily_percent,
+    soil_runoff_curve_number,
+    soil_water_storage,
+    day_of_planting,
+    printout_freq
+];
+
+impl YearlyData {
+    /// The default, units, valid range and description of each parameter
+    /// in [`YEARLY_PARAMETERS`], with `None` for units or bounds that don't
+    /// apply
+    pub fn parameter_docs(py: Python<'_>) -> PyResult<&PyDict> {
+        let defaults = Self::default().to_py_dict(py)?;
+        let docs = PyDict::new(py);
+        for p in YEARLY_PARAMETERS.iter() {
+            let doc = PyDict::new(py);
+            doc.set_item("default", defaults.get_item(p.name))?;
+            doc.set_item("units", p.units)?;
+            doc.set_item("min", p.min)?;
+            doc.set_item("max", p.max)?;
+            doc.set_item("description", p.description)?;
+            docs.set_item(p.name, doc)?;
+        }
+        Ok(docs)
+    }
+}
+
+fn run_py<'a>(
+    py: Python<'a>,
+    runner: Runner,
+    daily_stream_ref: &[u8],
+    yearly_stream_ref: &[u8],
+) -> PyResult<(&'a PyBytes, &'a PyBytes)> {
+    let daily_stream =
+        StreamReader::try_new(daily_stream_ref).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    let yearly_stream =
+        StreamReader::try_new(yearly_stream_ref).map_err(|e| PyIOError::new_err(e.to_string()))?;
+    let (plant, soil) = run(runner, daily_stream, yearly_stream)
+        .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
+    Ok((to_pybytes(py, plant)?, to_pybytes(py, soil)?))
+}
+
+#[cfg(feature = "fortran")]
+#[pyfunction]
+#[text_signature = "(daily_stream_ref, year_stream_ref, /)"]
+fn run_linked<'a>(
+    py: Python<'a>,
+    daily_stream_ref: &[u8],
+    yearly_stream_ref: &[u8],
+) -> PyResult<(&'a PyBytes, &'a PyBytes)> {
+    run_py(py, Runner::Linked, daily_stream_ref, yearly_stream_ref)
+}
+
+/// A working directory held by one SimpleCrop instance until it is closed
+#[pyclass(name = "Workspace")]
+struct PyWorkspace {
+    inner: Option<workspace::Workspace>,
+}
+
+#[pymethods]
+impl PyWorkspace {
+    /// Claim a directory, failing if another instance holds it
+    ///
+    /// :param dir: the directory, created if needed
+    /// :param shared: use the directory without claiming it
+    #[new]
+    #[args(shared = "false")]
+    fn new(dir: String, shared: bool) -> PyResult<Self> {
+        let inner = if shared {
+            workspace::Workspace::shared(dir)
+        } else {
+            workspace::Workspace::claim(dir)
+                .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?
+        };
+        Ok(Self { inner: Some(inner) })
+    }
+
+    /// A new directory under `base` that no other instance uses
+    ///
+    /// :param base: the parent directory
+    /// :param name: the start of the directory name
+    #[staticmethod]
+    #[args(name = "\"simplecrop\"")]
+    #[text_signature = "(base, name='simplecrop', /)"]
+    fn unique(base: String, name: &str) -> PyResult<Self> {
+        let inner = workspace::Workspace::unique(base, name)
+            .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        Ok(Self { inner: Some(inner) })
+    }
+
+    #[getter]
+    fn path(&self) -> PyResult<String> {
+        self.inner
+            .as_ref()
+            .map(|w| w.path().to_string_lossy().into_owned())
+            .ok_or_else(|| PyValueError::new_err("workspace is closed"))
+    }
+
+    #[getter]
+    fn shared(&self) -> bool {
+        self.inner.as_ref().is_some_and(|w| w.is_shared())
+    }
+
+    /// Release the directory so another instance can claim it
+    #[text_signature = "($self, /)"]
+    fn close(&mut self) {
+        self.inner = None;
+    }
 }
 
 #[pymodule]
 fn simplecrop_omf(_py: Python, m: &PyModule) -> PyResult<()> {
-    #[pyfn(m, "run")]
-    #[text_signature = "(cli_path, dir, daily_stream_ref, year_stream_ref, /)"]
-    fn run_py<'a>(
-        _py: Python<'a>,
+    /// Run the executable, streaming its inputs through named pipes instead
+    /// of writing them to `dir` if `piped`
+    #[pyfn(m, "run", piped = "false")]
+    #[text_signature = "(cli_path, dir, daily_stream_ref, year_stream_ref, piped=False, /)"]
+    fn run_cli_py<'a>(
+        py: Python<'a>,
         cli_path: String,
         dir: String,
         daily_stream_ref: &[u8],
         yearly_stream_ref: &[u8],
+        piped: bool,
     ) -> PyResult<(&'a PyBytes, &'a PyBytes)> {
-        let daily_stream = StreamReader::try_new(daily_stream_ref)
-            .map_err(|e| PyIOError::new_err(e.to_string()))?;
-        let yearly_stream = StreamReader::try_new(yearly_stream_ref)
-            .map_err(|e| PyIOError::new_err(e.to_string()))?;
-        let (plant, soil) = run(cli_path, dir, daily_stream, yearly_stream)
-            .map_err(|e| PyRuntimeError::new_err(format!("{:?}", e)))?;
-        let to_pybytes = |rb: RecordBatch| -> PyResult<&PyBytes> {
-            let mut sink = Vec::<u8>::new();
-            {
-                let mut writer =
-                    arrow::ipc::writer::StreamWriter::try_new(&mut sink, rb.schema().as_ref())
-                        .map_err(|e| PyIOError::new_err(e.to_string()))?;
-                writer.write(&rb).wrap_err("Cannot write record batch")
-                    .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        run_py(
+            py,
+            Runner::Cli {
+                cli_path,
+                dir,
+                piped,
+            },
+            daily_stream_ref,
+            yearly_stream_ref,
+        )
+    }
+
+    #[pyfn(m, "run_in_process")]
+    #[text_signature = "(daily_stream_ref, year_stream_ref, /)"]
+    fn run_in_process_py<'a>(
+        py: Python<'a>,
+        daily_stream_ref: &[u8],
+        yearly_stream_ref: &[u8],
+    ) -> PyResult<(&'a PyBytes, &'a PyBytes)> {
+        run_py(py, Runner::InProcess, daily_stream_ref, yearly_stream_ref)
+    }
+
+    /// The default yearly parameters as a one row IPC stream
+    #[pyfn(m, "yearly_defaults")]
+    #[text_signature = "()"]
+    fn yearly_defaults_py(py: Python<'_>) -> PyResult<&PyBytes> {
+        let rb = YearlyData::to_recordbatch(&[YearlyData::default()])
+            .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        to_pybytes(py, rb)
+    }
+
+    /// The yearly parameters as a dict keyed by parameter name, the first row
+    /// of `yearly_stream_ref` if given and the defaults otherwise
+    #[pyfn(m, "yearly_parameters", yearly_stream_ref = "None")]
+    #[text_signature = "(yearly_stream_ref=None, /)"]
+    fn yearly_parameters_py<'a>(
+        py: Python<'a>,
+        yearly_stream_ref: Option<&[u8]>,
+    ) -> PyResult<&'a PyDict> {
+        let yearly = match yearly_stream_ref {
+            Some(stream_ref) => {
+                let mut stream = StreamReader::try_new(stream_ref)
+                    .map_err(|e| PyIOError::new_err(e.to_string()))?;
+                let batch = stream
+                    .next()
+                    .ok_or_else(|| PyValueError::new_err("stream was empty"))?
+                    .map_err(|e| PyIOError::new_err(e.to_string()))?;
+                YearlyData::from_recordbatch_row(&batch, 0)
+                    .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?
             }
-            Ok(PyBytes::new(_py, sink.as_ref()))
+            None => YearlyData::default(),
         };
-        Ok((to_pybytes(plant)?, to_pybytes(soil)?))
+        yearly.to_py_dict(py)
+    }
+
+    /// The default, units, valid range and description of each yearly
+    /// parameter, keyed by parameter name
+    #[pyfn(m, "yearly_parameter_docs")]
+    #[text_signature = "()"]
+    fn yearly_parameter_docs_py(py: Python<'_>) -> PyResult<&PyDict> {
+        YearlyData::parameter_docs(py)
+    }
+
+    /// The `columns` and `rules` of the yearly source schema as json, with
+    /// the default, units, range and description of each parameter in the
+    /// column metadata
+    #[pyfn(m, "yearly_schema")]
+    #[text_signature = "()"]
+    fn yearly_schema_py() -> PyResult<String> {
+        let columns =
+            YearlyData::columns().map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        let schema = serde_json::json!({ "columns": columns, "rules": YearlyData::rules() });
+        Ok(schema.to_string())
+    }
+
+    /// A one row IPC stream of yearly parameters from a dict, with the
+    /// defaults for parameters it leaves out
+    ///
+    /// Parameters outside their valid range are a `ValueError`.
+    #[pyfn(m, "yearly_stream")]
+    #[text_signature = "(parameters, /)"]
+    fn yearly_stream_py<'a>(py: Python<'a>, parameters: &PyDict) -> PyResult<&'a PyBytes> {
+        let yearly = YearlyData::from_py_dict(parameters)?;
+        yearly
+            .check()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let rb = YearlyData::to_recordbatch(&[yearly])
+            .map_err(|e| PyValueError::new_err(format!("{:?}", e)))?;
+        to_pybytes(py, rb)
+    }
+
+    /// Check daily weather for spikes, swapped temperatures, impossible
+    /// radiation and repeated days, warning about them, and with `correct`
+    /// return the daily inputs with the fixable ones fixed
+    ///
+    /// The issues are (day, check, column, value) with days from 0. Checks
+    /// and corrections are made in `f32`, so corrected `f64` inputs come
+    /// back as `f32`.
+    #[pyfn(m, "quality_control", correct = "false")]
+    #[text_signature = "(daily_stream_ref, correct=False, /)"]
+    fn quality_control_py<'a>(
+        py: Python<'a>,
+        daily_stream_ref: &[u8],
+        correct: bool,
+    ) -> PyResult<(Vec<QcIssue>, Option<&'a PyBytes>)> {
+        let to_err = |e: stable_eyre::Report| PyValueError::new_err(format!("{:?}", e));
+        let mut stream = StreamReader::try_new(daily_stream_ref)
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let batch = stream
+            .next()
+            .ok_or_else(|| PyValueError::new_err("stream was empty"))?
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        match daily(&batch).map_err(to_err)? {
+            Daily::F32(daily) => quality_control(py, &daily, correct),
+            Daily::F64(daily) => daily.with_f32(|daily| quality_control(py, daily, correct)),
+        }
     }
 
+    /// Set how much the model prints to stderr: quiet, normal, verbose or debug
+    #[pyfn(m, "set_verbosity")]
+    #[text_signature = "(level, /)"]
+    fn set_verbosity_py(level: &str) -> PyResult<()> {
+        let level = level
+            .parse()
+            .map_err(|e: meillionen_mt::verbosity::VerbosityError| {
+                PyValueError::new_err(e.to_string())
+            })?;
+        meillionen_mt::verbosity::set_verbosity(level);
+        Ok(())
+    }
+
+    /// Turn strict mode, where validation warnings are errors, on or off
+    #[pyfn(m, "set_strict")]
+    #[text_signature = "(strict, /)"]
+    fn set_strict_py(strict: bool) {
+        meillionen_mt::strictness::set_strict(strict)
+    }
+
+    m.add_class::<PyWorkspace>()?;
+
+    #[cfg(feature = "fortran")]
+    m.add_function(pyo3::wrap_pyfunction!(run_linked, m)?)?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use pyo3::types::PyDict;
+    use pyo3::Python;
+
+    use crate::model::YearlyData;
+
+    #[test]
+    fn yearly_py_dict() {
+        Python::with_gil(|py| {
+            let yearly = YearlyData::default();
+            let dict = yearly.to_py_dict(py).unwrap();
+            assert_eq!(dict.len(), 26);
+            assert_eq!(YearlyData::from_py_dict(dict).unwrap(), yearly);
+
+            let changed = PyDict::new(py);
+            changed.set_item("plant_density", 6).unwrap();
+            changed.set_item("day_of_planting", 130).unwrap();
+            let changed = YearlyData::from_py_dict(changed).unwrap();
+            assert_eq!(changed.plant_density, 6.0);
+            assert_eq!(changed.day_of_planting, 130);
+            assert_eq!(changed.soil_profile_depth, yearly.soil_profile_depth);
+
+            let docs = YearlyData::parameter_docs(py).unwrap();
+            assert_eq!(docs.len(), dict.len());
+            let curve_number: &PyDict = docs
+                .get_item("soil_runoff_curve_number")
+                .unwrap()
+                .downcast()
+                .unwrap();
+            let max: Option<f64> = curve_number.get_item("max").unwrap().extract().unwrap();
+            assert_eq!(max, Some(100.0));
+            let default: f32 = curve_number.get_item("default").unwrap().extract().unwrap();
+            assert_eq!(default, yearly.soil_runoff_curve_number);
+
+            let unknown = PyDict::new(py);
+            unknown.set_item("planting_day", 130).unwrap();
+            assert!(YearlyData::from_py_dict(unknown).is_err());
+        })
+    }
+}
@@ -1,59 +1,198 @@
 #![cfg_attr(not(debug_assertions), deny(warnings))]
 
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
 use std::fs::{create_dir_all, File};
 use std::io;
-use std::io::{BufRead, BufReader, BufWriter, Write, ErrorKind};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Output};
 use std::sync::Arc;
 
 use arrow::array::{ArrayRef, Float32Array, Int32Array, PrimitiveArray};
-use arrow::datatypes::{ArrowPrimitiveType, Field, Schema};
+use arrow::datatypes::{ArrowPrimitiveType, Field, Float32Type, Float64Type, Schema};
 use arrow::record_batch::RecordBatch;
-use stable_eyre::eyre::WrapErr;
+use arrow::util::display::array_value_to_string;
+use meillionen_mt::arg::schema::Columns;
+use meillionen_mt::diff::{diff_with_tolerance, Difference};
+use meillionen_mt::outputs::OutputSelection;
+use meillionen_mt::validation::Rule;
+use serde_derive::{Deserialize, Serialize};
+use stable_eyre::eyre::{eyre, WrapErr};
 
-#[derive(Clone, Debug, Default, PartialEq)]
-pub struct DailyData<'a> {
+use crate::guard::{guard, GuardPolicy};
+use crate::native;
+
+/// A float type daily inputs can be given in
+///
+/// The model equations and the Fortran program work in `f32` but forcing
+/// such as reanalysis comes as `f64`. Keeping it as given means the input
+/// files are rounded from the original values and inputs compare exactly
+/// after a round trip, with `f32` copies only made for the equations.
+pub trait DailyValue:
+    Copy + Default + PartialEq + Debug + Into<f64> + Send + Sync + 'static
+{
+    type Arrow: ArrowPrimitiveType<Native = Self>;
+
+    fn from_f64(x: f64) -> Self;
+
+    /// Append the value with one decimal right aligned in `width` columns
+    fn push_fixed1(self, row: &mut Vec<u8>, width: usize);
+
+    /// The values as `f32`, borrowed if they already are
+    fn to_f32s(values: &[Self]) -> Cow<'_, [f32]>;
+}
+
+impl DailyValue for f32 {
+    type Arrow = Float32Type;
+
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+
+    fn push_fixed1(self, row: &mut Vec<u8>, width: usize) {
+        push_fixed1(row, self, width)
+    }
+
+    fn to_f32s(values: &[Self]) -> Cow<'_, [f32]> {
+        Cow::Borrowed(values)
+    }
+}
+
+impl DailyValue for f64 {
+    type Arrow = Float64Type;
+
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+
+    fn push_fixed1(self, row: &mut Vec<u8>, width: usize) {
+        // narrowed first so the files hold what the native and linked models
+        // read
+        push_fixed1(row, self as f32, width)
+    }
+
+    fn to_f32s(values: &[Self]) -> Cow<'_, [f32]> {
+        Cow::Owned(values.iter().map(|x| *x as f32).collect())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DailyData<'a, F = f32> {
     // irrigation related
-    pub irrigation: &'a [f32],
+    pub irrigation: &'a [F],
 
     // weather related
-    pub temp_max: &'a [f32],                   // tmax
-    pub temp_min: &'a [f32],                   // tmin
-    pub rainfall: &'a [f32],                   // rain
-    pub photosynthetic_energy_flux: &'a [f32], // par
-    pub energy_flux: &'a [f32],                // srad
+    pub temp_max: &'a [F],                   // tmax
+    pub temp_min: &'a [F],                   // tmin
+    pub rainfall: &'a [F],                   // rain
+    pub photosynthetic_energy_flux: &'a [F], // par
+    pub energy_flux: &'a [F],                // srad
 }
 
-impl<'a> DailyData<'a> {
+/// Append an integer right aligned in `width` columns, like
+/// `format!("{:width$}", i)`
+fn push_int(row: &mut Vec<u8>, i: usize, width: usize) {
+    let mut digits = itoa::Buffer::new();
+    let digits = digits.format(i);
+    row.extend(std::iter::repeat_n(
+        b' ',
+        width.saturating_sub(digits.len()),
+    ));
+    row.extend_from_slice(digits.as_bytes());
+}
+
+/// Append a float with one decimal right aligned in `width` columns, like
+/// `format!("{:>width$.1}", x)`
+fn push_fixed1(row: &mut Vec<u8>, x: f32, width: usize) {
+    if !x.is_finite() {
+        // writing to a Vec cannot fail
+        let _ = write!(row, "{:>width$.1}", x, width = width);
+        return;
+    }
+    // an f32 times 10 is exact as an f64 so this rounds the same way format! does
+    let tenths = (f64::from(x) * 10.0).abs().round_ties_even() as u64;
+    let mut digits = itoa::Buffer::new();
+    let whole = digits.format(tenths / 10);
+    let negative = x.is_sign_negative();
+    let len = whole.len() + 2 + usize::from(negative);
+    row.extend(std::iter::repeat_n(b' ', width.saturating_sub(len)));
+    if negative {
+        row.push(b'-');
+    }
+    row.extend_from_slice(whole.as_bytes());
+    row.push(b'.');
+    row.push(b'0' + (tenths % 10) as u8);
+}
+
+impl<'a, F: DailyValue> DailyData<'a, F> {
+    /// The same inputs with another rainfall series, such as the water that
+    /// infiltrated one cell of a grid
+    ///
+    /// The other columns are borrowed so inputs for many cells share them
+    /// instead of each holding a copy.
+    pub fn with_rainfall<'b>(&self, rainfall: &'b [F]) -> DailyData<'b, F>
+    where
+        'a: 'b,
+    {
+        DailyData { rainfall, ..*self }
+    }
+
+    /// Call `f` with the inputs as `f32`, which the model equations use,
+    /// copying them only if they are not already
+    pub fn with_f32<R>(&self, f: impl FnOnce(&DailyData<'_, f32>) -> R) -> R {
+        let irrigation = F::to_f32s(self.irrigation);
+        let temp_max = F::to_f32s(self.temp_max);
+        let temp_min = F::to_f32s(self.temp_min);
+        let rainfall = F::to_f32s(self.rainfall);
+        let photosynthetic_energy_flux = F::to_f32s(self.photosynthetic_energy_flux);
+        let energy_flux = F::to_f32s(self.energy_flux);
+        f(&DailyData {
+            irrigation: &irrigation,
+            temp_max: &temp_max,
+            temp_min: &temp_min,
+            rainfall: &rainfall,
+            photosynthetic_energy_flux: &photosynthetic_energy_flux,
+            energy_flux: &energy_flux,
+        })
+    }
+
     pub fn save_irrigation<W: Write>(&self, buf: &mut W) -> io::Result<()> {
-        let mut i = 1;
-        for obs in self.irrigation.iter() {
-            let row = format!("{:5}  {:1.1}\n", i, obs);
-            buf.write_all(row.as_bytes())?;
-            i += 1;
+        let mut row = Vec::with_capacity(16);
+        for (i, obs) in (1..).zip(self.irrigation.iter()) {
+            row.clear();
+            push_int(&mut row, i, 5);
+            row.extend_from_slice(b"  ");
+            obs.push_fixed1(&mut row, 1);
+            row.push(b'\n');
+            buf.write_all(&row)?;
         }
         Ok(())
     }
 
     pub fn save_weather<W: Write>(&self, buf: &mut W) -> io::Result<()> {
+        let mut row = Vec::with_capacity(64);
         for i in 0..self.temp_max.len() {
-            let row = format!(
-                "{:5}  {:>4.1}  {:>4.1}  {:>4.1}{:>6.1}              {:>4.1}\n",
-                i + 1,
-                self.energy_flux[i],
-                self.temp_max[i],
-                self.temp_min[i],
-                self.rainfall[i],
-                self.photosynthetic_energy_flux[i]
-            );
-            buf.write_all(row.as_bytes())?;
+            row.clear();
+            push_int(&mut row, i + 1, 5);
+            row.extend_from_slice(b"  ");
+            self.energy_flux[i].push_fixed1(&mut row, 4);
+            row.extend_from_slice(b"  ");
+            self.temp_max[i].push_fixed1(&mut row, 4);
+            row.extend_from_slice(b"  ");
+            self.temp_min[i].push_fixed1(&mut row, 4);
+            self.rainfall[i].push_fixed1(&mut row, 6);
+            row.extend_from_slice(b"              ");
+            self.photosynthetic_energy_flux[i].push_fixed1(&mut row, 4);
+            row.push(b'\n');
+            buf.write_all(&row)?;
         }
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
 pub struct YearlyData {
     // plant config
     pub plant_leaves_max_number: f32, // lfmax
@@ -94,7 +233,7 @@ impl YearlyData {
         name: &str,
         i: usize,
     ) -> stable_eyre::Result<T::Native> {
-        let col_ind = rb.schema().index_of(&name)?;
+        let col_ind = rb.schema().index_of(name)?;
         let col = rb.column(col_ind);
         col.as_any()
             .downcast_ref::<PrimitiveArray<T>>()
@@ -115,7 +254,7 @@ impl YearlyData {
                 }
             }
         }
-        Ok(make_inst_rb![
+        let yearly = make_inst_rb![
             (plant_leaves_max_number, Float32Type),
             (plant_emp2, Float32Type),
             (plant_emp1, Float32Type),
@@ -142,7 +281,124 @@ impl YearlyData {
             (soil_water_storage, Float32Type),
             (day_of_planting, Int32Type),
             (printout_freq, Int32Type)
-        ])
+        ];
+        yearly.check()?;
+        Ok(yearly)
+    }
+
+    /// Fail if a parameter is outside the range given in
+    /// [`YEARLY_PARAMETERS`]
+    pub fn check(&self) -> stable_eyre::Result<()> {
+        let values = serde_json::to_value(self).expect("yearly data to serialize");
+        let problems: Vec<String> = YEARLY_PARAMETERS
+            .iter()
+            .filter_map(|p| {
+                let value = values[p.name].as_f64()?;
+                let below = p.min.is_some_and(|min| value < min);
+                let above = p.max.is_some_and(|max| value > max);
+                if below || above {
+                    Some(format!("{} must be {} but is {}", p.name, p.range(), value))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(eyre!("{}", problems.join(", ")))
+        }
+    }
+
+    /// The min and max rules of [`YEARLY_PARAMETERS`], to validate tables
+    /// of yearly parameters
+    pub fn rules() -> Vec<Rule> {
+        let mut rules = vec![];
+        for p in YEARLY_PARAMETERS {
+            if let Some(value) = p.min {
+                rules.push(Rule::Min {
+                    column: p.name.to_string(),
+                    value,
+                });
+            }
+            if let Some(value) = p.max {
+                rules.push(Rule::Max {
+                    column: p.name.to_string(),
+                    value,
+                });
+            }
+        }
+        rules
+    }
+
+    /// The columns of [`YearlyData::to_recordbatch`] with the default, units,
+    /// range and description of each as field metadata, for the interface
+    /// schema of the yearly source
+    pub fn columns() -> stable_eyre::Result<Columns> {
+        let defaults = Self::to_recordbatch(&[Self::default()])?;
+        let schema = defaults.schema();
+        let fields = YEARLY_PARAMETERS
+            .iter()
+            .zip(schema.fields().iter().zip(defaults.columns()))
+            .map(|(p, (field, default))| {
+                debug_assert_eq!(p.name, field.name());
+                let mut field = field.clone();
+                field.set_metadata(Some(p.metadata(array_value_to_string(default, 0)?)));
+                Ok(field)
+            })
+            .collect::<arrow::error::Result<Vec<_>>>()?;
+        Ok(Columns::new(fields))
+    }
+
+    /// The parameters that differ from `other` by more than a relative
+    /// `tolerance`, such as [`meillionen_mt::diff::TOLERANCE`]
+    pub fn diff(&self, other: &Self, tolerance: f64) -> Vec<Difference> {
+        let value = |y: &Self| serde_json::to_value(y).expect("yearly data to serialize");
+        diff_with_tolerance(&value(self), &value(other), tolerance)
+    }
+
+    /// A table with a row for each set of yearly parameters, the inverse of
+    /// `from_recordbatch_row`
+    pub fn to_recordbatch(rows: &[Self]) -> stable_eyre::Result<RecordBatch> {
+        use arrow::datatypes::DataType::*;
+        macro_rules! make_rb {
+            ($(($field: ident, $dt: expr, $array: ty)), *) => {{
+                let fields = vec![$(Field::new(stringify!($field), $dt, false)),*];
+                let cols: Vec<ArrayRef> = vec![
+                    $(Arc::new(<$array>::from(rows.iter().map(|r| r.$field).collect::<Vec<_>>()))),*
+                ];
+                RecordBatch::try_new(Arc::new(Schema::new(fields)), cols)
+                    .wrap_err("Cannot create yearly record batch")
+            }}
+        }
+        make_rb![
+            (plant_leaves_max_number, Float32, Float32Array),
+            (plant_emp2, Float32, Float32Array),
+            (plant_emp1, Float32, Float32Array),
+            (plant_density, Float32, Float32Array),
+            (plant_nb, Float32, Float32Array),
+            (plant_leaf_max_appearance_rate, Float32, Float32Array),
+            (plant_growth_canopy_fraction, Float32, Float32Array),
+            (plant_min_repro_growth_temp, Float32, Float32Array),
+            (plant_repro_phase_duration, Float32, Float32Array),
+            (plant_leaves_number_of, Float32, Float32Array),
+            (plant_leaf_area_index, Float32, Float32Array),
+            (plant_matter, Float32, Float32Array),
+            (plant_matter_root, Float32, Float32Array),
+            (plant_matter_canopy, Float32, Float32Array),
+            (plant_matter_leaves_removed, Float32, Float32Array),
+            (plant_development_phase, Float32, Float32Array),
+            (plant_leaf_specific_area, Float32, Float32Array),
+            (soil_water_content_wilting_point, Float32, Float32Array),
+            (soil_water_content_field_capacity, Float32, Float32Array),
+            (soil_water_content_saturation, Float32, Float32Array),
+            (soil_profile_depth, Float32, Float32Array),
+            (soil_drainage_daily_percent, Float32, Float32Array),
+            (soil_runoff_curve_number, Float32, Float32Array),
+            (soil_water_storage, Float32, Float32Array),
+            (day_of_planting, Int32, Int32Array),
+            (printout_freq, Int32, Int32Array)
+        ]
     }
 
     pub fn save_plant_config<W: Write>(&self, buf: &mut W) -> io::Result<()> {
@@ -232,7 +488,256 @@ impl Default for YearlyData {
     }
 }
 
-#[derive(Debug, Default)]
+/// The units, valid range and description of a yearly parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParameterDoc {
+    pub name: &'static str,
+    /// UDUNITS style units, `None` for coefficients without any
+    pub units: Option<&'static str>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub description: &'static str,
+}
+
+impl ParameterDoc {
+    const fn new(
+        name: &'static str,
+        units: Option<&'static str>,
+        min: Option<f64>,
+        max: Option<f64>,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            units,
+            min,
+            max,
+            description,
+        }
+    }
+
+    /// The range as text, such as `in [0, 1] cm3 cm-3`
+    pub fn range(&self) -> String {
+        let range = match (self.min, self.max) {
+            (Some(min), Some(max)) => format!("in [{}, {}]", min, max),
+            (Some(min), None) => format!(">= {}", min),
+            (None, Some(max)) => format!("<= {}", max),
+            (None, None) => "any value".to_string(),
+        };
+        match self.units {
+            Some(units) => format!("{} {}", range, units),
+            None => range,
+        }
+    }
+
+    /// The `default`, `units`, `min`, `max` and `description` keys of the
+    /// interface field metadata, leaving out what doesn't apply
+    fn metadata(&self, default: String) -> BTreeMap<String, String> {
+        let mut metadata = BTreeMap::new();
+        metadata.insert("default".to_string(), default);
+        metadata.insert("description".to_string(), self.description.to_string());
+        let optional = [
+            ("units", self.units.map(str::to_string)),
+            ("min", self.min.map(|v| v.to_string())),
+            ("max", self.max.map(|v| v.to_string())),
+        ];
+        for (key, value) in optional.iter() {
+            if let Some(value) = value {
+                metadata.insert(key.to_string(), value.clone());
+            }
+        }
+        metadata
+    }
+}
+
+/// The yearly parameters in the column order of
+/// [`YearlyData::to_recordbatch`]
+pub const YEARLY_PARAMETERS: [ParameterDoc; 26] = [
+    ParameterDoc::new(
+        "plant_leaves_max_number",
+        Some("1"),
+        Some(0.0),
+        None,
+        "Maximum number of leaves (Lfmax)",
+    ),
+    ParameterDoc::new(
+        "plant_emp2",
+        Some("1"),
+        None,
+        None,
+        "Leaf expansion curve coefficient (EMP2)",
+    ),
+    ParameterDoc::new(
+        "plant_emp1",
+        Some("m2"),
+        Some(0.0),
+        None,
+        "Maximum leaf area expansion per leaf (EMP1)",
+    ),
+    ParameterDoc::new(
+        "plant_density",
+        Some("m-2"),
+        Some(0.0),
+        None,
+        "Plants per square metre (PD)",
+    ),
+    ParameterDoc::new(
+        "plant_nb",
+        Some("1"),
+        None,
+        None,
+        "Leaf number curve coefficient (nb)",
+    ),
+    ParameterDoc::new(
+        "plant_leaf_max_appearance_rate",
+        Some("d-1"),
+        Some(0.0),
+        None,
+        "Maximum rate of leaf appearance (rm)",
+    ),
+    ParameterDoc::new(
+        "plant_growth_canopy_fraction",
+        Some("1"),
+        Some(0.0),
+        Some(1.0),
+        "Fraction of growth partitioned to the canopy (fc)",
+    ),
+    ParameterDoc::new(
+        "plant_min_repro_growth_temp",
+        Some("degC"),
+        None,
+        None,
+        "Base temperature of reproductive growth (tb)",
+    ),
+    ParameterDoc::new(
+        "plant_repro_phase_duration",
+        Some("degC d"),
+        Some(0.0),
+        None,
+        "Thermal time of the reproductive phase (intot)",
+    ),
+    ParameterDoc::new(
+        "plant_leaves_number_of",
+        Some("1"),
+        Some(0.0),
+        None,
+        "Number of leaves at planting (n)",
+    ),
+    ParameterDoc::new(
+        "plant_leaf_area_index",
+        Some("1"),
+        Some(0.0),
+        None,
+        "Leaf area index at planting (lai)",
+    ),
+    ParameterDoc::new(
+        "plant_matter",
+        Some("g m-2"),
+        Some(0.0),
+        None,
+        "Plant dry matter at planting (w)",
+    ),
+    ParameterDoc::new(
+        "plant_matter_root",
+        Some("g m-2"),
+        Some(0.0),
+        None,
+        "Root dry matter at planting (wr)",
+    ),
+    ParameterDoc::new(
+        "plant_matter_canopy",
+        Some("g m-2"),
+        Some(0.0),
+        None,
+        "Canopy dry matter at planting (wc)",
+    ),
+    ParameterDoc::new(
+        "plant_matter_leaves_removed",
+        Some("g"),
+        Some(0.0),
+        None,
+        "Dry matter of leaves removed per plant after the last leaf (p1)",
+    ),
+    ParameterDoc::new(
+        "plant_development_phase",
+        None,
+        Some(0.0),
+        None,
+        "Development phase coefficient (f1)",
+    ),
+    ParameterDoc::new(
+        "plant_leaf_specific_area",
+        Some("m2 g-1"),
+        Some(0.0),
+        None,
+        "Specific leaf area (sla)",
+    ),
+    ParameterDoc::new(
+        "soil_water_content_wilting_point",
+        Some("cm3 cm-3"),
+        Some(0.0),
+        Some(1.0),
+        "Water content at wilting point (WPp)",
+    ),
+    ParameterDoc::new(
+        "soil_water_content_field_capacity",
+        Some("cm3 cm-3"),
+        Some(0.0),
+        Some(1.0),
+        "Water content at field capacity (FCp)",
+    ),
+    ParameterDoc::new(
+        "soil_water_content_saturation",
+        Some("cm3 cm-3"),
+        Some(0.0),
+        Some(1.0),
+        "Water content at saturation (STp)",
+    ),
+    ParameterDoc::new(
+        "soil_profile_depth",
+        Some("cm"),
+        Some(0.0),
+        None,
+        "Depth of the soil profile (DP)",
+    ),
+    ParameterDoc::new(
+        "soil_drainage_daily_percent",
+        Some("d-1"),
+        Some(0.0),
+        Some(1.0),
+        "Fraction of water above field capacity drained each day (DRNp)",
+    ),
+    ParameterDoc::new(
+        "soil_runoff_curve_number",
+        Some("1"),
+        Some(0.0),
+        Some(100.0),
+        "Runoff curve number (CN)",
+    ),
+    ParameterDoc::new(
+        "soil_water_storage",
+        Some("mm"),
+        Some(0.0),
+        None,
+        "Water stored in the profile at planting (SWC)",
+    ),
+    ParameterDoc::new(
+        "day_of_planting",
+        Some("d"),
+        Some(1.0),
+        Some(366.0),
+        "Day of the year the crop is planted (DOYP)",
+    ),
+    ParameterDoc::new(
+        "printout_freq",
+        Some("d"),
+        Some(1.0),
+        None,
+        "Days between plant output rows (FROP)",
+    ),
+];
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct SoilDataSet {
     pub day_of_year: Vec<i32>,
     pub soil_daily_runoff: Vec<f32>,             // rof
@@ -273,9 +778,9 @@ impl SoilDataSet {
         Some(())
     }
 
-    fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
-        let f = File::open(&p).wrap_err_with(||
-            format!("Could not open {}", p.as_ref().to_string_lossy()))?;
+    pub(crate) fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
+        let f = File::open(&p)
+            .wrap_err_with(|| format!("Could not open {}", p.as_ref().to_string_lossy()))?;
         let rdr = BufReader::new(f);
         let mut results = SoilDataSet::default();
         for line in rdr.lines().skip(6) {
@@ -287,16 +792,16 @@ impl SoilDataSet {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct PlantDataSet {
-    day_of_year: Vec<i32>,
-    plant_leaf_count: Vec<f32>,
-    air_accumulated_temp: Vec<f32>,
-    plant_matter: Vec<f32>,
-    plant_matter_canopy: Vec<f32>,
-    plant_matter_fruit: Vec<f32>,
-    plant_matter_root: Vec<f32>,
-    plant_leaf_area_index: Vec<f32>,
+    pub day_of_year: Vec<i32>,
+    pub plant_leaf_count: Vec<f32>,
+    pub air_accumulated_temp: Vec<f32>,
+    pub plant_matter: Vec<f32>,
+    pub plant_matter_canopy: Vec<f32>,
+    pub plant_matter_fruit: Vec<f32>,
+    pub plant_matter_root: Vec<f32>,
+    pub plant_leaf_area_index: Vec<f32>,
 }
 
 impl PlantDataSet {
@@ -320,13 +825,9 @@ impl PlantDataSet {
         Some(())
     }
 
-    fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
-        println!(
-            "Current Directory: {}",
-            std::env::current_dir().unwrap().display()
-        );
-        let f = File::open(&p).wrap_err(
-            format!("Could not open {}", p.as_ref().to_string_lossy()))?;
+    pub(crate) fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
+        let f =
+            File::open(&p).wrap_err(format!("Could not open {}", p.as_ref().to_string_lossy()))?;
         let rdr = BufReader::new(f);
         let mut results = PlantDataSet::default();
         for line in rdr.lines().skip(9) {
@@ -338,110 +839,189 @@ impl PlantDataSet {
     }
 }
 
-fn load_output_data<P: AsRef<Path>>(dir: P) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
-    let po = PlantDataSet::load(&dir.as_ref().join("output/plant.out"))?;
-    let so = SoilDataSet::load(&dir.as_ref().join("output/soil.out"))?;
-    use arrow::datatypes::DataType::*;
-    let soil = {
-        let (fields, cols): (Vec<Field>, Vec<ArrayRef>) = vec![
-            ("soil_daily_drainage", so.soil_daily_drainage),
-            ("soil_daily_infiltration", so.soil_daily_infiltration),
-            ("soil_daily_runoff", so.soil_daily_runoff),
-            ("soil_evaporation", so.soil_evaporation),
-            ("soil_evapotranspiration", so.soil_evapotranspiration),
-            ("soil_water_deficit_stress", so.soil_water_deficit_stress),
-            ("soil_water_excess_stress", so.soil_water_excess_stress),
-            ("soil_water_profile_ratio", so.soil_water_profile_ratio),
-            ("soil_water_storage_depth", so.soil_water_storage_depth),
-            (
-                "plant_potential_transpiration",
-                so.plant_potential_transpiration,
-            ),
-        ]
-        .into_iter()
-        .map(|(name, col)| -> (Field, ArrayRef) {
-            (
-                Field::new(name, Float32, false),
-                Arc::new(Float32Array::from(col)),
-            )
-        })
-        .chain({
-            let day: ArrayRef = Arc::new(Int32Array::from(so.day_of_year));
-            vec![(Field::new("day", Int32, false), day)]
-        })
-        .unzip();
+/// Read `output/plant.out` and `output/soil.out` from a SimpleCrop run directory
+pub fn load_output_data<P: AsRef<Path>>(dir: P) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+    let po = PlantDataSet::load(dir.as_ref().join("output/plant.out"))?;
+    let so = SoilDataSet::load(dir.as_ref().join("output/soil.out"))?;
+    to_recordbatches(po, so)
+}
 
-        let schema_ref = Arc::new(Schema::new(fields));
-        RecordBatch::try_new(schema_ref, cols).wrap_err("Cannot create soil record batch")
-    }?;
-    let plant = {
-        let (fields, cols): (Vec<Field>, Vec<ArrayRef>) = vec![
-            ("air_accumulated_temp", po.air_accumulated_temp),
-            ("plant_leaf_area_index", po.plant_leaf_area_index),
-            ("plant_leaf_count", po.plant_leaf_count),
-            ("plant_matter", po.plant_matter),
-            ("plant_matter_canopy", po.plant_matter_canopy),
-            ("plant_matter_fruit", po.plant_matter_fruit),
-            ("plant_matter_root", po.plant_matter_root),
-        ]
+/// Columns of a struct of `Vec` fields, each moved into an arrow array
+/// without copying it field by field
+macro_rules! impl_columns {
+    ($ty: ty, $(($name: expr, $field: ident, $array: ty)), *) => {
+        impl $ty {
+            /// The name and values of each column of the data set
+            pub fn into_columns(self) -> Vec<(&'static str, ArrayRef)> {
+                vec![$(($name, Arc::new(<$array>::from(self.$field)) as ArrayRef)),*]
+            }
+        }
+    }
+}
+
+impl_columns![
+    SoilDataSet,
+    ("soil_daily_drainage", soil_daily_drainage, Float32Array),
+    (
+        "soil_daily_infiltration",
+        soil_daily_infiltration,
+        Float32Array
+    ),
+    ("soil_daily_runoff", soil_daily_runoff, Float32Array),
+    ("soil_evaporation", soil_evaporation, Float32Array),
+    (
+        "soil_evapotranspiration",
+        soil_evapotranspiration,
+        Float32Array
+    ),
+    (
+        "soil_water_deficit_stress",
+        soil_water_deficit_stress,
+        Float32Array
+    ),
+    (
+        "soil_water_excess_stress",
+        soil_water_excess_stress,
+        Float32Array
+    ),
+    (
+        "soil_water_profile_ratio",
+        soil_water_profile_ratio,
+        Float32Array
+    ),
+    (
+        "soil_water_storage_depth",
+        soil_water_storage_depth,
+        Float32Array
+    ),
+    (
+        "plant_potential_transpiration",
+        plant_potential_transpiration,
+        Float32Array
+    ),
+    ("day", day_of_year, Int32Array)
+];
+
+impl_columns![
+    PlantDataSet,
+    ("air_accumulated_temp", air_accumulated_temp, Float32Array),
+    ("plant_leaf_area_index", plant_leaf_area_index, Float32Array),
+    ("plant_leaf_count", plant_leaf_count, Float32Array),
+    ("plant_matter", plant_matter, Float32Array),
+    ("plant_matter_canopy", plant_matter_canopy, Float32Array),
+    ("plant_matter_fruit", plant_matter_fruit, Float32Array),
+    ("plant_matter_root", plant_matter_root, Float32Array),
+    ("day", day_of_year, Int32Array)
+];
+
+/// A table of the columns asked for, always keeping the day that indexes
+/// them
+fn select_recordbatch(
+    columns: Vec<(&'static str, ArrayRef)>,
+    selection: &OutputSelection,
+    what: &str,
+) -> stable_eyre::Result<RecordBatch> {
+    let (fields, cols): (Vec<Field>, Vec<ArrayRef>) = columns
         .into_iter()
-        .map(|(name, col)| -> (Field, ArrayRef) {
-            (
-                Field::new(name, Float32, false),
-                Arc::new(Float32Array::from(col)),
-            )
-        })
-        .chain({
-            let day: ArrayRef = Arc::new(Int32Array::from(po.day_of_year));
-            vec![(Field::new("day", Int32, false), day)]
-        })
+        .filter(|(name, _)| *name == "day" || selection.wants(name))
+        .map(|(name, col)| (Field::new(name, col.data_type().clone(), false), col))
         .unzip();
-        let schema_ref = Arc::new(Schema::new(fields));
-        RecordBatch::try_new(schema_ref, cols).wrap_err("Cannot create plant record batch")
-    }?;
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), cols)
+        .wrap_err_with(|| format!("Cannot create {} record batch", what))
+}
+
+/// The plant and soil tables of a run, leaving out outputs the trial did
+/// not ask for in `MEILLIONEN_OUTPUTS`
+pub(crate) fn to_recordbatches(
+    po: PlantDataSet,
+    so: SoilDataSet,
+) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+    let selection = OutputSelection::from_env();
+    let soil = select_recordbatch(so.into_columns(), &selection, "soil")?;
+    let plant = select_recordbatch(po.into_columns(), &selection, "plant")?;
     Ok((plant, soil))
 }
 
-pub struct SimpleCropConfig<'a> {
-    pub daily: DailyData<'a>,
+/// Where a run of the executable keeps what it printed, relative to its
+/// working directory
+pub const STDOUT_LOG: &str = "stdout.log";
+pub const STDERR_LOG: &str = "stderr.log";
+
+pub struct SimpleCropConfig<'a, F = f32> {
+    pub daily: DailyData<'a, F>,
     pub yearly: YearlyData,
+    /// What to do with NaN or negative rainfall and radiation before they
+    /// reach the Fortran program
+    pub guard: GuardPolicy,
 }
 
-impl<'a> SimpleCropConfig<'a> {
-    fn save<P: AsRef<Path>>(&self, dir: P) -> stable_eyre::Result<()> {
-        let dp = dir.as_ref().join("data");
-        create_dir_all(&dp).wrap_err("Cannot create data dir")?;
-        let write_f = |path: &str| File::create(&dp.join(path))
-            .map(BufWriter::new)
-            .wrap_err_with(|| format!("cannot create file {}", path));
-
-        let mut weather_buf = write_f("weather.inp")?;
-        self.daily
-            .save_weather(&mut weather_buf)
+impl<'a, F: DailyValue> SimpleCropConfig<'a, F> {
+    /// The contents of each input file the executable reads from `data/`
+    fn inputs(&self) -> stable_eyre::Result<Vec<(&'static str, Vec<u8>)>> {
+        let guarded = guard(&self.daily, self.guard)?;
+        let daily = guarded
+            .as_ref()
+            .map_or(self.daily, |g| g.daily(&self.daily));
+        let mut weather = vec![];
+        daily
+            .save_weather(&mut weather)
             .wrap_err("weather save failed")?;
-
-        let mut irrigation_buf = write_f("irrig.inp")?;
-        self.daily
-            .save_irrigation(&mut irrigation_buf)
+        let mut irrigation = vec![];
+        daily
+            .save_irrigation(&mut irrigation)
             .wrap_err("irrigation save failed")?;
-
-        let mut plant_buf = write_f("plant.inp")?;
+        let mut plant = vec![];
         self.yearly
-            .save_plant_config(&mut plant_buf)
+            .save_plant_config(&mut plant)
             .wrap_err("plant save failed")?;
-
-        let mut soil_buf = write_f("soil.inp")?;
+        let mut soil = vec![];
         self.yearly
-            .save_soil_config(&mut soil_buf)
+            .save_soil_config(&mut soil)
             .wrap_err("soil save failed")?;
-
-        let mut simctrl_buf = write_f("simctrl.inp")?;
+        let mut simctrl = vec![];
         self.yearly
-            .save_simulation_config(&mut simctrl_buf)
+            .save_simulation_config(&mut simctrl)
             .wrap_err("simctrl save failed")?;
+        Ok(vec![
+            ("weather.inp", weather),
+            ("irrig.inp", irrigation),
+            ("plant.inp", plant),
+            ("soil.inp", soil),
+            ("simctrl.inp", simctrl),
+        ])
+    }
+
+    fn save<P: AsRef<Path>>(&self, dir: P) -> stable_eyre::Result<()> {
+        let dp = dir.as_ref().join("data");
+        create_dir_all(&dp).wrap_err("Cannot create data dir")?;
+        for (path, data) in self.inputs()? {
+            let mut buf = File::create(dp.join(path))
+                .map(BufWriter::new)
+                .wrap_err_with(|| format!("cannot create file {}", path))?;
+            buf.write_all(&data)
+                .and_then(|_| buf.flush())
+                .wrap_err_with(|| format!("cannot write file {}", path))?;
+        }
         Ok(())
     }
 
+    /// Run the Rust version of the SimpleCrop equations in process instead
+    /// of the Fortran executable
+    pub fn run_in_process(&self) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+        self.daily
+            .with_f32(|daily| native::run(daily, &self.yearly).finish())
+    }
+
+    /// Run the Fortran SimpleCrop linked in as a library
+    #[cfg(feature = "fortran")]
+    pub fn run_linked(&self) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+        let guarded = guard(&self.daily, self.guard)?;
+        let daily = guarded
+            .as_ref()
+            .map_or(self.daily, |g| g.daily(&self.daily));
+        daily.with_f32(|daily| crate::ffi::run(daily, &self.yearly))
+    }
+
     pub fn run(
         &self,
         cli_path: impl AsRef<Path>,
@@ -449,18 +1029,72 @@ impl<'a> SimpleCropConfig<'a> {
     ) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
         let cli_path = cli_path.as_ref();
         self.save(&dir)?;
-        create_dir_all(&dir.as_ref().join("output")).wrap_err("Cannot create output dir")?;
-        let _r = Command::new(cli_path).current_dir(&dir).spawn();
-        if _r.is_err() {
-            let e = _r.err().unwrap();
+        create_dir_all(dir.as_ref().join("output")).wrap_err("Cannot create output dir")?;
+        let output = Command::new(cli_path).current_dir(&dir).output();
+        collect_output(cli_path, dir.as_ref(), output)
+    }
+
+    /// Run the executable like [`SimpleCropConfig::run`] but stream its
+    /// inputs through named pipes instead of writing them to `dir`
+    #[cfg(unix)]
+    pub fn run_piped(
+        &self,
+        cli_path: impl AsRef<Path>,
+        dir: impl AsRef<Path>,
+    ) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+        let cli_path = cli_path.as_ref();
+        let dp = dir.as_ref().join("data");
+        create_dir_all(&dp).wrap_err("Cannot create data dir")?;
+        create_dir_all(dir.as_ref().join("output")).wrap_err("Cannot create output dir")?;
+        let inputs = self
+            .inputs()?
+            .into_iter()
+            .map(|(path, data)| (dp.join(path), data))
+            .collect();
+        let mut command = Command::new(cli_path);
+        command.current_dir(&dir);
+        let output = crate::pipes::output_with_pipes(command, inputs);
+        collect_output(cli_path, dir.as_ref(), output)
+    }
+}
+
+/// Save the logs of a run of the executable in `dir` and read its outputs
+fn collect_output(
+    cli_path: &Path,
+    dir: &Path,
+    output: io::Result<Output>,
+) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+    match output {
+        Err(e) => {
             let k = e.kind();
             if k == ErrorKind::NotFound {
-                return Err(e).wrap_err_with(|| format!("Executable not found at {}", &cli_path.to_string_lossy()));
+                return Err(e).wrap_err_with(|| {
+                    format!("Executable not found at {}", &cli_path.to_string_lossy())
+                });
             }
-            return Err(e).wrap_err_with(|| format!("Error executing simplecrop in dir {} (got {:?})", dir.as_ref().to_string_lossy(), k));
-        } else {
-            _r.unwrap().wait().wrap_err_with(|| format!("Error during simplecrop execution in dir {}", dir.as_ref().to_string_lossy()))?;
-            load_output_data(&dir)
+            Err(e).wrap_err_with(|| {
+                format!(
+                    "Error executing simplecrop in dir {} (got {:?})",
+                    dir.to_string_lossy(),
+                    k
+                )
+            })
+        }
+        Ok(output) => {
+            // kept with the outputs since the model prints warnings there
+            std::fs::write(dir.join(STDOUT_LOG), &output.stdout)
+                .wrap_err("Cannot write stdout log")?;
+            std::fs::write(dir.join(STDERR_LOG), &output.stderr)
+                .wrap_err("Cannot write stderr log")?;
+            if !output.status.success() {
+                return Err(stable_eyre::eyre::eyre!(
+                    "simplecrop in dir {} failed ({}): {}",
+                    dir.to_string_lossy(),
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            load_output_data(dir)
         }
     }
 }
@@ -471,7 +1105,66 @@ mod tests {
     use std::io::Cursor;
     use std::str;
 
-    use crate::model::{DailyData, PlantDataSet, SoilDataSet, YearlyData};
+    use crate::model::{
+        push_fixed1, DailyData, PlantDataSet, SoilDataSet, YearlyData, YEARLY_PARAMETERS,
+    };
+
+    #[test]
+    fn diff_yearly_data() {
+        let config = YearlyData::default();
+        let changed = YearlyData {
+            plant_density: 6.0,
+            soil_water_storage: 246.5001,
+            ..config
+        };
+        let differences = config.diff(&changed, 1e-4);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].path, "plant_density");
+        assert_eq!(differences[0].percent_change(), Some(20.0));
+        assert_eq!(config.diff(&changed, 0.0).len(), 2);
+    }
+
+    #[test]
+    fn yearly_data_ranges() {
+        let config = YearlyData::default();
+        assert!(config.check().is_ok());
+        let bad = YearlyData {
+            soil_runoff_curve_number: 120.0,
+            day_of_planting: 0,
+            ..config
+        };
+        assert_eq!(
+            bad.check().unwrap_err().to_string(),
+            "soil_runoff_curve_number must be in [0, 100] 1 but is 120, \
+             day_of_planting must be in [1, 366] d but is 0"
+        );
+        let rb = YearlyData::to_recordbatch(&[config, bad]).unwrap();
+        assert!(YearlyData::from_recordbatch_row(&rb, 0).is_ok());
+        assert!(YearlyData::from_recordbatch_row(&rb, 1).is_err());
+
+        let columns = serde_json::to_value(YearlyData::columns().unwrap()).unwrap();
+        let curve_number = &columns["fields"][22];
+        assert_eq!(curve_number["name"], "soil_runoff_curve_number");
+        assert_eq!(curve_number["metadata"]["default"], "55");
+        assert_eq!(curve_number["metadata"]["max"], "100");
+        assert_eq!(curve_number["metadata"]["units"], "1");
+        assert!(columns["fields"][15]["metadata"]["units"].is_null());
+
+        let names: Vec<_> = rb
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        let documented: Vec<_> = YEARLY_PARAMETERS.iter().map(|p| p.name).collect();
+        assert_eq!(names, documented);
+        let rules = YearlyData::rules();
+        assert_eq!(rules.len(), 30);
+        assert!(meillionen_mt::validation::validate(&rules, &[rb])
+            .unwrap()
+            .iter()
+            .any(|p| p.starts_with("day_of_planting >= 1")));
+    }
 
     #[test]
     fn write_yearly_data() {
@@ -490,6 +1183,10 @@ mod tests {
         config.save_soil_config(&mut cur).unwrap();
         let soil_ref_data = read_to_string("data/data/soil.inp").unwrap();
         assert_eq!(str::from_utf8(cur.get_ref()).unwrap(), soil_ref_data);
+
+        let rb = YearlyData::to_recordbatch(&[config]).unwrap();
+        assert_eq!(rb.num_columns(), 26);
+        assert_eq!(YearlyData::from_recordbatch_row(&rb, 0).unwrap(), config);
     }
 
     #[test]
@@ -518,6 +1215,79 @@ mod tests {
         );
     }
 
+    #[test]
+    fn write_f64_daily_data() {
+        // 0.3 as an f64 but 0.25 and so 0.2 once narrowed to f32
+        let rain = [0.250_000_000_1f64];
+        let w = DailyData {
+            irrigation: &[1.05f64],
+            energy_flux: &[5.1],
+            temp_max: &[20.0],
+            temp_min: &[4.4],
+            rainfall: &rain,
+            photosynthetic_energy_flux: &[10.7],
+        };
+
+        let mut cur = Cursor::new(Vec::new());
+        w.save_weather(&mut cur).unwrap();
+        assert_eq!(
+            str::from_utf8(cur.get_ref()).unwrap(),
+            "    1   5.1  20.0   4.4   0.2              10.7\n"
+        );
+
+        let mut cur = Cursor::new(Vec::new());
+        w.save_irrigation(&mut cur).unwrap();
+        // 1.05 as an f32 is just below 1.05
+        assert_eq!("    1  1.0\n", str::from_utf8(cur.get_ref()).unwrap());
+
+        w.with_f32(|daily| {
+            assert_eq!(daily.rainfall, &[0.25f32]);
+            assert_eq!(daily.temp_min, &[4.4f32]);
+            let mut narrowed = Cursor::new(Vec::new());
+            daily.save_weather(&mut narrowed).unwrap();
+            let mut cur = Cursor::new(Vec::new());
+            w.save_weather(&mut cur).unwrap();
+            assert_eq!(narrowed.get_ref(), cur.get_ref());
+        });
+    }
+
+    #[test]
+    fn with_rainfall() {
+        let shared = [1.0f32, 2.0];
+        let daily = DailyData {
+            irrigation: &shared,
+            temp_max: &shared,
+            temp_min: &shared,
+            rainfall: &shared,
+            photosynthetic_energy_flux: &shared,
+            energy_flux: &shared,
+        };
+        let cell = vec![5.0f32, 0.0];
+        let d = daily.with_rainfall(&cell);
+        assert_eq!(d.rainfall, &[5.0, 0.0]);
+        assert!(std::ptr::eq(d.temp_max, daily.temp_max));
+    }
+
+    #[test]
+    fn fixed1_matches_format() {
+        let mut values = vec![
+            0.05f32, 0.25, 0.35, -0.04, -0.0, 9.95, 99.95, 1e6, 12345.678,
+        ];
+        values.extend((-2000..2000).map(|i| i as f32 * 0.0137));
+        values.extend([f32::NAN, f32::INFINITY, f32::NEG_INFINITY].iter());
+        let mut row = Vec::new();
+        for x in values {
+            for width in [1, 4, 6].iter() {
+                row.clear();
+                push_fixed1(&mut row, x, *width);
+                assert_eq!(
+                    str::from_utf8(&row).unwrap(),
+                    format!("{:>width$.1}", x, width = width)
+                );
+            }
+        }
+    }
+
     #[test]
     fn read_plant_t() {
         let data = PlantDataSet::load("data/output/plant.out").unwrap();
@@ -527,6 +1297,13 @@ mod tests {
         assert_eq!(data.plant_matter_canopy[0], 0.25);
         assert_eq!(data.plant_matter_fruit[0], 0.0);
         assert_eq!(data.plant_leaf_area_index[0], 0.01);
+
+        let rows = data.day_of_year.len();
+        let columns = data.into_columns();
+        assert_eq!(columns.len(), 8);
+        assert_eq!(columns[0].0, "air_accumulated_temp");
+        assert_eq!(columns[7].0, "day");
+        assert!(columns.iter().all(|(_, c)| c.len() == rows));
     }
 
     #[test]
@@ -0,0 +1,812 @@
+//! The SimpleCrop plant and soil water equations, so the model can run in
+//! process one day at a time instead of through the Fortran executable
+//!
+//! The equations and the order they are applied in follow the Fortran sources
+//! so the results match `plant.out` and `soil.out`. That includes two quirks of
+//! the Fortran: photosynthetically active radiation is taken to be half of the
+//! solar radiation rather than read from the weather, and only the first 16
+//! values of `plant.inp` are read so the value written in the `f1` column is
+//! used as the specific leaf area.
+
+use arrow::record_batch::RecordBatch;
+use meillionen_mt::determinism::{expf, powff};
+use meillionen_mt::grid::{self, CellPolicy, GridError, GridRun};
+use meillionen_mt::state::{SimulationState, StateError};
+use meillionen_mt::variable::{
+    Composition, Dataset, VarView, Variable, VariableError, VecVariable,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::model::{to_recordbatches, DailyData, PlantDataSet, SoilDataSet, YearlyData};
+
+/// The last day the Fortran main loop runs to
+const LAST_DAY: i32 = 1000;
+
+/// Weather and irrigation for a single day
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct DailyWeather {
+    pub irrigation: f32,
+    pub temp_max: f32,
+    pub temp_min: f32,
+    pub rainfall: f32,
+    pub energy_flux: f32,
+}
+
+impl<'a> DailyData<'a> {
+    /// The number of days that have every daily input
+    pub fn days(&self) -> usize {
+        [
+            self.irrigation.len(),
+            self.temp_max.len(),
+            self.temp_min.len(),
+            self.rainfall.len(),
+            self.energy_flux.len(),
+        ]
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(0)
+    }
+
+    pub fn day(&self, i: usize) -> DailyWeather {
+        DailyWeather {
+            irrigation: self.irrigation[i],
+            temp_max: self.temp_max[i],
+            temp_min: self.temp_min[i],
+            rainfall: self.rainfall[i],
+            energy_flux: self.energy_flux[i],
+        }
+    }
+}
+
+/// The soil water balance (SW.f)
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Soil {
+    // water contents in mm
+    wilting_point: f32,
+    field_capacity: f32,
+    saturation: f32,
+    drought_threshold: f32,
+    depth: f32, // cm
+    drainage_fraction: f32,
+    retention: f32, // runoff curve number retention parameter
+    water: f32,
+    deficit_stress: f32,
+    excess_stress: f32,
+
+    // rates in mm/d
+    runoff: f32,
+    infiltration: f32,
+    drainage: f32,
+    evapotranspiration: f32,
+    evaporation: f32,
+    transpiration: f32,
+}
+
+impl Soil {
+    /// Water table depth in mm above which roots suffer from excess water
+    const STRESS_DEPTH: f32 = 250.0;
+
+    fn new(yearly: &YearlyData) -> Self {
+        let depth = yearly.soil_profile_depth;
+        let wilting_point = depth * yearly.soil_water_content_wilting_point * 10.0;
+        let field_capacity = depth * yearly.soil_water_content_field_capacity * 10.0;
+        let mut soil = Self {
+            wilting_point,
+            field_capacity,
+            saturation: depth * yearly.soil_water_content_saturation * 10.0,
+            drought_threshold: wilting_point + 0.75 * (field_capacity - wilting_point),
+            depth,
+            drainage_fraction: yearly.soil_drainage_daily_percent,
+            retention: 254.0 * (100.0 / yearly.soil_runoff_curve_number - 1.0),
+            water: yearly.soil_water_storage,
+            ..Self::default()
+        };
+        soil.stress();
+        soil
+    }
+
+    fn stress(&mut self) {
+        self.deficit_stress = if self.water < self.wilting_point {
+            0.0
+        } else if self.water > self.drought_threshold {
+            1.0
+        } else {
+            ((self.water - self.wilting_point) / (self.drought_threshold - self.wilting_point))
+                .clamp(0.0, 1.0)
+        };
+        let water_table = self.depth * 10.0
+            - (self.water - self.field_capacity) / (self.saturation - self.field_capacity)
+                * self.depth
+                * 10.0;
+        self.excess_stress = if water_table > Self::STRESS_DEPTH {
+            1.0
+        } else {
+            (water_table / Self::STRESS_DEPTH).clamp(0.0, 1.0)
+        };
+    }
+
+    /// Priestley-Taylor potential evapotranspiration
+    fn potential_evapotranspiration(weather: &DailyWeather, lai: f32) -> f32 {
+        let cover = expf(-0.7 * lai);
+        let albedo = 0.1 * cover + 0.2 * (1.0 - cover);
+        let temp = 0.6 * weather.temp_max + 0.4 * weather.temp_min;
+        let equilibrium = weather.energy_flux * (4.88e-3 - 4.37e-3 * albedo) * (temp + 29.0);
+        let factor = if weather.temp_max < 5.0 {
+            0.01 * expf(0.18 * (weather.temp_max + 20.0))
+        } else if weather.temp_max > 35.0 {
+            1.1 + 0.05 * (weather.temp_max - 35.0)
+        } else {
+            1.1
+        };
+        factor * equilibrium
+    }
+
+    fn rate(&mut self, weather: &DailyWeather, lai: f32) {
+        self.drainage = if self.water > self.field_capacity {
+            (self.water - self.field_capacity) * self.drainage_fraction
+        } else {
+            0.0
+        };
+        let potential_infiltration = weather.rainfall + weather.irrigation;
+        self.runoff = if potential_infiltration > 0.2 * self.retention {
+            (potential_infiltration - 0.2 * self.retention).powi(2)
+                / (potential_infiltration + 0.8 * self.retention)
+        } else {
+            0.0
+        };
+        self.infiltration = if potential_infiltration > 0.0 {
+            potential_infiltration - self.runoff
+        } else {
+            0.0
+        };
+
+        self.evapotranspiration = Self::potential_evapotranspiration(weather, lai);
+        let potential_evaporation = self.evapotranspiration * expf(-0.7 * lai);
+        let potential_transpiration = self.evapotranspiration * (1.0 - expf(-0.7 * lai));
+        let available = if self.water < self.wilting_point {
+            0.0
+        } else if self.water > self.field_capacity {
+            1.0
+        } else {
+            (self.water - self.wilting_point) / (self.field_capacity - self.wilting_point)
+        };
+        self.evaporation = potential_evaporation * available;
+        self.transpiration = potential_transpiration * self.deficit_stress.min(self.excess_stress);
+    }
+
+    fn integrate(&mut self) {
+        self.water += self.infiltration - self.evaporation - self.transpiration - self.drainage;
+        if self.water > self.saturation {
+            self.runoff += self.water - self.saturation;
+            self.water = self.saturation;
+        }
+        self.water = self.water.max(0.0);
+        self.stress();
+    }
+}
+
+/// Plant growth (PLANT.f)
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Plant {
+    leaves_max: f32,
+    emp1: f32,
+    emp2: f32,
+    density: f32,
+    nb: f32,
+    leaf_appearance_rate: f32,
+    canopy_fraction: f32,
+    min_repro_temp: f32,
+    repro_duration: f32,
+    leaves_removed: f32,
+    leaf_specific_area: f32,
+
+    leaves: f32,
+    repro_temp: f32,
+    lai: f32,
+    matter: f32,
+    canopy: f32,
+    root: f32,
+    fruit: f32,
+
+    d_leaves: f32,
+    d_lai: f32,
+    d_matter: f32,
+    d_canopy: f32,
+    d_root: f32,
+    d_fruit: f32,
+}
+
+impl Plant {
+    const ROW_SPACING: f32 = 60.0;
+
+    fn new(yearly: &YearlyData) -> Self {
+        Self {
+            leaves_max: yearly.plant_leaves_max_number,
+            emp1: yearly.plant_emp1,
+            emp2: yearly.plant_emp2,
+            density: yearly.plant_density,
+            nb: yearly.plant_nb,
+            leaf_appearance_rate: yearly.plant_leaf_max_appearance_rate,
+            canopy_fraction: yearly.plant_growth_canopy_fraction,
+            min_repro_temp: yearly.plant_min_repro_growth_temp,
+            repro_duration: yearly.plant_repro_phase_duration,
+            leaves_removed: yearly.plant_matter_leaves_removed,
+            leaf_specific_area: yearly.plant_development_phase,
+            leaves: yearly.plant_leaves_number_of,
+            lai: yearly.plant_leaf_area_index,
+            matter: yearly.plant_matter,
+            canopy: yearly.plant_matter_canopy,
+            root: yearly.plant_matter_root,
+            ..Self::default()
+        }
+    }
+
+    fn rate(&mut self, weather: &DailyWeather, water_stress: f32) {
+        let mean_temp = 0.5 * (weather.temp_max + weather.temp_min);
+        let temp_effect =
+            1.0 - 0.0025 * ((0.25 * weather.temp_min + 0.75 * weather.temp_max) - 26.0).powi(2);
+        let par = 0.5 * weather.energy_flux;
+        let extinction =
+            1.5 - 0.768 * powff((Self::ROW_SPACING * 0.01).powi(2) * self.density, 0.1);
+        let photosynthesis = temp_effect * water_stress * 2.1 * par / self.density
+            * (1.0 - expf(-extinction * self.lai));
+        self.d_matter = photosynthesis * self.density;
+
+        if self.leaves < self.leaves_max {
+            self.d_leaves = self.leaf_appearance_rate * temp_effect;
+            let a = expf(self.emp2 * (self.leaves - self.nb));
+            self.d_lai = water_stress
+                * self.density
+                * self.emp1
+                * temp_effect
+                * (a / (1.0 + a))
+                * self.d_leaves;
+            self.d_canopy = self.canopy_fraction * self.d_matter;
+            self.d_root = (1.0 - self.canopy_fraction) * self.d_matter;
+            self.d_fruit = 0.0;
+        } else {
+            let degree_days = if mean_temp >= self.min_repro_temp && mean_temp <= 25.0 {
+                mean_temp - self.min_repro_temp
+            } else {
+                0.0
+            };
+            self.repro_temp += degree_days;
+            self.d_leaves = 0.0;
+            self.d_lai =
+                -self.density * degree_days * self.leaves_removed * self.leaf_specific_area;
+            self.d_canopy = 0.0;
+            self.d_root = 0.0;
+            self.d_fruit = self.d_matter;
+        }
+    }
+
+    /// Returns whether the crop has matured
+    fn integrate(&mut self) -> bool {
+        self.lai = (self.lai + self.d_lai).max(0.0);
+        self.matter = (self.matter + self.d_matter).max(0.0);
+        self.canopy = (self.canopy + self.d_canopy).max(0.0);
+        self.root = (self.root + self.d_root).max(0.0);
+        self.fruit = (self.fruit + self.d_fruit).max(0.0);
+        self.leaves += self.d_leaves;
+        self.repro_temp > self.repro_duration
+    }
+}
+
+/// SimpleCrop stepped one day at a time
+///
+/// Day 0 is the initial state. Each call to [`SimpleCrop::update`] advances a
+/// day, recording output every `printout_freq` days, on the day of planting and
+/// on the day the crop matures like the Fortran executable.
+///
+/// The whole state, the parameters from the yearly config with the progress
+/// and output so far, can be saved with [`meillionen_mt::state::save`] and
+/// restored to carry on mid season.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SimpleCrop {
+    day: i32,
+    day_of_planting: i32,
+    printout_freq: i32,
+    weather: DailyWeather,
+    soil: Soil,
+    plant: Plant,
+    matured: bool,
+    plant_output: PlantDataSet,
+    soil_output: SoilDataSet,
+}
+
+impl SimpleCrop {
+    pub fn new(yearly: &YearlyData) -> Self {
+        let mut model = Self {
+            day: 0,
+            day_of_planting: yearly.day_of_planting,
+            printout_freq: yearly.printout_freq,
+            weather: DailyWeather::default(),
+            soil: Soil::new(yearly),
+            plant: Plant::new(yearly),
+            matured: false,
+            plant_output: PlantDataSet::default(),
+            soil_output: SoilDataSet::default(),
+        };
+        model.output();
+        model
+    }
+
+    pub fn day(&self) -> i32 {
+        self.day
+    }
+
+    pub fn is_matured(&self) -> bool {
+        self.matured
+    }
+
+    pub fn leaf_area_index(&self) -> f32 {
+        self.plant.lai
+    }
+
+    /// Soil water in mm
+    pub fn soil_water_storage(&self) -> f32 {
+        self.soil.water
+    }
+
+    /// Replace the soil water, such as after another model moved water in or
+    /// out of the cell
+    pub fn set_soil_water_storage(&mut self, water: f32) {
+        self.soil.water = water.max(0.0);
+        self.soil.stress();
+    }
+
+    /// Advance the model a day. Nothing happens once the crop has matured.
+    pub fn update(&mut self, weather: DailyWeather) {
+        if self.matured {
+            return;
+        }
+        self.day += 1;
+        self.weather = weather;
+        let growing = self.day > self.day_of_planting;
+        self.soil.rate(&weather, self.plant.lai);
+        if growing {
+            let water_stress = self.soil.deficit_stress.min(self.soil.excess_stress);
+            self.plant.rate(&weather, water_stress);
+        }
+        self.soil.integrate();
+        if growing {
+            self.matured = self.plant.integrate();
+        }
+        self.output();
+    }
+
+    fn output(&mut self) {
+        let scheduled = self.printout_freq > 0 && self.day % self.printout_freq == 0;
+        if !(scheduled || self.matured || self.day == self.day_of_planting) {
+            return;
+        }
+        let soil = &self.soil;
+        let so = &mut self.soil_output;
+        so.day_of_year.push(self.day);
+        so.soil_daily_runoff.push(soil.runoff);
+        so.soil_daily_infiltration.push(soil.infiltration);
+        so.soil_daily_drainage.push(soil.drainage);
+        so.soil_evapotranspiration.push(soil.evapotranspiration);
+        so.soil_evaporation.push(soil.evaporation);
+        so.plant_potential_transpiration.push(soil.transpiration);
+        so.soil_water_storage_depth.push(soil.water);
+        so.soil_water_profile_ratio.push(soil.water / soil.depth);
+        so.soil_water_deficit_stress.push(soil.deficit_stress);
+        so.soil_water_excess_stress.push(soil.excess_stress);
+
+        if self.day >= self.day_of_planting {
+            let plant = &self.plant;
+            let po = &mut self.plant_output;
+            po.day_of_year.push(self.day);
+            po.plant_leaf_count.push(plant.leaves);
+            po.air_accumulated_temp.push(plant.repro_temp);
+            po.plant_matter.push(plant.matter);
+            po.plant_matter_canopy.push(plant.canopy);
+            po.plant_matter_fruit.push(plant.fruit);
+            po.plant_matter_root.push(plant.root);
+            po.plant_leaf_area_index.push(plant.lai);
+        }
+    }
+
+    /// The recorded plant and soil output
+    pub fn finish(self) -> stable_eyre::Result<(RecordBatch, RecordBatch)> {
+        to_recordbatches(self.plant_output, self.soil_output)
+    }
+}
+
+impl SimulationState for SimpleCrop {
+    fn model_name(&self) -> &str {
+        "simplecrop"
+    }
+
+    fn serialize_state(&self) -> Result<Vec<u8>, StateError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    fn deserialize_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        *self = serde_json::from_slice(state)?;
+        Ok(())
+    }
+}
+
+/// Run SimpleCrop until the crop matures or the weather runs out
+pub fn run(daily: &DailyData, yearly: &YearlyData) -> SimpleCrop {
+    let mut model = SimpleCrop::new(yearly);
+    let days = daily.days().min(LAST_DAY as usize);
+    for i in 0..days {
+        if model.is_matured() {
+            break;
+        }
+        model.update(daily.day(i));
+    }
+    model
+}
+
+/// Run SimpleCrop for each cell of a grid that shares all daily inputs but
+/// rainfall
+pub fn run_cells<'a>(
+    daily: &DailyData,
+    yearly: &YearlyData,
+    rainfall: impl IntoIterator<Item = &'a [f32]>,
+) -> Vec<SimpleCrop> {
+    rainfall
+        .into_iter()
+        .map(|r| run(&daily.with_rainfall(r), yearly))
+        .collect()
+}
+
+/// Why a cell's rainfall can't be run
+fn check_rainfall(daily: &DailyData, rainfall: &[f32]) -> Result<(), String> {
+    if rainfall.len() < daily.temp_max.len() {
+        return Err(format!(
+            "rainfall has {} days but the weather has {}",
+            rainfall.len(),
+            daily.temp_max.len()
+        ));
+    }
+    match rainfall.iter().position(|r| !r.is_finite() || *r < 0.0) {
+        Some(day) => Err(format!("rainfall on day {} is {}", day + 1, rainfall[day])),
+        None => Ok(()),
+    }
+}
+
+/// [`run_cells`] that checks each cell's rainfall first and handles cells
+/// whose rainfall can't be run by `policy`
+pub fn try_run_cells(
+    daily: &DailyData,
+    yearly: &YearlyData,
+    rainfall: &[&[f32]],
+    policy: CellPolicy,
+) -> Result<GridRun<SimpleCrop>, GridError> {
+    grid::run_cells(rainfall.len(), policy, |cell| {
+        check_rainfall(daily, rainfall[cell])?;
+        Ok::<_, String>(run(&daily.with_rainfall(rainfall[cell]), yearly))
+    })
+}
+
+/// The daily water input of each cell of a grid, composed from the variables
+/// of `dataset` by `composition`, such as rain, snowmelt and irrigation from
+/// upstream models, to pass to [`try_run_cells`] as rainfall
+///
+/// Cells are the other dimensions of the composed variable in row-major
+/// order, a single cell if it only has `time`.
+pub fn cell_water_input(
+    dataset: &Dataset,
+    composition: &Composition,
+    time: &str,
+) -> Result<Vec<Vec<f32>>, VariableError> {
+    cells_along(&composition.compose(dataset)?, time)
+}
+
+/// The values of each cell of `water` along `time`
+fn cells_along(water: &VecVariable<f64>, time: &str) -> Result<Vec<Vec<f32>>, VariableError> {
+    let position = water.dimension_position(time).ok_or_else(|| {
+        VariableError::MissingDimension(time.to_string(), water.dimension_names())
+    })?;
+    let mut dimensions = water.dimensions().to_vec();
+    let time = dimensions.remove(position);
+    let days = time.size;
+    dimensions.push(time);
+    let values = VarView::new(water, dimensions)?.to_vec();
+    Ok(values
+        .chunks(days.max(1))
+        .map(|cell| cell.iter().map(|x| *x as f32).collect())
+        .collect())
+}
+
+/// [`try_run_cells`] on the water input composed from `dataset`, skipping
+/// the cells where it is missing on every day
+///
+/// Fill values of the variables in `dataset` are missing values, so ocean
+/// or cells outside a catchment are never run and their results are NaN,
+/// see [`grid::run_valid_cells`].
+pub fn run_grid(
+    daily: &DailyData,
+    yearly: &YearlyData,
+    dataset: &Dataset,
+    composition: &Composition,
+    time: &str,
+    policy: CellPolicy,
+) -> Result<GridRun<SimpleCrop>, GridError> {
+    let water = composition.compose(dataset)?;
+    let rainfall = cells_along(&water, time)?;
+    grid::run_valid_cells(&water, time, policy, |cell| {
+        check_rainfall(daily, &rainfall[cell])?;
+        Ok::<_, String>(run(&daily.with_rainfall(&rainfall[cell]), yearly))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use std::sync::Arc;
+
+    use crate::model::{DailyData, PlantDataSet, SoilDataSet, YearlyData};
+    use meillionen_mt::extension_columns::DimMeta;
+    use meillionen_mt::grid::{CellPolicy, GridError};
+    use meillionen_mt::state::{restore, save};
+    use meillionen_mt::variable::{
+        Attributes, Composition, Dataset, MissingValues, VariableError, VecVariable,
+    };
+
+    use crate::native::{cell_water_input, run, run_grid, try_run_cells, SimpleCrop};
+
+    fn columns(path: &str, ranges: &[(usize, usize)]) -> Vec<Vec<f32>> {
+        let text = read_to_string(path).unwrap();
+        let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+        ranges
+            .iter()
+            .map(|(start, end)| {
+                lines
+                    .iter()
+                    .map(|l| l[*start..*end].trim().parse().unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assert_close(name: &str, actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{}", name);
+        for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - e).abs() <= 0.011, "{}[{}]: {} != {}", name, i, a, e);
+        }
+    }
+
+    /// The weather and irrigation of the example run
+    struct DailyFixture {
+        weather: Vec<Vec<f32>>,
+        irrigation: Vec<f32>,
+    }
+
+    impl DailyFixture {
+        fn daily(&self) -> DailyData<'_> {
+            DailyData {
+                irrigation: &self.irrigation,
+                energy_flux: &self.weather[0],
+                temp_max: &self.weather[1],
+                temp_min: &self.weather[2],
+                rainfall: &self.weather[3],
+                photosynthetic_energy_flux: &self.weather[4],
+            }
+        }
+    }
+
+    fn daily_fixture() -> DailyFixture {
+        let weather = columns(
+            "data/data/weather.inp",
+            &[(7, 11), (13, 17), (19, 23), (23, 29), (43, 47)],
+        );
+        let mut irrigation = columns("data/data/irrig.inp", &[(7, 11)]);
+        DailyFixture {
+            weather,
+            irrigation: irrigation.remove(0),
+        }
+    }
+
+    #[test]
+    fn matches_fortran() {
+        let fixture = daily_fixture();
+        let daily = fixture.daily();
+        let model = run(&daily, &YearlyData::default());
+        assert!(model.is_matured());
+        assert_eq!(model.day(), 293);
+
+        let plant = PlantDataSet::load("data/output/plant.out").unwrap();
+        let soil = SoilDataSet::load("data/output/soil.out").unwrap();
+        assert_eq!(model.plant_output.day_of_year, plant.day_of_year);
+        assert_eq!(model.soil_output.day_of_year, soil.day_of_year);
+        assert_close(
+            "lai",
+            &model.plant_output.plant_leaf_area_index,
+            &plant.plant_leaf_area_index,
+        );
+        assert_close(
+            "fruit",
+            &model.plant_output.plant_matter_fruit,
+            &plant.plant_matter_fruit,
+        );
+        assert_close(
+            "swc",
+            &model.soil_output.soil_water_storage_depth,
+            &soil.soil_water_storage_depth,
+        );
+        assert_close(
+            "runoff",
+            &model.soil_output.soil_daily_runoff,
+            &soil.soil_daily_runoff,
+        );
+        assert_close(
+            "evaporation",
+            &model.soil_output.soil_evaporation,
+            &soil.soil_evaporation,
+        );
+
+        let (plant, soil) = model.finish().unwrap();
+        assert_eq!(plant.num_rows(), 59);
+        assert_eq!(soil.num_rows(), 100);
+    }
+
+    #[test]
+    fn bad_cells_are_isolated() {
+        let fixture = daily_fixture();
+        let daily = fixture.daily();
+        let mut negative = daily.rainfall.to_vec();
+        negative[10] = -1.0;
+        let rainfall = [daily.rainfall, &negative, &daily.rainfall[..10]];
+
+        let e = try_run_cells(
+            &daily,
+            &YearlyData::default(),
+            &rainfall,
+            CellPolicy::FailFast,
+        )
+        .unwrap_err();
+        assert!(matches!(e, GridError::CellFailed { cell: 1, .. }));
+
+        let grid =
+            try_run_cells(&daily, &YearlyData::default(), &rainfall, CellPolicy::Skip).unwrap();
+        assert_eq!(grid.succeeded(), 1);
+        assert!(grid.cells[0].as_ref().unwrap().is_matured());
+        assert_eq!(grid.failures[0].message, "rainfall on day 11 is -1");
+        assert_eq!(grid.failures[1].cell, 2);
+    }
+
+    #[test]
+    fn water_input_from_fluxes() {
+        let fixture = daily_fixture();
+        let daily = fixture.daily();
+        let days = daily.rainfall.len();
+        let t = Arc::new(DimMeta {
+            name: "t".to_string(),
+            size: days,
+            description: None,
+            labels: None,
+            times: None,
+        });
+        let x = Arc::new(DimMeta::labeled(
+            "x",
+            vec!["a".to_string(), "b".to_string()],
+        ));
+        let rain = daily.rainfall.iter().map(|r| f64::from(*r)).collect();
+        let mut snowmelt = vec![0.0; days];
+        snowmelt.extend(std::iter::repeat_n(0.5, days));
+        let mut ds = Dataset::new();
+        ds.insert("rain", VecVariable::new(vec![t.clone()], rain).unwrap())
+            .unwrap();
+        ds.insert("snowmelt", VecVariable::new(vec![x, t], snowmelt).unwrap())
+            .unwrap();
+
+        let water = cell_water_input(&ds, &Composition::sum(&["rain", "snowmelt"]), "t").unwrap();
+        assert_eq!(water.len(), 2);
+        assert_eq!(water[0], daily.rainfall);
+        assert_eq!(water[1][0], daily.rainfall[0] + 0.5);
+
+        let cells: Vec<&[f32]> = water.iter().map(|w| &w[..]).collect();
+        let grid =
+            try_run_cells(&daily, &YearlyData::default(), &cells, CellPolicy::FailFast).unwrap();
+        assert_eq!(grid.succeeded(), 2);
+        assert!(matches!(
+            cell_water_input(&ds, &Composition::sum(&["rain"]), "day"),
+            Err(VariableError::MissingDimension(..))
+        ));
+    }
+
+    #[test]
+    fn masked_cells_are_nan() {
+        let fixture = daily_fixture();
+        let daily = fixture.daily();
+        let days = daily.rainfall.len();
+        let t = Arc::new(DimMeta {
+            name: "t".to_string(),
+            size: days,
+            description: None,
+            labels: None,
+            times: None,
+        });
+        let x = Arc::new(DimMeta::labeled(
+            "x",
+            vec!["land".to_string(), "ocean".to_string(), "coast".to_string()],
+        ));
+        let mut rain: Vec<f64> = daily.rainfall.iter().map(|r| f64::from(*r)).collect();
+        rain.extend(std::iter::repeat_n(-9999.0, days));
+        rain.extend(daily.rainfall.iter().map(|r| f64::from(*r)));
+        let missing = MissingValues {
+            fill_value: Some(-9999.0),
+            missing_value: None,
+        };
+        let mut ds = Dataset::new();
+        ds.insert(
+            "rain",
+            VecVariable::new(vec![x.clone(), t], rain)
+                .unwrap()
+                .with_attributes(Attributes::default().with_missing(missing)),
+        )
+        .unwrap();
+
+        // failing fast would stop at the ocean if it were run
+        let grid = run_grid(
+            &daily,
+            &YearlyData::default(),
+            &ds,
+            &Composition::sum(&["rain"]),
+            "t",
+            CellPolicy::FailFast,
+        )
+        .unwrap();
+        assert_eq!(grid.masked, vec![1]);
+        assert!(grid.failures.is_empty());
+        let lai = grid
+            .values(vec![x], |m| {
+                f64::from(*m.plant_output.plant_leaf_area_index.last().unwrap())
+            })
+            .unwrap();
+        let lai = lai.data();
+        let expected = f64::from(
+            *run(&daily, &YearlyData::default())
+                .plant_output
+                .plant_leaf_area_index
+                .last()
+                .unwrap(),
+        );
+        assert_eq!(lai[0], expected);
+        assert!(lai[1].is_nan());
+        assert_eq!(lai[2], expected);
+    }
+
+    #[test]
+    fn restart_mid_season() {
+        let fixture = daily_fixture();
+        let daily = fixture.daily();
+        let yearly = YearlyData::default();
+        let whole = run(&daily, &yearly);
+
+        let path = std::env::temp_dir().join(format!("simplecrop-{}.snapshot", std::process::id()));
+        let mut model = SimpleCrop::new(&yearly);
+        for i in 0..150 {
+            model.update(daily.day(i));
+        }
+        save(&model, &path).unwrap();
+        drop(model);
+
+        let mut restarted = SimpleCrop::new(&yearly);
+        restore(&mut restarted, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restarted.day(), 150);
+        for i in 150..daily.days() {
+            if restarted.is_matured() {
+                break;
+            }
+            restarted.update(daily.day(i));
+        }
+        assert_eq!(restarted.day(), whole.day());
+        assert_eq!(
+            restarted.plant_output.plant_matter_fruit,
+            whole.plant_output.plant_matter_fruit
+        );
+        assert_eq!(
+            restarted.soil_output.soil_water_storage_depth,
+            whole.soil_output.soil_water_storage_depth
+        );
+    }
+}
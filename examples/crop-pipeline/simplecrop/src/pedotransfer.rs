@@ -0,0 +1,276 @@
+//! Soil water properties estimated from soil texture
+//!
+//! Water contents are taken at field capacity (33 kPa), the wilting point
+//! (1500 kPa) and saturation.
+
+use std::str::FromStr;
+
+use arrow::array::Float32Array;
+use arrow::record_batch::RecordBatch;
+use meillionen_mt::determinism::{ln, powf};
+use stable_eyre::eyre::eyre;
+
+use crate::model::YearlyData;
+
+/// Suction at field capacity and the wilting point in cm of water
+const FIELD_CAPACITY_SUCTION: f64 = 336.5;
+const WILTING_POINT_SUCTION: f64 = 15_297.0;
+
+/// mm/h per inch/h
+const MM_PER_INCH: f64 = 25.4;
+
+/// The texture of a soil layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoilTexture {
+    /// Sand and clay as fractions of the mineral soil by weight
+    pub sand: f32,
+    pub clay: f32,
+    /// Organic matter in percent by weight
+    pub organic_matter: f32,
+}
+
+impl SoilTexture {
+    /// A texture from sand, silt and clay in any units, such as percentages,
+    /// scaled to sum to one
+    pub fn from_separates(
+        sand: f32,
+        silt: f32,
+        clay: f32,
+        organic_matter: f32,
+    ) -> stable_eyre::Result<Self> {
+        let total = sand + silt + clay;
+        if !(sand >= 0.0 && silt >= 0.0 && clay >= 0.0 && total > 0.0) {
+            return Err(eyre!(
+                "sand {}, silt {} and clay {} must be non-negative and not all zero",
+                sand,
+                silt,
+                clay
+            ));
+        }
+        if !(0.0..100.0).contains(&organic_matter) {
+            return Err(eyre!(
+                "organic matter must be a percentage but is {}",
+                organic_matter
+            ));
+        }
+        Ok(Self {
+            sand: sand / total,
+            clay: clay / total,
+            organic_matter,
+        })
+    }
+
+    pub fn silt(&self) -> f32 {
+        1.0 - self.sand - self.clay
+    }
+}
+
+/// Volumetric water contents (cm3/cm3) and saturated conductivity of a soil
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoilWater {
+    pub wilting_point: f32,
+    pub field_capacity: f32,
+    pub saturation: f32,
+    /// mm/h
+    pub saturated_conductivity: f32,
+}
+
+impl SoilWater {
+    /// Set the soil water contents of `yearly`, leaving the profile depth,
+    /// drainage, curve number and starting water storage as they are
+    pub fn apply(&self, yearly: &mut YearlyData) {
+        yearly.soil_water_content_wilting_point = self.wilting_point;
+        yearly.soil_water_content_field_capacity = self.field_capacity;
+        yearly.soil_water_content_saturation = self.saturation;
+    }
+}
+
+/// The pedotransfer functions of Saxton and Rawls (2006) for soils without
+/// density or gravel adjustments
+pub fn saxton_rawls(texture: &SoilTexture) -> SoilWater {
+    let s = f64::from(texture.sand);
+    let c = f64::from(texture.clay);
+    let om = f64::from(texture.organic_matter);
+
+    let t1500 = -0.024 * s + 0.487 * c + 0.006 * om + 0.005 * s * om - 0.013 * c * om
+        + 0.068 * s * c
+        + 0.031;
+    let wilting_point = t1500 + (0.14 * t1500 - 0.02);
+
+    let t33 = -0.251 * s + 0.195 * c + 0.011 * om + 0.006 * s * om - 0.027 * c * om
+        + 0.452 * s * c
+        + 0.299;
+    let field_capacity = t33 + (1.283 * t33 * t33 - 0.374 * t33 - 0.015);
+
+    let ts33 = 0.278 * s + 0.034 * c + 0.022 * om - 0.018 * s * om - 0.027 * c * om - 0.584 * s * c
+        + 0.078;
+    let s33 = ts33 + (0.636 * ts33 - 0.107);
+    let saturation = field_capacity + s33 - 0.097 * s + 0.043;
+
+    let b = (ln(1500.0) - ln(33.0)) / (ln(field_capacity) - ln(wilting_point));
+    let saturated_conductivity = 1930.0 * powf(saturation - field_capacity, 3.0 - 1.0 / b);
+
+    SoilWater {
+        wilting_point: wilting_point as f32,
+        field_capacity: field_capacity as f32,
+        saturation: saturation as f32,
+        saturated_conductivity: saturated_conductivity as f32,
+    }
+}
+
+/// The Campbell retention curve parameters of Cosby et al. (1984), from sand
+/// and clay alone
+pub fn cosby(texture: &SoilTexture) -> SoilWater {
+    let sand = f64::from(texture.sand) * 100.0;
+    let clay = f64::from(texture.clay) * 100.0;
+    let silt = f64::from(texture.silt()) * 100.0;
+
+    // air entry suction in cm of water
+    let air_entry = powf(10.0, 1.54 - 0.0095 * sand + 0.0063 * silt);
+    let saturation = (50.5 - 0.142 * sand - 0.037 * clay) / 100.0;
+    let b = 3.10 + 0.157 * clay - 0.003 * sand;
+    let content = |suction: f64| saturation * powf(suction / air_entry, -1.0 / b);
+    let saturated_conductivity = MM_PER_INCH * powf(10.0, -0.60 + 0.0126 * sand - 0.0064 * clay);
+
+    SoilWater {
+        wilting_point: content(WILTING_POINT_SUCTION) as f32,
+        field_capacity: content(FIELD_CAPACITY_SUCTION) as f32,
+        saturation: saturation as f32,
+        saturated_conductivity: saturated_conductivity as f32,
+    }
+}
+
+/// A choice of pedotransfer functions, by the names `saxton_rawls` and
+/// `cosby`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Pedotransfer {
+    #[default]
+    SaxtonRawls,
+    Cosby,
+}
+
+impl Pedotransfer {
+    pub fn estimate(&self, texture: &SoilTexture) -> SoilWater {
+        match self {
+            Pedotransfer::SaxtonRawls => saxton_rawls(texture),
+            Pedotransfer::Cosby => cosby(texture),
+        }
+    }
+}
+
+impl FromStr for Pedotransfer {
+    type Err = stable_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "saxton_rawls" => Ok(Pedotransfer::SaxtonRawls),
+            "cosby" => Ok(Pedotransfer::Cosby),
+            _ => Err(eyre!(
+                "pedotransfer functions must be saxton_rawls or cosby but are {}",
+                s
+            )),
+        }
+    }
+}
+
+fn texture_column<'a>(rb: &'a RecordBatch, name: &str) -> stable_eyre::Result<Option<&'a [f32]>> {
+    let i = match rb.schema().index_of(name) {
+        Ok(i) => i,
+        Err(_) => return Ok(None),
+    };
+    rb.column(i)
+        .as_any()
+        .downcast_ref::<Float32Array>()
+        .map(|a| Some(a.values()))
+        .ok_or_else(|| eyre!("texture column {} must be Float32", name))
+}
+
+/// SimpleCrop soil parameters for each row of a table of soil textures
+///
+/// The table has `sand`, `silt`, `clay` and `organic_matter` columns, with
+/// `silt` optional when sand and clay are fractions. An optional
+/// `soil_profile_depth` column sets the depth. Other parameters are the
+/// defaults, with the profile starting at field capacity.
+pub fn soil_blocks(rb: &RecordBatch, ptf: Pedotransfer) -> stable_eyre::Result<Vec<YearlyData>> {
+    let required = |name| {
+        texture_column(rb, name)?.ok_or_else(|| eyre!("soil texture has no {} column", name))
+    };
+    let sand = required("sand")?;
+    let clay = required("clay")?;
+    let organic_matter = required("organic_matter")?;
+    let silt = texture_column(rb, "silt")?;
+    let depth = texture_column(rb, "soil_profile_depth")?;
+    (0..rb.num_rows())
+        .map(|i| {
+            let silt = silt.map_or(1.0 - sand[i] - clay[i], |s| s[i]);
+            let texture = SoilTexture::from_separates(sand[i], silt, clay[i], organic_matter[i])
+                .map_err(|e| e.wrap_err(format!("row {}", i)))?;
+            let mut yearly = YearlyData::default();
+            ptf.estimate(&texture).apply(&mut yearly);
+            if let Some(depth) = depth {
+                yearly.soil_profile_depth = depth[i];
+            }
+            // mm of water in a profile measured in cm
+            yearly.soil_water_storage =
+                yearly.soil_water_content_field_capacity * yearly.soil_profile_depth * 10.0;
+            Ok(yearly)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::pedotransfer::{saxton_rawls, soil_blocks, Pedotransfer, SoilTexture};
+
+    #[test]
+    fn saxton_rawls_loam() {
+        // table 3 of Saxton and Rawls (2006) lists 14, 28 and 46 percent
+        let loam = saxton_rawls(&SoilTexture {
+            sand: 0.4,
+            clay: 0.2,
+            organic_matter: 2.5,
+        });
+        assert!((loam.wilting_point - 0.14).abs() < 0.005);
+        assert!((loam.field_capacity - 0.28).abs() < 0.005);
+        assert!((loam.saturation - 0.46).abs() < 0.005);
+        assert!((loam.saturated_conductivity - 15.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn soil_blocks_from_texture() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("sand", DataType::Float32, false),
+            Field::new("silt", DataType::Float32, false),
+            Field::new("clay", DataType::Float32, false),
+            Field::new("organic_matter", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float32Array::from(vec![40.0, 20.0])),
+            Arc::new(Float32Array::from(vec![40.0, 20.0])),
+            Arc::new(Float32Array::from(vec![20.0, 60.0])),
+            Arc::new(Float32Array::from(vec![2.5, 2.5])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let ptf: Pedotransfer = "cosby".parse().unwrap();
+        let blocks = soil_blocks(&rb, ptf).unwrap();
+        assert_eq!(blocks.len(), 2);
+        let loam = &blocks[0];
+        assert!((loam.soil_water_content_wilting_point - 0.156).abs() < 0.005);
+        assert!((loam.soil_water_content_field_capacity - 0.29).abs() < 0.005);
+        assert!((loam.soil_water_content_saturation - 0.441).abs() < 0.005);
+        assert_eq!(
+            loam.soil_water_storage,
+            loam.soil_water_content_field_capacity * 1450.0
+        );
+        // clay holds more water than loam
+        assert!(blocks[1].soil_water_content_wilting_point > loam.soil_water_content_wilting_point);
+        assert!("van_genuchten".parse::<Pedotransfer>().is_err());
+        assert!(SoilTexture::from_separates(-1.0, 50.0, 51.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Handing inputs to an executable through named pipes
+//!
+//! An executable that opens its inputs by name and reads each once from start
+//! to end can be given a named pipe in place of each file. The inputs then go
+//! from memory to the process without touching the disk, which matters when
+//! thousands of runs share a network filesystem.
+
+use std::ffi::CString;
+use std::fs::{remove_file, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread::JoinHandle;
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    // a file left by a run that wrote its inputs to disk
+    match remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Open the read end of a pipe and close it again, so a writer waiting for a
+/// reader stops waiting
+fn release(path: &Path) {
+    let _ = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+}
+
+/// Run `command`, writing each input to a named pipe at its path
+///
+/// Inputs the executable does not read to the end are dropped once it exits.
+/// The pipes are removed afterwards so later runs can write files there.
+pub fn output_with_pipes(
+    mut command: Command,
+    inputs: Vec<(PathBuf, Vec<u8>)>,
+) -> io::Result<Output> {
+    let mut paths = vec![];
+    let result = (|| {
+        for (path, _) in inputs.iter() {
+            mkfifo(path)?;
+            paths.push(path.clone());
+        }
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let writers: Vec<(PathBuf, JoinHandle<io::Result<()>>)> = inputs
+            .into_iter()
+            .map(|(path, data)| {
+                let fifo = path.clone();
+                let writer = std::thread::spawn(move || {
+                    // blocks until the executable opens the pipe
+                    let mut f = OpenOptions::new().write(true).open(&fifo)?;
+                    f.write_all(&data)
+                });
+                (path, writer)
+            })
+            .collect();
+        let output = child.wait_with_output();
+        for (path, writer) in writers {
+            while !writer.is_finished() {
+                release(&path);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            match writer.join().expect("pipe writer not to panic") {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e),
+                _ => {}
+            }
+        }
+        output
+    })();
+    for path in paths {
+        let _ = remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use crate::pipes::output_with_pipes;
+
+    #[test]
+    fn pipe_inputs() {
+        let dir = std::env::temp_dir().join(format!("simplecrop-pipes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let weather = dir.join("weather.inp");
+        let irrigation = dir.join("irrig.inp");
+        // an input left on disk by an earlier run is replaced
+        std::fs::write(&weather, "stale").unwrap();
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("cat weather.inp").current_dir(&dir);
+        let inputs = vec![
+            (
+                weather.clone(),
+                b"  87001   5.1  20.0   4.4  23.9  10.7\n".to_vec(),
+            ),
+            // never opened, which must not hang the run
+            (irrigation.clone(), vec![b'0'; 1 << 20]),
+        ];
+        let output = output_with_pipes(command, inputs).unwrap();
+        let left = (weather.exists(), irrigation.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"  87001   5.1  20.0   4.4  23.9  10.7\n");
+        assert_eq!(left, (false, false));
+    }
+}
@@ -0,0 +1,378 @@
+//! Quality control of daily weather
+//!
+//! Station records have errors the model runs through without complaint: a
+//! misplaced decimal point makes a 45 °C day in spring, maximum and minimum
+//! temperature get swapped, radiation sensors read negative at night or far
+//! above what reaches the top of the atmosphere, and a logger stuck on one
+//! record repeats a day. [`check`] finds these and [`correct`] fixes the
+//! ones that have an obvious fix.
+
+use std::fmt;
+
+use meillionen_mt::strictness;
+
+use crate::dssat::{WeatherData, MISSING};
+use crate::model::DailyData;
+
+/// Limits of plausible weather
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QcConfig {
+    /// How far (°C) a day's temperature may stand out from both the day
+    /// before and the day after
+    pub spike: f32,
+    /// The most solar radiation (MJ/m2/d) that can reach the ground
+    pub max_energy_flux: f32,
+    /// The most photosynthetically active radiation (mol/m2/d) that can
+    /// reach the ground
+    pub max_photosynthetic_energy_flux: f32,
+}
+
+impl Default for QcConfig {
+    fn default() -> Self {
+        Self {
+            spike: 15.0,
+            // clear sky radiation at the solstice in the tropics is about 35
+            max_energy_flux: 40.0,
+            max_photosynthetic_energy_flux: 80.0,
+        }
+    }
+}
+
+/// The kinds of problem [`check`] looks for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Check {
+    /// A temperature far from the days on either side of it
+    Spike,
+    /// The maximum temperature is below the minimum
+    TmaxBelowTmin,
+    /// Radiation that is negative or above what can reach the ground
+    ImpossibleRadiation,
+    /// A day with the same weather as the day before, or the same date
+    DuplicatedDay,
+}
+
+impl Check {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Check::Spike => "spike",
+            Check::TmaxBelowTmin => "tmax_below_tmin",
+            Check::ImpossibleRadiation => "impossible_radiation",
+            Check::DuplicatedDay => "duplicated_day",
+        }
+    }
+}
+
+/// A value that failed a check
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    /// The index of the day, from 0
+    pub day: usize,
+    pub check: Check,
+    pub column: &'static str,
+    pub value: f32,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "day {}: {} of {} ({})",
+            self.day + 1,
+            self.check.as_str(),
+            self.column,
+            self.value
+        )
+    }
+}
+
+/// The problems found in a weather record
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QcReport {
+    pub issues: Vec<Issue>,
+}
+
+impl QcReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn count(&self, check: Check) -> usize {
+        self.issues.iter().filter(|i| i.check == check).count()
+    }
+
+    /// A validation warning for each kind of problem found, see
+    /// [`meillionen_mt::strictness`]
+    pub fn warn(&self) -> Result<(), strictness::StrictError> {
+        for check in [
+            Check::Spike,
+            Check::TmaxBelowTmin,
+            Check::ImpossibleRadiation,
+            Check::DuplicatedDay,
+        ] {
+            let days: Vec<String> = self
+                .issues
+                .iter()
+                .filter(|i| i.check == check)
+                .map(|i| (i.day + 1).to_string())
+                .collect();
+            if !days.is_empty() {
+                strictness::warn(format!(
+                    "weather failed the {} check on days {}",
+                    check.as_str(),
+                    days.join(", ")
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn known(x: f32) -> bool {
+    x.is_finite() && x != MISSING
+}
+
+fn spikes(column: &'static str, values: &[f32], limit: f32, issues: &mut Vec<Issue>) {
+    for day in 1..values.len().saturating_sub(1) {
+        let (before, x, after) = (values[day - 1], values[day], values[day + 1]);
+        if !(known(before) && known(x) && known(after)) {
+            continue;
+        }
+        let (up, down) = (x - before, x - after);
+        if up.abs() > limit && down.abs() > limit && up.signum() == down.signum() {
+            issues.push(Issue {
+                day,
+                check: Check::Spike,
+                column,
+                value: x,
+            });
+        }
+    }
+}
+
+/// Check daily weather for spikes, swapped temperatures, impossible
+/// radiation and repeated days
+///
+/// Missing values (`-99`) are not checked.
+pub fn check(daily: &DailyData, config: &QcConfig) -> QcReport {
+    let mut issues = vec![];
+    spikes("temp_max", daily.temp_max, config.spike, &mut issues);
+    spikes("temp_min", daily.temp_min, config.spike, &mut issues);
+    for (day, (tmax, tmin)) in daily.temp_max.iter().zip(daily.temp_min).enumerate() {
+        if known(*tmax) && known(*tmin) && tmax < tmin {
+            issues.push(Issue {
+                day,
+                check: Check::TmaxBelowTmin,
+                column: "temp_max",
+                value: *tmax,
+            });
+        }
+    }
+    for (column, values, max) in [
+        ("energy_flux", daily.energy_flux, config.max_energy_flux),
+        (
+            "photosynthetic_energy_flux",
+            daily.photosynthetic_energy_flux,
+            config.max_photosynthetic_energy_flux,
+        ),
+    ] {
+        for (day, value) in values.iter().enumerate() {
+            if known(*value) && !(0.0..=max).contains(value) {
+                issues.push(Issue {
+                    day,
+                    check: Check::ImpossibleRadiation,
+                    column,
+                    value: *value,
+                });
+            }
+        }
+    }
+    let columns = [
+        daily.temp_max,
+        daily.temp_min,
+        daily.rainfall,
+        daily.energy_flux,
+    ];
+    for day in 1..daily.temp_max.len() {
+        // a dry day can repeat yesterday's temperatures by chance to one
+        // decimal, so only count days where every value, rain included, repeats
+        let repeated = columns
+            .iter()
+            .all(|c| c.get(day).is_some_and(|x| known(*x) && *x == c[day - 1]));
+        if repeated && daily.rainfall[day] > 0.0 {
+            issues.push(Issue {
+                day,
+                check: Check::DuplicatedDay,
+                column: "rainfall",
+                value: daily.rainfall[day],
+            });
+        }
+    }
+    issues.sort_by_key(|i| (i.day, i.check));
+    QcReport { issues }
+}
+
+impl WeatherData {
+    /// [`check`] the weather, also flagging days whose date repeats an
+    /// earlier one
+    pub fn check(&self, config: &QcConfig) -> QcReport {
+        let mut report = check(&self.daily(), config);
+        for day in 1..self.dates.len() {
+            if self.dates[..day].contains(&self.dates[day])
+                && !report
+                    .issues
+                    .iter()
+                    .any(|i| i.day == day && i.check == Check::DuplicatedDay)
+            {
+                report.issues.push(Issue {
+                    day,
+                    check: Check::DuplicatedDay,
+                    column: "date",
+                    value: self.dates[day].1 as f32,
+                });
+            }
+        }
+        report.issues.sort_by_key(|i| (i.day, i.check));
+        report
+    }
+}
+
+/// Daily inputs with the problems [`correct`] could fix fixed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CorrectedDaily {
+    pub irrigation: Vec<f32>,
+    pub temp_max: Vec<f32>,
+    pub temp_min: Vec<f32>,
+    pub rainfall: Vec<f32>,
+    pub photosynthetic_energy_flux: Vec<f32>,
+    pub energy_flux: Vec<f32>,
+    /// The issues that were fixed
+    pub corrected: Vec<Issue>,
+}
+
+impl CorrectedDaily {
+    pub fn daily(&self) -> DailyData<'_> {
+        DailyData {
+            irrigation: &self.irrigation,
+            temp_max: &self.temp_max,
+            temp_min: &self.temp_min,
+            rainfall: &self.rainfall,
+            photosynthetic_energy_flux: &self.photosynthetic_energy_flux,
+            energy_flux: &self.energy_flux,
+        }
+    }
+}
+
+/// A copy of the inputs with the issues of `report` that have an obvious fix
+/// fixed
+///
+/// Spikes are replaced with the mean of the days either side, swapped
+/// temperatures are swapped back and radiation is clamped to the plausible
+/// range. Duplicated days are left for the user to fill since the true
+/// weather of the day is unknown.
+pub fn correct(daily: &DailyData, report: &QcReport, config: &QcConfig) -> CorrectedDaily {
+    let mut out = CorrectedDaily {
+        irrigation: daily.irrigation.to_vec(),
+        temp_max: daily.temp_max.to_vec(),
+        temp_min: daily.temp_min.to_vec(),
+        rainfall: daily.rainfall.to_vec(),
+        photosynthetic_energy_flux: daily.photosynthetic_energy_flux.to_vec(),
+        energy_flux: daily.energy_flux.to_vec(),
+        corrected: vec![],
+    };
+    for issue in report.issues.iter() {
+        let day = issue.day;
+        match (issue.check, issue.column) {
+            (Check::Spike, column) => {
+                let values = match column {
+                    "temp_max" => &mut out.temp_max,
+                    _ => &mut out.temp_min,
+                };
+                values[day] = (values[day - 1] + values[day + 1]) / 2.0;
+            }
+            (Check::TmaxBelowTmin, _) => {
+                if out.temp_max[day] >= out.temp_min[day] {
+                    // a spike correction already put them in order
+                    continue;
+                }
+                std::mem::swap(&mut out.temp_max[day], &mut out.temp_min[day]);
+            }
+            (Check::ImpossibleRadiation, "energy_flux") => {
+                out.energy_flux[day] = out.energy_flux[day].clamp(0.0, config.max_energy_flux);
+            }
+            (Check::ImpossibleRadiation, _) => {
+                out.photosynthetic_energy_flux[day] = out.photosynthetic_energy_flux[day]
+                    .clamp(0.0, config.max_photosynthetic_energy_flux);
+            }
+            (Check::DuplicatedDay, _) => continue,
+        }
+        out.corrected.push(issue.clone());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dssat::{WeatherData, MISSING};
+    use crate::model::DailyData;
+    use crate::qc::{check, correct, Check, QcConfig};
+
+    #[test]
+    fn check_and_correct() {
+        let temp_max = [25.0, 26.0, 62.0, 27.0, 12.0, 28.0, 28.0, MISSING];
+        let temp_min = [12.0, 13.0, 14.0, 13.0, 18.0, 15.0, 15.0, 14.0];
+        let rainfall = [0.0, 0.0, 5.0, 0.0, 0.0, 3.2, 3.2, 0.0];
+        let energy_flux = [20.0, -1.0, 21.0, 19.0, 18.0, 17.0, 17.0, 95.0];
+        let par = [40.0, 0.0, 42.0, 38.0, 36.0, 34.0, 34.0, 80.0];
+        let irrigation = [0.0; 8];
+        let daily = DailyData {
+            irrigation: &irrigation,
+            temp_max: &temp_max,
+            temp_min: &temp_min,
+            rainfall: &rainfall,
+            photosynthetic_energy_flux: &par,
+            energy_flux: &energy_flux,
+        };
+        let config = QcConfig::default();
+        let report = check(&daily, &config);
+        let found: Vec<(usize, Check)> = report.issues.iter().map(|i| (i.day, i.check)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, Check::ImpossibleRadiation),
+                (2, Check::Spike),
+                (4, Check::TmaxBelowTmin),
+                (6, Check::DuplicatedDay),
+                (7, Check::ImpossibleRadiation),
+            ]
+        );
+        assert_eq!(
+            report.issues[1].to_string(),
+            "day 3: spike of temp_max (62)"
+        );
+        assert!(report.warn().is_ok());
+
+        let fixed = correct(&daily, &report, &config);
+        assert_eq!(fixed.corrected.len(), 4);
+        assert_eq!(fixed.temp_max[2], 26.5);
+        assert_eq!((fixed.temp_max[4], fixed.temp_min[4]), (18.0, 12.0));
+        assert_eq!(fixed.energy_flux[1], 0.0);
+        assert_eq!(fixed.energy_flux[7], 40.0);
+        let recheck = check(&fixed.daily(), &config);
+        assert_eq!(recheck.count(Check::DuplicatedDay), 1);
+        assert_eq!(recheck.issues.len(), 1);
+
+        let weather = WeatherData {
+            dates: vec![(2020, 1), (2020, 2), (2020, 2)],
+            irrigation: vec![0.0; 3],
+            temp_max: vec![25.0, 26.0, 27.0],
+            temp_min: vec![12.0, 13.0, 14.0],
+            rainfall: vec![0.0; 3],
+            photosynthetic_energy_flux: vec![40.0; 3],
+            energy_flux: vec![20.0; 3],
+            ..WeatherData::default()
+        };
+        let report = weather.check(&config);
+        assert_eq!(report.count(Check::DuplicatedDay), 1);
+        assert_eq!(report.issues[0].column, "date");
+    }
+}
@@ -0,0 +1,253 @@
+//! Soil texture for a site from gridded soil databases, turned into SimpleCrop
+//! soil parameters with pedotransfer functions
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+use stable_eyre::eyre::{eyre, WrapErr};
+
+use crate::model::YearlyData;
+use crate::pedotransfer::{Pedotransfer, SoilTexture, SoilWater};
+
+/// Organic matter per unit of organic carbon
+const ORGANIC_MATTER_PER_CARBON: f32 = 1.724;
+
+/// A source of topsoil texture by latitude and longitude in degrees
+pub trait SoilDatabase {
+    fn texture(&self, lat: f64, lon: f64) -> stable_eyre::Result<SoilTexture>;
+}
+
+/// The ISRIC SoilGrids REST API
+///
+/// Requests are made with `curl` so no TLS stack is linked into the model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoilGrids {
+    pub url: String,
+    /// A SoilGrids depth interval such as `0-5cm` or `15-30cm`
+    pub depth: String,
+}
+
+impl Default for SoilGrids {
+    fn default() -> Self {
+        Self {
+            url: "https://rest.isric.org/soilgrids/v2.0/properties/query".to_string(),
+            depth: "0-5cm".to_string(),
+        }
+    }
+}
+
+impl SoilGrids {
+    pub fn query_url(&self, lat: f64, lon: f64) -> String {
+        format!(
+            "{}?lon={}&lat={}&property=sand&property=clay&property=soc&depth={}&value=mean",
+            self.url, lon, lat, self.depth
+        )
+    }
+
+    /// The mean of a property converted to its target units, percent for
+    /// sand and clay and g/kg for organic carbon
+    fn mean(response: &Value, property: &str, depth: &str) -> stable_eyre::Result<f32> {
+        let layer = response["properties"]["layers"]
+            .as_array()
+            .and_then(|layers| layers.iter().find(|l| l["name"] == property))
+            .ok_or_else(|| eyre!("SoilGrids response has no {} layer", property))?;
+        let d_factor = layer["unit_measure"]["d_factor"].as_f64().unwrap_or(1.0);
+        let mean = layer["depths"]
+            .as_array()
+            .and_then(|ds| ds.iter().find(|d| d["label"] == depth))
+            .and_then(|d| d["values"]["mean"].as_f64())
+            .ok_or_else(|| eyre!("SoilGrids has no {} at {} for this site", property, depth))?;
+        Ok((mean / d_factor) as f32)
+    }
+
+    /// The texture in a SoilGrids properties query response
+    pub fn parse_response(&self, body: &str) -> stable_eyre::Result<SoilTexture> {
+        let response: Value =
+            serde_json::from_str(body).wrap_err("SoilGrids response is not JSON")?;
+        let carbon = Self::mean(&response, "soc", &self.depth)?;
+        Ok(SoilTexture {
+            sand: Self::mean(&response, "sand", &self.depth)? / 100.0,
+            clay: Self::mean(&response, "clay", &self.depth)? / 100.0,
+            organic_matter: carbon * ORGANIC_MATTER_PER_CARBON / 10.0,
+        })
+    }
+}
+
+impl SoilDatabase for SoilGrids {
+    fn texture(&self, lat: f64, lon: f64) -> stable_eyre::Result<SoilTexture> {
+        let url = self.query_url(lat, lon);
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", &url])
+            .output()
+            .wrap_err("could not run curl")?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "SoilGrids query {} failed: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        self.parse_response(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Points of a local SSURGO extract, such as component horizons joined to map
+/// unit centroids
+///
+/// The CSV needs `latitude`, `longitude`, `sandtotal_r`, `claytotal_r` and
+/// `om_r` columns, with the SSURGO percentages. A lookup takes the nearest
+/// point within `max_distance` degrees.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SsurgoExtract {
+    points: Vec<(f64, f64, SoilTexture)>,
+    pub max_distance: f64,
+}
+
+impl SsurgoExtract {
+    pub fn load<P: AsRef<Path>>(p: P) -> stable_eyre::Result<Self> {
+        let mut rdr = csv::Reader::from_path(&p)
+            .wrap_err_with(|| format!("Could not open {}", p.as_ref().to_string_lossy()))?;
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h == name)
+                .ok_or_else(|| eyre!("SSURGO extract has no {} column", name))
+        };
+        let columns = [
+            column("latitude")?,
+            column("longitude")?,
+            column("sandtotal_r")?,
+            column("claytotal_r")?,
+            column("om_r")?,
+        ];
+        let mut points = vec![];
+        for (i, record) in rdr.records().enumerate() {
+            let record = record?;
+            let values = columns
+                .iter()
+                .map(|&c| record.get(c).and_then(|v| v.trim().parse::<f64>().ok()))
+                .collect::<Option<Vec<f64>>>();
+            // horizons without texture data are left out
+            if let Some(v) = values {
+                points.push((
+                    v[0],
+                    v[1],
+                    SoilTexture {
+                        sand: (v[2] / 100.0) as f32,
+                        clay: (v[3] / 100.0) as f32,
+                        organic_matter: v[4] as f32,
+                    },
+                ));
+            } else if record.get(columns[0]).is_none_or(str::is_empty) {
+                return Err(eyre!("row {} of the SSURGO extract has no location", i + 2));
+            }
+        }
+        Ok(Self {
+            points,
+            max_distance: 0.1,
+        })
+    }
+}
+
+impl SoilDatabase for SsurgoExtract {
+    fn texture(&self, lat: f64, lon: f64) -> stable_eyre::Result<SoilTexture> {
+        let scale = lat.to_radians().cos();
+        let distance = |p: &(f64, f64, SoilTexture)| {
+            ((p.0 - lat).powi(2) + ((p.1 - lon) * scale).powi(2)).sqrt()
+        };
+        self.points
+            .iter()
+            .map(|p| (distance(p), p.2))
+            .filter(|(d, _)| *d <= self.max_distance)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, t)| t)
+            .ok_or_else(|| {
+                eyre!(
+                    "no SSURGO point within {} degrees of {}, {}",
+                    self.max_distance,
+                    lat,
+                    lon
+                )
+            })
+    }
+}
+
+/// Look up the soil at a site and set the soil water contents of `yearly`
+/// with pedotransfer functions
+pub fn fill_yearly(
+    db: &dyn SoilDatabase,
+    ptf: Pedotransfer,
+    lat: f64,
+    lon: f64,
+    yearly: &mut YearlyData,
+) -> stable_eyre::Result<SoilWater> {
+    let texture = db
+        .texture(lat, lon)
+        .wrap_err_with(|| format!("soil lookup at {}, {} failed", lat, lon))?;
+    let water = ptf.estimate(&texture);
+    water.apply(yearly);
+    Ok(water)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::YearlyData;
+    use crate::pedotransfer::Pedotransfer;
+    use crate::soil::{fill_yearly, SoilDatabase, SoilGrids, SsurgoExtract};
+
+    const RESPONSE: &str = r#"{
+  "type": "Feature",
+  "properties": {
+    "layers": [
+      {"name": "clay", "unit_measure": {"d_factor": 10, "mapped_units": "g/kg", "target_units": "%"},
+       "depths": [{"label": "0-5cm", "values": {"mean": 200}}]},
+      {"name": "sand", "unit_measure": {"d_factor": 10, "mapped_units": "g/kg", "target_units": "%"},
+       "depths": [{"label": "0-5cm", "values": {"mean": 400}}]},
+      {"name": "soc", "unit_measure": {"d_factor": 10, "mapped_units": "dg/kg", "target_units": "g/kg"},
+       "depths": [{"label": "0-5cm", "values": {"mean": 145}}]}
+    ]
+  }
+}"#;
+
+    #[test]
+    fn lookup_sources() {
+        let grids = SoilGrids::default();
+        assert!(grids.query_url(29.63, -82.37).ends_with(
+            "?lon=-82.37&lat=29.63&property=sand&property=clay&property=soc&depth=0-5cm&value=mean"
+        ));
+        let texture = grids.parse_response(RESPONSE).unwrap();
+        assert_eq!((texture.sand, texture.clay), (0.4, 0.2));
+        assert!((texture.organic_matter - 2.5).abs() < 0.01);
+        assert!(grids
+            .parse_response(r#"{"properties": {"layers": []}}"#)
+            .is_err());
+
+        let path =
+            std::env::temp_dir().join(format!("simplecrop-ssurgo-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "mukey,latitude,longitude,sandtotal_r,claytotal_r,om_r\n\
+             1,29.6,-82.4,88,5,1.0\n\
+             2,29.7,-82.3,,,\n\
+             3,30.5,-82.4,40,20,2.5\n",
+        )
+        .unwrap();
+        let ssurgo = SsurgoExtract::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ssurgo.texture(29.63, -82.37).unwrap().sand, 0.88);
+        assert!(ssurgo.texture(40.0, -82.37).is_err());
+
+        let mut yearly = YearlyData::default();
+        let water =
+            fill_yearly(&ssurgo, Pedotransfer::default(), 29.63, -82.37, &mut yearly).unwrap();
+        assert_eq!(
+            yearly.soil_water_content_field_capacity,
+            water.field_capacity
+        );
+        assert!(
+            water.wilting_point < water.field_capacity && water.field_capacity < water.saturation
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Working directories for runs of the SimpleCrop executable
+//!
+//! The executable reads `data/` and writes `output/` relative to where it
+//! runs, so two runs in one directory overwrite each other's files. A
+//! workspace holds a lock file in its directory for as long as it lives so a
+//! second claim on the directory fails instead.
+
+use std::fs::{create_dir, create_dir_all, read_to_string, remove_file, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use stable_eyre::eyre::{eyre, WrapErr};
+
+pub const LOCK_FILE: &str = ".simplecrop.lock";
+
+static NEXT_WORKSPACE: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub struct Workspace {
+    dir: PathBuf,
+    /// The lock file, if the workspace is not shared
+    lock: Option<PathBuf>,
+}
+
+impl Workspace {
+    /// Claim `dir` for one instance, creating it if needed
+    ///
+    /// Fails if another workspace holds the directory. A lock left behind by
+    /// a process that crashed has to be removed by hand.
+    pub fn claim<P: AsRef<Path>>(dir: P) -> stable_eyre::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(&dir)
+            .wrap_err_with(|| format!("Cannot create workspace {}", dir.to_string_lossy()))?;
+        let lock = dir.join(LOCK_FILE);
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(mut f) => {
+                write!(f, "{}", std::process::id()).wrap_err("Cannot write workspace lock")?;
+                Ok(Self {
+                    dir,
+                    lock: Some(lock),
+                })
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let owner = read_to_string(&lock).unwrap_or_default();
+                Err(eyre!(
+                    "workspace {} is in use by process {}, give each instance its own \
+                     directory or share it explicitly (remove {} if that process is gone)",
+                    dir.to_string_lossy(),
+                    owner.trim(),
+                    lock.to_string_lossy()
+                ))
+            }
+            Err(e) => Err(e).wrap_err_with(|| format!("Cannot lock {}", lock.to_string_lossy())),
+        }
+    }
+
+    /// A new directory under `base` named `{name}-{pid}-{n}` that no other
+    /// instance uses
+    pub fn unique<P: AsRef<Path>>(base: P, name: &str) -> stable_eyre::Result<Self> {
+        let base = base.as_ref();
+        create_dir_all(base)
+            .wrap_err_with(|| format!("Cannot create {}", base.to_string_lossy()))?;
+        loop {
+            let n = NEXT_WORKSPACE.fetch_add(1, Ordering::Relaxed);
+            let dir = base.join(format!("{}-{}-{}", name, std::process::id(), n));
+            // directories left by an earlier process with the same pid are skipped
+            match create_dir(&dir) {
+                Ok(()) => return Self::claim(dir),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e)
+                        .wrap_err_with(|| format!("Cannot create {}", dir.to_string_lossy()))
+                }
+            }
+        }
+    }
+
+    /// Use `dir` without claiming it, for instances that share a directory on
+    /// purpose and run one at a time
+    pub fn shared<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            lock: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.lock.is_none()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if let Some(lock) = &self.lock {
+            let _ = remove_file(lock);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workspace::{Workspace, LOCK_FILE};
+
+    #[test]
+    fn workspaces_are_exclusive() {
+        let base =
+            std::env::temp_dir().join(format!("simplecrop-workspaces-{}", std::process::id()));
+        let a = Workspace::unique(&base, "simplecrop").unwrap();
+        let b = Workspace::unique(&base, "simplecrop").unwrap();
+        assert_ne!(a.path(), b.path());
+        assert!(a.path().join(LOCK_FILE).exists());
+
+        let err = Workspace::claim(a.path()).unwrap_err();
+        assert!(err.to_string().contains("in use"));
+        let shared = Workspace::shared(a.path());
+        assert!(shared.is_shared());
+        drop(shared);
+
+        let dir = a.path().to_path_buf();
+        drop(a);
+        assert!(!dir.join(LOCK_FILE).exists());
+        assert!(Workspace::claim(&dir).is_ok());
+        drop(b);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
+import asyncio
+import functools
 import pathlib
 from typing import Any, Dict, Optional
 
@@ -114,9 +116,28 @@ class ClientFunctionModel:
             'sinks': sinks
         }
 
-    def run(self, sources: Dict[str, Any], sinks: Optional[Dict[str, Any]] = None, partition: Optional[Dict[str, Any]] = None):
+    def run(self, sources: Dict[str, Any], sinks: Optional[Dict[str, Any]] = None, partition: Optional[Dict[str, Any]] = None,
+            traceparent: Optional[str] = None):
+        """
+        Run the model
+
+        :param traceparent: W3C trace context of the caller's span (what an OpenTelemetry
+          propagator injects as the ``traceparent`` header). The model program is run in a
+          child span of it.
+        """
         kwargs = self._prepare(sources=sources, sinks=sinks, partition=partition)
         fr = FuncRequest(**kwargs)
         rb = fr.to_recordbatch(self.path)
-        client_call_cli(self.path, rb)
+        client_call_cli(self.path, rb, traceparent)
         return kwargs['sinks']
+
+    async def run_async(self, sources: Dict[str, Any], sinks: Optional[Dict[str, Any]] = None,
+                        partition: Optional[Dict[str, Any]] = None, traceparent: Optional[str] = None):
+        """
+        Run the model on a worker thread so the event loop, and the widgets
+        and progress bars it drives, keep running
+        """
+        loop = asyncio.get_running_loop()
+        run = functools.partial(self.run, sources=sources, sinks=sinks, partition=partition,
+                                traceparent=traceparent)
+        return await loop.run_in_executor(None, run)
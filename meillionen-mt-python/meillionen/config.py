@@ -0,0 +1,166 @@
+import difflib
+import html
+import inspect
+import math
+import numbers
+from typing import Any, Dict, List, Optional
+
+import pandas as pd
+
+INTEGER_TYPES = {'Int8', 'Int16', 'Int32', 'Int64', 'UInt8', 'UInt16', 'UInt32', 'UInt64'}
+FLOAT_TYPES = {'Float16', 'Float32', 'Float64'}
+
+
+def handler_fields(handler) -> List[Dict[str, Any]]:
+    """The column fields declared by a data frame handler of an interface"""
+    return handler.schema.to_dict()['columns']['fields']
+
+
+def _check_value(name: str, data_type: str, value):
+    if isinstance(value, bool):
+        raise TypeError(f'{name} must be a number but is {value!r}')
+    if data_type in INTEGER_TYPES:
+        if isinstance(value, numbers.Real) and float(value).is_integer():
+            value = int(value)
+        if not isinstance(value, int):
+            raise TypeError(f'{name} must be an integer but is {value!r}')
+        return value
+    if data_type in FLOAT_TYPES:
+        if not isinstance(value, numbers.Real):
+            raise TypeError(f'{name} must be a number but is {value!r}')
+        if not math.isfinite(value):
+            raise ValueError(f'{name} must be finite but is {value!r}')
+        return float(value)
+    return value
+
+
+def _check_range(name: str, doc: Dict[str, Any], value):
+    low, high = doc.get('min'), doc.get('max')
+    if (low is not None and value < low) or (high is not None and value > high):
+        units = f" {doc['units']}" if doc.get('units') else ''
+        raise ValueError(f'{name} must be in [{low}, {high}]{units} but is {value!r}')
+    return value
+
+
+def config_class(name: str, fields: List[Dict[str, Any]], defaults: Optional[Dict[str, Any]] = None,
+                 aliases: Optional[Dict[str, str]] = None, doc: Optional[str] = None,
+                 docs: Optional[Dict[str, Dict[str, Any]]] = None):
+    """
+    Build a class with a keyword argument constructor for the columns of a model interface
+
+    Every field becomes a keyword argument, taking its default when one is given
+    and required otherwise. Values are checked against the field data types and
+    unknown names fail with the closest known names as suggestions.
+
+    :param name: the name of the class
+    :param fields: column fields with ``name`` and ``data_type`` keys, see ``handler_fields``
+    :param defaults: default values by field name
+    :param aliases: shorter names accepted in place of field names
+    :param doc: the class docstring
+    :param docs: the ``default``, ``units``, ``min``, ``max`` and ``description`` of
+        fields by field name, such as those a model exports from its parameter struct.
+        Values outside ``min`` and ``max`` fail and ``describe_parameters`` lists them.
+    """
+    fields = {f['name']: f['data_type'] for f in fields}
+    docs = {k: dict(v) for k, v in (docs or {}).items()}
+    unknown = set(docs) - set(fields)
+    if unknown:
+        raise ValueError(f'docs given for unknown fields {sorted(unknown)}')
+    defaults = dict({k: d['default'] for k, d in docs.items() if d.get('default') is not None}, **(defaults or {}))
+    aliases = dict(aliases or {})
+    unknown = set(defaults) - set(fields)
+    if unknown:
+        raise ValueError(f'defaults given for unknown fields {sorted(unknown)}')
+    defaults = {k: _check_range(k, docs.get(k, {}), _check_value(k, fields[k], v)) for k, v in defaults.items()}
+    for alias, target in aliases.items():
+        if target not in fields:
+            raise ValueError(f'alias {alias} refers to unknown field {target}')
+    known = list(fields) + list(aliases)
+
+    def __init__(self, **kwargs):
+        values = {}
+        for key, value in kwargs.items():
+            field = aliases.get(key, key)
+            if field not in fields:
+                matches = difflib.get_close_matches(key, known, n=3)
+                hint = f", did you mean {' or '.join(matches)}?" if matches else ''
+                raise TypeError(f'{name} has no parameter {key}{hint}')
+            if field in values:
+                raise TypeError(f'{name} got {field} more than once')
+            values[field] = _check_range(key, docs.get(field, {}), _check_value(key, fields[field], value))
+        missing = [f for f in fields if f not in values and f not in defaults]
+        if missing:
+            raise TypeError(f'{name} is missing parameters {", ".join(missing)}')
+        for field in fields:
+            if field not in values:
+                values[field] = defaults[field]
+        self._values = values
+
+    def __getattr__(self, key):
+        field = aliases.get(key, key)
+        try:
+            return self.__dict__['_values'][field]
+        except KeyError:
+            raise AttributeError(key) from None
+
+    def __eq__(self, other):
+        return type(self) is type(other) and self._values == other._values
+
+    def __repr__(self):
+        changed = ', '.join(f'{k}={v!r}' for k, v in self._values.items() if defaults.get(k) != v)
+        return f'{name}({changed})'
+
+    def _repr_html_(self):
+        rows = ''.join(
+            f'<tr><td>{html.escape(k)}</td><td>{html.escape(repr(v))}</td></tr>'
+            for k, v in self._values.items())
+        return f'<table><caption>{html.escape(name)}</caption>{rows}</table>'
+
+    def to_dict(self) -> Dict[str, Any]:
+        return dict(self._values)
+
+    def to_dataframe(self) -> pd.DataFrame:
+        """A one row data frame with a column of the declared type for each field"""
+        df = pd.DataFrame([self._values])
+        return df.astype({k: v.lower() for k, v in fields.items() if k in df and v in INTEGER_TYPES | FLOAT_TYPES})
+
+    @classmethod
+    def describe_parameters(cls) -> pd.DataFrame:
+        """A table of the data type, default, units, range and description of each parameter"""
+        rows = [
+            {
+                'parameter': f,
+                'data_type': fields[f],
+                'default': defaults.get(f),
+                'units': docs.get(f, {}).get('units'),
+                'min': docs.get(f, {}).get('min'),
+                'max': docs.get(f, {}).get('max'),
+                'description': docs.get(f, {}).get('description'),
+                'aliases': [a for a, t in aliases.items() if t == f],
+            }
+            for f in fields
+        ]
+        return pd.DataFrame(rows).set_index('parameter')
+
+    parameters = [
+        inspect.Parameter(f, inspect.Parameter.KEYWORD_ONLY,
+                          default=defaults.get(f, inspect.Parameter.empty))
+        for f in fields
+    ]
+    __init__.__signature__ = inspect.Signature(
+        [inspect.Parameter('self', inspect.Parameter.POSITIONAL_OR_KEYWORD)] + parameters)
+    return type(name, (), {
+        '__doc__': doc,
+        '__init__': __init__,
+        '__getattr__': __getattr__,
+        '__eq__': __eq__,
+        '__repr__': __repr__,
+        '_repr_html_': _repr_html_,
+        'to_dict': to_dict,
+        'to_dataframe': to_dataframe,
+        'describe_parameters': describe_parameters,
+        'fields': fields,
+        'aliases': aliases,
+        'defaults': defaults,
+        'docs': docs,
+    })
@@ -0,0 +1,128 @@
+"""
+Ensemble outputs gathered into one NetCDF file
+
+An ensemble of thousands of trials otherwise leaves thousands of sink files.
+``EnsembleCollector`` stacks a tabular sink of every member along an
+``ensemble_member`` dimension, with the parameters each member ran with as
+coordinates along it, so one ``xr.open_dataset`` gives the whole ensemble::
+
+    collector = EnsembleCollector('yearly', index=['year'])
+    for result, params in zip(results, samples.to_pylist()):
+        collector.add_result(result, params)
+    collector.write('outputs/ensemble.nc')
+
+``risk`` gives exceedance probabilities, return periods and CVaR of an output
+over the members as a tidy frame::
+
+    collector.risk('yield', group_by=['year'], thresholds=[2000.0], cvar_levels=[0.1])
+"""
+import pathlib
+from typing import Any, Dict, List, Optional, Sequence
+
+import pandas as pd
+import pyarrow as pa
+import xarray as xr
+
+from meillionen.experiment import RunResult
+from meillionen.meillionen import risk_table
+from meillionen.units import UnitsType
+
+MEMBER_DIM = 'ensemble_member'
+
+
+class EnsembleCollector:
+    """
+    Collects one sink of each ensemble member
+
+    Columns in ``index``, such as the day or a cell id, become dimensions and
+    the other columns variables. Members whose index values differ are
+    aligned, with missing values filled with NaN.
+    """
+    def __init__(self, sink: str, index: Sequence[str], member_dim: str = MEMBER_DIM):
+        self.sink = sink
+        self.index = list(index)
+        self.member_dim = member_dim
+        self._members: List[str] = []
+        self._datasets: List[xr.Dataset] = []
+        self._parameters: List[Dict[str, Any]] = []
+
+    def __len__(self):
+        return len(self._members)
+
+    def add(self, member: str, table: pa.Table, parameters: Optional[Dict[str, Any]] = None):
+        """
+        Add the sink table of a member and the parameter values it ran with
+
+        Columns with units keep them in a ``units`` attribute.
+        """
+        if member in self._members:
+            raise ValueError(f'ensemble member {member} was added more than once')
+        missing = [c for c in self.index if c not in table.column_names]
+        if missing:
+            raise ValueError(f'ensemble member {member} has no index columns {", ".join(missing)}')
+        units = {f.name: f.type.unit for f in table.schema if isinstance(f.type, UnitsType)}
+        dataset = xr.Dataset.from_dataframe(table.to_pandas().set_index(self.index))
+        for name, unit in units.items():
+            if name in dataset.data_vars:
+                dataset[name].attrs['units'] = unit
+        self._members.append(member)
+        self._datasets.append(dataset)
+        self._parameters.append(dict(parameters or {}))
+
+    def add_result(self, result: RunResult, parameters: Optional[Dict[str, Any]] = None) -> bool:
+        """
+        Add the sink of a finished trial, returning whether it was added
+
+        Trials that failed or have no such sink are left out.
+        """
+        if not result.succeeded or self.sink not in result.sinks:
+            return False
+        self.add(result.trial, result.sinks[self.sink], parameters)
+        return True
+
+    def to_dataset(self) -> xr.Dataset:
+        """
+        The members stacked along the member dimension
+
+        Each parameter is a coordinate along the member dimension, NaN for
+        members that were not given it.
+        """
+        if not self._datasets:
+            raise ValueError('no ensemble members have been added')
+        dataset = xr.concat(self._datasets, dim=self.member_dim, join='outer')
+        dataset = dataset.assign_coords({self.member_dim: self._members})
+        names = sorted({name for parameters in self._parameters for name in parameters})
+        clashes = [name for name in names if name in dataset.variables]
+        if clashes:
+            raise ValueError(f'parameters {", ".join(clashes)} have the names of variables or dimensions')
+        return dataset.assign_coords({
+            name: (self.member_dim, [p.get(name, float('nan')) for p in self._parameters])
+            for name in names
+        })
+
+    def write(self, path: str):
+        """Write every member to one NetCDF file"""
+        pathlib.Path(path).parent.mkdir(parents=True, exist_ok=True)
+        self.to_dataset().to_netcdf(path)
+
+    def risk(self, variable: str, group_by: Sequence[str] = (), thresholds: Sequence[float] = (),
+             return_periods: Sequence[float] = (), cvar_levels: Sequence[float] = (),
+             side: str = 'below') -> pd.DataFrame:
+        """
+        Risk metrics of a variable with each value of every member one outcome
+
+        ``thresholds`` give the probability of an outcome past them,
+        ``return_periods`` the mean number of outcomes between such events and
+        ``cvar_levels`` the mean of that fraction of the worst outcomes. The
+        worst outcomes are the lowest unless ``side`` is ``'above'``. There is
+        a row for each metric of each group of ``group_by`` values.
+        """
+        frame = self.to_dataset()[[variable]].to_dataframe().reset_index()
+        spec = {
+            'thresholds': list(thresholds),
+            'return_periods': list(return_periods),
+            'cvar_levels': list(cvar_levels),
+            'side': side,
+        }
+        batch = pa.RecordBatch.from_pandas(frame, preserve_index=False)
+        return risk_table(batch, variable, list(group_by), spec).to_pandas()
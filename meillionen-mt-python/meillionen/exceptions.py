@@ -7,4 +7,24 @@ class MethodNotFound(KeyError):
 
 
 class ResourceNotFound(KeyError):
-    pass
\ No newline at end of file
+    pass
+
+class StrictModeError(ValueError):
+    pass
+
+
+class VariableMismatchError(ValueError):
+    def __init__(self, message, suggestions):
+        super().__init__(message)
+        self.suggestions = suggestions
+
+
+class DimensionMismatchError(VariableMismatchError):
+    pass
+
+
+class SourcesError(ValueError):
+    """Every source of a bulk ``set_values`` that could not be bound, keyed by name"""
+    def __init__(self, errors):
+        self.errors = errors
+        super().__init__('\n'.join(f'{name}: {error}' for name, error in errors.items()))
@@ -1,6 +1,14 @@
+import asyncio
 import os
+import warnings
+from concurrent.futures import ThreadPoolExecutor
+from typing import Any, Callable, Dict, List, Optional, Sequence
 
+import pandas as pd
+import pyarrow as pa
 from meillionen.client import ClientFunctionModel
+from meillionen.meillionen import (
+    design_run_specs, experiment_run_spec, experiment_run_specs, parameters_html, summary_html)
 from pydantic import BaseModel
 
 
@@ -26,4 +34,193 @@ class Trial:
         self.name = name
         self.sinks = project.sinks.copy()
         self.sinks.base_path = os.path.join(self.sinks.base_path, name)
-        self.sources = project.sources
\ No newline at end of file
+        self.sources = project.sources
+
+class RunSpec(BaseModel):
+    """
+    One trial of an experiment file and its seed as plain data, so trials can
+    be pickled and mapped over a dask or multiprocessing cluster
+
+    Relative resource paths are resolved against the worker's working
+    directory.
+    """
+    trial: Dict[str, Any]
+    seed: Optional[int] = None
+
+    @classmethod
+    def from_experiment(cls, path: str, tags: Optional[Dict[str, Optional[str]]] = None) -> List['RunSpec']:
+        """
+        A spec for each trial of an experiment file, in run order
+
+        ``tags`` keeps only the trials with those tags, such as
+        ``{'project': 'maize'}``. A value of ``None`` only requires the tag be set.
+        """
+        specs = [cls(**spec) for spec in experiment_run_specs(path)]
+        return [spec for spec in specs if spec.has_tags(tags or {})]
+
+    @classmethod
+    def from_design(cls, path: str, tags: Optional[Dict[str, Optional[str]]] = None) -> List['RunSpec']:
+        """
+        A spec for each treatment and replicate of a factorial design file,
+        tagged with the level of each factor
+        """
+        specs = [cls(**spec) for spec in design_run_specs(path)]
+        return [spec for spec in specs if spec.has_tags(tags or {})]
+
+    def has_tags(self, tags: Dict[str, Optional[str]]) -> bool:
+        """Whether the trial, with the tags of its experiment, has every tag"""
+        trial_tags = self.trial.get('tags', {})
+        return all(
+            key in trial_tags and (value is None or trial_tags[key] == value)
+            for key, value in tags.items())
+
+    def _repr_html_(self):
+        return parameters_html(self.trial.get('name', 'trial'), self.dict())
+
+
+class RunResult(BaseModel):
+    """
+    The status of a trial, the tables in its feather and parquet sinks and what
+    the model program printed
+    """
+    trial: str
+    status: Dict[str, Any]
+    sinks: Dict[str, pa.Table]
+    stdout: str = ''
+    stderr: str = ''
+    metrics: Dict[str, float] = {}
+
+    class Config:
+        arbitrary_types_allowed = True
+
+    @property
+    def succeeded(self) -> bool:
+        return self.status['state'] == 'succeeded'
+
+    def logs(self) -> Dict[str, str]:
+        """The stdout and stderr of the model program, such as convergence warnings"""
+        return {'stdout': self.stdout, 'stderr': self.stderr}
+
+    def _repr_html_(self):
+        html = parameters_html(self.trial, self.status)
+        for name, table in self.sinks.items():
+            html += summary_html(table.to_batches(), name)
+        return html
+
+
+MetricFunction = Callable[[RunResult], float]
+
+
+def stack_by_tags(specs: List[RunSpec], results: List[RunResult], sink: str,
+                  keys: Sequence[str]) -> pd.DataFrame:
+    """
+    The sink of every succeeded trial in one frame, with a column for each
+    tag in ``keys`` first, such as the factors of a design for an analysis
+    of variance::
+
+        frame = stack_by_tags(specs, results, 'yearly', ['cultivar', 'irrigation'])
+        statsmodels.formula.api.ols('yield ~ C(cultivar) * C(irrigation)', frame)
+    """
+    tags = {spec.trial['name']: spec.trial.get('tags', {}) for spec in specs}
+    frames = []
+    for result in results:
+        if not result.succeeded or sink not in result.sinks:
+            continue
+        trial_tags = tags.get(result.trial, {})
+        missing = [key for key in keys if key not in trial_tags]
+        if missing:
+            raise ValueError(f'trial {result.trial} has no tags {", ".join(missing)}')
+        frame = result.sinks[sink].to_pandas()
+        for i, key in enumerate(keys):
+            frame.insert(i, key, trial_tags[key])
+        frames.append(frame)
+    if not frames:
+        raise ValueError(f'no succeeded trial has the sink {sink}')
+    return pd.concat(frames, ignore_index=True)
+
+
+def evaluate_metrics(metrics: Dict[str, MetricFunction], result: RunResult) -> Dict[str, float]:
+    """
+    The value of each metric on a succeeded run
+
+    A metric that raises is warned about and left out so one bad metric does
+    not lose the others.
+    """
+    values = {}
+    for name, metric in metrics.items():
+        try:
+            values[name] = float(metric(result))
+        except Exception as e:
+            warnings.warn(f'metric {name} failed on {result.trial}: {e}')
+    return values
+
+
+def run_spec(spec: RunSpec, metrics: Optional[Dict[str, MetricFunction]] = None) -> RunResult:
+    """
+    Run a trial, such as on a dask worker::
+
+        futures = client.map(run_spec, RunSpec.from_experiment('irrigation.toml'))
+
+    ``metrics`` are functions of the result, such as the final yield of a
+    sink, evaluated once the trial succeeds and kept in ``RunResult.metrics``::
+
+        run_spec(spec, metrics={'yield': lambda r: r.sinks['yearly']['yield'][-1].as_py()})
+    """
+    trial, status, sinks, logs = experiment_run_spec(spec.dict())
+    tables = {name: pa.Table.from_batches(batches) for name, batches in sinks.items() if batches}
+    result = RunResult(trial=trial, status=status, sinks=tables, **logs)
+    if result.succeeded and metrics:
+        result.metrics = evaluate_metrics(metrics, result)
+    return result
+
+
+class Runner:
+    """
+    Runs the trials of an experiment concurrently from asyncio
+
+    Model programs run on worker threads with the GIL released, so a notebook
+    stays responsive during long ensembles::
+
+        runner = Runner(RunSpec.from_experiment('irrigation.toml'), max_workers=4)
+        results = await runner.run_all(on_result=lambda r: bar.update(1))
+
+    The threads come from a ``ThreadPoolExecutor`` rather than pyo3-asyncio.
+    The extension's model calls are blocking, so pyo3-asyncio would only move
+    them onto a tokio blocking pool, and it would tie each pyo3 upgrade to a
+    matching pyo3-asyncio release.
+    """
+    def __init__(self, specs: List[RunSpec], max_workers: Optional[int] = None,
+                 metrics: Optional[Dict[str, MetricFunction]] = None):
+        self.specs = specs
+        self.max_workers = max_workers
+        self.metrics = dict(metrics or {})
+
+    async def run_all(self, on_result: Optional[Callable[[RunResult], None]] = None) -> List[RunResult]:
+        """
+        Run every spec, returning the results in spec order
+
+        ``on_result`` is called on the event loop as each trial finishes. If
+        the run is cancelled or a trial raises, trials that have not started
+        are dropped and running ones finish in the background.
+        """
+        loop = asyncio.get_running_loop()
+        # not a `with` block, which would wait for running trials on the event
+        # loop thread when leaving on a cancel or error
+        executor = ThreadPoolExecutor(max_workers=self.max_workers)
+
+        async def run(spec):
+            result = await loop.run_in_executor(executor, run_spec, spec, self.metrics)
+            if on_result is not None:
+                on_result(result)
+            return result
+
+        runs = [asyncio.ensure_future(run(spec)) for spec in self.specs]
+        try:
+            results = await asyncio.gather(*runs)
+        except BaseException:
+            for r in runs:
+                r.cancel()
+            executor.shutdown(wait=False)
+            raise
+        executor.shutdown()
+        return results
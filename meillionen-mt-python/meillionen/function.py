@@ -1,3 +1,5 @@
+import html
+
 import pyarrow as pa
 from typing import Dict, Any
 
@@ -34,6 +36,14 @@ class FuncBase:
     def source(self, name):
         return self._sources[name]
 
+    def _repr_html_(self):
+        parts = []
+        for kind, resources in (('source', self._sources), ('sink', self._sinks)):
+            for name, schema in resources.items():
+                parts.append(f'<h4>{kind} {html.escape(name)}</h4>')
+                parts.append(schema._repr_html_() if hasattr(schema, '_repr_html_') else html.escape(repr(schema)))
+        return '\n'.join(parts)
+
     @classmethod
     def _deserialize(cls, resources: pa.StringArray, payload: pa.BinaryArray, index: int):
         resource_name = str(resources[index])
@@ -0,0 +1,66 @@
+import json
+from typing import Optional
+
+import pyarrow as pa
+
+GEOMETRY_EXTENSION = 'geoarrow.wkb'
+
+
+class GeometryType(pa.ExtensionType):
+    """
+    A binary column of well-known binary points and polygons, following GeoArrow
+    """
+    def __init__(self, crs: Optional[str] = None):
+        self.crs = crs
+        super().__init__(pa.binary(), GEOMETRY_EXTENSION)
+
+    def __arrow_ext_serialize__(self):
+        meta = {} if self.crs is None else {'crs': self.crs}
+        return json.dumps(meta).encode('utf-8')
+
+    @classmethod
+    def __arrow_ext_deserialize__(cls, storage_type, serialized):
+        meta = json.loads(serialized.decode('utf-8')) if serialized else {}
+        return cls(meta.get('crs'))
+
+
+try:
+    pa.register_extension_type(GeometryType())
+except pa.ArrowKeyError:
+    # another GeoArrow implementation is already registered
+    pass
+
+
+def with_geometry(array: pa.BinaryArray, crs: Optional[str] = None) -> pa.ExtensionArray:
+    """
+    Mark a binary array as holding well-known binary geometries
+    """
+    return pa.ExtensionArray.from_storage(GeometryType(crs), array)
+
+
+def from_geopandas(gdf) -> pa.Table:
+    """
+    Convert a GeoDataFrame to arrow with its geometry as a WKB column
+    """
+    geometry = gdf.geometry
+    crs = geometry.crs.to_string() if geometry.crs is not None else None
+    table = pa.Table.from_pandas(gdf.drop(columns=geometry.name), preserve_index=False)
+    wkb = pa.array(geometry.to_wkb(), type=pa.binary())
+    return table.append_column(pa.field(geometry.name, GeometryType(crs)), with_geometry(wkb, crs))
+
+
+def to_geopandas(data):
+    """
+    Convert arrow data with a WKB column to a GeoDataFrame
+    """
+    import geopandas
+
+    df = data.to_pandas()
+    for field, column in zip(data.schema, data.columns):
+        if getattr(field.type, 'extension_name', None) == GEOMETRY_EXTENSION:
+            chunks = column.chunks if isinstance(column, pa.ChunkedArray) else [column]
+            wkb = [g for c in chunks for g in c.storage.to_pylist()]
+            crs = getattr(field.type, 'crs', None)
+            df[field.name] = geopandas.GeoSeries.from_wkb(wkb, crs=crs)
+            return geopandas.GeoDataFrame(df, geometry=field.name, crs=crs)
+    raise ValueError('no geometry column found')
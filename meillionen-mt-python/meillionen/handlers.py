@@ -7,9 +7,11 @@ from typing import List, Dict, Any, Union
 import xarray as xr
 
 from landlab.io import read_esri_ascii, write_esri_ascii
+from meillionen import outputs, strictness
 from meillionen.interface.bytesio import Resource, Schema
 import meillionen.interface.Schema as schema
-from meillionen.meillionen import DataFrameSchema, TensorSchema, FileResource, FeatherResource, NetCDFResource, ParquetResource
+from meillionen.meillionen import DataFrameSchema, TensorSchema, FileResource, FeatherResource, NetCDFResource, \
+    MultiNetCDFResource, ParquetResource
 
 
 def _serialize(handler, builder: flatbuffers.Builder, name):
@@ -74,12 +76,13 @@ class PandasHandler(DataFrameResourceBase):
     }
 
     @classmethod
-    def from_kwargs(cls, description, columns, resources=None):
+    def from_kwargs(cls, description, columns, resources=None, rules=None):
         cls._normalize_fields(columns['fields'])
         schema = DataFrameSchema.from_dict({
             'resources': cls.RESOURCE_TYPES if not resources else resources,
             'description': description,
-            'columns': columns
+            'columns': columns,
+            'rules': rules or []
         })
         return cls(schema=schema)
 
@@ -113,34 +116,69 @@ class PandasHandler(DataFrameResourceBase):
         """
         path = resource.to_dict()['path']
         _mkdir_p(path)
+        data = outputs.rename(outputs.select(data))
         getattr(data, self.PANDAS_SAVERS[resource.name])(path)
 
 
-def _netcdf_from_kwargs(cls, description, data_type, dimensions):
+def _netcdf_from_kwargs(cls, description, data_type, dimensions, attributes=None):
     validator = TensorSchema.from_dict({
         'resources': cls.RESOURCE_TYPES,
         'description': description,
         'dimensions': dimensions,
         'data_type': data_type,
+        'attributes': attributes if attributes else {},
     })
     return cls(validator)
 
 
+def _netcdf_compression_kwargs(sink, shape):
+    compression = sink.to_dict().get('compression')
+    if not compression:
+        return {}
+    kwargs = {'shuffle': compression.get('shuffle', False)}
+    level = compression.get('deflate_level')
+    if level is not None:
+        kwargs['zlib'] = True
+        kwargs['complevel'] = level
+    chunk_shape = sink.chunk_shape(list(shape))
+    if chunk_shape is not None:
+        kwargs['chunksizes'] = chunk_shape
+    return kwargs
+
+
 def _netcdf_create_variable(schema, sink, dimensions):
+    sink_resource = sink
     sink = sink.to_dict()
-    dimnames = schema.to_dict()['dimensions']
+    schema_dict = schema.to_dict()
+    dimnames = schema_dict['dimensions']
+    attributes = dict(schema_dict.get('attributes', {}))
+    for issue in schema.check_cf(sink['variable']):
+        strictness.warn('{severity} {variable}: {message}'.format(**issue))
     _mkdir_p(sink['path'])
     dataset = netCDF4.Dataset(sink['path'], mode='w')
     for dim in dimnames:
         size = dimensions[dim]
-        print((dim, size))
         dataset.createDimension(dim, size)
-    variable = dataset.createVariable(sink['variable'], 'f4', dimnames)
+    # _FillValue can only be set when the variable is created
+    fill_value = attributes.pop('_FillValue', None)
+    compression = _netcdf_compression_kwargs(sink_resource, [dimensions[dim] for dim in dimnames])
+    variable = dataset.createVariable(
+        outputs.alias(sink['variable']), 'f4', dimnames, fill_value=fill_value, **compression)
+    variable.setncatts(attributes)
     return dataset, variable
 
 
+def _netcdf_load_multi(resource: MultiNetCDFResource) -> xr.DataArray:
+    options = resource.to_dict()
+    dataset = xr.open_mfdataset(
+        resource.paths(),
+        combine='nested',
+        concat_dim=options['dimension'])
+    return dataset[options['variable']]
+
+
 class NetCDFHandler:
-    RESOURCE_TYPES = [NetCDFResource.name]
+    RESOURCE_TYPES = [NetCDFResource.name, MultiNetCDFResource.name]
 
     NETCDF_SAVERS = {
         NetCDFResource.name: NetCDFResource
@@ -150,23 +188,28 @@ class NetCDFHandler:
         self.schema = schema
 
     @classmethod
-    def from_kwargs(cls, description, data_type, dimensions):
+    def from_kwargs(cls, description, data_type, dimensions, attributes=None):
         return _netcdf_from_kwargs(
             cls,
             description=description,
             data_type=data_type,
-            dimensions=dimensions)
+            dimensions=dimensions,
+            attributes=attributes)
 
     def serialize(self, builder: flatbuffers.Builder, name):
         return _serialize(self, builder, name)
 
     def load(self, resource):
+        if resource.name == MultiNetCDFResource.name:
+            return _netcdf_load_multi(resource)
         resource = resource.to_dict()
         ds = netCDF4.Dataset(resource['path'])
         return ds[resource['variable']]
 
     def save(self, resource, data: xr.DataArray):
         resource = resource.to_dict()
+        if not outputs.wants(resource['variable']):
+            return
         data = data.transpose(resource['dimensions'])
         ds, variable = _netcdf_create_variable(self.schema, sink=resource, dimensions=data.dims)
         variable[:] = data
@@ -183,12 +226,13 @@ class NetCDFSliceHandler:
         self.schema = schema
 
     @classmethod
-    def from_kwargs(cls, description, data_type, dimensions):
+    def from_kwargs(cls, description, data_type, dimensions, attributes=None):
         return _netcdf_from_kwargs(
             cls,
             description=description,
             data_type=data_type,
-            dimensions=dimensions)
+            dimensions=dimensions,
+            attributes=attributes)
 
     def serialize(self, builder: flatbuffers.Builder, name):
         return _serialize(self, builder, name)
@@ -197,6 +241,8 @@ class NetCDFSliceHandler:
         return NetCDFSliceLoader(source=resource)
 
     def save(self, resource, dimensions):
+        if not outputs.wants(resource.to_dict()['variable']):
+            return NetCDFSliceDiscarder()
         return NetCDFSliceSaver(schema=self.schema, sink=resource, dimensions=dimensions)
 
 
@@ -247,6 +293,18 @@ class NetCDFSliceSaver:
         self._close()
 
 
+class NetCDFSliceDiscarder:
+    """Stands in for a NetCDFSliceSaver of a variable the trial did not ask for"""
+    def set(self, slices: Dict[str, Union[int, slice]], array: xr.DataArray):
+        pass
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exc_type, exc_val, exc_tb):
+        pass
+
+
 class LandLabGridHandler:
     """
     A loader to load raster files into landlab grid objects
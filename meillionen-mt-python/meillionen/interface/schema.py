@@ -1,14 +1,17 @@
 import functools
 import io
+import itertools
 import json
+import logging
 import pathlib
-from typing import Union, Dict, List, Any
+from typing import Union, Dict, List, Any, Iterator, Optional
 
 import flatbuffers
 import netCDF4
 import numpy as np
 import pandas as pd
 import pyarrow as pa
+import pyarrow.parquet as pq
 import xarray as xr
 from landlab.io import read_esri_ascii, write_esri_ascii
 
@@ -16,6 +19,8 @@ from . import _Schema as s
 from .base import field_to_bytesio
 from .resource import get_resource_class, Feather, Parquet, NetCDF, OtherFile
 
+logger = logging.getLogger(__name__)
+
 
 def _mkdir_p(path):
     p = pathlib.Path(path)
@@ -143,9 +148,43 @@ class Schema:
         return cls(name=name, schema=payload, resource_classes=resource_classes)
 
 
+def iter_record_batches(data: pd.DataFrame, chunk_rows: int) -> Iterator[pa.RecordBatch]:
+    """
+    Convert a dataframe to arrow one block of rows at a time
+
+    Only one block is held as arrow data at once. Every block is given the schema
+    of the first so that columns whose type pandas infers (like all null object
+    columns) stay the same across blocks.
+    """
+    schema = None
+    for start in range(0, len(data), chunk_rows):
+        block = data.iloc[start:start + chunk_rows]
+        batch = pa.RecordBatch.from_pandas(block, schema=schema, preserve_index=False)
+        schema = batch.schema
+        yield batch
+
+
+def _write_chunked(open_writer, path, batches: Iterator[pa.RecordBatch], write):
+    first = next(batches)
+    _mkdir_p(path)
+    with open_writer(path, first.schema) as writer:
+        for batch in itertools.chain([first], batches):
+            write(writer, batch)
+
+
 class PandasHandler:
-    def __init__(self, name: str, s: pa.Schema):
+    """
+    Loads and saves dataframes as feather or parquet
+
+    :param chunk_rows: Frames longer than this are converted and written in blocks
+      of this many rows so large frames are never converted to arrow all at once
+    """
+    def __init__(self, name: str, s: pa.Schema, chunk_rows: Optional[int] = None):
         self.schema = Schema(name=name, schema=DataFrameSchema(s), resource_classes=[Feather, Parquet])
+        self.chunk_rows = chunk_rows
+
+    def _chunked(self, data: pd.DataFrame):
+        return self.chunk_rows is not None and len(data) > self.chunk_rows
 
     @property
     def name(self):
@@ -172,11 +211,23 @@ class PandasHandler:
 
     @save.register
     def _save(self, resource: Feather, data: pd.DataFrame):
-        return data.to_feather(resource.path)
+        if not self._chunked(data):
+            return data.to_feather(resource.path)
+        _write_chunked(
+            pa.ipc.new_file,
+            resource.path,
+            iter_record_batches(data, self.chunk_rows),
+            lambda w, b: w.write_batch(b))
 
     @save.register
     def _save(self, resource: Parquet, data: pd.DataFrame):
-        return data.to_parquet(resource.path)
+        if not self._chunked(data):
+            return data.to_parquet(resource.path)
+        _write_chunked(
+            pq.ParquetWriter,
+            resource.path,
+            iter_record_batches(data, self.chunk_rows),
+            lambda w, b: w.write_table(pa.Table.from_batches([b])))
 
 
 class NetCDFHandler:
@@ -225,7 +276,7 @@ def _netcdf_create_variable(schema, sink, dimensions):
     dataset = netCDF4.Dataset(sink.path, mode='w')
     for dim in dimnames:
         size = dimensions[dim]
-        print((dim, size))
+        logger.debug('creating dimension %s of size %s', dim, size)
         dataset.createDimension(dim, size)
     variable = dataset.createVariable(sink.variable, 'f4', dimnames)
     return dataset, variable
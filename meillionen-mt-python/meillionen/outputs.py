@@ -0,0 +1,71 @@
+"""
+The output variables a trial needs and the names they are written under
+
+``meillionen run`` passes the ``outputs`` of a trial to the model program in
+``MEILLIONEN_OUTPUTS``. Writers leave out every other variable.
+
+The ``aliases`` of a trial, such as CSDMS standard names for a model's own,
+are passed in ``MEILLIONEN_ALIASES``. Writers save each variable under its
+alias so pandas, xarray and NetCDF readers all see the same names.
+"""
+import os
+from typing import Dict, Optional, Set
+
+OUTPUTS_ENV = 'MEILLIONEN_OUTPUTS'
+ALIASES_ENV = 'MEILLIONEN_ALIASES'
+
+
+def selected() -> Optional[Set[str]]:
+    """The variables asked for, or None if every variable is wanted"""
+    value = os.environ.get(OUTPUTS_ENV, '').strip()
+    if not value:
+        return None
+    return {v.strip() for v in value.split(',') if v.strip()}
+
+
+def wants(variable: str) -> bool:
+    variables = selected()
+    return variables is None or variable in variables
+
+
+def select(df):
+    """
+    A dataframe with only the floating point columns asked for
+
+    Other columns, such as the day or a cell id, index the values and are kept.
+    """
+    variables = selected()
+    if variables is None:
+        return df
+    keep = [c for c in df.columns if df[c].dtype.kind != 'f' or c in variables]
+    return df[keep]
+
+
+def aliases() -> Dict[str, str]:
+    """The alias of each internal variable name the model program was given"""
+    pairs = (pair.split('=', 1) for pair in os.environ.get(ALIASES_ENV, '').split(',') if '=' in pair)
+    return {internal.strip(): alias.strip() for internal, alias in pairs if internal.strip() and alias.strip()}
+
+
+def alias(variable: str) -> str:
+    """The name a variable is written under, its own if it has no alias"""
+    return aliases().get(variable, variable)
+
+
+def rename(data):
+    """
+    A dataframe, xarray dataset or data array with its variables renamed to
+    their aliases
+
+    Dimensions and index columns are renamed too.
+    """
+    names = aliases()
+    if not names:
+        return data
+    if hasattr(data, 'columns'):
+        return data.rename(columns=names)
+    if hasattr(data, 'data_vars'):
+        return data.rename({k: v for k, v in names.items() if k in data.variables or k in data.dims})
+    if getattr(data, 'name', None) in names:
+        return data.rename(names[data.name])
+    return data
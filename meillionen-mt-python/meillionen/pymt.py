@@ -1,4 +1,99 @@
+from typing import Any, Dict, List, Mapping, Optional, Tuple
+
+import numpy as np
+
 from meillionen.client import ClientFunctionModel
+from meillionen.exceptions import DimensionMismatchError, SourcesError, VariableMismatchError
+from meillionen.meillionen import negotiate_variable
+
+
+class VariableRequest:
+    """
+    What a model wants for a variable it is given, or what a caller wants for one it gets
+
+    Fields left as ``None`` accept anything. Dimension sizes of ``None`` accept any size.
+    """
+    def __init__(self, standard_name: Optional[str] = None, units: Optional[str] = None,
+                 dims: Optional[List[Tuple[str, Optional[int]]]] = None):
+        self.standard_name = standard_name
+        self.units = units
+        self.dims = dims
+
+    def to_dict(self) -> Dict[str, Any]:
+        request = {}
+        if self.standard_name is not None:
+            request['standard_name'] = self.standard_name
+        if self.units is not None:
+            request['units'] = self.units
+        if self.dims is not None:
+            request['dimensions'] = list(self.dims)
+        return request
+
+    def __repr__(self):
+        return f'VariableRequest(standard_name={self.standard_name!r}, units={self.units!r}, dims={self.dims!r})'
+
+
+def _response(value) -> Optional[Dict[str, Any]]:
+    """What a labelled array is, or ``None`` for values that don't say"""
+    dims = getattr(value, 'dims', None)
+    sizes = getattr(value, 'sizes', None)
+    if dims is None or sizes is None:
+        return None
+    attrs = getattr(value, 'attrs', {})
+    response = {'dimensions': [(dim, sizes[dim]) for dim in dims]}
+    for key in ('standard_name', 'units'):
+        if key in attrs:
+            response[key] = attrs[key]
+    return response
+
+
+def negotiate(name: str, request: VariableRequest, value):
+    """
+    Check a value against a request, converting it to the requested units
+
+    :raises DimensionMismatchError: if the dimensions don't fit
+    :raises VariableMismatchError: if the standard name differs or the units can't be converted
+    """
+    response = _response(value)
+    if response is None:
+        return value
+    converters, mismatch = negotiate_variable(name, request.to_dict(), response)
+    if mismatch is not None:
+        kind, message, suggestions = mismatch
+        if kind == 'dimensions':
+            raise DimensionMismatchError(message, suggestions)
+        raise VariableMismatchError(message, suggestions)
+    for converter in converters:
+        attrs = dict(value.attrs, units=converter['to'])
+        value = value * converter['scale'] + converter['offset']
+        value.attrs = attrs
+    return value
+
+
+def _grid(value) -> Optional[Tuple[Tuple[str, int], ...]]:
+    """The dimensions and sizes of a labelled array, ``None`` for values that don't say"""
+    dims = getattr(value, 'dims', None)
+    sizes = getattr(value, 'sizes', None)
+    if dims is None or sizes is None:
+        return None
+    return tuple((dim, sizes[dim]) for dim in dims)
+
+
+def _coordinate(value, dim: str) -> np.ndarray:
+    """The coordinates of a dimension, cell indices if it has none"""
+    coords = getattr(value, 'coords', {})
+    if dim in coords:
+        return np.asarray(coords[dim].values, dtype=float)
+    return np.arange(value.sizes[dim], dtype=float)
+
+
+def _fill(values, out: Optional[np.ndarray]) -> np.ndarray:
+    """Copy values into ``out`` like BMI does, or return them if not given"""
+    values = np.asarray(values)
+    if out is None:
+        return values
+    out[:] = values
+    return out
 
 
 class PyMTFunctionModel:
@@ -7,6 +102,9 @@ class PyMTFunctionModel:
         self.sinks = {}
         self.model: ClientFunctionModel = None
         self.partition = None
+        self.grid = {}
+        self.publisher = None
+        self.published_sinks = []
 
     def initialize(self, model):
         self.model = model
@@ -21,6 +119,16 @@ class PyMTFunctionModel:
     def get_input_var_type(self, name):
         return self.model.source_schema(name)
 
+    def get_input_var_request(self, name) -> VariableRequest:
+        """What the model wants for a source, checked by set_value"""
+        schema = self.get_input_var_type(name).to_dict()
+        attributes = schema.get('attributes') or {}
+        expected = schema.get('dimensions')
+        return VariableRequest(
+            standard_name=attributes.get('standard_name'),
+            units=attributes.get('units'),
+            dims=None if expected is None else [(dim, self.grid.get(dim)) for dim in expected])
+
     def get_output_var_names(self):
         return self.model.sink_names
 
@@ -30,14 +138,167 @@ class PyMTFunctionModel:
     def set_partition(self, partition):
         self.partition = partition
 
+    def set_grid(self, **sizes):
+        """Set the size of the model's dimensions, checked by set_value"""
+        self.grid.update(sizes)
+
     def set_value(self, source_name, source):
-        self.sources[source_name] = source
+        """
+        Give the model a source, converted to the units the model requests
+
+        Labelled arrays are checked against the source's request so a mismatch
+        is raised here rather than when the model runs.
+        """
+        self.sources[source_name] = negotiate(source_name, self.get_input_var_request(source_name), source)
 
-    def get_value(self, sink_name):
-        return self.sinks[sink_name]
+    def set_values(self, sources: Mapping[str, Any]):
+        """
+        Give the model several sources at once, such as numpy or xarray arrays
+        keyed by source name
+
+        Every source is checked before any is bound, so either all are set or
+        none are.
+
+        :raises SourcesError: with the problem of each source that did not fit,
+            including names the model has no source for
+        """
+        known = set(self.get_input_var_names())
+        negotiated = {}
+        errors = {}
+        for name, source in sources.items():
+            if name not in known:
+                errors[name] = KeyError(f'no source named {name}')
+                continue
+            try:
+                negotiated[name] = negotiate(name, self.get_input_var_request(name), source)
+            except VariableMismatchError as e:
+                errors[name] = e
+        if errors:
+            raise SourcesError(errors)
+        self.sources.update(negotiated)
+
+    def _grids(self) -> List[Tuple[str, Tuple[Tuple[str, int], ...]]]:
+        """Each distinct grid of the bound variables with the first one on it"""
+        grids = []
+        for name, value in {**self.sources, **self.sinks}.items():
+            grid = _grid(value)
+            if grid is not None and all(grid != g for _, g in grids):
+                grids.append((name, grid))
+        return grids
+
+    def _grid_value(self, grid: int):
+        grids = self._grids()
+        if not 0 <= grid < len(grids):
+            raise KeyError(f'no grid {grid}, there are {len(grids)}')
+        name, _ = grids[grid]
+        return self.sources[name] if name in self.sources else self.sinks[name]
+
+    def get_var_grid(self, name) -> int:
+        """
+        The grid a bound variable is on
+
+        Variables share a grid if they have the same dimensions and sizes.
+        Grids are numbered in the order variables were bound, sources before sinks.
+        """
+        value = self.sources[name] if name in self.sources else self.sinks.get(name)
+        grid = _grid(value)
+        if grid is None:
+            raise KeyError(f'{name} is not bound to a labelled array')
+        return [g for _, g in self._grids()].index(grid)
+
+    def get_grid_type(self, grid: int) -> str:
+        """``scalar``, ``uniform_rectilinear`` if the coordinates are evenly spaced, else ``rectilinear``"""
+        value = self._grid_value(grid)
+        if len(value.dims) == 0:
+            return 'scalar'
+        for dim in value.dims:
+            spacing = np.diff(_coordinate(value, dim))
+            if len(spacing) > 0 and not np.allclose(spacing, spacing[0]):
+                return 'rectilinear'
+        return 'uniform_rectilinear'
+
+    def get_grid_rank(self, grid: int) -> int:
+        return len(self._grid_value(grid).dims)
+
+    def get_grid_size(self, grid: int) -> int:
+        return int(np.prod(self.get_grid_shape(grid)))
+
+    def get_grid_shape(self, grid: int, shape: Optional[np.ndarray] = None) -> np.ndarray:
+        """The number of cells along each dimension, slowest varying first"""
+        value = self._grid_value(grid)
+        return _fill([value.sizes[dim] for dim in value.dims], shape)
+
+    def get_grid_spacing(self, grid: int, spacing: Optional[np.ndarray] = None) -> np.ndarray:
+        """
+        The distance between cells along each dimension of a uniform grid
+
+        :raises ValueError: if the grid is not uniform
+        """
+        if self.get_grid_type(grid) == 'rectilinear':
+            raise ValueError(f'grid {grid} is not uniform so has no single spacing')
+        value = self._grid_value(grid)
+        spacings = []
+        for dim in value.dims:
+            coords = _coordinate(value, dim)
+            spacings.append(coords[1] - coords[0] if len(coords) > 1 else 1.0)
+        return _fill(np.asarray(spacings, dtype=float), spacing)
+
+    def get_grid_origin(self, grid: int, origin: Optional[np.ndarray] = None) -> np.ndarray:
+        """The coordinates of the first cell along each dimension"""
+        value = self._grid_value(grid)
+        return _fill(np.asarray([_coordinate(value, dim)[0] for dim in value.dims], dtype=float), origin)
+
+    def _grid_coordinate(self, grid: int, axis: int, out: Optional[np.ndarray]) -> np.ndarray:
+        value = self._grid_value(grid)
+        if axis >= len(value.dims):
+            raise ValueError(f'grid {grid} has rank {len(value.dims)}')
+        return _fill(_coordinate(value, value.dims[-1 - axis]), out)
+
+    def get_grid_x(self, grid: int, x: Optional[np.ndarray] = None) -> np.ndarray:
+        """The cell coordinates of the last, fastest varying, dimension"""
+        return self._grid_coordinate(grid, 0, x)
+
+    def get_grid_y(self, grid: int, y: Optional[np.ndarray] = None) -> np.ndarray:
+        """The cell coordinates of the second last dimension"""
+        return self._grid_coordinate(grid, 1, y)
+
+    def get_grid_z(self, grid: int, z: Optional[np.ndarray] = None) -> np.ndarray:
+        """The cell coordinates of the third last dimension"""
+        return self._grid_coordinate(grid, 2, z)
+
+    def get_value(self, sink_name, request: Optional[VariableRequest] = None):
+        """A sink the model wrote, checked against and converted to ``request`` if one is given"""
+        sink = self.sinks[sink_name]
+        if request is None:
+            return sink
+        return negotiate(sink_name, request, sink)
+
+    def set_publisher(self, publisher, sinks: List[str]):
+        """
+        Publish the rows of ``sinks`` with ``publisher``, a ``StepPublisher``, after every update
+
+        Pass ``None`` to stop publishing.
+        """
+        self.publisher = publisher
+        self.published_sinks = list(sinks) if publisher is not None else []
+
+    def _publish(self):
+        if self.publisher is None:
+            return
+        import pyarrow as pa
+        for name in self.published_sinks:
+            df = self.sinks[name]
+            if any(n is not None for n in df.index.names):
+                df = df.reset_index()
+            self.publisher.publish(pa.RecordBatch.from_pandas(df, preserve_index=False))
 
     def update(self):
         self.sinks = self.model.run(sources=self.sources, partition=self.partition)
+        self._publish()
+
+    async def update_async(self):
+        self.sinks = await self.model.run_async(sources=self.sources, partition=self.partition)
+        self._publish()
 
     def finalize(self):
-        self.sinks = {}
\ No newline at end of file
+        self.sinks = {}
@@ -1,5 +1,5 @@
 from .interface.bytesio import Resource, Schema
-from meillionen.meillionen import FileResource, FeatherResource, NetCDFResource, ParquetResource, \
+from meillionen.meillionen import FileResource, FeatherResource, NetCDFResource, MultiNetCDFResource, ParquetResource, \
     DataFrameSchema, TensorSchema, Schemaless
 from typing import List
 
@@ -43,6 +43,7 @@ RESOURCES = ResourceRegistry([
     FileResource,
     FeatherResource,
     NetCDFResource,
+    MultiNetCDFResource,
     ParquetResource
 ])
 
@@ -10,6 +10,7 @@ from meillionen.meillionen import \
     FeatherResource as _FeatherResource, \
     FileResource as _FileResource, \
     NetCDFResource as _NetCDFResource, \
+    MultiNetCDFResource as _MultiNetCDFResource, \
     ParquetResource as _ParquetResource, \
     DataFrameSchema, \
     TensorSchema, \
@@ -54,12 +55,15 @@ class FileResource(BasePathResource):
 class NetCDFResource(BasePathResource):
     resource_class = _NetCDFResource
 
-    def __init__(self, base_path: Optional[str] = None, name: Optional[str] = None):
+    def __init__(self, base_path: Optional[str] = None, name: Optional[str] = None,
+                 compression: Optional[dict] = None):
         super().__init__('.nc', base_path=base_path, name=name)
+        self._compression = compression
 
     def build(self, settings, name: str, partition=None):
         kwargs = self.build_kwargs(settings=settings, partition=partition, name=name)
         kwargs['variable'] = name
+        kwargs['compression'] = self._compression
         return  self.resource_class(**kwargs)
 
 
@@ -103,6 +107,7 @@ DEFAULT_RESOURCE_DESERIALIZER = resource_deserializer({
         _FileResource,
         _FeatherResource,
         _NetCDFResource,
+        _MultiNetCDFResource,
         _ParquetResource,
     ]
 })
@@ -111,7 +116,7 @@ DEFAULT_RESOURCE_DESERIALIZER = resource_deserializer({
 RESOURCES = {
     r.name: r
     for r in
-    [_FileResource, _FeatherResource, _NetCDFResource, _ParquetResource]
+    [_FileResource, _FeatherResource, _NetCDFResource, _MultiNetCDFResource, _ParquetResource]
 }
 
 DATAFRAME_VALIDATOR = DataFrameSchema.name
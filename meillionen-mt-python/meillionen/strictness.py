@@ -0,0 +1,34 @@
+import warnings
+from typing import Callable, List
+
+from meillionen.exceptions import StrictModeError
+from meillionen.meillionen import is_strict, set_strict as _set_native_strict
+
+_native_hooks: List[Callable[[bool], None]] = [_set_native_strict]
+
+
+def register_native(hook: Callable[[bool], None]):
+    """
+    Have ``set_strict`` also reach the native code of a model wrapper, which
+    keeps its own setting
+    """
+    _native_hooks.append(hook)
+
+
+def set_strict(strict: bool = True):
+    """
+    Promote validation warnings, such as filled gaps, skipped lines or
+    variables that do not follow the CF conventions, to errors
+    """
+    for hook in _native_hooks:
+        hook(strict)
+
+
+def warn(message: str):
+    """
+    Warn about something a reader or check worked around, or raise
+    ``StrictModeError`` in strict mode
+    """
+    if is_strict():
+        raise StrictModeError(message)
+    warnings.warn(message, stacklevel=2)
@@ -0,0 +1,50 @@
+"""
+Changes made to the values of sources before a model reads them
+
+``meillionen run`` passes the ``transforms`` of a trial's sources to the model
+program in ``MEILLIONEN_TRANSFORMS`` as JSON keyed by source name. Unit
+conversions are already folded into ``scale`` and ``offset`` so applying one
+is ``value * scale + offset`` clamped to ``min`` and ``max``, to the listed
+``variables`` or every floating point one.
+"""
+import json
+import os
+from typing import Any, Dict
+
+TRANSFORMS_ENV = 'MEILLIONEN_TRANSFORMS'
+
+
+def transforms() -> Dict[str, Dict[str, Any]]:
+    """The transform of each source the model program was given"""
+    value = os.environ.get(TRANSFORMS_ENV, '').strip()
+    return json.loads(value) if value else {}
+
+
+def _apply(values, transform: Dict[str, Any]):
+    values = values * transform.get('scale', 1.0) + transform.get('offset', 0.0)
+    if 'min' in transform or 'max' in transform:
+        values = values.clip(transform.get('min'), transform.get('max'))
+    return values
+
+
+def apply(source_name: str, data):
+    """
+    A dataframe, xarray dataset or data array with the transform of a source
+    applied, unchanged if the source has none
+    """
+    transform = transforms().get(source_name)
+    if transform is None:
+        return data
+    variables = transform.get('variables') or []
+    if hasattr(data, 'columns'):
+        data = data.copy()
+        for column in data.columns:
+            if (column in variables) if variables else data[column].dtype.kind == 'f':
+                data[column] = _apply(data[column], transform)
+        return data
+    if hasattr(data, 'data_vars'):
+        names = variables or [k for k, v in data.data_vars.items() if v.dtype.kind == 'f']
+        return data.assign({k: _apply(data[k], transform).assign_attrs(data[k].attrs) for k in names if k in data})
+    if not variables or getattr(data, 'name', None) in variables:
+        return _apply(data, transform)
+    return data
@@ -0,0 +1,65 @@
+import json
+
+import pandas as pd
+import pyarrow as pa
+
+UNCERTAINTY_EXTENSION = 'meillionen.uncertainty'
+
+CHILDREN = {
+    'standard_deviation': ['value', 'sd'],
+    'range': ['min', 'mean', 'max'],
+}
+
+
+class UncertaintyType(pa.ExtensionType):
+    """
+    A struct column of a value and its uncertainty
+
+    ``kind`` is "standard_deviation" for value and sd children, such as
+    observations, or "range" for min, mean and max children, such as ensemble
+    summaries.
+    """
+    def __init__(self, kind: str):
+        self.kind = kind
+        storage = pa.struct([pa.field(c, pa.float64()) for c in CHILDREN[kind]])
+        super().__init__(storage, UNCERTAINTY_EXTENSION)
+
+    def __arrow_ext_serialize__(self):
+        return json.dumps({'kind': self.kind}).encode('utf-8')
+
+    @classmethod
+    def __arrow_ext_deserialize__(cls, storage_type, serialized):
+        return cls(json.loads(serialized.decode('utf-8'))['kind'])
+
+
+pa.register_extension_type(UncertaintyType('standard_deviation'))
+
+
+def with_uncertainty(array: pa.StructArray, kind: str) -> pa.ExtensionArray:
+    """
+    Mark a struct array as a value and its uncertainty
+    """
+    return pa.ExtensionArray.from_storage(UncertaintyType(kind), array)
+
+
+def to_pandas(data) -> pd.DataFrame:
+    """
+    Convert arrow data to a dataframe, splitting each uncertainty column into
+    a float column per child
+
+    A "yield" range column becomes "yield_min", "yield_mean" and "yield_max".
+    A standard deviation column keeps its name for the value and adds "_sd".
+    """
+    df = data.to_pandas()
+    for field, column in zip(data.schema, data.columns):
+        if not isinstance(field.type, UncertaintyType):
+            continue
+        chunks = column.chunks if isinstance(column, pa.ChunkedArray) else [column]
+        storage = pa.chunked_array([c.storage for c in chunks], type=field.type.storage_type)
+        position = df.columns.get_loc(field.name)
+        df = df.drop(columns=field.name)
+        for i, child in enumerate(CHILDREN[field.type.kind]):
+            name = field.name if child == 'value' else f'{field.name}_{child}'
+            values = pa.chunked_array([c.field(i) for c in storage.chunks], type=pa.float64())
+            df.insert(position + i, name, values.to_pandas().to_numpy())
+    return df
@@ -0,0 +1,65 @@
+import json
+from typing import Union
+
+import numpy as np
+import pandas as pd
+import pyarrow as pa
+
+UNITS_EXTENSION = 'meillionen.units'
+
+
+class UnitsType(pa.ExtensionType):
+    """
+    A float column whose values are in a unit pint can parse
+
+    ``Table.to_pandas`` reads these columns as plain floats, use ``to_pandas``
+    below to get pint-pandas columns whose units survive pandas operations.
+    """
+    def __init__(self, unit: str, storage_type: pa.DataType = pa.float64()):
+        self.unit = unit
+        super().__init__(storage_type, UNITS_EXTENSION)
+
+    def __arrow_ext_serialize__(self):
+        return json.dumps({'unit': self.unit}).encode('utf-8')
+
+    @classmethod
+    def __arrow_ext_deserialize__(cls, storage_type, serialized):
+        return cls(json.loads(serialized.decode('utf-8'))['unit'], storage_type)
+
+    def to_pandas_dtype(self):
+        return self.storage_type.to_pandas_dtype()
+
+
+pa.register_extension_type(UnitsType('dimensionless'))
+
+
+def with_units(array: pa.Array, unit: str) -> pa.ExtensionArray:
+    """
+    Mark a float array as holding values in a unit
+    """
+    return pa.ExtensionArray.from_storage(UnitsType(unit, array.type), array)
+
+
+def from_pint(series: pd.Series) -> pa.ExtensionArray:
+    """
+    Convert a pint-pandas series to a units column
+    """
+    magnitude = np.asarray(series.pint.magnitude, dtype='float64')
+    return with_units(pa.array(magnitude), str(series.pint.units))
+
+
+def to_pandas(data: Union[pa.Table, pa.RecordBatch]) -> pd.DataFrame:
+    """
+    Convert arrow data to a dataframe with a pint-pandas column for each units column
+    """
+    import pint_pandas
+
+    df = data.to_pandas()
+    for field, column in zip(data.schema, data.columns):
+        if isinstance(field.type, UnitsType):
+            chunks = column.chunks if isinstance(column, pa.ChunkedArray) else [column]
+            magnitude = np.concatenate(
+                [np.asarray(c.storage.to_numpy(zero_copy_only=False), dtype='float64') for c in chunks]
+                or [np.empty(0)])
+            df[field.name] = pint_pandas.PintArray(magnitude, dtype=f'pint[{field.type.unit}]')
+    return df
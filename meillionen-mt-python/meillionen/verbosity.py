@@ -0,0 +1,36 @@
+import logging
+from typing import Callable, List
+
+from meillionen.meillionen import set_verbosity as _set_native_verbosity
+
+LEVELS = {
+    'quiet': logging.ERROR,
+    'normal': logging.INFO,
+    'verbose': logging.DEBUG,
+    'debug': logging.DEBUG,
+}
+
+logger = logging.getLogger('meillionen')
+
+_native_hooks: List[Callable[[str], None]] = [_set_native_verbosity]
+
+
+def register_native(hook: Callable[[str], None]):
+    """
+    Have ``set_verbosity`` also reach the native code of a model wrapper, which
+    keeps its own console settings
+    """
+    _native_hooks.append(hook)
+
+
+def set_verbosity(level: str):
+    """
+    Set how much meillionen and registered model wrappers print
+
+    :param level: quiet, normal, verbose or debug
+    """
+    if level not in LEVELS:
+        raise ValueError(f'verbosity must be one of {", ".join(LEVELS)} but is {level}')
+    logger.setLevel(LEVELS[level])
+    for hook in _native_hooks:
+        hook(level)
@@ -25,10 +25,10 @@ fn to_pyarrow_array(array: &ArrayRef, py: Python) -> PyResult<PyObject> {
     Ok(array.to_object(py))
 }
 
-fn to_pyarrow_recordbatch<'a>(
+fn to_pyarrow_recordbatch(
     batch: &RecordBatch,
     py: Python,
-    pyarrow: &'a PyModule,
+    pyarrow: &PyModule,
 ) -> Result<PyObject, PyErr> {
     let mut py_arrays = vec![];
     let mut py_names = vec![];
@@ -72,7 +72,7 @@ where
 
 #[pyfunction]
 fn from_parquet(path: &str) -> PyResult<PyObject> {
-    let file = File::open(&Path::new(path)).map_err(ioerror)?;
+    let file = File::open(Path::new(path)).map_err(ioerror)?;
     let file_reader = SerializedFileReader::new(file).map_err(ioerror)?;
     let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(file_reader));
     let mut record_batch_reader = arrow_reader.get_record_reader(2048).map_err(ioerror)?;
@@ -80,8 +80,7 @@ fn from_parquet(path: &str) -> PyResult<PyObject> {
     while let Some(Ok(record_batch)) = record_batch_reader.next() {
         batches.push(record_batch);
     }
-    let table = to_pyarrow_table(&batches);
-    table
+    to_pyarrow_table(&batches)
 }
 
 pub fn init(m: &PyModule) -> PyResult<()> {
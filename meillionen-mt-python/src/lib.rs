@@ -1,21 +1,42 @@
 mod array;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use indoc::formatdoc;
 use libc::uintptr_t;
 use pyo3::exceptions::{PyIOError, PyValueError, PyIndexError};
 use pyo3::prelude::*;
-use std;
 
 use pythonize::{depythonize, pythonize};
 use serde::{Deserialize, Serialize};
 
+use meillionen_mt::archive;
 use meillionen_mt::arg::resource;
 use meillionen_mt::arg::schema;
+use meillionen_mt::calibration;
+use meillionen_mt::clock;
+use meillionen_mt::conservation;
+use meillionen_mt::design;
+use meillionen_mt::determinism;
+use meillionen_mt::experiment;
+use meillionen_mt::extension_columns;
+use meillionen_mt::interpolation;
 use meillionen_mt::model;
-use arrow::array::{ArrayRef, make_array_from_raw, Array};
+use meillionen_mt::plot;
+use meillionen_mt::report;
+use meillionen_mt::risk;
+use meillionen_mt::sampling;
+use meillionen_mt::sql;
+use meillionen_mt::stack;
+use meillionen_mt::stream;
+use meillionen_mt::timeseries;
+use meillionen_mt::trace;
+use meillionen_mt::strictness;
+use meillionen_mt::variable::negotiate;
+use meillionen_mt::verbosity;
+use arrow::array::{ArrayData, ArrayRef, make_array, make_array_from_raw, Array};
 use arrow::record_batch::RecordBatch;
-use arrow::datatypes::{Field, Schema};
+use arrow::datatypes::{DataType, Field, Schema};
 use std::convert::{TryInto, TryFrom};
 use arrow::ffi;
 use pyo3::types::PyDict;
@@ -42,6 +63,26 @@ where
 }
 
 fn to_py_array(array: &ArrayRef, py: Python, pa: &PyModule) -> PyResult<PyObject> {
+    // the C interface of this arrow release has no dictionaries, so they
+    // cross as their keys and values
+    if let DataType::Dictionary(key_type, _) = array.data_type() {
+        let data = array.data();
+        let keys = make_array(ArrayData::new(
+            key_type.as_ref().clone(),
+            data.len(),
+            Some(data.null_count()),
+            data.null_buffer().cloned(),
+            data.offset(),
+            data.buffers().to_vec(),
+            vec![],
+        ));
+        let values = make_array(data.child_data()[0].clone());
+        let array = pa.getattr("DictionaryArray")?.call_method1(
+            "from_arrays",
+            (to_py_array(&keys, py, pa)?, to_py_array(&values, py, pa)?),
+        )?;
+        return Ok(array.to_object(py));
+    }
     let (array_ptr, schema_ptr) = array.to_raw().map_err(value_error)?;
     let array = pa.getattr("Array")?
         .call_method1(
@@ -52,7 +93,33 @@ fn to_py_array(array: &ArrayRef, py: Python, pa: &PyModule) -> PyResult<PyObject
 
 fn to_py_recordbatch(rb: &RecordBatch, py: Python, pa: &PyModule) -> PyResult<PyObject> {
     let schema = rb.schema();
-    let arrays = rb.columns().iter().map(|a| to_py_array(a, py, pa)).collect::<PyResult<Vec<PyObject>>>()?;
+    let mut arrays = rb.columns().iter().map(|a| to_py_array(a, py, pa)).collect::<PyResult<Vec<PyObject>>>()?;
+    // field metadata does not cross the C interface so extension columns are
+    // rebuilt on the python side
+    for (array, field) in arrays.iter_mut().zip(schema.fields()) {
+        if let Some(units) = extension_columns::UnitsMeta::from_field(field) {
+            *array = py
+                .import("meillionen.units")?
+                .call_method1("with_units", (array.clone_ref(py), units.unit))?
+                .to_object(py);
+        }
+        if let Some(geometry) = extension_columns::GeometryMeta::from_field(field) {
+            *array = py
+                .import("meillionen.geometry")?
+                .call_method1("with_geometry", (array.clone_ref(py), geometry.crs))?
+                .to_object(py);
+        }
+        if let Some(uncertainty) = extension_columns::UncertaintyMeta::from_field(field) {
+            let kind = match uncertainty {
+                extension_columns::UncertaintyMeta::StandardDeviation => "standard_deviation",
+                extension_columns::UncertaintyMeta::Range => "range",
+            };
+            *array = py
+                .import("meillionen.uncertainty")?
+                .call_method1("with_uncertainty", (array.clone_ref(py), kind))?
+                .to_object(py);
+        }
+    }
     let names = schema.fields().iter().map(|f| f.name().as_str()).collect::<Vec<&str>>();
     let record = pa
         .getattr("RecordBatch")?
@@ -61,6 +128,20 @@ fn to_py_recordbatch(rb: &RecordBatch, py: Python, pa: &PyModule) -> PyResult<Py
 }
 
 fn to_rust_array(obj: &PyAny) -> PyResult<ArrayRef> {
+    if obj.hasattr("indices")? && obj.hasattr("dictionary")? {
+        let keys = to_rust_array(obj.getattr("indices")?)?;
+        let values = to_rust_array(obj.getattr("dictionary")?)?;
+        let data = ArrayData::new(
+            DataType::Dictionary(Box::new(keys.data_type().clone()), Box::new(values.data_type().clone())),
+            keys.len(),
+            Some(keys.null_count()),
+            keys.data().null_buffer().cloned(),
+            keys.offset(),
+            keys.data().buffers().to_vec(),
+            vec![values.data().clone()],
+        );
+        return Ok(make_array(data));
+    }
     let (array_ptr, schema_ptr) = ffi::ArrowArray::into_raw(unsafe { ffi::ArrowArray::empty() });
     obj.call_method1(
         "_export_to_c",
@@ -75,13 +156,36 @@ fn to_rust_recordbatch(obj: &PyAny) -> PyResult<RecordBatch> {
     let names: Vec<String> = obj.getattr("schema")?.getattr("names")?.extract()?;
     let mut columns = vec![];
     let mut fields = vec![];
-    for (pycol, name) in pycolumns.iter()?.zip(names) {
+    let pyschema = obj.getattr("schema")?;
+    for (i, (pycol, name)) in pycolumns.iter()?.zip(names).enumerate() {
         let pycol = pycol?;
         let col = to_rust_array(pycol)?;
         let dt = col.data_type().clone();
         let nullable = col.null_count() > 0;
         columns.push(col);
-        fields.push(Field::new(name.as_str(), dt, nullable))
+        let field = Field::new(name.as_str(), dt, nullable);
+        let pytype = pyschema.call_method1("field", (i,))?.getattr("type")?;
+        let extension: Option<String> = pytype.getattr("extension_name").ok().map(|n| n.extract()).transpose()?;
+        match extension.as_deref() {
+            Some(extension_columns::UNITS_EXTENSION) => {
+                let unit: String = pytype.getattr("unit")?.extract()?;
+                fields.push(extension_columns::UnitsMeta::new(&unit).annotate(field));
+            }
+            Some(extension_columns::UNCERTAINTY_EXTENSION) => {
+                let kind: String = pytype.getattr("kind")?.extract()?;
+                let uncertainty = match kind.as_str() {
+                    "range" => extension_columns::UncertaintyMeta::Range,
+                    _ => extension_columns::UncertaintyMeta::StandardDeviation,
+                };
+                fields.push(uncertainty.annotate(field));
+            }
+            Some(extension_columns::GEOMETRY_EXTENSION) => {
+                let crs: Option<String> = pytype.getattr("crs").ok().map(|c| c.extract()).transpose()?;
+                let geometry = extension_columns::GeometryMeta { crs };
+                fields.push(geometry.annotate(field));
+            }
+            _ => fields.push(field),
+        }
     }
     let rb = RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap();
     Ok(rb)
@@ -151,7 +255,7 @@ macro_rules! impl_transformers {
         #[pymethods]
         impl $T {
             #[staticmethod]
-            fn transformers(_py: Python) -> &PyDict {
+            fn transformers(_py: Python<'_>) -> &PyDict {
                 PyDict::new(_py)
             }
         }
@@ -160,7 +264,7 @@ macro_rules! impl_transformers {
         #[pymethods]
         impl $T {
             #[staticmethod]
-            fn transformers(_py: Python) -> PyResult<&PyDict> {
+            fn transformers(_py: Python<'_>) -> PyResult<&PyDict> {
                 let dict = PyDict::new(_py);
                 $(dict.set_item(std::stringify!($prop), std::stringify!($transformer))
                     .map_err(value_error)?;);*
@@ -187,6 +291,19 @@ macro_rules! impl_name_prop {
     }
 }
 
+macro_rules! impl_repr_html {
+    ($T:ty) => {
+        #[pymethods]
+        impl $T {
+            /// A table of the settings, shown by notebooks
+            fn _repr_html_(&self) -> PyResult<String> {
+                let value = serde_json::to_value(&self.inner).map_err(value_error)?;
+                Ok(report::html_parameters(std::stringify!($T), &value))
+            }
+        }
+    }
+}
+
 #[pyclass]
 #[derive(Debug)]
 struct DataFrameSchema {
@@ -194,11 +311,27 @@ struct DataFrameSchema {
 }
 
 impl_to_from_dict!(DataFrameSchema);
+impl_repr_html!(DataFrameSchema);
 impl_from_arrow_array!(DataFrameSchema, schema::DataFrameSchema);
 impl_to_builder!(DataFrameSchema);
 impl_name_prop!(DataFrameSchema, "data_frame");
 impl_transformers!(DataFrameSchema);
 
+#[pymethods]
+impl DataFrameSchema {
+    /// Check a table against the validation rules of the schema
+    ///
+    /// :param pyrb: the table to check
+    /// :type pyrb: RecordBatch
+    /// :returns: a message for each rule the table breaks
+    /// :rtype: List[str]
+    #[text_signature = "($self, pyrb, /)"]
+    fn check(&self, pyrb: &PyAny) -> PyResult<Vec<String>> {
+        let rb = to_rust_recordbatch(pyrb)?;
+        self.inner.check(&[rb]).map_err(value_error)
+    }
+}
+
 #[pyclass]
 #[derive(Debug)]
 struct TensorSchema {
@@ -206,11 +339,26 @@ struct TensorSchema {
 }
 
 impl_to_from_dict!(TensorSchema);
+impl_repr_html!(TensorSchema);
 impl_from_arrow_array!(TensorSchema, schema::TensorSchema);
 impl_to_builder!(TensorSchema);
 impl_name_prop!(TensorSchema, "tensor");
 impl_transformers!(TensorSchema);
 
+#[pymethods]
+impl TensorSchema {
+    /// Check that a variable written with this schema follows the CF conventions
+    ///
+    /// :param name: the name of the NetCDF variable
+    /// :type name: str
+    /// :returns: the issues found, each a dict with variable, severity and message keys
+    /// :rtype: List[dict]
+    #[text_signature = "(name, /)"]
+    fn check_cf(&self, name: &str) -> PyResult<PyObject> {
+        to_dict(&self.inner.check_cf(name))
+    }
+}
+
 #[pyclass]
 #[derive(Debug)]
 struct Schemaless {
@@ -218,6 +366,7 @@ struct Schemaless {
 }
 
 impl_to_from_dict!(Schemaless);
+impl_repr_html!(Schemaless);
 impl_from_arrow_array!(Schemaless, schema::Schemaless);
 impl_to_builder!(Schemaless);
 impl_name_prop!(Schemaless, "unvalidated");
@@ -264,6 +413,7 @@ struct NetCDFResource {
 }
 
 impl_to_from_dict!(NetCDFResource);
+impl_repr_html!(NetCDFResource);
 impl_from_arrow_array!(NetCDFResource, resource::NetCDFResource);
 impl_to_builder!(NetCDFResource);
 impl_name_prop!(NetCDFResource, "netcdf");
@@ -272,14 +422,446 @@ impl_transformers!(NetCDFResource, (path, path_transformer));
 #[pymethods]
 impl NetCDFResource {
     #[new]
-    fn __init__(path: String, variable: String) -> PyResult<Self> {
+    fn __init__(path: String, variable: String, compression: Option<&PyAny>) -> PyResult<Self> {
+        let compression: Option<resource::Compression> = match compression {
+            Some(c) => Some(from_dict(c)?),
+            None => None,
+        };
+        if let Some(c) = compression.as_ref() {
+            c.validate().map_err(value_error)?;
+        }
         Ok(Self {
             inner: resource::NetCDFResource {
                 path,
                 variable,
+                compression,
             }
         })
     }
+
+    /// The chunk shape to write a variable of the given shape with
+    ///
+    /// :param shape: the size of each of the variable's dimensions
+    /// :rtype: Optional[List[int]]
+    #[text_signature = "($self, shape)"]
+    fn chunk_shape(&self, shape: Vec<usize>) -> PyResult<Option<Vec<usize>>> {
+        match self.inner.compression.as_ref() {
+            Some(c) => c.chunk_shape(&shape).map_err(value_error),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Collects the results of many runs, cells or scenarios into one frame
+///
+/// :param keys: the labels each result is pushed with, such as ["run_id", "cell"]
+/// :type keys: List[str]
+/// :param index: result columns, such as "time", that follow the keys in the index
+/// :type index: Optional[List[str]]
+#[pyclass]
+#[text_signature = "(keys, index=None)"]
+#[derive(Debug)]
+struct ResultStack {
+    inner: stack::ResultStack,
+}
+
+#[pymethods]
+impl ResultStack {
+    #[new]
+    fn __init__(keys: Vec<&str>, index: Option<Vec<&str>>) -> Self {
+        let inner = index
+            .unwrap_or_default()
+            .into_iter()
+            .fold(stack::ResultStack::new(&keys), |s, c| s.index_by(c));
+        Self { inner }
+    }
+
+    /// Add the result of one run
+    ///
+    /// :param key: a label for each key
+    /// :type key: List[str]
+    /// :param pyrb: the result, with the same columns as earlier results
+    /// :type pyrb: RecordBatch
+    #[text_signature = "($self, key, pyrb, /)"]
+    fn push(&mut self, key: Vec<&str>, pyrb: &PyAny) -> PyResult<()> {
+        let rb = to_rust_recordbatch(pyrb)?;
+        self.inner.push(&key, rb).map_err(value_error)
+    }
+
+    /// The key and result columns that index the stacked frame
+    #[getter]
+    fn index(&self) -> Vec<String> {
+        self.inner.index()
+    }
+
+    fn __len__(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// A summary of each result column, shown by notebooks
+    fn _repr_html_(&self) -> PyResult<String> {
+        let title = format!("{} results by {}", self.inner.len(), self.inner.index().join(", "));
+        let batches: Vec<RecordBatch> = self
+            .inner
+            .finish(stack::StackLayout::Wide)
+            .map_err(value_error)?
+            .into_iter()
+            .collect();
+        Ok(report::html_summary(&title, &batches))
+    }
+
+    /// Join the results into a frame indexed by the keys and index columns
+    ///
+    /// :param long: stack the result columns into variable and value columns
+    /// :type long: Optional[bool]
+    /// :rtype: pandas.DataFrame
+    #[text_signature = "($self, long=False, /)"]
+    fn to_pandas(&self, py: Python, long: Option<bool>) -> PyResult<PyObject> {
+        let layout = if long.unwrap_or(false) {
+            stack::StackLayout::Long
+        } else {
+            stack::StackLayout::Wide
+        };
+        let rb = match self.inner.finish(layout).map_err(value_error)? {
+            Some(rb) => rb,
+            None => return Ok(py.import("pandas")?.getattr("DataFrame")?.call0()?.to_object(py)),
+        };
+        let mut index = self.inner.index();
+        if layout == stack::StackLayout::Long {
+            index.push("variable".to_string());
+        }
+        let pa = py.import("pyarrow")?;
+        let rb = to_py_recordbatch(&rb, py, pa)?;
+        let df = rb
+            .as_ref(py)
+            .call_method0("to_pandas")?
+            .call_method1("set_index", (index,))?;
+        Ok(df.to_object(py))
+    }
+}
+
+/// Schedules ``update_until`` calls for coupled models with different time steps
+///
+/// Times are in one unit for every model, such as hours. The model whose next
+/// step ends earliest goes first, and the shorter step wins ties, so an hourly
+/// model produces a day before a daily model reads it.
+///
+/// :param start: the time every model starts at
+/// :type start: Optional[float]
+#[pyclass]
+#[text_signature = "(start=0.0)"]
+#[derive(Debug)]
+struct SimulationClock {
+    inner: clock::SimulationClock,
+}
+
+impl_repr_html!(SimulationClock);
+
+#[pymethods]
+impl SimulationClock {
+    #[new]
+    fn __init__(start: Option<f64>) -> Self {
+        Self {
+            inner: clock::SimulationClock::new(start.unwrap_or(0.0)),
+        }
+    }
+
+    #[text_signature = "($self, name, step, /)"]
+    fn add_model(&mut self, name: &str, step: f64) -> PyResult<()> {
+        self.inner.add_model(name, step).map_err(value_error)
+    }
+
+    /// The time every model has reached
+    #[getter]
+    fn time(&self) -> f64 {
+        self.inner.time()
+    }
+
+    /// The current time and step of a model
+    ///
+    /// :rtype: Tuple[float, float]
+    #[text_signature = "($self, name, /)"]
+    fn model(&self, name: &str) -> PyResult<(f64, f64)> {
+        let m = self.inner.model(name).map_err(value_error)?;
+        Ok((m.time, m.step))
+    }
+
+    /// Set the time of a model, such as after it reports its own time
+    #[text_signature = "($self, name, time, /)"]
+    fn set_time(&mut self, name: &str, time: f64) -> PyResult<()> {
+        self.inner.set_time(name, time).map_err(value_error)
+    }
+
+    /// The next model to update and the time to update it until, or None
+    /// once every model is as close to ``until`` as whole steps allow
+    ///
+    /// :rtype: Optional[Tuple[str, float]]
+    #[text_signature = "($self, until, /)"]
+    fn next_update(&mut self, until: f64) -> Option<(String, f64)> {
+        self.inner.next_update(until).map(|u| (u.model, u.until))
+    }
+
+    /// Every update to make to bring the models up to ``until``
+    ///
+    /// :rtype: List[Tuple[str, float]]
+    #[text_signature = "($self, until, /)"]
+    fn schedule(&mut self, until: f64) -> Vec<(String, f64)> {
+        self.inner
+            .schedule(until)
+            .into_iter()
+            .map(|u| (u.model, u.until))
+            .collect()
+    }
+}
+
+/// Running totals of the mass crossing each coupling link
+///
+/// Record what one model hands over with ``send`` and what the other takes in
+/// with ``receive``, after unit conversion and regridding. ``check`` raises if
+/// any link's totals diverge.
+///
+/// :param tolerance: the largest allowed difference relative to the larger total
+/// :type tolerance: Optional[float]
+#[pyclass]
+#[text_signature = "(tolerance=1e-6)"]
+#[derive(Debug)]
+struct ConservationLedger {
+    inner: conservation::ConservationLedger,
+}
+
+impl_repr_html!(ConservationLedger);
+
+#[pymethods]
+impl ConservationLedger {
+    #[new]
+    fn __init__(tolerance: Option<f64>) -> Self {
+        let inner = match tolerance {
+            Some(t) => conservation::ConservationLedger::new(t),
+            None => conservation::ConservationLedger::default(),
+        };
+        Self { inner }
+    }
+
+    #[text_signature = "($self, link, amount, /)"]
+    fn send(&mut self, link: &str, amount: f64) {
+        self.inner.send(link, amount)
+    }
+
+    #[text_signature = "($self, link, amount, /)"]
+    fn receive(&mut self, link: &str, amount: f64) {
+        self.inner.receive(link, amount)
+    }
+
+    /// The sent and received totals of a link
+    ///
+    /// :rtype: Optional[Tuple[float, float]]
+    #[text_signature = "($self, link, /)"]
+    fn totals(&self, link: &str) -> Option<(f64, f64)> {
+        self.inner.link(link).map(|l| (l.sent, l.received))
+    }
+
+    /// Raise a ValueError listing every link whose totals diverge
+    #[text_signature = "($self, /)"]
+    fn check(&self) -> PyResult<()> {
+        let imbalances = self.inner.imbalances();
+        if imbalances.is_empty() {
+            Ok(())
+        } else {
+            let messages: Vec<String> = imbalances.iter().map(|e| e.to_string()).collect();
+            Err(PyValueError::new_err(messages.join("\n")))
+        }
+    }
+}
+
+/// Reads the results of an experiment from a zip written by
+/// ``meillionen archive``, without unpacking it
+///
+/// :param path: the archive
+/// :type path: str
+#[pyclass]
+#[text_signature = "(path)"]
+struct Archive {
+    inner: archive::ArchiveReader,
+}
+
+#[pymethods]
+impl Archive {
+    #[new]
+    fn __init__(path: &str) -> PyResult<Self> {
+        let inner = archive::ArchiveReader::open(path).map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    /// The archived sinks of each trial
+    ///
+    /// :rtype: Dict[str, List[str]]
+    #[text_signature = "($self, /)"]
+    fn sinks(&self) -> BTreeMap<String, Vec<String>> {
+        self.inner
+            .index()
+            .sinks
+            .iter()
+            .map(|(trial, sinks)| (trial.clone(), sinks.keys().cloned().collect()))
+            .collect()
+    }
+
+    /// The experiment status, manifest and where each entry came from
+    ///
+    /// :rtype: dict
+    #[text_signature = "($self, /)"]
+    fn index(&self) -> PyResult<PyObject> {
+        to_dict(self.inner.index())
+    }
+
+    /// The record batches of a feather or parquet sink of a trial
+    ///
+    /// :rtype: List[pyarrow.RecordBatch]
+    #[text_signature = "($self, trial, sink, /)"]
+    fn read(&mut self, py: Python, trial: &str, sink: &str) -> PyResult<Vec<PyObject>> {
+        let batches = self.inner.read_batches(trial, sink).map_err(value_error)?;
+        let pa = py.import("pyarrow")?;
+        batches.iter().map(|rb| to_py_recordbatch(rb, py, pa)).collect()
+    }
+
+    /// What the model program of a trial printed, by stream
+    ///
+    /// :rtype: Dict[str, str]
+    #[text_signature = "($self, trial, /)"]
+    fn logs(&mut self, trial: &str) -> PyResult<BTreeMap<String, String>> {
+        self.inner.logs(trial).map_err(value_error)
+    }
+}
+
+/// Values an upstream model hands over on a coupling link, filled in for a
+/// downstream model with a finer time step
+///
+/// States are pushed with the time they apply at and read with ``value_at``.
+/// Fluxes are pushed with the end of the step they accumulated over and read
+/// with ``amount``.
+///
+/// :param method: step_hold, linear or spline for states, disaggregate for fluxes
+/// :type method: str
+/// :param start: the time the link starts at
+/// :type start: Optional[float]
+#[pyclass]
+#[text_signature = "(method, start=0.0)"]
+#[derive(Debug)]
+struct CouplingLink {
+    inner: interpolation::CouplingLink,
+}
+
+impl_repr_html!(CouplingLink);
+
+#[pymethods]
+impl CouplingLink {
+    #[new]
+    fn __init__(method: &str, start: Option<f64>) -> PyResult<Self> {
+        let method = method.parse().map_err(value_error)?;
+        Ok(Self {
+            inner: interpolation::CouplingLink::new(method, start.unwrap_or(0.0)),
+        })
+    }
+
+    #[text_signature = "($self, time, value, /)"]
+    fn push(&mut self, time: f64, value: f64) -> PyResult<()> {
+        self.inner.push(time, value).map_err(value_error)
+    }
+
+    /// The value of a state at a time
+    ///
+    /// :rtype: float
+    #[text_signature = "($self, time, /)"]
+    fn value_at(&self, time: f64) -> PyResult<f64> {
+        self.inner.value_at(time).map_err(value_error)
+    }
+
+    /// The amount of a flux between two times
+    ///
+    /// :rtype: float
+    #[text_signature = "($self, start, end, /)"]
+    fn amount(&self, start: f64, end: f64) -> PyResult<f64> {
+        self.inner.amount(start, end).map_err(value_error)
+    }
+}
+
+/// Publishes the key outputs of each step of a coupled model to a NATS
+/// subject as the model produces them, for dashboards and monitoring
+///
+/// :param address: the NATS server, such as ``localhost:4222``
+/// :type address: str
+/// :param subject: the subject to publish to, without spaces
+/// :type subject: str
+/// :param model: the name of the model in each message
+/// :type model: str
+/// :param step: the column with the step of each row, such as ``day``
+/// :type step: str
+/// :param columns: the float columns to publish, such as soil water and LAI
+/// :type columns: List[str]
+#[pyclass]
+#[text_signature = "(address, subject, model, step, columns)"]
+struct StepPublisher {
+    inner: stream::StepStream<stream::NatsPublisher>,
+}
+
+#[pymethods]
+impl StepPublisher {
+    #[new]
+    fn __init__(
+        address: &str,
+        subject: &str,
+        model: &str,
+        step: &str,
+        columns: Vec<String>,
+    ) -> PyResult<Self> {
+        let publisher = stream::NatsPublisher::connect(address).map_err(value_error)?;
+        let inner = stream::StepStream::new(publisher, subject, model, step, columns)
+            .map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    /// Publish a message for each row of a pyarrow record batch
+    ///
+    /// :rtype: int
+    #[text_signature = "($self, batch, /)"]
+    fn publish(&mut self, batch: &PyAny) -> PyResult<usize> {
+        let rb = to_rust_recordbatch(batch)?;
+        self.inner.publish_batch(&rb).map_err(value_error)
+    }
+}
+
+#[pyclass]
+#[derive(Debug)]
+struct MultiNetCDFResource {
+    inner: resource::MultiNetCDFResource,
+}
+
+impl_to_from_dict!(MultiNetCDFResource);
+impl_repr_html!(MultiNetCDFResource);
+impl_from_arrow_array!(MultiNetCDFResource, resource::MultiNetCDFResource);
+impl_to_builder!(MultiNetCDFResource);
+impl_name_prop!(MultiNetCDFResource, "multi_netcdf");
+impl_transformers!(MultiNetCDFResource, (pattern, path_transformer));
+
+#[pymethods]
+impl MultiNetCDFResource {
+    #[new]
+    fn __init__(pattern: String, variable: String, dimension: String) -> Self {
+        Self {
+            inner: resource::MultiNetCDFResource {
+                pattern,
+                variable,
+                dimension,
+            }
+        }
+    }
+
+    /// The paths matching the pattern in the order they are concatenated
+    ///
+    /// :rtype: List[str]
+    fn paths(&self) -> PyResult<Vec<String>> {
+        let paths = self.inner.paths().map_err(value_error)?;
+        Ok(paths.iter().map(|p| p.to_string_lossy().to_string()).collect())
+    }
 }
 
 #[pyclass]
@@ -289,6 +871,7 @@ struct FeatherResource {
 }
 
 impl_to_from_dict!(FeatherResource);
+impl_repr_html!(FeatherResource);
 impl_from_arrow_array!(FeatherResource, resource::FeatherResource);
 impl_to_builder!(FeatherResource);
 impl_name_prop!(FeatherResource, "feather");
@@ -313,6 +896,7 @@ struct ParquetResource {
 }
 
 impl_to_from_dict!(ParquetResource);
+impl_repr_html!(ParquetResource);
 impl_from_arrow_array!(ParquetResource, resource::ParquetResource);
 impl_to_builder!(ParquetResource);
 impl_name_prop!(ParquetResource, "parquet");
@@ -337,6 +921,7 @@ struct FileResource {
 }
 
 impl_to_from_dict!(FileResource);
+impl_repr_html!(FileResource);
 impl_from_arrow_array!(FileResource, resource::FileResource);
 impl_to_builder!(FileResource);
 impl_name_prop!(FileResource, "file");
@@ -362,19 +947,29 @@ impl FileResource {
 /// :type program_name: str
 /// :param fc: the request
 /// :type fc: FuncRequest
+/// :param traceparent: the W3C trace context of the calling span
+/// :type traceparent: Optional[str]
 /// :returns: the stdout or stderr of the program execution
 /// :rtype: str
-#[pyfunction]
-#[text_signature = "(program_name, fc, /)"]
-fn client_call_cli(program_name: &str, pyrb: &PyAny) -> PyResult<String> {
+///
+/// The GIL is released while the program runs so other threads, such as an
+/// asyncio event loop, keep going.
+#[pyfunction(traceparent = "None")]
+#[text_signature = "(program_name, fc, traceparent=None, /)"]
+fn client_call_cli(py: Python, program_name: &str, pyrb: &PyAny, traceparent: Option<&str>) -> PyResult<String> {
     let request = to_rust_recordbatch(pyrb)?;
-    let output = model::client_call_cli(program_name, &request)
+    let trace = traceparent
+        .map(trace::TraceContext::parse)
+        .transpose()
+        .map_err(value_error)?;
+    let output = py
+        .allow_threads(|| model::client_call_cli(program_name, &request, trace.as_ref()))
         .map_err(|err| PyIOError::new_err(format!("{:?}", err)))?;
     if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        Ok(model::output_text(&output.stdout))
     } else {
-        let out = String::from_utf8_lossy(&output.stdout);
-        let err = String::from_utf8_lossy(&output.stderr);
+        let out = model::output_text(&output.stdout);
+        let err = model::output_text(&output.stderr);
         Err(PyIOError::new_err(formatdoc! {"
 
             Stdout:
@@ -421,16 +1016,506 @@ fn client_create_interface_from_cli(path: &str) -> PyResult<PyObject> {
     to_py_recordbatch(&rb, py, pa)
 }
 
+/// Apply a trailing window reduction to the float columns of a record batch
+///
+/// :param pyrb: a daily output record batch
+/// :type pyrb: RecordBatch
+/// :param window: the number of rows in each window
+/// :type window: int
+/// :param reduction: one of "Sum", "Mean", "Min", "Max" or {"Quantile": q}
+/// :returns: a record batch with the same columns
+/// :rtype: RecordBatch
+#[pyfunction]
+#[text_signature = "(pyrb, window, reduction, /)"]
+fn rolling(py: Python, pyrb: &PyAny, window: usize, reduction: &PyAny) -> PyResult<PyObject> {
+    let rb = to_rust_recordbatch(pyrb)?;
+    let result = timeseries::rolling(&rb, window, from_dict(reduction)?)
+        .map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&result, py, pa)
+}
+
+/// Aggregate the float columns of a daily record batch into coarser periods
+///
+/// :param pyrb: a daily output record batch
+/// :type pyrb: RecordBatch
+/// :param day: the name of the day of year column
+/// :type day: str
+/// :param period: {"Days": {"days": n, "leap_year": bool}} or
+///     {"Month": {"leap_year": bool}}, leap_year defaults to false
+/// :param reduction: one of "Sum", "Mean", "Min", "Max" or {"Quantile": q}
+/// :returns: a record batch with one row per period
+/// :rtype: RecordBatch
+#[pyfunction]
+#[text_signature = "(pyrb, day, period, reduction, /)"]
+fn resample(py: Python, pyrb: &PyAny, day: &str, period: &PyAny, reduction: &PyAny) -> PyResult<PyObject> {
+    let rb = to_rust_recordbatch(pyrb)?;
+    let result = timeseries::resample(&rb, day, from_dict(period)?, from_dict(reduction)?)
+        .map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&result, py, pa)
+}
+
+/// Risk metrics of an output over the outcomes of an ensemble
+///
+/// Each row is one outcome, such as one season of one ensemble member.
+///
+/// :param pyrb: the collected outputs of an ensemble
+/// :type pyrb: RecordBatch
+/// :param variable: the name of the column to compute metrics of
+/// :type variable: str
+/// :param group_by: the columns whose values pick out each group of outcomes
+/// :type group_by: List[str]
+/// :param spec: {"thresholds": [...], "return_periods": [...], "cvar_levels": [...], "side": "below" or "above"}
+/// :returns: a record batch with the group columns and metric, parameter, value and outcomes
+/// :rtype: RecordBatch
+#[pyfunction]
+#[text_signature = "(pyrb, variable, group_by, spec, /)"]
+fn risk_table(py: Python, pyrb: &PyAny, variable: &str, group_by: Vec<&str>, spec: &PyAny) -> PyResult<PyObject> {
+    let rb = to_rust_recordbatch(pyrb)?;
+    let result = risk::risk_table(&rb, variable, &group_by, &from_dict(spec)?)
+        .map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&result, py, pa)
+}
+
+/// Append a record batch to a table in a SQLite database file
+///
+/// The table is created if it does not exist. The file can be queried with
+/// sqlite3 or attached in DuckDB.
+///
+/// :param pyrb: the rows to append
+/// :type pyrb: RecordBatch
+/// :param path: the database file
+/// :type path: str
+/// :param table: the table name
+/// :type table: str
+/// :returns: the number of rows inserted
+/// :rtype: int
+#[pyfunction]
+#[text_signature = "(pyrb, path, table, /)"]
+fn to_sql(pyrb: &PyAny, path: &str, table: &str) -> PyResult<usize> {
+    let rb = to_rust_recordbatch(pyrb)?;
+    sql::to_sqlite_file(path, table, &rb).map_err(value_error)
+}
+
+/// Plot columns of a record batch against another column
+///
+/// A quick look at model output without matplotlib.
+///
+/// :param pyrb: the rows to plot, such as SimpleCrop plant or soil output
+/// :type pyrb: RecordBatch
+/// :param x: the column on the x axis, such as "day"
+/// :type x: str
+/// :param columns: the columns to draw as lines
+/// :type columns: List[str]
+/// :param title: the plot title
+/// :type title: str
+/// :param path: a .png or .svg file to write
+/// :type path: str
+#[pyfunction]
+#[text_signature = "(pyrb, x, columns, title, path, /)"]
+fn plot_timeseries(pyrb: &PyAny, x: &str, columns: Vec<&str>, title: &str, path: &str) -> PyResult<()> {
+    let rb = to_rust_recordbatch(pyrb)?;
+    plot::plot_timeseries(&rb, x, &columns, title, path.as_ref()).map_err(value_error)
+}
+
+/// Plot the median and spread of an ensemble over time
+///
+/// :param xs: the x value of each step
+/// :type xs: List[float]
+/// :param members: one list of values per ensemble member
+/// :type members: List[List[float]]
+/// :param title: the plot title
+/// :type title: str
+/// :param path: a .png or .svg file to write
+/// :type path: str
+#[pyfunction]
+#[text_signature = "(xs, members, title, path, /)"]
+fn plot_fan(xs: Vec<f64>, members: Vec<Vec<f64>>, title: &str, path: &str) -> PyResult<()> {
+    plot::plot_fan(&xs, &members, title, path.as_ref()).map_err(value_error)
+}
+
+/// Find the Pareto set of parameterizations over competing objectives
+///
+/// Uses NSGA-II. Every objective is minimized.
+///
+/// :param parameters: (name, lower, upper) of each parameter to calibrate
+/// :type parameters: List[Tuple[str, float, float]]
+/// :param objectives: the names of the objectives
+/// :type objectives: List[str]
+/// :param evaluate: runs the model with a dict of parameter values and returns
+///     the objectives and a reference to the run
+/// :type evaluate: Callable[[Dict[str, float]], Tuple[List[float], str]]
+/// :param config: population, generations, crossover_eta, mutation_eta and seed
+/// :type config: Optional[dict]
+/// :returns: a parameter, objective, rank and run column for each solution
+/// :rtype: RecordBatch
+#[pyfunction(config = "None")]
+#[text_signature = "(parameters, objectives, evaluate, config=None, /)"]
+fn calibrate_pareto(
+    py: Python,
+    parameters: Vec<(String, f64, f64)>,
+    objectives: Vec<&str>,
+    evaluate: &PyAny,
+    config: Option<&PyAny>,
+) -> PyResult<PyObject> {
+    let parameters: Vec<calibration::Parameter> = parameters
+        .iter()
+        .map(|(name, lower, upper)| calibration::Parameter::new(name, *lower, *upper))
+        .collect();
+    let config: calibration::Nsga2Config = match config {
+        Some(c) => from_dict(c)?,
+        None => Default::default(),
+    };
+    let solutions = calibration::nsga2(&parameters, &config, |x| {
+        let values = PyDict::new(py);
+        for (p, v) in parameters.iter().zip(x) {
+            values.set_item(&p.name, v)?;
+        }
+        let (values, run): (Vec<f64>, String) = evaluate.call1((values,))?.extract()?;
+        if values.len() != objectives.len() {
+            return Err(PyValueError::new_err(format!(
+                "evaluate returned {} objectives but {} are named",
+                values.len(),
+                objectives.len()
+            )));
+        }
+        Ok(calibration::Evaluation { objectives: values, run })
+    })?;
+    let rb = calibration::solutions_to_recordbatch(&parameters, &objectives, &solutions)
+        .map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&rb, py, pa)
+}
+
+/// Sample the posterior of model parameters with Metropolis-Hastings
+///
+/// Priors are uniform between the bounds. Chains run on separate threads, so
+/// ``log_likelihood`` may be called concurrently when it releases the GIL
+/// (for example while waiting on a model program).
+///
+/// :param parameters: (name, lower, upper) of each parameter to calibrate
+/// :type parameters: List[Tuple[str, float, float]]
+/// :param log_likelihood: runs the model with a dict of parameter values and
+///     scores it against observations
+/// :type log_likelihood: Callable[[Dict[str, float]], float]
+/// :param config: chains, samples, burn_in, proposal_scale and seed
+/// :type config: Optional[dict]
+/// :returns: a column per parameter and the chain of each sample
+/// :rtype: RecordBatch
+#[pyfunction(config = "None")]
+#[text_signature = "(parameters, log_likelihood, config=None, /)"]
+fn calibrate_posterior(
+    py: Python,
+    parameters: Vec<(String, f64, f64)>,
+    log_likelihood: PyObject,
+    config: Option<&PyAny>,
+) -> PyResult<PyObject> {
+    let parameters: Vec<calibration::Parameter> = parameters
+        .iter()
+        .map(|(name, lower, upper)| calibration::Parameter::new(name, *lower, *upper))
+        .collect();
+    let config: calibration::McmcConfig = match config {
+        Some(c) => from_dict(c)?,
+        None => Default::default(),
+    };
+    let score = |x: &[f64]| {
+        Python::with_gil(|py| {
+            let values = PyDict::new(py);
+            for (p, v) in parameters.iter().zip(x) {
+                values.set_item(&p.name, v)?;
+            }
+            log_likelihood.call1(py, (values,))?.extract::<f64>(py)
+        })
+    };
+    let posterior = py.allow_threads(|| calibration::metropolis(&parameters, &config, score))?;
+    let rb = posterior.to_recordbatch(&parameters).map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&rb, py, pa)
+}
+
+/// Design parameter sets for a sweep, sensitivity analysis or surrogate
+///
+/// :param parameters: (name, lower, upper) of each parameter to vary
+/// :type parameters: List[Tuple[str, float, float]]
+/// :param config: method (latin_hypercube, sobol or halton), samples, seed
+///     and scramble
+/// :type config: Optional[dict]
+/// :returns: a sample column and a column per parameter
+/// :rtype: RecordBatch
+#[pyfunction(config = "None")]
+#[text_signature = "(parameters, config=None, /)"]
+fn sample(
+    py: Python,
+    parameters: Vec<(String, f64, f64)>,
+    config: Option<&PyAny>,
+) -> PyResult<PyObject> {
+    let parameters: Vec<calibration::Parameter> = parameters
+        .iter()
+        .map(|(name, lower, upper)| calibration::Parameter::new(name, *lower, *upper))
+        .collect();
+    let config: sampling::SamplingConfig = match config {
+        Some(c) => from_dict(c)?,
+        None => Default::default(),
+    };
+    let samples = sampling::sample(&parameters, &config).map_err(value_error)?;
+    let rb = sampling::samples_to_recordbatch(&parameters, &samples).map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    to_py_recordbatch(&rb, py, pa)
+}
+
+/// The trials of an experiment file as run specs, in run order
+///
+/// :param path: the experiment file
+/// :type path: str
+/// :returns: a trial and seed for each trial
+/// :rtype: List[dict]
+#[pyfunction]
+#[text_signature = "(path, /)"]
+fn experiment_run_specs(py: Python, path: &str) -> PyResult<PyObject> {
+    let config = experiment::ExperimentConfig::load(path).map_err(value_error)?;
+    config.validate().map_err(value_error)?;
+    pythonize(py, &config.run_specs()).map_err(value_error)
+}
+
+/// The trials of a factorial design file as run specs, in run order
+///
+/// Each trial is tagged with the level of every factor and its replicate.
+///
+/// :param path: the design file
+/// :type path: str
+/// :returns: a trial and seed for each treatment and replicate
+/// :rtype: List[dict]
+#[pyfunction]
+#[text_signature = "(path, /)"]
+fn design_run_specs(py: Python, path: &str) -> PyResult<PyObject> {
+    let config = design::FactorialConfig::load(path).map_err(value_error)?;
+    let experiment = config.experiment().map_err(value_error)?;
+    pythonize(py, &experiment.run_specs()).map_err(value_error)
+}
+
+/// Run one trial of an experiment from its run spec
+///
+/// The GIL is released while the model program runs.
+///
+/// :param spec: a trial and seed, as from experiment_run_specs
+/// :type spec: dict
+/// :returns: the trial name, its status, the batches of each tabular sink and
+///     the stdout and stderr of the model program
+/// :rtype: Tuple[str, dict, Dict[str, List[RecordBatch]], dict]
+#[pyfunction]
+#[text_signature = "(spec, /)"]
+fn experiment_run_spec(
+    py: Python,
+    spec: &PyAny,
+) -> PyResult<(String, PyObject, PyObject, PyObject)> {
+    let spec: experiment::RunSpec = from_dict(spec)?;
+    let outcome = py.allow_threads(|| spec.run()).map_err(value_error)?;
+    let pa = py.import("pyarrow")?;
+    let sinks = PyDict::new(py);
+    for (name, batches) in outcome.sinks.iter() {
+        let batches = batches
+            .iter()
+            .map(|rb| to_py_recordbatch(rb, py, pa))
+            .collect::<PyResult<Vec<PyObject>>>()?;
+        sinks.set_item(name, batches)?;
+    }
+    Ok((
+        outcome.trial,
+        to_dict(&outcome.status)?,
+        sinks.to_object(py),
+        to_dict(&outcome.logs)?,
+    ))
+}
+
+/// Set how much the native code prints to stderr
+///
+/// :param level: quiet, normal, verbose or debug
+/// :type level: str
+#[pyfunction]
+#[text_signature = "(level, /)"]
+fn set_verbosity(level: &str) -> PyResult<()> {
+    verbosity::set_verbosity(level.parse().map_err(value_error)?);
+    Ok(())
+}
+
+/// Turn strict mode, where validation warnings are errors, on or off
+///
+/// :param strict: whether warnings are errors
+/// :type strict: bool
+#[pyfunction]
+#[text_signature = "(strict, /)"]
+fn set_strict(strict: bool) {
+    strictness::set_strict(strict)
+}
+
+/// Whether validation warnings are errors
+///
+/// :rtype: bool
+#[pyfunction]
+#[text_signature = "()"]
+fn is_strict() -> bool {
+    strictness::is_strict()
+}
+
+/// Turn deterministic mode, where floats are computed and written the same
+/// way on every platform, on or off
+///
+/// :param deterministic: whether to use deterministic mode
+/// :type deterministic: bool
+#[pyfunction]
+#[text_signature = "(deterministic, /)"]
+fn set_deterministic(deterministic: bool) {
+    determinism::set_deterministic(deterministic)
+}
+
+/// Whether floats are computed and written the same way on every platform
+///
+/// :rtype: bool
+#[pyfunction]
+#[text_signature = "()"]
+fn is_deterministic() -> bool {
+    determinism::is_deterministic()
+}
+
+/// Explain how a variable's dimensions differ from the ones a model expects
+///
+/// :param variable: the name of the variable
+/// :type variable: str
+/// :param expected: dimension names and sizes the model expects, ``None`` for any size
+/// :type expected: list
+/// :param got: dimension names and sizes of the variable
+/// :type got: list
+/// :return: ``None`` if they match, otherwise the explanation and the suggested transforms
+/// :rtype: Optional[tuple]
+#[pyfunction]
+#[text_signature = "(variable, expected, got, /)"]
+fn check_dimensions(
+    py: Python,
+    variable: &str,
+    expected: Vec<(String, Option<usize>)>,
+    got: Vec<(String, usize)>,
+) -> PyResult<Option<(String, PyObject)>> {
+    match negotiate::DimensionMismatch::check(variable, &expected, &got) {
+        None => Ok(None),
+        Some(mismatch) => {
+            let suggestions = pythonize(py, &mismatch.suggestions()).map_err(value_error)?;
+            Ok(Some((mismatch.to_string(), suggestions)))
+        }
+    }
+}
+
+/// What does not match, the explanation and the suggested transforms
+type NegotiationMismatch = (&'static str, String, PyObject);
+
+/// Check a variable against what a model requests for it
+///
+/// :param variable: the name of the variable
+/// :type variable: str
+/// :param request: the ``standard_name``, ``units`` and ``dimensions`` the model expects
+/// :type request: dict
+/// :param response: the ``standard_name``, ``units`` and ``dimensions`` of the variable
+/// :type response: dict
+/// :return: the unit conversions to apply and ``None``, or ``None`` and what does not match
+///     (``standard_name``, ``units`` or ``dimensions``), the explanation and the suggested transforms
+/// :rtype: tuple
+#[pyfunction]
+#[text_signature = "(variable, request, response, /)"]
+fn negotiate_variable(
+    py: Python,
+    variable: &str,
+    request: &PyAny,
+    response: &PyAny,
+) -> PyResult<(Option<PyObject>, Option<NegotiationMismatch>)> {
+    let request: negotiate::VariableRequest = from_dict(request)?;
+    let response: negotiate::VariableResponse = from_dict(response)?;
+    match request.accept(variable, &response) {
+        Ok(converters) => Ok((
+            Some(pythonize(py, &converters).map_err(value_error)?),
+            None,
+        )),
+        Err(e) => {
+            let kind = match e {
+                negotiate::NegotiationError::StandardName { .. } => "standard_name",
+                negotiate::NegotiationError::Units { .. } => "units",
+                negotiate::NegotiationError::Dimensions(_) => "dimensions",
+            };
+            let suggestions = pythonize(py, &e.suggestions()).map_err(value_error)?;
+            Ok((None, Some((kind, e.to_string(), suggestions))))
+        }
+    }
+}
+
+/// An html table of settings, as notebooks show
+///
+/// :param title: the caption of the table
+/// :type title: str
+/// :param settings: nested settings, such as a trial config
+/// :type settings: dict
+/// :rtype: str
+#[pyfunction]
+#[text_signature = "(title, settings, /)"]
+fn parameters_html(title: &str, settings: &PyAny) -> PyResult<String> {
+    let value: serde_json::Value = from_dict(settings)?;
+    Ok(report::html_parameters(title, &value))
+}
+
+/// An html summary of each column of a result, as notebooks show
+///
+/// :param batches: the record batches of the result
+/// :type batches: List[RecordBatch]
+/// :param title: the caption of the table
+/// :type title: str
+/// :rtype: str
+#[pyfunction]
+#[text_signature = "(batches, title, /)"]
+fn summary_html(batches: &PyAny, title: &str) -> PyResult<String> {
+    let batches = batches
+        .iter()?
+        .map(|rb| to_rust_recordbatch(rb?))
+        .collect::<PyResult<Vec<RecordBatch>>>()?;
+    Ok(report::html_summary(title, &batches))
+}
+
 #[pymodule]
 fn meillionen(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(pyo3::wrap_pyfunction!(client_call_cli, m)?)?;
     m.add_function(pyo3::wrap_pyfunction!(client_create_interface_from_cli, m)?)?;
     m.add_function(pyo3::wrap_pyfunction!(server_respond_from_cli, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(rolling, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(resample, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(risk_table, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(to_sql, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(plot_timeseries, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(plot_fan, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(calibrate_pareto, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(calibrate_posterior, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(sample, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(experiment_run_specs, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(experiment_run_spec, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(design_run_specs, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(parameters_html, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(summary_html, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(set_verbosity, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(set_strict, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(is_strict, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(set_deterministic, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(is_deterministic, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(check_dimensions, m)?)?;
+    m.add_function(pyo3::wrap_pyfunction!(negotiate_variable, m)?)?;
 
     m.add_class::<ResourceBuilder>()?;
+    m.add_class::<ResultStack>()?;
+    m.add_class::<ConservationLedger>()?;
+    m.add_class::<CouplingLink>()?;
+    m.add_class::<Archive>()?;
+    m.add_class::<SimulationClock>()?;
+    m.add_class::<StepPublisher>()?;
     m.add_class::<FileResource>()?;
     m.add_class::<FeatherResource>()?;
     m.add_class::<NetCDFResource>()?;
+    m.add_class::<MultiNetCDFResource>()?;
     m.add_class::<ParquetResource>()?;
 
     m.add_class::<DataFrameSchema>()?;
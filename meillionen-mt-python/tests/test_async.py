@@ -0,0 +1,104 @@
+import asyncio
+import threading
+import time
+
+import pytest
+
+import meillionen.experiment
+from meillionen.client import ClientFunctionModel
+from meillionen.experiment import RunResult, RunSpec, Runner
+
+TRIALS = 3
+
+
+def spec(name, **params):
+    return RunSpec(trial={'name': name, **params})
+
+
+def concurrent_run_spec(barrier):
+    """
+    A ``run_spec`` that only returns once every trial is running, so running
+    the trials one at a time fails with a broken barrier
+    """
+    def run_spec(spec, metrics):
+        barrier.wait(timeout=10)
+        name = spec.trial['name']
+        if spec.trial.get('fail'):
+            return RunResult(trial=name, status={'state': 'failed', 'message': 'exit 1'}, sinks={})
+        if spec.trial.get('raise'):
+            raise RuntimeError(f'{name} could not start')
+        return RunResult(trial=name, status={'state': 'succeeded'}, sinks={})
+    return run_spec
+
+
+def test_run_all_runs_trials_concurrently(monkeypatch):
+    monkeypatch.setattr(meillionen.experiment, 'run_spec', concurrent_run_spec(threading.Barrier(TRIALS)))
+    specs = [spec('a'), spec('b', fail=True), spec('c')]
+    finished = []
+
+    results = asyncio.run(Runner(specs, max_workers=TRIALS).run_all(on_result=finished.append))
+
+    assert [r.trial for r in results] == ['a', 'b', 'c']
+    assert [r.succeeded for r in results] == [True, False, True]
+    assert results[1].status['message'] == 'exit 1'
+    assert sorted(r.trial for r in finished) == ['a', 'b', 'c']
+
+
+def test_run_all_raises_errors_of_trials(monkeypatch):
+    monkeypatch.setattr(meillionen.experiment, 'run_spec', concurrent_run_spec(threading.Barrier(TRIALS)))
+    specs = [spec('a'), spec('b', **{'raise': True}), spec('c')]
+
+    with pytest.raises(RuntimeError, match='b could not start'):
+        asyncio.run(Runner(specs, max_workers=TRIALS).run_all())
+
+
+def test_cancelling_run_all_does_not_wait_for_trials(monkeypatch):
+    started, release = threading.Event(), threading.Event()
+
+    def run_spec(spec, metrics):
+        started.set()
+        release.wait(timeout=10)
+        return RunResult(trial=spec.trial['name'], status={'state': 'succeeded'}, sinks={})
+
+    monkeypatch.setattr(meillionen.experiment, 'run_spec', run_spec)
+    specs = [spec('a'), spec('b')]
+
+    async def cancel():
+        task = asyncio.ensure_future(Runner(specs, max_workers=1).run_all())
+        while not started.is_set():
+            await asyncio.sleep(0.01)
+        cancelled_at = time.monotonic()
+        task.cancel()
+        with pytest.raises(asyncio.CancelledError):
+            await task
+        return time.monotonic() - cancelled_at
+
+    try:
+        assert asyncio.run(cancel()) < 5
+    finally:
+        release.set()
+
+
+def test_run_async_runs_models_concurrently(monkeypatch):
+    barrier = threading.Barrier(TRIALS)
+
+    def run(self, sources, sinks=None, partition=None, traceparent=None):
+        barrier.wait(timeout=10)
+        if sources.get('fail'):
+            raise ValueError(f'{sources["site"]} has no weather')
+        return {'daily': f'{sources["site"]}.parquet'}
+
+    monkeypatch.setattr(ClientFunctionModel, 'run', run)
+    model = ClientFunctionModel(interface=None, path='simplecrop', trial=None, sinks={}, partitioning=None)
+
+    async def run_sites():
+        return await asyncio.gather(
+            model.run_async(sources={'site': 'a'}),
+            model.run_async(sources={'site': 'b', 'fail': True}),
+            model.run_async(sources={'site': 'c'}),
+            return_exceptions=True)
+
+    a, b, c = asyncio.run(run_sites())
+    assert a == {'daily': 'a.parquet'}
+    assert isinstance(b, ValueError) and str(b) == 'b has no weather'
+    assert c == {'daily': 'c.parquet'}
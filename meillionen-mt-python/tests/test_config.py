@@ -0,0 +1,52 @@
+import pytest
+
+from meillionen.config import config_class
+
+Crop = config_class(
+    'Crop',
+    [
+        {'name': 'day_of_planting', 'data_type': 'Int32'},
+        {'name': 'soil_profile_depth', 'data_type': 'Float32'},
+    ],
+    defaults={'day_of_planting': 121, 'soil_profile_depth': 145.0},
+    aliases={'planting_day': 'day_of_planting'},
+    docs={
+        'day_of_planting': {'units': 'd', 'min': 1, 'max': 366, 'description': 'Day of the year the crop is planted'},
+        'soil_profile_depth': {'units': 'cm', 'min': 0.0},
+    }
+)
+
+
+def test_config_class_kwargs():
+    crop = Crop(planting_day=130, soil_profile_depth=120)
+    assert crop.day_of_planting == 130
+    assert crop.planting_day == 130
+    assert crop.soil_profile_depth == 120.0
+    assert crop == Crop(day_of_planting=130.0, soil_profile_depth=120.0)
+    assert repr(Crop()) == 'Crop()'
+    assert list(crop.to_dataframe().dtypes.astype(str)) == ['int32', 'float32']
+
+    with pytest.raises(TypeError, match='did you mean soil_profile_depth'):
+        Crop(soil_profil_depth=120)
+    with pytest.raises(TypeError, match='integer'):
+        Crop(planting_day=130.5)
+    with pytest.raises(TypeError, match='more than once'):
+        Crop(planting_day=130, day_of_planting=131)
+
+
+def test_config_class_docs():
+    with pytest.raises(ValueError, match=r'must be in \[1, 366\] d'):
+        Crop(planting_day=400)
+    with pytest.raises(ValueError, match='unknown fields'):
+        config_class('Crop', [{'name': 'day_of_planting', 'data_type': 'Int32'}], docs={'depth': {}})
+
+    described = Crop.describe_parameters()
+    assert list(described.index) == ['day_of_planting', 'soil_profile_depth']
+    assert described.loc['day_of_planting', 'default'] == 121
+    assert described.loc['day_of_planting', 'aliases'] == ['planting_day']
+    assert described.loc['soil_profile_depth', 'units'] == 'cm'
+    assert described.loc['soil_profile_depth', 'description'] is None
+
+    Planted = config_class('Planted', [{'name': 'day_of_planting', 'data_type': 'Int32'}],
+                           docs={'day_of_planting': {'default': 121}})
+    assert Planted().day_of_planting == 121
@@ -1,8 +1,12 @@
 import netCDF4
 import numpy as np
+import pandas as pd
 import pyarrow as pa
+import pytest
+from meillionen.ensemble import EnsembleCollector
 from meillionen.interface.schema import PandasHandler, NetCDFSliceHandler
-from meillionen.interface.resource import Feather, NetCDF
+from meillionen.interface.resource import Feather, NetCDF, Parquet
+from meillionen.units import UnitsType, with_units
 import xarray as xr
 
 
@@ -25,4 +29,45 @@ def test_save_netcdf():
         assert swid.variables.keys() == {'soil_water_infiltration__depth'}
         v = swid['soil_water_infiltration__depth']
         assert v.dimensions == ('x', 'y', 'time')
-        assert v[5,10,5] == 65
\ No newline at end of file
+        assert v[5,10,5] == 65
+
+def test_save_chunked(tmp_path):
+    df = pd.DataFrame({
+        'day': np.arange(10, dtype='int32'),
+        'rainfall': np.linspace(0., 1., 10, dtype='float32'),
+        'site': ['a', 'b'] * 5,
+    })
+    handler = PandasHandler(name='daily', s=pa.schema([]), chunk_rows=3)
+    for resource in [Feather(path=str(tmp_path / 'daily.feather')), Parquet(path=str(tmp_path / 'daily.parquet'))]:
+        handler.save(resource, df)
+        pd.testing.assert_frame_equal(handler.load(resource), df)
+
+
+def test_units_column(tmp_path):
+    path = str(tmp_path / 'storage.arrow')
+    table = pa.table({'soil_water_storage': with_units(pa.array([1.5, 2.0]), 'mm')})
+    with pa.ipc.new_file(path, table.schema) as writer:
+        writer.write_table(table)
+    loaded = pa.ipc.open_file(path).read_all()
+    assert loaded.schema.field('soil_water_storage').type == UnitsType('mm')
+    assert loaded.to_pandas()['soil_water_storage'].tolist() == [1.5, 2.0]
+
+
+def test_ensemble_collector(tmp_path):
+    collector = EnsembleCollector('yearly', index=['year'])
+    for member, (yield_, planting) in enumerate([(1.5, 110), (2.0, 130)]):
+        table = pa.table({
+            'year': pa.array([1990, 1991], pa.int32()),
+            'yield': with_units(pa.array([yield_, yield_ + 1.0]), 't/ha'),
+        })
+        collector.add(f'member{member}', table, {'day_of_planting': planting})
+    with pytest.raises(ValueError, match='more than once'):
+        collector.add('member0', table)
+
+    path = str(tmp_path / 'ensemble.nc')
+    collector.write(path)
+    ensemble = xr.open_dataset(path)
+    assert ensemble['yield'].dims == ('ensemble_member', 'year')
+    assert ensemble['yield'].attrs['units'] == 't/ha'
+    assert ensemble['yield'].sel(ensemble_member='member1', year=1991).item() == 3.0
+    assert ensemble['day_of_planting'].values.tolist() == [110, 130]
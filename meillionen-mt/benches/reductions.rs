@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use meillionen_mt::calibration::step_quantiles;
+use meillionen_mt::extension_columns::DimMeta;
+use meillionen_mt::variable::parallel::{par_reduce, par_step_quantiles, par_to_vec};
+use meillionen_mt::variable::{Reduction, Variable, VecVariable};
+
+const MEMBERS: usize = 1000;
+const DAYS: usize = 365;
+
+fn dim(name: &str, size: usize) -> Arc<DimMeta> {
+    Arc::new(DimMeta {
+        name: name.to_string(),
+        size,
+        description: None,
+        labels: None,
+        times: None,
+    })
+}
+
+/// A year of daily values for each member of an ensemble
+fn ensemble() -> VecVariable<f32> {
+    let data = (0..MEMBERS * DAYS)
+        .map(|i| ((i % DAYS) as f32 * 0.37).sin() * (1.0 + (i / DAYS) as f32 * 0.001))
+        .collect();
+    VecVariable::new(vec![dim("member", MEMBERS), dim("day", DAYS)], data).unwrap()
+}
+
+fn reductions(c: &mut Criterion) {
+    let ensemble = ensemble();
+    let summary = [
+        Reduction::Quantile(0.05),
+        Reduction::Quantile(0.5),
+        Reduction::Quantile(0.95),
+    ];
+
+    let mut group = c.benchmark_group("ensemble_quantiles");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            summary
+                .iter()
+                .map(|r| (&ensemble).reduce("member", *r).unwrap().to_vec())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("parallel_to_vec", |b| {
+        b.iter(|| {
+            summary
+                .iter()
+                .map(|r| par_to_vec(&(&ensemble).reduce("member", *r).unwrap()))
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("parallel_reduce", |b| {
+        b.iter(|| par_reduce(&ensemble, "member", &summary).unwrap())
+    });
+    group.finish();
+
+    let series: Vec<Vec<f64>> = (0..MEMBERS)
+        .map(|m| {
+            (0..DAYS)
+                .map(|d| (d as f64 * 0.37).sin() + m as f64 * 0.001)
+                .collect()
+        })
+        .collect();
+    let mut group = c.benchmark_group("step_quantiles");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| step_quantiles(&series, DAYS, 0.95))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| par_step_quantiles(&series, DAYS, 0.95))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, reductions);
+criterion_main!(benches);
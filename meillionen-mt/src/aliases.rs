@@ -0,0 +1,230 @@
+//! Names output variables are shown under
+//!
+//! Models name their outputs after their internals, such as `swfac1` or
+//! `soil_water_deficit_stress`. An experiment can give aliases for them, its
+//! own names or standard names such as those of CSDMS, in `aliases` tables of
+//! `internal = "alias"`, for every trial and for each trial on top.
+//!
+//! A trial's aliases are passed to the model program in `MEILLIONEN_ALIASES`
+//! and writers rename the columns and variables of every sink they write, so
+//! feather, parquet and NetCDF sinks, and the pandas and xarray tables read
+//! from them, all use the aliases. The `outputs` of a trial can be given by
+//! either name.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "data")]
+use std::sync::Arc;
+
+#[cfg(feature = "data")]
+use arrow::datatypes::{Field, Schema};
+#[cfg(feature = "data")]
+use arrow::error::ArrowError;
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "data")]
+use thiserror::Error;
+
+/// The environment variable holding the comma separated `internal=alias` pairs
+pub const ALIASES_ENV: &str = "MEILLIONEN_ALIASES";
+
+#[cfg(feature = "data")]
+#[derive(Debug, Error)]
+pub enum AliasError {
+    #[error("renaming gives more than one column named {0}")]
+    Clash(String),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+}
+
+/// Aliases of output variables keyed by their internal names
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Aliases {
+    names: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new<I, S, T>(names: I) -> Self
+    where
+        I: IntoIterator<Item = (S, T)>,
+        S: Into<String>,
+        T: Into<String>,
+    {
+        Self {
+            names: names
+                .into_iter()
+                .map(|(i, a)| (i.into(), a.into()))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.names.iter().map(|(i, a)| (i.as_str(), a.as_str()))
+    }
+
+    /// These aliases with those of `overrides` taking precedence
+    pub fn merge(&self, overrides: &Aliases) -> Aliases {
+        let mut names = self.names.clone();
+        names.extend(
+            overrides
+                .iter()
+                .map(|(i, a)| (i.to_string(), a.to_string())),
+        );
+        Aliases { names }
+    }
+
+    /// The name a variable is shown under, its own if it has no alias
+    pub fn alias<'a>(&'a self, internal: &'a str) -> &'a str {
+        self.names.get(internal).map_or(internal, String::as_str)
+    }
+
+    /// The internal name of a variable given by alias or internal name
+    pub fn internal<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names
+            .iter()
+            .find(|(_, a)| a.as_str() == name)
+            .map_or(name, |(i, _)| i.as_str())
+    }
+
+    /// Problems that would make the names ambiguous or unable to be passed on
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mut seen: BTreeMap<&str, &str> = BTreeMap::new();
+        for (internal, alias) in self.iter() {
+            for name in [internal, alias].iter() {
+                if name.is_empty() || name.contains([',', '=']) {
+                    problems.push(format!(
+                        "alias names must not be empty or contain ',' or '=' but {:?} does",
+                        name
+                    ));
+                }
+            }
+            if let Some(other) = seen.insert(alias, internal) {
+                problems.push(format!(
+                    "{} and {} are both aliased to {}",
+                    other, internal, alias
+                ));
+            }
+        }
+        problems
+    }
+
+    /// The aliases a model program was given, none if it was given none
+    pub fn from_env() -> Self {
+        match std::env::var(ALIASES_ENV) {
+            Ok(value) => Self::new(value.split(',').filter_map(|pair| {
+                let (internal, alias) = pair.split_once('=')?;
+                let (internal, alias) = (internal.trim(), alias.trim());
+                if internal.is_empty() || alias.is_empty() {
+                    None
+                } else {
+                    Some((internal.to_string(), alias.to_string()))
+                }
+            })),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The value of [`ALIASES_ENV`] for the aliases, if there are any
+    pub fn env_value(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(
+            self.iter()
+                .map(|(i, a)| format!("{}={}", i, a))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+    }
+
+    /// A batch with its columns renamed to their aliases
+    #[cfg(feature = "data")]
+    pub fn rename(&self, rb: &RecordBatch) -> Result<RecordBatch, AliasError> {
+        self.rename_with(rb, |name| self.alias(name).to_string())
+    }
+
+    /// A batch with aliased columns given their internal names again, such as
+    /// an output fed to another model
+    #[cfg(feature = "data")]
+    pub fn restore(&self, rb: &RecordBatch) -> Result<RecordBatch, AliasError> {
+        self.rename_with(rb, |name| self.internal(name).to_string())
+    }
+
+    #[cfg(feature = "data")]
+    fn rename_with<F>(&self, rb: &RecordBatch, name: F) -> Result<RecordBatch, AliasError>
+    where
+        F: Fn(&str) -> String,
+    {
+        if self.is_empty() {
+            return Ok(rb.clone());
+        }
+        let schema = rb.schema();
+        let mut fields: Vec<Field> = vec![];
+        for field in schema.fields().iter() {
+            let renamed = name(field.name());
+            if fields.iter().any(|f| f.name() == &renamed) {
+                return Err(AliasError::Clash(renamed));
+            }
+            let mut renamed = Field::new(&renamed, field.data_type().clone(), field.is_nullable());
+            renamed.set_metadata(field.metadata().clone());
+            fields.push(renamed);
+        }
+        let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+        Ok(RecordBatch::try_new(
+            Arc::new(schema),
+            rb.columns().to_vec(),
+        )?)
+    }
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::aliases::{AliasError, Aliases};
+
+    #[test]
+    fn rename_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("soil_water_deficit_stress", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1])),
+            Arc::new(Float32Array::from(vec![0.5])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+
+        let aliases = Aliases::new(vec![("soil_water_deficit_stress", "swfac1")]);
+        let renamed = aliases.rename(&rb).unwrap();
+        assert_eq!(renamed.schema().field(1).name(), "swfac1");
+        assert_eq!(aliases.restore(&renamed).unwrap().schema(), rb.schema());
+        assert_eq!(aliases.internal("swfac1"), "soil_water_deficit_stress");
+        assert_eq!(aliases.alias("day"), "day");
+        assert_eq!(
+            aliases.env_value(),
+            Some("soil_water_deficit_stress=swfac1".to_string())
+        );
+
+        let clashing = aliases.merge(&Aliases::new(vec![("soil_water_deficit_stress", "day")]));
+        assert!(matches!(
+            clashing.rename(&rb),
+            Err(AliasError::Clash(ref name)) if name == "day"
+        ));
+        let ambiguous = aliases.merge(&Aliases::new(vec![("day", "swfac1")]));
+        assert_eq!(
+            ambiguous.problems(),
+            vec!["day and soil_water_deficit_stress are both aliased to swfac1"]
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Bundling a finished experiment into one read-only zip file
+//!
+//! The archive holds the sinks and logs of every trial with an index of
+//! where they came from. Entries are read straight from the zip, so results
+//! can be queried after the run directories are deleted without unpacking
+//! anything.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use arrow::record_batch::RecordBatch;
+use parquet::file::serialized_reader::SliceableCursor;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::experiment::{
+    feather_batches, parquet_batches, ExperimentConfig, ExperimentError, ExperimentStatus,
+    ResourceConfig,
+};
+use crate::manifest::RunManifest;
+use crate::repro::sha256_file;
+use crate::schema::{current_version, SCHEMA_VERSION};
+
+/// The name of the index entry of an archive
+pub const INDEX_ENTRY: &str = "index.json";
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("{0} is not in the archive")]
+    MissingEntry(String),
+    #[error("sink {trial}.{sink} is not in the archive")]
+    UnknownSink { trial: String, sink: String },
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A sink of a trial and the entries holding its files
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchivedResource {
+    pub resource: ResourceConfig,
+    pub entries: Vec<String>,
+}
+
+/// What an archive holds and where each entry came from
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ArchiveIndex {
+    /// The schema version, see [`crate::schema`]
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub created: u64,
+    pub config: ExperimentConfig,
+    pub status: ExperimentStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<RunManifest>,
+    /// The sinks of each trial, by trial and sink name
+    pub sinks: BTreeMap<String, BTreeMap<String, ArchivedResource>>,
+    /// The log entries of each trial, by trial and stream
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub logs: BTreeMap<String, BTreeMap<String, String>>,
+    /// The sha256 of each entry, by entry name
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl ArchiveIndex {
+    /// The original files archived, which may be deleted afterwards
+    pub fn original_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self
+            .sinks
+            .values()
+            .flat_map(|sinks| sinks.values())
+            .flat_map(|s| s.resource.files())
+            .collect();
+        if let Some(manifest) = &self.manifest {
+            files.extend(
+                manifest
+                    .trials
+                    .values()
+                    .flat_map(|t| t.logs.values())
+                    .map(PathBuf::from),
+            );
+        }
+        files
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "data".to_string())
+}
+
+/// Write the sinks and logs of an experiment to a zip archive at `output`
+///
+/// Relative sink paths are resolved against the working directory as when
+/// the experiment ran. `created` is a unix time.
+pub fn archive(
+    config_path: &Path,
+    output: &Path,
+    created: u64,
+) -> Result<ArchiveIndex, ArchiveError> {
+    let config = ExperimentConfig::load(config_path)?;
+    let status = ExperimentStatus::load(&config, &ExperimentStatus::path_for(config_path))?;
+    let manifest_path = RunManifest::path_for(config_path);
+    let manifest = if manifest_path.exists() {
+        Some(RunManifest::load(&manifest_path)?)
+    } else {
+        None
+    };
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut checksums = BTreeMap::new();
+    let mut add =
+        |zip: &mut ZipWriter<File>, name: String, path: &Path| -> Result<String, ArchiveError> {
+            zip.start_file(name.as_str(), options)?;
+            std::io::copy(&mut File::open(path)?, zip)?;
+            checksums.insert(name.clone(), sha256_file(path)?);
+            Ok(name)
+        };
+
+    let mut sinks = BTreeMap::new();
+    for trial in config.trials.iter() {
+        let mut archived = BTreeMap::new();
+        for (name, sink) in trial.sinks.iter() {
+            let mut entries = vec![];
+            for path in sink.files() {
+                let entry = format!("sinks/{}/{}/{}", trial.name, name, file_name(&path));
+                entries.push(add(&mut zip, entry, &path)?);
+            }
+            archived.insert(
+                name.clone(),
+                ArchivedResource {
+                    resource: sink.clone(),
+                    entries,
+                },
+            );
+        }
+        sinks.insert(trial.name.clone(), archived);
+    }
+
+    let mut logs = BTreeMap::new();
+    for (trial, t) in manifest.iter().flat_map(|m| m.trials.iter()) {
+        let mut streams = BTreeMap::new();
+        for (stream, path) in t.logs.iter() {
+            let path = Path::new(path);
+            if path.is_file() {
+                let entry = format!("logs/{}", file_name(path));
+                streams.insert(stream.clone(), add(&mut zip, entry, path)?);
+            }
+        }
+        if !streams.is_empty() {
+            logs.insert(trial.clone(), streams);
+        }
+    }
+
+    let index = ArchiveIndex {
+        version: SCHEMA_VERSION,
+        created,
+        config,
+        status,
+        manifest,
+        sinks,
+        logs,
+        checksums,
+    };
+    zip.start_file(INDEX_ENTRY, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&index)?)?;
+    zip.finish()?;
+    Ok(index)
+}
+
+/// Reads results out of an archive without unpacking it
+pub struct ArchiveReader {
+    zip: ZipArchive<File>,
+    index: ArchiveIndex,
+}
+
+impl ArchiveReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ArchiveError> {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        let index = match zip.by_name(INDEX_ENTRY) {
+            Ok(entry) => serde_json::from_reader(entry)?,
+            Err(zip::result::ZipError::FileNotFound) => {
+                return Err(ArchiveError::MissingEntry(INDEX_ENTRY.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { zip, index })
+    }
+
+    pub fn index(&self) -> &ArchiveIndex {
+        &self.index
+    }
+
+    /// The contents of an entry
+    pub fn read_entry(&mut self, name: &str) -> Result<Vec<u8>, ArchiveError> {
+        let mut entry = match self.zip.by_name(name) {
+            Ok(entry) => entry,
+            Err(zip::result::ZipError::FileNotFound) => {
+                return Err(ArchiveError::MissingEntry(name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// The record batches of a feather or parquet sink of a trial
+    pub fn read_batches(
+        &mut self,
+        trial: &str,
+        sink: &str,
+    ) -> Result<Vec<RecordBatch>, ArchiveError> {
+        let archived = self
+            .index
+            .sinks
+            .get(trial)
+            .and_then(|s| s.get(sink))
+            .ok_or_else(|| ArchiveError::UnknownSink {
+                trial: trial.to_string(),
+                sink: sink.to_string(),
+            })?
+            .clone();
+        let entry = archived
+            .entries
+            .first()
+            .ok_or_else(|| ArchiveError::MissingEntry(archived.resource.path().to_string()))?;
+        let data = self.read_entry(entry)?;
+        let batches = match archived.resource {
+            ResourceConfig::Feather(_) => feather_batches(Cursor::new(data))?,
+            ResourceConfig::Parquet(_) => parquet_batches(SliceableCursor::new(data))?,
+            r => return Err(ExperimentError::NotTabular(r.path().to_string()).into()),
+        };
+        Ok(batches)
+    }
+
+    /// What the model program of a trial printed, by stream
+    pub fn logs(&mut self, trial: &str) -> Result<BTreeMap<String, String>, ArchiveError> {
+        let entries = self.index.logs.get(trial).cloned().unwrap_or_default();
+        let mut logs = BTreeMap::new();
+        for (stream, entry) in entries {
+            let text = String::from_utf8_lossy(&self.read_entry(&entry)?).to_string();
+            logs.insert(stream, text);
+        }
+        Ok(logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::ipc::writer::FileWriter;
+    use arrow::record_batch::RecordBatch;
+
+    use crate::archive::{archive, ArchiveError, ArchiveReader};
+
+    #[test]
+    fn read_results_from_archive() {
+        let dir = std::env::temp_dir().join(format!("meillionen-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("day", DataType::Int32, false)]));
+        let columns: Vec<ArrayRef> = vec![Arc::new(Int32Array::from(vec![1, 2, 3]))];
+        let rb = RecordBatch::try_new(schema.clone(), columns).unwrap();
+        let sink = dir.join("yearly.feather");
+        let mut writer =
+            FileWriter::try_new(std::fs::File::create(&sink).unwrap(), &schema).unwrap();
+        writer.write(&rb).unwrap();
+        writer.finish().unwrap();
+        let config = dir.join("irrigation.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "name = \"irrigation\"\n\n[[trials]]\nname = \"baseline\"\nmodel = \"simplecrop_omf\"\n\n\
+                 [trials.sinks.yearly]\ntype = \"feather\"\npath = {:?}\n",
+                sink.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let output = dir.join("irrigation.zip");
+        let index = archive(&config, &output, 0).unwrap();
+        assert_eq!(index.original_files(), vec![sink.clone()]);
+        std::fs::remove_file(&sink).unwrap();
+        let mut reader = ArchiveReader::open(&output).unwrap();
+        let batches = reader.read_batches("baseline", "yearly");
+        let unknown = reader.read_batches("baseline", "daily");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let batches = batches.unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].column(0).data(), rb.column(0).data());
+        assert!(matches!(unknown, Err(ArchiveError::UnknownSink { .. })));
+        assert_eq!(
+            reader.index().sinks["baseline"]["yearly"].entries,
+            vec!["sinks/baseline/yearly/yearly.feather"]
+        );
+    }
+}
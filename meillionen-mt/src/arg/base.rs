@@ -1,14 +1,14 @@
 #[macro_export]
 macro_rules! impl_try_from_u8 {
- ($T:ty) => {
-    impl std::convert::TryFrom<&[u8]> for $T {
-        type Error = serde_json::Error;
+    ($T:ty) => {
+        impl std::convert::TryFrom<&[u8]> for $T {
+            type Error = serde_json::Error;
 
-        fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-            serde_json::from_reader(value)
+            fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+                serde_json::from_reader(value)
+            }
         }
-    }
- }
+    };
 }
 
 #[macro_export]
@@ -21,5 +21,5 @@ macro_rules! impl_try_from_validator {
                 serde_json::to_vec(value)
             }
         }
-    }
-}
\ No newline at end of file
+    };
+}
@@ -1,3 +1,4 @@
+mod base;
 pub mod resource;
+#[cfg(feature = "data")]
 pub mod schema;
-mod base;
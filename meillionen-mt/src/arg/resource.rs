@@ -1,16 +1,104 @@
 use crate::{impl_try_from_u8, impl_try_from_validator};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CompressionError {
+    #[error("deflate level must be between 1 and 9 but is {0}")]
+    InvalidDeflateLevel(u8),
+    #[error("{got} chunk sizes given for a variable with {expected} dimensions")]
+    ChunkRank { expected: usize, got: usize },
+    #[error("chunk sizes must be greater than zero")]
+    EmptyChunk,
+}
+
+/// How a NetCDF variable is compressed and chunked when it is written
+///
+/// Without a deflate level the variable is stored uncompressed. Shuffling
+/// bytes before deflating usually compresses floating point data better.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Compression {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deflate_level: Option<u8>,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_sizes: Option<Vec<usize>>,
+}
+
+impl Compression {
+    pub fn validate(&self) -> Result<(), CompressionError> {
+        match self.deflate_level {
+            Some(level) if !(1..=9).contains(&level) => {
+                Err(CompressionError::InvalidDeflateLevel(level))
+            }
+            _ => match self.chunk_sizes.as_ref() {
+                Some(sizes) if sizes.contains(&0) => Err(CompressionError::EmptyChunk),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// The chunk shape to use for a variable of the given shape
+    ///
+    /// Chunks larger than a dimension are cut down to the dimension's size.
+    pub fn chunk_shape(&self, shape: &[usize]) -> Result<Option<Vec<usize>>, CompressionError> {
+        self.validate()?;
+        let sizes = match self.chunk_sizes.as_ref() {
+            Some(sizes) => sizes,
+            None => return Ok(None),
+        };
+        if sizes.len() != shape.len() {
+            return Err(CompressionError::ChunkRank {
+                expected: shape.len(),
+                got: sizes.len(),
+            });
+        }
+        Ok(Some(
+            sizes
+                .iter()
+                .zip(shape)
+                .map(|(&c, &s)| c.min(s.max(1)))
+                .collect(),
+        ))
+    }
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NetCDFResource {
     pub path: String,
     pub variable: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
 }
 
 impl_try_from_u8!(NetCDFResource);
 impl_try_from_validator!(NetCDFResource);
 
+/// A NetCDF variable split across files matching a glob pattern
+///
+/// The files are ordered by path and joined along `dimension`, so files
+/// named by year (`rain_1990.nc`, `rain_1991.nc`, ...) form one time series.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MultiNetCDFResource {
+    pub pattern: String,
+    pub variable: String,
+    pub dimension: String,
+}
+
+impl MultiNetCDFResource {
+    pub fn paths(&self) -> Result<Vec<PathBuf>, glob::PatternError> {
+        let mut paths: Vec<PathBuf> = glob::glob(&self.pattern)?.filter_map(Result::ok).collect();
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+impl_try_from_u8!(MultiNetCDFResource);
+impl_try_from_validator!(MultiNetCDFResource);
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FeatherResource {
     pub path: String,
@@ -34,3 +122,71 @@ pub struct FileResource {
 
 impl_try_from_u8!(FileResource);
 impl_try_from_validator!(FileResource);
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, File};
+    use std::path::PathBuf;
+
+    use crate::arg::resource::{
+        Compression, CompressionError, MultiNetCDFResource, NetCDFResource,
+    };
+
+    #[test]
+    fn multi_netcdf_paths() {
+        let dir = std::env::temp_dir().join(format!("meillionen-mfdataset-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        for name in ["rain_1991.nc", "rain_1990.nc", "other.nc"].iter() {
+            File::create(dir.join(name)).unwrap();
+        }
+        let r = MultiNetCDFResource {
+            pattern: dir.join("rain_*.nc").to_string_lossy().to_string(),
+            variable: "rainfall".to_string(),
+            dimension: "time".to_string(),
+        };
+        let paths = r.paths().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let expected: Vec<PathBuf> = vec![dir.join("rain_1990.nc"), dir.join("rain_1991.nc")];
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn compression_chunks() {
+        let c = Compression {
+            deflate_level: Some(4),
+            shuffle: true,
+            chunk_sizes: Some(vec![365, 100, 100]),
+        };
+        assert_eq!(
+            c.chunk_shape(&[730, 50, 200]).unwrap(),
+            Some(vec![365, 50, 100])
+        );
+        assert_eq!(
+            c.chunk_shape(&[730, 50]).err().unwrap(),
+            CompressionError::ChunkRank {
+                expected: 2,
+                got: 3
+            }
+        );
+        assert_eq!(Compression::default().chunk_shape(&[10]).unwrap(), None);
+        let c = Compression {
+            deflate_level: Some(10),
+            ..Compression::default()
+        };
+        assert_eq!(
+            c.validate().err().unwrap(),
+            CompressionError::InvalidDeflateLevel(10)
+        );
+    }
+
+    #[test]
+    fn netcdf_without_compression() {
+        let r: NetCDFResource =
+            serde_json::from_str(r#"{"path": "out.nc", "variable": "yield"}"#).unwrap();
+        assert_eq!(r.compression, None);
+        assert_eq!(
+            serde_json::to_string(&r).unwrap(),
+            r#"{"path":"out.nc","variable":"yield"}"#
+        );
+    }
+}
@@ -1,5 +1,10 @@
-use arrow::datatypes::{Field};
+use crate::validation::{validate, Rule, ValidationError};
+use crate::variable::cf::{check_variable, CfIssue};
+use crate::variable::table::to_recordbatch;
+use crate::variable::{Attributes, VecVariable};
 use crate::{impl_try_from_u8, impl_try_from_validator};
+use arrow::datatypes::Field;
+use arrow::record_batch::RecordBatch;
 use serde_derive::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -8,6 +13,28 @@ pub struct TensorSchema {
     pub dimensions: Vec<String>,
     pub data_type: arrow::datatypes::DataType,
     pub resources: Vec<String>,
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// Rules on the values, with the variable name and its dimensions as
+    /// columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+}
+
+impl TensorSchema {
+    /// Check that a variable written with this schema follows the CF conventions
+    pub fn check_cf(&self, name: &str) -> Vec<CfIssue> {
+        check_variable(name, &self.dimensions, &self.attributes)
+    }
+
+    /// The rules the variable breaks
+    pub fn check(
+        &self,
+        name: &str,
+        variable: &VecVariable<f64>,
+    ) -> Result<Vec<String>, ValidationError> {
+        validate(&self.rules, &[to_recordbatch(variable, name)?])
+    }
 }
 
 impl_try_from_u8!(TensorSchema);
@@ -29,6 +56,8 @@ pub struct DataFrameSchema {
     columns: Arc<Columns>,
     description: String,
     resources: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<Rule>,
 }
 
 impl DataFrameSchema {
@@ -36,9 +65,24 @@ impl DataFrameSchema {
         Self {
             resources,
             description: description.to_string(),
-            columns
+            columns,
+            rules: vec![],
         }
     }
+
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The rules a table read or written with this schema breaks
+    pub fn check(&self, batches: &[RecordBatch]) -> Result<Vec<String>, ValidationError> {
+        validate(&self.rules, batches)
+    }
 }
 
 impl_try_from_u8!(DataFrameSchema);
@@ -46,16 +90,16 @@ impl_try_from_validator!(DataFrameSchema);
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Schemaless {
-    ext: String
+    ext: String,
 }
 
 impl Schemaless {
     pub fn new(ext: &str) -> Self {
         Self {
-            ext: ext.to_string()
+            ext: ext.to_string(),
         }
     }
 }
 
 impl_try_from_u8!(Schemaless);
-impl_try_from_validator!(Schemaless);
\ No newline at end of file
+impl_try_from_validator!(Schemaless);
@@ -0,0 +1,1721 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use stable_eyre::eyre::{eyre, WrapErr};
+
+use meillionen_mt::archive::{archive, ArchiveReader};
+use meillionen_mt::compare::{compare, RunResults, ToleranceProfile};
+use meillionen_mt::concurrency::{ConcurrencyLimit, Controller, ControllerConfig};
+use meillionen_mt::conservation::check_experiment;
+use meillionen_mt::design::FactorialConfig;
+use meillionen_mt::determinism::set_deterministic;
+use meillionen_mt::diff::{diff_table, diff_with_tolerance, load_config};
+use meillionen_mt::experiment::{
+    export, export_batches, ExperimentConfig, ExperimentStatus, ExportFormat, TrialConfig,
+    TrialLogs, TrialStatus,
+};
+use meillionen_mt::gc::{self, parse_size, GcPolicy};
+use meillionen_mt::infer::{infer, ParsingSpec};
+use meillionen_mt::manifest::{checksums, RunManifest, TrialManifest};
+#[cfg(feature = "sqlite")]
+use meillionen_mt::metrics::{serve_metrics, ServiceMetrics};
+use meillionen_mt::model::{client_create_interface_from_cli, InterfaceArg};
+#[cfg(feature = "postgres-sink")]
+use meillionen_mt::postgres::PostgresSink;
+use meillionen_mt::progress::EnsembleProgress;
+use meillionen_mt::provenance::{prov_json, write_ro_crate};
+#[cfg(feature = "sqlite")]
+use meillionen_mt::queue::{workspace_bytes, Job, JobQueue, JobState};
+use meillionen_mt::report::{experiment_report, html_diff};
+use meillionen_mt::repro::timestamp;
+use meillionen_mt::scheduler::Scheduler;
+use meillionen_mt::sites::MultiSiteConfig;
+use meillionen_mt::strictness::set_strict;
+#[cfg(feature = "sqlite")]
+use meillionen_mt::submission::{Submission, SubmissionClient, SubmissionService};
+use meillionen_mt::tags::TagFilter;
+use meillionen_mt::verbosity::{set_verbosity, Verbosity};
+use meillionen_mt::workflow;
+
+fn config_arg() -> Arg<'static, 'static> {
+    Arg::with_name("config")
+        .help("experiment file (.toml, .yaml or .yml)")
+        .required(true)
+}
+
+fn tolerance_arg() -> Arg<'static, 'static> {
+    Arg::with_name("tolerance")
+        .long("tolerance")
+        .takes_value(true)
+        .default_value("1e-9")
+        .help("relative difference below which numbers are the same")
+}
+
+fn tag_arg() -> Arg<'static, 'static> {
+    Arg::with_name("tag")
+        .long("tag")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("only trials tagged key=value, or with key set (may be repeated)")
+}
+
+fn tag_filters(matches: &ArgMatches) -> stable_eyre::Result<Vec<TagFilter>> {
+    Ok(matches
+        .values_of("tag")
+        .map(|tags| tags.map(str::parse).collect::<Result<Vec<_>, _>>())
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn load<'a>(matches: &'a ArgMatches) -> stable_eyre::Result<(ExperimentConfig, &'a str)> {
+    let path = matches.value_of("config").expect("config to be required");
+    let config =
+        ExperimentConfig::load(path).wrap_err_with(|| format!("could not load {}", path))?;
+    Ok((config, path))
+}
+
+fn validate(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (config, path) = load(matches)?;
+    config.validate()?;
+    println!("{} is valid ({} trials)", path, config.trials.len());
+    Ok(())
+}
+
+fn hash(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (config, _) = load(matches)?;
+    println!("{}", config.content_hash()?);
+    Ok(())
+}
+
+/// The database the outputs of successful trials are stored in, if
+/// `--postgres` is given
+#[cfg(feature = "postgres-sink")]
+fn results_db(matches: &ArgMatches) -> stable_eyre::Result<Option<PostgresSink>> {
+    matches
+        .value_of("postgres")
+        .map(|params| {
+            PostgresSink::connect_with_root_certificate(
+                params,
+                matches.value_of("postgres-ca").map(Path::new),
+            )
+            .wrap_err("could not connect to the results database")
+        })
+        .transpose()
+}
+
+#[cfg(not(feature = "postgres-sink"))]
+enum ResultsDb {}
+
+#[cfg(not(feature = "postgres-sink"))]
+impl ResultsDb {
+    fn record_trial(
+        &mut self,
+        _: &str,
+        _: &meillionen_mt::experiment::TrialConfig,
+        _: &meillionen_mt::tags::Tags,
+        _: &str,
+    ) -> stable_eyre::Result<i64> {
+        match *self {}
+    }
+
+    fn delete_trials(&mut self, _: &str, _: &[String]) -> stable_eyre::Result<u64> {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "postgres-sink"))]
+fn results_db(matches: &ArgMatches) -> stable_eyre::Result<Option<ResultsDb>> {
+    match matches.value_of("postgres") {
+        Some(_) => Err(eyre!(
+            "meillionen was built without the postgres-sink feature"
+        )),
+        None => Ok(None),
+    }
+}
+
+/// The environment variable the results database connection string is read
+/// from, which is how `serve` passes it to runs so passwords stay off their
+/// command lines
+const POSTGRES_ENV: &str = "MEILLIONEN_POSTGRES";
+
+/// Where to store trial outputs, shared by `run` and `serve`, which passes
+/// them on to the runs of its jobs
+fn postgres_args() -> [Arg<'static, 'static>; 3] {
+    [
+        Arg::with_name("postgres")
+            .long("postgres")
+            .takes_value(true)
+            .env(POSTGRES_ENV)
+            .hide_env_values(true)
+            .help("connection string of a database to store the outputs of successful trials in (needs the postgres-sink feature)"),
+        Arg::with_name("postgres-ca")
+            .long("postgres-ca")
+            .takes_value(true)
+            .help("PEM certificate to trust for the database as well as the system's"),
+        Arg::with_name("postgres-step")
+            .long("postgres-step")
+            .takes_value(true)
+            .default_value("day")
+            .help("column of the sinks that indexes the stored outputs"),
+    ]
+}
+
+fn run(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (config, path) = load(matches)?;
+    config.validate()?;
+    let status_path = ExperimentStatus::path_for(Path::new(path));
+    let status = ExperimentStatus::load(&config, &status_path)?;
+    let filters = tag_filters(matches)?;
+    let trials: Vec<_> = match matches.values_of("trial") {
+        Some(names) => names
+            .map(|n| config.trial(n))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => config.ordered_trials(),
+    }
+    .into_iter()
+    .filter(|t| TagFilter::all(&filters, &config.trial_tags(t)))
+    .collect();
+    let repro = config.repro.as_ref();
+    let manifest_path = RunManifest::path_for(Path::new(path));
+    let logs_dir = RunManifest::logs_dir_for(Path::new(path));
+    let names: Vec<&str> = trials.iter().map(|t| t.name.as_str()).collect();
+    let jobs = matches
+        .value_of("jobs")
+        .expect("jobs to have a default")
+        .parse::<usize>()
+        .wrap_err("--jobs must be a number of trials")?;
+    let state = Mutex::new(RunState {
+        progress: EnsembleProgress::new(&names),
+        reporter: Reporter::new(matches.is_present("tui"))?,
+        manifest: RunManifest::new(&config, timestamp(repro)),
+        status,
+    });
+    let results_db = results_db(matches)?.map(Mutex::new);
+    let step = matches
+        .value_of("postgres-step")
+        .expect("step to have a default");
+    let hints: Vec<Option<f64>> = trials.iter().map(|t| t.cost).collect();
+    let mut scheduler = Scheduler::new(jobs);
+    let _watcher = if matches.is_present("adaptive") {
+        let mut controller = ControllerConfig::new(jobs);
+        controller.target_load = matches
+            .value_of("target-load")
+            .expect("target load to have a default")
+            .parse()
+            .wrap_err("--target-load must be a number of threads per CPU")?;
+        let limit = Arc::new(ConcurrencyLimit::new(jobs));
+        scheduler = scheduler.limited(limit.clone());
+        Some(Controller::new(controller).watch(limit))
+    } else {
+        None
+    };
+    let (results, load) =
+        scheduler.run_with_hints(&trials, &hints, |trial| -> stable_eyre::Result<()> {
+            {
+                let mut state = state.lock().expect("run state not to be poisoned");
+                let RunState {
+                    progress, reporter, ..
+                } = &mut *state;
+                progress.start(&trial.name);
+                reporter.started(progress, &trial.name)?;
+            }
+            let started = timestamp(repro);
+            let seed = repro.map(|r| r.trial_seed(&trial.name));
+            let pinned = repro.map(|r| r.check_executable(&trial.model));
+            let model_sha256 = match &pinned {
+                Some(Ok(hash)) => Some(hash.clone()),
+                _ => None,
+            };
+            let inputs = checksums(trial.sources.values())?;
+            let (result, logs) = match pinned {
+                Some(Err(e)) => (Err(e.to_string()), TrialLogs::default()),
+                _ => TrialConfig {
+                    aliases: config.trial_aliases(trial),
+                    ..(*trial).clone()
+                }
+                .run_logged(seed)
+                .unwrap_or_else(|e| (Err(e.to_string()), TrialLogs::default())),
+            };
+            let logs = logs.save(&logs_dir, &trial.name)?;
+            let outputs = checksums(trial.sinks.values())?;
+            let recorded = match (&results_db, &result) {
+                (Some(db), Ok(())) => db
+                    .lock()
+                    .expect("results database not to be poisoned")
+                    .record_trial(&config.name, trial, &config.trial_tags(trial), step)
+                    .map(|_| ()),
+                _ => Ok(()),
+            };
+
+            let mut state = state.lock().expect("run state not to be poisoned");
+            let RunState {
+                progress,
+                reporter,
+                manifest,
+                status,
+            } = &mut *state;
+            progress.finish(&trial.name, result.clone());
+            reporter.finished(progress, &trial.name, &result)?;
+            let outcome = match result {
+                Ok(()) => TrialStatus::Succeeded,
+                Err(message) => TrialStatus::Failed { message },
+            };
+            manifest.trials.insert(
+                trial.name.clone(),
+                TrialManifest {
+                    model: trial.model.clone(),
+                    model_sha256,
+                    seed,
+                    args: trial.args.clone(),
+                    env: trial.env.clone(),
+                    started,
+                    finished: timestamp(repro),
+                    status: outcome.clone(),
+                    inputs,
+                    outputs,
+                    pruned: None,
+                    logs,
+                    tags: config.trial_tags(trial),
+                    transforms: trial.transforms.clone(),
+                },
+            );
+            status.trials.insert(trial.name.clone(), outcome);
+            // saved after every trial so an interrupted experiment can be inspected
+            status.save(&status_path)?;
+            manifest.save(&manifest_path)?;
+            recorded.wrap_err_with(|| format!("could not store the outputs of {}", trial.name))
+        });
+    let RunState {
+        progress,
+        mut reporter,
+        ..
+    } = state.into_inner().expect("run state not to be poisoned");
+    results
+        .into_iter()
+        .collect::<stable_eyre::Result<Vec<()>>>()?;
+    if jobs > 1 {
+        tracing::info!("{}", load);
+    }
+    for t in load.tasks.iter() {
+        tracing::debug!(
+            trial = %trials[t.task].name,
+            worker = t.worker,
+            seconds = t.seconds,
+            stolen = t.stolen,
+            "trial timing"
+        );
+    }
+    reporter.done(&progress)?;
+    let failed = progress.failed();
+    if failed > 0 {
+        return Err(eyre!("{} trials failed", failed));
+    }
+    let imbalances = check_experiment(&config)?;
+    for imbalance in imbalances.iter() {
+        tracing::error!("{}", imbalance);
+    }
+    if imbalances.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!("{} conservation checks failed", imbalances.len()))
+    }
+}
+
+/// What the trials of a run update as they finish
+struct RunState {
+    progress: EnsembleProgress,
+    reporter: Reporter,
+    manifest: RunManifest,
+    status: ExperimentStatus,
+}
+
+/// Shows the progress of a run on stderr or in a dashboard
+struct Reporter {
+    #[cfg(feature = "tui")]
+    dashboard: Option<meillionen_mt::tui::Dashboard>,
+}
+
+impl Reporter {
+    fn new(tui: bool) -> stable_eyre::Result<Self> {
+        #[cfg(feature = "tui")]
+        {
+            let dashboard = if tui {
+                Some(meillionen_mt::tui::Dashboard::new()?)
+            } else {
+                None
+            };
+            Ok(Self { dashboard })
+        }
+        #[cfg(not(feature = "tui"))]
+        {
+            if tui {
+                return Err(eyre!("meillionen was built without the tui feature"));
+            }
+            Ok(Self {})
+        }
+    }
+
+    #[cfg(feature = "tui")]
+    fn dashboard(&mut self) -> Option<&mut meillionen_mt::tui::Dashboard> {
+        self.dashboard.as_mut()
+    }
+
+    #[cfg(not(feature = "tui"))]
+    fn dashboard(&mut self) -> Option<&mut Never> {
+        None
+    }
+
+    fn started(&mut self, progress: &EnsembleProgress, name: &str) -> stable_eyre::Result<()> {
+        match self.dashboard() {
+            Some(d) => d.draw(progress)?,
+            None => tracing::info!("running {}", name),
+        }
+        Ok(())
+    }
+
+    fn finished(
+        &mut self,
+        progress: &EnsembleProgress,
+        name: &str,
+        result: &Result<(), String>,
+    ) -> stable_eyre::Result<()> {
+        match (self.dashboard(), result) {
+            (Some(d), _) => d.draw(progress)?,
+            (None, Err(message)) => tracing::error!("{} failed:\n{}", name, message),
+            (None, Ok(())) => {}
+        }
+        Ok(())
+    }
+
+    fn done(&mut self, progress: &EnsembleProgress) -> stable_eyre::Result<()> {
+        if let Some(d) = self.dashboard() {
+            d.draw(progress)?;
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Stands in for the dashboard when the tui feature is off
+#[cfg(not(feature = "tui"))]
+enum Never {}
+
+#[cfg(not(feature = "tui"))]
+impl Never {
+    fn draw(&mut self, _: &EnsembleProgress) -> std::io::Result<()> {
+        match *self {}
+    }
+}
+
+fn status(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (config, path) = load(matches)?;
+    let status = ExperimentStatus::load(&config, &ExperimentStatus::path_for(Path::new(path)))?;
+    let filters = tag_filters(matches)?;
+    for (name, s) in status.trials.iter() {
+        let tagged = config
+            .trial(name)
+            .map(|t| TagFilter::all(&filters, &config.trial_tags(t)))
+            .unwrap_or(filters.is_empty());
+        if !tagged {
+            continue;
+        }
+        match s {
+            TrialStatus::Pending => println!("{}\tpending", name),
+            TrialStatus::Succeeded => println!("{}\tsucceeded", name),
+            TrialStatus::Failed { message } => {
+                let first = message.lines().next().unwrap_or("");
+                println!("{}\tfailed\t{}", name, first)
+            }
+        }
+    }
+    Ok(())
+}
+
+fn report(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (config, path) = load(matches)?;
+    let path = Path::new(path);
+    let status = ExperimentStatus::load(&config, &ExperimentStatus::path_for(path))?;
+    let mut report = experiment_report(&config, path, &status)?;
+    if let Some(baseline) = matches.value_of("baseline") {
+        let differences = diff_with_tolerance(
+            &load_config(baseline).wrap_err_with(|| format!("could not load {}", baseline))?,
+            &load_config(path)?,
+            tolerance(matches)?,
+        );
+        report.changes = Some(html_diff(
+            &format!("changes from {}", baseline),
+            &differences,
+        ));
+    }
+    let output = matches.value_of("output").expect("output to be required");
+    std::fs::write(output, report.render())?;
+    Ok(())
+}
+
+fn verify(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = Path::new(matches.value_of("config").expect("config to be required"));
+    let manifest_path = RunManifest::path_for(path);
+    let manifest = RunManifest::load(&manifest_path)
+        .wrap_err_with(|| format!("could not load {}", manifest_path.display()))?;
+    let base = match matches.value_of("base") {
+        Some(base) => Path::new(base).to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let problems = manifest.verify(&base)?;
+    for p in problems.iter() {
+        println!("{}", p);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn gc(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = Path::new(matches.value_of("config").expect("config to be required"));
+    let manifest_path = RunManifest::path_for(path);
+    let mut manifest = RunManifest::load(&manifest_path)
+        .wrap_err_with(|| format!("could not load {}", manifest_path.display()))?;
+    let base = match matches.value_of("base") {
+        Some(base) => Path::new(base).to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let policy = GcPolicy {
+        older_than: matches
+            .value_of("older-than")
+            .map(|d| d.parse::<u64>().map(|d| d * 24 * 60 * 60))
+            .transpose()
+            .wrap_err("--older-than must be a number of days")?,
+        status: matches.value_of("status").map(str::parse).transpose()?,
+        max_bytes: matches.value_of("max-size").map(parse_size).transpose()?,
+        tags: tag_filters(matches)?,
+        all: matches.is_present("all"),
+    };
+    if !(policy.has_filters() || policy.all) {
+        return Err(eyre!(
+            "gc needs --older-than, --status, --max-size or --tag, or --all to prune every trial"
+        ));
+    }
+    let now = timestamp(None);
+    let plan = gc::plan(&manifest, &base, &policy, now)?;
+    for f in plan.files.iter() {
+        println!("{}", f.display());
+    }
+    println!(
+        "{} {} bytes from {} trials",
+        if matches.is_present("dry-run") {
+            "would free"
+        } else {
+            "freed"
+        },
+        plan.bytes,
+        plan.trials.len()
+    );
+    if !matches.is_present("dry-run") {
+        let mut results_db = results_db(matches)?;
+        gc::collect(&mut manifest, &plan, now)?;
+        manifest.save(&manifest_path)?;
+        if let Some(db) = results_db.as_mut() {
+            let deleted = db.delete_trials(&manifest.experiment, &plan.trials)?;
+            println!("deleted {} runs from the results database", deleted);
+        }
+    }
+    Ok(())
+}
+
+fn archive_experiment(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = Path::new(matches.value_of("config").expect("config to be required"));
+    let output = Path::new(matches.value_of("output").expect("output to be required"));
+    let index = archive(path, output, timestamp(None))
+        .wrap_err_with(|| format!("could not archive {}", path.display()))?;
+    // read it back before deleting anything
+    ArchiveReader::open(output)?;
+    println!(
+        "archived {} files to {}",
+        index.checksums.len(),
+        output.display()
+    );
+    if matches.is_present("remove") {
+        for f in index.original_files() {
+            match std::fs::remove_file(&f) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn provenance(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = Path::new(matches.value_of("config").expect("config to be required"));
+    let output = matches.value_of("output");
+    match matches
+        .value_of("format")
+        .expect("format to have a default")
+    {
+        "ro-crate" => {
+            let output = Path::new(output.ok_or_else(|| eyre!("an ro-crate needs --output"))?);
+            write_ro_crate(path, output)
+                .wrap_err_with(|| format!("could not write a crate of {}", path.display()))?;
+            println!("wrote {}", output.display());
+        }
+        _ => {
+            let manifest_path = RunManifest::path_for(path);
+            let manifest = RunManifest::load(&manifest_path)
+                .wrap_err_with(|| format!("could not load {}", manifest_path.display()))?;
+            let prov = serde_json::to_string_pretty(&prov_json(&manifest))?;
+            match output {
+                Some(output) => std::fs::write(output, prov)?,
+                None => println!("{}", prov),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn export_results(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = matches.value_of("config").expect("config to be required");
+    let trial = matches.value_of("trial").expect("trial to be required");
+    let sink = matches.value_of("sink").expect("sink to be required");
+    let format: ExportFormat = matches
+        .value_of("format")
+        .expect("format to have a default")
+        .parse()?;
+    let output = Path::new(matches.value_of("output").expect("output to be required"));
+    if path.ends_with(".zip") {
+        let batches = ArchiveReader::open(path)?.read_batches(trial, sink)?;
+        export_batches(&batches, format, output)?;
+    } else {
+        let (config, _) = load(matches)?;
+        export(config.trial(trial)?.sink(sink)?, format, output)?;
+    }
+    Ok(())
+}
+
+fn interface(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let model = matches.value_of("model").expect("model to be required");
+    let args = InterfaceArg::from_recordbatch(&client_create_interface_from_cli(model)?)?;
+    match matches
+        .value_of("format")
+        .expect("format to have a default")
+    {
+        "json" => println!("{}", serde_json::to_string_pretty(&args)?),
+        "text" => {
+            let descriptions: Vec<String> = args.iter().map(InterfaceArg::describe).collect();
+            println!("{}", descriptions.join("\n\n"));
+        }
+        format => print!("{}", workflow::describe(model, &args, format.parse()?)),
+    }
+    Ok(())
+}
+
+fn call(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let model = matches.value_of("model").expect("model to be required");
+    let bindings = |name: &str| {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(workflow::parse_binding)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let trial = workflow::call_trial(model, &bindings("source")?, &bindings("sink")?);
+    trial.run(None)?.map_err(|message| eyre!(message))
+}
+
+fn tolerance(matches: &ArgMatches) -> stable_eyre::Result<f64> {
+    matches
+        .value_of("tolerance")
+        .expect("tolerance to have a default")
+        .parse()
+        .wrap_err("--tolerance must be a relative difference such as 1e-6")
+}
+
+fn diff_config(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let load = |name: &str| {
+        let path = matches.value_of(name).expect("configs to be required");
+        load_config(path).wrap_err_with(|| format!("could not load {}", path))
+    };
+    let differences = diff_with_tolerance(&load("left")?, &load("right")?, tolerance(matches)?);
+    if !differences.is_empty() {
+        print!("{}", diff_table(&differences));
+    }
+    // like diff, exit with 1 when the files differ
+    if !differences.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn compare_runs(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let open = |name: &str| {
+        let path = matches.value_of(name).expect("runs to be required");
+        RunResults::open(path).wrap_err_with(|| format!("could not load {}", path))
+    };
+    let profile = match matches.value_of("tolerances") {
+        Some(path) => {
+            ToleranceProfile::load(path).wrap_err_with(|| format!("could not load {}", path))?
+        }
+        None => ToleranceProfile::default(),
+    };
+    let report = compare(&mut open("reference")?, &mut open("new")?, &profile)?;
+    if matches.value_of("format") == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", report);
+    }
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn infer_spec(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = matches.value_of("sample").expect("sample to be required");
+    let text =
+        std::fs::read_to_string(path).wrap_err_with(|| format!("could not read {}", path))?;
+    if let Some(spec_path) = matches.value_of("check") {
+        let spec = ParsingSpec::load(spec_path)
+            .wrap_err_with(|| format!("could not load {}", spec_path))?;
+        let rb = spec
+            .parse(&text)
+            .wrap_err_with(|| format!("{} does not parse {}", spec_path, path))?;
+        println!(
+            "read {} rows of {} columns",
+            rb.num_rows(),
+            rb.num_columns()
+        );
+        return Ok(());
+    }
+    let spec = infer(&text).wrap_err_with(|| format!("could not infer a spec for {}", path))?;
+    // parse the sample with the spec before proposing it
+    spec.parse(&text)?;
+    match matches.value_of("output") {
+        Some(output) => spec.save(output)?,
+        None => print!("{}", toml::to_string(&spec)?),
+    }
+    Ok(())
+}
+
+/// Save an expanded experiment to the `output` file as yaml, or print it
+fn write_expanded(matches: &ArgMatches, experiment: &ExperimentConfig) -> stable_eyre::Result<()> {
+    let text = serde_yaml::to_string(experiment)?;
+    match matches.value_of("output") {
+        Some(output) => {
+            std::fs::write(output, text)?;
+            println!("wrote {} trials to {}", experiment.trials.len(), output);
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+fn expand_sites(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = matches.value_of("sites").expect("sites to be required");
+    let config =
+        MultiSiteConfig::load(path).wrap_err_with(|| format!("could not load {}", path))?;
+    write_expanded(matches, &config.experiment()?)
+}
+
+fn expand_design(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = matches.value_of("design").expect("design to be required");
+    let config =
+        FactorialConfig::load(path).wrap_err_with(|| format!("could not load {}", path))?;
+    write_expanded(matches, &config.experiment()?)
+}
+
+#[cfg(feature = "sqlite")]
+fn open_queue(matches: &ArgMatches) -> stable_eyre::Result<JobQueue> {
+    let path = matches.value_of("queue").expect("queue to have a default");
+    JobQueue::open(path).wrap_err_with(|| format!("could not open the job queue {}", path))
+}
+
+/// Open the queue file for its admin, who alone may use a shared queue
+/// directly
+#[cfg(feature = "sqlite")]
+fn open_queue_as_admin(matches: &ArgMatches) -> stable_eyre::Result<JobQueue> {
+    let queue = open_queue(matches)?;
+    if queue.is_shared()? {
+        queue.check_admin(matches.value_of("admin-token"))?;
+    }
+    Ok(queue)
+}
+
+#[cfg(feature = "sqlite")]
+fn queue_user(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    match matches.subcommand() {
+        ("add", Some(m)) => {
+            let queue = open_queue(m)?;
+            let name = m.value_of("name").expect("name to be required");
+            let workspace = m.value_of("workspace").expect("workspace to be required");
+            let max_running = m
+                .value_of("max-running")
+                .expect("max-running to have a default")
+                .parse::<usize>()
+                .wrap_err("--max-running must be a number of jobs")?;
+            let max_bytes = m.value_of("max-disk").map(parse_size).transpose()?;
+            let token = queue.add_user(
+                m.value_of("admin-token"),
+                name,
+                Path::new(workspace),
+                max_running,
+                max_bytes,
+            )?;
+            println!("{}", token);
+        }
+        ("list", Some(m)) => {
+            let queue = open_queue_as_admin(m)?;
+            for user in queue.users()? {
+                let used = workspace_bytes(&user.workspace)?;
+                let limit = user
+                    .max_bytes
+                    .map(|b| b.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{}\t{}\t{}\t{}/{}",
+                    user.name,
+                    user.workspace.display(),
+                    user.max_running,
+                    used,
+                    limit
+                );
+            }
+        }
+        ("remove", Some(m)) => {
+            let queue = open_queue(m)?;
+            queue.remove_user(
+                m.value_of("admin-token"),
+                m.value_of("name").expect("name to be required"),
+            )?;
+        }
+        _ => unreachable!("subcommand to be required"),
+    }
+    Ok(())
+}
+
+/// Print jobs as `meillionen queue list` does
+#[cfg(feature = "sqlite")]
+fn print_jobs(jobs: &[Job]) {
+    for job in jobs {
+        let cancelling = if job.cancel_requested && !job.state.is_finished() {
+            " (cancelling)"
+        } else {
+            ""
+        };
+        println!(
+            "{}\t{}{}\t{}\t{}",
+            job.id,
+            job.state,
+            cancelling,
+            job.priority,
+            job.config.display()
+        );
+    }
+}
+
+/// `meillionen queue submit`, `list` and `cancel` against a service started
+/// with `meillionen serve --listen`
+#[cfg(feature = "sqlite")]
+fn queue_remote(server: &str, matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let (command, m) = matches.subcommand();
+    let m = m.expect("subcommand to be required");
+    let client = SubmissionClient::new(server, m.value_of("token"))?;
+    match command {
+        "submit" => {
+            let path = m.value_of("config").expect("config to be required");
+            let experiment = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("could not read {}", path))?;
+            let format = match Path::new(path).extension().and_then(|e| e.to_str()) {
+                Some("yaml") | Some("yml") => "yaml",
+                _ => "toml",
+            };
+            let id = client.submit(&Submission {
+                experiment,
+                format: format.to_string(),
+                dir: m.value_of("dir").map(Into::into),
+                priority: m
+                    .value_of("priority")
+                    .expect("priority to have a default")
+                    .parse::<i64>()
+                    .wrap_err("--priority must be a whole number")?,
+                jobs: m
+                    .value_of("jobs")
+                    .expect("jobs to have a default")
+                    .parse::<usize>()
+                    .wrap_err("--jobs must be a number of trials")?,
+                force: m.is_present("force"),
+            })?;
+            println!("{}", id);
+        }
+        "list" => print_jobs(&client.jobs()?),
+        "cancel" => {
+            let id = m
+                .value_of("id")
+                .expect("id to be required")
+                .parse::<i64>()
+                .wrap_err("the job id must be a number")?;
+            match client.cancel(id)? {
+                JobState::Running => println!("job {} will be stopped", id),
+                state => println!("job {} {}", id, state),
+            }
+        }
+        _ => {
+            return Err(eyre!(
+                "meillionen queue {} needs the queue file, not --server",
+                command
+            ))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn queue(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    if let Some(server) = matches.subcommand().1.and_then(|m| m.value_of("server")) {
+        return queue_remote(server, matches);
+    }
+    match matches.subcommand() {
+        ("init", Some(m)) => {
+            let queue = open_queue(m)?;
+            println!("{}", queue.init_admin(m.value_of("admin-token"))?);
+        }
+        ("submit", Some(m)) => {
+            let queue = open_queue_as_admin(m)?;
+            let (config, path) = load(m)?;
+            config.validate()?;
+            let priority = m
+                .value_of("priority")
+                .expect("priority to have a default")
+                .parse::<i64>()
+                .wrap_err("--priority must be a whole number")?;
+            let jobs = m
+                .value_of("jobs")
+                .expect("jobs to have a default")
+                .parse::<usize>()
+                .wrap_err("--jobs must be a number of trials")?;
+            let dir = std::env::current_dir()?;
+            if !m.is_present("force") {
+                if let Some(job) = queue.same_as(&config.content_hash()?, &dir)? {
+                    return Err(eyre!(
+                        "job {} ({}) runs the same experiment, use its results or give --force to run it again",
+                        job.id,
+                        job.state
+                    ));
+                }
+            }
+            let id = queue.submit(Path::new(path), &dir, priority, jobs, None)?;
+            println!("{}", id);
+        }
+        ("list", Some(m)) => print_jobs(&open_queue_as_admin(m)?.jobs()?),
+        ("cancel", Some(m)) => {
+            let queue = open_queue_as_admin(m)?;
+            let id = m
+                .value_of("id")
+                .expect("id to be required")
+                .parse::<i64>()
+                .wrap_err("the job id must be a number")?;
+            match queue.cancel(id, None)? {
+                JobState::Running => println!("job {} will be stopped", id),
+                state => println!("job {} {}", id, state),
+            }
+        }
+        ("user", Some(m)) => queue_user(m)?,
+        _ => unreachable!("subcommand to be required"),
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn queue(_: &ArgMatches) -> stable_eyre::Result<()> {
+    Err(eyre!("meillionen was built without the sqlite feature"))
+}
+
+/// Lines of a job's stderr kept as its failure message
+#[cfg(feature = "sqlite")]
+const MESSAGE_LINES: usize = 20;
+
+/// Run a claimed job as `meillionen run` in the directory it was submitted
+/// from, stopping it if it is cancelled
+#[cfg(feature = "sqlite")]
+fn run_job(
+    queue: &JobQueue,
+    job: &Job,
+    poll: std::time::Duration,
+    run_args: &[String],
+    run_env: &[(&str, String)],
+) -> stable_eyre::Result<Result<(), String>> {
+    use std::collections::VecDeque;
+    use std::io::BufRead;
+    use std::process::{Command, Stdio};
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg("run")
+        .arg(&job.config)
+        .arg("--jobs")
+        .arg(job.jobs.to_string())
+        .args(run_args)
+        .envs(run_env.iter().cloned())
+        .current_dir(&job.dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    // a group of its own so cancelling stops the models it started too
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn()?;
+    let stderr = child.stderr.take().expect("stderr to be piped");
+    let tail = std::thread::spawn(move || {
+        let mut tail = VecDeque::with_capacity(MESSAGE_LINES);
+        for line in std::io::BufReader::new(stderr).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if tail.len() == MESSAGE_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        Vec::from(tail).join("\n")
+    });
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if queue.cancel_requested(job.id)? {
+            tracing::info!("stopping job {}", job.id);
+            #[cfg(unix)]
+            Command::new("kill")
+                .arg("-KILL")
+                .arg(format!("-{}", child.id()))
+                .status()?;
+            #[cfg(not(unix))]
+            child.kill()?;
+            break child.wait()?;
+        }
+        std::thread::sleep(poll);
+    };
+    let message = tail.join().unwrap_or_default();
+    Ok(if status.success() {
+        Ok(())
+    } else {
+        Err(message)
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn serve(matches: &ArgMatches) -> stable_eyre::Result<()> {
+    let path = matches.value_of("queue").expect("queue to have a default");
+    let workers = matches
+        .value_of("workers")
+        .expect("workers to have a default")
+        .parse::<usize>()
+        .wrap_err("--workers must be a number of experiments")?;
+    let poll = std::time::Duration::from_secs_f64(
+        matches
+            .value_of("poll")
+            .expect("poll to have a default")
+            .parse()
+            .wrap_err("--poll must be a number of seconds")?,
+    );
+    let requeued = open_queue(matches)?.requeue_running()?;
+    if requeued > 0 {
+        tracing::warn!(
+            "requeued {} jobs left running by a stopped server",
+            requeued
+        );
+    }
+    // the runs of jobs store their outputs where the service was told to
+    let mut run_args = vec![];
+    for name in ["postgres-ca", "postgres-step"].iter() {
+        if let Some(value) = matches.value_of(name) {
+            run_args.push(format!("--{}", name));
+            run_args.push(value.to_string());
+        }
+    }
+    let run_env: Vec<(&str, String)> = matches
+        .value_of("postgres")
+        .map(|params| (POSTGRES_ENV, params.to_string()))
+        .into_iter()
+        .collect();
+    let metrics = Arc::new(ServiceMetrics::default());
+    if let Some(addr) = matches.value_of("metrics") {
+        let listener = std::net::TcpListener::bind(addr)
+            .wrap_err_with(|| format!("could not listen on {}", addr))?;
+        tracing::info!(
+            "serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        let metrics = metrics.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve_metrics(&metrics, listener) {
+                tracing::error!("metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+    if let Some(addr) = matches.value_of("listen") {
+        let listener = std::net::TcpListener::bind(addr)
+            .wrap_err_with(|| format!("could not listen on {}", addr))?;
+        let workspace = matches
+            .value_of("workspace")
+            .expect("workspace to have a default");
+        let mut service = SubmissionService::new(open_queue(matches)?, Path::new(workspace))?;
+        tracing::info!("accepting submissions on http://{}", listener.local_addr()?);
+        std::thread::spawn(move || {
+            if let Err(e) = service.serve(listener) {
+                tracing::error!("submission endpoint stopped: {}", e);
+            }
+        });
+    }
+    tracing::info!("serving {} with {} workers", path, workers);
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|worker| {
+            let path = path.to_string();
+            let metrics = metrics.clone();
+            let run_args = run_args.clone();
+            let run_env = run_env.clone();
+            std::thread::spawn(move || -> stable_eyre::Result<()> {
+                let mut queue = JobQueue::open(&path)?;
+                loop {
+                    let claimed = queue.claim()?;
+                    metrics.set_queue_depth(queue.queued()?);
+                    let job = match claimed {
+                        Some(job) => job,
+                        None => {
+                            std::thread::sleep(poll);
+                            continue;
+                        }
+                    };
+                    tracing::info!(worker, "running job {} {}", job.id, job.config.display());
+                    metrics.run_started();
+                    metrics.add_bytes_received(workspace_bytes(&job.config)?);
+                    let before = workspace_bytes(&job.dir)?;
+                    let started = std::time::Instant::now();
+                    let result = match queue.check_job(&job) {
+                        Ok(()) => run_job(&queue, &job, poll, &run_args, &run_env)
+                            .unwrap_or_else(|e| Err(format!("could not run job: {}", e))),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    metrics.run_finished(started.elapsed(), result.is_ok());
+                    metrics.add_bytes_sent(workspace_bytes(&job.dir)?.saturating_sub(before));
+                    let state = queue.finish(job.id, result)?;
+                    tracing::info!(worker, "job {} {}", job.id, state);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("worker not to panic")?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn serve(_: &ArgMatches) -> stable_eyre::Result<()> {
+    Err(eyre!("meillionen was built without the sqlite feature"))
+}
+
+fn queue_arg() -> Arg<'static, 'static> {
+    Arg::with_name("queue")
+        .long("queue")
+        .takes_value(true)
+        .default_value("meillionen-queue.db")
+        .help("sqlite database of the job queue")
+}
+
+fn server_arg() -> Arg<'static, 'static> {
+    Arg::with_name("server")
+        .long("server")
+        .takes_value(true)
+        .env("MEILLIONEN_SERVER")
+        .help("URL of a meillionen serve --listen to use instead of the queue file, like http://host:9185")
+}
+
+fn token_arg() -> Arg<'static, 'static> {
+    Arg::with_name("token")
+        .long("token")
+        .takes_value(true)
+        .env("MEILLIONEN_TOKEN")
+        .hide_env_values(true)
+        .help("token from meillionen queue user add, checked by the --server")
+}
+
+fn admin_token_arg() -> Arg<'static, 'static> {
+    Arg::with_name("admin-token")
+        .long("admin-token")
+        .takes_value(true)
+        .env("MEILLIONEN_ADMIN_TOKEN")
+        .hide_env_values(true)
+        .help("token from meillionen queue init, needed to manage users or use a shared queue file")
+}
+
+fn main() -> stable_eyre::Result<()> {
+    stable_eyre::install()?;
+    let matches = App::new("meillionen")
+        .about("Run and inspect model experiments")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .multiple(true)
+                .global(true)
+                .help("only print errors"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short("v")
+                .multiple(true)
+                .global(true)
+                .help("print more details, -vv prints everything"),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .global(true)
+                .help("fail on validation warnings such as filled gaps or skipped lines"),
+        )
+        .arg(
+            Arg::with_name("deterministic")
+                .long("deterministic")
+                .global(true)
+                .help("compute and write floats the same way on every platform"),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("check an experiment file without running it")
+                .arg(config_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("hash")
+                .about("print a hash of what an experiment runs, the same for reordered or relabelled copies")
+                .arg(config_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("run the trials of an experiment")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("trial")
+                        .long("trial")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("only run these trials"),
+                )
+                .arg(tag_arg())
+                .arg(
+                    Arg::with_name("jobs")
+                        .long("jobs")
+                        .short("j")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("trials to run at once, costliest first by their cost hints"),
+                )
+                .arg(
+                    Arg::with_name("adaptive")
+                        .long("adaptive")
+                        .help("run fewer than --jobs trials at once while the machine is busy or short of memory"),
+                )
+                .arg(
+                    Arg::with_name("target-load")
+                        .long("target-load")
+                        .takes_value(true)
+                        .default_value("1.0")
+                        .help("runnable threads per CPU --adaptive keeps under"),
+                )
+                .arg(
+                    Arg::with_name("tui")
+                        .long("tui")
+                        .help("show a dashboard of the trials (needs the tui feature)"),
+                )
+                .args(&postgres_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("show which trials have run")
+                .arg(config_arg())
+                .arg(tag_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("check the files of a run against the checksums in its manifest")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("base").long("base").takes_value(true).help(
+                        "directory the experiment was run from (defaults to the current one)",
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("delete the outputs and logs of old or failed trials, keeping the manifest")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("base").long("base").takes_value(true).help(
+                        "directory the experiment was run from (defaults to the current one)",
+                    ),
+                )
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .takes_value(true)
+                        .help("only prune trials that finished at least this many days ago"),
+                )
+                .arg(
+                    Arg::with_name("status")
+                        .long("status")
+                        .takes_value(true)
+                        .possible_values(&["succeeded", "failed"])
+                        .help("only prune trials with this status"),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .takes_value(true)
+                        .help("prune the oldest trials until outputs fit in this size, like 500M"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("prune every trial when no other filter is given"),
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("list what would be deleted without deleting it"),
+                )
+                .arg(tag_arg())
+                .arg(
+                    Arg::with_name("postgres")
+                        .long("postgres")
+                        .takes_value(true)
+                        .env(POSTGRES_ENV)
+                        .hide_env_values(true)
+                        .help("connection string of a results database to delete the runs of pruned trials from (needs the postgres-sink feature)"),
+                )
+                .arg(postgres_args()[1].clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("archive")
+                .about("bundle the sinks and logs of a finished experiment into a zip file")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .long("remove")
+                        .help("delete the archived files once the archive is written"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("provenance")
+                .about("export what a run used, made and how its trials were coupled")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["prov", "ro-crate"])
+                        .default_value("prov")
+                        .help("W3C PROV-JSON, or an RO-Crate directory with copies of the files"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("file for PROV-JSON, printed when left out, or the crate directory"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("write an html summary of an experiment to share")
+                .arg(config_arg())
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("baseline")
+                        .long("baseline")
+                        .takes_value(true)
+                        .help("experiment file to list the changed settings against"),
+                )
+                .arg(tolerance_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("interface")
+                .about("show the sources and sinks a model accepts")
+                .arg(
+                    Arg::with_name("model")
+                        .help("path to the model program")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json", "cwl", "nextflow", "snakemake"])
+                        .default_value("text")
+                        .help("cwl, nextflow and snakemake describe a step running the model with meillionen call"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("call")
+                .about("run a model once on files, picking each resource by its extension")
+                .arg(
+                    Arg::with_name("model")
+                        .help("path to the model program")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("source")
+                        .long("source")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("name=path of a source (may be repeated)"),
+                )
+                .arg(
+                    Arg::with_name("sink")
+                        .long("sink")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("name=path of a sink (may be repeated)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff-config")
+                .about("show the fields that differ between two experiment files or run manifests")
+                .arg(Arg::with_name("left").required(true))
+                .arg(Arg::with_name("right").required(true))
+                .arg(tolerance_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("compare the outputs of two runs value by value")
+                .arg(
+                    Arg::with_name("reference")
+                        .help("experiment file or archive of the reference run")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .help("experiment file or archive of the run to check")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("tolerances")
+                        .long("tolerances")
+                        .takes_value(true)
+                        .help("toml file of tolerances by variable, exact when not given"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("infer-spec")
+                .about("propose a spec for parsing the table in a model's text output")
+                .arg(
+                    Arg::with_name("sample")
+                        .help("a fixed-width or delimited output of the model")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("toml file to save the spec to, printed when not given"),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .takes_value(true)
+                        .conflicts_with("output")
+                        .help("parse the sample with an edited spec instead"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("expand-sites")
+                .about("expand treatments run at many sites into an experiment file")
+                .arg(
+                    Arg::with_name("sites")
+                        .help("multi-site file (.toml, .yaml or .yml)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("yaml file to save the experiment to, printed when not given"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("expand-design")
+                .about("expand a factorial design into an experiment file")
+                .arg(
+                    Arg::with_name("design")
+                        .help("design file (.toml, .yaml or .yml)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .takes_value(true)
+                        .help("yaml file to save the experiment to, printed when not given"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("results")
+                .about("work with the results of an experiment")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("convert a tabular sink to another format")
+                        .arg(
+                            config_arg()
+                                .help("experiment file, or a .zip written by meillionen archive"),
+                        )
+                        .arg(
+                            Arg::with_name("trial")
+                                .long("trial")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("sink")
+                                .long("sink")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["parquet", "netcdf", "csv"])
+                                .default_value("parquet"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .short("o")
+                                .takes_value(true)
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("queue")
+                .about("submit experiments to run with meillionen serve (needs the sqlite feature)")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("init")
+                        .about("share the queue, printing the admin token that manages its users")
+                        .arg(queue_arg())
+                        .arg(admin_token_arg().help("the current admin token, to replace it")),
+                )
+                .subcommand(
+                    SubCommand::with_name("submit")
+                        .about("add an experiment to the queue, printing its job id")
+                        .arg(config_arg())
+                        .arg(queue_arg())
+                        .arg(server_arg())
+                        .arg(token_arg())
+                        .arg(admin_token_arg())
+                        .arg(
+                            Arg::with_name("dir")
+                                .long("dir")
+                                .takes_value(true)
+                                .requires("server")
+                                .help("directory in your workspace on the server that relative paths in the experiment are resolved against"),
+                        )
+                        .arg(
+                            Arg::with_name("force")
+                                .long("force")
+                                .help("submit even if a job from this directory runs the same experiment"),
+                        )
+                        .arg(
+                            Arg::with_name("priority")
+                                .long("priority")
+                                .takes_value(true)
+                                .default_value("0")
+                                .allow_hyphen_values(true)
+                                .help("jobs with a higher priority run first"),
+                        )
+                        .arg(
+                            Arg::with_name("jobs")
+                                .long("jobs")
+                                .short("j")
+                                .takes_value(true)
+                                .default_value("1")
+                                .help("trials of the experiment to run at once"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("show every job, or only your own, with its state and priority")
+                        .arg(queue_arg())
+                        .arg(server_arg())
+                        .arg(token_arg())
+                        .arg(admin_token_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("cancel")
+                        .about("drop a queued job or stop a running one")
+                        .arg(Arg::with_name("id").required(true))
+                        .arg(queue_arg())
+                        .arg(server_arg())
+                        .arg(token_arg())
+                        .arg(admin_token_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("user")
+                        .about("manage who may submit to a shared queue")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(
+                            SubCommand::with_name("add")
+                                .about("add or replace a user, printing their new token")
+                                .arg(Arg::with_name("name").required(true))
+                                .arg(
+                                    Arg::with_name("workspace")
+                                        .long("workspace")
+                                        .takes_value(true)
+                                        .required(true)
+                                        .help("directory the user's experiments must be in"),
+                                )
+                                .arg(
+                                    Arg::with_name("max-running")
+                                        .long("max-running")
+                                        .takes_value(true)
+                                        .default_value("1")
+                                        .help("jobs of the user to run at once"),
+                                )
+                                .arg(
+                                    Arg::with_name("max-disk")
+                                        .long("max-disk")
+                                        .takes_value(true)
+                                        .help(
+                                            "size like 20G the workspace may use before the \
+                                             user's jobs wait",
+                                        ),
+                                )
+                                .arg(queue_arg())
+                                .arg(admin_token_arg()),
+                        )
+                        .subcommand(
+                            SubCommand::with_name("list")
+                                .about("show each user's workspace, running limit and disk use")
+                                .arg(queue_arg())
+                                .arg(admin_token_arg()),
+                        )
+                        .subcommand(
+                            SubCommand::with_name("remove")
+                                .about("revoke a user's token, leaving their jobs")
+                                .arg(Arg::with_name("name").required(true))
+                                .arg(queue_arg())
+                                .arg(admin_token_arg()),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("run the experiments submitted to a queue (needs the sqlite feature)")
+                .arg(queue_arg())
+                .arg(
+                    Arg::with_name("workers")
+                        .long("workers")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("experiments to run at once"),
+                )
+                .arg(
+                    Arg::with_name("poll")
+                        .long("poll")
+                        .takes_value(true)
+                        .default_value("2")
+                        .help("seconds between checks for new jobs and cancellations"),
+                )
+                .arg(
+                    Arg::with_name("metrics")
+                        .long("metrics")
+                        .takes_value(true)
+                        .help("address to serve Prometheus metrics on at /metrics, like 0.0.0.0:9184"),
+                )
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .help("address to accept submissions from meillionen queue --server on, like 0.0.0.0:9185"),
+                )
+                .arg(
+                    Arg::with_name("workspace")
+                        .long("workspace")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("directory experiments submitted over --listen are saved and run in while the queue has no users"),
+                )
+                .args(&postgres_args()),
+        )
+        .get_matches();
+    // global flags given anywhere on the line are seen by the last subcommand
+    let mut innermost = &matches;
+    while let (_, Some(m)) = innermost.subcommand() {
+        innermost = m;
+    }
+    set_verbosity(Verbosity::from_flags(
+        innermost.occurrences_of("quiet"),
+        innermost.occurrences_of("verbose"),
+    ));
+    set_strict(innermost.is_present("strict"));
+    // without the flag the mode comes from MEILLIONEN_DETERMINISTIC
+    if innermost.is_present("deterministic") {
+        set_deterministic(true);
+    }
+
+    match matches.subcommand() {
+        ("validate", Some(m)) => validate(m),
+        ("hash", Some(m)) => hash(m),
+        ("run", Some(m)) => run(m),
+        ("status", Some(m)) => status(m),
+        ("verify", Some(m)) => verify(m),
+        ("gc", Some(m)) => gc(m),
+        ("archive", Some(m)) => archive_experiment(m),
+        ("provenance", Some(m)) => provenance(m),
+        ("report", Some(m)) => report(m),
+        ("interface", Some(m)) => interface(m),
+        ("call", Some(m)) => call(m),
+        ("diff-config", Some(m)) => diff_config(m),
+        ("compare", Some(m)) => compare_runs(m),
+        ("infer-spec", Some(m)) => infer_spec(m),
+        ("expand-sites", Some(m)) => expand_sites(m),
+        ("expand-design", Some(m)) => expand_design(m),
+        ("results", Some(m)) => match m.subcommand() {
+            ("export", Some(m)) => export_results(m),
+            _ => unreachable!("subcommand to be required"),
+        },
+        ("queue", Some(m)) => queue(m),
+        ("serve", Some(m)) => serve(m),
+        _ => unreachable!("subcommand to be required"),
+    }
+}
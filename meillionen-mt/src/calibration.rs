@@ -0,0 +1,583 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::determinism;
+use crate::variable::Reduction;
+
+/// A model parameter to calibrate and the bounds it is searched within
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Parameter {
+    pub fn new(name: &str, lower: f64, upper: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            lower,
+            upper,
+        }
+    }
+
+    fn clamp(&self, v: f64) -> f64 {
+        v.max(self.lower).min(self.upper)
+    }
+}
+
+/// The objectives of one model run, all to be minimized, and a reference to
+/// the run (such as its output directory) so its results can be found later
+#[derive(Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    pub objectives: Vec<f64>,
+    pub run: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Nsga2Config {
+    pub population: usize,
+    pub generations: usize,
+    /// Distribution index of simulated binary crossover
+    pub crossover_eta: f64,
+    /// Distribution index of polynomial mutation
+    pub mutation_eta: f64,
+    pub seed: u64,
+}
+
+impl Default for Nsga2Config {
+    fn default() -> Self {
+        Self {
+            population: 40,
+            generations: 25,
+            crossover_eta: 15.0,
+            mutation_eta: 20.0,
+            seed: 0,
+        }
+    }
+}
+
+/// A parameterization and how well it did
+#[derive(Clone, Debug, PartialEq)]
+pub struct Solution {
+    pub parameters: Vec<f64>,
+    pub objectives: Vec<f64>,
+    pub run: String,
+    /// The non-dominated front the solution is in, 0 being the Pareto front
+    pub rank: usize,
+    pub crowding: f64,
+}
+
+fn dominates(a: &[f64], b: &[f64]) -> bool {
+    a.iter().zip(b).all(|(x, y)| x <= y) && a.iter().zip(b).any(|(x, y)| x < y)
+}
+
+/// Split solutions into non-dominated fronts, setting their rank
+fn sort_fronts(solutions: &mut [Solution]) -> Vec<Vec<usize>> {
+    let n = solutions.len();
+    let mut dominated_by = vec![0usize; n];
+    let mut dominating: Vec<Vec<usize>> = vec![vec![]; n];
+    for i in 0..n {
+        for j in 0..n {
+            if dominates(&solutions[i].objectives, &solutions[j].objectives) {
+                dominating[i].push(j);
+            } else if dominates(&solutions[j].objectives, &solutions[i].objectives) {
+                dominated_by[i] += 1;
+            }
+        }
+    }
+    let mut fronts = vec![];
+    let mut front: Vec<usize> = (0..n).filter(|i| dominated_by[*i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = vec![];
+        for &i in front.iter() {
+            solutions[i].rank = fronts.len();
+            for &j in dominating[i].iter() {
+                dominated_by[j] -= 1;
+                if dominated_by[j] == 0 {
+                    next.push(j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+fn set_crowding(solutions: &mut [Solution], front: &[usize]) {
+    for &i in front.iter() {
+        solutions[i].crowding = 0.0;
+    }
+    let objectives = solutions[front[0]].objectives.len();
+    for m in 0..objectives {
+        let mut sorted = front.to_vec();
+        sorted.sort_by(|a, b| {
+            solutions[*a].objectives[m]
+                .partial_cmp(&solutions[*b].objectives[m])
+                .unwrap_or(Ordering::Equal)
+        });
+        let lo = solutions[sorted[0]].objectives[m];
+        let hi = solutions[sorted[sorted.len() - 1]].objectives[m];
+        solutions[sorted[0]].crowding = f64::INFINITY;
+        solutions[sorted[sorted.len() - 1]].crowding = f64::INFINITY;
+        if hi > lo {
+            for k in 1..sorted.len().saturating_sub(1) {
+                let gap =
+                    solutions[sorted[k + 1]].objectives[m] - solutions[sorted[k - 1]].objectives[m];
+                solutions[sorted[k]].crowding += gap / (hi - lo);
+            }
+        }
+    }
+}
+
+/// Whether `a` is preferred to `b`: a lower rank, then a less crowded spot
+fn better(a: &Solution, b: &Solution) -> bool {
+    a.rank < b.rank || (a.rank == b.rank && a.crowding > b.crowding)
+}
+
+fn tournament<'a>(rng: &mut StdRng, population: &'a [Solution]) -> &'a Solution {
+    let a = &population[rng.gen_range(0..population.len())];
+    let b = &population[rng.gen_range(0..population.len())];
+    if better(a, b) {
+        a
+    } else {
+        b
+    }
+}
+
+/// Simulated binary crossover of two parents
+fn crossover(rng: &mut StdRng, eta: f64, p: &Parameter, a: f64, b: f64) -> (f64, f64) {
+    if rng.gen::<f64>() > 0.5 || (a - b).abs() < 1e-14 {
+        return (a, b);
+    }
+    let u: f64 = rng.gen();
+    let beta = if u <= 0.5 {
+        determinism::powf(2.0 * u, 1.0 / (eta + 1.0))
+    } else {
+        determinism::powf(1.0 / (2.0 * (1.0 - u)), 1.0 / (eta + 1.0))
+    };
+    let c1 = 0.5 * ((1.0 + beta) * a + (1.0 - beta) * b);
+    let c2 = 0.5 * ((1.0 - beta) * a + (1.0 + beta) * b);
+    (p.clamp(c1), p.clamp(c2))
+}
+
+/// Polynomial mutation, applied to each parameter with probability 1 / n
+fn mutate(rng: &mut StdRng, eta: f64, parameters: &[Parameter], x: &mut [f64]) {
+    let rate = 1.0 / parameters.len() as f64;
+    for (p, v) in parameters.iter().zip(x.iter_mut()) {
+        if rng.gen::<f64>() >= rate {
+            continue;
+        }
+        let u: f64 = rng.gen();
+        let delta = if u < 0.5 {
+            determinism::powf(2.0 * u, 1.0 / (eta + 1.0)) - 1.0
+        } else {
+            1.0 - determinism::powf(2.0 * (1.0 - u), 1.0 / (eta + 1.0))
+        };
+        *v = p.clamp(*v + delta * (p.upper - p.lower));
+    }
+}
+
+fn evaluated<F, E>(evaluate: &mut F, parameters: Vec<f64>) -> Result<Solution, E>
+where
+    F: FnMut(&[f64]) -> Result<Evaluation, E>,
+{
+    let e = evaluate(&parameters)?;
+    Ok(Solution {
+        parameters,
+        objectives: e.objectives,
+        run: e.run,
+        rank: 0,
+        crowding: 0.0,
+    })
+}
+
+/// Search for the Pareto set of parameterizations over competing objectives
+/// with NSGA-II
+///
+/// `evaluate` runs the model with a parameterization, in the order of
+/// `parameters`, and returns its objectives. Each objective is minimized, so
+/// use an error such as [`crate::observation::Alignment::rmse`] rather than a
+/// goodness of fit. The solutions returned are the non-dominated ones of the
+/// final population.
+pub fn nsga2<F, E>(
+    parameters: &[Parameter],
+    config: &Nsga2Config,
+    mut evaluate: F,
+) -> Result<Vec<Solution>, E>
+where
+    F: FnMut(&[f64]) -> Result<Evaluation, E>,
+{
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let size = config.population.max(2);
+    let mut population = (0..size)
+        .map(|_| {
+            let x = parameters
+                .iter()
+                .map(|p| rng.gen_range(p.lower..=p.upper))
+                .collect();
+            evaluated(&mut evaluate, x)
+        })
+        .collect::<Result<Vec<_>, E>>()?;
+    for front in sort_fronts(&mut population) {
+        set_crowding(&mut population, &front);
+    }
+
+    for _ in 0..config.generations {
+        let mut offspring = Vec::with_capacity(size);
+        while offspring.len() < size {
+            let a = tournament(&mut rng, &population).parameters.clone();
+            let b = tournament(&mut rng, &population).parameters.clone();
+            let (mut c1, mut c2): (Vec<f64>, Vec<f64>) = parameters
+                .iter()
+                .zip(a.iter().zip(b.iter()))
+                .map(|(p, (x, y))| crossover(&mut rng, config.crossover_eta, p, *x, *y))
+                .unzip();
+            mutate(&mut rng, config.mutation_eta, parameters, &mut c1);
+            mutate(&mut rng, config.mutation_eta, parameters, &mut c2);
+            offspring.push(evaluated(&mut evaluate, c1)?);
+            if offspring.len() < size {
+                offspring.push(evaluated(&mut evaluate, c2)?);
+            }
+        }
+
+        population.extend(offspring);
+        let fronts = sort_fronts(&mut population);
+        let mut keep = Vec::with_capacity(size);
+        for front in fronts {
+            set_crowding(&mut population, &front);
+            if keep.len() + front.len() <= size {
+                keep.extend(front);
+            } else {
+                let mut front = front;
+                front.sort_by(|a, b| {
+                    population[*b]
+                        .crowding
+                        .partial_cmp(&population[*a].crowding)
+                        .unwrap_or(Ordering::Equal)
+                });
+                keep.extend(front.into_iter().take(size - keep.len()));
+                break;
+            }
+        }
+        population = keep.into_iter().map(|i| population[i].clone()).collect();
+    }
+    Ok(population.into_iter().filter(|s| s.rank == 0).collect())
+}
+
+/// A table of solutions with a column per parameter and objective, the rank
+/// and a `run` column referencing the model run
+pub fn solutions_to_recordbatch(
+    parameters: &[Parameter],
+    objectives: &[&str],
+    solutions: &[Solution],
+) -> Result<RecordBatch, ArrowError> {
+    let mut fields = vec![];
+    let mut columns: Vec<ArrayRef> = vec![];
+    for (i, p) in parameters.iter().enumerate() {
+        fields.push(Field::new(&p.name, DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from(
+            solutions
+                .iter()
+                .map(|s| s.parameters[i])
+                .collect::<Vec<_>>(),
+        )));
+    }
+    for (i, name) in objectives.iter().enumerate() {
+        fields.push(Field::new(name, DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from(
+            solutions
+                .iter()
+                .map(|s| s.objectives[i])
+                .collect::<Vec<_>>(),
+        )));
+    }
+    fields.push(Field::new("rank", DataType::UInt32, false));
+    columns.push(Arc::new(UInt32Array::from(
+        solutions.iter().map(|s| s.rank as u32).collect::<Vec<_>>(),
+    )));
+    fields.push(Field::new("run", DataType::Utf8, false));
+    columns.push(Arc::new(StringArray::from(
+        solutions.iter().map(|s| s.run.as_str()).collect::<Vec<_>>(),
+    )));
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct McmcConfig {
+    /// Chains are run in parallel, one thread each
+    pub chains: usize,
+    /// Samples kept from each chain after burn in
+    pub samples: usize,
+    pub burn_in: usize,
+    /// Standard deviation of the random walk, as a fraction of each
+    /// parameter's range
+    pub proposal_scale: f64,
+    pub seed: u64,
+}
+
+impl Default for McmcConfig {
+    fn default() -> Self {
+        Self {
+            chains: 4,
+            samples: 1000,
+            burn_in: 500,
+            proposal_scale: 0.05,
+            seed: 0,
+        }
+    }
+}
+
+/// Parameter values in the order of the parameters, one per sample
+type Samples = Vec<Vec<f64>>;
+
+/// Posterior samples of the parameters from every chain
+#[derive(Clone, Debug, PartialEq)]
+pub struct Posterior {
+    /// The samples of each chain, each sample in the order of the parameters
+    pub chains: Vec<Samples>,
+    /// The fraction of proposals accepted by each chain
+    pub acceptance: Vec<f64>,
+}
+
+impl Posterior {
+    pub fn samples(&self) -> impl Iterator<Item = &Vec<f64>> {
+        self.chains.iter().flatten()
+    }
+
+    /// The posterior mean of each parameter
+    pub fn mean(&self) -> Vec<f64> {
+        let n = self.samples().count() as f64;
+        let width = self.samples().next().map(|s| s.len()).unwrap_or(0);
+        (0..width)
+            .map(|i| self.samples().map(|s| s[i]).sum::<f64>() / n)
+            .collect()
+    }
+
+    /// A table with a column per parameter and the chain of each sample
+    pub fn to_recordbatch(&self, parameters: &[Parameter]) -> Result<RecordBatch, ArrowError> {
+        let mut fields = vec![];
+        let mut columns: Vec<ArrayRef> = vec![];
+        for (i, p) in parameters.iter().enumerate() {
+            fields.push(Field::new(&p.name, DataType::Float64, false));
+            columns.push(Arc::new(Float64Array::from(
+                self.samples().map(|s| s[i]).collect::<Vec<_>>(),
+            )));
+        }
+        fields.push(Field::new("chain", DataType::UInt32, false));
+        columns.push(Arc::new(UInt32Array::from(
+            self.chains
+                .iter()
+                .enumerate()
+                .flat_map(|(c, samples)| std::iter::repeat_n(c as u32, samples.len()))
+                .collect::<Vec<_>>(),
+        )));
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+    }
+}
+
+/// A standard normal draw using the Box-Muller transform
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * determinism::ln(u)).sqrt() * determinism::cos(2.0 * std::f64::consts::PI * v)
+}
+
+fn chain<F, E>(
+    parameters: &[Parameter],
+    config: &McmcConfig,
+    seed: u64,
+    log_likelihood: &F,
+) -> Result<(Samples, f64), E>
+where
+    F: Fn(&[f64]) -> Result<f64, E>,
+{
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut x: Vec<f64> = parameters
+        .iter()
+        .map(|p| rng.gen_range(p.lower..=p.upper))
+        .collect();
+    let mut ll = log_likelihood(&x)?;
+    let mut samples = Vec::with_capacity(config.samples);
+    let mut accepted = 0;
+    let steps = config.burn_in + config.samples;
+    for step in 0..steps {
+        let proposal: Vec<f64> = parameters
+            .iter()
+            .zip(x.iter())
+            .map(|(p, v)| {
+                v + standard_normal(&mut rng) * config.proposal_scale * (p.upper - p.lower)
+            })
+            .collect();
+        // the prior is uniform within the bounds so proposals outside them are
+        // rejected without running the model
+        let inside = parameters
+            .iter()
+            .zip(proposal.iter())
+            .all(|(p, v)| *v >= p.lower && *v <= p.upper);
+        if inside {
+            let proposed = log_likelihood(&proposal)?;
+            if determinism::ln(rng.gen::<f64>()) < proposed - ll {
+                x = proposal;
+                ll = proposed;
+                accepted += 1;
+            }
+        }
+        if step >= config.burn_in {
+            samples.push(x.clone());
+        }
+    }
+    Ok((samples, accepted as f64 / steps.max(1) as f64))
+}
+
+/// Sample the posterior of the parameters with random walk
+/// Metropolis-Hastings
+///
+/// The prior of each parameter is uniform between its bounds. `log_likelihood`
+/// runs the model with a parameterization and scores it against observations,
+/// for example with [`crate::observation::Alignment::log_likelihood`]. It is
+/// called from one thread per chain.
+pub fn metropolis<F, E>(
+    parameters: &[Parameter],
+    config: &McmcConfig,
+    log_likelihood: F,
+) -> Result<Posterior, E>
+where
+    F: Fn(&[f64]) -> Result<f64, E> + Sync,
+    E: Send,
+{
+    let results: Vec<Result<(Samples, f64), E>> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..config.chains.max(1))
+            .map(|c| {
+                let log_likelihood = &log_likelihood;
+                let seed = config.seed.wrapping_add(c as u64);
+                s.spawn(move || chain(parameters, config, seed, log_likelihood))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("chain not to panic"))
+            .collect()
+    });
+    let mut posterior = Posterior {
+        chains: vec![],
+        acceptance: vec![],
+    };
+    for r in results {
+        let (samples, acceptance) = r?;
+        posterior.chains.push(samples);
+        posterior.acceptance.push(acceptance);
+    }
+    Ok(posterior)
+}
+
+/// The quantile of each step across a set of series, such as the model output
+/// of each posterior sample or ensemble member
+///
+/// Steps a series does not reach and NaN values are left out.
+pub fn step_quantiles(series: &[Vec<f64>], steps: usize, q: f64) -> Vec<f64> {
+    (0..steps).map(|i| step_quantile(series, i, q)).collect()
+}
+
+pub(crate) fn step_quantile(series: &[Vec<f64>], step: usize, q: f64) -> f64 {
+    let mut values: Vec<f64> = series
+        .iter()
+        .filter_map(|m| m.get(step).copied())
+        .filter(|v| !v.is_nan())
+        .collect();
+    Reduction::Quantile(q).apply(&mut values)
+}
+
+/// The lower and upper bound of a central predictive interval at each step
+///
+/// `predictions` holds the model output for each posterior sample. A `level`
+/// of 0.9 gives the 5% and 95% quantiles.
+pub fn predictive_interval(predictions: &[Vec<f64>], level: f64) -> (Vec<f64>, Vec<f64>) {
+    let steps = predictions.iter().map(|p| p.len()).max().unwrap_or(0);
+    let tail = (1.0 - level) / 2.0;
+    (
+        step_quantiles(predictions, steps, tail),
+        step_quantiles(predictions, steps, 1.0 - tail),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use crate::calibration::{
+        dominates, metropolis, nsga2, predictive_interval, solutions_to_recordbatch, Evaluation,
+        McmcConfig, Nsga2Config, Parameter,
+    };
+
+    #[test]
+    fn pareto_front_of_schaffer() {
+        // f1 = x^2 and f2 = (x - 2)^2 trade off for x in [0, 2]
+        let parameters = vec![Parameter::new("x", -10.0, 10.0)];
+        let mut runs = 0;
+        let front = nsga2(&parameters, &Nsga2Config::default(), |x| {
+            runs += 1;
+            Ok::<_, Infallible>(Evaluation {
+                objectives: vec![x[0].powi(2), (x[0] - 2.0).powi(2)],
+                run: format!("run-{}", runs),
+            })
+        })
+        .unwrap();
+        assert_eq!(runs, 40 * 26);
+        assert!(front.len() > 10);
+        for s in front.iter() {
+            assert!(s.parameters[0] > -0.1 && s.parameters[0] < 2.1);
+            assert!(front
+                .iter()
+                .all(|o| !dominates(&o.objectives, &s.objectives)));
+        }
+
+        let rb = solutions_to_recordbatch(&parameters, &["f1", "f2"], &front).unwrap();
+        let names: Vec<String> = rb
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, vec!["x", "f1", "f2", "rank", "run"]);
+        assert_eq!(rb.num_rows(), front.len());
+    }
+
+    #[test]
+    fn posterior_of_normal_mean() {
+        // observations of a normal with known sd 1 give a posterior of the mean
+        // centred on the sample mean with sd 1 / sqrt(n)
+        let observations = [1.8, 2.4, 1.6, 2.2, 2.0, 1.9, 2.1, 2.0];
+        let parameters = vec![Parameter::new("mu", -5.0, 5.0)];
+        let config = McmcConfig {
+            proposal_scale: 0.05,
+            ..McmcConfig::default()
+        };
+        let posterior = metropolis(&parameters, &config, |x| {
+            Ok::<_, Infallible>(observations.iter().map(|o| -0.5 * (o - x[0]).powi(2)).sum())
+        })
+        .unwrap();
+        assert_eq!(posterior.chains.len(), 4);
+        assert_eq!(posterior.samples().count(), 4000);
+        assert!((posterior.mean()[0] - 2.0).abs() < 0.1);
+        assert!(posterior.acceptance.iter().all(|a| *a > 0.2 && *a < 0.95));
+        let rb = posterior.to_recordbatch(&parameters).unwrap();
+        assert_eq!((rb.num_rows(), rb.num_columns()), (4000, 2));
+
+        let predictions = vec![vec![1.0, 10.0], vec![2.0, 20.0], vec![3.0, 30.0]];
+        let (lower, upper) = predictive_interval(&predictions, 0.5);
+        assert_eq!(lower, vec![1.5, 15.0]);
+        assert_eq!(upper, vec![2.5, 25.0]);
+    }
+}
@@ -0,0 +1,171 @@
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum ClockError {
+    #[error("model {0} is already on the clock")]
+    DuplicateModel(String),
+    #[error("model {0} not found on the clock")]
+    UnknownModel(String),
+    #[error("time step of {name} must be positive and finite but is {step}")]
+    InvalidStep { name: String, step: f64 },
+}
+
+/// The current time and time step of one coupled model
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ModelClock {
+    pub name: String,
+    pub time: f64,
+    pub step: f64,
+}
+
+impl ModelClock {
+    fn next(&self) -> f64 {
+        self.time + self.step
+    }
+}
+
+/// An `update_until` call the control loop should make
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Update {
+    pub model: String,
+    pub until: f64,
+}
+
+/// Keeps coupled models with different time steps in step
+///
+/// Times are in one unit for every model, such as hours since the start of
+/// the simulation. The model whose next step ends earliest is updated first,
+/// so an hourly hydrology model runs 24 steps before a daily crop model reads
+/// the day it produced. When steps end together the model with the shorter
+/// step goes first, then the model added first.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SimulationClock {
+    start: f64,
+    models: Vec<ModelClock>,
+}
+
+impl SimulationClock {
+    pub fn new(start: f64) -> Self {
+        Self {
+            start,
+            models: vec![],
+        }
+    }
+
+    pub fn add_model(&mut self, name: &str, step: f64) -> Result<(), ClockError> {
+        if !(step.is_finite() && step > 0.0) {
+            return Err(ClockError::InvalidStep {
+                name: name.to_string(),
+                step,
+            });
+        }
+        if self.models.iter().any(|m| m.name == name) {
+            return Err(ClockError::DuplicateModel(name.to_string()));
+        }
+        self.models.push(ModelClock {
+            name: name.to_string(),
+            time: self.start,
+            step,
+        });
+        Ok(())
+    }
+
+    pub fn models(&self) -> &[ModelClock] {
+        &self.models
+    }
+
+    pub fn model(&self, name: &str) -> Result<&ModelClock, ClockError> {
+        self.models
+            .iter()
+            .find(|m| m.name == name)
+            .ok_or_else(|| ClockError::UnknownModel(name.to_string()))
+    }
+
+    /// The time every model has reached
+    pub fn time(&self) -> f64 {
+        self.models
+            .iter()
+            .map(|m| m.time)
+            .fold(None, |t: Option<f64>, x| Some(t.map_or(x, |t| t.min(x))))
+            .unwrap_or(self.start)
+    }
+
+    /// Set the time of a model, such as after it reports its own time
+    pub fn set_time(&mut self, name: &str, time: f64) -> Result<(), ClockError> {
+        self.models
+            .iter_mut()
+            .find(|m| m.name == name)
+            .ok_or_else(|| ClockError::UnknownModel(name.to_string()))?
+            .time = time;
+        Ok(())
+    }
+
+    /// The next update to make on the way to `until`, advancing the clock as
+    /// if it was made
+    ///
+    /// Models only take whole steps, so a model whose next step would end
+    /// after `until` waits.
+    pub fn next_update(&mut self, until: f64) -> Option<Update> {
+        let model = self.models.iter_mut().filter(|m| m.next() <= until).fold(
+            None,
+            |best: Option<&mut ModelClock>, m| match best {
+                Some(b) if (b.next(), b.step) <= (m.next(), m.step) => Some(b),
+                _ => Some(m),
+            },
+        )?;
+        model.time = model.next();
+        Some(Update {
+            model: model.name.clone(),
+            until: model.time,
+        })
+    }
+
+    /// Every update to make to bring the models up to `until`
+    pub fn schedule(&mut self, until: f64) -> Vec<Update> {
+        std::iter::from_fn(|| self.next_update(until)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{ClockError, SimulationClock, Update};
+
+    #[test]
+    fn hourly_and_daily_models() {
+        let mut clock = SimulationClock::new(0.0);
+        clock.add_model("crop", 24.0).unwrap();
+        clock.add_model("hydrology", 1.0).unwrap();
+        assert_eq!(
+            clock.add_model("crop", 1.0),
+            Err(ClockError::DuplicateModel("crop".to_string()))
+        );
+        assert!(clock.add_model("soil", 0.0).is_err());
+
+        let updates = clock.schedule(48.0);
+        assert_eq!(updates.len(), 50);
+        assert_eq!(
+            updates[23..26],
+            [
+                Update {
+                    model: "hydrology".to_string(),
+                    until: 24.0
+                },
+                Update {
+                    model: "crop".to_string(),
+                    until: 24.0
+                },
+                Update {
+                    model: "hydrology".to_string(),
+                    until: 25.0
+                },
+            ]
+        );
+        assert_eq!(clock.time(), 48.0);
+
+        // half a day is not enough for the crop model to take a step
+        assert_eq!(clock.schedule(60.0).len(), 12);
+        assert_eq!(clock.model("crop").unwrap().time, 48.0);
+        assert_eq!(clock.time(), 48.0);
+    }
+}
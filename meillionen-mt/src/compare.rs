@@ -0,0 +1,385 @@
+//! Comparing the outputs of two runs of an experiment
+//!
+//! Used to check a new version of a model program against an old one: every
+//! numeric column of every tabular sink the runs share is compared row by
+//! row, so each timestep and cell, against a tolerance for its variable.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::archive::{ArchiveError, ArchiveReader};
+use crate::experiment::{read_batches, ExperimentConfig, ExperimentError};
+use crate::timeseries::numeric_values;
+
+#[derive(Debug, Error)]
+pub enum CompareError {
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[error(transparent)]
+    Archive(#[from] ArchiveError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+}
+
+/// How far a value may be from its reference, `absolute + relative * |reference|`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Tolerance {
+    #[serde(default)]
+    pub absolute: f64,
+    #[serde(default)]
+    pub relative: f64,
+}
+
+impl Tolerance {
+    /// Whether `value` is within tolerance of `reference`, counting two
+    /// missing values as equal
+    pub fn accepts(&self, reference: f64, value: f64) -> bool {
+        if reference.is_nan() || value.is_nan() {
+            return reference.is_nan() && value.is_nan();
+        }
+        (value - reference).abs() <= self.absolute + self.relative * reference.abs()
+    }
+}
+
+/// The tolerance of each variable, read from a file like
+///
+/// ```toml
+/// [default]
+/// relative = 1e-6
+///
+/// [variables.plant_leaf_area_index]
+/// absolute = 0.01
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ToleranceProfile {
+    #[serde(default)]
+    pub default: Tolerance,
+    #[serde(default)]
+    pub variables: BTreeMap<String, Tolerance>,
+}
+
+impl ToleranceProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CompareError> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn tolerance(&self, variable: &str) -> Tolerance {
+        self.variables
+            .get(variable)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// The results of a run, from an experiment's sinks or an archive of them
+pub enum RunResults {
+    Experiment(ExperimentConfig),
+    Archive(Box<ArchiveReader>),
+}
+
+impl RunResults {
+    /// An archive if the path ends in `.zip`, an experiment file otherwise
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, CompareError> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|e| e == "zip") {
+            Ok(RunResults::Archive(Box::new(ArchiveReader::open(path)?)))
+        } else {
+            Ok(RunResults::Experiment(ExperimentConfig::load(path)?))
+        }
+    }
+
+    /// The sinks of each trial
+    pub fn sinks(&self) -> BTreeMap<String, Vec<String>> {
+        match self {
+            RunResults::Experiment(config) => config
+                .trials
+                .iter()
+                .map(|t| (t.name.clone(), t.sinks.keys().cloned().collect()))
+                .collect(),
+            RunResults::Archive(reader) => reader
+                .index()
+                .sinks
+                .iter()
+                .map(|(t, sinks)| (t.clone(), sinks.keys().cloned().collect()))
+                .collect(),
+        }
+    }
+
+    pub fn read_batches(
+        &mut self,
+        trial: &str,
+        sink: &str,
+    ) -> Result<Vec<RecordBatch>, CompareError> {
+        match self {
+            RunResults::Experiment(config) => Ok(read_batches(config.trial(trial)?.sink(sink)?)?),
+            RunResults::Archive(reader) => Ok(reader.read_batches(trial, sink)?),
+        }
+    }
+}
+
+/// How one variable of a sink differs between two runs
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct VariableComparison {
+    pub trial: String,
+    pub sink: String,
+    pub variable: String,
+    pub tolerance: Tolerance,
+    /// Rows compared
+    pub compared: usize,
+    /// Rows outside the tolerance
+    pub exceedances: usize,
+    /// The first row outside the tolerance
+    pub first_exceedance: Option<usize>,
+    pub max_absolute: f64,
+    pub max_relative: f64,
+}
+
+impl VariableComparison {
+    fn new(trial: &str, sink: &str, variable: &str, tolerance: Tolerance) -> Self {
+        Self {
+            trial: trial.to_string(),
+            sink: sink.to_string(),
+            variable: variable.to_string(),
+            tolerance,
+            compared: 0,
+            exceedances: 0,
+            first_exceedance: None,
+            max_absolute: 0.0,
+            max_relative: 0.0,
+        }
+    }
+
+    fn compare(&mut self, reference: &[f64], values: &[f64]) {
+        for (r, v) in reference.iter().zip(values.iter()) {
+            if !self.tolerance.accepts(*r, *v) {
+                self.first_exceedance.get_or_insert(self.compared);
+                self.exceedances += 1;
+            }
+            let diff = (v - r).abs();
+            if !diff.is_nan() {
+                self.max_absolute = self.max_absolute.max(diff);
+                if *r != 0.0 {
+                    self.max_relative = self.max_relative.max(diff / r.abs());
+                }
+            }
+            self.compared += 1;
+        }
+    }
+}
+
+/// Every variable compared and what could not be
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CompareReport {
+    pub variables: Vec<VariableComparison>,
+    /// Trials, sinks or variables in only one run, and sinks with different
+    /// numbers of rows
+    pub problems: Vec<String>,
+}
+
+impl CompareReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty() && self.variables.iter().all(|v| v.exceedances == 0)
+    }
+}
+
+impl fmt::Display for CompareReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in self.variables.iter().filter(|v| v.exceedances > 0) {
+            writeln!(
+                f,
+                "{}.{}.{}: {} of {} values outside tolerance, first at row {}, max difference {:e} ({:e} relative)",
+                v.trial,
+                v.sink,
+                v.variable,
+                v.exceedances,
+                v.compared,
+                v.first_exceedance.unwrap_or_default(),
+                v.max_absolute,
+                v.max_relative
+            )?;
+        }
+        for p in self.problems.iter() {
+            writeln!(f, "{}", p)?;
+        }
+        let failed = self.variables.iter().filter(|v| v.exceedances > 0).count();
+        write!(
+            f,
+            "{} of {} variables within tolerance",
+            self.variables.len() - failed,
+            self.variables.len()
+        )
+    }
+}
+
+fn numeric_columns(batches: &[RecordBatch]) -> Vec<String> {
+    batches
+        .first()
+        .map(|rb| {
+            rb.schema()
+                .fields()
+                .iter()
+                .map(|f| f.name().clone())
+                .filter(|name| numeric_values(rb, name).is_some())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn column(batches: &[RecordBatch], name: &str) -> Vec<f64> {
+    batches
+        .iter()
+        .flat_map(|rb| numeric_values(rb, name).unwrap_or_default())
+        .collect()
+}
+
+/// Compare the tables of one sink, with `reference` from the older run
+pub fn compare_batches(
+    trial: &str,
+    sink: &str,
+    reference: &[RecordBatch],
+    batches: &[RecordBatch],
+    profile: &ToleranceProfile,
+    report: &mut CompareReport,
+) {
+    let rows = |bs: &[RecordBatch]| bs.iter().map(|rb| rb.num_rows()).sum::<usize>();
+    if rows(reference) != rows(batches) {
+        report.problems.push(format!(
+            "{}.{} has {} rows in the reference but {}",
+            trial,
+            sink,
+            rows(reference),
+            rows(batches)
+        ));
+    }
+    let names = numeric_columns(batches);
+    let reference_names = numeric_columns(reference);
+    for name in names.iter().filter(|n| !reference_names.contains(n)) {
+        report.problems.push(format!(
+            "{}.{}.{} is not in the reference",
+            trial, sink, name
+        ));
+    }
+    for name in reference_names {
+        if !names.contains(&name) {
+            report.problems.push(format!(
+                "{}.{}.{} is only in the reference",
+                trial, sink, name
+            ));
+            continue;
+        }
+        let mut comparison = VariableComparison::new(trial, sink, &name, profile.tolerance(&name));
+        comparison.compare(&column(reference, &name), &column(batches, &name));
+        report.variables.push(comparison);
+    }
+}
+
+/// Compare every tabular sink two runs share
+pub fn compare(
+    reference: &mut RunResults,
+    results: &mut RunResults,
+    profile: &ToleranceProfile,
+) -> Result<CompareReport, CompareError> {
+    let mut report = CompareReport::default();
+    let theirs = results.sinks();
+    for (trial, sinks) in reference.sinks() {
+        let other = match theirs.get(&trial) {
+            Some(other) => other,
+            None => {
+                report
+                    .problems
+                    .push(format!("trial {} is only in the reference", trial));
+                continue;
+            }
+        };
+        for sink in sinks {
+            if !other.contains(&sink) {
+                report
+                    .problems
+                    .push(format!("{}.{} is only in the reference", trial, sink));
+                continue;
+            }
+            let a = match reference.read_batches(&trial, &sink) {
+                Ok(a) => a,
+                // sinks such as netcdf files are not compared
+                Err(CompareError::Experiment(ExperimentError::NotTabular(_)))
+                | Err(CompareError::Archive(ArchiveError::Experiment(
+                    ExperimentError::NotTabular(_),
+                ))) => continue,
+                Err(e) => return Err(e),
+            };
+            let b = results.read_batches(&trial, &sink)?;
+            compare_batches(&trial, &sink, &a, &b, profile, &mut report);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float64Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::compare::{compare_batches, CompareReport, ToleranceProfile};
+
+    fn batch(lai: Vec<f64>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("lai", DataType::Float64, true),
+        ]));
+        let days: Vec<i32> = (1..=lai.len() as i32).collect();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(days)),
+            Arc::new(Float64Array::from(lai)),
+        ];
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    #[test]
+    fn tolerance_profiles() {
+        let profile: ToleranceProfile =
+            toml::from_str("[default]\nrelative = 1e-6\n\n[variables.lai]\nabsolute = 0.01\n")
+                .unwrap();
+        let reference = [batch(vec![1.0, 2.0, f64::NAN, 4.0])];
+        let mut report = CompareReport::default();
+        compare_batches(
+            "baseline",
+            "daily",
+            &reference,
+            &[batch(vec![1.005, 2.5, f64::NAN, 3.0])],
+            &profile,
+            &mut report,
+        );
+        assert!(!report.passed());
+        assert_eq!(report.variables[0].exceedances, 0);
+        let lai = &report.variables[1];
+        assert_eq!(
+            (lai.compared, lai.exceedances, lai.first_exceedance),
+            (4, 2, Some(1))
+        );
+        assert_eq!(lai.max_absolute, 1.0);
+        assert!(report
+            .to_string()
+            .ends_with("1 of 2 variables within tolerance"));
+
+        let mut short = CompareReport::default();
+        compare_batches(
+            "baseline",
+            "daily",
+            &reference,
+            &[batch(vec![1.0, 2.0])],
+            &profile,
+            &mut short,
+        );
+        assert_eq!(short.problems.len(), 1);
+    }
+}
@@ -0,0 +1,294 @@
+//! Running fewer trials at once when the machine is busy
+//!
+//! Model programs are often multithreaded themselves, through OpenMP in the
+//! Fortran or a BLAS underneath, so running one per CPU can put several
+//! threads on every core. A [`Controller`] looks at how many threads are ready
+//! to run per CPU and how much memory is left, and raises or lowers the
+//! number of tasks a [`ConcurrencyLimit`] lets a [`crate::scheduler`] start.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// How many tasks may run at once, changed while they run
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    /// Tasks running and tasks allowed
+    state: Mutex<(usize, usize)>,
+    changed: Condvar,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(allowed: usize) -> Self {
+        Self {
+            state: Mutex::new((0, allowed.max(1))),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.state.lock().expect("limit not to be poisoned").0
+    }
+
+    pub fn allowed(&self) -> usize {
+        self.state.lock().expect("limit not to be poisoned").1
+    }
+
+    /// Change the limit, which running tasks finish under
+    pub fn set_allowed(&self, allowed: usize) {
+        self.state.lock().expect("limit not to be poisoned").1 = allowed.max(1);
+        self.changed.notify_all();
+    }
+
+    /// Wait until another task may start, returning a permit that lets the
+    /// next one start when dropped
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.state.lock().expect("limit not to be poisoned");
+        while state.0 >= state.1 {
+            state = self.changed.wait(state).expect("limit not to be poisoned");
+        }
+        state.0 += 1;
+        Permit { limit: self }
+    }
+}
+
+pub struct Permit<'a> {
+    limit: &'a ConcurrencyLimit,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().expect("limit not to be poisoned").0 -= 1;
+        self.limit.changed.notify_all();
+    }
+}
+
+/// A snapshot of how busy the machine is
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SystemLoad {
+    /// Threads running or ready to run
+    pub runnable: usize,
+    pub cpus: usize,
+    pub available_memory: u64,
+    pub total_memory: u64,
+}
+
+impl SystemLoad {
+    /// Read the load from `/proc`, so only on Linux
+    pub fn sample() -> std::io::Result<Self> {
+        let proc = Path::new("/proc");
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::parse(
+            &std::fs::read_to_string(proc.join("loadavg"))?,
+            &std::fs::read_to_string(proc.join("meminfo"))?,
+            cpus,
+        )
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "could not read /proc/loadavg and /proc/meminfo",
+            )
+        })
+    }
+
+    /// The load from the contents of `/proc/loadavg` and `/proc/meminfo`
+    pub fn parse(loadavg: &str, meminfo: &str, cpus: usize) -> Option<Self> {
+        // the fourth field is running/total scheduling entities
+        let runnable = loadavg
+            .split_whitespace()
+            .nth(3)?
+            .split('/')
+            .next()?
+            .parse()
+            .ok()?;
+        let kib = |key: &str| -> Option<u64> {
+            meminfo
+                .lines()
+                .find(|l| l.starts_with(key))?
+                .split_whitespace()
+                .nth(1)?
+                .parse::<u64>()
+                .ok()
+                .map(|v| v * 1024)
+        };
+        Some(Self {
+            runnable,
+            cpus: cpus.max(1),
+            available_memory: kib("MemAvailable:")?,
+            total_memory: kib("MemTotal:")?,
+        })
+    }
+
+    /// Runnable threads per CPU
+    pub fn pressure(&self) -> f64 {
+        self.runnable as f64 / self.cpus as f64
+    }
+
+    pub fn memory_headroom(&self) -> f64 {
+        self.available_memory as f64 / self.total_memory.max(1) as f64
+    }
+}
+
+/// When to run more or fewer tasks at once
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ControllerConfig {
+    pub min: usize,
+    pub max: usize,
+    /// Runnable threads per CPU to stay under
+    pub target_load: f64,
+    /// The fraction of memory to keep available
+    pub min_memory_headroom: f64,
+    pub interval: Duration,
+}
+
+impl ControllerConfig {
+    /// Up to `max` tasks at once, one runnable thread per CPU and a tenth of
+    /// memory free, checked every second
+    pub fn new(max: usize) -> Self {
+        Self {
+            min: 1,
+            max: max.max(1),
+            target_load: 1.0,
+            min_memory_headroom: 0.1,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    fn clamp(&self, allowed: usize) -> usize {
+        let min = self.min.max(1);
+        allowed.clamp(min, self.max.max(min))
+    }
+}
+
+/// Adjusts a limit one task at a time from samples of the system load
+#[derive(Clone, Debug)]
+pub struct Controller {
+    config: ControllerConfig,
+    /// An exponentially weighted average of the pressure, since the number
+    /// of runnable threads jumps around between samples
+    pressure: Option<f64>,
+}
+
+impl Controller {
+    pub fn new(config: ControllerConfig) -> Self {
+        Self {
+            config,
+            pressure: None,
+        }
+    }
+
+    /// The limit to use next, given the current one, how many tasks are
+    /// running under it and a sample of the load
+    pub fn adjust(&mut self, allowed: usize, running: usize, load: &SystemLoad) -> usize {
+        let pressure = match self.pressure {
+            Some(p) => 0.5 * p + 0.5 * load.pressure(),
+            None => load.pressure(),
+        };
+        self.pressure = Some(pressure);
+        let next = if load.memory_headroom() < self.config.min_memory_headroom
+            || pressure > self.config.target_load
+        {
+            allowed.saturating_sub(1)
+        } else if pressure < 0.75 * self.config.target_load && running >= allowed {
+            // only when every permit is in use, or raising it changes nothing
+            allowed + 1
+        } else {
+            allowed
+        };
+        self.config.clamp(next)
+    }
+
+    /// Adjust `limit` from a background thread until the watcher is dropped
+    ///
+    /// The limit starts at one task per CPU, up to the maximum.
+    pub fn watch(self, limit: Arc<ConcurrencyLimit>) -> Watcher {
+        let stop = Arc::new(AtomicBool::new(false));
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        limit.set_allowed(self.config.clamp(cpus));
+        let handle = {
+            let stop = stop.clone();
+            let mut controller = self;
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match SystemLoad::sample() {
+                        Ok(load) => {
+                            let allowed = limit.allowed();
+                            let next = controller.adjust(allowed, limit.running(), &load);
+                            if next != allowed {
+                                tracing::debug!(
+                                    allowed = next,
+                                    runnable = load.runnable,
+                                    memory_headroom = load.memory_headroom(),
+                                    "changing the number of trials run at once"
+                                );
+                                limit.set_allowed(next);
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!("not adapting concurrency to the load: {}", e);
+                            return;
+                        }
+                    }
+                    std::thread::sleep(controller.config.interval);
+                }
+            })
+        };
+        Watcher {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// Stops a controller's thread when dropped
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{Controller, ControllerConfig, SystemLoad};
+
+    const MEMINFO: &str =
+        "MemTotal:       16000000 kB\nMemFree:         1000000 kB\nMemAvailable:    8000000 kB\n";
+
+    fn load(runnable: usize, available_kib: u64) -> SystemLoad {
+        SystemLoad::parse(
+            &format!("0.50 0.40 0.30 {}/900 12345", runnable),
+            &MEMINFO.replace("8000000", &available_kib.to_string()),
+            8,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn back_off_when_oversubscribed() {
+        assert_eq!(load(4, 8000000).memory_headroom(), 0.5);
+        let mut controller = Controller::new(ControllerConfig::new(8));
+        // an idle machine with every permit in use runs one more
+        assert_eq!(controller.adjust(4, 4, &load(2, 8000000)), 5);
+        // but not while tasks are still waiting to take the permits it has
+        assert_eq!(controller.adjust(4, 2, &load(2, 8000000)), 4);
+        // a multithreaded model keeping 4 threads ready per CPU
+        let mut controller = Controller::new(ControllerConfig::new(8));
+        assert_eq!(controller.adjust(8, 8, &load(32, 8000000)), 7);
+        assert_eq!(controller.adjust(1, 1, &load(32, 8000000)), 1);
+        // short of memory
+        let mut controller = Controller::new(ControllerConfig::new(8));
+        assert_eq!(controller.adjust(4, 4, &load(2, 800000)), 3);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::experiment::ExperimentConfig;
+#[cfg(feature = "data")]
+use crate::experiment::{read_batches, ExperimentError};
+#[cfg(feature = "data")]
+use crate::timeseries::numeric_values;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum ConservationError {
+    #[error("{link}: {sent} sent but {received} received, more than the relative tolerance of {tolerance}")]
+    Imbalance {
+        link: String,
+        sent: f64,
+        received: f64,
+        tolerance: f64,
+    },
+    #[error("link {0} not found")]
+    UnknownLink(String),
+}
+
+fn default_tolerance() -> f64 {
+    1e-6
+}
+
+/// Running totals of what crosses one coupling link, such as runoff from a
+/// hydrology model becoming infiltration in a crop model
+///
+/// Missing values are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FluxLedger {
+    pub sent: f64,
+    pub received: f64,
+}
+
+impl FluxLedger {
+    pub fn send(&mut self, amount: f64) {
+        if !amount.is_nan() {
+            self.sent += amount;
+        }
+    }
+
+    pub fn receive(&mut self, amount: f64) {
+        if !amount.is_nan() {
+            self.received += amount;
+        }
+    }
+
+    /// Whether the totals differ by at most `tolerance` times the larger of them
+    pub fn balanced(&self, tolerance: f64) -> bool {
+        let scale = self.sent.abs().max(self.received.abs());
+        (self.sent - self.received).abs() <= tolerance * scale
+    }
+}
+
+/// The ledgers of every link in a coupled run
+///
+/// The coupler records the mass each model hands over and takes in after
+/// unit conversion and regridding. A mismatch usually means one of those
+/// steps is wrong.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ConservationLedger {
+    tolerance: f64,
+    links: BTreeMap<String, FluxLedger>,
+}
+
+impl Default for ConservationLedger {
+    fn default() -> Self {
+        Self::new(default_tolerance())
+    }
+}
+
+impl ConservationLedger {
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            links: BTreeMap::new(),
+        }
+    }
+
+    pub fn send(&mut self, link: &str, amount: f64) {
+        self.links.entry(link.to_string()).or_default().send(amount)
+    }
+
+    pub fn receive(&mut self, link: &str, amount: f64) {
+        self.links
+            .entry(link.to_string())
+            .or_default()
+            .receive(amount)
+    }
+
+    pub fn link(&self, link: &str) -> Option<&FluxLedger> {
+        self.links.get(link)
+    }
+
+    pub fn check_link(&self, link: &str) -> Result<(), ConservationError> {
+        let ledger = self
+            .link(link)
+            .ok_or_else(|| ConservationError::UnknownLink(link.to_string()))?;
+        if ledger.balanced(self.tolerance) {
+            Ok(())
+        } else {
+            Err(ConservationError::Imbalance {
+                link: link.to_string(),
+                sent: ledger.sent,
+                received: ledger.received,
+                tolerance: self.tolerance,
+            })
+        }
+    }
+
+    /// Every link whose totals diverge
+    pub fn imbalances(&self) -> Vec<ConservationError> {
+        self.links
+            .keys()
+            .filter_map(|link| self.check_link(link).err())
+            .collect()
+    }
+}
+
+/// A column of a trial's resource
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LinkEnd {
+    pub trial: String,
+    pub resource: String,
+    pub column: String,
+}
+
+#[cfg(feature = "data")]
+impl LinkEnd {
+    /// The sum of the column
+    pub fn total(&self, config: &ExperimentConfig) -> Result<f64, ExperimentError> {
+        let trial = config.trial(&self.trial)?;
+        let resource = trial
+            .sinks
+            .get(&self.resource)
+            .or_else(|| trial.sources.get(&self.resource))
+            .ok_or_else(|| ExperimentError::UnknownSink(self.resource.clone()))?;
+        let mut total = 0.0;
+        for rb in read_batches(resource)? {
+            let values = numeric_values(&rb, &self.column)
+                .ok_or_else(|| ExperimentError::NotTabular(self.to_string()))?;
+            total += values.iter().filter(|v| !v.is_nan()).sum::<f64>();
+        }
+        Ok(total)
+    }
+}
+
+impl std::fmt::Display for LinkEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.trial, self.resource, self.column)
+    }
+}
+
+/// A link between trials whose totals must match, checked after a run
+///
+/// `from` is usually a sink of one trial and `to` a sink of the trial that
+/// consumed it, such as total runoff and total infiltration.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ConservationCheck {
+    pub name: String,
+    pub from: LinkEnd,
+    pub to: LinkEnd,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+impl ConservationCheck {
+    /// Problems with trials and resources the check refers to
+    pub fn problems(&self, config: &ExperimentConfig) -> Vec<String> {
+        let mut problems = vec![];
+        for end in [&self.from, &self.to].iter() {
+            match config.trials.iter().find(|t| t.name == end.trial) {
+                None => problems.push(format!(
+                    "conservation check {} refers to unknown trial {}",
+                    self.name, end.trial
+                )),
+                Some(t)
+                    if !t.sinks.contains_key(&end.resource)
+                        && !t.sources.contains_key(&end.resource) =>
+                {
+                    problems.push(format!(
+                        "conservation check {} refers to unknown resource {}.{}",
+                        self.name, end.trial, end.resource
+                    ))
+                }
+                _ => {}
+            }
+        }
+        problems
+    }
+
+    #[cfg(feature = "data")]
+    pub fn check(&self, config: &ExperimentConfig) -> Result<FluxLedger, ExperimentError> {
+        Ok(FluxLedger {
+            sent: self.from.total(config)?,
+            received: self.to.total(config)?,
+        })
+    }
+}
+
+/// The conservation checks of an experiment that fail
+#[cfg(feature = "data")]
+pub fn check_experiment(
+    config: &ExperimentConfig,
+) -> Result<Vec<ConservationError>, ExperimentError> {
+    let mut failures = vec![];
+    for c in config.conservation.iter() {
+        let ledger = c.check(config)?;
+        if !ledger.balanced(c.tolerance) {
+            failures.push(ConservationError::Imbalance {
+                link: c.name.clone(),
+                sent: ledger.sent,
+                received: ledger.received,
+                tolerance: c.tolerance,
+            });
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conservation::{ConservationError, ConservationLedger};
+
+    #[test]
+    fn ledger_catches_imbalance() {
+        let mut ledger = ConservationLedger::new(1e-3);
+        for runoff in [1.0, 2.0, f64::NAN].iter() {
+            ledger.send("runoff", *runoff);
+        }
+        ledger.receive("runoff", 3.0);
+        // mm received as m
+        ledger.send("drainage", 10.0);
+        ledger.receive("drainage", 0.01);
+        assert_eq!(ledger.check_link("runoff"), Ok(()));
+        assert_eq!(
+            ledger.imbalances(),
+            vec![ConservationError::Imbalance {
+                link: "drainage".to_string(),
+                sent: 10.0,
+                received: 0.01,
+                tolerance: 1e-3
+            }]
+        );
+        assert!(ledger.check_link("evaporation").is_err());
+    }
+}
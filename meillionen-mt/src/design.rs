@@ -0,0 +1,568 @@
+//! Factorial experiment designs
+//!
+//! A [`Design`] crosses [`Factor`]s, such as cultivar, planting date and
+//! irrigation regime, each with a few levels. Its treatments are every
+//! combination of levels, or a regular fraction of them when some two-level
+//! factors are given by [`Generator`]s, repeated `replicates` times.
+//!
+//! [`Design::trials`] makes a trial of each treatment and replicate from a
+//! template whose strings can refer to `{FACTOR}` for the level of a factor,
+//! `{treatment}` and `{replicate}`. The trials are tagged with the level of
+//! each factor and the replicate, which [`stack_by_tags`] turns into key
+//! columns of the stacked outputs for analysis of variance.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::aliases::Aliases;
+use crate::experiment::{fill_placeholders, ExperimentConfig, ExperimentError, TrialConfig};
+#[cfg(feature = "data")]
+use crate::experiment::{RunOutcome, TrialStatus};
+use crate::repro::ReproConfig;
+use crate::schema::current_version;
+#[cfg(feature = "data")]
+use crate::stack::{ResultStack, StackError, StackLayout};
+use crate::tags::{self, TagError, Tags};
+
+/// The tag and placeholder holding the replicate of a trial
+pub const REPLICATE: &str = "replicate";
+
+#[derive(Debug, Error)]
+pub enum DesignError {
+    #[error("a design needs at least one factor")]
+    NoFactors,
+    #[error("factor {0} has no levels")]
+    NoLevels(String),
+    #[error("factor {0} is given more than once")]
+    DuplicateFactor(String),
+    #[error("factor {0} has the name of a placeholder every trial has")]
+    ReservedFactor(String),
+    #[error("generator of {factor}: {problem}")]
+    Generator { factor: String, problem: String },
+    #[error("a design needs at least one replicate")]
+    NoReplicates,
+    #[error("trial {trial} has no tag {key}")]
+    MissingTag { trial: String, key: String },
+    #[error(transparent)]
+    Tag(#[from] TagError),
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Stack(#[from] StackError),
+}
+
+/// A level of a factor, such as a cultivar name or a day of the year
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Level {
+    Integer(i64),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Integer(i) => write!(f, "{}", i),
+            Level::Number(x) => write!(f, "{}", x),
+            Level::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Something varied between treatments and the values it takes
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Factor {
+    pub name: String,
+    pub levels: Vec<Level>,
+}
+
+/// Gives the level of a two-level factor from those of other two-level
+/// factors rather than crossing it with them
+///
+/// Taking the first level of each factor as -1 and the second as +1, the
+/// factor is at the level of the product of the `from` factors, so `D = ABC`
+/// runs four factors in the eight treatments of three. The effect of the
+/// factor is then confounded with the interaction of the `from` factors.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Generator {
+    pub factor: String,
+    pub from: Vec<String>,
+}
+
+/// A combination of levels, one of each factor in the order of the design
+#[derive(Clone, Debug, PartialEq)]
+pub struct Treatment {
+    pub levels: Vec<(String, Level)>,
+}
+
+impl Treatment {
+    /// The levels joined by `-`, such as `maize-110-full`
+    pub fn name(&self) -> String {
+        self.levels
+            .iter()
+            .map(|(_, level)| level.to_string())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn is_one(n: &u32) -> bool {
+    *n == 1
+}
+
+/// Factors crossed to make treatments
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Design {
+    pub factors: Vec<Factor>,
+    /// Factors given by others, for a fractional factorial design
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generators: Vec<Generator>,
+    /// How many times each treatment is run
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub replicates: u32,
+}
+
+impl Design {
+    /// The full factorial design of `factors`
+    pub fn full(factors: Vec<Factor>) -> Self {
+        Self {
+            factors,
+            generators: vec![],
+            replicates: 1,
+        }
+    }
+
+    fn factor(&self, name: &str) -> Option<&Factor> {
+        self.factors.iter().find(|f| f.name == name)
+    }
+
+    fn check(&self) -> Result<(), DesignError> {
+        if self.factors.is_empty() {
+            return Err(DesignError::NoFactors);
+        }
+        if self.replicates == 0 {
+            return Err(DesignError::NoReplicates);
+        }
+        let mut names = BTreeSet::new();
+        for factor in self.factors.iter() {
+            tags::check_key(&factor.name)?;
+            if factor.name == "treatment" || factor.name == REPLICATE {
+                return Err(DesignError::ReservedFactor(factor.name.clone()));
+            }
+            if !names.insert(factor.name.as_str()) {
+                return Err(DesignError::DuplicateFactor(factor.name.clone()));
+            }
+            if factor.levels.is_empty() {
+                return Err(DesignError::NoLevels(factor.name.clone()));
+            }
+        }
+        let generated: BTreeSet<&str> = self.generators.iter().map(|g| g.factor.as_str()).collect();
+        for generator in self.generators.iter() {
+            let problem = |problem: String| DesignError::Generator {
+                factor: generator.factor.clone(),
+                problem,
+            };
+            if self
+                .generators
+                .iter()
+                .filter(|g| g.factor == generator.factor)
+                .count()
+                > 1
+            {
+                return Err(problem(
+                    "the factor is generated more than once".to_string(),
+                ));
+            }
+            if generator.from.is_empty() {
+                return Err(problem("no factors to generate from".to_string()));
+            }
+            for name in std::iter::once(&generator.factor).chain(generator.from.iter()) {
+                match self.factor(name) {
+                    None => return Err(problem(format!("there is no factor {}", name))),
+                    Some(f) if f.levels.len() != 2 => {
+                        return Err(problem(format!("factor {} does not have two levels", name)))
+                    }
+                    Some(_) => {}
+                }
+            }
+            if let Some(name) = generator
+                .from
+                .iter()
+                .find(|n| generated.contains(n.as_str()))
+            {
+                return Err(problem(format!("factor {} is generated itself", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Every treatment of the design, the level of the first factor changing
+    /// slowest
+    pub fn treatments(&self) -> Result<Vec<Treatment>, DesignError> {
+        self.check()?;
+        let base: Vec<usize> = (0..self.factors.len())
+            .filter(|i| {
+                let name = &self.factors[*i].name;
+                !self.generators.iter().any(|g| &g.factor == name)
+            })
+            .collect();
+        let position = |name: &str| self.factors.iter().position(|f| f.name == name).unwrap();
+        let mut treatments = vec![];
+        let mut indices = vec![0; self.factors.len()];
+        loop {
+            for generator in self.generators.iter() {
+                let positive = generator
+                    .from
+                    .iter()
+                    .filter(|name| indices[position(name)] == 0)
+                    .count()
+                    % 2
+                    == 0;
+                indices[position(&generator.factor)] = if positive { 1 } else { 0 };
+            }
+            treatments.push(Treatment {
+                levels: self
+                    .factors
+                    .iter()
+                    .zip(indices.iter())
+                    .map(|(f, i)| (f.name.clone(), f.levels[*i].clone()))
+                    .collect(),
+            });
+            // advance the base factors like an odometer, the last fastest
+            let mut carried = true;
+            for i in base.iter().rev() {
+                indices[*i] += 1;
+                if indices[*i] < self.factors[*i].levels.len() {
+                    carried = false;
+                    break;
+                }
+                indices[*i] = 0;
+            }
+            if carried {
+                return Ok(treatments);
+            }
+        }
+    }
+
+    /// The tags keying a trial of the design: the factors, then the
+    /// replicate when there is more than one
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self.factors.iter().map(|f| f.name.as_str()).collect();
+        if self.replicates > 1 {
+            keys.push(REPLICATE);
+        }
+        keys
+    }
+
+    /// A trial of each treatment and replicate made from `template`
+    ///
+    /// Trials are named `TEMPLATE.TREATMENT`, with `.rN` after it for
+    /// replicate N when there is more than one.
+    pub fn trials(&self, template: &TrialConfig) -> Result<Vec<TrialConfig>, DesignError> {
+        let mut trials = vec![];
+        for treatment in self.treatments()? {
+            for replicate in 1..=self.replicates {
+                let mut name = format!("{}.{}", template.name, treatment.name());
+                if self.replicates > 1 {
+                    name = format!("{}.r{}", name, replicate);
+                }
+                let lookup = |key: &str| match key {
+                    "treatment" => Some(Some(treatment.name())),
+                    REPLICATE => Some(Some(replicate.to_string())),
+                    _ => treatment
+                        .levels
+                        .iter()
+                        .find(|(factor, _)| factor == key)
+                        .map(|(_, level)| Some(level.to_string())),
+                };
+                let mut value = serde_json::to_value(template).map_err(ExperimentError::from)?;
+                fill_placeholders(&mut value, &lookup)
+                    .expect("every placeholder of a design to have a value");
+                let mut trial: TrialConfig =
+                    serde_json::from_value(value).map_err(ExperimentError::from)?;
+                trial.name = name;
+                let mut labels: Tags = treatment
+                    .levels
+                    .iter()
+                    .map(|(factor, level)| (factor.clone(), level.to_string()))
+                    .collect();
+                if self.replicates > 1 {
+                    labels.insert(REPLICATE.to_string(), replicate.to_string());
+                }
+                trial.tags = tags::merge(&trial.tags, &labels);
+                trials.push(trial);
+            }
+        }
+        Ok(trials)
+    }
+}
+
+/// A design run with one template trial
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FactorialConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repro: Option<ReproConfig>,
+    #[serde(default, skip_serializing_if = "Tags::is_empty")]
+    pub tags: Tags,
+    /// Names the outputs of every trial are written under, see
+    /// [`crate::aliases`]
+    #[serde(default, skip_serializing_if = "Aliases::is_empty")]
+    pub aliases: Aliases,
+    pub design: Design,
+    /// The trial run for each treatment, which may refer to its levels
+    pub trial: TrialConfig,
+}
+
+impl FactorialConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DesignError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(ExperimentError::from)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&text).map_err(ExperimentError::from)?),
+            Some("yaml") | Some("yml") => {
+                Ok(serde_yaml::from_str(&text).map_err(ExperimentError::from)?)
+            }
+            _ => Err(ExperimentError::UnknownFormat(path.display().to_string()).into()),
+        }
+    }
+
+    /// The validated experiment running each treatment and replicate
+    pub fn experiment(&self) -> Result<ExperimentConfig, DesignError> {
+        let experiment = ExperimentConfig {
+            version: current_version(),
+            name: self.name.clone(),
+            repro: self.repro.clone(),
+            store: None,
+            tags: self.tags.clone(),
+            aliases: self.aliases.clone(),
+            trials: self.design.trials(&self.trial)?,
+            conservation: vec![],
+        };
+        experiment.validate()?;
+        Ok(experiment)
+    }
+}
+
+/// Stack a tabular sink of the succeeded runs of an experiment with a key
+/// column for each of the tags `keys`, such as [`Design::keys`]
+///
+/// Runs are stacked in the order of the trials of `config`. Returns `None`
+/// if no run has the sink.
+#[cfg(feature = "data")]
+pub fn stack_by_tags(
+    config: &ExperimentConfig,
+    outcomes: &[RunOutcome],
+    keys: &[&str],
+    sink: &str,
+    layout: StackLayout,
+) -> Result<Option<RecordBatch>, DesignError> {
+    let mut stack = ResultStack::new(keys);
+    for trial in config.trials.iter() {
+        let tags = config.trial_tags(trial);
+        let key = keys
+            .iter()
+            .map(|k| {
+                tags.get(*k)
+                    .map(String::as_str)
+                    .ok_or_else(|| DesignError::MissingTag {
+                        trial: trial.name.clone(),
+                        key: k.to_string(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let batches = outcomes
+            .iter()
+            .filter(|o| o.trial == trial.name && o.status == TrialStatus::Succeeded)
+            .filter_map(|o| o.sinks.get(sink))
+            .flatten();
+        for batch in batches {
+            stack.push(&key, batch.clone())?;
+        }
+    }
+    Ok(stack.finish(layout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::design::{Design, DesignError, Factor, FactorialConfig, Generator, Level};
+
+    fn factor(name: &str, levels: &[&str]) -> Factor {
+        Factor {
+            name: name.to_string(),
+            levels: levels.iter().map(|l| Level::Text(l.to_string())).collect(),
+        }
+    }
+
+    fn names(design: &Design) -> Vec<String> {
+        design
+            .treatments()
+            .unwrap()
+            .iter()
+            .map(|t| t.name())
+            .collect()
+    }
+
+    #[test]
+    fn full_and_fractional() {
+        let full = Design::full(vec![
+            factor("cultivar", &["a", "b"]),
+            factor("planting", &["early", "mid", "late"]),
+        ]);
+        assert_eq!(
+            names(&full),
+            vec!["a-early", "a-mid", "a-late", "b-early", "b-mid", "b-late"]
+        );
+
+        let mut half = Design::full(vec![
+            factor("a", &["lo", "hi"]),
+            factor("b", &["lo", "hi"]),
+            factor("c", &["lo", "hi"]),
+        ]);
+        half.generators = vec![Generator {
+            factor: "c".to_string(),
+            from: vec!["a".to_string(), "b".to_string()],
+        }];
+        assert_eq!(
+            names(&half),
+            vec!["lo-lo-hi", "lo-hi-lo", "hi-lo-lo", "hi-hi-hi"]
+        );
+
+        let mut bad = half.clone();
+        bad.generators[0].from.push("planting".to_string());
+        assert!(matches!(
+            bad.treatments(),
+            Err(DesignError::Generator { .. })
+        ));
+        let mut reserved = full.clone();
+        reserved.factors[0].name = "treatment".to_string();
+        assert!(matches!(
+            reserved.treatments(),
+            Err(DesignError::ReservedFactor(_))
+        ));
+    }
+
+    const CONFIG: &str = r#"
+name = "maize factorial"
+
+[design]
+replicates = 2
+factors = [
+    { name = "cultivar", levels = ["pioneer", "dekalb"] },
+    { name = "planting", levels = [110, 130] },
+]
+
+[trial]
+name = "maize"
+model = "simplecrop_omf"
+args = ["--cultivar", "{cultivar}", "--planting-day", "{planting}"]
+sinks = { yearly = { type = "feather", path = "out/{treatment}/{replicate}.feather" } }
+"#;
+
+    #[test]
+    fn trials_from_template() {
+        let config: FactorialConfig = toml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            config.design.keys(),
+            vec!["cultivar", "planting", "replicate"]
+        );
+        let experiment = config.experiment().unwrap();
+        assert_eq!(experiment.trials.len(), 8);
+        let trial = &experiment.trials[3];
+        assert_eq!(trial.name, "maize.pioneer-130.r2");
+        assert_eq!(
+            trial.args,
+            vec!["--cultivar", "pioneer", "--planting-day", "130"]
+        );
+        assert_eq!(trial.sinks["yearly"].path(), "out/pioneer-130/2.feather");
+        assert_eq!(trial.tags["planting"], "130");
+        assert_eq!(trial.tags["replicate"], "2");
+
+        let mut unknown = config.clone();
+        unknown.trial.args.push("{irrigation}".to_string());
+        // not a factor so left as it is
+        assert_eq!(
+            unknown.design.trials(&unknown.trial).unwrap()[0].args[4],
+            "{irrigation}"
+        );
+    }
+
+    #[cfg(feature = "data")]
+    #[test]
+    fn stack_with_factor_columns() {
+        use std::collections::BTreeMap;
+        use std::sync::Arc;
+
+        use arrow::array::{ArrayRef, Float32Array, StringArray};
+        use arrow::compute::cast;
+        use arrow::datatypes::{DataType, Field, Schema};
+        use arrow::record_batch::RecordBatch;
+
+        use crate::design::{stack_by_tags, DesignError};
+        use crate::experiment::{RunOutcome, TrialLogs, TrialStatus};
+        use crate::stack::StackLayout;
+
+        let config: FactorialConfig = toml::from_str(CONFIG).unwrap();
+        let experiment = config.experiment().unwrap();
+        let outcomes: Vec<RunOutcome> = experiment
+            .trials
+            .iter()
+            .enumerate()
+            .map(|(i, trial)| {
+                let schema = Arc::new(Schema::new(vec![Field::new(
+                    "plant_matter_fruit",
+                    DataType::Float32,
+                    false,
+                )]));
+                let columns: Vec<ArrayRef> = vec![Arc::new(Float32Array::from(vec![i as f32]))];
+                let mut sinks = BTreeMap::new();
+                sinks.insert(
+                    "yearly".to_string(),
+                    vec![RecordBatch::try_new(schema, columns).unwrap()],
+                );
+                RunOutcome {
+                    trial: trial.name.clone(),
+                    status: TrialStatus::Succeeded,
+                    sinks,
+                    logs: TrialLogs::default(),
+                    metrics: BTreeMap::new(),
+                }
+            })
+            .collect();
+        let stacked = stack_by_tags(
+            &experiment,
+            &outcomes,
+            &config.design.keys(),
+            "yearly",
+            StackLayout::Wide,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(stacked.num_rows(), 8);
+        let planting = cast(stacked.column(1), &DataType::Utf8).unwrap();
+        let planting = planting.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(planting.value(2), "130");
+        assert!(matches!(
+            stack_by_tags(
+                &experiment,
+                &outcomes,
+                &["site"],
+                "yearly",
+                StackLayout::Wide
+            ),
+            Err(DesignError::MissingTag { .. })
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Getting the same numbers on every platform
+//!
+//! Most of the float arithmetic models and transforms do is exact IEEE 754
+//! and comes out the same everywhere: Rust never fuses a multiply and an add
+//! unless asked to, `sqrt` is correctly rounded, and the parallel reductions
+//! in [`crate::variable::parallel`] add each element's values in the same
+//! order as the serial ones. What differs between platforms is
+//!
+//! - transcendental functions such as `exp`, `ln`, `powf` and `cos`, which
+//!   call the C maths library of the platform and may differ in the last bit
+//!   between glibc, musl, macOS and Windows, and `powi` above squaring, whose
+//!   rounding depends on how the compiler expands it
+//! - text written with a fixed number of digits, such as the summary tables
+//!   of reports, which reads back to a different value than was written
+//!
+//! In deterministic mode the functions here compute with the pure Rust port
+//! of musl's maths library in the `libm` crate instead, and [`format_f64`]
+//! writes the shortest decimal that reads back to the same bits. Outside it
+//! they are the standard library functions. Parsing needs no mode, Rust
+//! parses decimals to the nearest float on every platform.
+//!
+//! The runner passes the mode on to model programs in [`DETERMINISTIC_ENV`],
+//! which is read the first time the mode is checked.
+//!
+//! The `golden` feature builds a test of these functions against values
+//! recorded on one platform, to run on the others.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The environment variable that turns deterministic mode on when it is `1`
+pub const DETERMINISTIC_ENV: &str = "MEILLIONEN_DETERMINISTIC";
+
+const UNSET: u8 = 0;
+const OFF: u8 = 1;
+const ON: u8 = 2;
+
+static DETERMINISTIC: AtomicU8 = AtomicU8::new(UNSET);
+
+pub fn set_deterministic(deterministic: bool) {
+    DETERMINISTIC.store(if deterministic { ON } else { OFF }, Ordering::Relaxed);
+}
+
+pub fn is_deterministic() -> bool {
+    match DETERMINISTIC.load(Ordering::Relaxed) {
+        UNSET => {
+            let on = std::env::var(DETERMINISTIC_ENV).is_ok_and(|v| v == "1");
+            set_deterministic(on);
+            on
+        }
+        mode => mode == ON,
+    }
+}
+
+pub fn exp(x: f64) -> f64 {
+    if is_deterministic() {
+        libm::exp(x)
+    } else {
+        x.exp()
+    }
+}
+
+pub fn expf(x: f32) -> f32 {
+    if is_deterministic() {
+        libm::expf(x)
+    } else {
+        x.exp()
+    }
+}
+
+pub fn ln(x: f64) -> f64 {
+    if is_deterministic() {
+        libm::log(x)
+    } else {
+        x.ln()
+    }
+}
+
+pub fn lnf(x: f32) -> f32 {
+    if is_deterministic() {
+        libm::logf(x)
+    } else {
+        x.ln()
+    }
+}
+
+pub fn cos(x: f64) -> f64 {
+    if is_deterministic() {
+        libm::cos(x)
+    } else {
+        x.cos()
+    }
+}
+
+pub fn powf(x: f64, y: f64) -> f64 {
+    if is_deterministic() {
+        libm::pow(x, y)
+    } else {
+        x.powf(y)
+    }
+}
+
+pub fn powff(x: f32, y: f32) -> f32 {
+    if is_deterministic() {
+        libm::powf(x, y)
+    } else {
+        x.powf(y)
+    }
+}
+
+pub fn powi(x: f64, n: i32) -> f64 {
+    if is_deterministic() {
+        libm::pow(x, f64::from(n))
+    } else {
+        x.powi(n)
+    }
+}
+
+pub fn powif(x: f32, n: i32) -> f32 {
+    if is_deterministic() {
+        libm::powf(x, n as f32)
+    } else {
+        x.powi(n)
+    }
+}
+
+/// A float as text, exactly in deterministic mode and to `digits` decimal
+/// places otherwise
+pub fn format_f64(x: f64, digits: usize) -> String {
+    if is_deterministic() {
+        // the shortest decimal that parses back to x, with an exponent for
+        // very large or small values
+        format!("{:?}", x)
+    } else {
+        format!("{:.*}", digits, x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::determinism::{exp, format_f64, is_deterministic, powi};
+
+    // deterministic mode is tested by tests/golden.rs, in its own process so
+    // turning it on does not change the numbers other tests see
+    #[test]
+    fn standard_library_by_default() {
+        assert!(!is_deterministic());
+        assert_eq!(format_f64(0.1 + 0.2, 4), "0.3000");
+        assert_eq!(exp(1.5), 1.5f64.exp());
+        assert_eq!(powi(1.1, 3), 1.1f64.powi(3));
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::experiment::ExperimentError;
+
+/// Relative difference below which two numbers are considered equal by
+/// default
+pub const TOLERANCE: f64 = 1e-9;
+
+/// Units a quantity like `"25 mm"` may be written in, with their dimension
+/// and the factor to the dimension's base unit
+const UNITS: [(&str, &str, f64); 16] = [
+    ("mm", "length", 1e-3),
+    ("cm", "length", 1e-2),
+    ("m", "length", 1.0),
+    ("km", "length", 1e3),
+    ("g", "mass", 1e-3),
+    ("kg", "mass", 1.0),
+    ("t", "mass", 1e3),
+    ("s", "time", 1.0),
+    ("min", "time", 60.0),
+    ("h", "time", 3600.0),
+    ("d", "time", 86400.0),
+    ("day", "time", 86400.0),
+    ("days", "time", 86400.0),
+    ("degC", "temperature", 1.0),
+    ("%", "fraction", 1e-2),
+    ("1", "fraction", 1.0),
+];
+
+/// The factor from a unit to the base unit of its dimension, and the dimension
+pub(crate) fn unit(unit: &str) -> Option<(f64, &'static str)> {
+    UNITS
+        .iter()
+        .find(|(u, _, _)| *u == unit)
+        .map(|(_, dimension, factor)| (*factor, *dimension))
+}
+
+/// A number with units, in the base unit of its dimension
+fn quantity(s: &str) -> Option<(f64, &'static str)> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .unwrap_or(s.len());
+    let value: f64 = s[..split].trim().parse().ok()?;
+    unit(s[split..].trim()).map(|(factor, dimension)| (value * factor, dimension))
+}
+
+fn close(a: f64, b: f64, tolerance: f64) -> bool {
+    a == b || (a - b).abs() <= tolerance * a.abs().max(b.abs())
+}
+
+fn same_leaf(a: &Value, b: &Value, tolerance: f64) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => match (x.as_f64(), y.as_f64()) {
+            (Some(x), Some(y)) => close(x, y, tolerance),
+            _ => x == y,
+        },
+        (Value::String(x), Value::String(y)) => {
+            x == y
+                || match (quantity(x), quantity(y)) {
+                    (Some((x, dx)), Some((y, dy))) => dx == dy && close(x, y, tolerance),
+                    _ => false,
+                }
+        }
+        _ => a == b,
+    }
+}
+
+/// A number, or a quantity with units in the base unit of its dimension
+fn magnitude(value: &Value) -> Option<(f64, Option<&'static str>)> {
+    match value {
+        Value::Number(n) => n.as_f64().map(|x| (x, None)),
+        Value::String(s) => quantity(s).map(|(x, dimension)| (x, Some(dimension))),
+        _ => None,
+    }
+}
+
+/// A field that differs between two configs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    /// Where the field is, such as `trials[0].sinks.yearly.path`
+    pub path: String,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.left, &self.right) {
+            (Some(l), Some(r)) => write!(f, "~ {}: {} -> {}", self.path, l, r),
+            (Some(l), None) => write!(f, "- {}: {}", self.path, l),
+            (None, Some(r)) => write!(f, "+ {}: {}", self.path, r),
+            (None, None) => write!(f, "  {}", self.path),
+        }
+    }
+}
+
+impl Difference {
+    /// The old and new values of a changed number, quantities in the base
+    /// unit of their dimension
+    pub fn values(&self) -> Option<(f64, f64)> {
+        let (old, old_dimension) = magnitude(self.left.as_ref()?)?;
+        let (new, new_dimension) = magnitude(self.right.as_ref()?)?;
+        if old_dimension == new_dimension {
+            Some((old, new))
+        } else {
+            None
+        }
+    }
+
+    /// The change of a number relative to its old value in percent, `None`
+    /// when the old value is zero or the field is not a number
+    pub fn percent_change(&self) -> Option<f64> {
+        self.values()
+            .filter(|(old, _)| *old != 0.0)
+            .map(|(old, new)| (new - old) / old.abs() * 100.0)
+    }
+
+    /// The cells of a row of a table of differences: the field, the old and
+    /// new values, written as in the config, and the percent change
+    pub fn cells(&self) -> [String; 4] {
+        let cell = |v: &Option<Value>| match v {
+            None => String::new(),
+            Some(Value::String(s)) => s.clone(),
+            Some(v) => v.to_string(),
+        };
+        let change = match (&self.left, &self.right, self.percent_change()) {
+            (_, _, Some(p)) => format!("{:+.2}%", p),
+            (None, _, _) => "added".to_string(),
+            (_, None, _) => "removed".to_string(),
+            _ => String::new(),
+        };
+        [
+            self.path.clone(),
+            cell(&self.left),
+            cell(&self.right),
+            change,
+        ]
+    }
+}
+
+/// The column names of [`Difference::cells`]
+pub const TABLE_HEADER: [&str; 4] = ["field", "old", "new", "change"];
+
+/// A plain text table of differences with aligned columns
+pub fn diff_table(differences: &[Difference]) -> String {
+    let rows: Vec<[String; 4]> = std::iter::once(TABLE_HEADER.map(String::from))
+        .chain(differences.iter().map(Difference::cells))
+        .collect();
+    let mut widths = [0; 4];
+    for row in rows.iter() {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, w)| format!("{:<1$}", cell, w))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn diff_at(
+    path: String,
+    a: Option<&Value>,
+    b: Option<&Value>,
+    tolerance: f64,
+    out: &mut Vec<Difference>,
+) {
+    match (a, b) {
+        (Some(Value::Object(x)), Some(Value::Object(y))) => {
+            let mut keys: Vec<&String> = x.keys().chain(y.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for k in keys {
+                diff_at(join(&path, k), x.get(k), y.get(k), tolerance, out);
+            }
+        }
+        (Some(Value::Array(x)), Some(Value::Array(y))) => {
+            for i in 0..x.len().max(y.len()) {
+                diff_at(
+                    format!("{}[{}]", path, i),
+                    x.get(i),
+                    y.get(i),
+                    tolerance,
+                    out,
+                );
+            }
+        }
+        (Some(x), Some(y)) if same_leaf(x, y, tolerance) => {}
+        (None, None) => {}
+        (a, b) => out.push(Difference {
+            path,
+            left: a.cloned(),
+            right: b.cloned(),
+        }),
+    }
+}
+
+/// Compare two configs field by field
+///
+/// Numbers are equal if they agree to within [`TOLERANCE`] and quantities
+/// written with units (`"25 mm"`, `"2.5 cm"`) are compared after conversion.
+pub fn diff(a: &Value, b: &Value) -> Vec<Difference> {
+    diff_with_tolerance(a, b, TOLERANCE)
+}
+
+/// Compare two configs like [`diff`], with numbers equal if their relative
+/// difference is at most `tolerance`
+pub fn diff_with_tolerance(a: &Value, b: &Value, tolerance: f64) -> Vec<Difference> {
+    let mut out = vec![];
+    diff_at(String::new(), Some(a), Some(b), tolerance, &mut out);
+    out
+}
+
+/// A number written the same way however it was given, to 12 significant
+/// digits so a quantity converted from other units hashes like one that
+/// was not
+fn canonical_number(x: f64) -> Value {
+    if x.fract() == 0.0 && x.abs() < 2f64.powi(53) {
+        Value::from(x as i64)
+    } else {
+        Value::from(format!("{:.11e}", x))
+    }
+}
+
+/// A copy of a config where fields that compare equal in [`diff`] are
+/// written the same way
+///
+/// Object keys are sorted, arrays of objects that all have a `name` (such
+/// as trials) are sorted by name, numbers are rounded and quantities with
+/// units are converted to the base unit of their dimension. Other arrays
+/// keep their order since it can matter, as with the arguments of a model.
+pub fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let mut out = Map::new();
+            for k in keys {
+                out.insert(k.clone(), canonical(&map[k]));
+            }
+            Value::Object(out)
+        }
+        Value::Array(items) => {
+            let mut items: Vec<Value> = items.iter().map(canonical).collect();
+            if items.iter().all(|i| i["name"].is_string()) {
+                items.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+            }
+            Value::Array(items)
+        }
+        Value::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(_), _, _) | (_, Some(_), _) => value.clone(),
+            (_, _, Some(x)) => canonical_number(x),
+            _ => value.clone(),
+        },
+        Value::String(s) => match quantity(s) {
+            Some((x, dimension)) => {
+                let mut q = Map::new();
+                q.insert(dimension.to_string(), canonical_number(x));
+                Value::Object(q)
+            }
+            None => value.clone(),
+        },
+        _ => value.clone(),
+    }
+}
+
+/// The lowercase hex sha256 of the [`canonical`] form of a config
+pub fn content_hash(value: &Value) -> String {
+    let text = serde_json::to_string(&canonical(value)).expect("json values to serialize");
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Read an experiment file or run manifest (TOML, YAML or JSON) to compare
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Value, ExperimentError> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Ok(toml::from_str(&text)?),
+        Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&text)?),
+        Some("json") => Ok(serde_json::from_str(&text)?),
+        _ => Err(ExperimentError::UnknownFormat(path.display().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::diff::{content_hash, diff, diff_table, diff_with_tolerance, quantity};
+
+    #[test]
+    fn quantities() {
+        assert_eq!(quantity("25 mm"), Some((0.025, "length")));
+        assert_eq!(quantity("1.5e1days"), Some((15.0 * 86400.0, "time")));
+        assert_eq!(quantity("25 furlongs"), None);
+        assert_eq!(quantity("mm"), None);
+    }
+
+    #[test]
+    fn field_differences() {
+        let a = json!({
+            "name": "irrigation",
+            "irrigation": "25 mm",
+            "rate": 0.1,
+            "trials": [{"name": "a"}, {"name": "b"}]
+        });
+        let b = json!({
+            "name": "irrigation",
+            "irrigation": "2.5 cm",
+            "rate": 0.1 + 1e-12,
+            "trials": [{"name": "a", "model": "simplecrop"}],
+            "seed": 4
+        });
+        let lines: Vec<String> = diff(&a, &b).iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "+ seed: 4",
+                "+ trials[0].model: \"simplecrop\"",
+                "- trials[1]: {\"name\":\"b\"}",
+            ]
+        );
+        let c = json!({"irrigation": "25 kg"});
+        assert_eq!(diff(&json!({"irrigation": "25 mm"}), &c).len(), 1);
+    }
+
+    #[test]
+    fn change_table() {
+        let a = json!({"irrigation": "25 mm", "rate": 0.1, "density": 3.5, "model": "simplecrop"});
+        let b = json!({"irrigation": "3 cm", "rate": 0.1001, "density": 0, "seed": 4});
+        let loose = diff_with_tolerance(&a, &b, 0.01);
+        let paths: Vec<&str> = loose.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec!["density", "irrigation", "model", "seed"]);
+        assert_eq!(loose[1].values(), Some((0.025, 0.03)));
+        assert_eq!(diff(&a, &b).len(), 5);
+        assert_eq!(
+            diff_table(&loose),
+            concat!(
+                "field       old         new   change\n",
+                "density     3.5         0     -100.00%\n",
+                "irrigation  25 mm       3 cm  +20.00%\n",
+                "model       simplecrop        removed\n",
+                "seed                    4     added\n",
+            )
+        );
+    }
+
+    #[test]
+    fn hashes_ignore_order_and_units() {
+        let a = json!({
+            "name": "irrigation",
+            "irrigation": "25 mm",
+            "rate": 1.0,
+            "trials": [
+                {"name": "a", "args": ["--wet", "--fast"]},
+                {"name": "b", "model": "simplecrop"}
+            ]
+        });
+        let b = json!({
+            "trials": [
+                {"model": "simplecrop", "name": "b"},
+                {"args": ["--wet", "--fast"], "name": "a"}
+            ],
+            "rate": 1,
+            "irrigation": "2.5 cm",
+            "name": "irrigation"
+        });
+        assert_eq!(content_hash(&a), content_hash(&b));
+        assert_eq!(content_hash(&a).len(), 64);
+
+        let mut c = a.clone();
+        c["trials"][0]["args"] = json!(["--fast", "--wet"]);
+        assert_ne!(content_hash(&a), content_hash(&c));
+        let mut d = a.clone();
+        d["irrigation"] = json!("25 kg");
+        assert_ne!(content_hash(&a), content_hash(&d));
+    }
+}
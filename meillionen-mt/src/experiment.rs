@@ -0,0 +1,1003 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+#[cfg(feature = "data")]
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(feature = "data")]
+use std::sync::Arc;
+
+#[cfg(feature = "data")]
+use arrow::ipc::reader::FileReader;
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "data")]
+use parquet::arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader};
+#[cfg(feature = "data")]
+use parquet::file::reader::{ChunkReader, SerializedFileReader};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::aliases::{Aliases, ALIASES_ENV};
+use crate::arg::resource::{
+    FeatherResource, FileResource, MultiNetCDFResource, NetCDFResource, ParquetResource,
+};
+use crate::conservation::ConservationCheck;
+#[cfg(feature = "data")]
+use crate::determinism::is_deterministic;
+use crate::determinism::DETERMINISTIC_ENV;
+use crate::diff;
+#[cfg(feature = "data")]
+use crate::model::{client_call_cli_with_args, output_text, ResourceBuilder};
+use crate::outputs::{OutputSelection, OUTPUTS_ENV};
+use crate::repro::{ReproConfig, SEED_ENV};
+#[cfg(feature = "data")]
+use crate::run_metrics::MetricRegistry;
+use crate::schema::{current_version, migrate, EXPERIMENT_MIGRATIONS};
+use crate::store::StoreConfig;
+use crate::tags::{self, Tags};
+#[cfg(feature = "data")]
+use crate::trace::TraceContext;
+use crate::trace::{OTLP_FILE_ENV, TRACEPARENT_ENV};
+#[cfg(feature = "data")]
+use crate::transforms::TransformError;
+use crate::transforms::{Transforms, TRANSFORMS_ENV};
+#[cfg(feature = "data")]
+use crate::validation::validate;
+use crate::validation::{Rule, ValidationError};
+
+#[derive(Debug, Error)]
+pub enum ExperimentError {
+    #[error("experiment files must end in .toml, .yaml or .yml: {0}")]
+    UnknownFormat(String),
+    #[error("invalid experiment:\n{}", .0.join("\n"))]
+    Invalid(Vec<String>),
+    #[error("trial {0} not found")]
+    UnknownTrial(String),
+    #[error("sink {0} not found")]
+    UnknownSink(String),
+    #[error("{0} is not a feather or parquet resource so it cannot be exported")]
+    NotTabular(String),
+    #[error("could not run model: {0}")]
+    Call(String),
+    #[error("export to {0} is not supported")]
+    UnsupportedExport(String),
+    #[error("schema version must be a positive integer but is {0}")]
+    InvalidVersion(String),
+    #[error("unsupported schema version {found}, this meillionen reads versions 1 to {supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Transform(#[from] TransformError),
+}
+
+/// A resource given to a model in an experiment file
+///
+/// The `type` key picks the kind of resource, the other keys are its fields.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResourceConfig {
+    File(FileResource),
+    Feather(FeatherResource),
+    Parquet(ParquetResource),
+    Netcdf(NetCDFResource),
+    MultiNetcdf(MultiNetCDFResource),
+}
+
+impl ResourceConfig {
+    /// The resource name model programs dispatch on
+    ///
+    /// These match the `name` class attribute of the python resource classes.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ResourceConfig::File(_) => "meillionen::FileResource",
+            ResourceConfig::Feather(_) => "meillionen::FeatherResource",
+            ResourceConfig::Parquet(_) => "meillionen::ParquetResource",
+            ResourceConfig::Netcdf(_) => "meillionen::NetCDFResource",
+            ResourceConfig::MultiNetcdf(_) => "meillionen::MultiNetCDFResource",
+        }
+    }
+
+    pub fn payload(&self) -> serde_json::Result<Vec<u8>> {
+        match self {
+            ResourceConfig::File(r) => serde_json::to_vec(r),
+            ResourceConfig::Feather(r) => serde_json::to_vec(r),
+            ResourceConfig::Parquet(r) => serde_json::to_vec(r),
+            ResourceConfig::Netcdf(r) => serde_json::to_vec(r),
+            ResourceConfig::MultiNetcdf(r) => serde_json::to_vec(r),
+        }
+    }
+
+    /// The files the resource is made of that exist
+    pub fn files(&self) -> Vec<PathBuf> {
+        let paths = match self {
+            ResourceConfig::MultiNetcdf(r) => r.paths().unwrap_or_default(),
+            r => vec![PathBuf::from(r.path())],
+        };
+        paths.into_iter().filter(|p| p.is_file()).collect()
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            ResourceConfig::File(r) => &r.path,
+            ResourceConfig::Feather(r) => &r.path,
+            ResourceConfig::Parquet(r) => &r.path,
+            ResourceConfig::Netcdf(r) => &r.path,
+            ResourceConfig::MultiNetcdf(r) => &r.pattern,
+        }
+    }
+}
+
+/// Fill in the `{key}` placeholders in every string of a serialized config
+///
+/// `lookup` gives `None` for keys that are not placeholders, which are left
+/// as they are, and `Some(None)` for placeholders without a value, the first
+/// of which is returned as the error.
+pub(crate) fn fill_placeholders(
+    value: &mut serde_json::Value,
+    lookup: &dyn Fn(&str) -> Option<Option<String>>,
+) -> Result<(), String> {
+    match value {
+        serde_json::Value::String(s) => {
+            let mut out = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find('{') {
+                out.push_str(&rest[..start]);
+                let after = &rest[start + 1..];
+                let filled = after
+                    .find('}')
+                    .and_then(|end| lookup(&after[..end]).map(|v| (&after[..end], v, end)));
+                match filled {
+                    Some((key, v, end)) => {
+                        out.push_str(&v.ok_or_else(|| key.to_string())?);
+                        rest = &after[end + 1..];
+                    }
+                    None => {
+                        out.push('{');
+                        rest = after;
+                    }
+                }
+            }
+            out.push_str(rest);
+            *s = out;
+        }
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                fill_placeholders(item, lookup)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for item in map.values_mut() {
+                fill_placeholders(item, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// One run of a model program
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrialConfig {
+    pub name: String,
+    /// The path to the model program, or its name if it is on the PATH
+    pub model: String,
+    #[serde(default)]
+    pub sources: BTreeMap<String, ResourceConfig>,
+    #[serde(default)]
+    pub sinks: BTreeMap<String, ResourceConfig>,
+    /// Extra arguments passed to the program after `run`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Extra environment variables for the program, such as `OMP_NUM_THREADS`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Rules checked on sources before the run and on sinks after it, keyed
+    /// by resource name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<String, Vec<Rule>>,
+    /// The output variables needed, every one when empty, see
+    /// [`crate::outputs`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
+    /// Names outputs are written under, on top of the experiment's, see
+    /// [`crate::aliases`]
+    #[serde(default, skip_serializing_if = "Aliases::is_empty")]
+    pub aliases: Aliases,
+    /// Changes to the values of sources before the model reads them, keyed
+    /// by source name, see [`crate::transforms`]
+    #[serde(default, skip_serializing_if = "Transforms::is_empty")]
+    pub transforms: Transforms,
+    /// Labels added to the experiment's, see [`crate::tags`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    /// How long the trial takes relative to the others, so `meillionen run
+    /// --jobs` can start the slowest first, see [`crate::scheduler`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl TrialConfig {
+    /// The request record batch the model program reads from stdin
+    #[cfg(feature = "data")]
+    pub fn request(&self) -> Result<RecordBatch, ExperimentError> {
+        let mut rb = ResourceBuilder::new(&self.model);
+        for (field, resources) in [("sink", &self.sinks), ("source", &self.sources)].iter() {
+            for (name, resource) in resources.iter() {
+                rb.add(field, name, resource.type_name(), &resource.payload()?)?;
+            }
+        }
+        Ok(rb.extract_to_recordbatch())
+    }
+
+    pub fn sink(&self, name: &str) -> Result<&ResourceConfig, ExperimentError> {
+        self.sinks
+            .get(name)
+            .ok_or_else(|| ExperimentError::UnknownSink(name.to_string()))
+    }
+
+    /// The rules the tabular resources break
+    ///
+    /// Only resources with rules are read.
+    #[cfg(feature = "data")]
+    pub fn check(
+        &self,
+        resources: &BTreeMap<String, ResourceConfig>,
+    ) -> Result<Vec<String>, ExperimentError> {
+        let mut problems = vec![];
+        for (name, resource) in resources.iter() {
+            let rules = match self.rules.get(name) {
+                Some(rules) if !rules.is_empty() => rules,
+                _ => continue,
+            };
+            for problem in validate(rules, &read_batches(resource)?)? {
+                problems.push(format!("{}: {}", name, problem));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Run the model program, returning its stderr if it fails
+    ///
+    /// The trial's `args` and `env` are passed to the program and a seed is
+    /// passed in `MEILLIONEN_SEED`, the outputs needed in `MEILLIONEN_OUTPUTS`
+    /// and their aliases in `MEILLIONEN_ALIASES`, and the transforms of its
+    /// sources in `MEILLIONEN_TRANSFORMS`. The run fails
+    /// without calling the program if its sources break their rules, and
+    /// fails afterwards if its sinks do.
+    #[cfg(feature = "data")]
+    pub fn run(&self, seed: Option<u64>) -> Result<Result<(), String>, ExperimentError> {
+        self.run_logged(seed).map(|(result, _)| result)
+    }
+
+    pub fn output_selection(&self) -> OutputSelection {
+        if self.outputs.is_empty() {
+            OutputSelection::all()
+        } else {
+            OutputSelection::only(
+                self.outputs
+                    .iter()
+                    .map(|name| self.aliases.internal(name).to_string()),
+            )
+        }
+    }
+
+    /// Run the model program like [`TrialConfig::run`], also returning what
+    /// it printed
+    #[cfg(feature = "data")]
+    pub fn run_logged(
+        &self,
+        seed: Option<u64>,
+    ) -> Result<(Result<(), String>, TrialLogs), ExperimentError> {
+        let problems = self.check(&self.sources)?;
+        if !problems.is_empty() {
+            let message = format!("invalid sources:\n{}", problems.join("\n"));
+            return Ok((Err(message), TrialLogs::default()));
+        }
+        let mut env = self.env.clone();
+        if let Some(seed) = seed {
+            env.insert(SEED_ENV.to_string(), seed.to_string());
+        }
+        if let Some(outputs) = self.output_selection().env_value() {
+            env.insert(OUTPUTS_ENV.to_string(), outputs);
+        }
+        if let Some(aliases) = self.aliases.env_value() {
+            env.insert(ALIASES_ENV.to_string(), aliases);
+        }
+        if let Some(transforms) = self.transforms.env_value()? {
+            env.insert(TRANSFORMS_ENV.to_string(), transforms);
+        }
+        if is_deterministic() {
+            env.insert(DETERMINISTIC_ENV.to_string(), "1".to_string());
+        }
+        tracing::debug!(trial = %self.name, model = %self.model, "running trial");
+        let output = client_call_cli_with_args(
+            &self.model,
+            &self.request()?,
+            TraceContext::from_env().as_ref(),
+            &env,
+            &self.args,
+        )
+        .map_err(|e| ExperimentError::Call(format!("{:#}", e)))?;
+        let logs = TrialLogs {
+            stdout: output_text(&output.stdout),
+            stderr: output_text(&output.stderr),
+        };
+        let result = if output.status.success() {
+            let problems = self.check(&self.sinks)?;
+            if problems.is_empty() {
+                Ok(())
+            } else {
+                Err(format!("invalid sinks:\n{}", problems.join("\n")))
+            }
+        } else {
+            Err(logs.stderr.clone())
+        };
+        Ok((result, logs))
+    }
+}
+
+/// What a model program printed during a trial
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct TrialLogs {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl TrialLogs {
+    /// Write the logs to `{trial}.stdout.log` and `{trial}.stderr.log` in
+    /// `dir`, returning the paths by stream
+    pub fn save(
+        &self,
+        dir: &Path,
+        trial: &str,
+    ) -> Result<BTreeMap<String, String>, ExperimentError> {
+        std::fs::create_dir_all(dir)?;
+        let mut paths = BTreeMap::new();
+        for (stream, text) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            let path = dir.join(format!("{}.{}.log", trial, stream));
+            std::fs::write(&path, text)?;
+            paths.insert(stream.to_string(), path.to_string_lossy().to_string());
+        }
+        Ok(paths)
+    }
+}
+
+/// Everything a worker needs to run one trial, so trials can be sent to a
+/// cluster as plain data
+///
+/// Relative resource paths are resolved against the working directory of the
+/// worker.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunSpec {
+    pub trial: TrialConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// The status of a run and the tables its tabular sinks hold
+#[cfg(feature = "data")]
+#[derive(Debug)]
+pub struct RunOutcome {
+    pub trial: String,
+    pub status: TrialStatus,
+    pub sinks: BTreeMap<String, Vec<RecordBatch>>,
+    pub logs: TrialLogs,
+    /// The value of each metric of [`RunSpec::run_with_metrics`], empty if
+    /// the trial failed
+    pub metrics: BTreeMap<String, f64>,
+}
+
+#[cfg(feature = "data")]
+impl RunSpec {
+    /// Run the trial, reading back its feather and parquet sinks when it
+    /// succeeds
+    pub fn run(&self) -> Result<RunOutcome, ExperimentError> {
+        self.run_with_metrics(&MetricRegistry::new())
+    }
+
+    /// Run the trial like [`RunSpec::run`] and evaluate `metrics` on its sinks
+    /// if it succeeds
+    pub fn run_with_metrics(
+        &self,
+        metrics: &MetricRegistry,
+    ) -> Result<RunOutcome, ExperimentError> {
+        let mut sinks = BTreeMap::new();
+        let (result, logs) = self.trial.run_logged(self.seed)?;
+        let status = match result {
+            Ok(()) => {
+                for (name, resource) in self.trial.sinks.iter() {
+                    if let ResourceConfig::Feather(_) | ResourceConfig::Parquet(_) = resource {
+                        sinks.insert(name.clone(), read_batches(resource)?);
+                    }
+                }
+                TrialStatus::Succeeded
+            }
+            Err(message) => TrialStatus::Failed { message },
+        };
+        let metrics = match status {
+            TrialStatus::Succeeded => metrics.evaluate(&sinks),
+            _ => BTreeMap::new(),
+        };
+        Ok(RunOutcome {
+            trial: self.trial.name.clone(),
+            status,
+            sinks,
+            logs,
+            metrics,
+        })
+    }
+}
+
+/// Environment variables the runner sets, which trials cannot override
+const RESERVED_ENV: [&str; 7] = [
+    SEED_ENV,
+    TRACEPARENT_ENV,
+    OTLP_FILE_ENV,
+    OUTPUTS_ENV,
+    ALIASES_ENV,
+    TRANSFORMS_ENV,
+    DETERMINISTIC_ENV,
+];
+
+/// An experiment file listing the trials to run
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExperimentConfig {
+    /// The schema version, see [`crate::schema`]
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub name: String,
+    /// Makes repeated runs reproducible when given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repro: Option<ReproConfig>,
+    /// Where model variables are kept, in memory when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<StoreConfig>,
+    /// Labels such as the project, given to every trial
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    /// Names the outputs of every trial are written under, see
+    /// [`crate::aliases`]
+    #[serde(default, skip_serializing_if = "Aliases::is_empty")]
+    pub aliases: Aliases,
+    pub trials: Vec<TrialConfig>,
+    /// Totals that must match between trials after a run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conservation: Vec<ConservationCheck>,
+}
+
+impl ExperimentConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ExperimentError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let value: serde_json::Value = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text)?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&text)?,
+            _ => return Err(ExperimentError::UnknownFormat(path.display().to_string())),
+        };
+        Ok(serde_json::from_value(migrate(
+            value,
+            EXPERIMENT_MIGRATIONS,
+        )?)?)
+    }
+
+    /// Problems that would make the experiment fail or overwrite its own results
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.trials.is_empty() {
+            problems.push("no trials given".to_string());
+        }
+        for key in self.tags.keys() {
+            if let Err(e) = tags::check_key(key) {
+                problems.push(e.to_string());
+            }
+        }
+        let mut names = BTreeSet::new();
+        let mut sink_paths: BTreeMap<&str, String> = BTreeMap::new();
+        for trial in self.trials.iter() {
+            if trial.name.is_empty() {
+                problems.push("trial names must not be empty".to_string());
+            }
+            if !names.insert(trial.name.as_str()) {
+                problems.push(format!("trial {} is given more than once", trial.name));
+            }
+            for name in trial.env.keys() {
+                if RESERVED_ENV.contains(&name.as_str()) {
+                    problems.push(format!(
+                        "trial {} sets {} which meillionen sets itself",
+                        trial.name, name
+                    ));
+                }
+            }
+            for key in trial.tags.keys() {
+                if let Err(e) = tags::check_key(key) {
+                    problems.push(format!("trial {}: {}", trial.name, e));
+                }
+            }
+            for problem in self.trial_aliases(trial).problems() {
+                problems.push(format!("trial {}: {}", trial.name, problem));
+            }
+            for problem in trial.transforms.problems(trial.sources.keys()) {
+                problems.push(format!("trial {}: {}", trial.name, problem));
+            }
+            if let Some(cost) = trial.cost.filter(|c| !(c.is_finite() && *c >= 0.0)) {
+                problems.push(format!(
+                    "trial {} has cost {}, which must be a number of at least 0",
+                    trial.name, cost
+                ));
+            }
+            for name in trial.rules.keys() {
+                if !trial.sources.contains_key(name) && !trial.sinks.contains_key(name) {
+                    problems.push(format!(
+                        "trial {} has rules for {} which is not one of its resources",
+                        trial.name, name
+                    ));
+                }
+            }
+            for (name, sink) in trial.sinks.iter() {
+                let owner = format!("{}.{}", trial.name, name);
+                if let Some(other) = sink_paths.insert(sink.path(), owner.clone()) {
+                    problems.push(format!(
+                        "sinks {} and {} both write to {}",
+                        other,
+                        owner,
+                        sink.path()
+                    ));
+                }
+            }
+        }
+        for check in self.conservation.iter() {
+            problems.extend(check.problems(self));
+        }
+        problems
+    }
+
+    pub fn validate(&self) -> Result<(), ExperimentError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ExperimentError::Invalid(problems))
+        }
+    }
+
+    /// A hash of what the experiment runs, see [`crate::diff::content_hash`]
+    ///
+    /// Names and tags of the experiment and the tags and cost hints of its
+    /// trials only label a run, so experiments differing in them alone hash
+    /// the same.
+    pub fn content_hash(&self) -> Result<String, ExperimentError> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(experiment) = value.as_object_mut() {
+            experiment.remove("name");
+            experiment.remove("tags");
+        }
+        for trial in value["trials"].as_array_mut().into_iter().flatten() {
+            if let Some(trial) = trial.as_object_mut() {
+                trial.remove("tags");
+                trial.remove("cost");
+            }
+        }
+        Ok(diff::content_hash(&value))
+    }
+
+    /// The trials in the order they are run, sorted by name in reproducible
+    /// runs and as written otherwise
+    pub fn ordered_trials(&self) -> Vec<&TrialConfig> {
+        let mut trials: Vec<&TrialConfig> = self.trials.iter().collect();
+        if self.repro.as_ref().is_some_and(|r| r.sort_trials) {
+            trials.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        trials
+    }
+
+    /// A run spec for each trial in run order, seeded as `meillionen run`
+    /// seeds them
+    pub fn run_specs(&self) -> Vec<RunSpec> {
+        self.ordered_trials()
+            .into_iter()
+            .map(|trial| RunSpec {
+                trial: TrialConfig {
+                    tags: self.trial_tags(trial),
+                    aliases: self.trial_aliases(trial),
+                    ..trial.clone()
+                },
+                seed: self.repro.as_ref().map(|r| r.trial_seed(&trial.name)),
+            })
+            .collect()
+    }
+
+    /// The experiment's tags with the trial's own on top
+    pub fn trial_tags(&self, trial: &TrialConfig) -> Tags {
+        tags::merge(&self.tags, &trial.tags)
+    }
+
+    /// The experiment's aliases with the trial's own on top
+    pub fn trial_aliases(&self, trial: &TrialConfig) -> Aliases {
+        self.aliases.merge(&trial.aliases)
+    }
+
+    pub fn trial(&self, name: &str) -> Result<&TrialConfig, ExperimentError> {
+        self.trials
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| ExperimentError::UnknownTrial(name.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TrialStatus {
+    Pending,
+    Succeeded,
+    Failed { message: String },
+}
+
+/// The outcome of each trial of an experiment, kept next to the experiment file
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ExperimentStatus {
+    pub trials: BTreeMap<String, TrialStatus>,
+}
+
+impl ExperimentStatus {
+    /// `baseline.toml` keeps its status in `baseline.status.json`
+    pub fn path_for(config: &Path) -> PathBuf {
+        config.with_extension("status.json")
+    }
+
+    /// Load the status of an experiment, treating trials never run as pending
+    pub fn load(config: &ExperimentConfig, path: &Path) -> Result<Self, ExperimentError> {
+        let mut status: Self = if path.exists() {
+            serde_json::from_reader(File::open(path)?)?
+        } else {
+            Self::default()
+        };
+        for trial in config.trials.iter() {
+            status
+                .trials
+                .entry(trial.name.clone())
+                .or_insert(TrialStatus::Pending);
+        }
+        Ok(status)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ExperimentError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Parquet,
+    Csv,
+    NetCDF,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ExperimentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parquet" => Ok(ExportFormat::Parquet),
+            "csv" => Ok(ExportFormat::Csv),
+            "netcdf" => Ok(ExportFormat::NetCDF),
+            _ => Err(ExperimentError::UnsupportedExport(s.to_string())),
+        }
+    }
+}
+
+#[cfg(feature = "data")]
+pub(crate) fn feather_batches<R: Read + Seek>(
+    reader: R,
+) -> Result<Vec<RecordBatch>, ExperimentError> {
+    let reader = FileReader::try_new(reader)?;
+    Ok(reader.collect::<arrow::error::Result<Vec<RecordBatch>>>()?)
+}
+
+#[cfg(feature = "data")]
+pub(crate) fn parquet_batches<R: ChunkReader + 'static>(
+    reader: R,
+) -> Result<Vec<RecordBatch>, ExperimentError> {
+    let file_reader = SerializedFileReader::new(reader)?;
+    let mut reader = ParquetFileArrowReader::new(Arc::new(file_reader));
+    let batches = reader.get_record_reader(8192)?;
+    Ok(batches.collect::<arrow::error::Result<Vec<RecordBatch>>>()?)
+}
+
+#[cfg(feature = "data")]
+pub(crate) fn read_batches(resource: &ResourceConfig) -> Result<Vec<RecordBatch>, ExperimentError> {
+    match resource {
+        ResourceConfig::Feather(r) => feather_batches(File::open(&r.path)?),
+        ResourceConfig::Parquet(r) => parquet_batches(File::open(&r.path)?),
+        r => Err(ExperimentError::NotTabular(r.path().to_string())),
+    }
+}
+
+/// Convert a tabular sink of a trial to another format
+#[cfg(feature = "data")]
+pub fn export(
+    resource: &ResourceConfig,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<(), ExperimentError> {
+    export_batches(&read_batches(resource)?, format, output)
+}
+
+/// Write record batches, such as a sink read from an archive, in a format
+#[cfg(feature = "data")]
+pub fn export_batches(
+    batches: &[RecordBatch],
+    format: ExportFormat,
+    output: &Path,
+) -> Result<(), ExperimentError> {
+    let schema = match batches.first() {
+        Some(b) => b.schema(),
+        None => return Ok(()),
+    };
+    let file = File::create(output)?;
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = arrow::csv::Writer::new(file);
+            for batch in batches.iter() {
+                writer.write(batch)?;
+            }
+        }
+        ExportFormat::Parquet => {
+            let mut writer = ArrowWriter::try_new(file, schema, None)?;
+            for batch in batches.iter() {
+                writer.write(batch)?;
+            }
+            writer.close()?;
+        }
+        ExportFormat::NetCDF => {
+            return Err(ExperimentError::UnsupportedExport("netcdf".to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::fs::File;
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::ipc::writer::FileWriter;
+    use arrow::record_batch::RecordBatch;
+
+    use crate::aliases::Aliases;
+    use crate::experiment::{
+        export, ExperimentConfig, ExperimentStatus, ExportFormat, RunSpec, TrialStatus,
+    };
+    use crate::outputs::OutputSelection;
+    use crate::transforms::{Transforms, ValueTransform};
+
+    const TOML: &str = r#"
+name = "irrigation"
+
+[[trials]]
+name = "baseline"
+model = "simplecrop_omf"
+
+[trials.sources.daily]
+type = "feather"
+path = "inputs/daily.feather"
+
+[trials.sinks.yearly]
+type = "parquet"
+path = "outputs/baseline/yearly.parquet"
+"#;
+
+    const YAML: &str = r#"
+name: irrigation
+trials:
+  - name: baseline
+    model: simplecrop_omf
+    sources:
+      daily: {type: feather, path: inputs/daily.feather}
+    sinks:
+      yearly: {type: parquet, path: outputs/baseline/yearly.parquet}
+"#;
+
+    #[test]
+    fn toml_and_yaml() {
+        let t: ExperimentConfig = toml::from_str(TOML).unwrap();
+        let y: ExperimentConfig = serde_yaml::from_str(YAML).unwrap();
+        assert_eq!(
+            serde_json::to_value(&t).unwrap(),
+            serde_json::to_value(&y).unwrap()
+        );
+        t.validate().unwrap();
+
+        // labels do not change what runs
+        let mut renamed = y.clone();
+        renamed.name = "irrigation-again".to_string();
+        renamed.trials[0].cost = Some(2.0);
+        assert_eq!(t.content_hash().unwrap(), renamed.content_hash().unwrap());
+        renamed.trials[0].model = "simplecrop".to_string();
+        assert_ne!(t.content_hash().unwrap(), renamed.content_hash().unwrap());
+
+        let rb = t.trial("baseline").unwrap().request().unwrap();
+        assert_eq!(rb.num_rows(), 2);
+        let resources = rb.column(2).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(resources.value(0), "meillionen::ParquetResource");
+        assert_eq!(resources.value(1), "meillionen::FeatherResource");
+
+        // specs survive a trip through JSON, as when sent to a worker
+        let specs = t.run_specs();
+        let spec: RunSpec =
+            serde_json::from_value(serde_json::to_value(&specs[0]).unwrap()).unwrap();
+        assert_eq!(spec.trial.name, "baseline");
+        assert_eq!(spec.seed, None);
+    }
+
+    #[test]
+    fn problems() {
+        let mut c: ExperimentConfig = toml::from_str(TOML).unwrap();
+        c.trials.push(c.trials[0].clone());
+        assert_eq!(
+            c.problems(),
+            vec![
+                "trial baseline is given more than once".to_string(),
+                "sinks baseline.yearly and baseline.yearly both write to outputs/baseline/yearly.parquet"
+                    .to_string()
+            ]
+        );
+        assert!(c.trial("drought").is_err());
+
+        let mut d: ExperimentConfig = toml::from_str(TOML).unwrap();
+        d.trials[0]
+            .env
+            .insert("OMP_NUM_THREADS".to_string(), "4".to_string());
+        d.trials[0]
+            .env
+            .insert("MEILLIONEN_SEED".to_string(), "1".to_string());
+        assert_eq!(
+            d.problems(),
+            vec!["trial baseline sets MEILLIONEN_SEED which meillionen sets itself".to_string()]
+        );
+
+        // aliases of the experiment reach each trial and its outputs
+        let mut e: ExperimentConfig = toml::from_str(TOML).unwrap();
+        e.aliases = Aliases::new(vec![("soil_water_deficit_stress", "swfac1")]);
+        e.trials[0].outputs = vec!["swfac1".to_string()];
+        let spec = &e.run_specs()[0];
+        assert_eq!(
+            spec.trial.output_selection(),
+            OutputSelection::only(vec!["soil_water_deficit_stress"])
+        );
+        assert_eq!(
+            spec.trial.aliases.env_value(),
+            Some("soil_water_deficit_stress=swfac1".to_string())
+        );
+        e.trials[0].aliases = Aliases::new(vec![("soil_water_excess_stress", "swfac1")]);
+        assert_eq!(
+            e.problems(),
+            vec![
+                "trial baseline: soil_water_deficit_stress and soil_water_excess_stress are both aliased to swfac1"
+                    .to_string()
+            ]
+        );
+
+        // transforms are declared per source
+        let mut f: ExperimentConfig = toml::from_str(&format!(
+            "{}\n[trials.transforms.daily]\nvariables = [\"rainfall\"]\nunits = {{ from = \"m\", to = \"mm\" }}\nmin = 0.0\n",
+            TOML
+        ))
+        .unwrap();
+        let daily = f.trials[0].transforms.get("daily").unwrap();
+        assert_eq!(daily.variables, vec!["rainfall"]);
+        assert_eq!(daily.resolve("daily").unwrap().apply(0.002), 2.0);
+        f.validate().unwrap();
+        f.trials[0].transforms = Transforms::new(vec![("weather", ValueTransform::scaled(100.0))]);
+        assert_eq!(
+            f.problems(),
+            vec![
+                "trial baseline: transform of weather which is not one of its sources".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn status_and_export() {
+        let dir =
+            std::env::temp_dir().join(format!("meillionen-experiment-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config_path = dir.join("irrigation.toml");
+        std::fs::write(&config_path, TOML).unwrap();
+        let config = ExperimentConfig::load(&config_path).unwrap();
+        let status_path = ExperimentStatus::path_for(&config_path);
+        assert_eq!(status_path, dir.join("irrigation.status.json"));
+        let mut status = ExperimentStatus::load(&config, &status_path).unwrap();
+        assert_eq!(status.trials["baseline"], TrialStatus::Pending);
+        status
+            .trials
+            .insert("baseline".to_string(), TrialStatus::Succeeded);
+        status.save(&status_path).unwrap();
+        assert_eq!(
+            ExperimentStatus::load(&config, &status_path).unwrap(),
+            status
+        );
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("year", DataType::Int32, false),
+            Field::new("yield", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1990, 1991])),
+            Arc::new(Float32Array::from(vec![1.5, 2.0])),
+        ];
+        let rb = RecordBatch::try_new(schema.clone(), columns).unwrap();
+        let feather = dir.join("yearly.feather");
+        let mut writer = FileWriter::try_new(File::create(&feather).unwrap(), &schema).unwrap();
+        writer.write(&rb).unwrap();
+        writer.finish().unwrap();
+
+        let resource: crate::experiment::ResourceConfig =
+            serde_json::from_value(serde_json::json!({
+                "type": "feather",
+                "path": feather.to_string_lossy()
+            }))
+            .unwrap();
+        let trial: crate::experiment::TrialConfig = serde_json::from_value(serde_json::json!({
+            "name": "baseline",
+            "model": "simplecrop_omf",
+            "sinks": {"yearly": resource},
+            "rules": {"yearly": [{"rule": "min", "column": "yield", "value": 1.8}]}
+        }))
+        .unwrap();
+        let problems = trial.check(&trial.sinks).unwrap();
+
+        let logs = crate::experiment::TrialLogs {
+            stdout: "converged after 12 iterations\n".to_string(),
+            stderr: String::new(),
+        };
+        let log_paths = logs
+            .save(
+                &crate::manifest::RunManifest::logs_dir_for(&config_path),
+                "baseline",
+            )
+            .unwrap();
+        let stdout_log = std::fs::read_to_string(&log_paths["stdout"]).unwrap();
+
+        let csv = dir.join("yearly.csv");
+        export(&resource, ExportFormat::Csv, &csv).unwrap();
+        let text = std::fs::read_to_string(&csv).unwrap();
+        export(
+            &resource,
+            ExportFormat::Parquet,
+            &dir.join("yearly.parquet"),
+        )
+        .unwrap();
+        let netcdf = export(&resource, "netcdf".parse().unwrap(), &dir.join("yearly.nc"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(text, "year,yield\n1990,1.5\n1991,2.0\n");
+        assert!(netcdf.is_err());
+        assert_eq!(stdout_log, logs.stdout);
+        assert!(log_paths["stderr"].ends_with("irrigation.logs/baseline.stderr.log"));
+        assert_eq!(
+            problems,
+            vec!["yearly: yield >= 1.8 fails at 1 rows, first at row 0".to_string()]
+        );
+    }
+}
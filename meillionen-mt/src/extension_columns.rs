@@ -1,11 +1,100 @@
+use arrow::array::{ArrayRef, BinaryArray, Float64Array, StructArray};
+use arrow::datatypes::{DataType, Field};
+use chrono::NaiveDateTime;
+use serde::de::DeserializeOwned;
+use serde::Serialize as SerializeTrait;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
 use std::sync::Arc;
+use thiserror::Error;
+
+use crate::variable::time::{time_units, TimeError};
+use crate::variable::Attributes;
+
+/// Field metadata keys of arrow extension types
+pub const EXTENSION_NAME_KEY: &str = "ARROW:extension:name";
+pub const EXTENSION_METADATA_KEY: &str = "ARROW:extension:metadata";
+
+/// The extension type of a float column with units, which the python package
+/// reads as a pint-pandas `PintArray`
+pub const UNITS_EXTENSION: &str = "meillionen.units";
+
+/// The extension type of a struct column of a value and its uncertainty
+pub const UNCERTAINTY_EXTENSION: &str = "meillionen.uncertainty";
+
+/// The GeoArrow extension type of a binary column of well-known binary
+/// geometries, which geopandas reads with `GeoSeries.from_wkb`
+pub const GEOMETRY_EXTENSION: &str = "geoarrow.wkb";
+
+/// Mark a field as an extension type with serialized metadata
+fn annotate<M: SerializeTrait>(mut field: Field, extension: &str, meta: &M) -> Field {
+    let mut metadata = field.metadata().clone().unwrap_or_default();
+    metadata.insert(EXTENSION_NAME_KEY.to_string(), extension.to_string());
+    metadata.insert(
+        EXTENSION_METADATA_KEY.to_string(),
+        serde_json::to_string(meta).expect("extension metadata to serialize"),
+    );
+    field.set_metadata(Some(metadata));
+    field
+}
+
+/// The metadata of a field marked as an extension type
+fn extension_meta<M: DeserializeOwned>(field: &Field, extension: &str) -> Option<M> {
+    let metadata: &BTreeMap<String, String> = field.metadata().as_ref()?;
+    if metadata.get(EXTENSION_NAME_KEY).map(String::as_str) != Some(extension) {
+        return None;
+    }
+    serde_json::from_str(metadata.get(EXTENSION_METADATA_KEY)?).ok()
+}
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct DimMeta {
     pub name: String,
     pub size: usize,
     pub description: Option<String>,
+    /// A name for each position along the dimension, such as cultivar or
+    /// site names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    /// The time of each position along the dimension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<Vec<NaiveDateTime>>,
+}
+
+impl DimMeta {
+    /// A dimension with one position per label
+    pub fn labeled(name: &str, labels: Vec<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            size: labels.len(),
+            description: None,
+            labels: Some(labels),
+            times: None,
+        }
+    }
+
+    /// A dimension with one position per time
+    pub fn timed(name: &str, times: Vec<NaiveDateTime>) -> Self {
+        Self {
+            name: name.to_string(),
+            size: times.len(),
+            description: None,
+            labels: None,
+            times: Some(times),
+        }
+    }
+
+    /// A time dimension from the values of a NetCDF time coordinate and its
+    /// `units` and `calendar` attributes
+    pub fn cf_timed(
+        name: &str,
+        values: &[f64],
+        attributes: &Attributes,
+    ) -> Result<Self, TimeError> {
+        let (units, calendar) = time_units(attributes)?;
+        Ok(Self::timed(name, units.decode_naive(values, calendar)?))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -23,6 +112,261 @@ impl TensorStackMeta {
     }
 }
 
+/// The units of a `meillionen.units` column, in a form pint can parse
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct UnitsMeta {
+    pub unit: String,
+}
+
+impl UnitsMeta {
+    pub fn new(unit: &str) -> Self {
+        Self {
+            unit: unit.to_string(),
+        }
+    }
+
+    /// Mark a float field as holding values in these units
+    ///
+    /// The values are stored unchanged, readers that do not know the
+    /// extension type see a plain float column.
+    pub fn annotate(&self, field: Field) -> Field {
+        annotate(field, UNITS_EXTENSION, self)
+    }
+
+    /// The units of a field marked with `annotate`
+    pub fn from_field(field: &Field) -> Option<Self> {
+        extension_meta(field, UNITS_EXTENSION)
+    }
+}
+
+/// How the uncertainty of a `meillionen.uncertainty` column is given
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UncertaintyMeta {
+    /// `value` and `sd` children, such as an observation and its measurement
+    /// error
+    StandardDeviation,
+    /// `min`, `mean` and `max` children, such as an ensemble summary
+    Range,
+}
+
+impl UncertaintyMeta {
+    /// The names of the children of the struct column
+    pub fn children(&self) -> &'static [&'static str] {
+        match self {
+            UncertaintyMeta::StandardDeviation => &["value", "sd"],
+            UncertaintyMeta::Range => &["min", "mean", "max"],
+        }
+    }
+
+    /// A column with one float child per part, in the order of `children`
+    ///
+    /// NaN parts, like an observation without a known error, are null.
+    pub fn column(
+        &self,
+        name: &str,
+        parts: Vec<Vec<f64>>,
+    ) -> arrow::error::Result<(Field, ArrayRef)> {
+        let children = self.children();
+        if parts.len() != children.len() || parts.iter().any(|p| p.len() != parts[0].len()) {
+            return Err(arrow::error::ArrowError::InvalidArgumentError(format!(
+                "{} needs {} parts of equal length",
+                name,
+                children.len()
+            )));
+        }
+        let columns: Vec<(Field, ArrayRef)> = children
+            .iter()
+            .zip(parts)
+            .map(|(child, values)| {
+                let values: Float64Array = values
+                    .into_iter()
+                    .map(|v| Some(v).filter(|v| !v.is_nan()))
+                    .collect();
+                (
+                    Field::new(child, DataType::Float64, true),
+                    Arc::new(values) as ArrayRef,
+                )
+            })
+            .collect();
+        let fields = columns.iter().map(|(f, _)| f.clone()).collect();
+        let field = Field::new(name, DataType::Struct(fields), false);
+        Ok((self.annotate(field), Arc::new(StructArray::from(columns))))
+    }
+
+    /// Mark a struct field as holding values and their uncertainty
+    pub fn annotate(&self, field: Field) -> Field {
+        annotate(field, UNCERTAINTY_EXTENSION, self)
+    }
+
+    /// The kind of uncertainty of a field made with `column`
+    pub fn from_field(field: &Field) -> Option<Self> {
+        extension_meta(field, UNCERTAINTY_EXTENSION)
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GeometryError {
+    #[error("geometry is truncated at byte {0}")]
+    Truncated(usize),
+    #[error("geometry type {0} is not supported")]
+    UnsupportedType(u32),
+    #[error("geometry column must be Binary but is {0:?}")]
+    NotBinary(DataType),
+}
+
+/// A site location or zone outline
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    Point {
+        x: f64,
+        y: f64,
+    },
+    /// The exterior ring followed by any holes, each closed by repeating its
+    /// first point
+    Polygon(Vec<Vec<(f64, f64)>>),
+}
+
+const WKB_POINT: u32 = 1;
+const WKB_POLYGON: u32 = 3;
+
+/// Reads the little or big endian numbers of a WKB geometry
+struct WkbReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    little_endian: bool,
+}
+
+impl<'a> WkbReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], GeometryError> {
+        let end = self.position + N;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(GeometryError::Truncated(self.position))?;
+        self.position = end;
+        Ok(bytes.try_into().expect("slice to have N bytes"))
+    }
+
+    fn u32(&mut self) -> Result<u32, GeometryError> {
+        let bytes = self.take::<4>()?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn f64(&mut self) -> Result<f64, GeometryError> {
+        let bytes = self.take::<8>()?;
+        Ok(if self.little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+
+    fn point(&mut self) -> Result<(f64, f64), GeometryError> {
+        Ok((self.f64()?, self.f64()?))
+    }
+}
+
+impl Geometry {
+    /// Encode as little endian well-known binary
+    pub fn to_wkb(&self) -> Vec<u8> {
+        let mut wkb = vec![1];
+        match self {
+            Geometry::Point { x, y } => {
+                wkb.extend_from_slice(&WKB_POINT.to_le_bytes());
+                wkb.extend_from_slice(&x.to_le_bytes());
+                wkb.extend_from_slice(&y.to_le_bytes());
+            }
+            Geometry::Polygon(rings) => {
+                wkb.extend_from_slice(&WKB_POLYGON.to_le_bytes());
+                wkb.extend_from_slice(&(rings.len() as u32).to_le_bytes());
+                for ring in rings.iter() {
+                    wkb.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+                    for (x, y) in ring.iter() {
+                        wkb.extend_from_slice(&x.to_le_bytes());
+                        wkb.extend_from_slice(&y.to_le_bytes());
+                    }
+                }
+            }
+        }
+        wkb
+    }
+
+    /// Decode a 2D point or polygon from well-known binary
+    pub fn from_wkb(bytes: &[u8]) -> Result<Self, GeometryError> {
+        let mut reader = WkbReader {
+            bytes,
+            position: 1,
+            little_endian: *bytes.first().ok_or(GeometryError::Truncated(0))? == 1,
+        };
+        match reader.u32()? {
+            WKB_POINT => {
+                let (x, y) = reader.point()?;
+                Ok(Geometry::Point { x, y })
+            }
+            WKB_POLYGON => {
+                let mut rings = vec![];
+                for _ in 0..reader.u32()? {
+                    let n = reader.u32()?;
+                    rings.push((0..n).map(|_| reader.point()).collect::<Result<_, _>>()?);
+                }
+                Ok(Geometry::Polygon(rings))
+            }
+            t => Err(GeometryError::UnsupportedType(t)),
+        }
+    }
+}
+
+/// The coordinate reference system of a `geoarrow.wkb` column
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct GeometryMeta {
+    /// Such as `EPSG:4326`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crs: Option<String>,
+}
+
+impl GeometryMeta {
+    pub fn new(crs: &str) -> Self {
+        Self {
+            crs: Some(crs.to_string()),
+        }
+    }
+
+    pub fn annotate(&self, field: Field) -> Field {
+        annotate(field, GEOMETRY_EXTENSION, self)
+    }
+
+    pub fn from_field(field: &Field) -> Option<Self> {
+        extension_meta(field, GEOMETRY_EXTENSION)
+    }
+
+    /// A column of geometries, with null for sites without one
+    pub fn column(&self, name: &str, geometries: &[Option<Geometry>]) -> (Field, ArrayRef) {
+        let wkb: Vec<Option<Vec<u8>>> = geometries
+            .iter()
+            .map(|g| g.as_ref().map(Geometry::to_wkb))
+            .collect();
+        let array = BinaryArray::from(wkb.iter().map(|g| g.as_deref()).collect::<Vec<_>>());
+        let field = Field::new(name, DataType::Binary, true);
+        (self.annotate(field), Arc::new(array))
+    }
+}
+
+/// Decode a binary column of well-known binary geometries
+pub fn geometries(array: &ArrayRef) -> Result<Vec<Option<Geometry>>, GeometryError> {
+    let wkb = array
+        .as_any()
+        .downcast_ref::<BinaryArray>()
+        .ok_or_else(|| GeometryError::NotBinary(array.data_type().clone()))?;
+    wkb.iter()
+        .map(|g| g.map(Geometry::from_wkb).transpose())
+        .collect()
+}
+
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum TableMeta {
     TensorStackMeta(Arc<TensorStackMeta>),
@@ -32,22 +376,69 @@ pub enum TableMeta {
 mod tests {
 
     use crate::extension_columns::DimMeta;
-    use crate::extension_columns::{TableMeta, TensorStackMeta};
+    use crate::extension_columns::{
+        geometries, Geometry, GeometryError, GeometryMeta, TableMeta, TensorStackMeta, UnitsMeta,
+    };
+    use arrow::datatypes::{DataType, Field};
     use std::sync::Arc;
 
+    #[test]
+    fn geometry_column() {
+        let zone = Geometry::Polygon(vec![vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)]]);
+        let site = Geometry::Point { x: -111.9, y: 33.4 };
+        let (field, column) = GeometryMeta::new("EPSG:4326")
+            .column("geometry", &[Some(site.clone()), None, Some(zone.clone())]);
+        assert_eq!(
+            GeometryMeta::from_field(&field).unwrap().crs.as_deref(),
+            Some("EPSG:4326")
+        );
+        assert_eq!(
+            geometries(&column).unwrap(),
+            vec![Some(site), None, Some(zone)]
+        );
+        // POINT (1 2) written big endian
+        let wkb = [
+            0, 0, 0, 0, 1, 63, 240, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(
+            Geometry::from_wkb(&wkb),
+            Ok(Geometry::Point { x: 1.0, y: 2.0 })
+        );
+        assert_eq!(
+            Geometry::from_wkb(&wkb[..12]),
+            Err(GeometryError::Truncated(5))
+        );
+    }
+
+    #[test]
+    fn units_field() {
+        let field = Field::new("soil_water_storage", DataType::Float64, true);
+        assert_eq!(UnitsMeta::from_field(&field), None);
+        let field = UnitsMeta::new("mm").annotate(field);
+        assert_eq!(UnitsMeta::from_field(&field), Some(UnitsMeta::new("mm")));
+        assert_eq!(
+            field.metadata().as_ref().unwrap()["ARROW:extension:metadata"],
+            r#"{"unit":"mm"}"#
+        );
+    }
+
+    #[test]
     fn array_meta() {
         let am = TensorStackMeta::new(vec![]);
-        assert_eq!(serde_json::to_string(&am).unwrap(), "[]");
+        assert_eq!(serde_json::to_string(&am).unwrap(), r#"{"dimensions":[]}"#);
 
         let am = TensorStackMeta::new(vec![Arc::new(DimMeta {
             name: 'x'.to_string(),
             size: 10,
             description: None,
+            labels: None,
+            times: None,
         })]);
-        let am_s = r#"[{"name":"x","size":10}]"#;
+        let am_s = r#"{"dimensions":[{"name":"x","size":10,"description":null}]}"#;
         assert_eq!(serde_json::to_string(&am).unwrap(), am_s);
 
-        let tm_s = r#"{"TableStackMeta":[{"name":"x","size":10}]}"#;
+        let tm_s =
+            r#"{"TensorStackMeta":{"dimensions":[{"name":"x","size":10,"description":null}]}}"#;
         let tm = TableMeta::TensorStackMeta(Arc::new(am));
         assert_eq!(serde_json::to_string(&tm).unwrap(), tm_s);
         assert_eq!(serde_json::from_str::<TableMeta>(tm_s).unwrap(), tm);
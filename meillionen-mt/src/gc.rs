@@ -0,0 +1,332 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::experiment::TrialStatus;
+use crate::manifest::RunManifest;
+use crate::tags::TagFilter;
+
+#[derive(Debug, Error)]
+pub enum GcError {
+    #[error("status must be succeeded or failed but is {0}")]
+    UnknownStatus(String),
+    #[error("{0} is not a size, use a number of bytes with an optional K, M or G suffix")]
+    InvalidSize(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Which finished trials may be pruned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GcStatus {
+    Succeeded,
+    Failed,
+}
+
+impl GcStatus {
+    fn matches(&self, status: &TrialStatus) -> bool {
+        matches!(
+            (self, status),
+            (GcStatus::Succeeded, TrialStatus::Succeeded)
+                | (GcStatus::Failed, TrialStatus::Failed { .. })
+        )
+    }
+}
+
+impl FromStr for GcStatus {
+    type Err = GcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "succeeded" => Ok(GcStatus::Succeeded),
+            "failed" => Ok(GcStatus::Failed),
+            _ => Err(GcError::UnknownStatus(s.to_string())),
+        }
+    }
+}
+
+/// A size like `500M` in bytes
+pub fn parse_size(s: &str) -> Result<u64, GcError> {
+    let invalid = || GcError::InvalidSize(s.to_string());
+    let (digits, scale) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    let n: u64 = digits.trim().parse().map_err(|_| invalid())?;
+    n.checked_mul(scale).ok_or_else(invalid)
+}
+
+/// Which trial outputs to delete
+///
+/// Trials must match every filter given. With a budget the oldest matching
+/// trials are pruned only until the outputs of every trial fit in it,
+/// otherwise every matching trial is pruned. A policy without filters prunes
+/// nothing unless `all` is set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcPolicy {
+    /// Seconds since the trial finished
+    pub older_than: Option<u64>,
+    pub status: Option<GcStatus>,
+    /// Bytes the outputs of all trials may use
+    pub max_bytes: Option<u64>,
+    pub tags: Vec<TagFilter>,
+    /// Prune every trial when no filter is given
+    pub all: bool,
+}
+
+impl GcPolicy {
+    /// Whether any filter or budget limits the trials pruned
+    pub fn has_filters(&self) -> bool {
+        self.older_than.is_some()
+            || self.status.is_some()
+            || self.max_bytes.is_some()
+            || !self.tags.is_empty()
+    }
+}
+
+/// The trials and files a policy would prune
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcPlan {
+    pub trials: Vec<String>,
+    /// The outputs and logs of the trials
+    pub files: Vec<PathBuf>,
+    /// The directories under the base holding the files, deepest first,
+    /// which are removed if deleting the files leaves them empty
+    pub dirs: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+fn file_size(path: &Path) -> std::io::Result<u64> {
+    match std::fs::metadata(path) {
+        Ok(m) => Ok(m.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Find the outputs and logs to prune
+///
+/// Relative output paths are resolved against `base` as in
+/// `RunManifest::verify`. `now` is a unix time.
+pub fn plan(
+    manifest: &RunManifest,
+    base: &Path,
+    policy: &GcPolicy,
+    now: u64,
+) -> Result<GcPlan, GcError> {
+    if !(policy.has_filters() || policy.all) {
+        return Ok(GcPlan::default());
+    }
+    let mut used = 0;
+    let mut candidates = vec![];
+    for (name, trial) in manifest.trials.iter() {
+        if trial.pruned.is_some() {
+            continue;
+        }
+        let files: Vec<PathBuf> = trial
+            .outputs
+            .keys()
+            .chain(trial.logs.values())
+            .map(|p| base.join(p))
+            .collect();
+        let mut bytes = 0;
+        for f in files.iter() {
+            bytes += file_size(f)?;
+        }
+        used += bytes;
+        let old_enough = policy
+            .older_than
+            .is_none_or(|age| now.saturating_sub(trial.finished) >= age);
+        let status_matches = policy.status.is_none_or(|s| s.matches(&trial.status));
+        let tags_match = TagFilter::all(&policy.tags, &trial.tags);
+        if old_enough && status_matches && tags_match {
+            candidates.push((trial.finished, name.clone(), files, bytes));
+        }
+    }
+    candidates.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+    let mut plan = GcPlan::default();
+    for (_, name, files, bytes) in candidates {
+        if let Some(budget) = policy.max_bytes {
+            if used <= budget {
+                break;
+            }
+        }
+        used -= bytes;
+        plan.trials.push(name);
+        plan.files.extend(files);
+        plan.bytes += bytes;
+    }
+    let mut dirs: Vec<PathBuf> = plan
+        .files
+        .iter()
+        .flat_map(|f| f.ancestors().skip(1))
+        .filter(|d| d.starts_with(base) && *d != base)
+        .map(Path::to_path_buf)
+        .collect();
+    dirs.sort_by(|a, b| (b.components().count(), b).cmp(&(a.components().count(), a)));
+    dirs.dedup();
+    plan.dirs = dirs;
+    Ok(plan)
+}
+
+fn is_empty_dir(path: &Path) -> std::io::Result<bool> {
+    match std::fs::read_dir(path) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete the planned files and the directories they leave empty, and mark
+/// their trials as pruned at `now`
+///
+/// The manifest keeps the checksums of the deleted outputs so the run can
+/// still be audited, save it afterwards.
+pub fn collect(manifest: &mut RunManifest, plan: &GcPlan, now: u64) -> Result<(), GcError> {
+    for f in plan.files.iter() {
+        match std::fs::remove_file(f) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    for d in plan.dirs.iter() {
+        if is_empty_dir(d)? {
+            std::fs::remove_dir(d)?;
+        }
+    }
+    for name in plan.trials.iter() {
+        if let Some(trial) = manifest.trials.get_mut(name) {
+            trial.pruned = Some(now);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::experiment::TrialStatus;
+    use crate::gc::{collect, parse_size, plan, GcPlan, GcPolicy, GcStatus};
+    use crate::manifest::{RunManifest, TrialManifest};
+
+    fn trial(finished: u64, status: TrialStatus, output: &str) -> TrialManifest {
+        let mut outputs = BTreeMap::new();
+        outputs.insert(output.to_string(), "00".to_string());
+        TrialManifest {
+            model: "simplecrop_omf".to_string(),
+            model_sha256: None,
+            seed: None,
+            args: vec![],
+            env: Default::default(),
+            started: finished,
+            finished,
+            status,
+            inputs: Default::default(),
+            outputs,
+            pruned: None,
+            logs: Default::default(),
+            tags: Default::default(),
+            transforms: Default::default(),
+        }
+    }
+
+    #[test]
+    fn prune_by_age_status_and_budget() {
+        let dir = std::env::temp_dir().join(format!("meillionen-gc-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("runs/1")).unwrap();
+        for (name, size) in [
+            ("runs/1/a.parquet", 100),
+            ("runs/1/a.stderr.log", 0),
+            ("b.parquet", 200),
+            ("c.parquet", 300),
+        ] {
+            std::fs::write(dir.join(name), vec![0u8; size]).unwrap();
+        }
+        let failed = TrialStatus::Failed {
+            message: "exit 1".to_string(),
+        };
+        let mut manifest = RunManifest {
+            version: 1,
+            experiment: "irrigation".to_string(),
+            created: 0,
+            repro: None,
+            tags: Default::default(),
+            trials: BTreeMap::new(),
+        };
+        let mut a = trial(10, TrialStatus::Succeeded, "runs/1/a.parquet");
+        a.logs
+            .insert("stderr".to_string(), "runs/1/a.stderr.log".to_string());
+        manifest.trials.insert("a".to_string(), a);
+        manifest
+            .trials
+            .insert("b".to_string(), trial(20, failed, "b.parquet"));
+        manifest.trials.insert(
+            "c".to_string(),
+            trial(30, TrialStatus::Succeeded, "c.parquet"),
+        );
+
+        let failed_only = GcPolicy {
+            status: Some(GcStatus::Failed),
+            ..GcPolicy::default()
+        };
+        assert_eq!(
+            plan(&manifest, &dir, &failed_only, 100).unwrap().trials,
+            vec!["b"]
+        );
+        let old = GcPolicy {
+            older_than: Some(75),
+            ..GcPolicy::default()
+        };
+        assert_eq!(
+            plan(&manifest, &dir, &old, 100).unwrap().trials,
+            vec!["a", "b"]
+        );
+        manifest
+            .trials
+            .get_mut("c")
+            .unwrap()
+            .tags
+            .insert("project".to_string(), "maize".to_string());
+        let tagged = GcPolicy {
+            tags: vec!["project=maize".parse().unwrap()],
+            ..GcPolicy::default()
+        };
+        assert_eq!(
+            plan(&manifest, &dir, &tagged, 100).unwrap().trials,
+            vec!["c"]
+        );
+
+        let budget = GcPolicy {
+            max_bytes: Some(parse_size("400").unwrap()),
+            ..GcPolicy::default()
+        };
+        let p = plan(&manifest, &dir, &budget, 100).unwrap();
+        assert_eq!(p.trials, vec!["a", "b"]);
+        assert_eq!(p.bytes, 300);
+        assert_eq!(p.dirs, vec![dir.join("runs/1"), dir.join("runs")]);
+        collect(&mut manifest, &p, 100).unwrap();
+        let runs_removed = !dir.join("runs").exists();
+        let nothing = plan(&manifest, &dir, &GcPolicy::default(), 100).unwrap();
+        let everything = GcPolicy {
+            all: true,
+            ..GcPolicy::default()
+        };
+        let left = plan(&manifest, &dir, &everything, 100).unwrap();
+        let verify = manifest.verify(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(nothing, GcPlan::default());
+        assert!(runs_removed);
+        assert_eq!(manifest.trials["a"].pruned, Some(100));
+        assert_eq!(left.trials, vec!["c"]);
+        // c's checksum is wrong but pruned outputs are not reported
+        assert_eq!(verify.len(), 1);
+        assert_eq!(parse_size("2K").unwrap(), 2048);
+        assert!(parse_size("lots").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Running a model once for each cell of a grid
+//!
+//! [`run_cells`] calls a model for every cell and a [`CellPolicy`] decides
+//! what a failed cell does to the rest of the run. Unless the policy is to
+//! fail fast the cells that succeeded are kept, with a mask of the cells that
+//! failed to write next to the gridded results so they can still be used.
+//! [`run_valid_cells`] skips the cells an input marks missing, such as ocean
+//! or cells outside a catchment, whose results are NaN.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::extension_columns::DimMeta;
+use crate::variable::mask::valid_cells;
+use crate::variable::{Attributes, Float, Indices, Variable, VariableError, VecVariable};
+
+/// The name the failed cell mask is usually written under
+pub const FAILED_MASK: &str = "failed_cells";
+
+#[derive(Debug, Error)]
+pub enum GridError {
+    #[error("cell {cell} failed: {message}")]
+    CellFailed { cell: usize, message: String },
+    #[error("cell policy must be fail-fast, skip or retry:N but is {0}")]
+    UnknownPolicy(String),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+}
+
+/// What a failed cell does to the rest of a grid run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellPolicy {
+    /// Stop at the first failed cell
+    #[default]
+    FailFast,
+    /// Record the failure and go on to the next cell
+    Skip,
+    /// Run a failed cell again up to this many times in all, then skip it
+    Retry(usize),
+}
+
+impl FromStr for CellPolicy {
+    type Err = GridError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail-fast" => Ok(CellPolicy::FailFast),
+            "skip" => Ok(CellPolicy::Skip),
+            _ => s
+                .strip_prefix("retry:")
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .map(CellPolicy::Retry)
+                .ok_or_else(|| GridError::UnknownPolicy(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for CellPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellPolicy::FailFast => f.write_str("fail-fast"),
+            CellPolicy::Skip => f.write_str("skip"),
+            CellPolicy::Retry(attempts) => write!(f, "retry:{}", attempts),
+        }
+    }
+}
+
+/// A cell that failed every time it was run
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellFailure {
+    /// The position of the cell in the order the cells were run
+    pub cell: usize,
+    pub attempts: usize,
+    /// Why the last attempt failed
+    pub message: String,
+}
+
+/// The results of the cells of a grid, `None` for cells that failed or were
+/// masked
+#[derive(Clone, Debug)]
+pub struct GridRun<T> {
+    pub cells: Vec<Option<T>>,
+    pub failures: Vec<CellFailure>,
+    /// The cells that were not run because their inputs are missing
+    pub masked: Vec<usize>,
+}
+
+impl<T> GridRun<T> {
+    pub fn succeeded(&self) -> usize {
+        self.cells.iter().filter(|c| c.is_some()).count()
+    }
+
+    /// 1 for each cell that failed and 0 for the others, laid out over the
+    /// grid's dimensions in the order the cells were run
+    pub fn failed_mask(
+        &self,
+        dimensions: Vec<Arc<DimMeta>>,
+    ) -> Result<VecVariable<f64>, VariableError> {
+        let mut data = vec![0.0; self.cells.len()];
+        for failure in self.failures.iter() {
+            data[failure.cell] = 1.0;
+        }
+        let mut attributes = Attributes::default().with_long_name("cells whose run failed");
+        attributes
+            .other
+            .insert("flag_values".to_string(), json!([0, 1]));
+        attributes
+            .other
+            .insert("flag_meanings".to_string(), json!("succeeded failed"));
+        Ok(VecVariable::new(dimensions, data)?.with_attributes(attributes))
+    }
+
+    /// A value of each cell laid out over the grid's dimensions, NaN for
+    /// cells that failed or were masked
+    pub fn values<F>(
+        &self,
+        dimensions: Vec<Arc<DimMeta>>,
+        value: F,
+    ) -> Result<VecVariable<f64>, VariableError>
+    where
+        F: Fn(&T) -> f64,
+    {
+        let data = self
+            .cells
+            .iter()
+            .map(|c| c.as_ref().map_or(f64::NAN, &value))
+            .collect();
+        VecVariable::new(dimensions, data)
+    }
+}
+
+/// Run `run` for each of `cells` cells in turn, handling failures by `policy`
+///
+/// Only a fail fast policy returns an error for a failed cell.
+pub fn run_cells<T, E, F>(cells: usize, policy: CellPolicy, run: F) -> Result<GridRun<T>, GridError>
+where
+    E: fmt::Display,
+    F: FnMut(usize) -> Result<T, E>,
+{
+    run_unmasked(&vec![true; cells], policy, run)
+}
+
+/// [`run_cells`] for the cells of `input` that have a value along `along`
+///
+/// Cells are the indices into every other dimension of `input` in
+/// row-major order, as in [`valid_cells`]. Read `input` masked by its
+/// attributes so fill values count as missing. Cells where every value is
+/// missing are not run and are listed in [`GridRun::masked`].
+pub fn run_valid_cells<V, T, E, F>(
+    input: &V,
+    along: &str,
+    policy: CellPolicy,
+    run: F,
+) -> Result<GridRun<T>, GridError>
+where
+    V: Variable,
+    V::Elem: Float,
+    E: fmt::Display,
+    F: FnMut(usize) -> Result<T, E>,
+{
+    let valid = valid_cells(input, along)?;
+    let mut cell_shape = input.shape();
+    if let Some(position) = input.dimension_position(along) {
+        cell_shape.remove(position);
+    }
+    let mut valid = valid.into_iter().peekable();
+    let unmasked: Vec<bool> = Indices::new(cell_shape)
+        .map(|cell| valid.next_if_eq(&cell).is_some())
+        .collect();
+    run_unmasked(&unmasked, policy, run)
+}
+
+fn run_unmasked<T, E, F>(
+    unmasked: &[bool],
+    policy: CellPolicy,
+    mut run: F,
+) -> Result<GridRun<T>, GridError>
+where
+    E: fmt::Display,
+    F: FnMut(usize) -> Result<T, E>,
+{
+    let attempts = match policy {
+        CellPolicy::Retry(attempts) => attempts.max(1),
+        _ => 1,
+    };
+    let mut grid = GridRun {
+        cells: Vec::with_capacity(unmasked.len()),
+        failures: vec![],
+        masked: vec![],
+    };
+    for (cell, unmasked) in unmasked.iter().enumerate() {
+        if !unmasked {
+            grid.cells.push(None);
+            grid.masked.push(cell);
+            continue;
+        }
+        let mut result = run(cell);
+        let mut attempt = 1;
+        while attempt < attempts && result.is_err() {
+            attempt += 1;
+            result = run(cell);
+        }
+        match result {
+            Ok(value) => grid.cells.push(Some(value)),
+            Err(e) => {
+                let message = e.to_string();
+                if policy == CellPolicy::FailFast {
+                    return Err(GridError::CellFailed { cell, message });
+                }
+                tracing::warn!(cell, attempts = attempt, "cell failed: {}", message);
+                grid.cells.push(None);
+                grid.failures.push(CellFailure {
+                    cell,
+                    attempts: attempt,
+                    message,
+                });
+            }
+        }
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::{run_cells, run_valid_cells, CellFailure, CellPolicy, GridError};
+    use crate::variable::{dim, MissingValues, Variable, VecVariable};
+
+    fn flaky(cell: usize, calls: &mut Vec<usize>) -> Result<f64, String> {
+        calls.push(cell);
+        let tries = calls.iter().filter(|c| **c == cell).count();
+        match cell {
+            // always fails
+            1 => Err("rainfall is negative".to_string()),
+            // fails once
+            2 if tries == 1 => Err("model crashed".to_string()),
+            _ => Ok(cell as f64 * 10.0),
+        }
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(
+            "retry:3".parse::<CellPolicy>().unwrap(),
+            CellPolicy::Retry(3)
+        );
+        assert!("retry:0".parse::<CellPolicy>().is_err());
+        assert_eq!(
+            CellPolicy::Skip.to_string().parse::<CellPolicy>().unwrap(),
+            CellPolicy::Skip
+        );
+
+        let mut calls = vec![];
+        let e = run_cells(4, CellPolicy::FailFast, |c| flaky(c, &mut calls)).unwrap_err();
+        assert!(matches!(e, GridError::CellFailed { cell: 1, .. }));
+        assert_eq!(calls, vec![0, 1]);
+
+        let mut calls = vec![];
+        let skipped = run_cells(4, CellPolicy::Skip, |c| flaky(c, &mut calls)).unwrap();
+        assert_eq!(skipped.succeeded(), 2);
+        assert_eq!(skipped.failures.len(), 2);
+
+        let mut calls = vec![];
+        let retried = run_cells(4, CellPolicy::Retry(3), |c| flaky(c, &mut calls)).unwrap();
+        assert_eq!(calls, vec![0, 1, 1, 1, 2, 2, 3]);
+        assert_eq!(
+            retried.failures,
+            vec![CellFailure {
+                cell: 1,
+                attempts: 3,
+                message: "rainfall is negative".to_string()
+            }]
+        );
+
+        let dims = vec![dim("x", 2), dim("y", 2)];
+        let mask = retried.failed_mask(dims.clone()).unwrap();
+        assert_eq!(mask.data(), &[0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(
+            mask.attributes().unwrap().other["flag_meanings"],
+            "succeeded failed"
+        );
+        let values = retried.values(dims, |v| *v).unwrap();
+        assert_eq!(values.get(&[0, 0]), 0.0);
+        assert!(values.get(&[0, 1]).is_nan());
+        assert_eq!(values.get(&[1, 1]), 30.0);
+        assert!(retried.failed_mask(vec![dim("x", 3)]).is_err());
+    }
+
+    #[test]
+    fn masked_cells_are_skipped() {
+        // cell 1 is ocean and cell 3 only has a missing first day
+        let rain = VecVariable::new(
+            vec![dim("x", 4), dim("t", 2)],
+            vec![1.0, 2.0, -9999.0, -9999.0, 0.0, 1.0, f64::NAN, 4.0],
+        )
+        .unwrap()
+        .masked(MissingValues {
+            fill_value: Some(-9999.0),
+            missing_value: None,
+        });
+        let mut calls = vec![];
+        let grid = run_valid_cells(&rain, "t", CellPolicy::Skip, |cell| {
+            calls.push(cell);
+            if cell == 2 {
+                Err("model crashed")
+            } else {
+                Ok(cell as f64)
+            }
+        })
+        .unwrap();
+        assert_eq!(calls, vec![0, 2, 3]);
+        assert_eq!(grid.masked, vec![1]);
+        assert_eq!(grid.failures.len(), 1);
+        let dims = vec![dim("x", 4)];
+        assert_eq!(
+            grid.failed_mask(dims.clone()).unwrap().data(),
+            &[0.0, 0.0, 1.0, 0.0]
+        );
+        let values = grid.values(dims, |v| *v).unwrap();
+        assert_eq!(values.get(&[0]), 0.0);
+        assert!(values.get(&[1]).is_nan());
+        assert_eq!(values.get(&[3]), 3.0);
+        assert!(matches!(
+            run_valid_cells(&rain, "day", CellPolicy::Skip, Ok::<_, String>),
+            Err(GridError::Variable(_))
+        ));
+    }
+}
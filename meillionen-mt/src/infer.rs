@@ -0,0 +1,452 @@
+//! Proposing how to parse the text output of a model
+//!
+//! Legacy models write tables as fixed-width or delimited text under a few
+//! lines of headings. [`infer`] looks at a sample file and guesses the header
+//! rows, the column boundaries, names and types. The spec it proposes can be
+//! saved as toml, corrected by hand and used to parse every later output.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, Int32Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InferError {
+    #[error("no rows of values found")]
+    NoData,
+    #[error("line {line}: {value:?} in column {column} is not {data_type:?}")]
+    Value {
+        line: usize,
+        column: String,
+        value: String,
+        data_type: ColumnType,
+    },
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Int32,
+    Float64,
+    Utf8,
+}
+
+impl ColumnType {
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            ColumnType::Int32 => value.parse::<i32>().is_ok(),
+            ColumnType::Float64 => value.parse::<f64>().is_ok(),
+            ColumnType::Utf8 => true,
+        }
+    }
+
+    /// The narrowest type holding every value
+    fn of<'a, I: IntoIterator<Item = &'a str>>(values: I) -> Self {
+        values.into_iter().fold(ColumnType::Int32, |t, v| {
+            [t, ColumnType::Float64, ColumnType::Utf8]
+                .iter()
+                .copied()
+                .find(|t| t.accepts(v))
+                .unwrap_or(ColumnType::Utf8)
+        })
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            ColumnType::Int32 => DataType::Int32,
+            ColumnType::Float64 => DataType::Float64,
+            ColumnType::Utf8 => DataType::Utf8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextFormat {
+    /// Columns at fixed character positions
+    FixedWidth,
+    /// Columns separated by a character
+    Delimited { delimiter: char },
+}
+
+/// A column of a table, with its character positions if fixed width
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub data_type: ColumnType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<usize>,
+    /// The position after the column, the end of the line when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+}
+
+/// How to read the table in a text output
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ParsingSpec {
+    /// Lines before the first row of values
+    pub header_rows: usize,
+    pub format: TextFormat,
+    pub columns: Vec<ColumnSpec>,
+}
+
+const DELIMITERS: [char; 3] = [',', '\t', ';'];
+
+fn split(format: &TextFormat, line: &str) -> Vec<String> {
+    match format {
+        TextFormat::FixedWidth => line.split_whitespace().map(str::to_string).collect(),
+        TextFormat::Delimited { delimiter } => line
+            .split(*delimiter)
+            .map(|v| v.trim().to_string())
+            .collect(),
+    }
+}
+
+/// A line of mostly numbers, not counting blank fields
+fn is_values(fields: &[String]) -> bool {
+    let filled = fields.iter().filter(|f| !f.is_empty());
+    let numbers = filled.clone().filter(|f| f.parse::<f64>().is_ok()).count();
+    numbers > 0 && numbers * 2 >= filled.count()
+}
+
+/// The first line of the block of value rows ending the text, which all have
+/// the same number of fields
+fn first_value_row(lines: &[&str], format: &TextFormat) -> Option<usize> {
+    let mut first = None;
+    let mut width = None;
+    for (i, line) in lines.iter().enumerate().rev() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = split(format, line);
+        if !is_values(&fields) || width.is_some_and(|w| w != fields.len()) {
+            break;
+        }
+        if let TextFormat::Delimited { .. } = format {
+            if fields.len() < 2 {
+                break;
+            }
+        }
+        width = Some(fields.len());
+        first = Some(i);
+    }
+    first
+}
+
+/// Lower case words joined by underscores
+fn sanitize(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// The runs of character positions that are not blank on any line
+fn occupied_runs(lines: &[&str]) -> Vec<(usize, usize)> {
+    let mut occupied: Vec<bool> = vec![];
+    for line in lines {
+        for (i, c) in line.chars().enumerate() {
+            if i >= occupied.len() {
+                occupied.resize(i + 1, false);
+            }
+            occupied[i] |= !c.is_whitespace();
+        }
+    }
+    let mut runs = vec![];
+    let mut start = None;
+    for (i, o) in occupied.iter().chain(std::iter::once(&false)).enumerate() {
+        match (start, o) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                runs.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    runs
+}
+
+fn slice(line: &str, start: usize, end: Option<usize>) -> String {
+    let chars = line.chars().skip(start);
+    match end {
+        Some(end) => chars.take(end.saturating_sub(start)).collect(),
+        None => chars.collect(),
+    }
+}
+
+/// Names from the words of the header lines above each column
+///
+/// Headings often stack a name over several lines. Lines with a word
+/// crossing a column boundary, like a title, and rulers of dashes are left
+/// out.
+fn fixed_width_names(header: &[&str], columns: &[ColumnSpec]) -> Vec<String> {
+    let starts: Vec<usize> = columns.iter().filter_map(|c| c.start).skip(1).collect();
+    let fits = |line: &str| {
+        let mut pos = 0;
+        !line.chars().all(|c| "-=_ ".contains(c))
+            && line.split(' ').all(|word| {
+                let (from, to) = (pos, pos + word.chars().count());
+                pos = to + 1;
+                !starts.iter().any(|s| from < *s && *s < to)
+            })
+    };
+    let lines: Vec<&str> = header.iter().copied().filter(|l| fits(l)).collect();
+    columns
+        .iter()
+        .map(|c| {
+            let words: Vec<String> = lines
+                .iter()
+                .map(|l| slice(l, c.start.unwrap_or(0), c.end).trim().to_string())
+                .filter(|w| !w.is_empty())
+                .collect();
+            sanitize(&words.join(" "))
+        })
+        .collect()
+}
+
+/// Propose a spec for the table in a sample output
+pub fn infer(text: &str) -> Result<ParsingSpec, InferError> {
+    let lines: Vec<&str> = text.lines().collect();
+    let formats = DELIMITERS
+        .iter()
+        .map(|d| TextFormat::Delimited { delimiter: *d })
+        .chain(std::iter::once(TextFormat::FixedWidth));
+    // the format finding the most rows of values
+    let (format, header_rows) = formats
+        .filter_map(|f| first_value_row(&lines, &f).map(|i| (f, i)))
+        .min_by_key(|(_, i)| *i)
+        .ok_or(InferError::NoData)?;
+    let (header, rows) = lines.split_at(header_rows);
+    let rows: Vec<&str> = rows
+        .iter()
+        .copied()
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+
+    let (mut columns, names) = match format {
+        TextFormat::FixedWidth => {
+            let runs = occupied_runs(&rows);
+            let mut columns: Vec<ColumnSpec> = vec![];
+            for (i, (_, end)) in runs.iter().enumerate() {
+                let start = columns.last().and_then(|c| c.end).unwrap_or(0);
+                let end = if i + 1 == runs.len() {
+                    None
+                } else {
+                    Some(*end)
+                };
+                let values: Vec<String> = rows
+                    .iter()
+                    .map(|r| slice(r, start, end).trim().to_string())
+                    .collect();
+                columns.push(ColumnSpec {
+                    name: String::new(),
+                    data_type: ColumnType::of(
+                        values.iter().map(String::as_str).filter(|v| !v.is_empty()),
+                    ),
+                    start: Some(start),
+                    end,
+                });
+            }
+            let names = fixed_width_names(header, &columns);
+            (columns, names)
+        }
+        TextFormat::Delimited { .. } => {
+            let fields: Vec<Vec<String>> = rows.iter().map(|r| split(&format, r)).collect();
+            let width = fields[0].len();
+            let columns = (0..width)
+                .map(|i| ColumnSpec {
+                    name: String::new(),
+                    data_type: ColumnType::of(
+                        fields
+                            .iter()
+                            .map(|f| f[i].as_str())
+                            .filter(|v| !v.is_empty()),
+                    ),
+                    start: None,
+                    end: None,
+                })
+                .collect();
+            let names = header
+                .iter()
+                .rev()
+                .map(|l| split(&format, l))
+                .find(|f| f.len() == width)
+                .map(|f| f.iter().map(|n| sanitize(n)).collect())
+                .unwrap_or_else(|| vec![String::new(); width]);
+            (columns, names)
+        }
+    };
+
+    for (i, (column, name)) in columns.iter_mut().zip(names).enumerate() {
+        column.name = if name.is_empty() {
+            format!("column_{}", i)
+        } else {
+            name
+        };
+    }
+    // keep names unique so the table has a valid schema
+    for i in 1..columns.len() {
+        if columns[..i].iter().any(|c| c.name == columns[i].name) {
+            columns[i].name = format!("{}_{}", columns[i].name, i);
+        }
+    }
+    Ok(ParsingSpec {
+        header_rows,
+        format,
+        columns,
+    })
+}
+
+impl ParsingSpec {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, InferError> {
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), InferError> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn schema(&self) -> Schema {
+        Schema::new(
+            self.columns
+                .iter()
+                .map(|c| Field::new(&c.name, c.data_type.data_type(), true))
+                .collect(),
+        )
+    }
+
+    fn fields(&self, line: &str) -> Vec<String> {
+        match self.format {
+            TextFormat::FixedWidth => self
+                .columns
+                .iter()
+                .map(|c| slice(line, c.start.unwrap_or(0), c.end).trim().to_string())
+                .collect(),
+            _ => split(&self.format, line),
+        }
+    }
+
+    /// Read the table of an output, with blank values as nulls
+    pub fn parse(&self, text: &str) -> Result<RecordBatch, InferError> {
+        let mut values: Vec<Vec<Option<String>>> = vec![vec![]; self.columns.len()];
+        for (n, line) in text.lines().enumerate().skip(self.header_rows) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = self.fields(line).into_iter();
+            for (column, values) in self.columns.iter().zip(values.iter_mut()) {
+                let value = fields.next().filter(|v| !v.is_empty());
+                if let Some(v) = &value {
+                    if !column.data_type.accepts(v) {
+                        return Err(InferError::Value {
+                            line: n + 1,
+                            column: column.name.clone(),
+                            value: v.clone(),
+                            data_type: column.data_type,
+                        });
+                    }
+                }
+                values.push(value);
+            }
+        }
+        let arrays: Vec<ArrayRef> = self
+            .columns
+            .iter()
+            .zip(values)
+            .map(|(column, values)| -> ArrayRef {
+                match column.data_type {
+                    ColumnType::Int32 => Arc::new(Int32Array::from(
+                        values
+                            .iter()
+                            .map(|v| v.as_ref().and_then(|v| v.parse().ok()))
+                            .collect::<Vec<Option<i32>>>(),
+                    )),
+                    ColumnType::Float64 => Arc::new(Float64Array::from(
+                        values
+                            .iter()
+                            .map(|v| v.as_ref().and_then(|v| v.parse().ok()))
+                            .collect::<Vec<Option<f64>>>(),
+                    )),
+                    ColumnType::Utf8 => Arc::new(StringArray::from(
+                        values.iter().map(|v| v.as_deref()).collect::<Vec<_>>(),
+                    )),
+                }
+            })
+            .collect();
+        Ok(RecordBatch::try_new(Arc::new(self.schema()), arrays)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{Float64Array, Int32Array};
+
+    use crate::infer::{infer, ColumnType, ParsingSpec, TextFormat};
+
+    const PLANT: &str = "Results of plant growth simulation:
+
+       Number    Leaf
+  Day      of    Area
+   of    Leaf   Index
+ Year   Nodes
+ ----  ------  ------
+  121    2.00    0.01
+  123    2.20    0.02
+
+  126   12.50    0.03
+";
+
+    #[test]
+    fn infer_fixed_width_and_csv() {
+        let spec = infer(PLANT).unwrap();
+        assert_eq!(spec.header_rows, 7);
+        assert_eq!(spec.format, TextFormat::FixedWidth);
+        let names: Vec<&str> = spec.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["day_of_year", "number_of_leaf_nodes", "leaf_area_index"]
+        );
+        assert_eq!(spec.columns[0].data_type, ColumnType::Int32);
+        assert_eq!(
+            (spec.columns[1].start, spec.columns[1].end),
+            (Some(5), Some(13))
+        );
+
+        let saved: ParsingSpec = toml::from_str(&toml::to_string(&spec).unwrap()).unwrap();
+        assert_eq!(saved, spec);
+        let rb = saved.parse(PLANT).unwrap();
+        assert_eq!(rb.num_rows(), 3);
+        let days = rb.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+        assert_eq!(days.value(2), 126);
+        let nodes = rb
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(nodes.value(2), 12.5);
+
+        let csv = infer("# weather\nday,rain (mm),station\n1,0.5,ames\n2,,ames\n").unwrap();
+        assert_eq!(csv.format, TextFormat::Delimited { delimiter: ',' });
+        assert_eq!(csv.header_rows, 2);
+        assert_eq!(csv.columns[1].name, "rain_mm");
+        assert_eq!(csv.columns[2].data_type, ColumnType::Utf8);
+        let rain = csv.parse("# weather\nday,rain (mm),station\n1,0.5,ames\n2,,ames\n");
+        assert!(rain.unwrap().column(1).is_null(1));
+    }
+}
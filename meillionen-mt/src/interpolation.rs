@@ -0,0 +1,236 @@
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::determinism;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum InterpolationError {
+    #[error("interpolation must be step_hold, linear, spline or disaggregate but is {0}")]
+    UnknownMethod(String),
+    #[error("time {time} is not after the last value at {last}")]
+    NotIncreasing { time: f64, last: f64 },
+    #[error("no values have been pushed yet")]
+    Empty,
+    #[error("time {time} is before the first value at {first}")]
+    Before { time: f64, first: f64 },
+    #[error("time {time} is after the last value at {last}, update the upstream model first")]
+    After { time: f64, last: f64 },
+    #[error("{0:?} interpolates states, not fluxes")]
+    NotAFlux(Interpolation),
+    #[error("{0:?} disaggregates fluxes, not states")]
+    NotAState(Interpolation),
+}
+
+/// How a coupling link fills in values between those the upstream model
+/// supplies
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// A state holds its last value until the next one
+    StepHold,
+    /// A state changes linearly between values
+    Linear,
+    /// A state follows a natural cubic spline through the values
+    Spline,
+    /// A flux, such as rainfall, is spread evenly over the step it was
+    /// accumulated in
+    Disaggregate,
+}
+
+impl Interpolation {
+    pub fn is_flux(self) -> bool {
+        self == Interpolation::Disaggregate
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = InterpolationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "step_hold" => Ok(Interpolation::StepHold),
+            "linear" => Ok(Interpolation::Linear),
+            "spline" => Ok(Interpolation::Spline),
+            "disaggregate" => Ok(Interpolation::Disaggregate),
+            _ => Err(InterpolationError::UnknownMethod(s.to_string())),
+        }
+    }
+}
+
+/// The values an upstream model has handed over on one coupling link, read
+/// by a downstream model with a finer time step
+///
+/// States are pushed with the time they apply at. Fluxes are pushed with the
+/// end of the step they were accumulated over, which starts where the
+/// previous one ended or at the start of the link.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CouplingLink {
+    method: Interpolation,
+    start: f64,
+    times: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl CouplingLink {
+    pub fn new(method: Interpolation, start: f64) -> Self {
+        Self {
+            method,
+            start,
+            times: vec![],
+            values: vec![],
+        }
+    }
+
+    pub fn method(&self) -> Interpolation {
+        self.method
+    }
+
+    pub fn push(&mut self, time: f64, value: f64) -> Result<(), InterpolationError> {
+        let last = self.times.last().copied().or(if self.method.is_flux() {
+            Some(self.start)
+        } else {
+            None
+        });
+        if let Some(last) = last {
+            if time <= last {
+                return Err(InterpolationError::NotIncreasing { time, last });
+            }
+        }
+        self.times.push(time);
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn check_range(&self, time: f64, first: f64) -> Result<(), InterpolationError> {
+        let last = self.times.last().copied().unwrap_or(first);
+        if time < first {
+            Err(InterpolationError::Before { time, first })
+        } else if time > last {
+            Err(InterpolationError::After { time, last })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The value of a state at `time`
+    pub fn value_at(&self, time: f64) -> Result<f64, InterpolationError> {
+        if self.method.is_flux() {
+            return Err(InterpolationError::NotAState(self.method));
+        }
+        let first = *self.times.first().ok_or(InterpolationError::Empty)?;
+        if self.method == Interpolation::StepHold {
+            if time < first {
+                return Err(InterpolationError::Before { time, first });
+            }
+        } else {
+            self.check_range(time, first)?;
+        }
+        // the last value at or before time
+        let i = self.times.partition_point(|t| *t <= time) - 1;
+        if self.times[i] == time || i + 1 == self.times.len() {
+            return Ok(self.values[i]);
+        }
+        match self.method {
+            Interpolation::StepHold => Ok(self.values[i]),
+            Interpolation::Linear => {
+                let (t0, t1) = (self.times[i], self.times[i + 1]);
+                let (v0, v1) = (self.values[i], self.values[i + 1]);
+                Ok(v0 + (v1 - v0) * (time - t0) / (t1 - t0))
+            }
+            Interpolation::Spline => Ok(self.spline(i, time)),
+            Interpolation::Disaggregate => unreachable!(),
+        }
+    }
+
+    /// The natural cubic spline through every value, evaluated between knots
+    /// `i` and `i + 1`
+    fn spline(&self, i: usize, time: f64) -> f64 {
+        let (t, v) = (&self.times, &self.values);
+        let n = t.len();
+        let h: Vec<f64> = t.windows(2).map(|w| w[1] - w[0]).collect();
+        // second derivatives, zero at both ends, by the Thomas algorithm
+        let mut m = vec![0.0; n];
+        if n > 2 {
+            let mut diag = vec![0.0; n];
+            let mut rhs = vec![0.0; n];
+            for k in 1..n - 1 {
+                diag[k] = 2.0 * (h[k - 1] + h[k]);
+                rhs[k] = 6.0 * ((v[k + 1] - v[k]) / h[k] - (v[k] - v[k - 1]) / h[k - 1]);
+            }
+            for k in 2..n - 1 {
+                let w = h[k - 1] / diag[k - 1];
+                diag[k] -= w * h[k - 1];
+                rhs[k] -= w * rhs[k - 1];
+            }
+            for k in (1..n - 1).rev() {
+                m[k] = (rhs[k] - h[k] * m[k + 1]) / diag[k];
+            }
+        }
+        let (a, b) = (t[i + 1] - time, time - t[i]);
+        m[i] * determinism::powi(a, 3) / (6.0 * h[i])
+            + m[i + 1] * determinism::powi(b, 3) / (6.0 * h[i])
+            + (v[i] / h[i] - m[i] * h[i] / 6.0) * a
+            + (v[i + 1] / h[i] - m[i + 1] * h[i] / 6.0) * b
+    }
+
+    /// The amount of a flux between `start` and `end`
+    pub fn amount(&self, start: f64, end: f64) -> Result<f64, InterpolationError> {
+        if !self.method.is_flux() {
+            return Err(InterpolationError::NotAFlux(self.method));
+        }
+        self.check_range(start, self.start)?;
+        self.check_range(end, self.start)?;
+        let mut total = 0.0;
+        let mut from = self.start;
+        for (to, value) in self.times.iter().zip(self.values.iter()) {
+            let overlap = to.min(end) - from.max(start);
+            if overlap > 0.0 {
+                total += value * overlap / (to - from);
+            }
+            from = *to;
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpolation::{CouplingLink, Interpolation, InterpolationError};
+
+    #[test]
+    fn daily_to_hourly() {
+        let mut soil = CouplingLink::new(Interpolation::Linear, 0.0);
+        soil.push(0.0, 0.3).unwrap();
+        soil.push(24.0, 0.2).unwrap();
+        assert!(soil.push(24.0, 0.1).is_err());
+        assert!((soil.value_at(6.0).unwrap() - 0.275).abs() < 1e-12);
+        assert_eq!(
+            soil.value_at(30.0),
+            Err(InterpolationError::After {
+                time: 30.0,
+                last: 24.0
+            })
+        );
+
+        let mut held = CouplingLink::new(Interpolation::StepHold, 0.0);
+        held.push(0.0, 0.3).unwrap();
+        assert_eq!(held.value_at(30.0), Ok(0.3));
+
+        // a spline through points on a line is the line
+        let mut spline = CouplingLink::new(Interpolation::Spline, 0.0);
+        for day in 0..4 {
+            spline.push(day as f64 * 24.0, day as f64).unwrap();
+        }
+        assert!((spline.value_at(30.0).unwrap() - 1.25).abs() < 1e-12);
+
+        let mut rain = CouplingLink::new(Interpolation::Disaggregate, 0.0);
+        rain.push(24.0, 12.0).unwrap();
+        rain.push(48.0, 24.0).unwrap();
+        assert_eq!(rain.amount(0.0, 1.0), Ok(0.5));
+        assert_eq!(rain.amount(23.0, 25.0), Ok(1.5));
+        assert_eq!(rain.amount(0.0, 48.0), Ok(36.0));
+        assert!(rain.value_at(1.0).is_err());
+    }
+}
@@ -1,3 +1,77 @@
+pub mod aliases;
+#[cfg(feature = "data")]
+pub mod archive;
 pub mod arg;
+#[cfg(feature = "data")]
+pub mod calibration;
+pub mod clock;
+#[cfg(feature = "data")]
+pub mod compare;
+pub mod concurrency;
+pub mod conservation;
+pub mod design;
+pub mod determinism;
+pub mod diff;
+pub mod experiment;
+#[cfg(feature = "data")]
 pub mod extension_columns;
-pub mod model;
\ No newline at end of file
+pub mod gc;
+#[cfg(feature = "data")]
+pub mod grid;
+#[cfg(feature = "data")]
+pub mod infer;
+pub mod interpolation;
+pub mod manifest;
+pub mod metrics;
+#[cfg(feature = "data")]
+pub mod model;
+#[cfg(feature = "data")]
+pub mod observation;
+pub mod outputs;
+#[cfg(feature = "plot")]
+pub mod plot;
+#[cfg(feature = "postgres-sink")]
+pub mod postgres;
+pub mod progress;
+pub mod provenance;
+#[cfg(feature = "sqlite")]
+pub mod queue;
+#[cfg(feature = "data")]
+pub mod report;
+pub mod repro;
+#[cfg(feature = "data")]
+pub mod risk;
+#[cfg(feature = "data")]
+pub mod run_metrics;
+#[cfg(feature = "data")]
+pub mod sampling;
+#[cfg(feature = "data")]
+pub mod scheduler;
+pub mod schema;
+pub mod sites;
+#[cfg(feature = "sqlite")]
+pub mod sql;
+#[cfg(feature = "data")]
+pub mod stack;
+pub mod state;
+pub mod store;
+#[cfg(feature = "data")]
+pub mod stream;
+pub mod strictness;
+#[cfg(feature = "sqlite")]
+pub mod submission;
+#[cfg(feature = "data")]
+pub mod surrogate;
+pub mod tags;
+#[cfg(feature = "data")]
+pub mod timeseries;
+pub mod trace;
+pub mod transforms;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod validation;
+#[cfg(feature = "data")]
+pub mod variable;
+pub mod verbosity;
+#[cfg(feature = "data")]
+pub mod workflow;
@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::experiment::{ExperimentConfig, ExperimentError, ResourceConfig, TrialStatus};
+use crate::repro::{sha256_file, ReproConfig};
+use crate::schema::{current_version, migrate, MANIFEST_MIGRATIONS, SCHEMA_VERSION};
+use crate::tags::Tags;
+use crate::transforms::Transforms;
+
+/// What was run for a trial and how it went
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TrialManifest {
+    pub model: String,
+    /// The sha256 of the model program, if it could be found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// The extra arguments and environment variables the program was given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Unix times
+    pub started: u64,
+    pub finished: u64,
+    pub status: TrialStatus,
+    /// The sha256 of each source file before the run, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, String>,
+    /// The sha256 of each sink file after the run, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, String>,
+    /// The unix time the outputs were deleted by `meillionen gc`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruned: Option<u64>,
+    /// The files holding what the program printed, by stream
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub logs: BTreeMap<String, String>,
+    /// The experiment's tags with the trial's own on top
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    /// The changes made to the values of sources before the model read them
+    #[serde(default, skip_serializing_if = "Transforms::is_empty")]
+    pub transforms: Transforms,
+}
+
+/// The sha256 of every file of a set of resources, by path
+///
+/// Resources that are not files, such as directories or paths that were not
+/// written, are left out.
+pub fn checksums<'a>(
+    resources: impl Iterator<Item = &'a ResourceConfig>,
+) -> Result<BTreeMap<String, String>, ExperimentError> {
+    let mut hashes = BTreeMap::new();
+    for path in resources.flat_map(|r| r.files()) {
+        let hash = sha256_file(&path)?;
+        hashes.insert(path.to_string_lossy().to_string(), hash);
+    }
+    Ok(hashes)
+}
+
+/// A file that no longer matches the checksum in a manifest
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChecksumProblem {
+    Missing {
+        trial: String,
+        path: String,
+    },
+    Changed {
+        trial: String,
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for ChecksumProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumProblem::Missing { trial, path } => {
+                write!(f, "{}: {} is missing", trial, path)
+            }
+            ChecksumProblem::Changed {
+                trial,
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: {} has sha256 {} but {} was recorded",
+                trial, path, actual, expected
+            ),
+        }
+    }
+}
+
+/// A record of an experiment run, kept next to the experiment file
+///
+/// Trials are kept in a sorted map so the file does not depend on the order
+/// they ran in.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RunManifest {
+    /// The schema version, see [`crate::schema`]
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub experiment: String,
+    pub created: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repro: Option<ReproConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    pub trials: BTreeMap<String, TrialManifest>,
+}
+
+impl RunManifest {
+    pub fn new(config: &ExperimentConfig, created: u64) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            experiment: config.name.clone(),
+            created,
+            repro: config.repro.clone(),
+            tags: config.tags.clone(),
+            trials: BTreeMap::new(),
+        }
+    }
+
+    /// `baseline.toml` keeps its manifest in `baseline.manifest.json`
+    pub fn path_for(config: &Path) -> PathBuf {
+        config.with_extension("manifest.json")
+    }
+
+    /// `baseline.toml` keeps the output of its trials in `baseline.logs/`
+    pub fn logs_dir_for(config: &Path) -> PathBuf {
+        config.with_extension("logs")
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ExperimentError> {
+        let value = serde_json::from_reader(File::open(path)?)?;
+        Ok(serde_json::from_value(migrate(
+            value,
+            MANIFEST_MIGRATIONS,
+        )?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ExperimentError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Re-hash the files recorded in the manifest to find any that went
+    /// missing or changed since the run
+    ///
+    /// Relative paths are resolved against `base`, usually the directory the
+    /// experiment was run from. Outputs of pruned trials are not checked.
+    pub fn verify(&self, base: &Path) -> Result<Vec<ChecksumProblem>, ExperimentError> {
+        let mut problems = vec![];
+        for (trial, m) in self.trials.iter() {
+            let outputs = m.outputs.iter().filter(|_| m.pruned.is_none());
+            for (path, expected) in m.inputs.iter().chain(outputs) {
+                let full = base.join(path);
+                if !full.is_file() {
+                    problems.push(ChecksumProblem::Missing {
+                        trial: trial.clone(),
+                        path: path.clone(),
+                    });
+                    continue;
+                }
+                let actual = sha256_file(&full)?;
+                if &actual != expected {
+                    problems.push(ChecksumProblem::Changed {
+                        trial: trial.clone(),
+                        path: path.clone(),
+                        expected: expected.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+        Ok(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::experiment::{ExperimentConfig, ResourceConfig, TrialStatus};
+    use crate::manifest::{checksums, ChecksumProblem, RunManifest, TrialManifest};
+
+    const TOML: &str = r#"
+name = "irrigation"
+
+[repro]
+seed = 4
+timestamp = 1000
+
+[[trials]]
+name = "drought"
+model = "simplecrop_omf"
+
+[[trials]]
+name = "baseline"
+model = "simplecrop_omf"
+"#;
+
+    fn manifest(config: &ExperimentConfig, order: &[usize]) -> String {
+        let repro = config.repro.as_ref().unwrap();
+        let mut m = RunManifest::new(config, repro.timestamp);
+        for i in order {
+            let trial = &config.trials[*i];
+            m.trials.insert(
+                trial.name.clone(),
+                TrialManifest {
+                    model: trial.model.clone(),
+                    model_sha256: None,
+                    seed: Some(repro.trial_seed(&trial.name)),
+                    args: trial.args.clone(),
+                    env: trial.env.clone(),
+                    started: repro.timestamp,
+                    finished: repro.timestamp,
+                    status: TrialStatus::Succeeded,
+                    inputs: Default::default(),
+                    outputs: Default::default(),
+                    pruned: None,
+                    logs: Default::default(),
+                    tags: config.trial_tags(trial),
+                    transforms: trial.transforms.clone(),
+                },
+            );
+        }
+        serde_json::to_string_pretty(&m).unwrap()
+    }
+
+    #[test]
+    fn identical_across_runs() {
+        let config: ExperimentConfig = toml::from_str(TOML).unwrap();
+        assert_eq!(
+            config
+                .ordered_trials()
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["baseline", "drought"]
+        );
+        let a = manifest(&config, &[0, 1]);
+        let b = manifest(&config, &[1, 0]);
+        assert_eq!(a, b);
+        let loaded: RunManifest = serde_json::from_str(&a).unwrap();
+        assert_eq!(loaded.repro.unwrap().seed, 4);
+    }
+
+    #[test]
+    fn verify_checksums() {
+        let dir = std::env::temp_dir().join(format!("meillionen-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("daily.feather");
+        let output = dir.join("yearly.parquet");
+        std::fs::write(&input, "daily").unwrap();
+        std::fs::write(&output, "yearly").unwrap();
+        let resource = |path: &std::path::Path| -> ResourceConfig {
+            serde_json::from_value(serde_json::json!({
+                "type": "feather",
+                "path": path.to_string_lossy()
+            }))
+            .unwrap()
+        };
+        let config: ExperimentConfig = toml::from_str(TOML).unwrap();
+        let mut manifest = RunManifest::new(&config, 0);
+        manifest.trials.insert(
+            "baseline".to_string(),
+            TrialManifest {
+                model: "simplecrop_omf".to_string(),
+                model_sha256: None,
+                seed: None,
+                args: vec![],
+                env: Default::default(),
+                started: 0,
+                finished: 0,
+                status: TrialStatus::Succeeded,
+                inputs: checksums(std::iter::once(&resource(&input))).unwrap(),
+                outputs: checksums([resource(&output), resource(&dir.join("unwritten"))].iter())
+                    .unwrap(),
+                pruned: None,
+                logs: Default::default(),
+                tags: Default::default(),
+                transforms: Default::default(),
+            },
+        );
+        let clean = manifest.verify(&dir).unwrap();
+        std::fs::write(&output, "tampered").unwrap();
+        std::fs::remove_file(&input).unwrap();
+        let problems = manifest.verify(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(manifest.trials["baseline"].outputs.len(), 1);
+        assert!(clean.is_empty());
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(problems[0], ChecksumProblem::Missing { .. }));
+        assert!(problems[1].to_string().contains("was recorded"));
+    }
+}
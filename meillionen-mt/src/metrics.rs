@@ -0,0 +1,231 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds in seconds of the run duration histogram buckets
+///
+/// Model runs range from a single site season (under a second) to gridded
+/// ensembles that take hours.
+pub const DURATION_BUCKETS: [f64; 10] =
+    [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    counts: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(DURATION_BUCKETS.iter()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Metrics for a shared model execution service
+///
+/// `render` produces the Prometheus text exposition format so the service
+/// can answer scrapes of its `/metrics` endpoint.
+#[derive(Debug, Default)]
+pub struct ServiceMetrics {
+    runs_started: AtomicU64,
+    runs_failed: AtomicU64,
+    queue_depth: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    duration: Mutex<Histogram>,
+}
+
+impl ServiceMetrics {
+    pub fn run_started(&self) {
+        self.runs_started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn run_finished(&self, duration: Duration, succeeded: bool) {
+        if !succeeded {
+            self.runs_failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.duration
+            .lock()
+            .expect("metrics lock not to be poisoned")
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn set_queue_depth(&self, depth: u64) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_received(&self, n: u64) {
+        self.bytes_received.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_sent(&self, n: u64) {
+        self.bytes_sent.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        };
+        metric(
+            "meillionen_runs_started_total",
+            "counter",
+            "Model runs started.",
+            self.runs_started.load(Ordering::Relaxed),
+        );
+        metric(
+            "meillionen_runs_failed_total",
+            "counter",
+            "Model runs that exited with an error.",
+            self.runs_failed.load(Ordering::Relaxed),
+        );
+        metric(
+            "meillionen_queue_depth",
+            "gauge",
+            "Runs waiting to be started.",
+            self.queue_depth.load(Ordering::Relaxed),
+        );
+        metric(
+            "meillionen_bytes_received_total",
+            "counter",
+            "Bytes of experiment files received.",
+            self.bytes_received.load(Ordering::Relaxed),
+        );
+        metric(
+            "meillionen_bytes_sent_total",
+            "counter",
+            "Bytes of outputs written by runs.",
+            self.bytes_sent.load(Ordering::Relaxed),
+        );
+
+        let h = self
+            .duration
+            .lock()
+            .expect("metrics lock not to be poisoned");
+        let name = "meillionen_run_duration_seconds";
+        writeln!(
+            out,
+            "# HELP {} Wall clock time of finished model runs.",
+            name
+        )
+        .unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (count, bound) in h.counts.iter().zip(DURATION_BUCKETS.iter()) {
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, h.count).unwrap();
+        writeln!(out, "{}_sum {}", name, h.sum).unwrap();
+        writeln!(out, "{}_count {}", name, h.count).unwrap();
+        out
+    }
+}
+
+/// Answer `GET /metrics` on `listener` with the rendered metrics until
+/// accepting fails
+///
+/// Requests are answered one at a time, which is enough for a scraper.
+/// Other paths get a 404.
+pub fn serve_metrics(metrics: &ServiceMetrics, listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        if reader.read_line(&mut request).is_err() {
+            continue;
+        }
+        // read the headers so closing doesn't reset the connection
+        let mut header = String::new();
+        while matches!(reader.read_line(&mut header), Ok(n) if n > 0 && !header.trim().is_empty()) {
+            header.clear();
+        }
+        let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+            ["GET", "/metrics"] => ("200 OK", metrics.render()),
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+        // a scraper that hung up is not an error of the service
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::metrics::{serve_metrics, ServiceMetrics};
+
+    #[test]
+    fn exposition() {
+        let m = ServiceMetrics::default();
+        m.run_started();
+        m.run_started();
+        m.run_finished(Duration::from_millis(250), true);
+        m.run_finished(Duration::from_secs(120), false);
+        m.set_queue_depth(3);
+        m.add_bytes_sent(1024);
+        let text = m.render();
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "meillionen_runs_started_total 2",
+            "meillionen_runs_failed_total 1",
+            "meillionen_queue_depth 3",
+            "meillionen_bytes_sent_total 1024",
+            "meillionen_run_duration_seconds_bucket{le=\"0.1\"} 0",
+            "meillionen_run_duration_seconds_bucket{le=\"0.5\"} 1",
+            "meillionen_run_duration_seconds_bucket{le=\"300\"} 2",
+            "meillionen_run_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "meillionen_run_duration_seconds_sum 120.25",
+            "meillionen_run_duration_seconds_count 2",
+        ]
+        .iter()
+        {
+            assert!(lines.contains(line), "{} not in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn metrics_endpoint() {
+        let metrics = Arc::new(ServiceMetrics::default());
+        metrics.run_started();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        {
+            let metrics = metrics.clone();
+            std::thread::spawn(move || serve_metrics(&metrics, listener));
+        }
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP meillionen_runs_started_total"));
+        assert!(response.contains("\nmeillionen_runs_started_total 1\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
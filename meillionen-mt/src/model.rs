@@ -1,5 +1,6 @@
 use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
 use std::sync::Arc;
 use std::{env, fmt};
@@ -8,13 +9,19 @@ use itertools::Itertools;
 use serde_derive::{Deserialize, Serialize};
 use thiserror::Error;
 
-use std::fmt::Formatter;
 use arrow::array::{BinaryBuilder, StringBuilder};
-use arrow::record_batch::RecordBatch;
 use arrow::ipc::reader::StreamReader;
-use stable_eyre;
-use stable_eyre::eyre::{WrapErr, ContextCompat};
 use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use stable_eyre;
+use stable_eyre::eyre::{ContextCompat, WrapErr};
+use std::fmt::Formatter;
+
+use crate::repro::resolve_program;
+use crate::trace::{
+    append_otlp_json, export_otlp_http, otlp_endpoint_from_env, Span, TraceContext, OTLP_FILE_ENV,
+    TRACEPARENT_ENV,
+};
 
 #[derive(Debug, Error, Deserialize, Serialize)]
 pub enum FuncRequestSchemaError {
@@ -66,7 +73,7 @@ pub struct ResourceBuilder {
     field: StringBuilder,
     name: StringBuilder,
     resource: StringBuilder,
-    payload: BinaryBuilder
+    payload: BinaryBuilder,
 }
 
 impl ResourceBuilder {
@@ -80,7 +87,13 @@ impl ResourceBuilder {
         }
     }
 
-    pub fn add(&mut self, field: &str, name: &str, resource: &str, payload: &[u8]) -> arrow::error::Result<()> {
+    pub fn add(
+        &mut self,
+        field: &str,
+        name: &str,
+        resource: &str,
+        payload: &[u8],
+    ) -> arrow::error::Result<()> {
         self.field.append_value(field)?;
         self.name.append_value(name)?;
         self.resource.append_value(resource)?;
@@ -92,12 +105,15 @@ impl ResourceBuilder {
         use arrow::datatypes::{DataType, Field, Schema};
         let mut metadata = HashMap::new();
         metadata.insert("meillionen-name".to_string(), self.program_name.clone());
-        let schema = Schema::new_with_metadata(vec![
-            Field::new("field", DataType::Utf8, false),
-            Field::new("name", DataType::Utf8, false),
-            Field::new("resource", DataType::Utf8, false),
-            Field::new("payload", DataType::Binary, false),
-        ], metadata);
+        let schema = Schema::new_with_metadata(
+            vec![
+                Field::new("field", DataType::Utf8, false),
+                Field::new("name", DataType::Utf8, false),
+                Field::new("resource", DataType::Utf8, false),
+                Field::new("payload", DataType::Binary, false),
+            ],
+            metadata,
+        );
         Arc::new(schema)
     }
 
@@ -108,33 +124,160 @@ impl ResourceBuilder {
                 Arc::new(self.field.finish()),
                 Arc::new(self.name.finish()),
                 Arc::new(self.resource.finish()),
-                Arc::new(self.payload.finish())
-            ]
-        ).expect("schema and columns to match")
+                Arc::new(self.payload.finish()),
+            ],
+        )
+        .expect("schema and columns to match")
     }
 }
 
+/// Run a model program with a request
+///
+/// When a parent trace context is given the run gets its own span and the
+/// program gets the span's context in the `TRACEPARENT` environment variable
+/// so its spans join the caller's trace. The run span is sent to the
+/// collector named by `OTEL_EXPORTER_OTLP_ENDPOINT` (or
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) over OTLP/HTTP, and appended as OTLP
+/// JSON to the file named by `MEILLIONEN_OTLP_FILE`, when they are set.
 pub fn client_call_cli(
     program_path: &str,
     rb: &RecordBatch,
+    trace: Option<&TraceContext>,
+) -> stable_eyre::Result<Output> {
+    client_call_cli_with_env(program_path, rb, trace, &BTreeMap::new())
+}
+
+/// Run a model program with a request and extra environment variables
+pub fn client_call_cli_with_env(
+    program_path: &str,
+    rb: &RecordBatch,
+    trace: Option<&TraceContext>,
+    env: &BTreeMap<String, String>,
+) -> stable_eyre::Result<Output> {
+    client_call_cli_with_args(program_path, rb, trace, env, &[])
+}
+
+/// Run a model program with a request, extra environment variables and
+/// extra arguments after its `run` subcommand
+pub fn client_call_cli_with_args(
+    program_path: &str,
+    rb: &RecordBatch,
+    trace: Option<&TraceContext>,
+    env: &BTreeMap<String, String>,
+    args: &[String],
 ) -> stable_eyre::Result<Output> {
-    let mut cmd = Command::new(program_path)
+    let mut command = Command::new(program_command_path(program_path));
+    command.envs(env);
+    let mut span = trace.map(|parent| {
+        let mut span = Span::start("run", Some(parent));
+        span.set_attribute("program", program_path);
+        span
+    });
+    if let Some(span) = span.as_ref() {
+        command.env(TRACEPARENT_ENV, span.context.traceparent());
+    }
+    tracing::debug!(program = program_path, ?args, "calling model");
+    let output = run_cli(command, program_path, rb, args);
+    if let Ok(output) = &output {
+        tracing::debug!(program = program_path, status = %output.status, "model finished");
+    }
+    if let Some(span) = span.as_mut() {
+        span.end();
+        if let Some(path) = env::var_os(OTLP_FILE_ENV) {
+            append_otlp_json(path, "meillionen", std::slice::from_ref(span))
+                .wrap_err("could not write trace span")?;
+        }
+        if let Some(endpoint) = otlp_endpoint_from_env() {
+            // a collector that is down should not fail the model run
+            if let Err(e) = export_otlp_http(&endpoint, "meillionen", std::slice::from_ref(span)) {
+                tracing::warn!(%endpoint, error = %e, "could not export trace span");
+            }
+        }
+    }
+    output
+}
+
+/// Windows paths this long or longer need the `\\?\` prefix
+const MAX_PATH: usize = 260;
+
+/// Prefix a long absolute windows path with `\\?\` so it is not cut off at
+/// `MAX_PATH`, leaving other paths and other platforms alone
+pub fn long_path(path: &Path) -> PathBuf {
+    if cfg!(windows) {
+        verbatim_path(&path.to_string_lossy())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| path.to_path_buf())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn verbatim_path(path: &str) -> Option<String> {
+    if path.len() < MAX_PATH || path.starts_with(r"\\?\") {
+        return None;
+    }
+    let path = path.replace('/', "\\");
+    if let Some(share) = path.strip_prefix(r"\\") {
+        return Some(format!(r"\\?\UNC\{}", share));
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b":\\" {
+        Some(format!(r"\\?\{}", path))
+    } else {
+        None
+    }
+}
+
+/// The path to spawn a model program from, found with `PATHEXT` on windows
+fn program_command_path(program: &str) -> PathBuf {
+    resolve_program(program)
+        .map(|p| long_path(&p))
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+/// Program output as text with windows line endings made unix ones
+pub fn output_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).replace("\r\n", "\n")
+}
+
+fn run_cli(
+    mut command: Command,
+    program_path: &str,
+    rb: &RecordBatch,
+    args: &[String],
+) -> stable_eyre::Result<Output> {
+    let mut cmd = command
         .arg("run")
+        .args(args)
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
-        .unwrap_or_else(|_| panic!("could not spawn process {}", program_path));
+        .wrap_err_with(|| format!("could not spawn process {}", program_path))?;
 
-    let stdin = cmd.stdin.take().wrap_err("could not open stdin")?;
-    let mut sw = StreamWriter::try_new(stdin, rb.schema().as_ref()).wrap_err(format!("could not create input stream writer for {}", program_path))?;
-    sw.write(rb).wrap_err("could not write record batch to input stream")?;
-    sw.finish().wrap_err("failed to finish record batch stream to stdout")?;
-    cmd.wait_with_output().wrap_err_with(|| format!("waiting for program {} to finish failed", program_path))
+    let write_input = |cmd: &mut std::process::Child| -> stable_eyre::Result<()> {
+        let stdin = cmd.stdin.take().wrap_err("could not open stdin")?;
+        let mut sw = StreamWriter::try_new(stdin, rb.schema().as_ref()).wrap_err(format!(
+            "could not create input stream writer for {}",
+            program_path
+        ))?;
+        sw.write(rb)
+            .wrap_err("could not write record batch to input stream")?;
+        sw.finish()
+            .wrap_err("failed to finish record batch stream to stdout")?;
+        Ok(())
+    };
+    if let Err(e) = write_input(&mut cmd) {
+        let _ = cmd.kill();
+        let _ = cmd.wait();
+        return Err(e);
+    }
+    cmd.wait_with_output()
+        .wrap_err_with(|| format!("waiting for program {} to finish failed", program_path))
 }
 
 pub fn client_create_interface_from_cli(path: &str) -> stable_eyre::Result<RecordBatch> {
-    let child = Command::new(path)
+    let child = Command::new(program_command_path(path))
         .arg("interface")
         .stdin(Stdio::piped())
         .stderr(Stdio::piped())
@@ -145,20 +288,36 @@ pub fn client_create_interface_from_cli(path: &str) -> stable_eyre::Result<Recor
         .wrap_err_with(|| format!("executing cli program {} failed", path))?;
     let mut sr = StreamReader::try_new(child.stdout.as_slice())
         .wrap_err_with(|| format!("could not read stdout from program {}", path))?;
-    let res = sr.next()
-        .ok_or(stable_eyre::eyre::eyre!("record batch stream for program {} empty", path))?;
+    let res = sr.next().ok_or(stable_eyre::eyre::eyre!(
+        "record batch stream for program {} empty",
+        path
+    ))?;
     if let Ok(rb) = res {
         Ok(rb)
     } else {
-        Err(stable_eyre::eyre::eyre!("could not read record batch for program {}", path))
+        Err(stable_eyre::eyre::eyre!(
+            "could not read record batch for program {}",
+            path
+        ))
     }
 }
 
-pub fn server_respond_from_cli(name: &str, interface: &RecordBatch) -> stable_eyre::Result<RecordBatch> {
+pub fn server_respond_from_cli(
+    name: &str,
+    interface: &RecordBatch,
+) -> stable_eyre::Result<RecordBatch> {
     let mut app = clap::App::new(name).subcommand(
         clap::SubCommand::with_name("interface").about("json describing the model interface"),
     );
-    let run = clap::SubCommand::with_name("run").about("run the model");
+    // extra arguments from the experiment file are left for the model to read
+    let run = clap::SubCommand::with_name("run")
+        .about("run the model")
+        .setting(clap::AppSettings::TrailingVarArg)
+        .arg(
+            clap::Arg::with_name("args")
+                .multiple(true)
+                .allow_hyphen_values(true),
+        );
     app = app.subcommand(run);
     let matches = app.get_matches_from(
         vec![OsString::from(name)]
@@ -166,18 +325,19 @@ pub fn server_respond_from_cli(name: &str, interface: &RecordBatch) -> stable_ey
             .chain(env::args_os().dropping(2)),
     );
     if matches.subcommand_matches("interface").is_some() {
-        let mut sw =
-            StreamWriter::try_new(std::io::stdout(), interface.schema().as_ref())
-                .wrap_err("failed to open stdout stream")?;
+        let mut sw = StreamWriter::try_new(std::io::stdout(), interface.schema().as_ref())
+            .wrap_err("failed to open stdout stream")?;
         sw.write(interface)
             .wrap_err("failed to write interface record batch to stdout")?;
-        sw.finish().wrap_err("failed to finish record batch stream to stdout")?;
+        sw.finish()
+            .wrap_err("failed to finish record batch stream to stdout")?;
         std::process::exit(0);
     } else if matches.subcommand_matches("run").is_some() {
         let mut sr = StreamReader::try_new(std::io::stdin())
             .wrap_err("could not read input of run command")?;
-        let res = sr.next()
-            .ok_or(stable_eyre::eyre::eyre!("could not read one record batch from stdin"))?;
+        let res = sr.next().ok_or(stable_eyre::eyre::eyre!(
+            "could not read one record batch from stdin"
+        ))?;
         if let Ok(rb) = res {
             Ok(rb)
         } else {
@@ -191,6 +351,157 @@ pub fn server_respond_from_cli(name: &str, interface: &RecordBatch) -> stable_ey
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SerializedResource {
     dtype: String,
-    payload: Vec<u8>
+    payload: Vec<u8>,
 }
 pub type ResourceMap = BTreeMap<String, Arc<SerializedResource>>;
+
+/// One source or sink in a model's interface
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InterfaceArg {
+    /// `source` or `sink`
+    pub field: String,
+    pub name: String,
+    /// The schema type, such as `meillionen::TensorSchema`
+    pub schema_type: String,
+    pub schema: serde_json::Value,
+}
+
+impl InterfaceArg {
+    /// Read the arguments of an interface record batch returned by a model
+    pub fn from_recordbatch(rb: &RecordBatch) -> stable_eyre::Result<Vec<Self>> {
+        use arrow::array::{BinaryArray, StringArray};
+        let strings = |i: usize| {
+            rb.column(i)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .wrap_err_with(|| format!("interface column {} must be a string column", i))
+        };
+        let (field, name, resource) = (strings(0)?, strings(1)?, strings(2)?);
+        let payload = rb
+            .column(3)
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .wrap_err("interface payload column must be a binary column")?;
+        (0..rb.num_rows())
+            .map(|i| {
+                Ok(Self {
+                    field: field.value(i).to_string(),
+                    name: name.value(i).to_string(),
+                    schema_type: resource.value(i).to_string(),
+                    schema: serde_json::from_slice(payload.value(i)).wrap_err_with(|| {
+                        format!("schema of {} is not valid json", name.value(i))
+                    })?,
+                })
+            })
+            .collect()
+    }
+
+    /// A few indented lines describing the argument for people
+    pub fn describe(&self) -> String {
+        let schema_type = self.schema_type.rsplit("::").next().unwrap_or("");
+        let mut lines = vec![format!("{} {} ({})", self.field, self.name, schema_type)];
+        let s = &self.schema;
+        if let Some(d) = s["description"].as_str().filter(|d| !d.is_empty()) {
+            lines.push(format!("  {}", d));
+        }
+        if let Some(dims) = s["dimensions"].as_array() {
+            let dims: Vec<&str> = dims.iter().filter_map(|d| d.as_str()).collect();
+            lines.push(format!("  dimensions: {}", dims.join(", ")));
+        }
+        if !s["data_type"].is_null() {
+            lines.push(format!("  data type: {}", s["data_type"]));
+        }
+        if let Some(units) = s["attributes"]["units"].as_str() {
+            lines.push(format!("  units: {}", units));
+        }
+        if let Some(fields) = s["columns"]["fields"].as_array() {
+            lines.push("  columns:".to_string());
+            for f in fields {
+                let mut line = format!(
+                    "    {}: {}",
+                    f["name"].as_str().unwrap_or("?"),
+                    f["data_type"]
+                );
+                let doc = |key: &str| f["metadata"][key].as_str();
+                if let Some(default) = doc("default") {
+                    line.push_str(&format!(", default {}", default));
+                }
+                match (doc("min"), doc("max")) {
+                    (Some(min), Some(max)) => line.push_str(&format!(", in [{}, {}]", min, max)),
+                    (Some(min), None) => line.push_str(&format!(", >= {}", min)),
+                    (None, Some(max)) => line.push_str(&format!(", <= {}", max)),
+                    (None, None) => {}
+                }
+                if let Some(units) = doc("units") {
+                    line.push_str(&format!(", units: {}", units));
+                }
+                lines.push(line);
+                if let Some(description) = doc("description") {
+                    lines.push(format!("      {}", description));
+                }
+            }
+        }
+        if let Some(ext) = s["ext"].as_str() {
+            lines.push(format!("  extension: {}", ext));
+        }
+        if let Some(resources) = s["resources"].as_array() {
+            let resources: Vec<&str> = resources
+                .iter()
+                .filter_map(|r| r.as_str())
+                .map(|r| r.rsplit("::").next().unwrap_or(r))
+                .collect();
+            lines.push(format!("  resources: {}", resources.join(", ")));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{output_text, verbatim_path, InterfaceArg, ResourceBuilder, MAX_PATH};
+
+    #[test]
+    fn windows_output_and_paths() {
+        assert_eq!(output_text(b"error\r\nat line 2\r\n"), "error\nat line 2\n");
+
+        let dir = "d".repeat(MAX_PATH);
+        assert_eq!(verbatim_path(r"C:\models\simplecrop.exe"), None);
+        assert_eq!(
+            verbatim_path(&format!("C:/{}/model.exe", dir)),
+            Some(format!(r"\\?\C:\{}\model.exe", dir))
+        );
+        assert_eq!(
+            verbatim_path(&format!(r"\\server\share\{}", dir)),
+            Some(format!(r"\\?\UNC\server\share\{}", dir))
+        );
+        assert_eq!(verbatim_path(&format!(r"relative\{}", dir)), None);
+    }
+
+    #[test]
+    fn interface_args() {
+        let mut rb = ResourceBuilder::new("simplecrop");
+        let schema = br#"{"dimensions": ["x", "y"], "data_type": "Float32", "resources": ["meillionen::NetCDFResource"], "attributes": {"units": "mm"}}"#;
+        rb.add("sink", "soil_water", "meillionen::TensorSchema", schema)
+            .unwrap();
+        let args = InterfaceArg::from_recordbatch(&rb.extract_to_recordbatch()).unwrap();
+        assert_eq!(args.len(), 1);
+        assert_eq!(
+            args[0].describe(),
+            "sink soil_water (TensorSchema)\n  dimensions: x, y\n  data type: \"Float32\"\n  units: mm\n  resources: NetCDFResource"
+        );
+
+        let mut rb = ResourceBuilder::new("simplecrop");
+        let schema = br#"{"description": "Yearly parameters", "columns": {"fields": [
+            {"name": "soil_runoff_curve_number", "data_type": "Float32", "nullable": false, "dict_id": 0, "dict_is_ordered": false,
+             "metadata": {"default": "55", "min": "0", "max": "100", "units": "1", "description": "Runoff curve number (CN)"}},
+            {"name": "plant_nb", "data_type": "Float32", "nullable": false, "dict_id": 0, "dict_is_ordered": false}
+        ]}, "resources": []}"#;
+        rb.add("source", "yearly", "meillionen::DataFrameSchema", schema)
+            .unwrap();
+        let args = InterfaceArg::from_recordbatch(&rb.extract_to_recordbatch()).unwrap();
+        assert_eq!(
+            args[0].describe(),
+            "source yearly (DataFrameSchema)\n  Yearly parameters\n  columns:\n    soil_runoff_curve_number: \"Float32\", default 55, in [0, 100], units: 1\n      Runoff curve number (CN)\n    plant_nb: \"Float32\"\n  resources: "
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{Date32Array, Int32Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use chrono::{Duration, NaiveDate};
+use serde_derive::Deserialize;
+use thiserror::Error;
+
+use crate::determinism;
+use crate::extension_columns::UncertaintyMeta;
+use crate::timeseries::numeric_values;
+
+#[derive(Debug, Error)]
+pub enum ObservationError {
+    #[error("line {0}: {1}")]
+    InvalidDate(u64, String),
+    #[error("date column {0} not found")]
+    MissingDateColumn(String),
+    #[error("date column {0} must be Date32 or an Int32 day of year but is {1:?}")]
+    NotDate(String, DataType),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+}
+
+/// A measurement of a variable on a date, such as a LAI reading in the field
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    pub date: NaiveDate,
+    pub variable: String,
+    pub value: f64,
+    /// The standard deviation of the measurement, if known
+    pub uncertainty: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ObservationRecord {
+    date: String,
+    variable: String,
+    value: f64,
+    #[serde(default)]
+    uncertainty: Option<f64>,
+}
+
+/// How the rows of simulated output are dated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DateIndex<'a> {
+    /// A Date32 column
+    Date(&'a str),
+    /// An Int32 day of year column in a single year, like SimpleCrop's `day`
+    DayOfYear { column: &'a str, year: i32 },
+}
+
+impl<'a> DateIndex<'a> {
+    fn dates(&self, rb: &RecordBatch) -> Result<Vec<Option<NaiveDate>>, ObservationError> {
+        let name = match self {
+            DateIndex::Date(c) => c,
+            DateIndex::DayOfYear { column, .. } => column,
+        };
+        let i = rb
+            .schema()
+            .index_of(name)
+            .map_err(|_| ObservationError::MissingDateColumn(name.to_string()))?;
+        let column = rb.column(i);
+        match (self, column.data_type()) {
+            (DateIndex::Date(_), DataType::Date32) => {
+                let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).expect("epoch to be a valid date");
+                let days = column
+                    .as_any()
+                    .downcast_ref::<Date32Array>()
+                    .expect("array type to match data type");
+                Ok(days
+                    .iter()
+                    .map(|d| d.map(|d| epoch + Duration::days(d.into())))
+                    .collect())
+            }
+            (DateIndex::DayOfYear { year, .. }, DataType::Int32) => {
+                let days = column
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .expect("array type to match data type");
+                Ok(days
+                    .iter()
+                    .map(|d| d.and_then(|d| NaiveDate::from_yo_opt(*year, d as u32)))
+                    .collect())
+            }
+            (_, t) => Err(ObservationError::NotDate(name.to_string(), t.clone())),
+        }
+    }
+}
+
+/// An observation paired with the simulated value of the same variable on the
+/// same date
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aligned {
+    pub date: NaiveDate,
+    pub variable: String,
+    pub observed: f64,
+    pub simulated: f64,
+    pub uncertainty: Option<f64>,
+}
+
+impl Aligned {
+    pub fn residual(&self) -> f64 {
+        self.simulated - self.observed
+    }
+}
+
+/// The result of matching observations to simulated output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Alignment {
+    pub pairs: Vec<Aligned>,
+    /// Observations whose date or variable is not in the simulated output
+    pub unmatched: Vec<Observation>,
+}
+
+impl Alignment {
+    /// The root mean square error of the simulated values of a variable, or
+    /// NaN if none were observed
+    pub fn rmse(&self, variable: &str) -> f64 {
+        let residuals: Vec<f64> = self
+            .pairs
+            .iter()
+            .filter(|p| p.variable == variable)
+            .map(|p| p.residual().powi(2))
+            .collect();
+        (residuals.iter().sum::<f64>() / residuals.len() as f64).sqrt()
+    }
+
+    /// The Gaussian log likelihood of the observations given the simulated
+    /// values, up to a constant
+    ///
+    /// Observations without an uncertainty use `default_sd`.
+    pub fn log_likelihood(&self, default_sd: f64) -> f64 {
+        self.pairs
+            .iter()
+            .map(|p| {
+                let sd = p.uncertainty.unwrap_or(default_sd);
+                -0.5 * (p.residual() / sd).powi(2) - determinism::ln(sd)
+            })
+            .sum()
+    }
+}
+
+/// A set of observations in long format
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Observations {
+    pub rows: Vec<Observation>,
+}
+
+impl Observations {
+    /// Read observations from csv with `date`, `variable`, `value` and an
+    /// optional `uncertainty` column
+    ///
+    /// Dates are written `YYYY-MM-DD`.
+    pub fn from_csv_reader<R: Read>(reader: R) -> Result<Self, ObservationError> {
+        let mut reader = csv::Reader::from_reader(reader);
+        let mut rows = vec![];
+        for record in reader.deserialize() {
+            let record: ObservationRecord = record?;
+            let date = NaiveDate::parse_from_str(record.date.trim(), "%Y-%m-%d").map_err(|e| {
+                // the header is line 1
+                ObservationError::InvalidDate(rows.len() as u64 + 2, e.to_string())
+            })?;
+            rows.push(Observation {
+                date,
+                variable: record.variable,
+                value: record.value,
+                uncertainty: record.uncertainty,
+            });
+        }
+        Ok(Self { rows })
+    }
+
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> Result<Self, ObservationError> {
+        Self::from_csv_reader(std::fs::File::open(path).map_err(csv::Error::from)?)
+    }
+
+    /// The observations of one variable
+    pub fn variable<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Observation> {
+        self.rows.iter().filter(move |o| o.variable == name)
+    }
+
+    /// The observations as a record batch with `date`, `variable` and a
+    /// `meillionen.uncertainty` `value` column holding each value and its
+    /// standard deviation
+    pub fn to_recordbatch(&self) -> arrow::error::Result<RecordBatch> {
+        let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).expect("epoch to be a valid date");
+        let dates = Date32Array::from(
+            self.rows
+                .iter()
+                .map(|o| (o.date - epoch).num_days() as i32)
+                .collect::<Vec<_>>(),
+        );
+        let variables = StringArray::from_iter_values(self.rows.iter().map(|o| &o.variable));
+        let (value, values) = UncertaintyMeta::StandardDeviation.column(
+            "value",
+            vec![
+                self.rows.iter().map(|o| o.value).collect(),
+                self.rows
+                    .iter()
+                    .map(|o| o.uncertainty.unwrap_or(f64::NAN))
+                    .collect(),
+            ],
+        )?;
+        let schema = Schema::new(vec![
+            Field::new("date", DataType::Date32, false),
+            Field::new("variable", DataType::Utf8, false),
+            value,
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(dates), Arc::new(variables), values],
+        )
+    }
+
+    /// Match each observation to the simulated column of the same name on the
+    /// same date
+    ///
+    /// Simulated values that are missing or NaN do not match.
+    pub fn align(
+        &self,
+        simulated: &RecordBatch,
+        index: DateIndex,
+    ) -> Result<Alignment, ObservationError> {
+        let rows: BTreeMap<NaiveDate, usize> = index
+            .dates(simulated)?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, d)| d.map(|d| (d, i)))
+            .collect();
+        let mut columns: BTreeMap<&str, Option<Vec<f64>>> = BTreeMap::new();
+        let mut alignment = Alignment::default();
+        for o in self.rows.iter() {
+            let values = columns
+                .entry(o.variable.as_str())
+                .or_insert_with(|| numeric_values(simulated, &o.variable));
+            let simulated = values
+                .as_ref()
+                .zip(rows.get(&o.date))
+                .map(|(v, i)| v[*i])
+                .filter(|v| !v.is_nan());
+            match simulated {
+                Some(simulated) => alignment.pairs.push(Aligned {
+                    date: o.date,
+                    variable: o.variable.clone(),
+                    observed: o.value,
+                    simulated,
+                    uncertainty: o.uncertainty,
+                }),
+                None => alignment.unmatched.push(o.clone()),
+            }
+        }
+        Ok(alignment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array, StructArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use chrono::NaiveDate;
+
+    use crate::observation::{DateIndex, Observations};
+
+    const CSV: &str = "date,variable,value,uncertainty
+1990-05-02,plant_leaf_area_index,0.25,0.05
+1990-05-03,plant_leaf_area_index,0.5,
+1990-05-03,plant_height,12,
+1990-07-01,plant_leaf_area_index,2.0,0.2
+";
+
+    #[test]
+    fn load_and_align() {
+        let obs = Observations::from_csv_reader(CSV.as_bytes()).unwrap();
+        assert_eq!(obs.rows.len(), 4);
+        assert_eq!(obs.rows[0].uncertainty, Some(0.05));
+        assert_eq!(obs.rows[1].uncertainty, None);
+        assert_eq!(obs.variable("plant_height").count(), 1);
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![122, 123, 124])),
+            Arc::new(Float32Array::from(vec![0.5, 0.75, 1.0])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let index = DateIndex::DayOfYear {
+            column: "day",
+            year: 1990,
+        };
+        let alignment = obs.align(&rb, index).unwrap();
+        assert_eq!(alignment.pairs.len(), 2);
+        assert_eq!(
+            alignment.pairs[0].date,
+            NaiveDate::from_ymd_opt(1990, 5, 2).unwrap()
+        );
+        assert_eq!(alignment.pairs[0].residual(), 0.25);
+        assert_eq!(alignment.pairs[1].simulated, 0.75);
+        assert_eq!(alignment.rmse("plant_leaf_area_index"), 0.25);
+        assert!(alignment.rmse("plant_height").is_nan());
+        let ll = -0.5 * (0.25f64 / 0.05).powi(2)
+            - 0.05f64.ln()
+            - 0.5 * (0.25f64 / 0.1).powi(2)
+            - 0.1f64.ln();
+        assert!((alignment.log_likelihood(0.1) - ll).abs() < 1e-9);
+        let unmatched: Vec<&str> = alignment
+            .unmatched
+            .iter()
+            .map(|o| o.variable.as_str())
+            .collect();
+        assert_eq!(unmatched, vec!["plant_height", "plant_leaf_area_index"]);
+
+        let table = obs.to_recordbatch().unwrap();
+        assert_eq!(table.num_rows(), 4);
+        let value = table
+            .column(2)
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap();
+        let sd = value.column(1);
+        assert_eq!(sd.null_count(), 2);
+
+        let bad = "date,variable,value\n1990-13-01,lai,1\n";
+        assert!(Observations::from_csv_reader(bad.as_bytes()).is_err());
+        assert!(obs.align(&rb, DateIndex::Date("day")).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! The output variables a trial needs
+//!
+//! A trial listing `outputs` has them passed to the model program in
+//! `MEILLIONEN_OUTPUTS`. Parsers and writers then leave out every other
+//! variable, which for a large sweep where only yield is looked at saves
+//! most of the output.
+
+use std::collections::BTreeSet;
+#[cfg(feature = "data")]
+use std::sync::Arc;
+
+#[cfg(feature = "data")]
+use arrow::datatypes::{DataType, Schema};
+#[cfg(feature = "data")]
+use arrow::error::ArrowError;
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+
+/// The environment variable holding the comma separated output variables
+pub const OUTPUTS_ENV: &str = "MEILLIONEN_OUTPUTS";
+
+/// Which output variables to keep, every one unless some are listed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OutputSelection {
+    variables: Option<BTreeSet<String>>,
+}
+
+impl OutputSelection {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn only<I, S>(variables: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            variables: Some(variables.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// The selection a model program was given, every variable if none was
+    pub fn from_env() -> Self {
+        match std::env::var(OUTPUTS_ENV) {
+            Ok(value) if !value.trim().is_empty() => Self::only(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string),
+            ),
+            _ => Self::all(),
+        }
+    }
+
+    /// The value of [`OUTPUTS_ENV`] for the selection, if it selects anything
+    pub fn env_value(&self) -> Option<String> {
+        self.variables
+            .as_ref()
+            .map(|vs| vs.iter().cloned().collect::<Vec<_>>().join(","))
+    }
+
+    pub fn wants(&self, variable: &str) -> bool {
+        self.variables
+            .as_ref()
+            .is_none_or(|vs| vs.contains(variable))
+    }
+
+    /// The columns of a batch to keep
+    ///
+    /// Columns that are not floating point, such as the day or a cell id,
+    /// index the values and are always kept.
+    #[cfg(feature = "data")]
+    pub fn keeps(&self, name: &str, data_type: &DataType) -> bool {
+        !matches!(
+            data_type,
+            DataType::Float16 | DataType::Float32 | DataType::Float64
+        ) || self.wants(name)
+    }
+
+    /// A batch with only the columns the selection keeps
+    #[cfg(feature = "data")]
+    pub fn select(&self, rb: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+        if self.variables.is_none() {
+            return Ok(rb.clone());
+        }
+        let schema = rb.schema();
+        let (fields, columns) = schema
+            .fields()
+            .iter()
+            .zip(rb.columns())
+            .filter(|(f, _)| self.keeps(f.name(), f.data_type()))
+            .map(|(f, c)| (f.clone(), c.clone()))
+            .unzip();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+    }
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::outputs::OutputSelection;
+
+    #[test]
+    fn select_columns() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("plant_matter_fruit", DataType::Float32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1])),
+            Arc::new(Float32Array::from(vec![2.0])),
+            Arc::new(Float32Array::from(vec![0.5])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+
+        let yield_only = OutputSelection::only(vec!["plant_matter_fruit"]);
+        let selected = yield_only.select(&rb).unwrap().schema();
+        let names: Vec<&str> = selected
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect();
+        assert_eq!(names, vec!["day", "plant_matter_fruit"]);
+        assert_eq!(OutputSelection::all().select(&rb).unwrap().num_columns(), 3);
+        assert_eq!(
+            yield_only.env_value(),
+            Some("plant_matter_fruit".to_string())
+        );
+        assert!(!yield_only.wants("soil_daily_runoff"));
+    }
+}
@@ -0,0 +1,240 @@
+use std::path::Path;
+
+use arrow::record_batch::RecordBatch;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use thiserror::Error;
+
+use crate::calibration::step_quantiles;
+use crate::timeseries::numeric_values;
+
+/// Size in pixels of the plots written
+pub const PLOT_SIZE: (u32, u32) = (800, 480);
+
+/// The quantile bands drawn in a fan chart, outermost first
+pub const FAN_BANDS: [(f64, f64); 2] = [(0.05, 0.95), (0.25, 0.75)];
+
+#[derive(Debug, Error)]
+pub enum PlotError {
+    #[error("plots must end in .png or .svg: {0}")]
+    UnknownFormat(String),
+    #[error("column {0} not found or not numeric")]
+    MissingColumn(String),
+    #[error("no values to plot")]
+    Empty,
+    #[error("could not draw plot: {0}")]
+    Draw(String),
+}
+
+impl<E: std::error::Error + Send + Sync> From<DrawingAreaErrorKind<E>> for PlotError {
+    fn from(e: DrawingAreaErrorKind<E>) -> Self {
+        PlotError::Draw(e.to_string())
+    }
+}
+
+fn bounds<'a>(values: impl Iterator<Item = &'a f64>) -> Result<(f64, f64), PlotError> {
+    let (lo, hi) = values
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(*v), hi.max(*v))
+        });
+    if lo > hi {
+        Err(PlotError::Empty)
+    } else if lo == hi {
+        Ok((lo - 0.5, hi + 0.5))
+    } else {
+        Ok((lo, hi))
+    }
+}
+
+/// A chart that can be drawn on any plotters backend
+trait Chart {
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), PlotError>
+    where
+        DB::ErrorType: 'static;
+
+    /// Draw to a png or svg file depending on the extension of `path`
+    fn save(&self, path: &Path) -> Result<(), PlotError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => {
+                let root = SVGBackend::new(path, PLOT_SIZE).into_drawing_area();
+                self.draw(&root)?;
+                root.present()?;
+            }
+            Some("png") => {
+                let root = BitMapBackend::new(path, PLOT_SIZE).into_drawing_area();
+                self.draw(&root)?;
+                root.present()?;
+            }
+            _ => return Err(PlotError::UnknownFormat(path.display().to_string())),
+        }
+        Ok(())
+    }
+}
+
+fn points(xs: &[f64], ys: &[f64]) -> Vec<(f64, f64)> {
+    xs.iter()
+        .zip(ys.iter())
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .map(|(x, y)| (*x, *y))
+        .collect()
+}
+
+struct TimeSeriesChart<'a> {
+    title: &'a str,
+    xs: Vec<f64>,
+    series: Vec<(&'a str, Vec<f64>)>,
+}
+
+impl<'a> Chart for TimeSeriesChart<'a> {
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), PlotError>
+    where
+        DB::ErrorType: 'static,
+    {
+        let (title, xs, series) = (self.title, &self.xs, &self.series);
+        let x = bounds(xs.iter())?;
+        let y = bounds(series.iter().flat_map(|(_, ys)| ys.iter()))?;
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption(title, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(x.0..x.1, y.0..y.1)?;
+        chart.configure_mesh().draw()?;
+        for (i, (name, ys)) in series.iter().enumerate() {
+            let color = Palette99::pick(i).to_rgba();
+            chart
+                .draw_series(LineSeries::new(points(xs, ys), &color))?
+                .label(*name)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+        Ok(())
+    }
+}
+
+/// Plot columns of a record batch, such as the plant or soil output of
+/// SimpleCrop, against the column `x`
+///
+/// The file is a png or svg depending on the extension of `path`.
+pub fn plot_timeseries(
+    rb: &RecordBatch,
+    x: &str,
+    columns: &[&str],
+    title: &str,
+    path: &Path,
+) -> Result<(), PlotError> {
+    let column = |name: &str| {
+        numeric_values(rb, name).ok_or_else(|| PlotError::MissingColumn(name.to_string()))
+    };
+    let xs = column(x)?;
+    let series = columns
+        .iter()
+        .map(|c| Ok((*c, column(c)?)))
+        .collect::<Result<Vec<_>, PlotError>>()?;
+    TimeSeriesChart { title, xs, series }.save(path)
+}
+
+struct FanChart<'a> {
+    title: &'a str,
+    xs: &'a [f64],
+    members: &'a [Vec<f64>],
+}
+
+impl<'a> Chart for FanChart<'a> {
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), PlotError>
+    where
+        DB::ErrorType: 'static,
+    {
+        let (title, xs, members) = (self.title, self.xs, self.members);
+        let x = bounds(xs.iter())?;
+        let y = bounds(members.iter().flat_map(|m| m.iter()))?;
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption(title, ("sans-serif", 20))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(x.0..x.1, y.0..y.1)?;
+        chart.configure_mesh().draw()?;
+        for (i, (lower, upper)) in FAN_BANDS.iter().enumerate() {
+            let lo = points(xs, &step_quantiles(members, xs.len(), *lower));
+            let hi = points(xs, &step_quantiles(members, xs.len(), *upper));
+            let band: Vec<(f64, f64)> = lo.into_iter().chain(hi.into_iter().rev()).collect();
+            let alpha = 0.2 * (i + 1) as f64;
+            chart.draw_series(std::iter::once(Polygon::new(band, BLUE.mix(alpha))))?;
+        }
+        chart.draw_series(LineSeries::new(
+            points(xs, &step_quantiles(members, xs.len(), 0.5)),
+            BLUE.stroke_width(2),
+        ))?;
+        Ok(())
+    }
+}
+
+/// Plot the spread of an ensemble over time
+///
+/// Each member is a series of values at `xs`. The median is drawn as a line
+/// with shaded [`FAN_BANDS`] around it.
+pub fn plot_fan(
+    xs: &[f64],
+    members: &[Vec<f64>],
+    title: &str,
+    path: &Path,
+) -> Result<(), PlotError> {
+    if members.is_empty() {
+        return Err(PlotError::Empty);
+    }
+    FanChart { title, xs, members }.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::plot::{plot_fan, plot_timeseries};
+
+    #[test]
+    fn writes_svg_and_png() {
+        let dir = std::env::temp_dir().join(format!("meillionen-plot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![121, 122, 123])),
+            Arc::new(Float32Array::from(vec![0.1, 0.3, 0.4])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let svg = dir.join("lai.svg");
+        plot_timeseries(&rb, "day", &["plant_leaf_area_index"], "lai", &svg).unwrap();
+        let text = std::fs::read_to_string(&svg).unwrap();
+        let missing = plot_timeseries(&rb, "day", &["yield"], "yield", &svg);
+
+        let members = vec![
+            vec![1.0, 2.0, 3.0],
+            vec![2.0, 3.0, 5.0],
+            vec![3.0, 4.0, 4.0],
+        ];
+        let png = dir.join("fan.png");
+        plot_fan(&[1.0, 2.0, 3.0], &members, "yield", &png).unwrap();
+        let header = std::fs::read(&png).unwrap()[..4].to_vec();
+        let unknown = plot_fan(&[1.0], &members, "yield", &dir.join("fan.gif"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(text.contains("<svg") && text.contains("plant_leaf_area_index"));
+        assert!(missing.is_err());
+        assert_eq!(header, b"\x89PNG");
+        assert!(unknown.is_err());
+    }
+}
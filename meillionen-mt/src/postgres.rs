@@ -0,0 +1,565 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use arrow::array::{Float32Array, Float64Array, Int32Array, Int64Array};
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use native_tls::{Certificate, TlsConnector};
+use postgres::{Client, Transaction};
+use postgres_native_tls::MakeTlsConnector;
+use thiserror::Error;
+
+use crate::experiment::{read_batches, ExperimentError, ResourceConfig, TrialConfig};
+use crate::surrogate::RunRecord;
+use crate::tags::{TagFilter, Tags};
+
+#[derive(Debug, Error)]
+pub enum PostgresError {
+    #[error("column {0} not found")]
+    MissingColumn(String),
+    #[error("step column {0} must be an integer column but is {1:?}")]
+    NotStep(String, DataType),
+    #[error(transparent)]
+    Postgres(#[from] postgres::Error),
+    #[error(transparent)]
+    Tls(#[from] native_tls::Error),
+    #[error("could not read certificate {path}: {source}")]
+    Certificate {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+}
+
+const CREATE_TABLES: &str = "
+CREATE TABLE IF NOT EXISTS meillionen_runs (
+    id BIGSERIAL PRIMARY KEY,
+    model TEXT NOT NULL,
+    started TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+ALTER TABLE meillionen_runs ADD COLUMN IF NOT EXISTS trial TEXT;
+ALTER TABLE meillionen_runs ADD COLUMN IF NOT EXISTS experiment TEXT;
+ALTER TABLE meillionen_runs ADD COLUMN IF NOT EXISTS args TEXT[] NOT NULL DEFAULT '{}';
+CREATE TABLE IF NOT EXISTS meillionen_env (
+    run_id BIGINT NOT NULL REFERENCES meillionen_runs (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (run_id, name)
+);
+CREATE TABLE IF NOT EXISTS meillionen_parameters (
+    run_id BIGINT NOT NULL REFERENCES meillionen_runs (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    PRIMARY KEY (run_id, name)
+);
+CREATE TABLE IF NOT EXISTS meillionen_tags (
+    run_id BIGINT NOT NULL REFERENCES meillionen_runs (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (run_id, key)
+);
+CREATE INDEX IF NOT EXISTS meillionen_tags_key ON meillionen_tags (key, value);
+CREATE TABLE IF NOT EXISTS meillionen_outputs (
+    run_id BIGINT NOT NULL REFERENCES meillionen_runs (id) ON DELETE CASCADE,
+    step BIGINT NOT NULL,
+    variable TEXT NOT NULL,
+    value DOUBLE PRECISION
+);
+CREATE INDEX IF NOT EXISTS meillionen_outputs_run ON meillionen_outputs (run_id, variable);
+CREATE TABLE IF NOT EXISTS meillionen_metrics (
+    run_id BIGINT NOT NULL REFERENCES meillionen_runs (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value DOUBLE PRECISION,
+    PRIMARY KEY (run_id, name)
+);
+";
+
+/// One output value of a run at a time step
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputRow {
+    pub step: i64,
+    pub variable: String,
+    pub value: Option<f64>,
+}
+
+fn step_values(rb: &RecordBatch, step: &str) -> Result<Vec<i64>, PostgresError> {
+    let index = rb
+        .schema()
+        .index_of(step)
+        .map_err(|_| PostgresError::MissingColumn(step.to_string()))?;
+    let column = rb.column(index);
+    let any = column.as_any();
+    match column.data_type() {
+        DataType::Int32 => Ok(any
+            .downcast_ref::<Int32Array>()
+            .expect("array type to match data type")
+            .values()
+            .iter()
+            .map(|&v| i64::from(v))
+            .collect()),
+        DataType::Int64 => Ok(any
+            .downcast_ref::<Int64Array>()
+            .expect("array type to match data type")
+            .values()
+            .to_vec()),
+        dt => Err(PostgresError::NotStep(step.to_string(), dt.clone())),
+    }
+}
+
+/// Unpivot the floating point columns of a batch into long format
+///
+/// `step` names the integer column (such as the day of year) that indexes
+/// the rows. Null and NaN values are stored as SQL nulls.
+pub fn output_rows(rb: &RecordBatch, step: &str) -> Result<Vec<OutputRow>, PostgresError> {
+    let steps = step_values(rb, step)?;
+    let schema = rb.schema();
+    let mut rows = vec![];
+    for (field, column) in schema.fields().iter().zip(rb.columns()) {
+        match field.data_type() {
+            DataType::Float32 | DataType::Float64 => {}
+            _ => continue,
+        }
+        let value = |i: usize| -> Option<f64> {
+            if column.is_null(i) {
+                return None;
+            }
+            let v = match column.data_type() {
+                DataType::Float32 => f64::from(
+                    column
+                        .as_any()
+                        .downcast_ref::<Float32Array>()
+                        .expect("array type to match data type")
+                        .value(i),
+                ),
+                DataType::Float64 => column
+                    .as_any()
+                    .downcast_ref::<Float64Array>()
+                    .expect("array type to match data type")
+                    .value(i),
+                _ => unreachable!("only float columns are unpivoted"),
+            };
+            Some(v).filter(|v| !v.is_nan())
+        };
+        rows.extend(steps.iter().enumerate().map(|(i, &s)| OutputRow {
+            step: s,
+            variable: field.name().clone(),
+            value: value(i),
+        }));
+    }
+    Ok(rows)
+}
+
+fn is_table(resource: &ResourceConfig) -> bool {
+    matches!(
+        resource,
+        ResourceConfig::Feather(_) | ResourceConfig::Parquet(_)
+    )
+}
+
+/// The numeric columns of a trial's single row table sources
+pub fn trial_parameters(trial: &TrialConfig) -> Result<BTreeMap<String, f64>, PostgresError> {
+    let mut parameters = BTreeMap::new();
+    for resource in trial.sources.values().filter(|r| is_table(r)) {
+        let batches = read_batches(resource)?;
+        if batches.iter().map(|b| b.num_rows()).sum::<usize>() != 1 {
+            continue;
+        }
+        let batch = batches
+            .iter()
+            .find(|b| b.num_rows() == 1)
+            .expect("a batch to have the row");
+        for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+            if column.is_null(0) {
+                continue;
+            }
+            let any = column.as_any();
+            let value = match column.data_type() {
+                DataType::Float32 => f64::from(
+                    any.downcast_ref::<Float32Array>()
+                        .expect("array type to match data type")
+                        .value(0),
+                ),
+                DataType::Float64 => any
+                    .downcast_ref::<Float64Array>()
+                    .expect("array type to match data type")
+                    .value(0),
+                DataType::Int32 => f64::from(
+                    any.downcast_ref::<Int32Array>()
+                        .expect("array type to match data type")
+                        .value(0),
+                ),
+                DataType::Int64 => any
+                    .downcast_ref::<Int64Array>()
+                    .expect("array type to match data type")
+                    .value(0) as f64,
+                _ => continue,
+            };
+            parameters.insert(field.name().clone(), value);
+        }
+    }
+    Ok(parameters)
+}
+
+/// `trial` is the names of the experiment and the trial for runs of
+/// experiments
+fn insert_run(
+    tx: &mut Transaction,
+    model: &str,
+    trial: Option<(&str, &str)>,
+    args: &[String],
+    env: &BTreeMap<String, String>,
+    parameters: &BTreeMap<String, f64>,
+    tags: &Tags,
+) -> Result<i64, PostgresError> {
+    let (experiment, trial) = trial.unzip();
+    let run_id: i64 = tx
+        .query_one(
+            "INSERT INTO meillionen_runs (model, experiment, trial, args) \
+             VALUES ($1, $2, $3, $4) RETURNING id",
+            &[&model, &experiment, &trial, &args],
+        )?
+        .get(0);
+    let insert =
+        tx.prepare("INSERT INTO meillionen_env (run_id, name, value) VALUES ($1, $2, $3)")?;
+    for (name, value) in env.iter() {
+        tx.execute(&insert, &[&run_id, name, value])?;
+    }
+    let insert =
+        tx.prepare("INSERT INTO meillionen_parameters (run_id, name, value) VALUES ($1, $2, $3)")?;
+    for (name, value) in parameters.iter() {
+        tx.execute(&insert, &[&run_id, name, value])?;
+    }
+    let insert =
+        tx.prepare("INSERT INTO meillionen_tags (run_id, key, value) VALUES ($1, $2, $3)")?;
+    for (key, value) in tags.iter() {
+        tx.execute(&insert, &[&run_id, key, value])?;
+    }
+    Ok(run_id)
+}
+
+fn insert_outputs(
+    tx: &mut Transaction,
+    run_id: i64,
+    rb: &RecordBatch,
+    step: &str,
+) -> Result<usize, PostgresError> {
+    let rows = output_rows(rb, step)?;
+    let insert = tx.prepare(
+        "INSERT INTO meillionen_outputs (run_id, step, variable, value) VALUES ($1, $2, $3, $4)",
+    )?;
+    for row in rows.iter() {
+        tx.execute(&insert, &[&run_id, &row.step, &row.variable, &row.value])?;
+    }
+    Ok(rows.len())
+}
+
+/// Stores runs, their parameters and their outputs in a PostgreSQL database
+///
+/// Outputs are kept in long format (run, step, variable, value) so results
+/// from models with different outputs share one table that BI tools can
+/// pivot.
+pub struct PostgresSink {
+    client: Client,
+}
+
+impl PostgresSink {
+    /// Connect with a libpq style connection string and create the tables
+    ///
+    /// Servers are checked against the system's root certificates. Whether
+    /// TLS is used follows the `sslmode` of the connection string: `prefer`
+    /// by default, `require` to refuse plain connections or `disable`.
+    pub fn connect(params: &str) -> Result<Self, PostgresError> {
+        Self::connect_with_root_certificate(params, None)
+    }
+
+    /// Connect like [`PostgresSink::connect`], also trusting the PEM
+    /// certificate at `root_certificate`, such as the CA of a private server
+    pub fn connect_with_root_certificate(
+        params: &str,
+        root_certificate: Option<&Path>,
+    ) -> Result<Self, PostgresError> {
+        let mut builder = TlsConnector::builder();
+        if let Some(path) = root_certificate {
+            let pem = std::fs::read(path).map_err(|source| PostgresError::Certificate {
+                path: path.display().to_string(),
+                source,
+            })?;
+            builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+        let tls = MakeTlsConnector::new(builder.build()?);
+        let mut client = Client::connect(params, tls)?;
+        client.batch_execute(CREATE_TABLES)?;
+        Ok(Self { client })
+    }
+
+    /// Record a finished trial of an experiment as a run with its name,
+    /// arguments, environment, parameters and tags and the outputs of its
+    /// feather and parquet sinks, returning the run id
+    ///
+    /// The parameters are the numeric columns of the trial's single row
+    /// feather and parquet sources, such as yearly parameters, keyed by
+    /// column name. Sinks without a `step` column, such as yearly summaries
+    /// indexed by something else, are left out. Nothing is stored if any
+    /// part of the trial can't be.
+    pub fn record_trial(
+        &mut self,
+        experiment: &str,
+        trial: &TrialConfig,
+        tags: &Tags,
+        step: &str,
+    ) -> Result<i64, PostgresError> {
+        let parameters = trial_parameters(trial)?;
+        let mut tx = self.client.transaction()?;
+        let run_id = insert_run(
+            &mut tx,
+            &trial.model,
+            Some((experiment, &trial.name)),
+            &trial.args,
+            &trial.env,
+            &parameters,
+            tags,
+        )?;
+        for (name, resource) in trial.sinks.iter() {
+            if !is_table(resource) {
+                continue;
+            }
+            for batch in read_batches(resource)? {
+                match insert_outputs(&mut tx, run_id, &batch, step) {
+                    Err(PostgresError::MissingColumn(_)) => {
+                        tracing::warn!(
+                            "sink {} of {} has no {} column and is not stored",
+                            name,
+                            trial.name,
+                            step
+                        );
+                        break;
+                    }
+                    result => {
+                        result?;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(run_id)
+    }
+
+    /// Delete the runs recorded for trials of an experiment with their
+    /// parameters, outputs and metrics, such as after `meillionen gc` prunes
+    /// the trials, returning how many runs were deleted
+    pub fn delete_trials(
+        &mut self,
+        experiment: &str,
+        trials: &[String],
+    ) -> Result<u64, PostgresError> {
+        Ok(self.client.execute(
+            "DELETE FROM meillionen_runs WHERE experiment = $1 AND trial = ANY($2)",
+            &[&experiment, &trials],
+        )?)
+    }
+
+    /// Record a new run with its parameters and tags, returning the run id
+    pub fn start_run(
+        &mut self,
+        model: &str,
+        parameters: &BTreeMap<String, f64>,
+        tags: &Tags,
+    ) -> Result<i64, PostgresError> {
+        let mut tx = self.client.transaction()?;
+        let run_id = insert_run(
+            &mut tx,
+            model,
+            None,
+            &[],
+            &BTreeMap::new(),
+            parameters,
+            tags,
+        )?;
+        tx.commit()?;
+        Ok(run_id)
+    }
+
+    /// Append the floating point columns of an output batch to a run
+    pub fn write_outputs(
+        &mut self,
+        run_id: i64,
+        rb: &RecordBatch,
+        step: &str,
+    ) -> Result<usize, PostgresError> {
+        let mut tx = self.client.transaction()?;
+        let n = insert_outputs(&mut tx, run_id, rb, step)?;
+        tx.commit()?;
+        Ok(n)
+    }
+
+    /// Record the metrics of a run, such as from
+    /// [`crate::experiment::RunOutcome::metrics`], replacing any of the same
+    /// name
+    ///
+    /// NaN values are stored as SQL nulls.
+    pub fn write_metrics(
+        &mut self,
+        run_id: i64,
+        metrics: &BTreeMap<String, f64>,
+    ) -> Result<usize, PostgresError> {
+        let mut tx = self.client.transaction()?;
+        let upsert = tx.prepare(
+            "INSERT INTO meillionen_metrics (run_id, name, value) VALUES ($1, $2, $3) \
+             ON CONFLICT (run_id, name) DO UPDATE SET value = EXCLUDED.value",
+        )?;
+        for (name, value) in metrics.iter() {
+            let value = Some(*value).filter(|v| !v.is_nan());
+            tx.execute(&upsert, &[&run_id, name, &value])?;
+        }
+        tx.commit()?;
+        Ok(metrics.len())
+    }
+
+    /// Read back the runs of a model whose tags match every filter, with their
+    /// parameters, outputs and metrics, such as to assemble a
+    /// [`crate::surrogate::TrainingSet`]
+    ///
+    /// Null outputs are read as NaN.
+    pub fn load_runs(
+        &mut self,
+        model: &str,
+        filters: &[TagFilter],
+    ) -> Result<Vec<RunRecord>, PostgresError> {
+        let mut runs: BTreeMap<i64, RunRecord> = BTreeMap::new();
+        for row in self
+            .client
+            .query("SELECT id FROM meillionen_runs WHERE model = $1", &[&model])?
+        {
+            let id: i64 = row.get(0);
+            runs.insert(
+                id,
+                RunRecord {
+                    run: id.to_string(),
+                    ..RunRecord::default()
+                },
+            );
+        }
+        for row in self.client.query(
+            "SELECT t.run_id, t.key, t.value FROM meillionen_tags t \
+             JOIN meillionen_runs r ON r.id = t.run_id WHERE r.model = $1",
+            &[&model],
+        )? {
+            if let Some(run) = runs.get_mut(&row.get::<_, i64>(0)) {
+                run.tags.insert(row.get(1), row.get(2));
+            }
+        }
+        runs.retain(|_, run| TagFilter::all(filters, &run.tags));
+        for row in self.client.query(
+            "SELECT p.run_id, p.name, p.value FROM meillionen_parameters p \
+             JOIN meillionen_runs r ON r.id = p.run_id WHERE r.model = $1",
+            &[&model],
+        )? {
+            if let Some(run) = runs.get_mut(&row.get::<_, i64>(0)) {
+                run.parameters.insert(row.get(1), row.get(2));
+            }
+        }
+        for row in self.client.query(
+            "SELECT o.run_id, o.variable, o.value FROM meillionen_outputs o \
+             JOIN meillionen_runs r ON r.id = o.run_id WHERE r.model = $1 \
+             ORDER BY o.run_id, o.variable, o.step",
+            &[&model],
+        )? {
+            if let Some(run) = runs.get_mut(&row.get::<_, i64>(0)) {
+                let value: Option<f64> = row.get(2);
+                run.outputs
+                    .entry(row.get(1))
+                    .or_insert_with(Vec::new)
+                    .push(value.unwrap_or(f64::NAN));
+            }
+        }
+        for row in self.client.query(
+            "SELECT m.run_id, m.name, m.value FROM meillionen_metrics m \
+             JOIN meillionen_runs r ON r.id = m.run_id WHERE r.model = $1",
+            &[&model],
+        )? {
+            if let Some(run) = runs.get_mut(&row.get::<_, i64>(0)) {
+                let value: Option<f64> = row.get(2);
+                run.metrics.insert(row.get(1), value.unwrap_or(f64::NAN));
+            }
+        }
+        Ok(runs.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::ipc::writer::FileWriter;
+    use arrow::record_batch::RecordBatch;
+
+    use crate::experiment::TrialConfig;
+    use crate::postgres::{output_rows, trial_parameters, OutputRow, PostgresError};
+
+    #[test]
+    fn unpivot() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![3, 4])),
+            Arc::new(Float32Array::from(vec![0.5, f32::NAN])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let row = |step, value| OutputRow {
+            step,
+            variable: "plant_leaf_area_index".to_string(),
+            value,
+        };
+        assert_eq!(
+            output_rows(&rb, "day").unwrap(),
+            vec![row(3, Some(0.5)), row(4, None)]
+        );
+        assert!(matches!(
+            output_rows(&rb, "plant_leaf_area_index"),
+            Err(PostgresError::NotStep(_, DataType::Float32))
+        ));
+    }
+
+    #[test]
+    fn parameters_of_single_row_sources() {
+        let dir = std::env::temp_dir().join(format!("meillionen-pg-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, rows: Vec<i32>| {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("day_of_planting", DataType::Int32, false),
+                Field::new("site", DataType::Utf8, false),
+            ]));
+            let sites = vec!["a"; rows.len()];
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(Int32Array::from(rows)),
+                Arc::new(arrow::array::StringArray::from(sites)),
+            ];
+            let rb = RecordBatch::try_new(schema.clone(), columns).unwrap();
+            let path = dir.join(name);
+            let mut writer = FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+            writer.write(&rb).unwrap();
+            writer.finish().unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let trial: TrialConfig = serde_json::from_value(serde_json::json!({
+            "name": "baseline",
+            "model": "simplecrop_omf",
+            "sources": {
+                "yearly": {"type": "feather", "path": write("yearly.feather", vec![121])},
+                "daily": {"type": "feather", "path": write("daily.feather", vec![1, 2])}
+            }
+        }))
+        .unwrap();
+        let parameters = trial_parameters(&trial).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(parameters.len(), 1);
+        assert_eq!(parameters["day_of_planting"], 121.0);
+    }
+}
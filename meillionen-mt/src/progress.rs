@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+/// Lines of a failed member's stderr kept for display
+pub const STDERR_SNIPPET_LINES: usize = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MemberState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed { stderr: String },
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub name: String,
+    pub state: MemberState,
+    pub elapsed: Option<Duration>,
+    started: Option<Instant>,
+}
+
+/// The last few non-empty lines of a program's stderr
+///
+/// The end of stderr usually holds the error that stopped the program.
+pub fn stderr_snippet(stderr: &str, lines: usize) -> String {
+    let kept: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    kept[kept.len().saturating_sub(lines)..].join("\n")
+}
+
+/// The progress of the members of an ensemble being run
+#[derive(Clone, Debug)]
+pub struct EnsembleProgress {
+    pub members: Vec<Member>,
+    started: Instant,
+}
+
+impl EnsembleProgress {
+    pub fn new<S: AsRef<str>>(names: &[S]) -> Self {
+        Self {
+            members: names
+                .iter()
+                .map(|n| Member {
+                    name: n.as_ref().to_string(),
+                    state: MemberState::Pending,
+                    elapsed: None,
+                    started: None,
+                })
+                .collect(),
+            started: Instant::now(),
+        }
+    }
+
+    fn member_mut(&mut self, name: &str) -> Option<&mut Member> {
+        self.members.iter_mut().find(|m| m.name == name)
+    }
+
+    pub fn start(&mut self, name: &str) {
+        if let Some(m) = self.member_mut(name) {
+            m.state = MemberState::Running;
+            m.started = Some(Instant::now());
+        }
+    }
+
+    pub fn finish(&mut self, name: &str, result: Result<(), String>) {
+        if let Some(m) = self.member_mut(name) {
+            m.elapsed = m.started.map(|s| s.elapsed());
+            m.state = match result {
+                Ok(()) => MemberState::Succeeded,
+                Err(stderr) => MemberState::Failed {
+                    stderr: stderr_snippet(&stderr, STDERR_SNIPPET_LINES),
+                },
+            };
+        }
+    }
+
+    pub fn count(&self, f: impl Fn(&MemberState) -> bool) -> usize {
+        self.members.iter().filter(|m| f(&m.state)).count()
+    }
+
+    pub fn finished(&self) -> usize {
+        self.count(|s| matches!(s, MemberState::Succeeded | MemberState::Failed { .. }))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, MemberState::Failed { .. }))
+    }
+
+    /// Finished members per minute since the ensemble started
+    pub fn throughput(&self) -> f64 {
+        let minutes = self.started.elapsed().as_secs_f64() / 60.0;
+        if minutes > 0.0 {
+            self.finished() as f64 / minutes
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{stderr_snippet, EnsembleProgress, MemberState};
+
+    #[test]
+    fn member_states() {
+        let mut p = EnsembleProgress::new(&["a", "b", "c"]);
+        p.start("a");
+        assert_eq!(p.members[0].state, MemberState::Running);
+        p.finish("a", Ok(()));
+        p.start("b");
+        p.finish(
+            "b",
+            Err("reading weather\n\nline 3: bad value\nexit 1\n".to_string()),
+        );
+        assert_eq!((p.finished(), p.failed()), (2, 1));
+        assert_eq!(
+            p.members[1].state,
+            MemberState::Failed {
+                stderr: "reading weather\nline 3: bad value\nexit 1".to_string()
+            }
+        );
+        assert!(p.members[0].elapsed.is_some());
+        assert_eq!(stderr_snippet("a\nb\nc", 2), "b\nc");
+    }
+}
@@ -0,0 +1,470 @@
+//! Provenance of an experiment run as W3C PROV-JSON or an RO-Crate
+//!
+//! Each trial of a [`RunManifest`] becomes an activity associated with its
+//! model program, using the source files and generating the sink files it
+//! recorded checksums for. Trials coupled through a file, where one trial
+//! reads what another wrote, are linked with `wasInformedBy` in PROV and
+//! appear as the `result` of one action and the `object` of the next in an
+//! RO-Crate.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+use crate::experiment::{ExperimentError, TrialStatus};
+use crate::manifest::{RunManifest, TrialManifest};
+
+/// Terms of our own in PROV documents
+pub const NAMESPACE: &str = "https://github.com/cpritcha/meillionen/ns#";
+
+const RO_CRATE_CONTEXT: &str = "https://w3id.org/ro/crate/1.1/context";
+const RO_CRATE_METADATA: &str = "ro-crate-metadata.json";
+
+#[derive(Debug, Error)]
+pub enum ProvenanceError {
+    #[error("{0} has no run manifest, run the experiment first")]
+    NoManifest(PathBuf),
+    #[error("{0} already exists")]
+    Exists(PathBuf),
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A unix time as an ISO 8601 date time in UTC
+fn iso_time(t: u64) -> String {
+    DateTime::from_timestamp(t as i64, 0)
+        .expect("unix times to be in range")
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Pairs of trials where the second read a file the first wrote with the
+/// same checksum
+pub fn couplings(manifest: &RunManifest) -> BTreeSet<(String, String)> {
+    let mut writers: BTreeMap<(&str, &str), Vec<&str>> = BTreeMap::new();
+    for (name, trial) in manifest.trials.iter() {
+        for (path, hash) in trial.outputs.iter() {
+            writers
+                .entry((path.as_str(), hash.as_str()))
+                .or_default()
+                .push(name);
+        }
+    }
+    let mut pairs = BTreeSet::new();
+    for (name, trial) in manifest.trials.iter() {
+        for (path, hash) in trial.inputs.iter() {
+            for writer in writers
+                .get(&(path.as_str(), hash.as_str()))
+                .into_iter()
+                .flatten()
+            {
+                if *writer != name {
+                    pairs.insert((writer.to_string(), name.clone()));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+fn status_attributes(trial: &TrialManifest, attributes: &mut Map<String, Value>) {
+    let (status, message) = match &trial.status {
+        TrialStatus::Pending => ("pending", None),
+        TrialStatus::Succeeded => ("succeeded", None),
+        TrialStatus::Failed { message } => ("failed", Some(message)),
+    };
+    attributes.insert("meillionen:status".to_string(), json!(status));
+    if let Some(message) = message {
+        attributes.insert("meillionen:message".to_string(), json!(message));
+    }
+}
+
+/// The run as a PROV-JSON document
+pub fn prov_json(manifest: &RunManifest) -> Value {
+    let mut entities = Map::new();
+    let mut activities = Map::new();
+    let mut agents = Map::new();
+    let mut used = Map::new();
+    let mut generated = Map::new();
+    let mut associated = Map::new();
+    let mut informed = Map::new();
+
+    // a file rewritten during the run is a new entity each time
+    let mut file = |path: &str, hash: &str| {
+        let id = format!("file:{}@{}", path, &hash[..hash.len().min(12)]);
+        entities.insert(
+            id.clone(),
+            json!({"prov:location": path, "meillionen:sha256": hash}),
+        );
+        id
+    };
+    for (name, trial) in manifest.trials.iter() {
+        let activity = format!("trial:{}", name);
+        let mut attributes = Map::new();
+        attributes.insert("prov:startTime".to_string(), json!(iso_time(trial.started)));
+        attributes.insert("prov:endTime".to_string(), json!(iso_time(trial.finished)));
+        attributes.insert("prov:label".to_string(), json!(name));
+        status_attributes(trial, &mut attributes);
+        if let Some(seed) = trial.seed {
+            attributes.insert("meillionen:seed".to_string(), json!(seed));
+        }
+        if !trial.args.is_empty() {
+            attributes.insert("meillionen:args".to_string(), json!(trial.args.join(" ")));
+        }
+        for (key, value) in trial.tags.iter() {
+            attributes.insert(format!("meillionen:tag_{}", key), json!(value));
+        }
+        activities.insert(activity.clone(), Value::Object(attributes));
+
+        let agent = format!("model:{}", trial.model);
+        let mut model = json!({"prov:type": "prov:SoftwareAgent", "prov:label": trial.model});
+        if let Some(hash) = &trial.model_sha256 {
+            model["meillionen:sha256"] = json!(hash);
+        }
+        agents.insert(agent.clone(), model);
+        associated.insert(
+            format!("_:assoc_{}", name),
+            json!({"prov:activity": activity, "prov:agent": agent}),
+        );
+
+        for (path, hash) in trial.inputs.iter() {
+            used.insert(
+                format!("_:used_{}_{}", name, path),
+                json!({"prov:activity": activity, "prov:entity": file(path, hash)}),
+            );
+        }
+        for (path, hash) in trial.outputs.iter() {
+            generated.insert(
+                format!("_:gen_{}_{}", name, path),
+                json!({"prov:entity": file(path, hash), "prov:activity": activity,
+                       "prov:time": iso_time(trial.finished)}),
+            );
+        }
+    }
+    for (writer, reader) in couplings(manifest) {
+        informed.insert(
+            format!("_:informed_{}_{}", reader, writer),
+            json!({"prov:informed": format!("trial:{}", reader),
+                   "prov:informant": format!("trial:{}", writer)}),
+        );
+    }
+
+    entities.insert(
+        format!("experiment:{}", manifest.experiment),
+        json!({"prov:type": "prov:Bundle", "prov:label": manifest.experiment,
+               "meillionen:created": iso_time(manifest.created)}),
+    );
+    json!({
+        "prefix": {
+            "meillionen": NAMESPACE,
+            "experiment": "urn:meillionen:experiment:",
+            "trial": format!("urn:meillionen:{}:trial:", manifest.experiment),
+            "model": "urn:meillionen:model:",
+            "file": "urn:meillionen:file:",
+        },
+        "entity": entities,
+        "activity": activities,
+        "agent": agents,
+        "used": used,
+        "wasGeneratedBy": generated,
+        "wasAssociatedWith": associated,
+        "wasInformedBy": informed,
+    })
+}
+
+/// Where a file of the run is kept inside a crate
+///
+/// Relative paths are kept and absolute ones are put under `external/`.
+pub fn crate_path(path: &str) -> String {
+    let parts: Vec<String> = Path::new(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(p) => Some(p.to_string_lossy().into_owned()),
+            Component::ParentDir => Some("parent".to_string()),
+            _ => None,
+        })
+        .collect();
+    if Path::new(path).is_absolute() {
+        format!("external/{}", parts.join("/"))
+    } else {
+        parts.join("/")
+    }
+}
+
+/// The `ro-crate-metadata.json` describing the run
+///
+/// `experiment` and `manifest_file` are the crate paths of the experiment and
+/// manifest files and `files` the crate paths of the files actually in the
+/// crate, so files that could not be copied are still described but not
+/// listed as parts of it.
+pub fn ro_crate_metadata(
+    manifest: &RunManifest,
+    experiment: &str,
+    manifest_file: &str,
+    files: &BTreeSet<String>,
+) -> Value {
+    let mut graph = vec![
+        json!({
+            "@id": RO_CRATE_METADATA,
+            "@type": "CreativeWork",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"},
+            "about": {"@id": "./"},
+        }),
+        json!({"@id": experiment, "@type": "File", "name": "experiment file"}),
+        json!({"@id": manifest_file, "@type": "File", "name": "run manifest"}),
+    ];
+    let mut parts = vec![json!({"@id": experiment}), json!({"@id": manifest_file})];
+    let mut described = BTreeSet::new();
+    let mut models = BTreeMap::new();
+    let mut actions = vec![];
+    for (name, trial) in manifest.trials.iter() {
+        let mut refs = |paths: &BTreeMap<String, String>| -> Vec<Value> {
+            let mut refs = vec![];
+            for (path, hash) in paths.iter() {
+                let id = crate_path(path);
+                if described.insert(id.clone()) {
+                    graph.push(json!({
+                        "@id": id, "@type": "File", "name": path, "sha256": hash,
+                    }));
+                    if files.contains(&id) {
+                        parts.push(json!({"@id": id}));
+                    }
+                }
+                refs.push(json!({"@id": id}));
+            }
+            refs
+        };
+        let object = refs(&trial.inputs);
+        let result = refs(&trial.outputs);
+        let model_id = format!("#model-{}", trial.model);
+        models.entry(model_id.clone()).or_insert_with(|| {
+            let mut model = json!({
+                "@id": model_id, "@type": "SoftwareApplication", "name": trial.model,
+            });
+            if let Some(hash) = &trial.model_sha256 {
+                model["sha256"] = json!(hash);
+            }
+            model
+        });
+        let mut action = json!({
+            "@id": format!("#trial-{}", name),
+            "@type": "CreateAction",
+            "name": name,
+            "instrument": {"@id": model_id},
+            "object": object,
+            "result": result,
+            "startTime": iso_time(trial.started),
+            "endTime": iso_time(trial.finished),
+        });
+        match &trial.status {
+            TrialStatus::Succeeded => {
+                action["actionStatus"] = json!({"@id": "http://schema.org/CompletedActionStatus"})
+            }
+            TrialStatus::Failed { message } => {
+                action["actionStatus"] = json!({"@id": "http://schema.org/FailedActionStatus"});
+                action["error"] = json!(message);
+            }
+            TrialStatus::Pending => {
+                action["actionStatus"] = json!({"@id": "http://schema.org/ActiveActionStatus"})
+            }
+        }
+        actions.push(action);
+    }
+    let mentions: Vec<Value> = actions.iter().map(|a| json!({"@id": a["@id"]})).collect();
+    graph.insert(
+        1,
+        json!({
+            "@id": "./",
+            "@type": "Dataset",
+            "name": manifest.experiment,
+            "datePublished": iso_time(manifest.created),
+            "hasPart": parts,
+            "mentions": mentions,
+        }),
+    );
+    graph.extend(models.into_values());
+    graph.extend(actions);
+    json!({"@context": RO_CRATE_CONTEXT, "@graph": graph})
+}
+
+/// Copy an experiment file, its manifest and the files of its run into a new
+/// directory `output` described by an `ro-crate-metadata.json`
+///
+/// Relative paths in the manifest are resolved against the working directory
+/// as when the experiment ran. Files that no longer exist are described but
+/// not copied.
+pub fn write_ro_crate(config_path: &Path, output: &Path) -> Result<Value, ProvenanceError> {
+    let manifest_path = RunManifest::path_for(config_path);
+    if !manifest_path.exists() {
+        return Err(ProvenanceError::NoManifest(config_path.to_path_buf()));
+    }
+    if output.exists() {
+        return Err(ProvenanceError::Exists(output.to_path_buf()));
+    }
+    let manifest = RunManifest::load(&manifest_path)?;
+    std::fs::create_dir_all(output)?;
+    let copy = |from: &Path, to: &str| -> std::io::Result<()> {
+        let to = output.join(to);
+        if let Some(parent) = to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from, to).map(|_| ())
+    };
+    let name = |p: &Path| {
+        p.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let experiment = name(config_path);
+    let manifest_file = name(&manifest_path);
+    copy(config_path, &experiment)?;
+    copy(&manifest_path, &manifest_file)?;
+    let mut files = BTreeSet::new();
+    for trial in manifest.trials.values() {
+        for path in trial.inputs.keys().chain(trial.outputs.keys()) {
+            let id = crate_path(path);
+            if !files.contains(&id) && Path::new(path).is_file() {
+                copy(Path::new(path), &id)?;
+                files.insert(id);
+            }
+        }
+    }
+    let metadata = ro_crate_metadata(&manifest, &experiment, &manifest_file, &files);
+    std::fs::write(
+        output.join(RO_CRATE_METADATA),
+        serde_json::to_vec_pretty(&metadata)?,
+    )?;
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::experiment::TrialStatus;
+    use crate::manifest::{RunManifest, TrialManifest};
+    use crate::provenance::{couplings, crate_path, prov_json, ro_crate_metadata};
+    use crate::schema::SCHEMA_VERSION;
+
+    fn trial(inputs: &[(&str, &str)], outputs: &[(&str, &str)]) -> TrialManifest {
+        let files = |fs: &[(&str, &str)]| {
+            fs.iter()
+                .map(|(p, h)| (p.to_string(), h.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        TrialManifest {
+            model: "simplecrop".to_string(),
+            model_sha256: Some("abc".to_string()),
+            seed: Some(7),
+            args: vec![],
+            env: BTreeMap::new(),
+            started: 0,
+            finished: 60,
+            status: TrialStatus::Succeeded,
+            inputs: files(inputs),
+            outputs: files(outputs),
+            pruned: None,
+            logs: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            transforms: Default::default(),
+        }
+    }
+
+    fn manifest() -> RunManifest {
+        let mut trials = BTreeMap::new();
+        trials.insert(
+            "soil".to_string(),
+            trial(
+                &[("inputs/daily.feather", "d1")],
+                &[("outputs/soil.nc", "s1")],
+            ),
+        );
+        let mut crop = trial(
+            &[("outputs/soil.nc", "s1")],
+            &[("/data/yield.parquet", "y1")],
+        );
+        crop.status = TrialStatus::Failed {
+            message: "diverged".to_string(),
+        };
+        trials.insert("crop".to_string(), crop);
+        // read an older soil file than the one written
+        trials.insert(
+            "stale".to_string(),
+            trial(&[("outputs/soil.nc", "s0")], &[]),
+        );
+        RunManifest {
+            version: SCHEMA_VERSION,
+            experiment: "irrigation".to_string(),
+            created: 86400,
+            repro: None,
+            tags: BTreeMap::new(),
+            trials,
+        }
+    }
+
+    #[test]
+    fn prov_links_coupled_trials() {
+        let manifest = manifest();
+        let pairs: Vec<_> = couplings(&manifest).into_iter().collect();
+        assert_eq!(pairs, vec![("soil".to_string(), "crop".to_string())]);
+
+        let prov = prov_json(&manifest);
+        assert_eq!(
+            prov["activity"]["trial:crop"]["meillionen:message"],
+            "diverged"
+        );
+        assert_eq!(
+            prov["activity"]["trial:soil"]["prov:endTime"],
+            "1970-01-01T00:01:00Z"
+        );
+        assert_eq!(
+            prov["wasInformedBy"]["_:informed_crop_soil"]["prov:informant"],
+            "trial:soil"
+        );
+        assert_eq!(
+            prov["wasGeneratedBy"]["_:gen_soil_outputs/soil.nc"]["prov:entity"],
+            "file:outputs/soil.nc@s1"
+        );
+        assert!(prov["entity"]["file:outputs/soil.nc@s0"].is_object());
+        assert_eq!(
+            prov["agent"]["model:simplecrop"]["prov:type"],
+            "prov:SoftwareAgent"
+        );
+    }
+
+    #[test]
+    fn ro_crate_describes_actions() {
+        assert_eq!(
+            crate_path("/data/yield.parquet"),
+            "external/data/yield.parquet"
+        );
+        assert_eq!(crate_path("./outputs/../soil.nc"), "outputs/parent/soil.nc");
+
+        let files: BTreeSet<String> = vec!["outputs/soil.nc".to_string()].into_iter().collect();
+        let metadata = ro_crate_metadata(
+            &manifest(),
+            "irrigation.toml",
+            "irrigation.manifest.json",
+            &files,
+        );
+        let graph = metadata["@graph"].as_array().unwrap();
+        let find = |id: &str| graph.iter().find(|e| e["@id"] == id).unwrap();
+        let parts = find("./")["hasPart"].as_array().unwrap().clone();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(find("outputs/soil.nc")["sha256"], "s1");
+        let crop = find("#trial-crop");
+        assert_eq!(crop["object"][0]["@id"], "outputs/soil.nc");
+        assert_eq!(crop["result"][0]["@id"], "external/data/yield.parquet");
+        assert_eq!(
+            crop["actionStatus"]["@id"],
+            "http://schema.org/FailedActionStatus"
+        );
+        assert_eq!(find("#model-simplecrop")["@type"], "SoftwareApplication");
+    }
+}
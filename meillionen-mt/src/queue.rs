@@ -0,0 +1,1012 @@
+//! A persistent queue of experiments for `meillionen serve`
+//!
+//! `meillionen queue submit` adds an experiment file to a SQLite database and
+//! a running `meillionen serve` claims queued jobs, highest priority first and
+//! oldest first within a priority. The database is the only state the service
+//! keeps, so jobs survive a restart and several users on one machine can
+//! share a queue. Clients without access to the database submit to the
+//! service over HTTP instead, see [`crate::submission`].
+//!
+//! A queue is shared once `meillionen queue init` has given it an admin
+//! token. Only the admin may then add and remove users or use the database
+//! directly, and users submit through `meillionen serve --listen`, which
+//! checks their token. The database is created readable only by its owner,
+//! since anyone who can write it could add themselves as a user.
+//!
+//! Each user submits from their own workspace, sees and cancels only their
+//! own jobs, and has limits on how many of their jobs run at once and how
+//! much disk their workspace may use. The experiment file, the directory it
+//! runs in and every source, sink and store it names must resolve to a path
+//! inside the workspace, following symlinks, both when the job is submitted
+//! and again before it runs.
+
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension, Row, TransactionBehavior, NO_PARAMS};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::experiment::{ExperimentConfig, ExperimentError, ResourceConfig};
+use crate::repro::timestamp;
+use crate::store::StoreConfig;
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("job {0} not found")]
+    UnknownJob(i64),
+    #[error("job {id} has already {state}")]
+    Finished { id: i64, state: JobState },
+    #[error("job state must be queued, running, succeeded, failed or cancelled but is {0}")]
+    UnknownState(String),
+    #[error("trials at once must be at least 1")]
+    NoJobs,
+    #[error("the queue has users, give a token with --token or MEILLIONEN_TOKEN")]
+    TokenRequired,
+    #[error("the token does not belong to any user of the queue")]
+    InvalidToken,
+    #[error("the queue has no admin token, create one with meillionen queue init")]
+    NoAdmin,
+    #[error(
+        "the queue is shared, give its admin token with --admin-token or MEILLIONEN_ADMIN_TOKEN"
+    )]
+    AdminTokenRequired,
+    #[error("the admin token does not match the queue's")]
+    InvalidAdminToken,
+    #[error("user {0} not found")]
+    UnknownUser(String),
+    #[error("{path} is outside the workspace {workspace} of user {user}")]
+    OutsideWorkspace {
+        user: String,
+        workspace: PathBuf,
+        path: PathBuf,
+    },
+    #[error("job {id} belongs to another user")]
+    NotOwner { id: i64 },
+    #[error("jobs running at once must be at least 1")]
+    NoRunning,
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Succeeded => "succeeded",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+impl FromStr for JobState {
+    type Err = QueueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobState::Queued),
+            "running" => Ok(JobState::Running),
+            "succeeded" => Ok(JobState::Succeeded),
+            "failed" => Ok(JobState::Failed),
+            "cancelled" => Ok(JobState::Cancelled),
+            _ => Err(QueueError::UnknownState(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An experiment submitted to the queue
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Job {
+    pub id: i64,
+    /// The absolute path of the experiment file
+    pub config: PathBuf,
+    /// The directory the experiment was submitted from, which relative
+    /// resource paths are resolved against
+    pub dir: PathBuf,
+    /// Jobs with a higher priority are run first
+    pub priority: i64,
+    /// Trials of the experiment to run at once, as `meillionen run --jobs`
+    pub jobs: usize,
+    pub state: JobState,
+    pub submitted: u64,
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+    /// Set by `meillionen queue cancel` while the job runs
+    pub cancel_requested: bool,
+    /// Why the job failed
+    pub message: Option<String>,
+    /// Who submitted the job, if the queue had users then
+    pub user: Option<String>,
+    /// The [`ExperimentConfig::content_hash`] of the experiment when it was
+    /// submitted
+    pub config_hash: Option<String>,
+}
+
+const JOB_COLUMNS: &str = "id, config, dir, priority, jobs, state, submitted, started, finished, \
+                           cancel_requested, message, user, config_hash";
+
+fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+    let state: String = row.get(5)?;
+    Ok(Job {
+        id: row.get(0)?,
+        config: PathBuf::from(row.get::<_, String>(1)?),
+        dir: PathBuf::from(row.get::<_, String>(2)?),
+        priority: row.get(3)?,
+        jobs: row.get::<_, i64>(4)? as usize,
+        // the column is only written from JobState::as_str
+        state: state.parse().unwrap_or(JobState::Failed),
+        submitted: row.get::<_, i64>(6)? as u64,
+        started: row.get::<_, Option<i64>>(7)?.map(|t| t as u64),
+        finished: row.get::<_, Option<i64>>(8)?.map(|t| t as u64),
+        cancel_requested: row.get(9)?,
+        message: row.get(10)?,
+        user: row.get(11)?,
+        config_hash: row.get(12)?,
+    })
+}
+
+/// Someone allowed to submit to a queue
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct User {
+    pub name: String,
+    /// The absolute path of the directory the user's experiments must be
+    /// submitted from
+    pub workspace: PathBuf,
+    /// Jobs of the user the service runs at once
+    pub max_running: usize,
+    /// Bytes the workspace may use before the service stops starting the
+    /// user's jobs
+    pub max_bytes: Option<u64>,
+}
+
+const USER_COLUMNS: &str = "name, workspace, max_running, max_bytes";
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        name: row.get(0)?,
+        workspace: PathBuf::from(row.get::<_, String>(1)?),
+        max_running: row.get::<_, i64>(2)? as usize,
+        max_bytes: row.get::<_, Option<i64>>(3)?.map(|b| b as u64),
+    })
+}
+
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `path` with symlinks followed and `.` and `..` removed
+///
+/// The parts of the path that don't exist yet are added back as they are,
+/// so a sink that hasn't been written resolves to where it will be. Gives
+/// `None` for relative paths and paths through a dangling symlink, whose
+/// target can't be checked.
+pub fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut missing = vec![];
+    let mut existing = path;
+    loop {
+        match existing.canonicalize() {
+            Ok(mut resolved) => {
+                for part in missing.iter().rev() {
+                    match part {
+                        Component::ParentDir => {
+                            resolved.pop();
+                        }
+                        Component::CurDir => {}
+                        part => resolved.push(part),
+                    }
+                }
+                return Some(resolved);
+            }
+            Err(_) if std::fs::symlink_metadata(existing).is_ok() => return None,
+            Err(_) => {
+                missing.push(existing.components().next_back()?);
+                existing = existing.parent()?;
+            }
+        }
+    }
+}
+
+/// The directories a glob pattern can match files in, which is the part
+/// before the first component with a wildcard
+fn glob_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| {
+            !c.as_os_str()
+                .to_string_lossy()
+                .contains(&['*', '?', '['][..])
+        })
+        .collect()
+}
+
+/// The first path an experiment run in `dir` would read or write outside
+/// `workspace`
+///
+/// The experiment file, `dir`, the sources and sinks of every trial and the
+/// store are checked. Relative paths are resolved against `dir` as
+/// `meillionen run` does.
+pub fn outside_workspace(
+    workspace: &Path,
+    dir: &Path,
+    config: &Path,
+    experiment: &ExperimentConfig,
+) -> Option<PathBuf> {
+    let mut paths = vec![dir.to_path_buf(), config.to_path_buf()];
+    for trial in experiment.trials.iter() {
+        for resource in trial.sources.values().chain(trial.sinks.values()) {
+            paths.push(match resource {
+                ResourceConfig::MultiNetcdf(r) => glob_root(&r.pattern),
+                r => PathBuf::from(r.path()),
+            });
+        }
+    }
+    match &experiment.store {
+        Some(StoreConfig::Parquet { dir: path })
+        | Some(StoreConfig::Zarr { path })
+        | Some(StoreConfig::NetCDF { path }) => paths.push(PathBuf::from(path)),
+        Some(StoreConfig::Memory) | None => {}
+    }
+    paths
+        .into_iter()
+        .map(|p| dir.join(p))
+        .find(|p| resolve(p).is_none_or(|resolved| !resolved.starts_with(workspace)))
+}
+
+/// The bytes used by the files under a directory, not following symlinks
+pub fn workspace_bytes(path: &Path) -> std::io::Result<u64> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut bytes = 0;
+    for entry in std::fs::read_dir(path)? {
+        bytes += workspace_bytes(&entry?.path())?;
+    }
+    Ok(bytes)
+}
+
+fn check_workspace(
+    user: &User,
+    dir: &Path,
+    config: &Path,
+    experiment: &ExperimentConfig,
+) -> Result<(), QueueError> {
+    match outside_workspace(&user.workspace, dir, config, experiment) {
+        Some(path) => Err(QueueError::OutsideWorkspace {
+            user: user.name.clone(),
+            workspace: user.workspace.clone(),
+            path,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// The queue of jobs kept in a SQLite database
+pub struct JobQueue {
+    conn: Connection,
+}
+
+impl JobQueue {
+    /// Open a queue, creating the database if it does not exist
+    ///
+    /// New databases are only readable by their owner. Each process or
+    /// thread opens its own queue. Writers wait for each other for up to
+    /// `busy_timeout` rather than failing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, QueueError> {
+        let path = path.as_ref();
+        #[cfg(unix)]
+        if !path.exists() {
+            use std::os::unix::fs::OpenOptionsExt;
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(30))?;
+        Self::new(conn)
+    }
+
+    pub fn new(conn: Connection) -> Result<Self, QueueError> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS meillionen_jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                config TEXT NOT NULL,
+                dir TEXT NOT NULL,
+                priority INTEGER NOT NULL DEFAULT 0,
+                jobs INTEGER NOT NULL DEFAULT 1,
+                state TEXT NOT NULL,
+                submitted INTEGER NOT NULL,
+                started INTEGER,
+                finished INTEGER,
+                cancel_requested INTEGER NOT NULL DEFAULT 0,
+                message TEXT,
+                user TEXT,
+                config_hash TEXT
+            )",
+            NO_PARAMS,
+        )?;
+        // queues made before these columns were added
+        for column in ["user", "config_hash"] {
+            let exists: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('meillionen_jobs') WHERE name = ?",
+                params![column],
+                |r| r.get(0),
+            )?;
+            if exists == 0 {
+                conn.execute(
+                    &format!("ALTER TABLE meillionen_jobs ADD COLUMN {} TEXT", column),
+                    NO_PARAMS,
+                )?;
+            }
+        }
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS meillionen_users (
+                name TEXT PRIMARY KEY,
+                token_sha256 TEXT NOT NULL UNIQUE,
+                workspace TEXT NOT NULL,
+                max_running INTEGER NOT NULL DEFAULT 1,
+                max_bytes INTEGER
+            )",
+            NO_PARAMS,
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS meillionen_admin (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                token_sha256 TEXT NOT NULL
+            )",
+            NO_PARAMS,
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Give the queue a new admin token, returning it
+    ///
+    /// Replacing the token of a shared queue needs the current one.
+    pub fn init_admin(&self, current: Option<&str>) -> Result<String, QueueError> {
+        if self.is_shared()? {
+            self.check_admin(current)?;
+        }
+        let token = new_token();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meillionen_admin (id, token_sha256) VALUES (1, ?)",
+            params![hash_token(&token)],
+        )?;
+        Ok(token)
+    }
+
+    /// Whether the queue has an admin token or users, so that only the
+    /// admin may use the database directly
+    pub fn is_shared(&self) -> Result<bool, QueueError> {
+        let shared: i64 = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM meillionen_admin)
+                 OR EXISTS (SELECT 1 FROM meillionen_users)",
+            NO_PARAMS,
+            |r| r.get(0),
+        )?;
+        Ok(shared != 0)
+    }
+
+    /// Check a token is the admin token of the queue
+    pub fn check_admin(&self, token: Option<&str>) -> Result<(), QueueError> {
+        let expected: String = self
+            .conn
+            .query_row(
+                "SELECT token_sha256 FROM meillionen_admin WHERE id = 1",
+                NO_PARAMS,
+                |r| r.get(0),
+            )
+            .optional()?
+            .ok_or(QueueError::NoAdmin)?;
+        let token = token.ok_or(QueueError::AdminTokenRequired)?;
+        if hash_token(token) != expected {
+            return Err(QueueError::InvalidAdminToken);
+        }
+        Ok(())
+    }
+
+    /// Add a user, or replace one with the same name, returning their token
+    ///
+    /// Needs the admin token. The workspace is created if it does not
+    /// exist. The user's token is only ever shown here; adding the user
+    /// again issues a new one.
+    pub fn add_user(
+        &self,
+        admin_token: Option<&str>,
+        name: &str,
+        workspace: &Path,
+        max_running: usize,
+        max_bytes: Option<u64>,
+    ) -> Result<String, QueueError> {
+        self.check_admin(admin_token)?;
+        if max_running == 0 {
+            return Err(QueueError::NoRunning);
+        }
+        std::fs::create_dir_all(workspace)?;
+        let workspace = workspace.canonicalize()?;
+        let token = new_token();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO meillionen_users
+             (name, token_sha256, workspace, max_running, max_bytes)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                name,
+                hash_token(&token),
+                workspace.to_string_lossy(),
+                max_running as i64,
+                max_bytes.map(|b| b as i64)
+            ],
+        )?;
+        Ok(token)
+    }
+
+    /// Remove a user so their token stops working
+    ///
+    /// Needs the admin token. Their jobs stay in the queue.
+    pub fn remove_user(&self, admin_token: Option<&str>, name: &str) -> Result<(), QueueError> {
+        self.check_admin(admin_token)?;
+        let removed = self
+            .conn
+            .execute("DELETE FROM meillionen_users WHERE name = ?", params![name])?;
+        if removed == 0 {
+            return Err(QueueError::UnknownUser(name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn users(&self) -> Result<Vec<User>, QueueError> {
+        let mut query = self.conn.prepare(&format!(
+            "SELECT {} FROM meillionen_users ORDER BY name",
+            USER_COLUMNS
+        ))?;
+        let users = query
+            .query_map(NO_PARAMS, user_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(users)
+    }
+
+    pub fn user(&self, name: &str) -> Result<User, QueueError> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM meillionen_users WHERE name = ?",
+                    USER_COLUMNS
+                ),
+                params![name],
+                user_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| QueueError::UnknownUser(name.to_string()))
+    }
+
+    /// The user a token belongs to
+    ///
+    /// A queue that isn't shared is open to anyone and gives `None`.
+    pub fn authenticate(&self, token: Option<&str>) -> Result<Option<User>, QueueError> {
+        let token = match token {
+            Some(token) => token,
+            None if !self.is_shared()? => return Ok(None),
+            None => return Err(QueueError::TokenRequired),
+        };
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM meillionen_users WHERE token_sha256 = ?",
+                    USER_COLUMNS
+                ),
+                params![hash_token(token)],
+                user_from_row,
+            )
+            .optional()?
+            .map(Some)
+            .ok_or(QueueError::InvalidToken)
+    }
+
+    /// Add an experiment to the queue, returning the id of its job
+    ///
+    /// The experiment file and `dir` are made absolute so the service finds
+    /// them wherever it was started. Everything a user's experiment reads
+    /// and writes must be inside their workspace, see [`outside_workspace`].
+    /// Submitting does not check for an earlier job running the same
+    /// experiment, see [`JobQueue::same_as`].
+    pub fn submit(
+        &self,
+        config: &Path,
+        dir: &Path,
+        priority: i64,
+        jobs: usize,
+        user: Option<&User>,
+    ) -> Result<i64, QueueError> {
+        if jobs == 0 {
+            return Err(QueueError::NoJobs);
+        }
+        let dir = dir.canonicalize()?;
+        let config = dir.join(config).canonicalize()?;
+        let experiment = ExperimentConfig::load(&config)?;
+        if let Some(user) = user {
+            check_workspace(user, &dir, &config, &experiment)?;
+        }
+        let config_hash = experiment.content_hash()?;
+        self.conn.execute(
+            "INSERT INTO meillionen_jobs
+             (config, dir, priority, jobs, state, submitted, user, config_hash)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                config.to_string_lossy(),
+                dir.to_string_lossy(),
+                priority,
+                jobs as i64,
+                JobState::Queued.as_str(),
+                timestamp(None) as i64,
+                user.map(|u| u.name.as_str()),
+                config_hash
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// The latest job submitted from `dir` with the same content hash that
+    /// is queued, running or succeeded, whose results can be used instead of
+    /// running the experiment again
+    ///
+    /// Jobs must come from the same directory since relative paths in the
+    /// experiment are resolved against it.
+    pub fn same_as(&self, config_hash: &str, dir: &Path) -> Result<Option<Job>, QueueError> {
+        let dir = dir.canonicalize()?;
+        Ok(self
+            .conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM meillionen_jobs
+                     WHERE config_hash = ? AND dir = ? AND state IN (?, ?, ?)
+                     ORDER BY id DESC LIMIT 1",
+                    JOB_COLUMNS
+                ),
+                params![
+                    config_hash,
+                    dir.to_string_lossy(),
+                    JobState::Queued.as_str(),
+                    JobState::Running.as_str(),
+                    JobState::Succeeded.as_str()
+                ],
+                job_from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn job(&self, id: i64) -> Result<Job, QueueError> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM meillionen_jobs WHERE id = ?", JOB_COLUMNS),
+                params![id],
+                job_from_row,
+            )
+            .optional()?
+            .ok_or(QueueError::UnknownJob(id))
+    }
+
+    /// Every job in the order they were submitted
+    pub fn jobs(&self) -> Result<Vec<Job>, QueueError> {
+        let mut query = self.conn.prepare(&format!(
+            "SELECT {} FROM meillionen_jobs ORDER BY id",
+            JOB_COLUMNS
+        ))?;
+        let jobs = query
+            .query_map(NO_PARAMS, job_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    /// Check a user's job still only reads and writes inside their
+    /// workspace, which symlinks made after it was submitted could change
+    pub fn check_job(&self, job: &Job) -> Result<(), QueueError> {
+        if let Some(name) = &job.user {
+            let user = self.user(name)?;
+            let experiment = ExperimentConfig::load(&job.config)?;
+            check_workspace(&user, &job.dir, &job.config, &experiment)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the next queued job as running and return it
+    ///
+    /// Jobs of users already running as many jobs as they may, or whose
+    /// workspace is over its disk limit, wait until the user is back under
+    /// their limits. Claims are made in an immediate transaction so two
+    /// workers never claim the same job.
+    pub fn claim(&mut self) -> Result<Option<Job>, QueueError> {
+        let mut over_disk = vec![];
+        for user in self.users()? {
+            if let Some(max_bytes) = user.max_bytes {
+                if workspace_bytes(&user.workspace)? > max_bytes {
+                    over_disk.push(user.name);
+                }
+            }
+        }
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let id = {
+            let mut query = tx.prepare(
+                "SELECT j.id, j.user FROM meillionen_jobs j
+                 LEFT JOIN meillionen_users u ON j.user = u.name
+                 WHERE j.state = ?1 AND (u.name IS NULL OR u.max_running >
+                     (SELECT COUNT(*) FROM meillionen_jobs r
+                      WHERE r.user = j.user AND r.state = ?2))
+                 ORDER BY j.priority DESC, j.id",
+            )?;
+            let candidates = query.query_map(
+                params![JobState::Queued.as_str(), JobState::Running.as_str()],
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, Option<String>>(1)?)),
+            )?;
+            let mut id = None;
+            for candidate in candidates {
+                let (candidate, user) = candidate?;
+                if !user.is_some_and(|u| over_disk.contains(&u)) {
+                    id = Some(candidate);
+                    break;
+                }
+            }
+            id
+        };
+        if let Some(id) = id {
+            tx.execute(
+                "UPDATE meillionen_jobs SET state = ?, started = ? WHERE id = ?",
+                params![JobState::Running.as_str(), timestamp(None) as i64, id],
+            )?;
+        }
+        tx.commit()?;
+        id.map(|id| self.job(id)).transpose()
+    }
+
+    /// Cancel a job, returning its new state
+    ///
+    /// Queued jobs are cancelled straight away. Running jobs are marked so
+    /// the service stops them the next time it checks. A user may only
+    /// cancel their own jobs.
+    pub fn cancel(&self, id: i64, user: Option<&User>) -> Result<JobState, QueueError> {
+        let job = self.job(id)?;
+        if let Some(user) = user {
+            if job.user.as_ref() != Some(&user.name) {
+                return Err(QueueError::NotOwner { id });
+            }
+        }
+        match job.state {
+            JobState::Queued => {
+                self.set_finished(id, JobState::Cancelled, None)?;
+                Ok(JobState::Cancelled)
+            }
+            JobState::Running => {
+                self.conn.execute(
+                    "UPDATE meillionen_jobs SET cancel_requested = 1 WHERE id = ?",
+                    params![id],
+                )?;
+                Ok(JobState::Running)
+            }
+            state => Err(QueueError::Finished { id, state }),
+        }
+    }
+
+    pub fn cancel_requested(&self, id: i64) -> Result<bool, QueueError> {
+        Ok(self.job(id)?.cancel_requested)
+    }
+
+    /// Record how a running job ended
+    pub fn finish(&self, id: i64, result: Result<(), String>) -> Result<JobState, QueueError> {
+        let state = match (&result, self.cancel_requested(id)?) {
+            (_, true) => JobState::Cancelled,
+            (Ok(()), false) => JobState::Succeeded,
+            (Err(_), false) => JobState::Failed,
+        };
+        self.set_finished(id, state, result.err())?;
+        Ok(state)
+    }
+
+    fn set_finished(
+        &self,
+        id: i64,
+        state: JobState,
+        message: Option<String>,
+    ) -> Result<(), QueueError> {
+        self.conn.execute(
+            "UPDATE meillionen_jobs SET state = ?, finished = ?, message = ? WHERE id = ?",
+            params![state.as_str(), timestamp(None) as i64, message, id],
+        )?;
+        Ok(())
+    }
+
+    /// The number of jobs waiting to be claimed
+    pub fn queued(&self) -> Result<u64, QueueError> {
+        let n: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM meillionen_jobs WHERE state = ?",
+            params![JobState::Queued.as_str()],
+            |row| row.get(0),
+        )?;
+        Ok(n as u64)
+    }
+
+    /// Put jobs left running by a service that stopped back in the queue,
+    /// returning how many there were
+    ///
+    /// Only call this when no other service is using the queue.
+    pub fn requeue_running(&self) -> Result<usize, QueueError> {
+        Ok(self.conn.execute(
+            "UPDATE meillionen_jobs SET state = ?, started = NULL WHERE state = ?",
+            params![JobState::Queued.as_str(), JobState::Running.as_str()],
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rusqlite::Connection;
+
+    use crate::experiment::ExperimentConfig;
+    use crate::queue::{outside_workspace, JobQueue, JobState, QueueError};
+
+    const EXPERIMENT: &str = r#"
+name = "irrigation"
+
+[[trials]]
+name = "baseline"
+model = "simplecrop_omf"
+"#;
+
+    #[test]
+    fn priority_and_cancellation() {
+        let dir = std::env::temp_dir().join(format!("meillionen-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("baseline.toml"), EXPERIMENT).unwrap();
+        let config = std::path::Path::new("baseline.toml");
+
+        let mut queue = JobQueue::new(Connection::open_in_memory().unwrap()).unwrap();
+        let low = queue.submit(config, &dir, 0, 1, None).unwrap();
+        let high = queue.submit(config, &dir, 5, 2, None).unwrap();
+        let other = queue.submit(config, &dir, 0, 1, None).unwrap();
+        assert!(queue.submit(config, &dir, 0, 0, None).is_err());
+        assert_eq!(queue.queued().unwrap(), 3);
+        assert_eq!(
+            queue.job(low).unwrap().config,
+            dir.canonicalize().unwrap().join(config)
+        );
+
+        let claimed = queue.claim().unwrap().unwrap();
+        assert_eq!((claimed.id, claimed.jobs), (high, 2));
+        assert_eq!(claimed.state, JobState::Running);
+        assert_eq!(queue.cancel(other, None).unwrap(), JobState::Cancelled);
+        assert_eq!(queue.claim().unwrap().unwrap().id, low);
+        assert!(queue.claim().unwrap().is_none());
+        assert_eq!(queue.queued().unwrap(), 0);
+
+        assert_eq!(queue.cancel(low, None).unwrap(), JobState::Running);
+        assert_eq!(queue.finish(low, Ok(())).unwrap(), JobState::Cancelled);
+        assert_eq!(
+            queue
+                .finish(high, Err("1 trials failed".to_string()))
+                .unwrap(),
+            JobState::Failed
+        );
+        assert!(matches!(
+            queue.cancel(high, None),
+            Err(QueueError::Finished {
+                state: JobState::Failed,
+                ..
+            })
+        ));
+        assert!(matches!(queue.job(99), Err(QueueError::UnknownJob(99))));
+
+        let again = queue.submit(config, &dir, 0, 1, None).unwrap();
+        queue.claim().unwrap();
+        assert_eq!(queue.requeue_running().unwrap(), 1);
+        assert_eq!(queue.job(again).unwrap().state, JobState::Queued);
+
+        // the failed and cancelled jobs ran the same experiment but only
+        // the queued one can be reused
+        let hash = queue.job(again).unwrap().config_hash.unwrap();
+        assert_eq!(queue.same_as(&hash, &dir).unwrap().unwrap().id, again);
+        queue.cancel(again, None).unwrap();
+        assert!(queue.same_as(&hash, &dir).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn users_are_isolated_and_limited() {
+        let root = std::env::temp_dir().join(format!("meillionen-users-{}", std::process::id()));
+        let config = std::path::Path::new("baseline.toml");
+
+        let mut queue = JobQueue::new(Connection::open_in_memory().unwrap()).unwrap();
+        assert_eq!(queue.authenticate(None).unwrap(), None);
+        assert!(matches!(
+            queue.add_user(None, "ann", &root.join("ann"), 1, None),
+            Err(QueueError::NoAdmin)
+        ));
+        let admin = queue.init_admin(None).unwrap();
+        let admin = Some(admin.as_str());
+        assert!(matches!(
+            queue.init_admin(None),
+            Err(QueueError::AdminTokenRequired)
+        ));
+        assert!(matches!(
+            queue.add_user(Some("guess"), "ann", &root.join("ann"), 1, None),
+            Err(QueueError::InvalidAdminToken)
+        ));
+        let ann_token = queue
+            .add_user(admin, "ann", &root.join("ann"), 1, None)
+            .unwrap();
+        let bob_token = queue
+            .add_user(
+                admin,
+                "bob",
+                &root.join("bob"),
+                2,
+                Some(EXPERIMENT.len() as u64 + 10),
+            )
+            .unwrap();
+        assert!(matches!(
+            queue.authenticate(None),
+            Err(QueueError::TokenRequired)
+        ));
+        assert!(matches!(
+            queue.authenticate(Some("guess")),
+            Err(QueueError::InvalidToken)
+        ));
+        let ann = queue.authenticate(Some(&ann_token)).unwrap().unwrap();
+        let bob = queue.authenticate(Some(&bob_token)).unwrap().unwrap();
+        assert_eq!(ann.name, "ann");
+        std::fs::write(ann.workspace.join("baseline.toml"), EXPERIMENT).unwrap();
+        std::fs::write(bob.workspace.join("baseline.toml"), EXPERIMENT).unwrap();
+
+        assert!(matches!(
+            queue.submit(config, &bob.workspace, 0, 1, Some(&ann)),
+            Err(QueueError::OutsideWorkspace { .. })
+        ));
+        let first = queue
+            .submit(config, &ann.workspace, 9, 1, Some(&ann))
+            .unwrap();
+        let second = queue
+            .submit(config, &ann.workspace, 9, 1, Some(&ann))
+            .unwrap();
+        let bobs = queue
+            .submit(config, &bob.workspace, 0, 1, Some(&bob))
+            .unwrap();
+        assert_eq!(queue.job(bobs).unwrap().user.as_deref(), Some("bob"));
+        assert!(matches!(
+            queue.cancel(bobs, Some(&ann)),
+            Err(QueueError::NotOwner { .. })
+        ));
+
+        // ann may only run one job at once, so bob's lower priority job runs
+        assert_eq!(queue.claim().unwrap().unwrap().id, first);
+        assert_eq!(queue.claim().unwrap().unwrap().id, bobs);
+        assert!(queue.claim().unwrap().is_none());
+        queue.finish(first, Ok(())).unwrap();
+        assert_eq!(queue.claim().unwrap().unwrap().id, second);
+
+        // bob's workspace is over its limit by a byte
+        std::fs::write(bob.workspace.join("out.csv"), "0123456789\n").unwrap();
+        queue
+            .submit(config, &bob.workspace, 0, 1, Some(&bob))
+            .unwrap();
+        assert!(queue.claim().unwrap().is_none());
+
+        assert!(queue.remove_user(None, "ann").is_err());
+        queue.remove_user(admin, "ann").unwrap();
+        assert!(queue.authenticate(Some(&ann_token)).is_err());
+        assert!(matches!(
+            queue.remove_user(admin, "ann"),
+            Err(QueueError::UnknownUser(_))
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resources_are_confined_to_the_workspace() {
+        let root = std::env::temp_dir().join(format!("meillionen-confine-{}", std::process::id()));
+        let queue = JobQueue::new(Connection::open_in_memory().unwrap()).unwrap();
+        let admin = queue.init_admin(None).unwrap();
+        let token = queue
+            .add_user(Some(&admin), "ann", &root.join("ann"), 1, None)
+            .unwrap();
+        let ann = queue.authenticate(Some(&token)).unwrap().unwrap();
+        std::fs::create_dir_all(root.join("other")).unwrap();
+        let submit = |source: &str, sink: &str| {
+            let experiment = format!(
+                "{}\n[trials.sources.weather]\ntype = \"file\"\npath = \"{}\"\n\
+                 [trials.sinks.daily]\ntype = \"file\"\npath = \"{}\"\n",
+                EXPERIMENT, source, sink
+            );
+            std::fs::write(ann.workspace.join("baseline.toml"), experiment).unwrap();
+            queue.submit(Path::new("baseline.toml"), &ann.workspace, 0, 1, Some(&ann))
+        };
+
+        // sinks that don't exist yet resolve to where they will be written
+        let id = submit("weather.csv", "out/new/../daily.feather").unwrap();
+        queue.check_job(&queue.job(id).unwrap()).unwrap();
+        for (source, sink) in [
+            ("../other/weather.csv", "daily.feather"),
+            ("weather.csv", "/tmp/daily.feather"),
+            ("weather.csv", "out/../../other/daily.feather"),
+        ] {
+            assert!(
+                matches!(
+                    submit(source, sink),
+                    Err(QueueError::OutsideWorkspace { .. })
+                ),
+                "{} {}",
+                source,
+                sink
+            );
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("other"), ann.workspace.join("out")).unwrap();
+            assert!(matches!(
+                queue.check_job(&queue.job(id).unwrap()),
+                Err(QueueError::OutsideWorkspace { .. })
+            ));
+        }
+
+        let pattern = |pattern: &str| {
+            let mut experiment: ExperimentConfig = toml::from_str(EXPERIMENT).unwrap();
+            experiment.trials[0].sources.insert(
+                "weather".to_string(),
+                toml::from_str(&format!(
+                    "type = \"multi_netcdf\"\npattern = \"{}\"\n\
+                     variable = \"tmax\"\ndimension = \"time\"",
+                    pattern
+                ))
+                .unwrap(),
+            );
+            outside_workspace(&ann.workspace, &ann.workspace, &ann.workspace, &experiment)
+        };
+        assert_eq!(pattern("weather/*.nc"), None);
+        assert!(pattern("../*/weather.nc").is_some());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use arrow::record_batch::RecordBatch;
+
+use crate::determinism::format_f64;
+use crate::diff::{Difference, TABLE_HEADER};
+use crate::experiment::{
+    read_batches, ExperimentConfig, ExperimentError, ExperimentStatus, TrialStatus,
+};
+use crate::timeseries::numeric_values;
+
+/// Columns plotted over time for each trial when a sink has them
+pub const KEY_OUTPUTS: [&str; 2] = ["plant_leaf_area_index", "soil_water_storage_depth"];
+
+/// The column whose final value is taken as the yield of a trial
+pub const YIELD_COLUMN: &str = "plant_matter_fruit";
+
+const WIDTH: f64 = 480.0;
+const HEIGHT: f64 = 240.0;
+const MARGIN: f64 = 40.0;
+const HISTOGRAM_BINS: usize = 10;
+
+/// Escape text to place it in an html element or attribute
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn range(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    let (lo, hi) = values
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        });
+    if lo > hi {
+        None
+    } else if lo == hi {
+        Some((lo - 0.5, hi + 0.5))
+    } else {
+        Some((lo, hi))
+    }
+}
+
+fn scale(v: f64, (lo, hi): (f64, f64), from: f64, to: f64) -> f64 {
+    from + (v - lo) / (hi - lo) * (to - from)
+}
+
+fn svg_frame(title: &str, x: (f64, f64), y: (f64, f64), body: &str) -> String {
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            r#"<text x="{m}" y="20" font-size="14">{title}</text>"#,
+            r#"<rect x="{m}" y="{m}" width="{iw}" height="{ih}" fill="none" stroke="gray"/>"#,
+            r#"<text x="{m}" y="{xl}" font-size="10">{x0:.4}</text>"#,
+            r#"<text x="{xr}" y="{xl}" font-size="10" text-anchor="end">{x1:.4}</text>"#,
+            r#"<text x="2" y="{yb}" font-size="10">{y0:.4}</text>"#,
+            r#"<text x="2" y="{yt}" font-size="10">{y1:.4}</text>"#,
+            "{body}</svg>"
+        ),
+        w = WIDTH,
+        h = HEIGHT,
+        m = MARGIN,
+        iw = WIDTH - 2.0 * MARGIN,
+        ih = HEIGHT - 2.0 * MARGIN,
+        xl = HEIGHT - MARGIN + 14.0,
+        xr = WIDTH - MARGIN,
+        yb = HEIGHT - MARGIN,
+        yt = MARGIN + 10.0,
+        title = escape(title),
+        x0 = x.0,
+        x1 = x.1,
+        y0 = y.0,
+        y1 = y.1,
+        body = body
+    )
+}
+
+/// An inline svg line plot of a series against `xs`
+///
+/// Points that are not finite are left out of the line.
+pub fn svg_line_plot(title: &str, xs: &[f64], ys: &[f64]) -> String {
+    let points: Vec<(f64, f64)> = xs
+        .iter()
+        .zip(ys.iter())
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .map(|(x, y)| (*x, *y))
+        .collect();
+    let (x, y) = match (
+        range(points.iter().map(|p| p.0)),
+        range(points.iter().map(|p| p.1)),
+    ) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return svg_frame(title, (0.0, 1.0), (0.0, 1.0), ""),
+    };
+    let path: Vec<String> = points
+        .iter()
+        .map(|(px, py)| {
+            format!(
+                "{:.1},{:.1}",
+                scale(*px, x, MARGIN, WIDTH - MARGIN),
+                scale(*py, y, HEIGHT - MARGIN, MARGIN)
+            )
+        })
+        .collect();
+    let body = format!(
+        r#"<polyline fill="none" stroke="steelblue" points="{}"/>"#,
+        path.join(" ")
+    );
+    svg_frame(title, x, y, &body)
+}
+
+/// An inline svg histogram of values
+pub fn svg_histogram(title: &str, values: &[f64], bins: usize) -> String {
+    let x = match range(values.iter().copied()) {
+        Some(x) => x,
+        None => return svg_frame(title, (0.0, 1.0), (0.0, 1.0), ""),
+    };
+    let bins = bins.max(1);
+    let mut counts = vec![0usize; bins];
+    for v in values.iter().filter(|v| v.is_finite()) {
+        let i = ((v - x.0) / (x.1 - x.0) * bins as f64) as usize;
+        counts[i.min(bins - 1)] += 1;
+    }
+    let y = (0.0, *counts.iter().max().unwrap_or(&1) as f64);
+    let bar_width = (WIDTH - 2.0 * MARGIN) / bins as f64;
+    let mut body = String::new();
+    for (i, count) in counts.iter().enumerate() {
+        let top = scale(*count as f64, y, HEIGHT - MARGIN, MARGIN);
+        write!(
+            body,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="steelblue" stroke="white"/>"#,
+            MARGIN + i as f64 * bar_width,
+            top,
+            bar_width,
+            HEIGHT - MARGIN - top
+        )
+        .expect("writing to a string to succeed");
+    }
+    svg_frame(title, x, y, &body)
+}
+
+/// A small inline svg line of a series without axes, for tables
+pub fn svg_sparkline(values: &[f64]) -> String {
+    const W: f64 = 100.0;
+    const H: f64 = 20.0;
+    let y = range(values.iter().copied());
+    let x = (0.0, (values.len().max(2) - 1) as f64);
+    let points: Vec<String> = match y {
+        Some(y) => values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_finite())
+            .map(|(i, v)| {
+                format!(
+                    "{:.1},{:.1}",
+                    scale(i as f64, x, 1.0, W - 1.0),
+                    scale(*v, y, H - 1.0, 1.0)
+                )
+            })
+            .collect(),
+        None => vec![],
+    };
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}"><polyline fill="none" stroke="steelblue" points="{p}"/></svg>"#,
+        w = W,
+        h = H,
+        p = points.join(" ")
+    )
+}
+
+fn flatten(prefix: &str, value: &serde_json::Value, rows: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map.iter() {
+                let key = if prefix.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(&key, v, rows);
+            }
+        }
+        serde_json::Value::String(s) => rows.push((prefix.to_string(), s.clone())),
+        v => rows.push((prefix.to_string(), v.to_string())),
+    }
+}
+
+/// An html table of the settings of a config, one row per setting with
+/// nested keys joined by dots
+///
+/// Notebooks show this for config objects instead of their repr.
+pub fn html_parameters(title: &str, value: &serde_json::Value) -> String {
+    let mut rows = vec![];
+    flatten("", value, &mut rows);
+    let mut html = format!(
+        "<table>\n<caption>{}</caption>\n<tr><th>parameter</th><th>value</th></tr>\n",
+        escape(title)
+    );
+    for (k, v) in rows.iter() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td></tr>\n",
+            escape(k),
+            escape(v)
+        ));
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// An html table summarising each column of a result, with the range, mean
+/// and a sparkline of numeric columns
+pub fn html_summary(title: &str, batches: &[RecordBatch]) -> String {
+    let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    let mut html = format!(
+        "<table>\n<caption>{} ({} rows)</caption>\n{}\n",
+        escape(title),
+        rows,
+        "<tr><th>column</th><th>type</th><th>missing</th><th>min</th><th>mean</th><th>max</th><th></th></tr>"
+    );
+    let schema = match batches.first() {
+        Some(b) => b.schema(),
+        None => return html + "</table>\n",
+    };
+    for (i, field) in schema.fields().iter().enumerate() {
+        let missing: usize = batches.iter().map(|b| b.column(i).null_count()).sum();
+        let mut cells = vec![
+            escape(field.name()),
+            escape(&format!("{:?}", field.data_type())),
+            missing.to_string(),
+        ];
+        match column_values(batches, field.name()) {
+            Some(values) => {
+                let finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+                let fold = |f: fn(f64, f64) -> f64, init| finite.iter().copied().fold(init, f);
+                if finite.is_empty() {
+                    cells.extend(vec![String::new(); 3]);
+                } else {
+                    let mean = finite.iter().sum::<f64>() / finite.len() as f64;
+                    cells.push(format_f64(fold(f64::min, f64::INFINITY), 4));
+                    cells.push(format_f64(mean, 4));
+                    cells.push(format_f64(fold(f64::max, f64::NEG_INFINITY), 4));
+                }
+                cells.push(svg_sparkline(&values));
+            }
+            None => cells.extend(vec![String::new(); 4]),
+        }
+        let cells: Vec<String> = cells.iter().map(|c| format!("<td>{}</td>", c)).collect();
+        html.push_str(&format!("<tr>{}</tr>\n", cells.concat()));
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// An html table of the fields that differ between two configs, with their
+/// old and new values and percent change
+pub fn html_diff(title: &str, differences: &[Difference]) -> String {
+    let header: Vec<String> = TABLE_HEADER
+        .iter()
+        .map(|h| format!("<th>{}</th>", h))
+        .collect();
+    let mut html = format!(
+        "<table>\n<caption>{}</caption>\n<tr>{}</tr>\n",
+        escape(title),
+        header.concat()
+    );
+    for d in differences.iter() {
+        let cells: Vec<String> = d
+            .cells()
+            .iter()
+            .map(|c| format!("<td>{}</td>", escape(c)))
+            .collect();
+        html.push_str(&format!("<tr>{}</tr>\n", cells.concat()));
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// A self-contained html summary of a run or ensemble
+///
+/// Plots are inline svg so the file can be shared on its own.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub title: String,
+    pub parameters: Vec<Vec<String>>,
+    pub plots: Vec<String>,
+    pub warnings: Vec<String>,
+    pub provenance: Vec<(String, String)>,
+    /// An [`html_diff`] of the config against a baseline
+    pub changes: Option<String>,
+}
+
+impl Report {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut html = String::new();
+        let title = escape(&self.title);
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>{}</title>\n", title));
+        html.push_str(concat!(
+            "<style>body{font-family:sans-serif;margin:2em}",
+            "table{border-collapse:collapse}td,th{border:1px solid #ccc;padding:2px 8px}",
+            ".warning{color:#a40}</style>\n"
+        ));
+        html.push_str("</head>\n<body>\n");
+        html.push_str(&format!("<h1>{}</h1>\n", title));
+
+        html.push_str("<h2>Warnings</h2>\n");
+        if self.warnings.is_empty() {
+            html.push_str("<p>None</p>\n");
+        } else {
+            html.push_str("<ul>\n");
+            for w in self.warnings.iter() {
+                html.push_str(&format!(
+                    "<li class=\"warning\"><pre>{}</pre></li>\n",
+                    escape(w)
+                ));
+            }
+            html.push_str("</ul>\n");
+        }
+
+        html.push_str("<h2>Parameters</h2>\n<table>\n");
+        html.push_str("<tr><th>trial</th><th>model</th><th>argument</th><th>resource</th><th>path</th></tr>\n");
+        for row in self.parameters.iter() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|c| format!("<td>{}</td>", escape(c)))
+                .collect();
+            html.push_str(&format!("<tr>{}</tr>\n", cells.concat()));
+        }
+        html.push_str("</table>\n");
+
+        if let Some(changes) = &self.changes {
+            html.push_str("<h2>Changes</h2>\n");
+            html.push_str(changes);
+        }
+
+        html.push_str("<h2>Outputs</h2>\n");
+        for plot in self.plots.iter() {
+            html.push_str(plot);
+            html.push('\n');
+        }
+
+        html.push_str("<h2>Provenance</h2>\n<table>\n");
+        for (k, v) in self.provenance.iter() {
+            html.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                escape(k),
+                escape(v)
+            ));
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// The values of a column across batches as floats
+fn column_values(batches: &[RecordBatch], name: &str) -> Option<Vec<f64>> {
+    let mut values = vec![];
+    for rb in batches.iter() {
+        values.extend(numeric_values(rb, name)?);
+    }
+    Some(values)
+}
+
+/// Build a report of an experiment from its config, status and tabular sinks
+///
+/// Sinks of trials that have not succeeded are not read.
+pub fn experiment_report(
+    config: &ExperimentConfig,
+    config_path: &Path,
+    status: &ExperimentStatus,
+) -> Result<Report, ExperimentError> {
+    let mut report = Report::new(&config.name);
+    report.warnings = config.problems();
+    let mut yields = vec![];
+    for trial in config.trials.iter() {
+        for (field, resources) in [("source", &trial.sources), ("sink", &trial.sinks)].iter() {
+            for (name, resource) in resources.iter() {
+                report.parameters.push(vec![
+                    trial.name.clone(),
+                    trial.model.clone(),
+                    format!("{} {}", field, name),
+                    resource.type_name().to_string(),
+                    resource.path().to_string(),
+                ]);
+            }
+        }
+        match status.trials.get(&trial.name) {
+            Some(TrialStatus::Succeeded) => {}
+            Some(TrialStatus::Failed { message }) => {
+                report
+                    .warnings
+                    .push(format!("trial {} failed:\n{}", trial.name, message));
+                continue;
+            }
+            _ => {
+                report
+                    .warnings
+                    .push(format!("trial {} has not been run", trial.name));
+                continue;
+            }
+        }
+        for (name, sink) in trial.sinks.iter() {
+            let batches = match read_batches(sink) {
+                Ok(b) => b,
+                Err(ExperimentError::NotTabular(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+            let xs = column_values(&batches, "day")
+                .unwrap_or_else(|| (0..rows).map(|i| i as f64).collect());
+            for output in KEY_OUTPUTS.iter() {
+                if let Some(ys) = column_values(&batches, output) {
+                    let title = format!("{} {}: {}", trial.name, name, output);
+                    report.plots.push(svg_line_plot(&title, &xs, &ys));
+                }
+            }
+            if let Some(y) = column_values(&batches, YIELD_COLUMN).and_then(|v| v.last().copied()) {
+                yields.push(y);
+            }
+        }
+    }
+    if !yields.is_empty() {
+        let title = format!("{} across {} trials", YIELD_COLUMN, yields.len());
+        report
+            .plots
+            .push(svg_histogram(&title, &yields, HISTOGRAM_BINS));
+    }
+
+    let generated = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    report.provenance = vec![
+        ("experiment".to_string(), config_path.display().to_string()),
+        (
+            "meillionen".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("generated (unix time)".to_string(), generated.to_string()),
+    ];
+    let mut models: Vec<&str> = config.trials.iter().map(|t| t.model.as_str()).collect();
+    models.sort_unstable();
+    models.dedup();
+    report
+        .provenance
+        .push(("models".to_string(), models.join(", ")));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::diff::diff;
+    use crate::report::{
+        escape, html_diff, html_parameters, html_summary, svg_histogram, svg_line_plot, Report,
+    };
+
+    #[test]
+    fn renders_escaped_sections() {
+        let mut report = Report::new("irrigation <draft>");
+        report
+            .warnings
+            .push("trial a failed:\nbad & worse".to_string());
+        report.parameters.push(vec![
+            "a".to_string(),
+            "simplecrop_omf".to_string(),
+            "source daily".to_string(),
+            "meillionen::FeatherResource".to_string(),
+            "daily.feather".to_string(),
+        ]);
+        report.plots.push(svg_line_plot(
+            "lai",
+            &[1.0, 2.0, 3.0],
+            &[0.0, f64::NAN, 2.0],
+        ));
+        report
+            .plots
+            .push(svg_histogram("yield", &[1.0, 1.0, 3.0], 2));
+        let html = report.render();
+        assert!(html.contains("<title>irrigation &lt;draft&gt;</title>"));
+        assert!(html.contains("bad &amp; worse"));
+        assert!(html.contains("<td>daily.feather</td>"));
+        assert!(html.contains(r#"points="40.0,200.0 440.0,40.0""#));
+        assert_eq!(html.matches("<svg").count(), 2);
+        assert_eq!(escape("'\""), "&#39;&quot;");
+        assert!(!html.contains("<h2>Changes</h2>"));
+
+        let changes = diff(
+            &serde_json::json!({"irrigation": "25 mm"}),
+            &serde_json::json!({"irrigation": "3 cm"}),
+        );
+        report.changes = Some(html_diff("changes from <baseline>", &changes));
+        let html = report.render();
+        assert!(html.contains("<caption>changes from &lt;baseline&gt;</caption>"));
+        assert!(html.contains("<td>25 mm</td><td>3 cm</td><td>+20.00%</td>"));
+    }
+
+    #[test]
+    fn notebook_tables() {
+        let params = html_parameters(
+            "FeatherResource",
+            &serde_json::json!({"path": "a<b>.feather", "compression": {"level": 4}}),
+        );
+        assert!(params.contains("<td>compression.level</td><td>4</td>"));
+        assert!(params.contains("<td>a&lt;b&gt;.feather</td>"));
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("lai", DataType::Float64, true),
+            Field::new("site", DataType::Utf8, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float64Array::from(vec![Some(0.5), None, Some(1.5)])),
+            Arc::new(StringArray::from(vec!["a", "b", "c"])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let html = html_summary("plant", &[rb]);
+        assert!(html.contains("<caption>plant (3 rows)</caption>"));
+        assert!(html.contains(
+            "<td>lai</td><td>Float64</td><td>1</td><td>0.5000</td><td>1.0000</td><td>1.5000</td><td><svg"
+        ));
+        assert!(html
+            .contains("<td>site</td><td>Utf8</td><td>0</td><td></td><td></td><td></td><td></td>"));
+    }
+}
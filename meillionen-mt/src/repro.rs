@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// The environment variable model programs read their random seed from
+pub const SEED_ENV: &str = "MEILLIONEN_SEED";
+
+#[derive(Debug, Error)]
+pub enum ReproError {
+    #[error("model program {0} not found")]
+    ProgramNotFound(String),
+    #[error("model program {program} has sha256 {actual} but {pinned} is pinned")]
+    HashMismatch {
+        program: String,
+        pinned: String,
+        actual: String,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Settings that make repeated runs of an experiment give bit-identical
+/// manifests
+///
+/// Each trial gets a seed derived from `seed` and its name, timestamps are
+/// fixed, trials run in name order and model programs can be pinned to the
+/// sha256 of their executable.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ReproConfig {
+    pub seed: u64,
+    /// The unix time recorded in place of the wall clock
+    pub timestamp: u64,
+    pub sort_trials: bool,
+    /// The sha256 each model program must have, by the name used in trials
+    pub executables: BTreeMap<String, String>,
+}
+
+impl Default for ReproConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            timestamp: 0,
+            sort_trials: true,
+            executables: BTreeMap::new(),
+        }
+    }
+}
+
+/// FNV-1a, which unlike the std hasher is the same across Rust releases
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf2_9ce4_8422_2325, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl ReproConfig {
+    /// The seed of a trial, which stays the same when trials are added or
+    /// reordered
+    pub fn trial_seed(&self, trial: &str) -> u64 {
+        fnv1a(self.seed.to_le_bytes().iter().copied().chain(trial.bytes()))
+    }
+
+    /// Check a model program against its pinned hash, returning its hash
+    pub fn check_executable(&self, program: &str) -> Result<String, ReproError> {
+        let path = resolve_program(program)
+            .ok_or_else(|| ReproError::ProgramNotFound(program.to_string()))?;
+        let actual = sha256_file(&path)?;
+        match self.executables.get(program) {
+            Some(pinned) if !pinned.eq_ignore_ascii_case(&actual) => {
+                Err(ReproError::HashMismatch {
+                    program: program.to_string(),
+                    pinned: pinned.clone(),
+                    actual,
+                })
+            }
+            _ => Ok(actual),
+        }
+    }
+}
+
+/// The current unix time, or the fixed time of a reproducible run
+pub fn timestamp(repro: Option<&ReproConfig>) -> u64 {
+    match repro {
+        Some(r) => r.timestamp,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    }
+}
+
+/// Find a program the way a shell would, by path if it has a directory part
+/// and on the PATH otherwise
+///
+/// On windows a name without an extension is tried with each extension in
+/// `PATHEXT`, so `simplecrop_omf` finds `simplecrop_omf.exe` or `.bat`.
+pub fn resolve_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return executable_candidates(path)
+            .into_iter()
+            .find(|p| p.is_file());
+    }
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .flat_map(|dir| executable_candidates(&dir.join(program)))
+            .find(|p| p.is_file())
+    })
+}
+
+fn executable_candidates(path: &Path) -> Vec<PathBuf> {
+    if cfg!(windows) {
+        let extensions = env::var("PATHEXT").unwrap_or_else(|_| DEFAULT_PATHEXT.to_string());
+        with_extensions(path, &extensions)
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
+
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+
+/// The path followed by the path with each `;` separated extension appended
+///
+/// A path that already has an extension is tried as given first.
+pub fn with_extensions(path: &Path, extensions: &str) -> Vec<PathBuf> {
+    let mut candidates = vec![];
+    if path.extension().is_some() {
+        candidates.push(path.to_path_buf());
+    }
+    for ext in extensions.split(';').filter(|e| !e.is_empty()) {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(ext.to_ascii_lowercase());
+        candidates.push(PathBuf::from(name));
+    }
+    candidates
+}
+
+/// The lowercase hex sha256 of a file
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::repro::{sha256_file, with_extensions, ReproConfig, ReproError};
+
+    #[test]
+    fn seeds_and_pins() {
+        let repro = ReproConfig::default();
+        assert_eq!(repro.trial_seed("baseline"), repro.trial_seed("baseline"));
+        assert_ne!(repro.trial_seed("baseline"), repro.trial_seed("drought"));
+        let other = ReproConfig {
+            seed: 1,
+            ..ReproConfig::default()
+        };
+        assert_ne!(repro.trial_seed("baseline"), other.trial_seed("baseline"));
+
+        let dir = std::env::temp_dir().join(format!("meillionen-repro-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("model");
+        std::fs::write(&program, "abc").unwrap();
+        let program = program.to_string_lossy().to_string();
+        let hash = sha256_file(&program).unwrap();
+        let mut pinned = ReproConfig::default();
+        pinned.executables.insert(program.clone(), "00".to_string());
+        let mismatch = pinned.check_executable(&program);
+        pinned
+            .executables
+            .insert(program.clone(), hash.to_uppercase());
+        let matched = pinned.check_executable(&program).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(matches!(mismatch, Err(ReproError::HashMismatch { .. })));
+        assert_eq!(matched, hash);
+        assert!(matches!(
+            pinned.check_executable("/no/such/model"),
+            Err(ReproError::ProgramNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn program_extensions() {
+        assert_eq!(
+            with_extensions(Path::new("bin/model"), ".EXE;.BAT;"),
+            vec![
+                PathBuf::from("bin/model.exe"),
+                PathBuf::from("bin/model.bat")
+            ]
+        );
+        assert_eq!(
+            with_extensions(Path::new("model.v2"), ".EXE"),
+            vec![PathBuf::from("model.v2"), PathBuf::from("model.v2.exe")]
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn resolves_batch_files() {
+        let dir = std::env::temp_dir().join(format!("meillionen-pathext-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("model.bat");
+        std::fs::write(&program, "@echo off").unwrap();
+        let found = crate::repro::resolve_program(&dir.join("model").to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found, Some(program));
+    }
+}
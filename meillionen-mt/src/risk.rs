@@ -0,0 +1,295 @@
+//! Risk metrics over the outcomes of an ensemble
+//!
+//! Every row of a result table, such as the yield of one season of one
+//! ensemble member, is taken as one equally likely outcome. From those
+//! [`probability`] gives the chance of an outcome past a threshold, such as a
+//! yield below what a farm needs, [`return_period`] how many outcomes there
+//! are between such events on average, and [`cvar`] the mean of the worst
+//! outcomes. [`risk_table`] computes them for each group of a table, such as
+//! each site of a stacked ensemble, as a tidy table with a row per metric.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt64Array};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::timeseries::numeric_values;
+
+#[derive(Debug, Error)]
+pub enum RiskError {
+    #[error("column {0} not found or not numeric")]
+    MissingColumn(String),
+    #[error("group column {0} not found")]
+    MissingGroup(String),
+    #[error("cvar level {0} is not above 0 and at most 1")]
+    InvalidLevel(f64),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+}
+
+/// Which side of a threshold an outcome falls on to count as an event, and
+/// which end of the outcomes is the worst
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    /// Low outcomes are bad, such as yield
+    #[default]
+    Below,
+    /// High outcomes are bad, such as days of water stress
+    Above,
+}
+
+impl Side {
+    fn past(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Side::Below => value < threshold,
+            Side::Above => value > threshold,
+        }
+    }
+}
+
+fn outcomes(values: &[f64]) -> Vec<f64> {
+    values.iter().copied().filter(|v| !v.is_nan()).collect()
+}
+
+/// The fraction of outcomes past `threshold`, NaN if there are none
+///
+/// Missing (NaN) outcomes are left out.
+pub fn probability(values: &[f64], threshold: f64, side: Side) -> f64 {
+    let outcomes = outcomes(values);
+    let events = outcomes
+        .iter()
+        .filter(|v| side.past(**v, threshold))
+        .count();
+    events as f64 / outcomes.len() as f64
+}
+
+/// The mean number of outcomes from one event past `threshold` to the
+/// next, such as the seasons between droughts when each outcome is a season
+///
+/// Infinite if no outcome is past the threshold.
+pub fn return_period(values: &[f64], threshold: f64, side: Side) -> f64 {
+    1.0 / probability(values, threshold, side)
+}
+
+/// The conditional value at risk: the mean of the worst `level` fraction of
+/// outcomes, at least one of them
+///
+/// A `level` of 0.1 gives the mean yield of the worst tenth of seasons.
+pub fn cvar(values: &[f64], level: f64, side: Side) -> Result<f64, RiskError> {
+    if !(level > 0.0 && level <= 1.0) {
+        return Err(RiskError::InvalidLevel(level));
+    }
+    let mut outcomes = outcomes(values);
+    if outcomes.is_empty() {
+        return Ok(f64::NAN);
+    }
+    outcomes.sort_by(|a, b| a.partial_cmp(b).expect("NaN to be left out"));
+    if side == Side::Above {
+        outcomes.reverse();
+    }
+    let worst = ((level * outcomes.len() as f64).ceil() as usize).max(1);
+    Ok(outcomes[..worst].iter().sum::<f64>() / worst as f64)
+}
+
+/// The metrics [`risk_table`] computes
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct RiskSpec {
+    /// Thresholds to give the probability of an outcome past
+    #[serde(default)]
+    pub thresholds: Vec<f64>,
+    /// Thresholds to give the return period of an outcome past
+    #[serde(default)]
+    pub return_periods: Vec<f64>,
+    /// Fractions of the worst outcomes to give the mean of
+    #[serde(default)]
+    pub cvar_levels: Vec<f64>,
+    #[serde(default)]
+    pub side: Side,
+}
+
+/// The values of a column as text, for grouping by
+fn labels(batch: &RecordBatch, name: &str) -> Result<Vec<String>, RiskError> {
+    let i = batch
+        .schema()
+        .index_of(name)
+        .map_err(|_| RiskError::MissingGroup(name.to_string()))?;
+    let text = cast(batch.column(i), &DataType::Utf8)?;
+    let text = text
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .expect("array type to match data type");
+    Ok(text.iter().map(|v| v.unwrap_or("").to_string()).collect())
+}
+
+/// Risk metrics of `variable` for each group of rows with the same values of
+/// the `group_by` columns
+///
+/// The table has the group columns, then `metric` (`probability`,
+/// `return_period` or `cvar`), `parameter` (the threshold or level),
+/// `value` and the number of `outcomes` the value was computed from. Groups
+/// are in the order they first appear.
+pub fn risk_table(
+    batch: &RecordBatch,
+    variable: &str,
+    group_by: &[&str],
+    spec: &RiskSpec,
+) -> Result<RecordBatch, RiskError> {
+    let values = numeric_values(batch, variable)
+        .ok_or_else(|| RiskError::MissingColumn(variable.to_string()))?;
+    let keys: Vec<Vec<String>> = group_by
+        .iter()
+        .map(|g| labels(batch, g))
+        .collect::<Result<_, _>>()?;
+    let mut groups: Vec<(Vec<&str>, Vec<f64>)> = vec![];
+    let mut positions: HashMap<Vec<&str>, usize> = HashMap::new();
+    for (row, value) in values.iter().enumerate() {
+        let key: Vec<&str> = keys.iter().map(|k| k[row].as_str()).collect();
+        let next = groups.len();
+        let position = *positions.entry(key.clone()).or_insert(next);
+        if position == next {
+            groups.push((key, vec![]));
+        }
+        groups[position].1.push(*value);
+    }
+
+    let mut group_columns: Vec<Vec<&str>> = vec![vec![]; group_by.len()];
+    let mut metric = vec![];
+    let mut parameter = vec![];
+    let mut value = vec![];
+    let mut count = vec![];
+    for (key, values) in groups.iter() {
+        let outcomes = values.iter().filter(|v| !v.is_nan()).count() as u64;
+        let mut rows = vec![];
+        for t in spec.thresholds.iter() {
+            rows.push(("probability", *t, probability(values, *t, spec.side)));
+        }
+        for t in spec.return_periods.iter() {
+            rows.push(("return_period", *t, return_period(values, *t, spec.side)));
+        }
+        for level in spec.cvar_levels.iter() {
+            rows.push(("cvar", *level, cvar(values, *level, spec.side)?));
+        }
+        for (m, p, v) in rows {
+            for (column, label) in group_columns.iter_mut().zip(key.iter()) {
+                column.push(label);
+            }
+            metric.push(m);
+            parameter.push(p);
+            value.push(v);
+            count.push(outcomes);
+        }
+    }
+
+    let mut fields: Vec<Field> = group_by
+        .iter()
+        .map(|g| Field::new(g, DataType::Utf8, false))
+        .collect();
+    let mut columns: Vec<ArrayRef> = group_columns
+        .into_iter()
+        .map(|c| Arc::new(StringArray::from(c)) as ArrayRef)
+        .collect();
+    fields.push(Field::new("metric", DataType::Utf8, false));
+    columns.push(Arc::new(StringArray::from(metric)));
+    fields.push(Field::new("parameter", DataType::Float64, false));
+    columns.push(Arc::new(Float64Array::from(parameter)));
+    fields.push(Field::new("value", DataType::Float64, false));
+    columns.push(Arc::new(Float64Array::from(value)));
+    fields.push(Field::new("outcomes", DataType::UInt64, false));
+    columns.push(Arc::new(UInt64Array::from(count)));
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Float64Array, Int32Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::risk::{cvar, probability, return_period, risk_table, RiskError, RiskSpec, Side};
+
+    #[test]
+    fn metrics() {
+        let yields = [3000.0, 1000.0, f64::NAN, 2500.0, 4000.0, 1500.0];
+        assert_eq!(probability(&yields, 2000.0, Side::Below), 0.4);
+        assert_eq!(return_period(&yields, 2000.0, Side::Below), 2.5);
+        assert!(return_period(&yields, 500.0, Side::Below).is_infinite());
+        assert!(probability(&[], 2000.0, Side::Below).is_nan());
+        assert_eq!(cvar(&yields, 0.4, Side::Below).unwrap(), 1250.0);
+        assert_eq!(cvar(&yields, 0.01, Side::Below).unwrap(), 1000.0);
+        assert_eq!(cvar(&yields, 0.2, Side::Above).unwrap(), 4000.0);
+        assert!(matches!(
+            cvar(&yields, 0.0, Side::Below),
+            Err(RiskError::InvalidLevel(_))
+        ));
+    }
+
+    #[test]
+    fn tidy_table() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("site", DataType::Utf8, false),
+            Field::new("member", DataType::Int32, false),
+            Field::new("yield", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["a", "b", "a", "b", "a", "b"])),
+            Arc::new(Int32Array::from(vec![0, 0, 1, 1, 2, 2])),
+            Arc::new(Float32Array::from(vec![
+                1000.0, 3000.0, 2500.0, 3500.0, 3000.0, 1000.0,
+            ])),
+        ];
+        let batch = RecordBatch::try_new(schema, columns).unwrap();
+        let spec = RiskSpec {
+            thresholds: vec![2000.0],
+            return_periods: vec![2000.0],
+            cvar_levels: vec![0.5],
+            side: Side::Below,
+        };
+        let table = risk_table(&batch, "yield", &["site"], &spec).unwrap();
+        assert_eq!(table.num_rows(), 6);
+        let text = |i: usize| {
+            let c = table.column(i);
+            let c = c.as_any().downcast_ref::<StringArray>().unwrap();
+            c.iter().map(|v| v.unwrap().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(text(0), vec!["a", "a", "a", "b", "b", "b"]);
+        assert_eq!(
+            text(1),
+            vec![
+                "probability",
+                "return_period",
+                "cvar",
+                "probability",
+                "return_period",
+                "cvar"
+            ]
+        );
+        let value = table
+            .column(3)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(
+            value.values(),
+            &[1.0 / 3.0, 3.0, 1750.0, 1.0 / 3.0, 3.0, 2000.0]
+        );
+
+        let whole = risk_table(&batch, "yield", &[], &spec).unwrap();
+        assert_eq!(whole.num_rows(), 3);
+        assert!(matches!(
+            risk_table(&batch, "yield", &["year"], &spec),
+            Err(RiskError::MissingGroup(_))
+        ));
+    }
+}
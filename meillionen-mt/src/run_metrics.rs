@@ -0,0 +1,197 @@
+//! Scores computed from the outputs of each run
+//!
+//! A [`MetricRegistry`] holds named functions of a run's sink tables, such
+//! as the final yield or the error against observations, that
+//! [`crate::experiment::RunSpec::run_with_metrics`] evaluates once each trial
+//! succeeds. The values are kept in [`crate::experiment::RunOutcome::metrics`],
+//! can be written to the run database and turned into the objectives of a
+//! calibration with [`objectives`].
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use arrow::record_batch::RecordBatch;
+use thiserror::Error;
+
+use crate::timeseries::numeric_values;
+use crate::variable::Reduction;
+
+#[derive(Debug, Error)]
+pub enum MetricError {
+    #[error("metric {0} is already registered")]
+    Duplicate(String),
+    #[error("run has no metric {0}")]
+    Missing(String),
+}
+
+/// The tables of each tabular sink of a run
+pub type Sinks = BTreeMap<String, Vec<RecordBatch>>;
+
+type MetricFn = Box<dyn Fn(&Sinks) -> Result<f64, String> + Send + Sync>;
+
+/// Named functions computing a value from the outputs of a run
+#[derive(Default)]
+pub struct MetricRegistry {
+    metrics: Vec<(String, MetricFn)>,
+}
+
+impl fmt::Debug for MetricRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricRegistry")
+            .field("metrics", &self.names())
+            .finish()
+    }
+}
+
+impl MetricRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a metric, which returns an error message if it cannot be computed
+    /// from a run's outputs
+    pub fn register<F>(&mut self, name: &str, metric: F) -> Result<(), MetricError>
+    where
+        F: Fn(&Sinks) -> Result<f64, String> + Send + Sync + 'static,
+    {
+        if self.metrics.iter().any(|(n, _)| n == name) {
+            return Err(MetricError::Duplicate(name.to_string()));
+        }
+        self.metrics.push((name.to_string(), Box::new(metric)));
+        Ok(())
+    }
+
+    /// Add a metric reducing a numeric column of a sink, the last non-NaN
+    /// value when `reduction` is `None`
+    pub fn register_column(
+        &mut self,
+        name: &str,
+        sink: &str,
+        column: &str,
+        reduction: Option<Reduction>,
+    ) -> Result<(), MetricError> {
+        let (sink, column) = (sink.to_string(), column.to_string());
+        self.register(name, move |sinks| {
+            let batches = sinks
+                .get(&sink)
+                .ok_or_else(|| format!("run has no tabular sink {}", sink))?;
+            let mut values = vec![];
+            for batch in batches.iter() {
+                let column_values = numeric_values(batch, &column)
+                    .ok_or_else(|| format!("sink {} has no numeric column {}", sink, column))?;
+                values.extend(column_values.into_iter().filter(|v| !v.is_nan()));
+            }
+            if values.is_empty() {
+                return Err(format!("column {} of sink {} has no values", column, sink));
+            }
+            Ok(match reduction {
+                Some(r) => r.apply(&mut values),
+                None => values[values.len() - 1],
+            })
+        })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.metrics.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+
+    /// The value of every metric on a run's sinks
+    ///
+    /// A metric that fails is logged and left out so one bad metric does not
+    /// lose the others.
+    pub fn evaluate(&self, sinks: &Sinks) -> BTreeMap<String, f64> {
+        let mut values = BTreeMap::new();
+        for (name, metric) in self.metrics.iter() {
+            match metric(sinks) {
+                Ok(value) => {
+                    values.insert(name.clone(), value);
+                }
+                Err(message) => tracing::warn!(metric = %name, "metric failed: {}", message),
+            }
+        }
+        values
+    }
+}
+
+/// The values of the named metrics in order, such as to return from the
+/// evaluation function of [`crate::calibration::nsga2`]
+pub fn objectives(
+    metrics: &BTreeMap<String, f64>,
+    names: &[&str],
+) -> Result<Vec<f64>, MetricError> {
+    names
+        .iter()
+        .map(|n| {
+            metrics
+                .get(*n)
+                .copied()
+                .ok_or_else(|| MetricError::Missing(n.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::run_metrics::{objectives, MetricError, MetricRegistry, Sinks};
+    use crate::variable::Reduction;
+
+    #[test]
+    fn evaluate_metrics() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("yield", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1, 2, 3])),
+            Arc::new(Float32Array::from(vec![0.0, 1500.0, 2000.0])),
+        ];
+        let mut sinks: Sinks = BTreeMap::new();
+        sinks.insert(
+            "daily".to_string(),
+            vec![RecordBatch::try_new(schema, columns).unwrap()],
+        );
+
+        let mut registry = MetricRegistry::new();
+        registry
+            .register_column("final_yield", "daily", "yield", None)
+            .unwrap();
+        registry
+            .register_column("peak_yield", "daily", "yield", Some(Reduction::Max))
+            .unwrap();
+        registry
+            .register("yield_gap", |sinks| {
+                Ok(2500.0 - sinks["daily"][0].num_rows() as f64 * 500.0)
+            })
+            .unwrap();
+        registry
+            .register_column("lai", "daily", "plant_leaf_area_index", None)
+            .unwrap();
+        assert!(matches!(
+            registry.register("lai", |_| Ok(0.0)),
+            Err(MetricError::Duplicate(_))
+        ));
+
+        let metrics = registry.evaluate(&sinks);
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics["final_yield"], 2000.0);
+        assert_eq!(
+            objectives(&metrics, &["yield_gap", "peak_yield"]).unwrap(),
+            vec![1000.0, 2000.0]
+        );
+        assert!(matches!(
+            objectives(&metrics, &["lai"]),
+            Err(MetricError::Missing(_))
+        ));
+    }
+}
@@ -0,0 +1,331 @@
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::calibration::Parameter;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum SamplingError {
+    #[error("bounds of {name} must be finite with lower <= upper but are {lower} and {upper}")]
+    InvalidBounds {
+        name: String,
+        lower: f64,
+        upper: f64,
+    },
+    #[error("sobol sequences support at most {max} parameters but {count} were given, use halton or latin_hypercube")]
+    TooManyParameters { count: usize, max: usize },
+}
+
+/// Primitive polynomials and initial direction numbers of the Sobol sequence
+/// from dimension 2 on, as (degree, coefficients, m) from Joe and Kuo's
+/// new-joe-kuo-6.21201
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+const SOBOL_BITS: usize = 32;
+
+/// How points are spread over the parameter space
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingMethod {
+    LatinHypercube,
+    Sobol,
+    Halton,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    pub method: SamplingMethod,
+    pub samples: usize,
+    pub seed: u64,
+    /// Randomly shift Sobol and Halton points so repeated designs differ,
+    /// latin hypercubes are always random
+    pub scramble: bool,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            method: SamplingMethod::LatinHypercube,
+            samples: 100,
+            seed: 0,
+            scramble: false,
+        }
+    }
+}
+
+/// `n` points of a latin hypercube in the unit cube, with one point in each
+/// of the `n` intervals of every dimension
+pub fn latin_hypercube(dimensions: usize, n: usize, rng: &mut StdRng) -> Vec<Vec<f64>> {
+    let mut points = vec![vec![0.0; dimensions]; n];
+    let mut strata: Vec<usize> = (0..n).collect();
+    for d in 0..dimensions {
+        strata.shuffle(rng);
+        for (point, &s) in points.iter_mut().zip(strata.iter()) {
+            point[d] = (s as f64 + rng.gen::<f64>()) / n as f64;
+        }
+    }
+    points
+}
+
+fn sobol_directions(dimension: usize) -> [u32; SOBOL_BITS] {
+    let mut v = [0u32; SOBOL_BITS];
+    if dimension == 0 {
+        for (i, x) in v.iter_mut().enumerate() {
+            *x = 1 << (SOBOL_BITS - 1 - i);
+        }
+        return v;
+    }
+    let (s, a, m) = SOBOL_DIRECTIONS[dimension - 1];
+    let s = s as usize;
+    for i in 0..SOBOL_BITS {
+        v[i] = if i < s {
+            m[i] << (SOBOL_BITS - 1 - i)
+        } else {
+            let mut x = v[i - s] ^ (v[i - s] >> s);
+            for k in 1..s {
+                if (a >> (s - 1 - k)) & 1 == 1 {
+                    x ^= v[i - k];
+                }
+            }
+            x
+        };
+    }
+    v
+}
+
+/// The first `n` points of the Sobol sequence in the unit cube, starting at
+/// the origin
+///
+/// With a shift each coordinate is xored with random bits, which keeps the
+/// balance of the sequence.
+pub fn sobol(
+    dimensions: usize,
+    n: usize,
+    shift: Option<&mut StdRng>,
+) -> Result<Vec<Vec<f64>>, SamplingError> {
+    let max = SOBOL_DIRECTIONS.len() + 1;
+    if dimensions > max {
+        return Err(SamplingError::TooManyParameters {
+            count: dimensions,
+            max,
+        });
+    }
+    let directions: Vec<[u32; SOBOL_BITS]> = (0..dimensions).map(sobol_directions).collect();
+    let shifts: Vec<u32> = match shift {
+        Some(rng) => (0..dimensions).map(|_| rng.gen()).collect(),
+        None => vec![0; dimensions],
+    };
+    let scale = (1u64 << SOBOL_BITS) as f64;
+    let mut x = vec![0u32; dimensions];
+    let mut points = Vec::with_capacity(n);
+    for i in 0..n {
+        if i > 0 {
+            // the bit to flip is the lowest zero bit of the previous index
+            let c = (!(i - 1)).trailing_zeros() as usize;
+            for (x, v) in x.iter_mut().zip(directions.iter()) {
+                *x ^= v[c];
+            }
+        }
+        points.push(
+            x.iter()
+                .zip(shifts.iter())
+                .map(|(x, s)| f64::from(x ^ s) / scale)
+                .collect(),
+        );
+    }
+    Ok(points)
+}
+
+fn primes(n: usize) -> Vec<u64> {
+    let mut primes: Vec<u64> = vec![];
+    let mut candidate = 2;
+    while primes.len() < n {
+        if primes.iter().all(|p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+fn radical_inverse(mut i: u64, base: u64) -> f64 {
+    let mut inverse = 0.0;
+    let mut f = 1.0 / base as f64;
+    while i > 0 {
+        inverse += (i % base) as f64 * f;
+        i /= base;
+        f /= base as f64;
+    }
+    inverse
+}
+
+/// The first `n` points of the Halton sequence in the unit cube, skipping the
+/// origin
+///
+/// With a shift every coordinate is moved by a random amount, wrapping
+/// around at one.
+pub fn halton(dimensions: usize, n: usize, shift: Option<&mut StdRng>) -> Vec<Vec<f64>> {
+    let bases = primes(dimensions);
+    let shifts: Vec<f64> = match shift {
+        Some(rng) => (0..dimensions).map(|_| rng.gen()).collect(),
+        None => vec![0.0; dimensions],
+    };
+    (1..=n as u64)
+        .map(|i| {
+            bases
+                .iter()
+                .zip(shifts.iter())
+                .map(|(&b, s)| (radical_inverse(i, b) + s).fract())
+                .collect()
+        })
+        .collect()
+}
+
+/// Parameter sets for a sweep or sensitivity analysis, one row per sample
+/// with the parameters in order
+pub fn sample(
+    parameters: &[Parameter],
+    config: &SamplingConfig,
+) -> Result<Vec<Vec<f64>>, SamplingError> {
+    for p in parameters {
+        if !(p.lower.is_finite() && p.upper.is_finite() && p.lower <= p.upper) {
+            return Err(SamplingError::InvalidBounds {
+                name: p.name.clone(),
+                lower: p.lower,
+                upper: p.upper,
+            });
+        }
+    }
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let d = parameters.len();
+    let n = config.samples;
+    let mut points = match config.method {
+        SamplingMethod::LatinHypercube => latin_hypercube(d, n, &mut rng),
+        SamplingMethod::Sobol => sobol(d, n, Some(&mut rng).filter(|_| config.scramble))?,
+        SamplingMethod::Halton => halton(d, n, Some(&mut rng).filter(|_| config.scramble)),
+    };
+    for point in points.iter_mut() {
+        for (x, p) in point.iter_mut().zip(parameters) {
+            *x = p.lower + *x * (p.upper - p.lower);
+        }
+    }
+    Ok(points)
+}
+
+/// A table with a `sample` column numbering the rows and a column per
+/// parameter
+pub fn samples_to_recordbatch(
+    parameters: &[Parameter],
+    samples: &[Vec<f64>],
+) -> Result<RecordBatch, ArrowError> {
+    let mut fields = vec![Field::new("sample", DataType::UInt32, false)];
+    let mut columns: Vec<ArrayRef> = vec![Arc::new(UInt32Array::from(
+        (0..samples.len() as u32).collect::<Vec<_>>(),
+    ))];
+    for (i, p) in parameters.iter().enumerate() {
+        fields.push(Field::new(&p.name, DataType::Float64, false));
+        columns.push(Arc::new(Float64Array::from(
+            samples.iter().map(|s| s[i]).collect::<Vec<_>>(),
+        )));
+    }
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::Parameter;
+    use crate::sampling::{sample, sobol, SamplingConfig, SamplingError, SamplingMethod};
+
+    #[test]
+    fn designs_cover_the_space() {
+        let parameters = vec![
+            Parameter::new("cn", 50.0, 90.0),
+            Parameter::new("drnp", 0.0, 1.0),
+        ];
+        let lhs = SamplingConfig {
+            samples: 10,
+            seed: 7,
+            ..SamplingConfig::default()
+        };
+        let points = sample(&parameters, &lhs).unwrap();
+        assert_eq!(points, sample(&parameters, &lhs).unwrap());
+        let mut strata: Vec<usize> = points
+            .iter()
+            .map(|p| ((p[0] - 50.0) / 4.0) as usize)
+            .collect();
+        strata.sort_unstable();
+        assert_eq!(strata, (0..10).collect::<Vec<_>>());
+
+        let first: Vec<Vec<f64>> = sobol(2, 5, None).unwrap();
+        assert_eq!(
+            first,
+            vec![
+                vec![0.0, 0.0],
+                vec![0.5, 0.5],
+                vec![0.75, 0.25],
+                vec![0.25, 0.75],
+                vec![0.375, 0.375]
+            ]
+        );
+        // every dimension of the first 2^k points hits each 1/2^k interval once
+        let points = sobol(21, 64, None).unwrap();
+        for d in 0..21 {
+            let mut cells: Vec<usize> = points.iter().map(|p| (p[d] * 64.0) as usize).collect();
+            cells.sort_unstable();
+            assert_eq!(cells, (0..64).collect::<Vec<_>>());
+        }
+        assert!(matches!(
+            sobol(22, 1, None),
+            Err(SamplingError::TooManyParameters { max: 21, .. })
+        ));
+
+        let halton = SamplingConfig {
+            method: SamplingMethod::Halton,
+            samples: 2,
+            ..SamplingConfig::default()
+        };
+        assert_eq!(
+            sample(&parameters, &halton).unwrap()[1],
+            vec![60.0, 2.0 / 3.0]
+        );
+        let shifted = SamplingConfig {
+            scramble: true,
+            ..halton
+        };
+        assert_ne!(
+            sample(&parameters, &shifted).unwrap(),
+            sample(&parameters, &halton).unwrap()
+        );
+        assert!(sample(&[Parameter::new("lai", 1.0, 0.0)], &lhs).is_err());
+    }
+}
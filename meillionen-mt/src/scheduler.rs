@@ -0,0 +1,277 @@
+//! Running tasks of uneven cost on a pool of threads
+//!
+//! Grid cells and trials can differ in runtime by orders of magnitude: a
+//! masked cell returns at once while a wet one iterates its water balance.
+//! Giving each thread a fixed chunk leaves threads idle while the one that got
+//! the slow cells finishes. Here each thread has its own queue and takes tasks
+//! from the others once it runs out. With cost hints the costliest tasks start
+//! first, so none is left running alone at the end.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crossbeam_deque::{Steal, Stealer, Worker};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::concurrency::ConcurrencyLimit;
+use crate::variable::reduce::Reduction;
+
+/// How long one task took and where it ran
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TaskTiming {
+    pub task: usize,
+    pub worker: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hint: Option<f64>,
+    pub seconds: f64,
+    /// Whether the task was taken from another worker's queue
+    pub stolen: bool,
+}
+
+/// The timing of every task of a run, to see how evenly the work was spread
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct LoadReport {
+    pub workers: usize,
+    /// Wall clock seconds from the first task starting to the last finishing
+    pub seconds: f64,
+    /// By task index
+    pub tasks: Vec<TaskTiming>,
+}
+
+impl LoadReport {
+    /// Seconds spent running tasks, by worker
+    pub fn busy(&self) -> Vec<f64> {
+        let mut busy = vec![0.0; self.workers];
+        for t in self.tasks.iter() {
+            busy[t.worker] += t.seconds;
+        }
+        busy
+    }
+
+    /// The busiest worker's time over the mean, 1 when the load is even
+    pub fn imbalance(&self) -> f64 {
+        let busy = self.busy();
+        let mean = busy.iter().sum::<f64>() / busy.len().max(1) as f64;
+        if mean > 0.0 {
+            busy.iter().copied().fold(0.0, f64::max) / mean
+        } else {
+            1.0
+        }
+    }
+
+    pub fn steals(&self) -> usize {
+        self.tasks.iter().filter(|t| t.stolen).count()
+    }
+
+    /// A quantile of the task times, between 0 and 1
+    pub fn task_seconds(&self, q: f64) -> f64 {
+        let mut seconds: Vec<f64> = self.tasks.iter().map(|t| t.seconds).collect();
+        Reduction::Quantile(q).apply(&mut seconds)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} tasks on {} workers in {:.3}s, {} stolen",
+            self.tasks.len(),
+            self.workers,
+            self.seconds,
+            self.steals()
+        )?;
+        writeln!(
+            f,
+            "task seconds: min {:.3}, median {:.3}, p95 {:.3}, max {:.3}",
+            self.task_seconds(0.0),
+            self.task_seconds(0.5),
+            self.task_seconds(0.95),
+            self.task_seconds(1.0)
+        )?;
+        let busy = self.busy();
+        write!(
+            f,
+            "busy seconds per worker: {}, imbalance {:.2}",
+            busy.iter()
+                .map(|b| format!("{:.3}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+            self.imbalance()
+        )
+    }
+}
+
+/// A pool of threads that steal work from each other
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    workers: usize,
+    limit: Option<Arc<ConcurrencyLimit>>,
+}
+
+impl Scheduler {
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+            limit: None,
+        }
+    }
+
+    /// Only run as many tasks at once as `limit` allows, which may be fewer
+    /// than the workers, see [`crate::concurrency`]
+    pub fn limited(mut self, limit: Arc<ConcurrencyLimit>) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// One worker per available CPU
+    pub fn available() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Run `f` on every task, returning the results in task order
+    pub fn run<T, R, F>(&self, tasks: &[T], f: F) -> (Vec<R>, LoadReport)
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        self.run_with_hints(tasks, &[], f)
+    }
+
+    /// Run `f` on every task, starting the tasks with the largest cost hints
+    /// first
+    ///
+    /// Hints are in any unit, such as expected seconds or active cells. Tasks
+    /// without one are taken to cost the mean of the others.
+    pub fn run_with_hints<T, R, F>(
+        &self,
+        tasks: &[T],
+        hints: &[Option<f64>],
+        f: F,
+    ) -> (Vec<R>, LoadReport)
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync,
+    {
+        let hint = |i: usize| hints.get(i).copied().flatten();
+        let given: Vec<f64> = (0..tasks.len()).filter_map(hint).collect();
+        let mean = given.iter().sum::<f64>() / given.len().max(1) as f64;
+        let mut order: Vec<usize> = (0..tasks.len()).collect();
+        // stable, so equal costs keep the order they were given in
+        order.sort_by(|a, b| {
+            let cost = |i| hint(i).unwrap_or(mean);
+            cost(*b)
+                .partial_cmp(&cost(*a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let workers = self.workers.min(tasks.len()).max(1);
+        let queues: Vec<Worker<usize>> = (0..workers).map(|_| Worker::new_fifo()).collect();
+        for (n, i) in order.into_iter().enumerate() {
+            queues[n % workers].push(i);
+        }
+        let stealers: Vec<Stealer<usize>> = queues.iter().map(Worker::stealer).collect();
+
+        let start = Instant::now();
+        let done: Vec<Vec<(R, TaskTiming)>> = std::thread::scope(|s| {
+            let handles: Vec<_> = queues
+                .into_iter()
+                .enumerate()
+                .map(|(worker, queue)| {
+                    let (stealers, f, hint, limit) = (&stealers, &f, &hint, &self.limit);
+                    s.spawn(move || {
+                        let mut done = vec![];
+                        while let Some((task, stolen)) = queue
+                            .pop()
+                            .map(|i| (i, false))
+                            .or_else(|| steal(stealers, worker).map(|i| (i, true)))
+                        {
+                            let permit = limit.as_ref().map(|l| l.acquire());
+                            let started = Instant::now();
+                            let result = f(&tasks[task]);
+                            drop(permit);
+                            let timing = TaskTiming {
+                                task,
+                                worker,
+                                hint: hint(task),
+                                seconds: started.elapsed().as_secs_f64(),
+                                stolen,
+                            };
+                            done.push((result, timing));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("task not to panic"))
+                .collect()
+        });
+
+        let mut done: Vec<(R, TaskTiming)> = done.into_iter().flatten().collect();
+        done.sort_by_key(|(_, t)| t.task);
+        let (results, timings) = done.into_iter().unzip();
+        let report = LoadReport {
+            workers,
+            seconds: start.elapsed().as_secs_f64(),
+            tasks: timings,
+        };
+        (results, report)
+    }
+}
+
+/// A task from the queue of another worker, looking at the next worker first
+fn steal(stealers: &[Stealer<usize>], worker: usize) -> Option<usize> {
+    let n = stealers.len();
+    for k in 1..n {
+        let stealer = &stealers[(worker + k) % n];
+        loop {
+            match stealer.steal() {
+                Steal::Success(i) => return Some(i),
+                Steal::Empty => break,
+                Steal::Retry => continue,
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use crate::scheduler::Scheduler;
+
+    #[test]
+    fn steal_from_slow_worker() {
+        let cells: Vec<usize> = (0..100).collect();
+        let (results, report) = Scheduler::new(4).run(&cells, |c| {
+            // one wet cell takes far longer than the rest
+            if *c == 0 {
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            c * 2
+        });
+        assert_eq!(results, (0..100).map(|c| c * 2).collect::<Vec<_>>());
+        assert_eq!(report.tasks.len(), 100);
+        let slow = report.tasks[0].worker;
+        let on_slow = report.tasks.iter().filter(|t| t.worker == slow).count();
+        // the worker with the slow cell would otherwise have run 25
+        assert!(on_slow < 25, "{}", on_slow);
+        assert!(report.steals() > 0);
+        assert!(report.imbalance() >= 1.0);
+
+        let ran = Mutex::new(vec![]);
+        let hints = [Some(1.0), None, Some(5.0), Some(4.0)];
+        Scheduler::new(1).run_with_hints(&[0, 1, 2, 3], &hints, |t| ran.lock().unwrap().push(*t));
+        assert_eq!(ran.into_inner().unwrap(), vec![2, 3, 1, 0]);
+    }
+}
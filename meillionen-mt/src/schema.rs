@@ -1,37 +1,83 @@
-#![allow(dead_code)]
+use serde_json::{Map, Value};
 
-use arrow::datatypes::DataType;
+use crate::experiment::ExperimentError;
 
-pub trait DataTypeValidator {
-    fn validate(&self, dt: &DataType) -> bool;
+/// The schema version stamped on experiment files and run manifests written
+/// by this version of meillionen
+///
+/// Bump it and add a migration whenever a change would stop older files from
+/// loading.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Upgrades a document from one schema version to the next
+pub type Migration = fn(&mut Map<String, Value>);
+
+/// Files written before versions were stamped count as version 1. Every field
+/// added since has a default so they need no changes.
+fn unversioned(_: &mut Map<String, Value>) {}
+
+/// `EXPERIMENT_MIGRATIONS[i]` upgrades an experiment file from version `i + 1`
+pub const EXPERIMENT_MIGRATIONS: &[Migration] = &[unversioned];
+
+/// `MANIFEST_MIGRATIONS[i]` upgrades a run manifest from version `i + 1`
+pub const MANIFEST_MIGRATIONS: &[Migration] = &[unversioned];
+
+pub(crate) fn current_version() -> u32 {
+    SCHEMA_VERSION
 }
 
-#[derive(PartialEq, Debug)]
-pub enum NetCDFType {
-    /// A boolean datatype representing the values true and false.
-    Boolean,
-    /// A signed 8-bit integer.
-    Int8,
-    /// A signed 16-bit integer.
-    Int16,
-    /// A signed 32-bit integer.
-    Int32,
-    /// A signed 64-bit integer.
-    Int64,
-    /// An unsigned 8-bit integer.
-    UInt8,
-    /// An unsigned 16-bit integer.
-    UInt16,
-    /// An unsigned 32-bit integer.
-    UInt32,
-    /// An unsigned 64-bit integer.
-    UInt64,
-    /// A 32-bit floating point number.
-    Float32,
-    /// A 64-bit floating point number.
-    Float64,
+/// Upgrade a document to [`SCHEMA_VERSION`], failing if it was written by a
+/// newer meillionen
+pub fn migrate(mut value: Value, migrations: &[Migration]) -> Result<Value, ExperimentError> {
+    let document = match value.as_object_mut() {
+        Some(document) => document,
+        None => return Ok(value),
+    };
+    let found = match document.get("version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .map(|v| v as u32)
+            .ok_or_else(|| ExperimentError::InvalidVersion(v.to_string()))?,
+    };
+    if found == 0 || found > SCHEMA_VERSION {
+        return Err(ExperimentError::UnsupportedVersion {
+            found,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    for migration in migrations[found as usize - 1..].iter() {
+        migration(document);
+    }
+    document.insert("version".to_string(), SCHEMA_VERSION.into());
+    Ok(value)
 }
 
-pub struct PrimitiveField {
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
 
-}
\ No newline at end of file
+    use crate::experiment::{ExperimentConfig, ExperimentError};
+    use crate::schema::{migrate, EXPERIMENT_MIGRATIONS, SCHEMA_VERSION};
+
+    #[test]
+    fn versions() {
+        let old = json!({"name": "irrigation", "trials": []});
+        let migrated = migrate(old, EXPERIMENT_MIGRATIONS).unwrap();
+        assert_eq!(migrated["version"], json!(SCHEMA_VERSION));
+        let config: ExperimentConfig = serde_json::from_value(migrated).unwrap();
+        assert_eq!(config.version, SCHEMA_VERSION);
+
+        let current = json!({"version": SCHEMA_VERSION, "name": "irrigation", "trials": []});
+        assert!(migrate(current, EXPERIMENT_MIGRATIONS).is_ok());
+
+        let newer = json!({"version": SCHEMA_VERSION + 1, "name": "irrigation", "trials": []});
+        let e = migrate(newer, EXPERIMENT_MIGRATIONS).unwrap_err();
+        assert!(matches!(e, ExperimentError::UnsupportedVersion { .. }));
+        assert!(e.to_string().starts_with("unsupported schema version"));
+        assert!(matches!(
+            migrate(json!({"version": "two"}), EXPERIMENT_MIGRATIONS),
+            Err(ExperimentError::InvalidVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,421 @@
+//! Running the same treatments at many sites
+//!
+//! Agronomic trials repeat a set of treatments, such as irrigation levels or
+//! cultivars, at sites with their own weather, soil and planting dates. A
+//! [`MultiSiteConfig`] lists the [`Site`]s and the treatments once, as trials
+//! whose strings, like the path of the site's weather, can refer to the site
+//! being run:
+//!
+//! - `{site}` and `{treatment}`, the names of the site and treatment
+//! - `{latitude}`, `{longitude}` and `{elevation}`
+//! - `{soil.NAME}`, a soil parameter of the site
+//! - `{planting.earliest}` and `{planting.latest}`, the planting window
+//!
+//! [`MultiSiteConfig::experiment`] expands it to an ordinary experiment with a
+//! trial for each site and treatment, named `SITE.TREATMENT` and tagged with
+//! both, whose results [`stack_sites`] stacks with `site` and `treatment`
+//! columns. Treatments can also be made by crossing factors with a
+//! [`crate::design::Design`].
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::aliases::Aliases;
+use crate::design::{Design, DesignError};
+use crate::experiment::{
+    fill_placeholders, ExperimentConfig, ExperimentError, ResourceConfig, TrialConfig,
+};
+#[cfg(feature = "data")]
+use crate::experiment::{RunOutcome, TrialStatus};
+use crate::repro::ReproConfig;
+use crate::schema::current_version;
+#[cfg(feature = "data")]
+use crate::stack::{ResultStack, StackError, StackLayout};
+use crate::tags::{self, Tags};
+
+/// The source the weather of a site is given to unless configured otherwise
+pub const WEATHER_SOURCE: &str = "daily";
+
+#[derive(Debug, Error)]
+pub enum SiteError {
+    #[error("trial {trial} refers to {placeholder} which site {site} does not have")]
+    MissingParameter {
+        trial: String,
+        site: String,
+        placeholder: String,
+    },
+    #[error("treatment {treatment} has its own {source_name} source, which each site gives")]
+    WeatherClash {
+        treatment: String,
+        source_name: String,
+    },
+    #[error(transparent)]
+    Design(#[from] DesignError),
+    #[error(transparent)]
+    Experiment(#[from] ExperimentError),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Stack(#[from] StackError),
+}
+
+/// The days of the year a crop can be planted at a site
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PlantingWindow {
+    pub earliest: u32,
+    pub latest: u32,
+}
+
+/// A place treatments are run, with its weather, soil and planting dates
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Site {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<f64>,
+    pub weather: ResourceConfig,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub soil: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub planting: Option<PlantingWindow>,
+    /// Labels given to every trial at the site, such as its region
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+}
+
+impl Site {
+    /// The value of a placeholder for this site, `None` if the site lacks it
+    fn value(&self, treatment: &str, key: &str) -> Option<String> {
+        match key {
+            "site" => Some(self.name.clone()),
+            "treatment" => Some(treatment.to_string()),
+            "latitude" => Some(self.latitude.to_string()),
+            "longitude" => Some(self.longitude.to_string()),
+            "elevation" => self.elevation.map(|e| e.to_string()),
+            "planting.earliest" => self.planting.map(|p| p.earliest.to_string()),
+            "planting.latest" => self.planting.map(|p| p.latest.to_string()),
+            _ => key
+                .strip_prefix("soil.")
+                .and_then(|name| self.soil.get(name))
+                .map(|v| v.to_string()),
+        }
+    }
+
+    /// The value of a placeholder, see [`crate::experiment::fill_placeholders`]
+    fn placeholder(&self, treatment: &str, key: &str) -> Option<Option<String>> {
+        let known = matches!(
+            key,
+            "site"
+                | "treatment"
+                | "latitude"
+                | "longitude"
+                | "elevation"
+                | "planting.earliest"
+                | "planting.latest"
+        ) || key.starts_with("soil.");
+        known.then(|| self.value(treatment, key))
+    }
+}
+
+/// The name of the trial of a treatment at a site
+pub fn trial_name(site: &str, treatment: &str) -> String {
+    format!("{}.{}", site, treatment)
+}
+
+/// Treatments to run at each of a set of sites
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MultiSiteConfig {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repro: Option<ReproConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: Tags,
+    /// Names the outputs of every trial are written under, see
+    /// [`crate::aliases`]
+    #[serde(default, skip_serializing_if = "Aliases::is_empty")]
+    pub aliases: Aliases,
+    /// The source of the treatments the weather of each site is given to
+    #[serde(default = "default_weather_source")]
+    pub weather_source: String,
+    pub sites: Vec<Site>,
+    /// Trials run at every site, which may refer to the site's values
+    pub treatments: Vec<TrialConfig>,
+    /// Crosses factors to make a treatment of each combination of levels
+    /// from each of `treatments`, see [`crate::design`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub design: Option<Design>,
+}
+
+fn default_weather_source() -> String {
+    WEATHER_SOURCE.to_string()
+}
+
+impl MultiSiteConfig {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SiteError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(ExperimentError::from)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&text).map_err(ExperimentError::from)?),
+            Some("yaml") | Some("yml") => {
+                Ok(serde_yaml::from_str(&text).map_err(ExperimentError::from)?)
+            }
+            _ => Err(ExperimentError::UnknownFormat(path.display().to_string()).into()),
+        }
+    }
+
+    /// The treatments run at every site, made by the design if there is one
+    pub fn treatments(&self) -> Result<Vec<TrialConfig>, SiteError> {
+        match &self.design {
+            Some(design) => {
+                let mut treatments = vec![];
+                for template in self.treatments.iter() {
+                    treatments.extend(design.trials(template)?);
+                }
+                Ok(treatments)
+            }
+            None => Ok(self.treatments.clone()),
+        }
+    }
+
+    /// The experiment running every treatment at every site, site by site
+    ///
+    /// The experiment is validated, so sinks must name the site or treatment
+    /// in their paths to keep runs from writing over each other.
+    pub fn experiment(&self) -> Result<ExperimentConfig, SiteError> {
+        let treatments = self.treatments()?;
+        let mut trials = vec![];
+        for site in self.sites.iter() {
+            for treatment in treatments.iter() {
+                let name = trial_name(&site.name, &treatment.name);
+                if treatment.sources.contains_key(&self.weather_source) {
+                    return Err(SiteError::WeatherClash {
+                        treatment: treatment.name.clone(),
+                        source_name: self.weather_source.clone(),
+                    });
+                }
+                let mut trial = treatment.clone();
+                trial
+                    .sources
+                    .insert(self.weather_source.clone(), site.weather.clone());
+                let mut value = serde_json::to_value(&trial).map_err(ExperimentError::from)?;
+                fill_placeholders(&mut value, &|key| site.placeholder(&treatment.name, key))
+                    .map_err(|placeholder| SiteError::MissingParameter {
+                        trial: name.clone(),
+                        site: site.name.clone(),
+                        placeholder,
+                    })?;
+                let mut trial: TrialConfig =
+                    serde_json::from_value(value).map_err(ExperimentError::from)?;
+                trial.name = name;
+                let mut labels = Tags::new();
+                labels.insert("site".to_string(), site.name.clone());
+                labels.insert("treatment".to_string(), treatment.name.clone());
+                trial.tags = tags::merge(&tags::merge(&site.tags, &trial.tags), &labels);
+                trials.push(trial);
+            }
+        }
+        let experiment = ExperimentConfig {
+            version: current_version(),
+            name: self.name.clone(),
+            repro: self.repro.clone(),
+            store: None,
+            tags: self.tags.clone(),
+            aliases: self.aliases.clone(),
+            trials,
+            conservation: vec![],
+        };
+        experiment.validate()?;
+        Ok(experiment)
+    }
+}
+
+/// Stack a tabular sink of the succeeded runs of a multi-site experiment,
+/// with `site` and `treatment` key columns
+///
+/// Runs are matched to sites and treatments by their trial names. Returns
+/// `None` if no run has the sink.
+#[cfg(feature = "data")]
+pub fn stack_sites(
+    config: &MultiSiteConfig,
+    outcomes: &[RunOutcome],
+    sink: &str,
+    layout: StackLayout,
+) -> Result<Option<RecordBatch>, SiteError> {
+    let treatments = config.treatments()?;
+    let mut stack = ResultStack::new(&["site", "treatment"]);
+    for site in config.sites.iter() {
+        for treatment in treatments.iter() {
+            let name = trial_name(&site.name, &treatment.name);
+            let batches = outcomes
+                .iter()
+                .filter(|o| o.trial == name && o.status == TrialStatus::Succeeded)
+                .filter_map(|o| o.sinks.get(sink))
+                .flatten();
+            for batch in batches {
+                stack.push(&[&site.name, &treatment.name], batch.clone())?;
+            }
+        }
+    }
+    Ok(stack.finish(layout)?)
+}
+
+/// Run every treatment at every site in turn and stack their `sink`
+#[cfg(feature = "data")]
+pub fn run_sites(
+    config: &MultiSiteConfig,
+    sink: &str,
+    layout: StackLayout,
+) -> Result<(Vec<RunOutcome>, Option<RecordBatch>), SiteError> {
+    let outcomes = config
+        .experiment()?
+        .run_specs()
+        .iter()
+        .map(|spec| spec.run())
+        .collect::<Result<Vec<_>, _>>()?;
+    let stacked = stack_sites(config, &outcomes, sink, layout)?;
+    Ok((outcomes, stacked))
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::design::{Design, Factor, Level};
+    use crate::experiment::{ExperimentError, RunOutcome, TrialLogs, TrialStatus};
+    use crate::sites::{stack_sites, MultiSiteConfig, SiteError};
+    use crate::stack::StackLayout;
+
+    const CONFIG: &str = r#"
+name = "maize trials"
+
+[[sites]]
+name = "gainesville"
+latitude = 29.65
+longitude = -82.32
+soil = { curve_number = 55.0 }
+planting = { earliest = 110, latest = 140 }
+weather = { type = "feather", path = "weather/gainesville.feather" }
+
+[[sites]]
+name = "quincy"
+latitude = 30.59
+longitude = -84.58
+soil = { curve_number = 70.0 }
+weather = { type = "feather", path = "weather/{site}.feather" }
+
+[[treatments]]
+name = "rainfed"
+model = "simplecrop_omf"
+args = ["--curve-number", "{soil.curve_number}"]
+sinks = { yearly = { type = "feather", path = "out/{site}/{treatment}.feather" } }
+
+[[treatments]]
+name = "irrigated"
+model = "simplecrop_omf"
+args = ["--curve-number", "{soil.curve_number}", "--irrigate"]
+sinks = { yearly = { type = "feather", path = "out/{site}/{treatment}.feather" } }
+"#;
+
+    #[test]
+    fn expand_and_stack() {
+        let config: MultiSiteConfig = toml::from_str(CONFIG).unwrap();
+        let experiment = config.experiment().unwrap();
+        let names: Vec<&str> = experiment.trials.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "gainesville.rainfed",
+                "gainesville.irrigated",
+                "quincy.rainfed",
+                "quincy.irrigated"
+            ]
+        );
+        let trial = &experiment.trials[3];
+        assert_eq!(trial.args, vec!["--curve-number", "70", "--irrigate"]);
+        assert_eq!(trial.sinks["yearly"].path(), "out/quincy/irrigated.feather");
+        assert_eq!(trial.sources["daily"].path(), "weather/quincy.feather");
+        assert_eq!(trial.tags["site"], "quincy");
+        assert_eq!(trial.tags["treatment"], "irrigated");
+
+        let mut planted = config.clone();
+        planted.treatments[0].args = vec!["{planting.earliest}".to_string()];
+        assert!(matches!(
+            planted.experiment(),
+            Err(SiteError::MissingParameter { ref site, .. }) if site == "quincy"
+        ));
+        let mut clashing = config.clone();
+        for treatment in clashing.treatments.iter_mut() {
+            treatment.sinks = experiment.trials[0].sinks.clone();
+        }
+        assert!(matches!(
+            clashing.experiment(),
+            Err(SiteError::Experiment(ExperimentError::Invalid(_)))
+        ));
+
+        let mut crossed = config.clone();
+        crossed.treatments.truncate(1);
+        crossed.design = Some(Design::full(vec![Factor {
+            name: "cultivar".to_string(),
+            levels: vec![
+                Level::Text("pioneer".to_string()),
+                Level::Text("dekalb".to_string()),
+            ],
+        }]));
+        let crossed = crossed.experiment().unwrap();
+        assert_eq!(crossed.trials.len(), 4);
+        assert_eq!(crossed.trials[1].name, "gainesville.rainfed.dekalb");
+        assert_eq!(crossed.trials[1].tags["cultivar"], "dekalb");
+
+        let outcome = |trial: &str, value: f32| {
+            let schema = Arc::new(Schema::new(vec![Field::new(
+                "plant_matter_fruit",
+                DataType::Float32,
+                false,
+            )]));
+            let columns: Vec<ArrayRef> = vec![Arc::new(Float32Array::from(vec![value]))];
+            let mut sinks = BTreeMap::new();
+            sinks.insert(
+                "yearly".to_string(),
+                vec![RecordBatch::try_new(schema, columns).unwrap()],
+            );
+            RunOutcome {
+                trial: trial.to_string(),
+                status: TrialStatus::Succeeded,
+                sinks,
+                logs: TrialLogs::default(),
+                metrics: BTreeMap::new(),
+            }
+        };
+        let outcomes = vec![
+            outcome("quincy.irrigated", 4.0),
+            outcome("gainesville.rainfed", 1.0),
+            outcome("quincy.rainfed", 3.0),
+        ];
+        let stacked = stack_sites(&config, &outcomes, "yearly", StackLayout::Wide)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stacked.num_rows(), 3);
+        assert_eq!(stacked.schema().field(0).name(), "site");
+        assert_eq!(stacked.schema().field(1).name(), "treatment");
+        let fruit = stacked
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(fruit.values(), &[1.0, 3.0, 4.0]);
+        assert!(stack_sites(&config, &outcomes, "daily", StackLayout::Wide)
+            .unwrap()
+            .is_none());
+    }
+}
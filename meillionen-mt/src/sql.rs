@@ -0,0 +1,247 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use arrow::array::{
+    ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+    Int8Array, StringArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow::datatypes::DataType;
+use arrow::record_batch::RecordBatch;
+use rusqlite::types::Value;
+use rusqlite::{Connection, NO_PARAMS};
+use thiserror::Error;
+
+use crate::variable::table::to_recordbatch;
+use crate::variable::{Float, Variable};
+
+#[derive(Debug, Error)]
+pub enum SqlError {
+    #[error("column {0} has type {1:?} which cannot be stored in a table")]
+    UnsupportedType(String, DataType),
+    #[error("column {0} has value {1} which is too large for a sqlite integer")]
+    OutOfRange(String, u64),
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+fn sql_type(data_type: &DataType) -> Option<&'static str> {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => Some("INTEGER"),
+        DataType::Float32 | DataType::Float64 => Some("REAL"),
+        DataType::Utf8 => Some("TEXT"),
+        _ => None,
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+macro_rules! downcast_value {
+    ($array:expr, $row:expr, $T:ty, $V:path, $convert:expr) => {{
+        let array = $array
+            .as_any()
+            .downcast_ref::<$T>()
+            .expect("array type to match data type");
+        $V($convert(array.value($row)))
+    }};
+}
+
+fn value(name: &str, array: &ArrayRef, row: usize) -> Result<Value, SqlError> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    Ok(match array.data_type() {
+        DataType::Boolean => downcast_value!(array, row, BooleanArray, Value::Integer, i64::from),
+        DataType::Int8 => downcast_value!(array, row, Int8Array, Value::Integer, i64::from),
+        DataType::Int16 => downcast_value!(array, row, Int16Array, Value::Integer, i64::from),
+        DataType::Int32 => downcast_value!(array, row, Int32Array, Value::Integer, i64::from),
+        DataType::Int64 => downcast_value!(array, row, Int64Array, Value::Integer, i64::from),
+        DataType::UInt8 => downcast_value!(array, row, UInt8Array, Value::Integer, i64::from),
+        DataType::UInt16 => downcast_value!(array, row, UInt16Array, Value::Integer, i64::from),
+        DataType::UInt32 => downcast_value!(array, row, UInt32Array, Value::Integer, i64::from),
+        // sqlite integers are signed so the largest values don't fit
+        DataType::UInt64 => {
+            let v = array
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("array type to match data type")
+                .value(row);
+            Value::Integer(i64::try_from(v).map_err(|_| SqlError::OutOfRange(name.to_string(), v))?)
+        }
+        DataType::Float32 => downcast_value!(array, row, Float32Array, Value::Real, f64::from),
+        DataType::Float64 => downcast_value!(array, row, Float64Array, Value::Real, |v: f64| v),
+        DataType::Utf8 => downcast_value!(array, row, StringArray, Value::Text, str::to_string),
+        _ => unreachable!("column types are checked before inserting"),
+    })
+}
+
+/// Append a record batch to a SQLite table, creating the table if needed
+///
+/// Returns the number of rows inserted. The rows are inserted in a single
+/// transaction so a failed export does not leave a partial table behind.
+pub fn to_sql(conn: &mut Connection, table: &str, rb: &RecordBatch) -> Result<usize, SqlError> {
+    let schema = rb.schema();
+    let mut columns = Vec::with_capacity(schema.fields().len());
+    for field in schema.fields() {
+        let t = sql_type(field.data_type()).ok_or_else(|| {
+            SqlError::UnsupportedType(field.name().clone(), field.data_type().clone())
+        })?;
+        columns.push(format!("{} {}", quote(field.name()), t));
+    }
+    let names: Vec<String> = schema.fields().iter().map(|f| quote(f.name())).collect();
+    let placeholders: Vec<&str> = names.iter().map(|_| "?").collect();
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            quote(table),
+            columns.join(", ")
+        ),
+        NO_PARAMS,
+    )?;
+    {
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(table),
+            names.join(", "),
+            placeholders.join(", ")
+        ))?;
+        for row in 0..rb.num_rows() {
+            let values = schema
+                .fields()
+                .iter()
+                .zip(rb.columns())
+                .map(|(field, column)| value(field.name(), column, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            insert.execute(values)?;
+        }
+    }
+    tx.commit()?;
+    Ok(rb.num_rows())
+}
+
+/// Append a record batch to a table in a SQLite database file
+///
+/// DuckDB can attach the file with its sqlite extension.
+pub fn to_sqlite_file<P: AsRef<Path>>(
+    path: P,
+    table: &str,
+    rb: &RecordBatch,
+) -> Result<usize, SqlError> {
+    let mut conn = Connection::open(path)?;
+    to_sql(&mut conn, table, rb)
+}
+
+/// Append a variable to a SQLite table in long format
+///
+/// The table has one integer column per dimension and a `value` column.
+pub fn variable_to_sql<V>(
+    conn: &mut Connection,
+    table: &str,
+    variable: &V,
+) -> Result<usize, SqlError>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    let rb = to_recordbatch(variable, "value")?;
+    to_sql(conn, table, &rb)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array, StringArray, UInt64Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    use crate::sql::{to_sql, variable_to_sql, SqlError};
+    use crate::variable::{dim, VecVariable};
+
+    #[test]
+    fn append_batches() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, true),
+            Field::new("site", DataType::Utf8, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1, 2])),
+            Arc::new(Float32Array::from(vec![Some(0.5), None])),
+            Arc::new(StringArray::from(vec!["a", "a"])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(to_sql(&mut conn, "plant", &rb).unwrap(), 2);
+        to_sql(&mut conn, "plant", &rb).unwrap();
+        let (n, lai): (i64, f64) = conn
+            .query_row(
+                "SELECT count(*), sum(plant_leaf_area_index) FROM plant",
+                NO_PARAMS,
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((n, lai), (4, 1.0));
+    }
+
+    #[test]
+    fn long_format_variable() {
+        let v = VecVariable::new(vec![dim("x", 2), dim("t", 2)], vec![1.0, 2.0, 3.0, 4.0]).unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        variable_to_sql(&mut conn, "yield", &v).unwrap();
+        let total: f64 = conn
+            .query_row("SELECT sum(value) FROM yield WHERE x = 1", NO_PARAMS, |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(total, 7.0);
+    }
+
+    #[test]
+    fn unsupported_columns() {
+        let schema = Arc::new(Schema::new(vec![Field::new("d", DataType::Date32, false)]));
+        let columns: Vec<ArrayRef> = vec![Arc::new(arrow::array::Date32Array::from(vec![1]))];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            to_sql(&mut conn, "d", &rb),
+            Err(SqlError::UnsupportedType(_, DataType::Date32))
+        ));
+    }
+
+    #[test]
+    fn large_unsigned_values() {
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::UInt64, false)]));
+        let batch = |values: Vec<u64>| {
+            let columns: Vec<ArrayRef> = vec![Arc::new(UInt64Array::from(values))];
+            RecordBatch::try_new(schema.clone(), columns).unwrap()
+        };
+        let mut conn = Connection::open_in_memory().unwrap();
+        to_sql(&mut conn, "n", &batch(vec![i64::MAX as u64])).unwrap();
+        assert!(matches!(
+            to_sql(&mut conn, "n", &batch(vec![1, u64::MAX])),
+            Err(SqlError::OutOfRange(_, u64::MAX))
+        ));
+        // the failed batch is rolled back
+        let (rows, max): (i64, i64) = conn
+            .query_row("SELECT count(*), max(n) FROM n", NO_PARAMS, |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((rows, max), (1, i64::MAX));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, Float64Array};
+use arrow::compute::concat;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::timeseries::numeric_values;
+use crate::variable::table::{label_column, label_type};
+
+/// Schema metadata key holding the columns that index a stacked table
+pub const INDEX_KEY: &str = "meillionen-index";
+
+#[derive(Debug, Error)]
+pub enum StackError {
+    #[error("{got} keys given but the stack is keyed by {expected:?}")]
+    KeyLength { expected: Vec<String>, got: usize },
+    #[error("result for {key:?} has columns {got:?} but {expected:?} were expected")]
+    SchemaMismatch {
+        key: Vec<String>,
+        expected: Vec<String>,
+        got: Vec<String>,
+    },
+    #[error("index column {0} not found in the results")]
+    MissingColumn(String),
+    #[error("column {0} is {1:?} but long format values must be numeric")]
+    NotNumeric(String, DataType),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+}
+
+/// How `ResultStack::finish` lays out the stacked results
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum StackLayout {
+    /// One row per result row with a column per output variable
+    Wide,
+    /// One row per value with `variable` and `value` columns
+    Long,
+}
+
+/// The labels of one key, numbered in the order they were first pushed
+#[derive(Clone, Debug, Default)]
+struct KeyLabels {
+    labels: Vec<String>,
+    positions: HashMap<String, u64>,
+    rows: Vec<u64>,
+}
+
+impl KeyLabels {
+    fn push(&mut self, label: &str, rows: usize) {
+        let next = self.labels.len() as u64;
+        let position = *self.positions.entry(label.to_string()).or_insert(next);
+        if position == next {
+            self.labels.push(label.to_string());
+        }
+        self.rows.extend(std::iter::repeat_n(position, rows));
+    }
+}
+
+/// Collects the results of many runs, cells or scenarios into one table
+///
+/// Each result is pushed with a label for every key, such as its run id and
+/// cell. The stacked table has a dictionary encoded column per key, read as a
+/// categorical by pandas, followed by the result columns. The key columns and
+/// any result columns added with `index_by` are listed in the schema metadata
+/// under `INDEX_KEY` so python can build a MultiIndex like (run_id, cell, time).
+#[derive(Clone, Debug)]
+pub struct ResultStack {
+    keys: Vec<String>,
+    index: Vec<String>,
+    labels: Vec<KeyLabels>,
+    schema: Option<SchemaRef>,
+    batches: Vec<RecordBatch>,
+}
+
+impl ResultStack {
+    pub fn new(keys: &[&str]) -> Self {
+        Self {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+            index: vec![],
+            labels: vec![KeyLabels::default(); keys.len()],
+            schema: None,
+            batches: vec![],
+        }
+    }
+
+    /// Add a result column, such as `time`, to the index after the keys
+    pub fn index_by(mut self, column: &str) -> Self {
+        self.index.push(column.to_string());
+        self
+    }
+
+    /// The key and result columns that index the stacked table
+    pub fn index(&self) -> Vec<String> {
+        self.keys.iter().chain(self.index.iter()).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Add the result of one run
+    ///
+    /// Every result must have the same columns as the first.
+    pub fn push(&mut self, key: &[&str], result: RecordBatch) -> Result<(), StackError> {
+        if key.len() != self.keys.len() {
+            return Err(StackError::KeyLength {
+                expected: self.keys.clone(),
+                got: key.len(),
+            });
+        }
+        let schema = self.schema.clone().unwrap_or_else(|| result.schema());
+        // nullability can differ between runs that did and did not produce
+        // missing values
+        let same = |a: &Field, b: &Field| a.name() == b.name() && a.data_type() == b.data_type();
+        let fields = result.schema().fields().clone();
+        if schema.fields().len() != fields.len()
+            || !schema.fields().iter().zip(&fields).all(|(a, b)| same(a, b))
+        {
+            let names = |s: &Schema| s.fields().iter().map(|f| f.name().clone()).collect();
+            return Err(StackError::SchemaMismatch {
+                key: key.iter().map(|k| k.to_string()).collect(),
+                expected: names(&schema),
+                got: names(&result.schema()),
+            });
+        }
+        if let Some(missing) = self.index.iter().find(|c| schema.index_of(c).is_err()) {
+            return Err(StackError::MissingColumn(missing.clone()));
+        }
+        for (labels, label) in self.labels.iter_mut().zip(key) {
+            labels.push(label, result.num_rows());
+        }
+        if self.schema.is_none() {
+            self.schema = Some(schema);
+        }
+        self.batches.push(result);
+        Ok(())
+    }
+
+    /// Join the results into one table
+    ///
+    /// Returns `None` if nothing was pushed.
+    pub fn finish(&self, layout: StackLayout) -> Result<Option<RecordBatch>, StackError> {
+        let schema = match &self.schema {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let mut fields: Vec<Field> = self
+            .keys
+            .iter()
+            .map(|k| Field::new(k, label_type(), false))
+            .collect();
+        let mut columns: Vec<ArrayRef> = self
+            .labels
+            .iter()
+            .map(|l| label_column(l.rows.clone(), &l.labels))
+            .collect();
+        for (i, field) in schema.fields().iter().enumerate() {
+            let parts: Vec<&dyn Array> =
+                self.batches.iter().map(|b| b.column(i).as_ref()).collect();
+            fields.push(Field::new(field.name(), field.data_type().clone(), true));
+            columns.push(concat(&parts)?);
+        }
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            INDEX_KEY.to_string(),
+            serde_json::to_string(&self.index()).expect("index to serialize"),
+        );
+        let wide = RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, metadata)),
+            columns,
+        )?;
+        match layout {
+            StackLayout::Wide => Ok(Some(wide)),
+            StackLayout::Long => self.melt(&wide).map(Some),
+        }
+    }
+
+    /// Stack the non index columns into `variable` and `value` columns
+    fn melt(&self, wide: &RecordBatch) -> Result<RecordBatch, StackError> {
+        let index = self.index();
+        let schema = wide.schema();
+        let variables: Vec<String> = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .filter(|n| !index.contains(n))
+            .collect();
+        let mut values = Vec::with_capacity(wide.num_rows() * variables.len());
+        for name in variables.iter() {
+            let column = numeric_values(wide, name).ok_or_else(|| {
+                let i = schema.index_of(name).expect("column to be in schema");
+                StackError::NotNumeric(name.clone(), wide.column(i).data_type().clone())
+            })?;
+            values.extend(column);
+        }
+
+        let mut fields = vec![];
+        let mut columns = vec![];
+        for name in index.iter() {
+            let i = schema.index_of(name).expect("index column to be in schema");
+            let column = wide.column(i);
+            let parts = vec![column.as_ref(); variables.len()];
+            fields.push(schema.field(i).clone());
+            columns.push(concat(&parts)?);
+        }
+        let rows: Vec<u64> = (0..variables.len() as u64)
+            .flat_map(|v| std::iter::repeat_n(v, wide.num_rows()))
+            .collect();
+        fields.push(Field::new("variable", label_type(), false));
+        columns.push(label_column(rows, &variables));
+        fields.push(Field::new("value", DataType::Float64, true));
+        columns.push(Arc::new(Float64Array::from(values)));
+
+        let mut metadata = schema.metadata().clone();
+        let mut long_index = index;
+        long_index.push("variable".to_string());
+        metadata.insert(
+            INDEX_KEY.to_string(),
+            serde_json::to_string(&long_index).expect("index to serialize"),
+        );
+        Ok(RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, metadata)),
+            columns,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, DictionaryArray, Float32Array, Float64Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Int32Type, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::stack::{ResultStack, StackError, StackLayout, INDEX_KEY};
+
+    fn result(day: Vec<i32>, lai: Vec<f32>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("lai", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(day)),
+            Arc::new(Float32Array::from(lai)),
+        ];
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    #[test]
+    fn stack_runs_and_cells() {
+        let mut stack = ResultStack::new(&["run_id", "cell"]).index_by("day");
+        assert!(stack.finish(StackLayout::Wide).unwrap().is_none());
+        stack
+            .push(&["r1", "0"], result(vec![1, 2], vec![0.5, 1.0]))
+            .unwrap();
+        stack
+            .push(&["r1", "1"], result(vec![1], vec![0.25]))
+            .unwrap();
+        stack
+            .push(&["r2", "0"], result(vec![1], vec![0.75]))
+            .unwrap();
+        assert_eq!(stack.len(), 3);
+        assert!(matches!(
+            stack.push(&["r3"], result(vec![], vec![])),
+            Err(StackError::KeyLength { .. })
+        ));
+
+        let wide = stack.finish(StackLayout::Wide).unwrap().unwrap();
+        assert_eq!(wide.num_rows(), 4);
+        assert_eq!(
+            wide.schema().metadata()[INDEX_KEY],
+            r#"["run_id","cell","day"]"#
+        );
+        let run = wide
+            .column(0)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        assert_eq!(run.keys().values(), &[0, 0, 0, 1]);
+        let cell = wide
+            .column(1)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        assert_eq!(cell.keys().values(), &[0, 0, 1, 0]);
+
+        let long = stack.finish(StackLayout::Long).unwrap().unwrap();
+        assert_eq!(long.num_rows(), 4);
+        assert_eq!(long.num_columns(), 5);
+        let value = long
+            .column(4)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(value.values(), &[0.5, 1.0, 0.25, 0.75]);
+
+        let mut other = ResultStack::new(&["run_id"]).index_by("time");
+        assert!(matches!(
+            other.push(&["r1"], result(vec![1], vec![0.5])),
+            Err(StackError::MissingColumn(_))
+        ));
+    }
+}
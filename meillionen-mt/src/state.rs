@@ -0,0 +1,191 @@
+//! Checkpointing models so long coupled simulations can restart
+//!
+//! A model that implements [`SimulationState`] can write its state out part
+//! way through a run and read it back later, in another process or on
+//! another machine. [`save`] writes a snapshot with a header naming the model
+//! and the sha256 of its state so [`restore`] refuses a snapshot that was cut
+//! short by a crash or belongs to another model.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::clock::SimulationClock;
+
+/// The version of the snapshot header written by [`save`]
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("snapshot is of {got} but {expected} was expected")]
+    WrongModel { expected: String, got: String },
+    #[error("snapshot version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("snapshot has no header")]
+    MissingHeader,
+    #[error("snapshot state does not match its checksum, it may have been cut short")]
+    Corrupt,
+    #[error("state is not valid: {0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A model whose state can be saved part way through a simulation and
+/// restored to carry on from there
+pub trait SimulationState {
+    /// The name snapshots of the model are saved under
+    fn model_name(&self) -> &str;
+
+    fn serialize_state(&self) -> Result<Vec<u8>, StateError>;
+
+    /// Replace the model's state with one from [`SimulationState::serialize_state`]
+    fn deserialize_state(&mut self, state: &[u8]) -> Result<(), StateError>;
+}
+
+impl SimulationState for SimulationClock {
+    fn model_name(&self) -> &str {
+        "clock"
+    }
+
+    fn serialize_state(&self) -> Result<Vec<u8>, StateError> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    fn deserialize_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        *self = serde_json::from_slice(state)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Header {
+    model: String,
+    version: u32,
+    sha256: String,
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Write a snapshot of `model` to `path`
+///
+/// The snapshot is written next to `path` and moved over it once complete so
+/// a crash while saving leaves the last snapshot in place.
+pub fn save<M: SimulationState + ?Sized>(model: &M, path: &Path) -> Result<(), StateError> {
+    let state = model.serialize_state()?;
+    let header = Header {
+        model: model.model_name().to_string(),
+        version: SNAPSHOT_VERSION,
+        sha256: sha256(&state),
+    };
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut file = BufWriter::new(File::create(&partial)?);
+    serde_json::to_writer(&mut file, &header)?;
+    file.write_all(b"\n")?;
+    file.write_all(&state)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Restore `model` from a snapshot written by [`save`]
+pub fn restore<M: SimulationState + ?Sized>(model: &mut M, path: &Path) -> Result<(), StateError> {
+    let bytes = fs::read(path)?;
+    let split = bytes
+        .iter()
+        .position(|b| *b == b'\n')
+        .ok_or(StateError::MissingHeader)?;
+    let header: Header = serde_json::from_slice(&bytes[..split])?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(StateError::UnsupportedVersion(header.version));
+    }
+    if header.model != model.model_name() {
+        return Err(StateError::WrongModel {
+            expected: model.model_name().to_string(),
+            got: header.model,
+        });
+    }
+    let state = &bytes[split + 1..];
+    if sha256(state) != header.sha256 {
+        return Err(StateError::Corrupt);
+    }
+    model.deserialize_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+    use std::fs;
+
+    use crate::clock::SimulationClock;
+    use crate::state::{restore, save, SimulationState, StateError};
+
+    struct Counter(u32);
+
+    impl SimulationState for Counter {
+        fn model_name(&self) -> &str {
+            "counter"
+        }
+
+        fn serialize_state(&self) -> Result<Vec<u8>, StateError> {
+            Ok(self.0.to_le_bytes().to_vec())
+        }
+
+        fn deserialize_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+            let bytes = state
+                .try_into()
+                .map_err(|_| StateError::Invalid(format!("{} bytes", state.len())))?;
+            self.0 = u32::from_le_bytes(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let dir = std::env::temp_dir().join(format!("meillionen-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clock.snapshot");
+
+        let mut clock = SimulationClock::new(0.0);
+        clock.add_model("hydrology", 1.0).unwrap();
+        clock.add_model("crop", 24.0).unwrap();
+        clock.schedule(30.0);
+        save(&clock, &path).unwrap();
+        assert!(!dir.join("clock.snapshot.partial").exists());
+
+        let mut restarted = SimulationClock::new(0.0);
+        restore(&mut restarted, &path).unwrap();
+        assert_eq!(restarted, clock);
+
+        let mut counter = Counter(0);
+        assert!(matches!(
+            restore(&mut counter, &path),
+            Err(StateError::WrongModel { .. })
+        ));
+
+        let path = dir.join("counter.snapshot");
+        save(&Counter(7), &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(
+            restore(&mut counter, &path),
+            Err(StateError::Corrupt)
+        ));
+        fs::write(&path, &bytes).unwrap();
+        restore(&mut counter, &path).unwrap();
+        assert_eq!(counter.0, 7);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,533 @@
+#[cfg(feature = "data")]
+use std::collections::BTreeMap;
+#[cfg(feature = "data")]
+use std::convert::TryInto;
+#[cfg(feature = "data")]
+use std::fs::File;
+#[cfg(feature = "data")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "data")]
+use std::sync::Arc;
+
+#[cfg(feature = "data")]
+use arrow::array::ArrayRef;
+#[cfg(feature = "data")]
+use arrow::compute::cast;
+#[cfg(feature = "data")]
+use arrow::datatypes::{DataType, Field, Schema};
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "data")]
+use parquet::arrow::{ArrowReader, ArrowWriter, ParquetFileArrowReader};
+#[cfg(feature = "data")]
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "data")]
+use thiserror::Error;
+
+#[cfg(feature = "data")]
+use crate::extension_columns::DimMeta;
+#[cfg(feature = "data")]
+use crate::variable::table::{self, TableError};
+#[cfg(feature = "data")]
+use crate::variable::{Attributes, Variable, VariableError, VecVariable};
+
+/// Schema metadata key holding the attributes of a stored variable
+#[cfg(feature = "data")]
+pub const ATTRIBUTES_KEY: &str = "meillionen-attributes";
+
+#[cfg(feature = "data")]
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("variable {0} not found in the store")]
+    MissingVariable(String),
+    #[error("{0} stores are not supported")]
+    Unsupported(String),
+    #[error("zarr array {0} is not supported: {1}")]
+    UnsupportedZarr(String, String),
+    #[error("{0} is not a valid variable name")]
+    InvalidName(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error(transparent)]
+    Table(#[from] TableError),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+}
+
+/// Somewhere to keep model variables by name
+///
+/// Backends are chosen in the experiment file with a [`StoreConfig`] so
+/// model code reads and writes variables the same way whether they are kept
+/// in memory or on disk.
+#[cfg(feature = "data")]
+pub trait VariableStore {
+    /// The names of the stored variables in sorted order
+    fn names(&self) -> Result<Vec<String>, StoreError>;
+
+    fn get(&self, name: &str) -> Result<VecVariable<f64>, StoreError>;
+
+    /// Add or replace a variable
+    fn put(&mut self, name: &str, variable: &VecVariable<f64>) -> Result<(), StoreError>;
+
+    fn contains(&self, name: &str) -> Result<bool, StoreError> {
+        Ok(self.names()?.iter().any(|n| n == name))
+    }
+}
+
+/// Names become file names so they cannot contain path separators
+#[cfg(feature = "data")]
+fn check_name(name: &str) -> Result<(), StoreError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(StoreError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Variables kept in memory, lost when the store is dropped
+#[cfg(feature = "data")]
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    variables: BTreeMap<String, VecVariable<f64>>,
+}
+
+#[cfg(feature = "data")]
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "data")]
+impl VariableStore for MemoryStore {
+    fn names(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.variables.keys().cloned().collect())
+    }
+
+    fn get(&self, name: &str) -> Result<VecVariable<f64>, StoreError> {
+        self.variables
+            .get(name)
+            .cloned()
+            .ok_or_else(|| StoreError::MissingVariable(name.to_string()))
+    }
+
+    fn put(&mut self, name: &str, variable: &VecVariable<f64>) -> Result<(), StoreError> {
+        // names are checked so a config can switch backends safely
+        check_name(name)?;
+        self.variables.insert(name.to_string(), variable.clone());
+        Ok(())
+    }
+}
+
+/// A directory with a `<name>.parquet` file per variable
+///
+/// Files are long format tables written by [`table::to_recordbatch`] with
+/// labels stored as strings, so pandas and polars can read them directly.
+#[cfg(feature = "data")]
+#[derive(Clone, Debug)]
+pub struct ParquetDirStore {
+    dir: PathBuf,
+}
+
+#[cfg(feature = "data")]
+impl ParquetDirStore {
+    /// Use a directory, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, StoreError> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.parquet", name))
+    }
+}
+
+#[cfg(feature = "data")]
+impl VariableStore for ParquetDirStore {
+    fn names(&self) -> Result<Vec<String>, StoreError> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some("parquet") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn get(&self, name: &str) -> Result<VecVariable<f64>, StoreError> {
+        check_name(name)?;
+        let path = self.path(name);
+        if !path.exists() {
+            return Err(StoreError::MissingVariable(name.to_string()));
+        }
+        let file_reader = SerializedFileReader::new(File::open(path)?)?;
+        let rows = file_reader.metadata().file_metadata().num_rows().max(1) as usize;
+        let mut reader = ParquetFileArrowReader::new(Arc::new(file_reader));
+        let schema = Arc::new(reader.get_schema()?);
+        let batch = match reader.get_record_reader(rows)?.next() {
+            Some(batch) => batch?,
+            None => RecordBatch::new_empty(schema.clone()),
+        };
+        let variable = table::from_recordbatch(&batch, name)?;
+        match schema.metadata().get(ATTRIBUTES_KEY) {
+            Some(attributes) => {
+                let attributes: Attributes = serde_json::from_str(attributes)?;
+                Ok(variable.with_attributes(attributes))
+            }
+            None => Ok(variable),
+        }
+    }
+
+    fn put(&mut self, name: &str, variable: &VecVariable<f64>) -> Result<(), StoreError> {
+        check_name(name)?;
+        let rb = table::to_recordbatch(variable, name)?;
+        // parquet 4 cannot write dictionary columns, labels are read back
+        // from the dimension metadata
+        let schema = rb.schema();
+        let mut fields = vec![];
+        let mut columns: Vec<ArrayRef> = vec![];
+        for (field, column) in schema.fields().iter().zip(rb.columns()) {
+            if let DataType::Dictionary(_, _) = field.data_type() {
+                fields.push(Field::new(
+                    field.name(),
+                    DataType::Utf8,
+                    field.is_nullable(),
+                ));
+                columns.push(cast(column, &DataType::Utf8)?);
+            } else {
+                fields.push(field.clone());
+                columns.push(column.clone());
+            }
+        }
+        let mut metadata = schema.metadata().clone();
+        if let Some(attributes) = variable.attributes() {
+            metadata.insert(
+                ATTRIBUTES_KEY.to_string(),
+                serde_json::to_string(attributes)?,
+            );
+        }
+        let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+        let rb = RecordBatch::try_new(schema.clone(), columns)?;
+        let mut writer = ArrowWriter::try_new(File::create(self.path(name))?, schema, None)?;
+        writer.write(&rb)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+/// The `.zarray` metadata of a zarr v2 array
+#[cfg(feature = "data")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ZarrArray {
+    zarr_format: u32,
+    shape: Vec<usize>,
+    chunks: Vec<usize>,
+    dtype: String,
+    compressor: Option<serde_json::Value>,
+    fill_value: Option<serde_json::Value>,
+    order: String,
+    filters: Option<serde_json::Value>,
+}
+
+/// A zarr v2 group with an array per variable
+///
+/// Each array is written as one uncompressed little endian `f8` chunk with
+/// the `_ARRAY_DIMENSIONS` attribute, so `xarray.open_zarr` reads the group
+/// as a dataset. The dimensions, with their labels and times, are kept in the
+/// `meillionen_dimensions` attribute. Only arrays in that layout can be read.
+#[cfg(feature = "data")]
+#[derive(Clone, Debug)]
+pub struct ZarrStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "data")]
+const ZARR_DIMENSIONS: &str = "_ARRAY_DIMENSIONS";
+#[cfg(feature = "data")]
+const ZARR_MEILLIONEN_DIMENSIONS: &str = "meillionen_dimensions";
+
+#[cfg(feature = "data")]
+impl ZarrStore {
+    /// Use a zarr group, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)?;
+        let group = path.join(".zgroup");
+        if !group.exists() {
+            std::fs::write(group, r#"{"zarr_format": 2}"#)?;
+        }
+        Ok(Self { path })
+    }
+
+    fn chunk_name(ndims: usize) -> String {
+        if ndims == 0 {
+            "0".to_string()
+        } else {
+            vec!["0"; ndims].join(".")
+        }
+    }
+}
+
+#[cfg(feature = "data")]
+impl VariableStore for ZarrStore {
+    fn names(&self) -> Result<Vec<String>, StoreError> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.join(".zarray").exists() {
+                if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn get(&self, name: &str) -> Result<VecVariable<f64>, StoreError> {
+        check_name(name)?;
+        let dir = self.path.join(name);
+        if !dir.join(".zarray").exists() {
+            return Err(StoreError::MissingVariable(name.to_string()));
+        }
+        let array: ZarrArray =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(".zarray"))?)?;
+        let unsupported =
+            |reason: &str| StoreError::UnsupportedZarr(name.to_string(), reason.to_string());
+        if array.dtype != "<f8" {
+            return Err(unsupported(&format!("dtype is {}", array.dtype)));
+        }
+        if array.compressor.is_some() || array.filters.is_some() {
+            return Err(unsupported("it is compressed or filtered"));
+        }
+        if array.order != "C" || array.chunks.len() != array.shape.len() {
+            return Err(unsupported("it is not in C order"));
+        }
+        if array.shape.iter().zip(&array.chunks).any(|(s, c)| c < s) {
+            return Err(unsupported("it has more than one chunk"));
+        }
+
+        let mut attrs: serde_json::Map<String, serde_json::Value> =
+            match std::fs::read_to_string(dir.join(".zattrs")) {
+                Ok(text) => serde_json::from_str(&text)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+                Err(e) => return Err(e.into()),
+            };
+        let dims: Vec<String> = match attrs.remove(ZARR_DIMENSIONS) {
+            Some(dims) => serde_json::from_value(dims)?,
+            None => (0..array.shape.len())
+                .map(|i| format!("dim_{}", i))
+                .collect(),
+        };
+        let dimensions: Vec<Arc<DimMeta>> = match attrs.remove(ZARR_MEILLIONEN_DIMENSIONS) {
+            Some(meta) => serde_json::from_value(meta)?,
+            None => dims
+                .iter()
+                .zip(&array.shape)
+                .map(|(name, &size)| {
+                    Arc::new(DimMeta {
+                        name: name.clone(),
+                        size,
+                        description: None,
+                        labels: None,
+                        times: None,
+                    })
+                })
+                .collect(),
+        };
+
+        let len: usize = array.shape.iter().product();
+        let data = match std::fs::read(dir.join(Self::chunk_name(array.shape.len()))) {
+            Ok(bytes) => {
+                // an edge chunk is padded to the chunk shape
+                let chunk: Vec<usize> = array.chunks.clone();
+                let values: Vec<f64> = bytes
+                    .chunks_exact(8)
+                    .map(|b| f64::from_le_bytes(b.try_into().expect("8 bytes")))
+                    .collect();
+                if values.len() < chunk.iter().product() {
+                    return Err(unsupported("its chunk is truncated"));
+                }
+                crate::variable::Indices::new(array.shape.clone())
+                    .map(|index| {
+                        let offset = index.iter().zip(&chunk).fold(0, |acc, (i, c)| acc * c + i);
+                        values[offset]
+                    })
+                    .collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![f64::NAN; len],
+            Err(e) => return Err(e.into()),
+        };
+        let variable = VecVariable::new(dimensions, data)?;
+        if attrs.is_empty() {
+            return Ok(variable);
+        }
+        let attributes: Attributes = serde_json::from_value(serde_json::Value::Object(attrs))?;
+        Ok(variable.with_attributes(attributes))
+    }
+
+    fn put(&mut self, name: &str, variable: &VecVariable<f64>) -> Result<(), StoreError> {
+        check_name(name)?;
+        let dir = self.path.join(name);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        let shape = variable.shape();
+        let array = ZarrArray {
+            zarr_format: 2,
+            chunks: shape.iter().map(|&s| s.max(1)).collect(),
+            shape: shape.clone(),
+            dtype: "<f8".to_string(),
+            compressor: None,
+            fill_value: Some(serde_json::Value::String("NaN".to_string())),
+            order: "C".to_string(),
+            filters: None,
+        };
+        std::fs::write(dir.join(".zarray"), serde_json::to_string_pretty(&array)?)?;
+
+        let mut attrs = match variable.attributes() {
+            Some(attributes) => match serde_json::to_value(attributes)? {
+                serde_json::Value::Object(attrs) => attrs,
+                _ => serde_json::Map::new(),
+            },
+            None => serde_json::Map::new(),
+        };
+        let dims: Vec<&str> = variable
+            .dimensions()
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect();
+        attrs.insert(ZARR_DIMENSIONS.to_string(), serde_json::to_value(dims)?);
+        attrs.insert(
+            ZARR_MEILLIONEN_DIMENSIONS.to_string(),
+            serde_json::to_value(variable.dimensions())?,
+        );
+        std::fs::write(dir.join(".zattrs"), serde_json::to_string_pretty(&attrs)?)?;
+
+        if variable.is_empty() {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = variable
+            .to_vec()
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        std::fs::write(dir.join(Self::chunk_name(shape.len())), bytes)?;
+        Ok(())
+    }
+}
+
+/// Where an experiment keeps its variables
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StoreConfig {
+    Memory,
+    Parquet {
+        dir: String,
+    },
+    Zarr {
+        path: String,
+    },
+    /// Not supported by the rust store, write NetCDF files from python with
+    /// xarray instead
+    NetCDF {
+        path: String,
+    },
+}
+
+#[cfg(feature = "data")]
+impl StoreConfig {
+    pub fn open(&self) -> Result<Box<dyn VariableStore>, StoreError> {
+        match self {
+            StoreConfig::Memory => Ok(Box::new(MemoryStore::new())),
+            StoreConfig::Parquet { dir } => Ok(Box::new(ParquetDirStore::open(dir)?)),
+            StoreConfig::Zarr { path } => Ok(Box::new(ZarrStore::open(path)?)),
+            StoreConfig::NetCDF { .. } => Err(StoreError::Unsupported("netcdf".to_string())),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::extension_columns::DimMeta;
+    use crate::store::{StoreConfig, StoreError};
+    use crate::variable::{dim, Attributes, Variable, VecVariable};
+
+    fn lai() -> VecVariable<f64> {
+        let cultivar = Arc::new(DimMeta::labeled(
+            "cultivar",
+            vec!["A".to_string(), "B".to_string()],
+        ));
+        VecVariable::new(
+            vec![cultivar, dim("t", 3)],
+            vec![0.0, 1.0, f64::NAN, 3.0, 4.0, 5.0],
+        )
+        .unwrap()
+        .with_attributes(
+            Attributes::default()
+                .with_units("m2/m2")
+                .with_long_name("leaf area index"),
+        )
+    }
+
+    fn round_trip(config: StoreConfig) {
+        let mut store = config.open().unwrap();
+        assert!(matches!(
+            store.get("lai"),
+            Err(StoreError::MissingVariable(_))
+        ));
+        let expected = lai();
+        store.put("lai", &expected).unwrap();
+        store
+            .put(
+                "rainfall",
+                &VecVariable::new(vec![dim("t", 3)], vec![1.0; 3]).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(store.names().unwrap(), vec!["lai", "rainfall"]);
+        assert!(store.contains("lai").unwrap());
+        assert!(store.put("../lai", &expected).is_err());
+
+        let got = store.get("lai").unwrap();
+        assert_eq!(got.dimensions(), expected.dimensions());
+        assert_eq!(got.attributes(), expected.attributes());
+        let values = got.to_vec();
+        assert!(values[2].is_nan());
+        assert_eq!(values[3..], [3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn backends_round_trip() {
+        let dir = std::env::temp_dir().join(format!("meillionen-store-{}", std::process::id()));
+        round_trip(StoreConfig::Memory);
+        round_trip(StoreConfig::Parquet {
+            dir: dir.join("parquet").display().to_string(),
+        });
+        round_trip(StoreConfig::Zarr {
+            path: dir.join("out.zarr").display().to_string(),
+        });
+        assert!(matches!(
+            StoreConfig::NetCDF {
+                path: "out.nc".to_string()
+            }
+            .open(),
+            Err(StoreError::Unsupported(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use arrow::array::{Int32Array, Int64Array};
+use arrow::datatypes::{DataType, Float32Type, Float64Type};
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::timeseries::float_values;
+
+#[derive(Debug, Error)]
+pub enum StreamError {
+    #[error("column {0} not found")]
+    MissingColumn(String),
+    #[error("column {0} must be a float column but is {1:?}")]
+    NotFloat(String, DataType),
+    #[error("step column {0} must be an integer column but is {1:?}")]
+    NotStep(String, DataType),
+    #[error("server rejected the connection: {0}")]
+    Rejected(String),
+    #[error("subject {0:?} must be non-empty and have no spaces or control characters")]
+    InvalidSubject(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// The outputs of one model at one time step
+///
+/// Missing (NaN) values are left out of `values`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct StepMessage {
+    pub model: String,
+    pub step: i64,
+    pub values: BTreeMap<String, f64>,
+}
+
+/// Split an output batch into one message per row
+///
+/// Only the `columns` asked for are published so dashboards get the key
+/// outputs (soil water, leaf area index) without every daily variable.
+pub fn step_messages(
+    model: &str,
+    rb: &RecordBatch,
+    step: &str,
+    columns: &[String],
+) -> Result<Vec<StepMessage>, StreamError> {
+    let schema = rb.schema();
+    let column = |name: &str| {
+        schema
+            .index_of(name)
+            .map(|i| rb.column(i))
+            .map_err(|_| StreamError::MissingColumn(name.to_string()))
+    };
+    let steps = column(step)?;
+    let steps: Vec<i64> = match steps.data_type() {
+        DataType::Int32 => steps
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .expect("array type to match data type")
+            .values()
+            .iter()
+            .map(|&s| i64::from(s))
+            .collect(),
+        DataType::Int64 => steps
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("array type to match data type")
+            .values()
+            .to_vec(),
+        dt => return Err(StreamError::NotStep(step.to_string(), dt.clone())),
+    };
+    let mut messages: Vec<StepMessage> = steps
+        .into_iter()
+        .map(|step| StepMessage {
+            model: model.to_string(),
+            step,
+            values: BTreeMap::new(),
+        })
+        .collect();
+    for name in columns.iter() {
+        let array = column(name)?;
+        let values = match array.data_type() {
+            DataType::Float32 => float_values::<Float32Type>(array),
+            DataType::Float64 => float_values::<Float64Type>(array),
+            dt => return Err(StreamError::NotFloat(name.clone(), dt.clone())),
+        };
+        for (message, value) in messages.iter_mut().zip(values) {
+            if !value.is_nan() {
+                message.values.insert(name.clone(), value);
+            }
+        }
+    }
+    Ok(messages)
+}
+
+/// Check a subject can be sent as one token of a protocol line, so it can't
+/// end the line early and inject another command
+pub fn check_subject(subject: &str) -> Result<(), StreamError> {
+    if subject.is_empty() || subject.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(StreamError::InvalidSubject(subject.to_string()));
+    }
+    Ok(())
+}
+
+/// Something step outputs can be sent to as they are produced
+pub trait Publisher {
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<(), StreamError>;
+
+    fn publish_step(&mut self, subject: &str, message: &StepMessage) -> Result<(), StreamError> {
+        let payload = serde_json::to_vec(message)?;
+        self.publish(subject, &payload)
+    }
+}
+
+/// Publishes to a NATS server using its plain text protocol
+///
+/// Kafka consumers can receive the messages through a NATS to Kafka bridge.
+/// A thread reads what the server sends, answering its PINGs with PONGs so
+/// the server doesn't drop the connection as stale between steps, and
+/// keeping the last error it reports for the next `publish` to return.
+pub struct NatsPublisher {
+    stream: Arc<Mutex<TcpStream>>,
+    error: Arc<Mutex<Option<String>>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+/// Answer server PINGs and keep server errors until the connection closes
+fn read_server(
+    reader: BufReader<TcpStream>,
+    stream: Arc<Mutex<TcpStream>>,
+    error: Arc<Mutex<Option<String>>>,
+) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.starts_with("PING") {
+            let mut stream = stream.lock().expect("stream lock not to be poisoned");
+            if stream.write_all(b"PONG\r\n").is_err() {
+                break;
+            }
+        } else if let Some(message) = line.strip_prefix("-ERR") {
+            *error.lock().expect("error lock not to be poisoned") =
+                Some(message.trim().trim_matches('\'').to_string());
+        }
+    }
+}
+
+impl NatsPublisher {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, StreamError> {
+        let mut stream = TcpStream::connect(addr)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        // the server greets every client with an INFO line
+        let mut info = String::new();
+        reader.read_line(&mut info)?;
+        if !info.starts_with("INFO") {
+            return Err(StreamError::Rejected(info.trim_end().to_string()));
+        }
+        stream.write_all(b"CONNECT {\"verbose\":false,\"name\":\"meillionen\"}\r\n")?;
+        let stream = Arc::new(Mutex::new(stream));
+        let error = Arc::new(Mutex::new(None));
+        let reader = {
+            let (stream, error) = (stream.clone(), error.clone());
+            thread::spawn(move || read_server(reader, stream, error))
+        };
+        Ok(Self {
+            stream,
+            error,
+            reader: Some(reader),
+        })
+    }
+}
+
+impl Publisher for NatsPublisher {
+    fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<(), StreamError> {
+        check_subject(subject)?;
+        if let Some(message) = self
+            .error
+            .lock()
+            .expect("error lock not to be poisoned")
+            .take()
+        {
+            return Err(StreamError::Rejected(message));
+        }
+        let mut message = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        message.extend_from_slice(payload);
+        message.extend_from_slice(b"\r\n");
+        // one write so a PONG from the reader can't land inside the message
+        let mut stream = self.stream.lock().expect("stream lock not to be poisoned");
+        stream.write_all(&message)?;
+        Ok(())
+    }
+}
+
+impl Drop for NatsPublisher {
+    fn drop(&mut self) {
+        if let Ok(stream) = self.stream.lock() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// Publishes the key outputs of each step of a coupled model as the model
+/// produces them
+pub struct StepStream<P> {
+    publisher: P,
+    subject: String,
+    model: String,
+    step: String,
+    columns: Vec<String>,
+}
+
+impl<P: Publisher> StepStream<P> {
+    /// Publish to `subject` the `columns` of each row of a model's outputs,
+    /// with the row's step taken from the `step` column
+    pub fn new(
+        publisher: P,
+        subject: &str,
+        model: &str,
+        step: &str,
+        columns: Vec<String>,
+    ) -> Result<Self, StreamError> {
+        check_subject(subject)?;
+        Ok(Self {
+            publisher,
+            subject: subject.to_string(),
+            model: model.to_string(),
+            step: step.to_string(),
+            columns,
+        })
+    }
+
+    /// Publish a message for each row of `rb`, returning how many were sent
+    pub fn publish_batch(&mut self, rb: &RecordBatch) -> Result<usize, StreamError> {
+        let messages = step_messages(&self.model, rb, &self.step, &self.columns)?;
+        for message in messages.iter() {
+            self.publisher.publish_step(&self.subject, message)?;
+        }
+        Ok(messages.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::stream::{
+        check_subject, step_messages, NatsPublisher, StepMessage, StepStream, StreamError,
+    };
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("soil_water", DataType::Float32, false),
+            Field::new("plant_leaf_area_index", DataType::Float32, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1, 2])),
+            Arc::new(Float32Array::from(vec![0.25, f32::NAN])),
+            Arc::new(Float32Array::from(vec![1.0, 1.5])),
+        ];
+        RecordBatch::try_new(schema, columns).unwrap()
+    }
+
+    fn messages() -> Vec<StepMessage> {
+        step_messages("simplecrop", &batch(), "day", &["soil_water".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn selected_columns() {
+        let m = messages();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m[0].values.get("soil_water"), Some(&0.25));
+        assert!(!m[0].values.contains_key("plant_leaf_area_index"));
+        assert!(m[1].values.is_empty());
+    }
+
+    #[test]
+    fn nats_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (ponged, pong) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {}\r\nPING\r\n").unwrap();
+            let mut received = vec![];
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                if line == "PONG" {
+                    ponged.send(()).unwrap();
+                } else {
+                    received.push(line);
+                }
+            }
+            received
+        });
+        {
+            let p = NatsPublisher::connect(addr).unwrap();
+            let mut steps = StepStream::new(
+                p,
+                "crop.steps",
+                "simplecrop",
+                "day",
+                vec!["soil_water".to_string()],
+            )
+            .unwrap();
+            assert_eq!(steps.publish_batch(&batch()).unwrap(), 2);
+            pong.recv().unwrap();
+        }
+        let lines = server.join().unwrap();
+        assert!(lines[0].starts_with("CONNECT "));
+        let payload = r#"{"model":"simplecrop","step":1,"values":{"soil_water":0.25}}"#;
+        assert_eq!(lines[1], format!("PUB crop.steps {}", payload.len()));
+        assert_eq!(lines[2], payload);
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn invalid_subjects() {
+        for subject in ["", "crop steps", "crop.steps\r\nPUB other 0"].iter() {
+            assert!(matches!(
+                check_subject(subject),
+                Err(StreamError::InvalidSubject(_))
+            ));
+        }
+        assert!(check_subject("crop.steps.>").is_ok());
+    }
+}
@@ -0,0 +1,51 @@
+//! Promoting validation warnings to errors
+//!
+//! Readers and checks report things they worked around, such as gaps they
+//! filled or lines they skipped, through [`warn`]. Normally that logs a
+//! warning and carries on. In strict mode, for runs that will be published,
+//! it fails instead.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use thiserror::Error;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("{0} (an error in strict mode)")]
+pub struct StrictError(pub String);
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+pub fn is_strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// Log a validation warning, or fail with it in strict mode
+pub fn warn<S: Into<String>>(message: S) -> Result<(), StrictError> {
+    let message = message.into();
+    if is_strict() {
+        return Err(StrictError(message));
+    }
+    tracing::warn!("{}", message);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strictness::{set_strict, warn, StrictError};
+
+    #[test]
+    fn strict_warnings_fail() {
+        assert!(warn("filled 2 gaps in rainfall").is_ok());
+        set_strict(true);
+        let result = warn("filled 2 gaps in rainfall");
+        set_strict(false);
+        assert_eq!(
+            result,
+            Err(StrictError("filled 2 gaps in rainfall".to_string()))
+        );
+    }
+}
@@ -0,0 +1,554 @@
+//! Experiment submissions to `meillionen serve` over HTTP
+//!
+//! With `--listen` the service accepts experiments from clients that can't
+//! open its queue database, such as users on other machines. A submission
+//! carries the text of the experiment file, which the service saves under
+//! the directory it runs from before queueing it, so the sources the
+//! experiment reads must already be on the service's machine.
+//!
+//! | Request             | Body                  | Response              |
+//! |---------------------|-----------------------|-----------------------|
+//! | `POST /jobs`        | a [`Submission`]      | `{"id": 1}`           |
+//! | `GET /jobs`         |                       | the caller's [`Job`]s |
+//! | `GET /jobs/1`       |                       | the [`Job`]           |
+//! | `DELETE /jobs/1`    |                       | `{"state": "queued"}` |
+//!
+//! Users give their token as `Authorization: Bearer <token>`, which the
+//! service checks against the queue. Experiments are refused with 403 if
+//! they would read or write outside the caller's workspace, or outside the
+//! service's `--workspace` for a queue that isn't shared. Errors are
+//! answered with `{"error": "..."}`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::experiment::{ExperimentConfig, ExperimentError};
+use crate::queue::{outside_workspace, resolve, Job, JobQueue, JobState, QueueError, User};
+
+/// The largest request body accepted, experiment files are much smaller
+const MAX_BODY: usize = 4 << 20;
+
+/// How long to wait on a slow client or server
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum SubmissionError {
+    #[error("server {0} is not an http:// URL")]
+    InvalidServer(String),
+    #[error("the server answered {status}: {message}")]
+    Rejected { status: u16, message: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// An experiment to queue
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Submission {
+    /// The text of the experiment file
+    pub experiment: String,
+    /// `toml` or `yaml`
+    #[serde(default = "default_format")]
+    pub format: String,
+    /// Directory relative to the caller's workspace that relative resource
+    /// paths are resolved against, the workspace itself when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    /// Queue the experiment even if a job from the same directory runs it
+    #[serde(default)]
+    pub force: bool,
+}
+
+fn default_format() -> String {
+    "toml".to_string()
+}
+
+fn default_jobs() -> usize {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Submitted {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Cancelled {
+    state: JobState,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: BTreeMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn token(&self) -> Option<&str> {
+        self.headers
+            .get("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+    }
+}
+
+/// Read a request, `None` if it is too large
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Ok(None);
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+fn write_response(mut stream: &TcpStream, status: u16, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )
+}
+
+/// Why a request failed, as an HTTP status and message
+struct Failure(u16, String);
+
+impl From<QueueError> for Failure {
+    fn from(e: QueueError) -> Self {
+        let status = match e {
+            QueueError::TokenRequired
+            | QueueError::InvalidToken
+            | QueueError::NoAdmin
+            | QueueError::AdminTokenRequired
+            | QueueError::InvalidAdminToken => 401,
+            QueueError::NotOwner { .. } | QueueError::OutsideWorkspace { .. } => 403,
+            QueueError::UnknownJob(_) | QueueError::UnknownUser(_) => 404,
+            QueueError::Finished { .. } => 409,
+            QueueError::NoJobs | QueueError::NoRunning | QueueError::Experiment(_) => 400,
+            _ => 500,
+        };
+        Failure(status, e.to_string())
+    }
+}
+
+impl From<ExperimentError> for Failure {
+    fn from(e: ExperimentError) -> Self {
+        Failure(400, e.to_string())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure(500, e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Failure {
+    fn from(e: serde_json::Error) -> Self {
+        Failure(400, e.to_string())
+    }
+}
+
+fn json<T: serde::Serialize>(status: u16, value: &T) -> Result<(u16, String), Failure> {
+    Ok((
+        status,
+        serde_json::to_string(value).map_err(|e| Failure(500, e.to_string()))?,
+    ))
+}
+
+/// Answers submissions to a queue
+pub struct SubmissionService {
+    queue: JobQueue,
+    /// Where experiments are saved and run when the queue has no users
+    workspace: PathBuf,
+}
+
+impl SubmissionService {
+    pub fn new(queue: JobQueue, workspace: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(workspace)?;
+        Ok(Self {
+            queue,
+            workspace: workspace.canonicalize()?,
+        })
+    }
+
+    /// Answer requests until the listener fails
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            let (status, body) = match read_request(&stream) {
+                Ok(Some(request)) => self.handle(&request),
+                Ok(None) => (413, error_body("the request is too large")),
+                // a client that hung up is not an error of the service
+                Err(_) => continue,
+            };
+            let _ = write_response(&stream, status, &body);
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, request: &Request) -> (u16, String) {
+        let result = self.route(request);
+        if let Err(Failure(status, message)) = &result {
+            tracing::debug!(status, "{} {}: {}", request.method, request.path, message);
+        }
+        result.unwrap_or_else(|Failure(status, message)| (status, error_body(&message)))
+    }
+
+    fn route(&mut self, request: &Request) -> Result<(u16, String), Failure> {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let user = self.queue.authenticate(request.token())?;
+        match (request.method.as_str(), &segments[..]) {
+            ("POST", ["jobs"]) => {
+                let submission: Submission = serde_json::from_slice(&request.body)?;
+                let id = self.submit(&submission, user.as_ref())?;
+                json(201, &Submitted { id })
+            }
+            ("GET", ["jobs"]) => {
+                let jobs: Vec<Job> = self
+                    .queue
+                    .jobs()?
+                    .into_iter()
+                    .filter(|job| owns(user.as_ref(), job))
+                    .collect();
+                json(200, &jobs)
+            }
+            ("GET", ["jobs", id]) => {
+                let job = self.queue.job(parse_id(id)?)?;
+                if !owns(user.as_ref(), &job) {
+                    return Err(QueueError::NotOwner { id: job.id }.into());
+                }
+                json(200, &job)
+            }
+            ("DELETE", ["jobs", id]) => {
+                let state = self.queue.cancel(parse_id(id)?, user.as_ref())?;
+                json(200, &Cancelled { state })
+            }
+            _ => Err(Failure(
+                404,
+                format!("no {} {}", request.method, request.path),
+            )),
+        }
+    }
+
+    /// Save the experiment under the caller's workspace and queue it
+    ///
+    /// Nothing is written unless `dir` is inside the workspace, and the
+    /// experiment is only queued if everything it reads and writes is.
+    fn submit(&self, submission: &Submission, user: Option<&User>) -> Result<i64, Failure> {
+        let workspace = user.map_or(&self.workspace, |u| &u.workspace);
+        let outside = |path: &Path| {
+            Failure(
+                403,
+                format!(
+                    "{} is outside the workspace {}",
+                    path.display(),
+                    workspace.display()
+                ),
+            )
+        };
+        let dir = match &submission.dir {
+            Some(dir) => workspace.join(dir),
+            None => workspace.clone(),
+        };
+        let dir = match resolve(&dir) {
+            Some(resolved) if resolved.starts_with(workspace) => resolved,
+            _ => return Err(outside(&dir)),
+        };
+        let extension = match submission.format.as_str() {
+            "toml" => "toml",
+            "yaml" | "yml" => "yaml",
+            format => {
+                return Err(Failure(
+                    400,
+                    format!("format must be toml or yaml but is {}", format),
+                ))
+            }
+        };
+        let saved = dir.join(".meillionen").join("submissions");
+        std::fs::create_dir_all(&saved)?;
+        let hash: String = Sha256::digest(submission.experiment.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let config = saved.join(format!("{}.{}", hash, extension));
+        std::fs::write(&config, &submission.experiment)?;
+        let experiment = ExperimentConfig::load(&config)?;
+        experiment.validate()?;
+        if let Some(path) = outside_workspace(workspace, &dir, &config, &experiment) {
+            return Err(outside(&path));
+        }
+        if !submission.force {
+            if let Some(job) = self.queue.same_as(&experiment.content_hash()?, &dir)? {
+                return Err(Failure(
+                    409,
+                    format!(
+                        "job {} ({}) runs the same experiment, use its results or force it to run again",
+                        job.id, job.state
+                    ),
+                ));
+            }
+        }
+        Ok(self
+            .queue
+            .submit(&config, &dir, submission.priority, submission.jobs, user)?)
+    }
+}
+
+fn owns(user: Option<&User>, job: &Job) -> bool {
+    user.is_none_or(|u| job.user.as_ref() == Some(&u.name))
+}
+
+fn parse_id(id: &str) -> Result<i64, Failure> {
+    id.parse()
+        .map_err(|_| Failure(404, format!("{} is not a job id", id)))
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::to_string(&ErrorBody {
+        error: message.to_string(),
+    })
+    .expect("a string to serialize")
+}
+
+/// Submits experiments to a service started with `meillionen serve --listen`
+pub struct SubmissionClient {
+    /// `host:port`
+    address: String,
+    token: Option<String>,
+}
+
+impl SubmissionClient {
+    /// A client of the service at `server`, a URL like `http://host:9185`
+    pub fn new(server: &str, token: Option<&str>) -> Result<Self, SubmissionError> {
+        let address = server
+            .strip_prefix("http://")
+            .map(|a| a.trim_end_matches('/'))
+            .filter(|a| !a.is_empty() && !a.contains('/'))
+            .ok_or_else(|| SubmissionError::InvalidServer(server.to_string()))?;
+        Ok(Self {
+            address: address.to_string(),
+            token: token.map(str::to_string),
+        })
+    }
+
+    fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: &str,
+    ) -> Result<T, SubmissionError> {
+        let addr = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| SubmissionError::InvalidServer(self.address.clone()))?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let authorization = self
+            .token
+            .as_ref()
+            .map(|t| format!("Authorization: Bearer {}\r\n", t))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            self.address,
+            authorization,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+        let status: u16 = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+        if (200..300).contains(&status) {
+            Ok(serde_json::from_str(body)?)
+        } else {
+            let message = serde_json::from_str::<ErrorBody>(body)
+                .map(|e| e.error)
+                .unwrap_or_else(|_| body.to_string());
+            Err(SubmissionError::Rejected { status, message })
+        }
+    }
+
+    /// Queue an experiment, returning the id of its job
+    pub fn submit(&self, submission: &Submission) -> Result<i64, SubmissionError> {
+        let body = serde_json::to_string(submission)?;
+        Ok(self.request::<Submitted>("POST", "/jobs", &body)?.id)
+    }
+
+    /// The caller's jobs, or every job if the queue has no users
+    pub fn jobs(&self) -> Result<Vec<Job>, SubmissionError> {
+        self.request("GET", "/jobs", "")
+    }
+
+    pub fn job(&self, id: i64) -> Result<Job, SubmissionError> {
+        self.request("GET", &format!("/jobs/{}", id), "")
+    }
+
+    /// Cancel a job, returning its new state as [`JobQueue::cancel`] does
+    pub fn cancel(&self, id: i64) -> Result<JobState, SubmissionError> {
+        Ok(self
+            .request::<Cancelled>("DELETE", &format!("/jobs/{}", id), "")?
+            .state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use rusqlite::Connection;
+
+    use crate::queue::{JobQueue, JobState};
+    use crate::submission::{Submission, SubmissionClient, SubmissionError, SubmissionService};
+
+    const EXPERIMENT: &str = r#"
+name = "irrigation"
+
+[[trials]]
+name = "baseline"
+model = "simplecrop_omf"
+"#;
+
+    fn submission() -> Submission {
+        Submission {
+            experiment: EXPERIMENT.to_string(),
+            format: "toml".to_string(),
+            dir: None,
+            priority: 0,
+            jobs: 1,
+            force: false,
+        }
+    }
+
+    #[test]
+    fn submit_list_and_cancel() {
+        let root = std::env::temp_dir().join(format!("meillionen-submit-{}", std::process::id()));
+        let queue = JobQueue::new(Connection::open_in_memory().unwrap()).unwrap();
+        let mut service = SubmissionService::new(queue, &root).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || service.serve(listener));
+
+        let client = SubmissionClient::new(&server, None).unwrap();
+        let id = client.submit(&submission()).unwrap();
+        let job = client.job(id).unwrap();
+        assert_eq!(job.state, JobState::Queued);
+        assert!(job.config.starts_with(root.canonicalize().unwrap()));
+        assert!(job.config.is_file());
+        assert!(matches!(
+            client.submit(&submission()),
+            Err(SubmissionError::Rejected { status: 409, .. })
+        ));
+        let invalid = Submission {
+            experiment: "name = \"empty\"\ntrials = []\n".to_string(),
+            ..submission()
+        };
+        assert!(matches!(
+            client.submit(&invalid),
+            Err(SubmissionError::Rejected { status: 400, .. })
+        ));
+        let escaping = Submission {
+            dir: Some("../elsewhere".into()),
+            ..submission()
+        };
+        assert!(matches!(
+            client.submit(&escaping),
+            Err(SubmissionError::Rejected { status: 403, .. })
+        ));
+        assert!(!root.with_file_name("elsewhere").exists());
+        let reading_outside = Submission {
+            experiment: format!(
+                "{}[trials.sources.weather]\ntype = \"file\"\npath = \"/etc/passwd\"\n",
+                EXPERIMENT
+            ),
+            ..submission()
+        };
+        assert!(matches!(
+            client.submit(&reading_outside),
+            Err(SubmissionError::Rejected { status: 403, .. })
+        ));
+        assert_eq!(client.jobs().unwrap().len(), 1);
+        assert_eq!(client.cancel(id).unwrap(), JobState::Cancelled);
+        assert!(matches!(
+            client.job(99),
+            Err(SubmissionError::Rejected { status: 404, .. })
+        ));
+        assert!(SubmissionClient::new("https://queue:9185", None).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::variable::Reduction;
+
+#[derive(Debug, Error)]
+pub enum SurrogateError {
+    #[error("test fraction must be between 0 and 1 but is {0}")]
+    InvalidTestFraction(f64),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+    #[error(transparent)]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+/// A model run as stored in the run database
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunRecord {
+    pub run: String,
+    pub parameters: BTreeMap<String, f64>,
+    pub tags: BTreeMap<String, String>,
+    /// The series of each output variable, ordered by step
+    pub outputs: BTreeMap<String, Vec<f64>>,
+    /// The metrics computed from the run's outputs, see [`crate::run_metrics`]
+    pub metrics: BTreeMap<String, f64>,
+}
+
+/// A scalar summary of an output series, such as the final yield or the mean
+/// soil water
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Summary {
+    pub name: String,
+    /// An output variable, or a metric of the run which is taken as is
+    pub variable: String,
+    /// `None` takes the last value of the series
+    pub reduction: Option<Reduction>,
+}
+
+impl Summary {
+    fn apply(&self, run: &RunRecord) -> Option<f64> {
+        if let Some(value) = run.metrics.get(&self.variable) {
+            return Some(*value).filter(|v| !v.is_nan());
+        }
+        let series = run.outputs.get(&self.variable)?;
+        let mut values: Vec<f64> = series.iter().copied().filter(|v| !v.is_nan()).collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(match self.reduction {
+            Some(r) => r.apply(&mut values),
+            None => values[values.len() - 1],
+        })
+    }
+}
+
+/// The mean and standard deviation used to standardize a column
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Normalization {
+    pub name: String,
+    pub mean: f64,
+    pub sd: f64,
+}
+
+impl Normalization {
+    fn of(name: &str, values: impl Iterator<Item = f64>) -> Self {
+        let values: Vec<f64> = values.collect();
+        let n = values.len().max(1) as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let var = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        Self {
+            name: name.to_string(),
+            mean,
+            // constant columns are left as is rather than divided by zero
+            sd: if var > 0.0 { var.sqrt() } else { 1.0 },
+        }
+    }
+}
+
+/// Parameters to summary outputs matrices for training an emulator
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingSet {
+    pub parameters: Vec<String>,
+    pub summaries: Vec<String>,
+    pub runs: Vec<String>,
+    /// One row of parameter values per run
+    pub x: Vec<Vec<f64>>,
+    /// One row of summary values per run
+    pub y: Vec<Vec<f64>>,
+}
+
+/// Metadata written next to an exported training set
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TrainingMetadata {
+    /// Computed from the training rows only so no test data leaks into it
+    pub x: Vec<Normalization>,
+    pub y: Vec<Normalization>,
+    pub train_runs: Vec<String>,
+    pub test_runs: Vec<String>,
+    /// Runs left out because they lacked a parameter or summary
+    pub skipped_runs: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrainingFormat {
+    /// `train.parquet` and `test.parquet` with a run column and a column per
+    /// parameter and summary
+    Parquet,
+    /// `x_train.npy`, `y_train.npy`, `x_test.npy` and `y_test.npy`
+    Npy,
+}
+
+impl TrainingSet {
+    /// Assemble a training set from runs, skipping those missing a parameter
+    /// or summary
+    ///
+    /// Returns the training set and the runs skipped.
+    pub fn assemble(runs: &[RunRecord], summaries: &[Summary]) -> (Self, Vec<String>) {
+        let parameters: Vec<String> = runs
+            .iter()
+            .flat_map(|r| r.parameters.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut set = Self {
+            parameters,
+            summaries: summaries.iter().map(|s| s.name.clone()).collect(),
+            runs: vec![],
+            x: vec![],
+            y: vec![],
+        };
+        let mut skipped = vec![];
+        for run in runs {
+            let x: Option<Vec<f64>> = set
+                .parameters
+                .iter()
+                .map(|p| run.parameters.get(p).copied())
+                .collect();
+            let y: Option<Vec<f64>> = summaries.iter().map(|s| s.apply(run)).collect();
+            match x.zip(y) {
+                Some((x, y)) => {
+                    set.runs.push(run.run.clone());
+                    set.x.push(x);
+                    set.y.push(y);
+                }
+                None => skipped.push(run.run.clone()),
+            }
+        }
+        (set, skipped)
+    }
+
+    /// Shuffle the rows with a seed and split off a fraction for testing
+    ///
+    /// Returns the row indices of the training and test sets.
+    pub fn split(
+        &self,
+        test_fraction: f64,
+        seed: u64,
+    ) -> Result<(Vec<usize>, Vec<usize>), SurrogateError> {
+        if !(0.0..1.0).contains(&test_fraction) {
+            return Err(SurrogateError::InvalidTestFraction(test_fraction));
+        }
+        let mut rows: Vec<usize> = (0..self.runs.len()).collect();
+        rows.shuffle(&mut StdRng::seed_from_u64(seed));
+        let test = (self.runs.len() as f64 * test_fraction).round() as usize;
+        let train = rows.split_off(test);
+        Ok((train, rows))
+    }
+
+    fn to_recordbatch(&self, rows: &[usize]) -> Result<RecordBatch, SurrogateError> {
+        let mut fields = vec![Field::new("run", DataType::Utf8, false)];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(StringArray::from(
+            rows.iter()
+                .map(|i| self.runs[*i].as_str())
+                .collect::<Vec<_>>(),
+        ))];
+        for (names, matrix) in [(&self.parameters, &self.x), (&self.summaries, &self.y)].iter() {
+            for (j, name) in names.iter().enumerate() {
+                fields.push(Field::new(name, DataType::Float64, false));
+                columns.push(Arc::new(Float64Array::from(
+                    rows.iter().map(|i| matrix[*i][j]).collect::<Vec<_>>(),
+                )));
+            }
+        }
+        Ok(RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            columns,
+        )?)
+    }
+
+    /// Write the training and test sets and a `metadata.json` with the
+    /// split and the normalization of each column to a directory
+    pub fn export(
+        &self,
+        dir: &Path,
+        format: TrainingFormat,
+        test_fraction: f64,
+        seed: u64,
+        skipped_runs: Vec<String>,
+    ) -> Result<TrainingMetadata, SurrogateError> {
+        let (train, test) = self.split(test_fraction, seed)?;
+        std::fs::create_dir_all(dir)?;
+        let normalize = |names: &[String], matrix: &[Vec<f64>]| -> Vec<Normalization> {
+            names
+                .iter()
+                .enumerate()
+                .map(|(j, n)| Normalization::of(n, train.iter().map(|i| matrix[*i][j])))
+                .collect()
+        };
+        let metadata = TrainingMetadata {
+            x: normalize(&self.parameters, &self.x),
+            y: normalize(&self.summaries, &self.y),
+            train_runs: train.iter().map(|i| self.runs[*i].clone()).collect(),
+            test_runs: test.iter().map(|i| self.runs[*i].clone()).collect(),
+            skipped_runs,
+        };
+        for (name, rows) in [("train", &train), ("test", &test)].iter() {
+            match format {
+                TrainingFormat::Parquet => {
+                    let rb = self.to_recordbatch(rows)?;
+                    let file = File::create(dir.join(format!("{}.parquet", name)))?;
+                    let mut writer = ArrowWriter::try_new(file, rb.schema(), None)?;
+                    writer.write(&rb)?;
+                    writer.close()?;
+                }
+                TrainingFormat::Npy => {
+                    let x: Vec<&[f64]> = rows.iter().map(|i| self.x[*i].as_slice()).collect();
+                    let y: Vec<&[f64]> = rows.iter().map(|i| self.y[*i].as_slice()).collect();
+                    write_npy(
+                        &dir.join(format!("x_{}.npy", name)),
+                        &x,
+                        self.parameters.len(),
+                    )?;
+                    write_npy(
+                        &dir.join(format!("y_{}.npy", name)),
+                        &y,
+                        self.summaries.len(),
+                    )?;
+                }
+            }
+        }
+        serde_json::to_writer_pretty(File::create(dir.join("metadata.json"))?, &metadata)?;
+        Ok(metadata)
+    }
+}
+
+/// Write a 2d float64 array in the NumPy `.npy` format (version 1.0)
+pub fn write_npy(path: &Path, rows: &[&[f64]], columns: usize) -> std::io::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+        rows.len(),
+        columns
+    );
+    // magic, version and header length take 10 bytes and the whole header
+    // ends in a newline at a multiple of 64 bytes
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+    let mut f = std::io::BufWriter::new(File::create(path)?);
+    f.write_all(b"\x93NUMPY\x01\x00")?;
+    f.write_all(&(header.len() as u16).to_le_bytes())?;
+    f.write_all(header.as_bytes())?;
+    for row in rows {
+        for v in row.iter() {
+            f.write_all(&v.to_le_bytes())?;
+        }
+    }
+    f.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::surrogate::{RunRecord, Summary, TrainingFormat, TrainingSet};
+    use crate::variable::Reduction;
+
+    fn run(name: &str, irrigation: f64, lai: Vec<f64>) -> RunRecord {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("irrigation".to_string(), irrigation);
+        let mut outputs = BTreeMap::new();
+        outputs.insert("plant_leaf_area_index".to_string(), lai);
+        RunRecord {
+            run: name.to_string(),
+            parameters,
+            outputs,
+            ..RunRecord::default()
+        }
+    }
+
+    #[test]
+    fn assemble_split_and_export() {
+        let runs: Vec<RunRecord> = (0..10)
+            .map(|i| run(&format!("r{}", i), i as f64, vec![0.0, i as f64, 1.0]))
+            .chain(std::iter::once(run("empty", 1.0, vec![])))
+            .collect();
+        let summaries = vec![
+            Summary {
+                name: "lai_max".to_string(),
+                variable: "plant_leaf_area_index".to_string(),
+                reduction: Some(Reduction::Max),
+            },
+            Summary {
+                name: "lai_final".to_string(),
+                variable: "plant_leaf_area_index".to_string(),
+                reduction: None,
+            },
+        ];
+        let (set, skipped) = TrainingSet::assemble(&runs, &summaries);
+        assert_eq!(skipped, vec!["empty"]);
+        assert_eq!(set.x.len(), 10);
+        assert_eq!(set.y[5], vec![5.0, 1.0]);
+
+        let (train, test) = set.split(0.2, 7).unwrap();
+        assert_eq!((train.len(), test.len()), (8, 2));
+        assert_eq!(set.split(0.2, 7).unwrap(), (train, test));
+        assert!(set.split(1.0, 7).is_err());
+
+        let dir = std::env::temp_dir().join(format!("meillionen-surrogate-{}", std::process::id()));
+        let metadata = set
+            .export(&dir, TrainingFormat::Npy, 0.2, 7, skipped)
+            .unwrap();
+        let npy = std::fs::read(dir.join("x_train.npy")).unwrap();
+        set.export(&dir, TrainingFormat::Parquet, 0.2, 7, vec![])
+            .unwrap();
+        let parquet = dir.join("test.parquet").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&npy[..6], b"\x93NUMPY");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(npy.len(), 10 + header_len + 8 * 8);
+        assert!(parquet);
+        assert_eq!(metadata.train_runs.len(), 8);
+        assert_eq!(metadata.y[1].sd, 1.0);
+    }
+}
@@ -0,0 +1,112 @@
+//! Labels on experiments and runs
+//!
+//! Tags are key-value pairs such as `project = "maize-2021"` that keep the
+//! results of different projects sharing one workspace apart. Trials inherit
+//! the tags of their experiment and may add or override their own.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum TagError {
+    #[error("tag key {0:?} must be letters, digits, '_', '-', '.' or '/' and not empty")]
+    InvalidKey(String),
+}
+
+pub fn check_key(key: &str) -> Result<(), TagError> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-./".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(TagError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Tags with `overrides` taking precedence over `base`
+pub fn merge(base: &Tags, overrides: &Tags) -> Tags {
+    let mut tags = base.clone();
+    tags.extend(overrides.iter().map(|(k, v)| (k.clone(), v.clone())));
+    tags
+}
+
+/// A condition on tags, `key=value` or just `key` to require the tag be set
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl TagFilter {
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match (tags.get(&self.key), &self.value) {
+            (Some(v), Some(want)) => v == want,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Whether tags match every filter
+    pub fn all(filters: &[TagFilter], tags: &Tags) -> bool {
+        filters.iter().all(|f| f.matches(tags))
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = TagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((k, v)) => (k.trim(), Some(v.trim().to_string())),
+            None => (s.trim(), None),
+        };
+        check_key(key)?;
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+impl fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(v) => write!(f, "{}={}", self.key, v),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tags::{merge, TagError, TagFilter, Tags};
+
+    #[test]
+    fn filter_tags() {
+        let experiment: Tags = vec![("project", "maize"), ("site", "ames")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut trial = Tags::new();
+        trial.insert("site".to_string(), "boone".to_string());
+        let tags = merge(&experiment, &trial);
+        assert_eq!(tags["site"], "boone");
+
+        let filters: Vec<TagFilter> = vec!["project=maize".parse().unwrap()];
+        assert!(TagFilter::all(&filters, &tags));
+        assert!(!TagFilter::all(&["site=ames".parse().unwrap()], &tags));
+        assert!(TagFilter::all(&["site".parse().unwrap()], &tags));
+        assert!(!TagFilter::all(&["owner".parse().unwrap()], &tags));
+        assert_eq!(
+            "=maize".parse::<TagFilter>(),
+            Err(TagError::InvalidKey("".to_string()))
+        );
+    }
+}
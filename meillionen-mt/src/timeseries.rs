@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Int32Array, PrimitiveArray};
+use arrow::datatypes::{ArrowPrimitiveType, DataType, Field, Float32Type, Float64Type, Schema};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::variable::{Float, Reduction};
+
+#[derive(Debug, Error)]
+pub enum TimeSeriesError {
+    #[error("column {0} not found")]
+    MissingColumn(String),
+    #[error("column {0} must be Int32 but is {1:?}")]
+    NotDay(String, DataType),
+    #[error("day {0} is not a valid day of year")]
+    InvalidDay(i32),
+    #[error("window size must be greater than zero")]
+    EmptyWindow,
+    #[error("period length must be greater than zero")]
+    EmptyPeriod,
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+}
+
+/// The length of the bins used when resampling a daily series
+///
+/// Day 366 is only valid in leap years, which also give February 29 days.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Period {
+    /// Consecutive bins of a number of days, counting from day one of each
+    /// year
+    Days {
+        days: u32,
+        #[serde(default)]
+        leap_year: bool,
+    },
+    /// Calendar months
+    Month {
+        #[serde(default)]
+        leap_year: bool,
+    },
+}
+
+impl Period {
+    pub fn days(days: u32) -> Self {
+        Period::Days {
+            days,
+            leap_year: false,
+        }
+    }
+
+    pub fn weekly() -> Self {
+        Self::days(7)
+    }
+
+    pub fn monthly() -> Self {
+        Period::Month { leap_year: false }
+    }
+
+    /// The same period in leap years or in common years
+    pub fn with_leap_year(self, leap_year: bool) -> Self {
+        match self {
+            Period::Days { days, .. } => Period::Days { days, leap_year },
+            Period::Month { .. } => Period::Month { leap_year },
+        }
+    }
+
+    pub fn leap_year(&self) -> bool {
+        match *self {
+            Period::Days { leap_year, .. } | Period::Month { leap_year } => leap_year,
+        }
+    }
+
+    /// The first day of year of the bin a day of year falls in
+    fn bin_start(&self, day: i32) -> Result<i32, TimeSeriesError> {
+        let year_length = if self.leap_year() { 366 } else { 365 };
+        if day < 1 || day > year_length {
+            return Err(TimeSeriesError::InvalidDay(day));
+        }
+        match *self {
+            Period::Days { days: 0, .. } => Err(TimeSeriesError::EmptyPeriod),
+            Period::Days { days, .. } => {
+                let n = days as i32;
+                Ok((day - 1) / n * n + 1)
+            }
+            Period::Month { leap_year } => {
+                let feb = if leap_year { 29 } else { 28 };
+                let lengths = [31, feb, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+                let mut start = 1;
+                for length in lengths.iter() {
+                    if day < start + length {
+                        break;
+                    }
+                    start += length;
+                }
+                Ok(start)
+            }
+        }
+    }
+}
+
+pub(crate) fn float_values<T>(array: &ArrayRef) -> Vec<f64>
+where
+    T: ArrowPrimitiveType,
+    T::Native: Float,
+{
+    array
+        .as_any()
+        .downcast_ref::<PrimitiveArray<T>>()
+        .expect("array type to match data type")
+        .iter()
+        .map(|v| v.map(Into::into).unwrap_or(f64::NAN))
+        .collect()
+}
+
+/// The values of a floating point or Int32 column as floats
+pub(crate) fn numeric_values(rb: &RecordBatch, name: &str) -> Option<Vec<f64>> {
+    let i = rb.schema().index_of(name).ok()?;
+    let column: &ArrayRef = rb.column(i);
+    match column.data_type() {
+        DataType::Float32 => Some(float_values::<Float32Type>(column)),
+        DataType::Float64 => Some(float_values::<Float64Type>(column)),
+        DataType::Int32 => Some(
+            column
+                .as_any()
+                .downcast_ref::<Int32Array>()
+                .expect("array type to match data type")
+                .iter()
+                .map(|v| v.map(f64::from).unwrap_or(f64::NAN))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn float_array<T>(values: Vec<f64>) -> ArrayRef
+where
+    T: ArrowPrimitiveType,
+    T::Native: Float,
+{
+    Arc::new(PrimitiveArray::<T>::from_iter_values(
+        values.into_iter().map(T::Native::from_f64),
+    ))
+}
+
+fn map_float_columns<F>(rb: &RecordBatch, f: F) -> Vec<(Field, ArrayRef)>
+where
+    F: Fn(Vec<f64>) -> Vec<f64>,
+{
+    let schema = rb.schema();
+    schema
+        .fields()
+        .iter()
+        .zip(rb.columns())
+        .filter_map(|(field, column)| {
+            let column = match field.data_type() {
+                DataType::Float32 => {
+                    float_array::<Float32Type>(f(float_values::<Float32Type>(column)))
+                }
+                DataType::Float64 => {
+                    float_array::<Float64Type>(f(float_values::<Float64Type>(column)))
+                }
+                _ => return None,
+            };
+            Some((
+                Field::new(field.name(), field.data_type().clone(), true),
+                column,
+            ))
+        })
+        .collect()
+}
+
+/// Apply a trailing window reduction to every floating point column
+///
+/// The first `window - 1` rows do not have a complete window and are NaN.
+/// Other columns (such as the day of year) are passed through unchanged.
+pub fn rolling(
+    rb: &RecordBatch,
+    window: usize,
+    reduction: Reduction,
+) -> Result<RecordBatch, TimeSeriesError> {
+    if window == 0 {
+        return Err(TimeSeriesError::EmptyWindow);
+    }
+    let mut rolled = map_float_columns(rb, |values| {
+        (0..values.len())
+            .map(|i| {
+                if i + 1 < window {
+                    f64::NAN
+                } else {
+                    reduction.apply(&mut values[i + 1 - window..=i].to_vec())
+                }
+            })
+            .collect()
+    })
+    .into_iter();
+    let schema = rb.schema();
+    let (fields, columns): (Vec<Field>, Vec<ArrayRef>) = schema
+        .fields()
+        .iter()
+        .zip(rb.columns())
+        .map(|(field, column)| match field.data_type() {
+            DataType::Float32 | DataType::Float64 => rolled.next().expect("a rolled column"),
+            _ => (field.clone(), column.clone()),
+        })
+        .unzip();
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+        columns,
+    )?)
+}
+
+/// Aggregate a daily series into coarser periods
+///
+/// `day` names an `Int32` day of year column. In the result it holds the
+/// first day of each period and is followed by the reduced floating point
+/// columns. Other columns cannot be aggregated and are dropped.
+///
+/// Rows must be in time order. A day of year lower than the one before it
+/// starts the next year, so a series of several years has a row for each
+/// period of each year, in order, and the same day of different years is
+/// never put in one bin.
+pub fn resample(
+    rb: &RecordBatch,
+    day: &str,
+    period: Period,
+    reduction: Reduction,
+) -> Result<RecordBatch, TimeSeriesError> {
+    if let Period::Days { days: 0, .. } = period {
+        return Err(TimeSeriesError::EmptyPeriod);
+    }
+    let schema = rb.schema();
+    let day_index = schema
+        .index_of(day)
+        .map_err(|_| TimeSeriesError::MissingColumn(day.to_string()))?;
+    let days = rb
+        .column(day_index)
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .ok_or_else(|| {
+            TimeSeriesError::NotDay(day.to_string(), rb.column(day_index).data_type().clone())
+        })?;
+    let mut bins: BTreeMap<(usize, i32), Vec<usize>> = BTreeMap::new();
+    let mut year = 0;
+    let mut previous = None;
+    for (row, d) in days.values().iter().enumerate() {
+        if previous.is_some_and(|p| *d < p) {
+            year += 1;
+        }
+        previous = Some(*d);
+        bins.entry((year, period.bin_start(*d)?))
+            .or_default()
+            .push(row);
+    }
+
+    let starts: ArrayRef = Arc::new(Int32Array::from(
+        bins.keys().map(|(_, start)| *start).collect::<Vec<i32>>(),
+    ));
+    let (fields, columns): (Vec<Field>, Vec<ArrayRef>) =
+        vec![(Field::new(day, DataType::Int32, false), starts)]
+            .into_iter()
+            .chain(map_float_columns(rb, |values| {
+                bins.values()
+                    .map(|rows| {
+                        let mut bin: Vec<f64> = rows.iter().map(|&r| values[r]).collect();
+                        reduction.apply(&mut bin)
+                    })
+                    .collect()
+            }))
+            .unzip();
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+        columns,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float32Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::timeseries::{resample, rolling, Period, TimeSeriesError};
+    use crate::variable::Reduction;
+
+    fn soil(days: Vec<i32>, stress: Vec<f32>) -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("soil_water_deficit_stress", DataType::Float32, false),
+            Field::new("day", DataType::Int32, false),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Float32Array::from(stress)),
+            Arc::new(Int32Array::from(days)),
+        ];
+        RecordBatch::try_new(Arc::new(schema), columns).unwrap()
+    }
+
+    fn floats(rb: &RecordBatch, i: usize) -> Vec<f32> {
+        rb.column(i)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap()
+            .values()
+            .to_vec()
+    }
+
+    #[test]
+    fn rolling_mean() {
+        let rb = soil(vec![1, 2, 3, 4], vec![1.0, 0.5, 0.0, 0.5]);
+        let rolled = rolling(&rb, 2, Reduction::Mean).unwrap();
+        let stress = floats(&rolled, 0);
+        assert!(stress[0].is_nan());
+        assert_eq!(&stress[1..], &[0.75, 0.25, 0.25]);
+        assert_eq!(rolled.column(1).as_ref(), rb.column(1).as_ref());
+        assert!(rolling(&rb, 0, Reduction::Sum).is_err());
+    }
+
+    #[test]
+    fn resample_weekly_and_monthly() {
+        let days: Vec<i32> = (25..=40).collect();
+        let stress: Vec<f32> = days.iter().map(|d| *d as f32).collect();
+        let rb = soil(days, stress);
+
+        let weekly = resample(&rb, "day", Period::weekly(), Reduction::Max).unwrap();
+        let starts = weekly
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(starts.values(), &[22, 29, 36]);
+        assert_eq!(floats(&weekly, 1), vec![28.0, 35.0, 40.0]);
+
+        let monthly = resample(&rb, "day", Period::monthly(), Reduction::Sum).unwrap();
+        let starts = monthly
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(starts.values(), &[1, 32]);
+        assert_eq!(
+            floats(&monthly, 1),
+            vec![(25..=31).sum::<i32>() as f32, (32..=40).sum::<i32>() as f32]
+        );
+    }
+
+    #[test]
+    fn month_starts() {
+        let leap = Period::monthly().with_leap_year(true);
+        let common = Period::monthly();
+        assert_eq!(common.bin_start(60).unwrap(), 60);
+        assert_eq!(leap.bin_start(60).unwrap(), 32);
+        assert_eq!(leap.bin_start(366).unwrap(), 336);
+        assert!(common.bin_start(366).is_err());
+        assert_eq!(
+            Period::weekly()
+                .with_leap_year(true)
+                .bin_start(366)
+                .unwrap(),
+            365
+        );
+        assert!(Period::weekly().bin_start(366).is_err());
+        assert!(matches!(
+            Period::days(0).bin_start(1),
+            Err(TimeSeriesError::EmptyPeriod)
+        ));
+        let period: Period = serde_json::from_str(r#"{"Days": {"days": 7}}"#).unwrap();
+        assert_eq!(period, Period::weekly());
+    }
+
+    #[test]
+    fn years_are_binned_apart() {
+        // the end of a leap year and the start of the next
+        let days = vec![364, 365, 366, 1, 2];
+        let rb = soil(days, vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+        let weekly = resample(
+            &rb,
+            "day",
+            Period::weekly().with_leap_year(true),
+            Reduction::Sum,
+        )
+        .unwrap();
+        let starts = weekly
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap();
+        assert_eq!(starts.values(), &[358, 365, 1]);
+        assert_eq!(floats(&weekly, 1), vec![1.0, 5.0, 9.0]);
+        assert!(resample(&rb, "day", Period::days(0), Reduction::Sum).is_err());
+    }
+}
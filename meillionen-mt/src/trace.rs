@@ -0,0 +1,379 @@
+use std::collections::hash_map::RandomState;
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+use thiserror::Error;
+
+/// The environment variable the trace context is passed to model programs in
+pub const TRACEPARENT_ENV: &str = "TRACEPARENT";
+
+/// The environment variable naming the file spans are appended to
+pub const OTLP_FILE_ENV: &str = "MEILLIONEN_OTLP_FILE";
+
+/// The standard OpenTelemetry environment variable naming the collector spans
+/// are sent to, `/v1/traces` is appended to it
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Like `OTLP_ENDPOINT_ENV` but the full URL of the traces endpoint
+pub const OTLP_TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+
+/// How long to wait on the collector before giving up on a span
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TraceError {
+    #[error("traceparent {0} is not a valid version 00 trace context")]
+    InvalidTraceParent(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("OTLP endpoint {0} is not an http:// URL")]
+    InvalidEndpoint(String),
+    #[error("OTLP collector at {endpoint} answered {status}")]
+    Rejected { endpoint: String, status: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    h.write_u128(now_unix_nano());
+    h.finish()
+}
+
+fn now_unix_nano() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != 2 * N
+        || !s
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+/// A W3C trace context identifying the span work is done under
+///
+/// Python callers pass the `traceparent` their tracer would inject into an
+/// HTTP request and the runner hands a child context to the model program
+/// in the `TRACEPARENT` environment variable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    pub fn new_root() -> Self {
+        let mut trace_id = [0u8; 16];
+        trace_id[..8].copy_from_slice(&random_u64().to_be_bytes());
+        trace_id[8..].copy_from_slice(&random_u64().to_be_bytes());
+        Self {
+            trace_id,
+            span_id: random_u64().to_be_bytes(),
+            sampled: true,
+        }
+    }
+
+    pub fn parse(traceparent: &str) -> Result<Self, TraceError> {
+        let invalid = || TraceError::InvalidTraceParent(traceparent.to_string());
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        if parts.len() != 4 || parts[0] != "00" {
+            return Err(invalid());
+        }
+        let trace_id = unhex::<16>(parts[1]).ok_or_else(invalid)?;
+        let span_id = unhex::<8>(parts[2]).ok_or_else(invalid)?;
+        let flags = unhex::<1>(parts[3]).ok_or_else(invalid)?;
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return Err(invalid());
+        }
+        Ok(Self {
+            trace_id,
+            span_id,
+            sampled: flags[0] & 1 == 1,
+        })
+    }
+
+    /// The trace context given to this process by its caller, if any
+    pub fn from_env() -> Option<Self> {
+        std::env::var(TRACEPARENT_ENV)
+            .ok()
+            .and_then(|tp| Self::parse(&tp).ok())
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    /// A new span in the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: random_u64().to_be_bytes(),
+            ..*self
+        }
+    }
+}
+
+/// A finished or running unit of work in a trace
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub name: String,
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub start_unix_nano: u128,
+    pub end_unix_nano: Option<u128>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Span {
+    /// Start a span, as a child of `parent` or as the root of a new trace
+    pub fn start(name: &str, parent: Option<&TraceContext>) -> Self {
+        let (context, parent_span_id) = match parent {
+            Some(p) => (p.child(), Some(p.span_id)),
+            None => (TraceContext::new_root(), None),
+        };
+        Self {
+            name: name.to_string(),
+            context,
+            parent_span_id,
+            start_unix_nano: now_unix_nano(),
+            end_unix_nano: None,
+            attributes: vec![],
+        }
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: &str) {
+        self.attributes.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn end(&mut self) {
+        self.end_unix_nano.get_or_insert_with(now_unix_nano);
+    }
+
+    fn to_otlp(&self) -> serde_json::Value {
+        let attributes: Vec<serde_json::Value> = self
+            .attributes
+            .iter()
+            .map(|(k, v)| json!({"key": k, "value": {"stringValue": v}}))
+            .collect();
+        json!({
+            "traceId": hex(&self.context.trace_id),
+            "spanId": hex(&self.context.span_id),
+            "parentSpanId": self.parent_span_id.map(|p| hex(&p)).unwrap_or_default(),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": self.start_unix_nano.to_string(),
+            "endTimeUnixNano": self.end_unix_nano.unwrap_or(self.start_unix_nano).to_string(),
+            "attributes": attributes,
+        })
+    }
+}
+
+/// Spans in the OTLP JSON encoding used by the collector's HTTP receiver
+pub fn to_otlp_json(service_name: &str, spans: &[Span]) -> serde_json::Value {
+    let spans: Vec<serde_json::Value> = spans.iter().map(Span::to_otlp).collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}]
+            },
+            "scopeSpans": [{
+                "scope": {"name": "meillionen"},
+                "spans": spans
+            }]
+        }]
+    })
+}
+
+/// Append spans as one line of OTLP JSON
+///
+/// The collector's `otlpjsonfile` receiver forwards the file to a tracing
+/// backend.
+pub fn append_otlp_json<P: AsRef<Path>>(
+    path: P,
+    service_name: &str,
+    spans: &[Span],
+) -> io::Result<()> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(f, "{}", to_otlp_json(service_name, spans))
+}
+
+/// The OTLP/HTTP traces endpoint set in the environment, if any
+pub fn otlp_endpoint_from_env() -> Option<String> {
+    match std::env::var(OTLP_TRACES_ENDPOINT_ENV) {
+        Ok(url) if !url.is_empty() => Some(url),
+        _ => std::env::var(OTLP_ENDPOINT_ENV)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .map(|url| format!("{}/v1/traces", url.trim_end_matches('/'))),
+    }
+}
+
+/// Send spans to an OpenTelemetry collector's OTLP/HTTP receiver
+///
+/// Spans are posted as OTLP JSON to `endpoint`, a plain `http://` URL like
+/// `http://localhost:4318/v1/traces`. Use a collector on the same host or
+/// network to forward them over TLS.
+pub fn export_otlp_http(
+    endpoint: &str,
+    service_name: &str,
+    spans: &[Span],
+) -> Result<(), ExportError> {
+    let invalid = || ExportError::InvalidEndpoint(endpoint.to_string());
+    let rest = endpoint.strip_prefix("http://").ok_or_else(invalid)?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    let addr = addr.to_socket_addrs()?.next().ok_or_else(invalid)?;
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    let body = to_otlp_json(service_name, spans).to_string();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+    let mut status = String::new();
+    BufReader::new(&stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(ExportError::Rejected {
+            endpoint: endpoint.to_string(),
+            status: status.trim().to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use crate::trace::{
+        export_otlp_http, to_otlp_json, ExportError, Span, TraceContext, TraceError,
+    };
+
+    const TP: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    #[test]
+    fn traceparent_round_trip() {
+        let ctx = TraceContext::parse(TP).unwrap();
+        assert!(ctx.sampled);
+        assert_eq!(ctx.traceparent(), TP);
+        let child = ctx.child();
+        assert_eq!(child.trace_id, ctx.trace_id);
+        assert_ne!(child.span_id, ctx.span_id);
+        for bad in [
+            "",
+            "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                TraceContext::parse(bad),
+                Err(TraceError::InvalidTraceParent(bad.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn otlp_spans() {
+        let parent = TraceContext::parse(TP).unwrap();
+        let mut span = Span::start("simplecrop run", Some(&parent));
+        span.set_attribute("program", "simplecrop");
+        span.end();
+        let otlp = to_otlp_json("meillionen", &[span.clone()]);
+        let s = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(s["traceId"], "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(s["parentSpanId"], "b7ad6b7169203331");
+        assert_eq!(s["name"], "simplecrop run");
+        assert_eq!(s["attributes"][0]["value"]["stringValue"], "simplecrop");
+        assert_eq!(Span::start("root", None).parent_span_id, None);
+    }
+
+    #[test]
+    fn otlp_http_export() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr().unwrap());
+        let collector = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut length = 0;
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                if let Some(n) = header.to_ascii_lowercase().strip_prefix("content-length:") {
+                    length = n.trim().parse().unwrap();
+                }
+                header.clear();
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (request, body)
+        });
+        let mut span = Span::start("run", Some(&TraceContext::parse(TP).unwrap()));
+        span.end();
+        export_otlp_http(&endpoint, "meillionen", &[span]).unwrap();
+        let (request, body) = collector.join().unwrap();
+        assert_eq!(request.trim(), "POST /v1/traces HTTP/1.1");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["name"],
+            "run"
+        );
+        assert!(matches!(
+            export_otlp_http("https://collector:4318/v1/traces", "meillionen", &[]),
+            Err(ExportError::InvalidEndpoint(_))
+        ));
+    }
+}
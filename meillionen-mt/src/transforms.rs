@@ -0,0 +1,281 @@
+//! Changes made to the values of sources before a model reads them
+//!
+//! A source is often not in the units or scale a model takes, such as
+//! reanalysis rainfall in metres for a model in millimetres. A trial declares
+//! how to fix that in a `transforms` table keyed by source name instead of
+//! the model program assuming one, so sources already in the right units are
+//! left alone:
+//!
+//! ```toml
+//! [trials.transforms.weather]
+//! variables = ["rainfall"]
+//! units = { from = "m", to = "mm" }
+//! min = 0.0
+//! ```
+//!
+//! Values are converted between units first, then multiplied by `scale`,
+//! added to `offset` and clamped to `min` and `max`. The transforms are
+//! passed to the model program in `MEILLIONEN_TRANSFORMS`, where readers
+//! apply them, and kept in the run manifest.
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "data")]
+use thiserror::Error;
+
+/// The environment variable holding the resolved transforms as JSON
+pub const TRANSFORMS_ENV: &str = "MEILLIONEN_TRANSFORMS";
+
+#[cfg(feature = "data")]
+#[derive(Debug, Error, PartialEq)]
+pub enum TransformError {
+    #[error("source {source_name} cannot be converted from {from} to {to}")]
+    Conversion {
+        source_name: String,
+        from: String,
+        to: String,
+    },
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn is_one(x: &f64) -> bool {
+    *x == 1.0
+}
+
+fn is_zero(x: &f64) -> bool {
+    *x == 0.0
+}
+
+/// The units a source is in and the ones the model takes
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UnitChange {
+    pub from: String,
+    pub to: String,
+}
+
+/// How the values of a source are changed before the model reads them
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ValueTransform {
+    /// The variables or columns changed, every floating point one when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<UnitChange>,
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub scale: f64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offset: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
+
+impl Default for ValueTransform {
+    fn default() -> Self {
+        Self {
+            variables: vec![],
+            units: None,
+            scale: 1.0,
+            offset: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl ValueTransform {
+    pub fn scaled(scale: f64) -> Self {
+        Self {
+            scale,
+            ..Self::default()
+        }
+    }
+
+    pub fn converted(from: &str, to: &str) -> Self {
+        Self {
+            units: Some(UnitChange {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// The same transform clamped to a range, either end of which may be open
+    pub fn clamped(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    /// The transform as a single `value * scale + offset` with the unit
+    /// conversion folded in
+    #[cfg(feature = "data")]
+    pub fn resolve(&self, source_name: &str) -> Result<ValueTransform, TransformError> {
+        let (scale, offset) = match &self.units {
+            Some(UnitChange { from, to }) => crate::variable::negotiate::conversion(from, to)
+                .ok_or_else(|| TransformError::Conversion {
+                    source_name: source_name.to_string(),
+                    from: from.clone(),
+                    to: to.clone(),
+                })?,
+            None => (1.0, 0.0),
+        };
+        Ok(ValueTransform {
+            variables: self.variables.clone(),
+            units: None,
+            scale: scale * self.scale,
+            offset: offset * self.scale + self.offset,
+            min: self.min,
+            max: self.max,
+        })
+    }
+
+    /// Transform a value, which must have been resolved first
+    pub fn apply(&self, value: f64) -> f64 {
+        let value = value * self.scale + self.offset;
+        let value = self.min.map_or(value, |min| value.max(min));
+        self.max.map_or(value, |max| value.min(max))
+    }
+}
+
+/// Transforms of a trial's sources keyed by source name
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Transforms {
+    sources: BTreeMap<String, ValueTransform>,
+}
+
+impl Transforms {
+    pub fn new<I, S>(sources: I) -> Self
+    where
+        I: IntoIterator<Item = (S, ValueTransform)>,
+        S: Into<String>,
+    {
+        Self {
+            sources: sources.into_iter().map(|(s, t)| (s.into(), t)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn get(&self, source_name: &str) -> Option<&ValueTransform> {
+        self.sources.get(source_name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ValueTransform)> {
+        self.sources.iter().map(|(s, t)| (s.as_str(), t))
+    }
+
+    /// Problems such as transforms of sources the trial doesn't have or
+    /// ranges that are empty
+    pub fn problems<'a>(&self, sources: impl Iterator<Item = &'a String> + Clone) -> Vec<String> {
+        let mut problems = vec![];
+        for (name, transform) in self.iter() {
+            if !sources.clone().any(|s| s == name) {
+                problems.push(format!(
+                    "transform of {} which is not one of its sources",
+                    name
+                ));
+            }
+            if !(transform.scale.is_finite() && transform.offset.is_finite()) {
+                problems.push(format!(
+                    "transform of {} has scale {} and offset {}, which must be numbers",
+                    name, transform.scale, transform.offset
+                ));
+            }
+            if let (Some(min), Some(max)) = (transform.min, transform.max) {
+                if min > max {
+                    problems.push(format!(
+                        "transform of {} clamps to {} to {}, which is empty",
+                        name, min, max
+                    ));
+                }
+            }
+            #[cfg(feature = "data")]
+            if let Err(e) = transform.resolve(name) {
+                problems.push(e.to_string());
+            }
+        }
+        problems
+    }
+
+    /// The value of [`TRANSFORMS_ENV`] for the transforms, resolved so model
+    /// programs need no unit conversions, if there are any
+    #[cfg(feature = "data")]
+    pub fn env_value(&self) -> Result<Option<String>, TransformError> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let resolved = self
+            .iter()
+            .map(|(name, t)| Ok((name.to_string(), t.resolve(name)?)))
+            .collect::<Result<BTreeMap<_, _>, TransformError>>()?;
+        Ok(Some(
+            serde_json::to_string(&resolved).expect("transforms to serialize"),
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use crate::transforms::{TransformError, Transforms, ValueTransform};
+
+    #[test]
+    fn resolve_and_apply() {
+        let t = ValueTransform::converted("m", "mm").clamped(Some(0.0), None);
+        let resolved = t.resolve("weather").unwrap();
+        assert_eq!(resolved.apply(0.012), 12.0);
+        assert_eq!(resolved.apply(-0.001), 0.0);
+
+        let celsius = ValueTransform {
+            scale: 2.0,
+            ..ValueTransform::converted("K", "degC")
+        }
+        .resolve("weather")
+        .unwrap();
+        assert_eq!(celsius.apply(283.15), 20.0);
+
+        let transforms = Transforms::new(vec![("weather", ValueTransform::scaled(100.0))]);
+        assert_eq!(
+            transforms.env_value().unwrap(),
+            Some(r#"{"weather":{"scale":100.0}}"#.to_string())
+        );
+        assert_eq!(Transforms::default().env_value().unwrap(), None);
+    }
+
+    #[test]
+    fn problems() {
+        let transforms = Transforms::new(vec![
+            ("weather", ValueTransform::converted("mm", "K")),
+            (
+                "soil",
+                ValueTransform::scaled(1.0).clamped(Some(1.0), Some(0.0)),
+            ),
+        ]);
+        let sources = ["weather".to_string()];
+        assert_eq!(
+            transforms.problems(sources.iter()),
+            vec![
+                "transform of soil which is not one of its sources",
+                "transform of soil clamps to 1 to 0, which is empty",
+                "source weather cannot be converted from mm to K",
+            ]
+        );
+        assert_eq!(
+            transforms.env_value().unwrap_err(),
+            TransformError::Conversion {
+                source_name: "weather".to_string(),
+                from: "mm".to_string(),
+                to: "K".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Stdout};
+
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph, Row, Table, Wrap};
+use ratatui::{Frame, Terminal};
+
+use crate::progress::{EnsembleProgress, MemberState};
+
+/// A full screen view of an ensemble run
+///
+/// The terminal is restored when the dashboard is dropped.
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Dashboard {
+    pub fn new() -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        Ok(Self {
+            terminal: Terminal::new(CrosstermBackend::new(stdout))?,
+        })
+    }
+
+    pub fn draw(&mut self, progress: &EnsembleProgress) -> io::Result<()> {
+        self.terminal.draw(|f| render(f, progress))?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+    }
+}
+
+fn state_cell(state: &MemberState) -> (&'static str, Color) {
+    match state {
+        MemberState::Pending => ("pending", Color::DarkGray),
+        MemberState::Running => ("running", Color::Yellow),
+        MemberState::Succeeded => ("succeeded", Color::Green),
+        MemberState::Failed { .. } => ("failed", Color::Red),
+    }
+}
+
+pub fn render(f: &mut Frame, progress: &EnsembleProgress) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(8),
+        ])
+        .split(f.size());
+
+    let total = progress.members.len().max(1);
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title("ensemble"))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(progress.finished() as f64 / total as f64)
+        .label(format!(
+            "{}/{} finished, {} failed, {:.1} runs/min",
+            progress.finished(),
+            progress.members.len(),
+            progress.failed(),
+            progress.throughput()
+        ));
+    f.render_widget(gauge, chunks[0]);
+
+    let rows = progress.members.iter().map(|m| {
+        let (state, color) = state_cell(&m.state);
+        let elapsed = m
+            .elapsed
+            .map(|e| format!("{:.1}s", e.as_secs_f64()))
+            .unwrap_or_default();
+        Row::new(vec![m.name.clone(), state.to_string(), elapsed]).style(Style::default().fg(color))
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(50),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ],
+    )
+    .header(Row::new(vec!["member", "state", "elapsed"]))
+    .block(Block::default().borders(Borders::ALL).title("members"));
+    f.render_widget(table, chunks[1]);
+
+    let failures: Vec<Line> = progress
+        .members
+        .iter()
+        .filter_map(|m| match &m.state {
+            MemberState::Failed { stderr } => Some((m, stderr)),
+            _ => None,
+        })
+        .flat_map(|(m, stderr)| {
+            std::iter::once(Line::from(format!("{}:", m.name)))
+                .chain(stderr.lines().map(|l| Line::from(format!("  {}", l))))
+        })
+        .collect();
+    let failures = Paragraph::new(failures)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title("failures"));
+    f.render_widget(failures, chunks[2]);
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use crate::progress::EnsembleProgress;
+    use crate::tui::render;
+
+    #[test]
+    fn draws_members_and_failures() {
+        let mut p = EnsembleProgress::new(&["baseline", "drought"]);
+        p.start("baseline");
+        p.finish("baseline", Ok(()));
+        p.start("drought");
+        p.finish("drought", Err("weather file missing".to_string()));
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal.draw(|f| render(f, &p)).unwrap();
+        let text: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect();
+        assert!(text.contains("2/2 finished, 1 failed"));
+        assert!(text.contains("succeeded"));
+        assert!(text.contains("weather file missing"));
+    }
+}
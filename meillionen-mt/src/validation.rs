@@ -0,0 +1,334 @@
+use std::fmt;
+
+#[cfg(feature = "data")]
+use arrow::record_batch::RecordBatch;
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(feature = "data")]
+use crate::timeseries::numeric_values;
+#[cfg(feature = "data")]
+use crate::variable::Dataset;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("column {0} not found")]
+    MissingColumn(String),
+    #[error("column {0} is not numeric so it cannot be validated")]
+    NotNumeric(String),
+    #[cfg(feature = "data")]
+    #[error(transparent)]
+    Arrow(#[from] arrow::error::ArrowError),
+}
+
+fn default_tolerance() -> f64 {
+    1e-6
+}
+
+/// How two columns of a row are compared
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    #[cfg(feature = "data")]
+    fn holds(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+        }
+    }
+}
+
+/// A check on the columns of a table
+///
+/// The `rule` key picks the kind of check. Missing values are skipped by
+/// every rule except `no_nan`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Rule {
+    Min {
+        column: String,
+        value: f64,
+    },
+    Max {
+        column: String,
+        value: f64,
+    },
+    /// Values never decrease, or never increase when `decreasing`
+    Monotonic {
+        column: String,
+        #[serde(default)]
+        decreasing: bool,
+        /// Neighbouring values must differ
+        #[serde(default)]
+        strict: bool,
+    },
+    NoNan {
+        column: String,
+    },
+    /// The columns of each row add up to one, such as soil texture fractions
+    SumToOne {
+        columns: Vec<String>,
+        #[serde(default = "default_tolerance")]
+        tolerance: f64,
+    },
+    /// Compare two columns of each row, such as `tmin <= tmax`
+    Compare {
+        left: String,
+        op: Comparison,
+        right: String,
+    },
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Min { column, value } => write!(f, "{} >= {}", column, value),
+            Rule::Max { column, value } => write!(f, "{} <= {}", column, value),
+            Rule::Monotonic {
+                column,
+                decreasing,
+                strict,
+            } => write!(
+                f,
+                "{} is {}{}",
+                column,
+                if *strict { "strictly " } else { "" },
+                if *decreasing {
+                    "decreasing"
+                } else {
+                    "increasing"
+                }
+            ),
+            Rule::NoNan { column } => write!(f, "{} has no missing values", column),
+            Rule::SumToOne { columns, .. } => write!(f, "{} sum to one", columns.join(" + ")),
+            Rule::Compare { left, op, right } => {
+                write!(f, "{} {} {}", left, op.symbol(), right)
+            }
+        }
+    }
+}
+
+/// The values of a column across batches
+#[cfg(feature = "data")]
+fn column(batches: &[RecordBatch], name: &str) -> Result<Vec<f64>, ValidationError> {
+    let mut values = vec![];
+    for rb in batches {
+        if rb.schema().index_of(name).is_err() {
+            return Err(ValidationError::MissingColumn(name.to_string()));
+        }
+        values.extend(
+            numeric_values(rb, name)
+                .ok_or_else(|| ValidationError::NotNumeric(name.to_string()))?,
+        );
+    }
+    Ok(values)
+}
+
+#[cfg(feature = "data")]
+impl Rule {
+    /// The rows that break the rule
+    pub fn failures(&self, batches: &[RecordBatch]) -> Result<Vec<usize>, ValidationError> {
+        let rows = |ok: &dyn Fn(usize) -> bool, n: usize| (0..n).filter(|&i| !ok(i)).collect();
+        match self {
+            Rule::Min { column: c, value } => {
+                let v = column(batches, c)?;
+                Ok(rows(&|i| v[i].is_nan() || v[i] >= *value, v.len()))
+            }
+            Rule::Max { column: c, value } => {
+                let v = column(batches, c)?;
+                Ok(rows(&|i| v[i].is_nan() || v[i] <= *value, v.len()))
+            }
+            Rule::Monotonic {
+                column: c,
+                decreasing,
+                strict,
+            } => {
+                let v = column(batches, c)?;
+                let mut failures = vec![];
+                let mut last: Option<f64> = None;
+                for (i, &x) in v.iter().enumerate() {
+                    if x.is_nan() {
+                        continue;
+                    }
+                    if let Some(prev) = last {
+                        let (a, b) = if *decreasing { (x, prev) } else { (prev, x) };
+                        if a > b || (*strict && a == b) {
+                            failures.push(i);
+                        }
+                    }
+                    last = Some(x);
+                }
+                Ok(failures)
+            }
+            Rule::NoNan { column: c } => {
+                let v = column(batches, c)?;
+                Ok(rows(&|i| !v[i].is_nan(), v.len()))
+            }
+            Rule::SumToOne { columns, tolerance } => {
+                let vs = columns
+                    .iter()
+                    .map(|c| column(batches, c))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let n = vs.first().map_or(0, Vec::len);
+                Ok(rows(
+                    &|i| {
+                        let sum: f64 = vs.iter().map(|v| v[i]).sum();
+                        sum.is_nan() || (sum - 1.0).abs() <= *tolerance
+                    },
+                    n,
+                ))
+            }
+            Rule::Compare { left, op, right } => {
+                let l = column(batches, left)?;
+                let r = column(batches, right)?;
+                Ok(rows(
+                    &|i| l[i].is_nan() || r[i].is_nan() || op.holds(l[i], r[i]),
+                    l.len(),
+                ))
+            }
+        }
+    }
+
+    /// A message describing where the rule fails, if it does
+    pub fn check(&self, batches: &[RecordBatch]) -> Result<Option<String>, ValidationError> {
+        let failures = self.failures(batches)?;
+        Ok(failures.first().map(|first| {
+            format!(
+                "{} fails at {} rows, first at row {}",
+                self,
+                failures.len(),
+                first
+            )
+        }))
+    }
+}
+
+/// The messages of every rule that fails
+#[cfg(feature = "data")]
+pub fn validate(rules: &[Rule], batches: &[RecordBatch]) -> Result<Vec<String>, ValidationError> {
+    let mut problems = vec![];
+    for rule in rules {
+        problems.extend(rule.check(batches)?);
+    }
+    Ok(problems)
+}
+
+/// Validate the variables of a dataset, with a column per variable and
+/// dimension as in `Dataset::to_recordbatch`
+#[cfg(feature = "data")]
+pub fn validate_dataset(rules: &[Rule], dataset: &Dataset) -> Result<Vec<String>, ValidationError> {
+    validate(rules, &[dataset.to_recordbatch()?])
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float64Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::validation::{validate, Rule, ValidationError};
+
+    const RULES: &str = r#"
+[[rules]]
+rule = "min"
+column = "rain"
+value = 0.0
+
+[[rules]]
+rule = "monotonic"
+column = "day"
+strict = true
+
+[[rules]]
+rule = "compare"
+left = "tmin"
+op = "le"
+right = "tmax"
+
+[[rules]]
+rule = "no_nan"
+column = "tmax"
+
+[[rules]]
+rule = "sum_to_one"
+columns = ["tmin", "tmax"]
+"#;
+
+    #[derive(serde_derive::Deserialize)]
+    struct Rules {
+        rules: Vec<Rule>,
+    }
+
+    #[test]
+    fn rules_from_config() {
+        let rules: Rules = toml::from_str(RULES).unwrap();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("day", DataType::Int32, false),
+            Field::new("rain", DataType::Float64, true),
+            Field::new("tmin", DataType::Float64, true),
+            Field::new("tmax", DataType::Float64, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(Int32Array::from(vec![1, 2, 2, 3])),
+            Arc::new(Float64Array::from(vec![
+                Some(0.0),
+                Some(-1.0),
+                None,
+                Some(2.0),
+            ])),
+            Arc::new(Float64Array::from(vec![0.25, 5.0, 0.5, 0.0])),
+            Arc::new(Float64Array::from(vec![
+                Some(0.75),
+                Some(4.0),
+                None,
+                Some(1.0),
+            ])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let problems = validate(&rules.rules, std::slice::from_ref(&rb)).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "rain >= 0 fails at 1 rows, first at row 1",
+                "day is strictly increasing fails at 1 rows, first at row 2",
+                "tmin <= tmax fails at 1 rows, first at row 1",
+                "tmax has no missing values fails at 1 rows, first at row 2",
+                "tmin + tmax sum to one fails at 1 rows, first at row 1",
+            ]
+        );
+
+        let missing = Rule::NoNan {
+            column: "wind".to_string(),
+        };
+        assert!(matches!(
+            missing.check(&[rb]),
+            Err(ValidationError::MissingColumn(_))
+        ));
+    }
+}
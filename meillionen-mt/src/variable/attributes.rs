@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::variable::MissingValues;
+
+/// NetCDF variable attributes
+///
+/// The attributes used by the CF conventions have their own fields, any
+/// others are kept in `other` so they survive a read and write.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Attributes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standard_name: Option<String>,
+    /// The calendar of a time coordinate, see [`crate::variable::time`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<String>,
+    #[serde(flatten)]
+    pub missing: MissingValues,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl Attributes {
+    pub fn with_units(mut self, units: &str) -> Self {
+        self.units = Some(units.to_string());
+        self
+    }
+
+    pub fn with_long_name(mut self, long_name: &str) -> Self {
+        self.long_name = Some(long_name.to_string());
+        self
+    }
+
+    pub fn with_standard_name(mut self, standard_name: &str) -> Self {
+        self.standard_name = Some(standard_name.to_string());
+        self
+    }
+
+    pub fn with_calendar(mut self, calendar: &str) -> Self {
+        self.calendar = Some(calendar.to_string());
+        self
+    }
+
+    pub fn with_missing(mut self, missing: MissingValues) -> Self {
+        self.missing = missing;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::{Attributes, MissingValues};
+
+    #[test]
+    fn round_trip() {
+        let s = r#"{"units":"mm","long_name":"rainfall","_FillValue":-9999.0,"source":"era5"}"#;
+        let attrs: Attributes = serde_json::from_str(s).unwrap();
+        assert_eq!(attrs.units.as_deref(), Some("mm"));
+        assert_eq!(attrs.missing.fill_value, Some(-9999.0));
+        assert_eq!(attrs.other["source"], "era5");
+        assert_eq!(
+            serde_json::from_str::<Attributes>(&serde_json::to_string(&attrs).unwrap()).unwrap(),
+            attrs
+        );
+        let attrs = Attributes::default()
+            .with_units("mm")
+            .with_missing(MissingValues {
+                fill_value: None,
+                missing_value: Some(-1.0),
+            });
+        assert_eq!(
+            serde_json::to_string(&attrs).unwrap(),
+            r#"{"units":"mm","missing_value":-1.0}"#
+        );
+    }
+}
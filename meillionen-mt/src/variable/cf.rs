@@ -0,0 +1,141 @@
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::variable::Attributes;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A way a variable does not follow the CF conventions
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CfIssue {
+    pub variable: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for CfIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}: {}", self.severity, self.variable, self.message)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check a variable against the CF conventions before it is written
+///
+/// Only the variable's name, dimension names and attributes are inspected.
+pub fn check_variable(name: &str, dimensions: &[String], attributes: &Attributes) -> Vec<CfIssue> {
+    let mut issues = vec![];
+    let mut issue = |severity, message: String| {
+        issues.push(CfIssue {
+            variable: name.to_string(),
+            severity,
+            message,
+        })
+    };
+
+    if !is_valid_name(name) {
+        issue(
+            Severity::Error,
+            "names must start with a letter and contain only letters, digits and underscores"
+                .to_string(),
+        );
+    }
+    for d in dimensions.iter().filter(|d| !is_valid_name(d)) {
+        issue(
+            Severity::Error,
+            format!("dimension name {} is not valid", d),
+        );
+    }
+    match attributes.units.as_deref() {
+        None => issue(Severity::Warning, "units attribute is missing".to_string()),
+        Some(u) if u.trim().is_empty() => {
+            issue(Severity::Error, "units attribute is empty".to_string())
+        }
+        _ => {}
+    }
+    if attributes.long_name.is_none() && attributes.standard_name.is_none() {
+        issue(
+            Severity::Warning,
+            "one of long_name or standard_name should be given".to_string(),
+        );
+    }
+    if let Some(sn) = attributes.standard_name.as_deref() {
+        let table_name = sn.split_whitespace().next().unwrap_or("");
+        if table_name.is_empty()
+            || !table_name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            issue(
+                Severity::Error,
+                format!("standard_name {} is not a standard name table entry", sn),
+            );
+        }
+    }
+    for (attr, value) in [
+        ("_FillValue", attributes.missing.fill_value),
+        ("missing_value", attributes.missing.missing_value),
+    ]
+    .iter()
+    {
+        if value.map(f64::is_nan).unwrap_or(false) {
+            issue(Severity::Error, format!("{} must not be NaN", attr));
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::cf::{check_variable, Severity};
+    use crate::variable::{Attributes, MissingValues};
+
+    #[test]
+    fn compliant() {
+        let attrs = Attributes::default()
+            .with_units("kg m-2")
+            .with_standard_name("precipitation_amount");
+        let dims = vec!["time".to_string(), "x".to_string()];
+        assert_eq!(check_variable("rainfall", &dims, &attrs), vec![]);
+    }
+
+    #[test]
+    fn issues() {
+        let attrs = Attributes::default()
+            .with_standard_name("Precipitation Amount")
+            .with_missing(MissingValues {
+                fill_value: Some(f64::NAN),
+                missing_value: None,
+            });
+        let dims = vec!["2d".to_string()];
+        let issues = check_variable("soil-water", &dims, &attrs);
+        let severities: Vec<Severity> = issues.iter().map(|i| i.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Severity::Error,
+                Severity::Error,
+                Severity::Warning,
+                Severity::Error,
+                Severity::Error
+            ]
+        );
+        assert_eq!(
+            issues[2].to_string(),
+            "Warning soil-water: units attribute is missing"
+        );
+    }
+}
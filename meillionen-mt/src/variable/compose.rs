@@ -0,0 +1,222 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::variable::{
+    Broadcast, Dataset, Reduction, VarView, Variable, VariableError, VecVariable,
+};
+
+fn one() -> f64 {
+    1.0
+}
+
+fn sum() -> Reduction {
+    Reduction::Sum
+}
+
+/// A variable of a dataset that is part of a [`Composition`]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Term {
+    pub variable: String,
+    /// What each value is multiplied by first, such as a unit conversion or
+    /// the share of irrigation that reaches the soil
+    #[serde(default = "one")]
+    pub scale: f64,
+}
+
+impl Term {
+    pub fn new(variable: &str) -> Self {
+        Self {
+            variable: variable.to_string(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn scaled(mut self, scale: f64) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
+/// A variable made from several others by a declared rule, such as the water
+/// reaching the soil from rain, snowmelt and irrigation of upstream models
+///
+/// Terms are broadcast over the dimensions any of them have, so a time only
+/// irrigation schedule adds to every cell of gridded rain. Each element is
+/// the `combine` reduction of the scaled terms, clamped to `min` if given.
+/// Terms are read masked by their attributes, and an element is NaN if any
+/// of its terms is missing.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Composition {
+    pub terms: Vec<Term>,
+    #[serde(default = "sum")]
+    pub combine: Reduction,
+    /// The smallest value of the result, such as zero for water input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+}
+
+impl Composition {
+    /// The sum of variables
+    pub fn sum(variables: &[&str]) -> Self {
+        Self {
+            terms: variables.iter().map(|v| Term::new(v)).collect(),
+            combine: Reduction::Sum,
+            min: None,
+        }
+    }
+
+    pub fn with_min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    /// Compose the variables of `dataset`, with the dataset's dimensions
+    /// used by any term in the dataset's order
+    pub fn compose(&self, dataset: &Dataset) -> Result<VecVariable<f64>, VariableError> {
+        self.combine.check()?;
+        if self.terms.is_empty() {
+            return Err(VariableError::NoParts);
+        }
+        let variables = self
+            .terms
+            .iter()
+            .map(|t| {
+                dataset
+                    .get(&t.variable)
+                    .ok_or_else(|| VariableError::MissingVariable(t.variable.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let dimensions: Vec<_> = dataset
+            .dimensions()
+            .iter()
+            .filter(|d| {
+                variables
+                    .iter()
+                    .any(|v| v.dimension_position(&d.name).is_some())
+            })
+            .cloned()
+            .collect();
+        let views = variables
+            .into_iter()
+            .map(|v| {
+                VarView::broadcast(
+                    v.masked_by_attributes(),
+                    dimensions.clone(),
+                    &Broadcast::All,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let values: Vec<Vec<f64>> = views.iter().map(|v| v.to_vec()).collect();
+        let len = dimensions.iter().map(|d| d.size).product();
+        let mut terms = vec![0.0; self.terms.len()];
+        let data = (0..len)
+            .map(|i| {
+                for ((term, values), value) in self.terms.iter().zip(&values).zip(&mut terms) {
+                    *value = values[i] * term.scale;
+                }
+                if terms.iter().any(|v| v.is_nan()) {
+                    return f64::NAN;
+                }
+                let combined = self.combine.apply(&mut terms);
+                self.min.map_or(combined, |min| combined.max(min))
+            })
+            .collect();
+        VecVariable::new(dimensions, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::compose::{Composition, Term};
+    use crate::variable::{
+        dim, Attributes, Dataset, MissingValues, Reduction, Variable, VariableError, VecVariable,
+    };
+
+    fn fluxes() -> Dataset {
+        let mut ds = Dataset::new();
+        ds.insert(
+            "rain",
+            VecVariable::new(
+                vec![dim("x", 2), dim("t", 3)],
+                vec![1.0, 0.0, 2.0, 3.0, 0.0, 0.0],
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        ds.insert(
+            "snowmelt",
+            VecVariable::new(
+                vec![dim("x", 2), dim("t", 3)],
+                vec![0.0, 4.0, 0.0, 0.0, 1.0, -2.0],
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        ds.insert(
+            "irrigation",
+            VecVariable::new(vec![dim("t", 3)], vec![10.0, 0.0, 0.0]).unwrap(),
+        )
+        .unwrap();
+        ds
+    }
+
+    #[test]
+    fn compose_fluxes() {
+        let ds = fluxes();
+        let water = Composition::sum(&["rain", "snowmelt", "irrigation"])
+            .with_min(0.0)
+            .compose(&ds)
+            .unwrap();
+        assert_eq!(water.dimension_names(), vec!["x", "t"]);
+        assert_eq!(water.to_vec(), vec![11.0, 4.0, 2.0, 13.0, 1.0, 0.0]);
+
+        let composition = Composition {
+            terms: vec![Term::new("rain"), Term::new("irrigation").scaled(0.5)],
+            combine: Reduction::Max,
+            min: None,
+        };
+        assert_eq!(
+            composition.compose(&ds).unwrap().to_vec(),
+            vec![5.0, 0.0, 2.0, 5.0, 0.0, 0.0]
+        );
+
+        // a fill value in one term makes the element missing, even after
+        // clamping
+        let mut masked = fluxes();
+        let missing = MissingValues {
+            fill_value: Some(-9999.0),
+            missing_value: None,
+        };
+        masked
+            .insert(
+                "rain",
+                VecVariable::new(
+                    vec![dim("x", 2), dim("t", 3)],
+                    vec![1.0, -9999.0, 2.0, 3.0, 0.0, 0.0],
+                )
+                .unwrap()
+                .with_attributes(Attributes::default().with_missing(missing)),
+            )
+            .unwrap();
+        let water = Composition::sum(&["rain", "snowmelt"])
+            .with_min(0.0)
+            .compose(&masked)
+            .unwrap()
+            .to_vec();
+        assert_eq!(water[0], 1.0);
+        assert!(water[1].is_nan());
+
+        let only_irrigation = Composition::sum(&["irrigation"]).compose(&ds).unwrap();
+        assert_eq!(only_irrigation.dimension_names(), vec!["t"]);
+
+        assert_eq!(
+            Composition::sum(&["rain", "runoff"])
+                .compose(&ds)
+                .unwrap_err(),
+            VariableError::MissingVariable("runoff".to_string())
+        );
+        assert_eq!(
+            Composition::sum(&[]).compose(&ds).unwrap_err(),
+            VariableError::NoParts
+        );
+    }
+}
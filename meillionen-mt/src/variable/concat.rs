@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use crate::extension_columns::DimMeta;
+use crate::variable::{Attributes, Variable, VariableError};
+
+/// Several variables joined end to end along one dimension
+///
+/// The parts must have the same dimensions in the same order and agree on
+/// every size except along the joined dimension. This presents a forcing
+/// split into one file per year as a single time series.
+#[derive(Clone, Debug)]
+pub struct Concat<V> {
+    parts: Vec<V>,
+    dimensions: Vec<Arc<DimMeta>>,
+    position: usize,
+    offsets: Vec<usize>,
+}
+
+impl<V: Variable> Concat<V> {
+    pub fn new(parts: Vec<V>, name: &str) -> Result<Self, VariableError> {
+        let first = parts.first().ok_or(VariableError::NoParts)?;
+        let position = first.dimension_position(name).ok_or_else(|| {
+            VariableError::MissingDimension(name.to_string(), first.dimension_names())
+        })?;
+        let names = first.dimension_names();
+        let mut dimensions = first.dimensions().to_vec();
+        for part in parts.iter() {
+            if part.dimension_names() != names {
+                let missing = names
+                    .iter()
+                    .zip(part.dimension_names())
+                    .find(|(a, b)| *a != b)
+                    .map(|(a, _)| a.clone())
+                    .unwrap_or_else(|| name.to_string());
+                return Err(VariableError::MissingDimension(
+                    missing,
+                    part.dimension_names(),
+                ));
+            }
+            for (i, (a, b)) in dimensions.iter().zip(part.dimensions()).enumerate() {
+                if i != position && a.size != b.size {
+                    return Err(VariableError::SizeMismatch {
+                        name: a.name.clone(),
+                        expected: a.size,
+                        got: b.size,
+                    });
+                }
+            }
+        }
+        // empty parts would make the offsets ambiguous
+        let parts: Vec<V> = parts
+            .into_iter()
+            .filter(|p| p.dimensions()[position].size > 0)
+            .collect();
+        let mut offsets = Vec::with_capacity(parts.len());
+        let mut total = 0;
+        for part in parts.iter() {
+            offsets.push(total);
+            total += part.dimensions()[position].size;
+        }
+        // the joined dimension keeps its labels and times only if every part
+        // has them
+        let labels = parts
+            .iter()
+            .map(|p| p.dimensions()[position].labels.clone())
+            .collect::<Option<Vec<_>>>()
+            .map(|labels| labels.concat());
+        let times = parts
+            .iter()
+            .map(|p| p.dimensions()[position].times.clone())
+            .collect::<Option<Vec<_>>>()
+            .map(|times| times.concat());
+        dimensions[position] = Arc::new(DimMeta {
+            size: total,
+            labels,
+            times,
+            ..(*dimensions[position]).clone()
+        });
+        Ok(Self {
+            parts,
+            dimensions,
+            position,
+            offsets,
+        })
+    }
+
+    pub fn parts(&self) -> &[V] {
+        &self.parts
+    }
+}
+
+impl<V: Variable> Variable for Concat<V> {
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        let i = index[self.position];
+        let part = match self.offsets.binary_search(&i) {
+            Ok(part) => part,
+            Err(part) => part - 1,
+        };
+        let mut inner_index = index.to_vec();
+        inner_index[self.position] = i - self.offsets[part];
+        self.parts[part].get(&inner_index)
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        self.parts.first().and_then(|p| p.attributes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::concat::Concat;
+    use crate::variable::{dim, Variable, VariableError, VecVariable};
+
+    #[test]
+    fn yearly_files() {
+        let y1 = VecVariable::new(vec![dim("x", 2), dim("time", 2)], vec![1, 2, 3, 4]).unwrap();
+        let y2 =
+            VecVariable::new(vec![dim("x", 2), dim("time", 3)], vec![5, 6, 7, 8, 9, 10]).unwrap();
+        let empty = VecVariable::new(vec![dim("x", 2), dim("time", 0)], vec![]).unwrap();
+        let c = Concat::new(vec![y1, empty, y2], "time").unwrap();
+        assert_eq!(c.shape(), vec![2, 5]);
+        assert_eq!(c.to_vec(), vec![1, 2, 5, 6, 7, 3, 4, 8, 9, 10]);
+        assert_eq!(c.slice("x", 1).unwrap().to_vec(), vec![3, 4, 8, 9, 10]);
+    }
+
+    #[test]
+    fn mismatched_parts() {
+        let y1 = VecVariable::new(vec![dim("x", 2), dim("time", 1)], vec![1, 2]).unwrap();
+        let y2 = VecVariable::new(vec![dim("x", 3), dim("time", 1)], vec![1, 2, 3]).unwrap();
+        assert_eq!(
+            Concat::new(vec![&y1, &y2], "time").err().unwrap(),
+            VariableError::SizeMismatch {
+                name: "x".to_string(),
+                expected: 2,
+                got: 3
+            }
+        );
+        let y3 = VecVariable::new(vec![dim("time", 1), dim("x", 2)], vec![1, 2]).unwrap();
+        assert!(Concat::new(vec![&y1, &y3], "time").is_err());
+        assert_eq!(
+            Concat::<&VecVariable<i32>>::new(vec![], "time")
+                .err()
+                .unwrap(),
+            VariableError::NoParts
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use arrow::array::{ArrayRef, Float64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+
+use crate::extension_columns::{DimMeta, TensorStackMeta, UnitsMeta};
+use crate::variable::table::{self, index_column, TableError, DIMENSIONS_KEY};
+use crate::variable::{Broadcast, Indices, VarView, Variable, VariableError, VecVariable};
+
+/// Named variables that share dimensions, like an in-memory xarray Dataset
+///
+/// A dimension has the same size, labels and times in every variable that
+/// uses it. Dimensions are kept in the order they were first added.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dataset {
+    dimensions: Vec<Arc<DimMeta>>,
+    variables: BTreeMap<String, VecVariable<f64>>,
+}
+
+/// Whether two dimensions of the same name describe the same positions
+fn check_same(existing: &DimMeta, other: &DimMeta) -> Result<(), VariableError> {
+    if existing.size != other.size {
+        return Err(VariableError::SizeMismatch {
+            name: existing.name.clone(),
+            expected: existing.size,
+            got: other.size,
+        });
+    }
+    if existing.labels != other.labels || existing.times != other.times {
+        return Err(VariableError::ConflictingDimension(existing.name.clone()));
+    }
+    Ok(())
+}
+
+impl Dataset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    pub fn dimension(&self, name: &str) -> Option<&Arc<DimMeta>> {
+        self.dimensions.iter().find(|d| d.name == name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.variables.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&VecVariable<f64>> {
+        self.variables.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+
+    /// Add or replace a variable
+    pub fn insert(&mut self, name: &str, variable: VecVariable<f64>) -> Result<(), VariableError> {
+        for d in variable.dimensions() {
+            if let Some(existing) = self.dimension(&d.name) {
+                check_same(existing, d)?;
+            }
+        }
+        for d in variable.dimensions() {
+            if self.dimension(&d.name).is_none() {
+                self.dimensions.push(d.clone());
+            }
+        }
+        self.variables.insert(name.to_string(), variable);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<VecVariable<f64>> {
+        let variable = self.variables.remove(name);
+        self.drop_unused_dimensions();
+        variable
+    }
+
+    fn drop_unused_dimensions(&mut self) {
+        let variables = &self.variables;
+        self.dimensions.retain(|d| {
+            variables
+                .values()
+                .any(|v| v.dimension_position(&d.name).is_some())
+        });
+    }
+
+    /// A dataset with only the named variables
+    pub fn select(&self, names: &[&str]) -> Result<Self, VariableError> {
+        let mut selected = Self::new();
+        for name in names {
+            let variable = self
+                .get(name)
+                .ok_or_else(|| VariableError::MissingVariable(name.to_string()))?;
+            selected.insert(name, variable.clone())?;
+        }
+        Ok(selected)
+    }
+
+    /// Fix a dimension at a position in every variable that has it, removing
+    /// the dimension
+    pub fn isel(&self, name: &str, index: usize) -> Result<Self, VariableError> {
+        if self.dimension(name).is_none() {
+            let names = self.dimensions.iter().map(|d| d.name.clone()).collect();
+            return Err(VariableError::MissingDimension(name.to_string(), names));
+        }
+        let mut selected = Self::new();
+        for (n, variable) in self.variables.iter() {
+            let variable = if variable.dimension_position(name).is_some() {
+                let slice = variable.slice(name, index)?;
+                let attributes = variable.attributes().cloned().unwrap_or_default();
+                VecVariable::new(slice.dimensions().to_vec(), slice.to_vec())?
+                    .with_attributes(attributes)
+            } else {
+                variable.clone()
+            };
+            selected.insert(n, variable)?;
+        }
+        Ok(selected)
+    }
+
+    /// Fix a labeled or timed dimension at the position of a label
+    ///
+    /// Times are matched by their `%Y-%m-%dT%H:%M:%S` form.
+    pub fn sel(&self, name: &str, label: &str) -> Result<Self, VariableError> {
+        let names = || self.dimensions.iter().map(|d| d.name.clone()).collect();
+        let d = self
+            .dimension(name)
+            .ok_or_else(|| VariableError::MissingDimension(name.to_string(), names()))?;
+        let position = match (&d.labels, &d.times) {
+            (Some(labels), _) => labels.iter().position(|l| l == label),
+            (_, Some(times)) => times
+                .iter()
+                .position(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string() == label),
+            _ => None,
+        };
+        let position = position.ok_or_else(|| VariableError::MissingLabel {
+            name: name.to_string(),
+            label: label.to_string(),
+        })?;
+        self.isel(name, position)
+    }
+
+    /// Combine the variables of two datasets, which must not share names
+    pub fn merge(mut self, other: Dataset) -> Result<Self, VariableError> {
+        for (name, variable) in other.variables {
+            if self.variables.contains_key(&name) {
+                return Err(VariableError::DuplicateVariable(name));
+            }
+            self.insert(&name, variable)?;
+        }
+        Ok(self)
+    }
+
+    /// Convert to a long format record batch with a column per variable
+    ///
+    /// Index columns are written as in `table::to_recordbatch`, one per
+    /// dimension of the dataset. Variables without a dimension repeat along
+    /// it, as in xarray's `to_dataframe`. In python
+    /// `batch.to_pandas().set_index(dims).to_xarray()` recovers the dataset
+    /// and xarray writes it to NetCDF.
+    pub fn to_recordbatch(&self) -> arrow::error::Result<RecordBatch> {
+        let shape: Vec<usize> = self.dimensions.iter().map(|d| d.size).collect();
+        let mut indices: Vec<Vec<u64>> = vec![vec![]; self.dimensions.len()];
+        for index in Indices::new(shape) {
+            for (column, &i) in indices.iter_mut().zip(&index) {
+                column.push(i as u64);
+            }
+        }
+        let (mut fields, mut columns): (Vec<Field>, Vec<ArrayRef>) = indices
+            .into_iter()
+            .zip(self.dimensions.iter())
+            .map(|(positions, d)| index_column(d, positions))
+            .collect::<arrow::error::Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        for (name, variable) in self.variables.iter() {
+            let view = VarView::broadcast(variable, self.dimensions.clone(), &Broadcast::All)
+                .map_err(|e| arrow::error::ArrowError::InvalidArgumentError(e.to_string()))?;
+            let mut field = Field::new(name, DataType::Float64, true);
+            if let Some(units) = variable.attributes().and_then(|a| a.units.as_ref()) {
+                field = UnitsMeta::new(units).annotate(field);
+            }
+            fields.push(field);
+            columns.push(Arc::new(Float64Array::from(view.to_vec())));
+        }
+
+        let meta = TensorStackMeta::new(self.dimensions.clone());
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            DIMENSIONS_KEY.to_string(),
+            serde_json::to_string(&meta).expect("dimensions to serialize"),
+        );
+        RecordBatch::try_new(
+            Arc::new(Schema::new_with_metadata(fields, metadata)),
+            columns,
+        )
+    }
+
+    /// Read a long format record batch whose `dims` columns index every other
+    /// column
+    ///
+    /// Each of the other columns becomes a variable over all of `dims`.
+    pub fn from_recordbatch(rb: &RecordBatch, dims: &[&str]) -> Result<Self, TableError> {
+        let schema = rb.schema();
+        let mut positions = vec![];
+        for d in dims {
+            positions.push(
+                schema
+                    .index_of(d)
+                    .map_err(|_| TableError::MissingColumn(d.to_string()))?,
+            );
+        }
+        let mut dataset = Self::new();
+        for (i, field) in schema.fields().iter().enumerate() {
+            if positions.contains(&i) {
+                continue;
+            }
+            let mut fields: Vec<Field> =
+                positions.iter().map(|p| schema.field(*p).clone()).collect();
+            fields.push(field.clone());
+            let mut columns: Vec<ArrayRef> =
+                positions.iter().map(|p| rb.column(*p).clone()).collect();
+            columns.push(rb.column(i).clone());
+            let projected = RecordBatch::try_new(
+                Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+                columns,
+            )?;
+            let variable = table::from_recordbatch(&projected, field.name())?;
+            dataset.insert(field.name(), variable)?;
+        }
+        Ok(dataset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::extension_columns::DimMeta;
+    use crate::variable::dataset::Dataset;
+    use crate::variable::{dim, Attributes, Variable, VariableError, VecVariable};
+
+    fn cultivars() -> Arc<DimMeta> {
+        Arc::new(DimMeta::labeled(
+            "cultivar",
+            vec!["A".to_string(), "B".to_string()],
+        ))
+    }
+
+    #[test]
+    fn select_merge_and_convert() {
+        let mut ds = Dataset::new();
+        let lai = VecVariable::new(
+            vec![cultivars(), dim("t", 3)],
+            (0..6).map(f64::from).collect(),
+        )
+        .unwrap()
+        .with_attributes(Attributes::default().with_units("m2/m2"));
+        let rainfall = VecVariable::new(vec![dim("t", 3)], vec![1.0, 0.0, 2.0]).unwrap();
+        ds.insert("lai", lai).unwrap();
+        ds.insert("rainfall", rainfall).unwrap();
+        assert_eq!(ds.dimensions().len(), 2);
+        assert_eq!(
+            ds.insert(
+                "bad",
+                VecVariable::new(vec![dim("t", 2)], vec![0.0; 2]).unwrap()
+            ),
+            Err(VariableError::SizeMismatch {
+                name: "t".to_string(),
+                expected: 3,
+                got: 2
+            })
+        );
+
+        let b = ds.sel("cultivar", "B").unwrap();
+        assert_eq!(b.get("lai").unwrap().to_vec(), vec![3.0, 4.0, 5.0]);
+        assert_eq!(b.get("rainfall").unwrap().to_vec(), vec![1.0, 0.0, 2.0]);
+        assert_eq!(b.dimensions().len(), 1);
+        assert!(ds.sel("cultivar", "C").is_err());
+
+        let only_rain = ds.select(&["rainfall"]).unwrap();
+        assert_eq!(only_rain.dimensions().len(), 1);
+        assert!(ds.clone().merge(only_rain).is_err());
+
+        let rb = ds.to_recordbatch().unwrap();
+        assert_eq!(rb.num_rows(), 6);
+        assert_eq!(rb.num_columns(), 4);
+        let back = Dataset::from_recordbatch(&rb, &["cultivar", "t"]).unwrap();
+        assert_eq!(back.get("lai"), ds.get("lai"));
+        // rainfall comes back repeated along cultivar
+        assert_eq!(
+            back.get("rainfall").unwrap().to_vec(),
+            vec![1.0, 0.0, 2.0, 1.0, 0.0, 2.0]
+        );
+    }
+}
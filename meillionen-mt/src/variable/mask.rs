@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::extension_columns::DimMeta;
+use crate::variable::{Attributes, Float, Indices, Variable, VariableError};
+
+/// The NetCDF attributes that mark values as missing
+///
+/// NaN is always treated as missing.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct MissingValues {
+    #[serde(
+        rename = "_FillValue",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fill_value: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_value: Option<f64>,
+}
+
+impl MissingValues {
+    pub fn is_missing(&self, value: f64) -> bool {
+        value.is_nan() || self.fill_value == Some(value) || self.missing_value == Some(value)
+    }
+}
+
+/// A variable whose missing values read as NaN
+#[derive(Clone, Debug)]
+pub struct Masked<V> {
+    variable: V,
+    missing: MissingValues,
+}
+
+impl<V> Masked<V> {
+    pub fn new(variable: V, missing: MissingValues) -> Self {
+        Self { variable, missing }
+    }
+}
+
+impl<V> Variable for Masked<V>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        self.variable.dimensions()
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        let value = self.variable.get(index);
+        if self.missing.is_missing(value.into()) {
+            V::Elem::from_f64(f64::NAN)
+        } else {
+            value
+        }
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        self.variable.attributes()
+    }
+}
+
+/// The cells of a variable that have at least one value along a dimension
+///
+/// A cell is an index into every dimension except `along`, in the order of
+/// the variable's dimensions. Cells where every value is missing (ocean,
+/// outside the catchment) are left out so [`crate::grid::run_valid_cells`]
+/// can skip them.
+pub fn valid_cells<V>(variable: &V, along: &str) -> Result<Vec<Vec<usize>>, VariableError>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    let position = variable.dimension_position(along).ok_or_else(|| {
+        VariableError::MissingDimension(along.to_string(), variable.dimension_names())
+    })?;
+    let mut cell_shape = variable.shape();
+    let size = cell_shape.remove(position);
+    let cells = Indices::new(cell_shape)
+        .filter(|cell| {
+            let mut index = cell.clone();
+            index.insert(position, 0);
+            (0..size).any(|i| {
+                index[position] = i;
+                !variable.get(&index).into().is_nan()
+            })
+        })
+        .collect();
+    Ok(cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::mask::{valid_cells, MissingValues};
+    use crate::variable::{dim, Attributes, Variable, VecVariable};
+
+    fn rainfall() -> VecVariable<f32> {
+        VecVariable::new(
+            vec![dim("x", 3), dim("t", 2)],
+            vec![1.0, 2.0, -9999.0, -9999.0, f32::NAN, 3.0],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn masked_values_are_nan() {
+        let missing = MissingValues {
+            fill_value: Some(-9999.0),
+            missing_value: None,
+        };
+        let r = rainfall().with_attributes(Attributes::default().with_units("mm"));
+        let masked = r.masked(missing);
+        assert_eq!(masked.attributes().unwrap().units.as_deref(), Some("mm"));
+        let values = masked.to_vec();
+        assert_eq!(values[..2], [1.0, 2.0]);
+        assert!(values[2..5].iter().all(|v| v.is_nan()));
+        assert_eq!(values[5], 3.0);
+    }
+
+    #[test]
+    fn skips_missing_cells() {
+        let missing = MissingValues {
+            fill_value: None,
+            missing_value: Some(-9999.0),
+        };
+        let r = rainfall().masked(missing);
+        assert_eq!(valid_cells(&r, "t").unwrap(), vec![vec![0], vec![2]]);
+        assert_eq!(
+            valid_cells(&rainfall(), "t").unwrap(),
+            vec![vec![0], vec![1], vec![2]]
+        );
+
+        let attrs = Attributes::default().with_missing(MissingValues {
+            fill_value: Some(-9999.0),
+            missing_value: None,
+        });
+        let r = rainfall().with_attributes(attrs).masked_by_attributes();
+        assert_eq!(valid_cells(&r, "t").unwrap(), vec![vec![0], vec![2]]);
+    }
+
+    #[test]
+    fn attribute_names() {
+        let missing: MissingValues = serde_json::from_str(r#"{"_FillValue": -1.0}"#).unwrap();
+        assert_eq!(missing.fill_value, Some(-1.0));
+        assert_eq!(
+            serde_json::to_string(&missing).unwrap(),
+            r#"{"_FillValue":-1.0}"#
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::extension_columns::DimMeta;
+
+pub mod attributes;
+pub mod cf;
+pub mod compose;
+pub mod concat;
+pub mod dataset;
+pub mod mask;
+pub mod negotiate;
+pub mod ops;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+pub mod reduce;
+pub mod table;
+pub mod time;
+pub mod view;
+
+pub use attributes::Attributes;
+pub use compose::{Composition, Term};
+pub use concat::Concat;
+pub use dataset::Dataset;
+pub use mask::{Masked, MissingValues};
+pub use ops::{Map, ZipWith};
+pub use reduce::{Reduce, Reduction};
+pub use view::{Broadcast, Slice, VarView};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum VariableError {
+    #[error("dimension {0} not found in {1:?}")]
+    MissingDimension(String, Vec<String>),
+    #[error("dimension {name} has size {got} but {expected} was expected")]
+    SizeMismatch {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
+    #[error("dimension {0} given more than once")]
+    DuplicateDimension(String),
+    #[error("data of length {0} does not fit shape {1:?}")]
+    ShapeMismatch(usize, Vec<usize>),
+    #[error("index {index} out of bounds for dimension {name} of size {size}")]
+    OutOfBounds {
+        name: String,
+        index: usize,
+        size: usize,
+    },
+    #[error("quantile {0} is not between 0 and 1")]
+    InvalidQuantile(f64),
+    #[error("no variables to concatenate")]
+    NoParts,
+    #[error("variable {0} not found")]
+    MissingVariable(String),
+    #[error("variable {0} is in both datasets")]
+    DuplicateVariable(String),
+    #[error("dimension {0} has different labels or times in different variables")]
+    ConflictingDimension(String),
+    #[error("dimension {name} has no label {label}")]
+    MissingLabel { name: String, label: String },
+}
+
+/// Floating point element types that can be reduced and converted to arrow
+pub trait Float: Copy + Into<f64> {
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl Float for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+/// An n-dimensional array of values indexed by named dimensions
+///
+/// Indices passed to `get` are in the order of `dimensions`.
+pub trait Variable {
+    type Elem: Copy;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>];
+
+    fn get(&self, index: &[usize]) -> Self::Elem;
+
+    /// The NetCDF attributes describing the values, if they are known
+    ///
+    /// Views that leave values unchanged pass on the attributes of the
+    /// variable they wrap.
+    fn attributes(&self) -> Option<&Attributes> {
+        None
+    }
+
+    fn shape(&self) -> Vec<usize> {
+        self.dimensions().iter().map(|d| d.size).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.dimensions().iter().map(|d| d.size).product()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn dimension_position(&self, name: &str) -> Option<usize> {
+        self.dimensions().iter().position(|d| d.name == name)
+    }
+
+    fn dimension_names(&self) -> Vec<String> {
+        self.dimensions().iter().map(|d| d.name.clone()).collect()
+    }
+
+    /// Collect all values in row-major order
+    fn to_vec(&self) -> Vec<Self::Elem> {
+        Indices::new(self.shape())
+            .map(|index| self.get(&index))
+            .collect()
+    }
+
+    /// Fix one dimension at an index, removing it from the dimensions
+    fn slice(self, name: &str, index: usize) -> Result<Slice<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Slice::new(self, name, index)
+    }
+
+    /// Read values marked missing by the attributes as NaN
+    fn masked(self, missing: MissingValues) -> Masked<Self>
+    where
+        Self: Sized,
+    {
+        Masked::new(self, missing)
+    }
+
+    /// Read values marked missing by the variable's own attributes as NaN
+    fn masked_by_attributes(self) -> Masked<Self>
+    where
+        Self: Sized,
+    {
+        let missing = self
+            .attributes()
+            .map(|a| a.missing.clone())
+            .unwrap_or_default();
+        Masked::new(self, missing)
+    }
+
+    fn map<F, T>(self, f: F) -> Map<Self, F>
+    where
+        Self: Sized,
+        F: Fn(Self::Elem) -> T,
+    {
+        Map::new(self, f)
+    }
+
+    fn zip_with<B, F, T>(self, other: B, f: F) -> Result<ZipWith<Self, B, F>, VariableError>
+    where
+        Self: Sized,
+        B: Variable,
+        F: Fn(Self::Elem, B::Elem) -> T,
+    {
+        ZipWith::new(self, other, f)
+    }
+
+    fn scale(self, factor: Self::Elem) -> Map<Self, UnaryOp<Self::Elem>>
+    where
+        Self: Sized,
+        Self::Elem: Mul<Output = Self::Elem> + 'static,
+    {
+        Map::new(self, Box::new(move |x| x * factor))
+    }
+
+    fn offset(self, amount: Self::Elem) -> Map<Self, UnaryOp<Self::Elem>>
+    where
+        Self: Sized,
+        Self::Elem: Add<Output = Self::Elem> + 'static,
+    {
+        Map::new(self, Box::new(move |x| x + amount))
+    }
+
+    fn add<B>(self, other: B) -> Result<ZipWith<Self, B, BinOp<Self::Elem>>, VariableError>
+    where
+        Self: Sized,
+        B: Variable<Elem = Self::Elem>,
+        Self::Elem: Add<Output = Self::Elem>,
+    {
+        ZipWith::new(self, other, Add::add)
+    }
+
+    fn sub<B>(self, other: B) -> Result<ZipWith<Self, B, BinOp<Self::Elem>>, VariableError>
+    where
+        Self: Sized,
+        B: Variable<Elem = Self::Elem>,
+        Self::Elem: Sub<Output = Self::Elem>,
+    {
+        ZipWith::new(self, other, Sub::sub)
+    }
+
+    fn mul<B>(self, other: B) -> Result<ZipWith<Self, B, BinOp<Self::Elem>>, VariableError>
+    where
+        Self: Sized,
+        B: Variable<Elem = Self::Elem>,
+        Self::Elem: Mul<Output = Self::Elem>,
+    {
+        ZipWith::new(self, other, Mul::mul)
+    }
+
+    fn div<B>(self, other: B) -> Result<ZipWith<Self, B, BinOp<Self::Elem>>, VariableError>
+    where
+        Self: Sized,
+        B: Variable<Elem = Self::Elem>,
+        Self::Elem: Div<Output = Self::Elem>,
+    {
+        ZipWith::new(self, other, Div::div)
+    }
+
+    fn reduce(self, name: &str, reduction: Reduction) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, reduction)
+    }
+
+    fn sum(self, name: &str) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, Reduction::Sum)
+    }
+
+    fn mean(self, name: &str) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, Reduction::Mean)
+    }
+
+    fn min(self, name: &str) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, Reduction::Min)
+    }
+
+    fn max(self, name: &str) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, Reduction::Max)
+    }
+
+    fn quantile(self, name: &str, q: f64) -> Result<Reduce<Self>, VariableError>
+    where
+        Self: Sized,
+    {
+        Reduce::new(self, name, Reduction::Quantile(q))
+    }
+}
+
+/// An elementwise operation with a captured operand used by `scale` and `offset`
+pub type UnaryOp<T> = Box<dyn Fn(T) -> T>;
+
+/// An elementwise binary operation used by the arithmetic combinators
+pub type BinOp<T> = fn(T, T) -> T;
+
+impl<V: Variable + ?Sized> Variable for &V {
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        (**self).dimensions()
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        (**self).get(index)
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        (**self).attributes()
+    }
+}
+
+/// A variable backed by a row-major vector
+#[derive(Clone, Debug, PartialEq)]
+pub struct VecVariable<T> {
+    dimensions: Vec<Arc<DimMeta>>,
+    strides: Vec<usize>,
+    data: Vec<T>,
+    attributes: Attributes,
+}
+
+impl<T> VecVariable<T> {
+    pub fn new(dimensions: Vec<Arc<DimMeta>>, data: Vec<T>) -> Result<Self, VariableError> {
+        check_unique(&dimensions)?;
+        let shape: Vec<usize> = dimensions.iter().map(|d| d.size).collect();
+        if shape.iter().product::<usize>() != data.len() {
+            return Err(VariableError::ShapeMismatch(data.len(), shape));
+        }
+        Ok(Self {
+            strides: strides(&shape),
+            dimensions,
+            data,
+            attributes: Attributes::default(),
+        })
+    }
+
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn set(&mut self, index: &[usize], value: T) {
+        let offset: usize = index.iter().zip(&self.strides).map(|(i, s)| i * s).sum();
+        self.data[offset] = value;
+    }
+
+    pub fn into_data(self) -> Vec<T> {
+        self.data
+    }
+}
+
+impl<T: Copy> VecVariable<T> {
+    /// A variable with every element set to a value
+    ///
+    /// Filling with NaN before writing the results of each cell leaves cells
+    /// that were skipped as missing.
+    pub fn full(dimensions: Vec<Arc<DimMeta>>, value: T) -> Result<Self, VariableError> {
+        let len = dimensions.iter().map(|d| d.size).product();
+        Self::new(dimensions, vec![value; len])
+    }
+}
+
+impl<T: Copy> Variable for VecVariable<T> {
+    type Elem = T;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    fn get(&self, index: &[usize]) -> T {
+        let offset: usize = index.iter().zip(&self.strides).map(|(i, s)| i * s).sum();
+        self.data[offset]
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        Some(&self.attributes)
+    }
+}
+
+/// Row-major strides for a shape
+pub(crate) fn strides(shape: &[usize]) -> Vec<usize> {
+    let mut strides = vec![1; shape.len()];
+    for i in (0..shape.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * shape[i + 1];
+    }
+    strides
+}
+
+pub(crate) fn check_unique(dimensions: &[Arc<DimMeta>]) -> Result<(), VariableError> {
+    for (i, d) in dimensions.iter().enumerate() {
+        if dimensions[..i].iter().any(|o| o.name == d.name) {
+            return Err(VariableError::DuplicateDimension(d.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Iterates over every multi-index of a shape in row-major order
+#[derive(Clone, Debug)]
+pub struct Indices {
+    shape: Vec<usize>,
+    next: Option<Vec<usize>>,
+}
+
+impl Indices {
+    pub fn new(shape: Vec<usize>) -> Self {
+        let next = if shape.contains(&0) {
+            None
+        } else {
+            Some(vec![0; shape.len()])
+        };
+        Self { shape, next }
+    }
+}
+
+impl Iterator for Indices {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        let mut following = current.clone();
+        for i in (0..following.len()).rev() {
+            following[i] += 1;
+            if following[i] < self.shape[i] {
+                self.next = Some(following);
+                break;
+            }
+            following[i] = 0;
+        }
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn dim(name: &str, size: usize) -> Arc<DimMeta> {
+    Arc::new(DimMeta {
+        name: name.to_string(),
+        size,
+        description: None,
+        labels: None,
+        times: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::{dim, Indices, Variable, VariableError, VecVariable};
+
+    #[test]
+    fn vec_variable_row_major() {
+        let v = VecVariable::new(vec![dim("y", 2), dim("x", 3)], (0..6).collect()).unwrap();
+        assert_eq!(v.get(&[0, 2]), 2);
+        assert_eq!(v.get(&[1, 0]), 3);
+        assert_eq!(v.to_vec(), (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn vec_variable_shape_mismatch() {
+        let v = VecVariable::new(vec![dim("y", 2), dim("x", 3)], vec![0; 5]);
+        assert_eq!(v, Err(VariableError::ShapeMismatch(5, vec![2, 3])));
+        let v = VecVariable::new(vec![dim("x", 2), dim("x", 2)], vec![0; 4]);
+        assert_eq!(v, Err(VariableError::DuplicateDimension("x".to_string())));
+    }
+
+    #[test]
+    fn full_and_set() {
+        let mut v = VecVariable::full(vec![dim("x", 2), dim("t", 2)], f64::NAN).unwrap();
+        v.set(&[1, 0], 4.0);
+        let values = v.to_vec();
+        assert_eq!(values[2], 4.0);
+        assert_eq!(values.iter().filter(|x| x.is_nan()).count(), 3);
+    }
+
+    #[test]
+    fn indices() {
+        let is: Vec<Vec<usize>> = Indices::new(vec![2, 2]).collect();
+        assert_eq!(is, vec![vec![0, 0], vec![0, 1], vec![1, 0], vec![1, 1]]);
+        assert_eq!(Indices::new(vec![]).count(), 1);
+        assert_eq!(Indices::new(vec![3, 0]).count(), 0);
+    }
+}
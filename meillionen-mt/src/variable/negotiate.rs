@@ -0,0 +1,504 @@
+//! Explaining why a variable does not fit the dimensions a model expects
+//!
+//! Shapes are checked when a variable is handed to a model so a mismatch is
+//! reported with both sets of dimensions and the transforms that would fix
+//! it, rather than as an index error somewhere inside the model.
+//!
+//! A [`VariableRequest`] says what a model wants for a variable: its standard
+//! name, units and dimensions. Accepting a [`VariableResponse`] against it
+//! catches a mismatch when the variable is bound and gives the unit
+//! conversions a coupler should apply on the way in.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::diff;
+use crate::variable::Attributes;
+
+/// A change to a variable that would make it fit the expected dimensions
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "transform", rename_all = "snake_case")]
+pub enum Transform {
+    /// Reorder the dimensions
+    Transpose { order: Vec<String> },
+    /// Repeat the values along dimensions the variable lacks
+    Broadcast { dimensions: Vec<String> },
+    /// Drop dimensions of size one the model does not use
+    Squeeze { dimensions: Vec<String> },
+    /// Interpolate or aggregate a dimension to the model's size
+    Regrid {
+        dimension: String,
+        from: usize,
+        to: usize,
+    },
+    /// Give a dimension the name the model uses
+    Rename { from: String, to: String },
+    /// Convert the values to other units, as `value * scale + offset`
+    Convert {
+        from: String,
+        to: String,
+        scale: f64,
+        offset: f64,
+    },
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Transpose { order } => write!(f, "transpose to ({})", order.join(", ")),
+            Transform::Broadcast { dimensions } => {
+                write!(f, "broadcast along {}", dimensions.join(", "))
+            }
+            Transform::Squeeze { dimensions } => {
+                write!(f, "squeeze out {}", dimensions.join(", "))
+            }
+            Transform::Regrid {
+                dimension,
+                from,
+                to,
+            } => write!(f, "regrid {} from {} to {} points", dimension, from, to),
+            Transform::Rename { from, to } => write!(f, "rename {} to {}", from, to),
+            Transform::Convert { from, to, .. } => write!(f, "convert from {} to {}", from, to),
+        }
+    }
+}
+
+/// The dimensions a model expects for a variable and the ones it was given
+///
+/// Expected sizes are `None` when the model accepts any size.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DimensionMismatch {
+    pub variable: String,
+    pub expected: Vec<(String, Option<usize>)>,
+    pub got: Vec<(String, usize)>,
+}
+
+impl DimensionMismatch {
+    /// The mismatch between the expected and given dimensions, if any
+    pub fn check(
+        variable: &str,
+        expected: &[(String, Option<usize>)],
+        got: &[(String, usize)],
+    ) -> Option<Self> {
+        let fits = expected.len() == got.len()
+            && expected
+                .iter()
+                .zip(got.iter())
+                .all(|((en, es), (gn, gs))| en == gn && es.is_none_or(|es| es == *gs));
+        if fits {
+            return None;
+        }
+        Some(Self {
+            variable: variable.to_string(),
+            expected: expected.to_vec(),
+            got: got.to_vec(),
+        })
+    }
+
+    /// Transforms that would make the variable fit, most likely first
+    pub fn suggestions(&self) -> Vec<Transform> {
+        let expected: BTreeMap<&str, Option<usize>> = self
+            .expected
+            .iter()
+            .map(|(n, s)| (n.as_str(), *s))
+            .collect();
+        let got: BTreeMap<&str, usize> = self.got.iter().map(|(n, s)| (n.as_str(), *s)).collect();
+        let mut suggestions = vec![];
+
+        let missing: Vec<String> = self
+            .expected
+            .iter()
+            .filter(|(n, _)| !got.contains_key(n.as_str()))
+            .map(|(n, _)| n.clone())
+            .collect();
+        let extra: Vec<&(String, usize)> = self
+            .got
+            .iter()
+            .filter(|(n, _)| !expected.contains_key(n.as_str()))
+            .collect();
+
+        // names that differ in the same positions, with sizes that agree
+        if missing.len() == extra.len() && !missing.is_empty() {
+            for (to, (from, size)) in missing.iter().zip(extra.iter()) {
+                if expected[to.as_str()].is_none_or(|s| s == *size) {
+                    suggestions.push(Transform::Rename {
+                        from: from.clone(),
+                        to: to.clone(),
+                    });
+                }
+            }
+            if suggestions.len() == missing.len() {
+                return suggestions;
+            }
+            suggestions.clear();
+        }
+
+        for (name, size) in self.got.iter() {
+            if let Some(Some(want)) = expected.get(name.as_str()) {
+                if want != size {
+                    suggestions.push(Transform::Regrid {
+                        dimension: name.clone(),
+                        from: *size,
+                        to: *want,
+                    });
+                }
+            }
+        }
+        let squeezable: Vec<String> = extra
+            .iter()
+            .filter(|(_, s)| *s == 1)
+            .map(|(n, _)| n.clone())
+            .collect();
+        if !squeezable.is_empty() {
+            suggestions.push(Transform::Squeeze {
+                dimensions: squeezable,
+            });
+        }
+        if !missing.is_empty() {
+            suggestions.push(Transform::Broadcast {
+                dimensions: missing,
+            });
+        }
+        let shared: Vec<&str> = self
+            .got
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .filter(|n| expected.contains_key(n))
+            .collect();
+        let order: Vec<&str> = self
+            .expected
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .filter(|n| got.contains_key(n))
+            .collect();
+        if shared != order {
+            suggestions.push(Transform::Transpose {
+                order: self.expected.iter().map(|(n, _)| n.clone()).collect(),
+            });
+        }
+        suggestions
+    }
+}
+
+impl fmt::Display for DimensionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |name: &str, size: Option<usize>| match size {
+            Some(s) => format!("{} ({})", name, s),
+            None => format!("{} (any)", name),
+        };
+        writeln!(
+            f,
+            "{} has dimensions that do not match the model",
+            self.variable
+        )?;
+        writeln!(f, "      {:<24}got", "expected")?;
+        for i in 0..self.expected.len().max(self.got.len()) {
+            let e = self.expected.get(i);
+            let g = self.got.get(i);
+            let matches = match (e, g) {
+                (Some((en, es)), Some((gn, gs))) => en == gn && es.is_none_or(|es| es == *gs),
+                _ => false,
+            };
+            writeln!(
+                f,
+                "{} {:<4}{:<24}{}",
+                if matches { " " } else { "*" },
+                i,
+                e.map(|(n, s)| describe(n, *s)).unwrap_or_default(),
+                g.map(|(n, s)| describe(n, Some(*s))).unwrap_or_default()
+            )?;
+        }
+        let suggestions = self.suggestions();
+        if suggestions.is_empty() {
+            write!(f, "no transform makes these fit")
+        } else {
+            write!(f, "try: ")?;
+            let text: Vec<String> = suggestions.iter().map(|s| s.to_string()).collect();
+            write!(f, "{}", text.join(", then "))
+        }
+    }
+}
+
+/// The factor from units like `mm` or `mm/d` to the base units of their
+/// dimensions, and the dimensions
+fn units_factor(units: &str) -> Option<(f64, String)> {
+    let mut parts = units.splitn(2, '/');
+    let (factor, dimension) = diff::unit(parts.next()?.trim())?;
+    match parts.next() {
+        Some(per) => {
+            let (per_factor, per_dimension) = diff::unit(per.trim())?;
+            Some((
+                factor / per_factor,
+                format!("{}/{}", dimension, per_dimension),
+            ))
+        }
+        None => Some((factor, dimension.to_string())),
+    }
+}
+
+/// The scale and offset that convert values in `from` units to `to` units,
+/// if they measure the same thing
+pub fn conversion(from: &str, to: &str) -> Option<(f64, f64)> {
+    match (from.trim(), to.trim()) {
+        (from, to) if from == to => Some((1.0, 0.0)),
+        ("degC", "K") => Some((1.0, 273.15)),
+        ("K", "degC") => Some((1.0, -273.15)),
+        (from, to) => {
+            let (from, from_dimension) = units_factor(from)?;
+            let (to, to_dimension) = units_factor(to)?;
+            (from_dimension == to_dimension).then(|| (from / to, 0.0))
+        }
+    }
+}
+
+/// What a model wants for a variable it is given, or what a caller wants
+/// for a variable it gets
+///
+/// Fields left out accept anything.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct VariableRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standard_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<String>,
+    /// Expected sizes are `None` when any size is accepted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dimensions: Vec<(String, Option<usize>)>,
+}
+
+/// What a variable offered for a [`VariableRequest`] is
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct VariableResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub standard_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub units: Option<String>,
+    #[serde(default)]
+    pub dimensions: Vec<(String, usize)>,
+}
+
+impl VariableResponse {
+    pub fn new(attributes: &Attributes, dimensions: Vec<(String, usize)>) -> Self {
+        Self {
+            standard_name: attributes.standard_name.clone(),
+            units: attributes.units.clone(),
+            dimensions,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum NegotiationError {
+    #[error("{variable} is {got} but {expected} was requested")]
+    StandardName {
+        variable: String,
+        expected: String,
+        got: String,
+    },
+    #[error("{variable} is in {got} which can't be converted to {expected}")]
+    Units {
+        variable: String,
+        expected: String,
+        got: String,
+    },
+    #[error("{0}")]
+    Dimensions(DimensionMismatch),
+}
+
+impl NegotiationError {
+    /// Transforms that would make the variable fit
+    pub fn suggestions(&self) -> Vec<Transform> {
+        match self {
+            NegotiationError::Dimensions(mismatch) => mismatch.suggestions(),
+            _ => vec![],
+        }
+    }
+}
+
+impl VariableRequest {
+    pub fn new(attributes: &Attributes, dimensions: Vec<(String, Option<usize>)>) -> Self {
+        Self {
+            standard_name: attributes.standard_name.clone(),
+            units: attributes.units.clone(),
+            dimensions,
+        }
+    }
+
+    /// Check `response` meets the request, returning the unit conversions to
+    /// apply to it
+    ///
+    /// A response that does not say what it is or what units it is in is
+    /// taken to match.
+    pub fn accept(
+        &self,
+        variable: &str,
+        response: &VariableResponse,
+    ) -> Result<Vec<Transform>, NegotiationError> {
+        if let (Some(expected), Some(got)) = (&self.standard_name, &response.standard_name) {
+            if expected != got {
+                return Err(NegotiationError::StandardName {
+                    variable: variable.to_string(),
+                    expected: expected.clone(),
+                    got: got.clone(),
+                });
+            }
+        }
+        if !self.dimensions.is_empty() {
+            if let Some(mismatch) =
+                DimensionMismatch::check(variable, &self.dimensions, &response.dimensions)
+            {
+                return Err(NegotiationError::Dimensions(mismatch));
+            }
+        }
+        let mut converters = vec![];
+        if let (Some(to), Some(from)) = (&self.units, &response.units) {
+            match conversion(from, to) {
+                Some((scale, offset)) if scale == 1.0 && offset == 0.0 => {}
+                Some((scale, offset)) => converters.push(Transform::Convert {
+                    from: from.clone(),
+                    to: to.clone(),
+                    scale,
+                    offset,
+                }),
+                None => {
+                    return Err(NegotiationError::Units {
+                        variable: variable.to_string(),
+                        expected: to.clone(),
+                        got: from.clone(),
+                    })
+                }
+            }
+        }
+        Ok(converters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::negotiate::{
+        conversion, DimensionMismatch, NegotiationError, Transform, VariableRequest,
+        VariableResponse,
+    };
+    use crate::variable::Attributes;
+
+    fn dims(d: &[(&str, usize)]) -> Vec<(String, usize)> {
+        d.iter().map(|(n, s)| (n.to_string(), *s)).collect()
+    }
+
+    #[test]
+    fn suggest_transforms() {
+        let grid = vec![
+            ("x".to_string(), Some(100)),
+            ("y".to_string(), Some(50)),
+            ("time".to_string(), None),
+        ];
+        assert!(DimensionMismatch::check(
+            "rain",
+            &grid,
+            &dims(&[("x", 100), ("y", 50), ("time", 365)])
+        )
+        .is_none());
+
+        let swapped = DimensionMismatch::check(
+            "rain",
+            &grid,
+            &dims(&[("y", 50), ("x", 100), ("time", 365)]),
+        )
+        .unwrap();
+        assert_eq!(
+            swapped.suggestions(),
+            vec![Transform::Transpose {
+                order: vec!["x".to_string(), "y".to_string(), "time".to_string()]
+            }]
+        );
+        let text = swapped.to_string();
+        assert!(text.contains("* 0   x (100)"));
+        assert!(text.contains("  2   time (any)"));
+        assert!(text.ends_with("try: transpose to (x, y, time)"));
+
+        let coarse = DimensionMismatch::check("rain", &grid, &dims(&[("time", 365), ("x", 10)]))
+            .unwrap()
+            .suggestions();
+        assert_eq!(
+            coarse[0],
+            Transform::Regrid {
+                dimension: "x".to_string(),
+                from: 10,
+                to: 100
+            }
+        );
+        assert_eq!(
+            coarse[1],
+            Transform::Broadcast {
+                dimensions: vec!["y".to_string()]
+            }
+        );
+
+        let renamed = DimensionMismatch::check(
+            "rain",
+            &grid,
+            &dims(&[("lon", 100), ("lat", 50), ("time", 365)]),
+        )
+        .unwrap();
+        assert_eq!(
+            renamed.suggestions()[1],
+            Transform::Rename {
+                from: "lat".to_string(),
+                to: "y".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn negotiate_requests() {
+        assert_eq!(conversion("cm", "mm"), Some((10.0, 0.0)));
+        assert_eq!(conversion("mm/d", "m/s"), Some((1e-3 / 86400.0, 0.0)));
+        assert_eq!(conversion("degC", "K"), Some((1.0, 273.15)));
+        assert_eq!(conversion("mm", "kg"), None);
+
+        let request = VariableRequest::new(
+            &Attributes::default()
+                .with_standard_name("precipitation_amount")
+                .with_units("mm"),
+            vec![("x".to_string(), Some(2)), ("time".to_string(), None)],
+        );
+        let mut response = VariableResponse::new(
+            &Attributes::default()
+                .with_standard_name("precipitation_amount")
+                .with_units("cm"),
+            dims(&[("x", 2), ("time", 365)]),
+        );
+        assert_eq!(
+            request.accept("rain", &response).unwrap(),
+            vec![Transform::Convert {
+                from: "cm".to_string(),
+                to: "mm".to_string(),
+                scale: 10.0,
+                offset: 0.0
+            }]
+        );
+        response.units = None;
+        assert!(request.accept("rain", &response).unwrap().is_empty());
+
+        response.units = Some("kg".to_string());
+        let e = request.accept("rain", &response).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "rain is in kg which can't be converted to mm"
+        );
+
+        response.dimensions = dims(&[("time", 365), ("x", 2)]);
+        let e = request.accept("rain", &response).unwrap_err();
+        assert!(matches!(e, NegotiationError::Dimensions(_)));
+        assert!(matches!(e.suggestions()[0], Transform::Transpose { .. }));
+
+        response.standard_name = Some("air_temperature".to_string());
+        assert!(matches!(
+            request.accept("rain", &response),
+            Err(NegotiationError::StandardName { .. })
+        ));
+    }
+}
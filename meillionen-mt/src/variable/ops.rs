@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::extension_columns::DimMeta;
+use crate::variable::{VarView, Variable, VariableError};
+
+/// Applies a function to every element of a variable when it is read
+#[derive(Clone, Debug)]
+pub struct Map<V, F> {
+    variable: V,
+    f: F,
+}
+
+impl<V, F> Map<V, F> {
+    pub fn new(variable: V, f: F) -> Self {
+        Self { variable, f }
+    }
+}
+
+impl<V, F, T> Variable for Map<V, F>
+where
+    V: Variable,
+    F: Fn(V::Elem) -> T,
+    T: Copy,
+{
+    type Elem = T;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        self.variable.dimensions()
+    }
+
+    fn get(&self, index: &[usize]) -> T {
+        (self.f)(self.variable.get(index))
+    }
+}
+
+/// Combines the elements of two variables with the same dimensions
+///
+/// The right hand variable is viewed in the dimension order of the left
+/// hand variable so the two do not need to share a layout.
+#[derive(Clone, Debug)]
+pub struct ZipWith<A, B, F> {
+    left: A,
+    right: VarView<B>,
+    f: F,
+}
+
+impl<A: Variable, B: Variable, F> ZipWith<A, B, F> {
+    pub fn new(left: A, right: B, f: F) -> Result<Self, VariableError> {
+        let right = VarView::new(right, left.dimensions().to_vec())?;
+        Ok(Self { left, right, f })
+    }
+}
+
+impl<A, B, F, T> Variable for ZipWith<A, B, F>
+where
+    A: Variable,
+    B: Variable,
+    F: Fn(A::Elem, B::Elem) -> T,
+    T: Copy,
+{
+    type Elem = T;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        self.left.dimensions()
+    }
+
+    fn get(&self, index: &[usize]) -> T {
+        (self.f)(self.left.get(index), self.right.get(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::{dim, Broadcast, VarView, Variable, VariableError, VecVariable};
+
+    #[test]
+    fn derived_forcing() {
+        let depth =
+            VecVariable::new(vec![dim("x", 2), dim("t", 2)], vec![10.0, 20.0, 30.0, 40.0]).unwrap();
+        let irrigation = VecVariable::new(vec![dim("t", 2)], vec![1.0, 2.0]).unwrap();
+        let irrigation =
+            VarView::broadcast(&irrigation, depth.dimensions().to_vec(), &Broadcast::All).unwrap();
+        let water = depth.scale(0.5).add(irrigation).unwrap();
+        assert_eq!(water.to_vec(), vec![6.0, 12.0, 16.0, 22.0]);
+
+        let today = water.slice("t", 1).unwrap();
+        assert_eq!(today.to_vec(), vec![12.0, 22.0]);
+    }
+
+    #[test]
+    fn zip_transposed() {
+        let a = VecVariable::new(vec![dim("x", 2), dim("y", 3)], (0..6).collect()).unwrap();
+        let b = VecVariable::new(vec![dim("y", 3), dim("x", 2)], vec![0, 3, 1, 4, 2, 5]).unwrap();
+        let diff = (&a).sub(&b).unwrap();
+        assert_eq!(diff.to_vec(), vec![0; 6]);
+        let ratio = a.map(|x| x as f32).div(b.map(|x| x as f32 + 1.0)).unwrap();
+        assert_eq!(ratio.get(&[1, 2]), 5.0 / 6.0);
+    }
+
+    #[test]
+    fn zip_mismatch() {
+        let a = VecVariable::new(vec![dim("x", 2)], vec![1, 2]).unwrap();
+        let b = VecVariable::new(vec![dim("y", 2)], vec![1, 2]).unwrap();
+        assert_eq!(
+            a.mul(b).err().unwrap(),
+            VariableError::MissingDimension("y".to_string(), vec!["x".to_string()])
+        );
+    }
+}
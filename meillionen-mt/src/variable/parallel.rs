@@ -0,0 +1,167 @@
+//! Reductions spread over every core with rayon
+//!
+//! Summarising a large ensemble, such as ten thousand members by a year of
+//! days, reads every member for each element of the result. The elements are
+//! independent, so here they are computed in parallel. Built with the
+//! `parallel` feature.
+
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use rayon::prelude::*;
+
+use crate::calibration::step_quantile;
+use crate::variable::table::with_range;
+use crate::variable::{Float, Reduction, Variable, VariableError, VecVariable};
+
+/// The index of the `i`th element in row-major order
+fn unravel(mut i: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for (k, size) in shape.iter().enumerate().rev() {
+        index[k] = i % size;
+        i /= size;
+    }
+    index
+}
+
+/// Collect all values in row-major order, reading them in parallel
+pub fn par_to_vec<V>(variable: &V) -> Vec<V::Elem>
+where
+    V: Variable + Sync,
+    V::Elem: Send,
+{
+    let shape = variable.shape();
+    (0..variable.len())
+        .into_par_iter()
+        .map(|i| variable.get(&unravel(i, &shape)))
+        .collect()
+}
+
+/// Reduce a variable along one dimension in several ways at once
+///
+/// The values along the dimension are read once per element for every
+/// reduction, where [`Variable::reduce`] reads them once per reduction. The
+/// results match those of [`Variable::reduce`].
+pub fn par_reduce<V>(
+    variable: &V,
+    name: &str,
+    reductions: &[Reduction],
+) -> Result<Vec<VecVariable<f64>>, VariableError>
+where
+    V: Variable + Sync,
+    V::Elem: Float,
+{
+    for r in reductions.iter() {
+        r.check()?;
+    }
+    let position = variable.dimension_position(name).ok_or_else(|| {
+        VariableError::MissingDimension(name.to_string(), variable.dimension_names())
+    })?;
+    let mut dimensions = variable.dimensions().to_vec();
+    let size = dimensions.remove(position).size;
+    let shape: Vec<usize> = dimensions.iter().map(|d| d.size).collect();
+    let elements: usize = shape.iter().product();
+    // quantiles sort the values, so they are taken after the rest
+    let order: Vec<usize> = (0..reductions.len())
+        .filter(|r| !matches!(reductions[*r], Reduction::Quantile(_)))
+        .chain((0..reductions.len()).filter(|r| matches!(reductions[*r], Reduction::Quantile(_))))
+        .collect();
+    let reduced: Vec<Vec<f64>> = (0..elements)
+        .into_par_iter()
+        .map_init(
+            || Vec::with_capacity(size),
+            |values, i| {
+                let mut index = unravel(i, &shape);
+                index.insert(position, 0);
+                values.clear();
+                for k in 0..size {
+                    index[position] = k;
+                    values.push(variable.get(&index).into());
+                }
+                let mut out = vec![0.0; reductions.len()];
+                for r in order.iter() {
+                    out[*r] = reductions[*r].apply(values);
+                }
+                out
+            },
+        )
+        .collect();
+    (0..reductions.len())
+        .map(|r| VecVariable::new(dimensions.clone(), reduced.iter().map(|v| v[r]).collect()))
+        .collect()
+}
+
+/// [`crate::variable::table::to_summary_recordbatch`] computed in parallel
+pub fn par_to_summary_recordbatch<V>(
+    variable: &V,
+    member: &str,
+    name: &str,
+) -> arrow::error::Result<RecordBatch>
+where
+    V: Variable + Sync,
+    V::Elem: Float,
+{
+    let parts = par_reduce(
+        variable,
+        member,
+        &[Reduction::Min, Reduction::Mean, Reduction::Max],
+    )
+    .map_err(|e| ArrowError::InvalidArgumentError(e.to_string()))?;
+    let mean = VecVariable::new(
+        parts[1].dimensions().to_vec(),
+        parts[1]
+            .data()
+            .iter()
+            .map(|v| V::Elem::from_f64(*v))
+            .collect(),
+    )
+    .map_err(|e| ArrowError::InvalidArgumentError(e.to_string()))?;
+    with_range(
+        &mean,
+        name,
+        parts.into_iter().map(|p| p.data().to_vec()).collect(),
+    )
+}
+
+/// [`crate::calibration::step_quantiles`] computed in parallel
+pub fn par_step_quantiles(series: &[Vec<f64>], steps: usize, q: f64) -> Vec<f64> {
+    (0..steps)
+        .into_par_iter()
+        .map(|i| step_quantile(series, i, q))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calibration::step_quantiles;
+    use crate::variable::parallel::{par_reduce, par_step_quantiles, par_to_vec};
+    use crate::variable::{dim, Reduction, Variable, VecVariable};
+
+    #[test]
+    fn matches_sequential() {
+        let data: Vec<f32> = (0..3 * 5 * 4).map(|i| ((i * 7) % 11) as f32).collect();
+        let ensemble =
+            VecVariable::new(vec![dim("member", 3), dim("day", 5), dim("x", 4)], data).unwrap();
+        let reductions = [
+            Reduction::Quantile(0.9),
+            Reduction::Mean,
+            Reduction::Max,
+            Reduction::Quantile(0.5),
+        ];
+        let parallel = par_reduce(&ensemble, "day", &reductions).unwrap();
+        for (r, p) in reductions.iter().zip(parallel.iter()) {
+            let sequential = (&ensemble).reduce("day", *r).unwrap();
+            assert_eq!(p.shape(), sequential.shape());
+            // the sequential reduction is rounded to the element type
+            let got: Vec<f32> = p.data().iter().map(|v| *v as f32).collect();
+            assert_eq!(got, sequential.to_vec(), "{:?}", r);
+        }
+        assert_eq!(par_to_vec(&ensemble), ensemble.to_vec());
+        assert!(par_reduce(&ensemble, "day", &[Reduction::Quantile(2.0)]).is_err());
+
+        let series = vec![vec![1.0, 2.0], vec![3.0], vec![f64::NAN, 4.0]];
+        assert_eq!(
+            par_step_quantiles(&series, 2, 0.5),
+            step_quantiles(&series, 2, 0.5)
+        );
+    }
+}
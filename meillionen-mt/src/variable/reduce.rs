@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::extension_columns::DimMeta;
+use crate::variable::{Float, Variable, VariableError};
+
+/// How the values along a dimension are combined
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Reduction {
+    Sum,
+    Mean,
+    Min,
+    Max,
+    /// The linearly interpolated quantile, between 0 and 1
+    Quantile(f64),
+}
+
+impl Reduction {
+    /// Reject quantiles outside of zero to one
+    pub fn check(&self) -> Result<(), VariableError> {
+        match *self {
+            Reduction::Quantile(q) if !(0.0..=1.0).contains(&q) => {
+                Err(VariableError::InvalidQuantile(q))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reduce a set of values, which may be reordered in the process
+    pub fn apply(&self, values: &mut [f64]) -> f64 {
+        match *self {
+            Reduction::Sum => values.iter().sum(),
+            Reduction::Mean => values.iter().sum::<f64>() / values.len() as f64,
+            Reduction::Min => values.iter().cloned().fold(f64::NAN, f64::min),
+            Reduction::Max => values.iter().cloned().fold(f64::NAN, f64::max),
+            Reduction::Quantile(q) => quantile(values, q),
+        }
+    }
+}
+
+fn quantile(values: &mut [f64], q: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = q * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    values[lower] + (values[upper] - values[lower]) * (rank - lower as f64)
+}
+
+/// A variable reduced along one of its dimensions
+///
+/// Each element is computed when read from the values along the reduced
+/// dimension only, so reducing a long time series never holds more than
+/// one series in memory.
+#[derive(Clone, Debug)]
+pub struct Reduce<V> {
+    variable: V,
+    dimensions: Vec<Arc<DimMeta>>,
+    position: usize,
+    reduction: Reduction,
+}
+
+impl<V: Variable> Reduce<V> {
+    pub fn new(variable: V, name: &str, reduction: Reduction) -> Result<Self, VariableError> {
+        reduction.check()?;
+        let position = variable.dimension_position(name).ok_or_else(|| {
+            VariableError::MissingDimension(name.to_string(), variable.dimension_names())
+        })?;
+        let mut dimensions = variable.dimensions().to_vec();
+        dimensions.remove(position);
+        Ok(Self {
+            variable,
+            dimensions,
+            position,
+            reduction,
+        })
+    }
+}
+
+impl<V> Variable for Reduce<V>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        let size = self.variable.dimensions()[self.position].size;
+        let mut inner_index = Vec::with_capacity(index.len() + 1);
+        inner_index.extend_from_slice(&index[..self.position]);
+        inner_index.push(0);
+        inner_index.extend_from_slice(&index[self.position..]);
+        let mut values: Vec<f64> = (0..size)
+            .map(|i| {
+                inner_index[self.position] = i;
+                self.variable.get(&inner_index).into()
+            })
+            .collect();
+        V::Elem::from_f64(self.reduction.apply(&mut values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::{dim, Reduction, Variable, VariableError, VecVariable};
+
+    fn rainfall() -> VecVariable<f32> {
+        VecVariable::new(
+            vec![dim("x", 2), dim("t", 4)],
+            vec![1.0, 2.0, 3.0, 4.0, 0.0, 0.0, 10.0, 2.0],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reductions() {
+        let r = rainfall();
+        assert_eq!((&r).sum("t").unwrap().to_vec(), vec![10.0, 12.0]);
+        assert_eq!((&r).mean("t").unwrap().to_vec(), vec![2.5, 3.0]);
+        assert_eq!((&r).min("t").unwrap().to_vec(), vec![1.0, 0.0]);
+        assert_eq!((&r).max("t").unwrap().to_vec(), vec![4.0, 10.0]);
+        assert_eq!((&r).sum("x").unwrap().to_vec(), vec![1.0, 2.0, 13.0, 6.0]);
+        assert_eq!(
+            (&r).sum("t").unwrap().sum("x").unwrap().to_vec(),
+            vec![22.0]
+        );
+    }
+
+    #[test]
+    fn quantiles() {
+        let r = rainfall();
+        assert_eq!((&r).quantile("t", 0.5).unwrap().to_vec(), vec![2.5, 1.0]);
+        assert_eq!((&r).quantile("t", 1.0).unwrap().to_vec(), vec![4.0, 10.0]);
+        assert_eq!((&r).quantile("t", 0.25).unwrap().to_vec(), vec![1.75, 0.0]);
+        assert_eq!(
+            (&r).quantile("t", 1.5).err().unwrap(),
+            VariableError::InvalidQuantile(1.5)
+        );
+    }
+
+    #[test]
+    fn missing_dimension() {
+        let r = rainfall();
+        assert_eq!(
+            (&r).reduce("y", Reduction::Sum).err().unwrap(),
+            VariableError::MissingDimension(
+                "y".to_string(),
+                vec!["x".to_string(), "t".to_string()]
+            )
+        );
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayData, ArrayRef, DictionaryArray, Float64Array, Int32Array, StringArray,
+    TimestampNanosecondArray, UInt64Array,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Field, Int32Type, Schema, TimeUnit};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, NaiveDateTime};
+use thiserror::Error;
+
+use crate::extension_columns::{DimMeta, TensorStackMeta, UncertaintyMeta, UnitsMeta};
+use crate::timeseries::numeric_values;
+use crate::variable::{
+    Attributes, Float, Indices, Reduction, Variable, VariableError, VecVariable,
+};
+
+/// Schema metadata key holding the dimensions of a converted variable
+pub const DIMENSIONS_KEY: &str = "meillionen-dimensions";
+
+#[derive(Debug, Error)]
+pub enum TableError {
+    #[error("column {0} not found")]
+    MissingColumn(String),
+    #[error("column {0} is {1:?} but values must be numeric")]
+    NotNumeric(String, DataType),
+    #[error("index column {0} is {1:?} but must be integers, strings, categorical or timestamps")]
+    UnsupportedIndex(String, DataType),
+    #[error("index column {0} has missing values")]
+    MissingIndex(String),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error(transparent)]
+    Variable(#[from] VariableError),
+}
+
+/// The type of the column holding a labeled dimension, which pandas reads as
+/// a categorical
+pub(crate) fn label_type() -> DataType {
+    DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+}
+
+fn time_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Nanosecond, None)
+}
+
+/// A dictionary column whose keys are the positions along a dimension and
+/// whose values are its labels, so each label is stored once
+pub(crate) fn label_column(positions: Vec<u64>, labels: &[String]) -> ArrayRef {
+    let keys = Int32Array::from_iter_values(positions.into_iter().map(|p| p as i32));
+    let values = StringArray::from_iter_values(labels);
+    let data = ArrayData::new(
+        label_type(),
+        keys.len(),
+        Some(0),
+        None,
+        0,
+        keys.data().buffers().to_vec(),
+        vec![values.data().clone()],
+    );
+    Arc::new(DictionaryArray::<Int32Type>::from(data))
+}
+
+/// The column a dimension is written as, with the value at each row
+pub(crate) fn index_column(
+    d: &DimMeta,
+    positions: Vec<u64>,
+) -> arrow::error::Result<(Field, ArrayRef)> {
+    let mismatch = |kind: &str, len: usize| {
+        ArrowError::InvalidArgumentError(format!(
+            "dimension {} has size {} but {} {}",
+            d.name, d.size, len, kind
+        ))
+    };
+    if let Some(labels) = &d.labels {
+        if labels.len() != d.size {
+            return Err(mismatch("labels", labels.len()));
+        }
+        let field = Field::new(&d.name, label_type(), false);
+        return Ok((field, label_column(positions, labels)));
+    }
+    if let Some(times) = &d.times {
+        if times.len() != d.size {
+            return Err(mismatch("times", times.len()));
+        }
+        let nanos = times
+            .iter()
+            .map(|t| {
+                t.and_utc().timestamp_nanos_opt().ok_or_else(|| {
+                    ArrowError::InvalidArgumentError(format!(
+                        "time {} of dimension {} is out of range",
+                        t, d.name
+                    ))
+                })
+            })
+            .collect::<arrow::error::Result<Vec<i64>>>()?;
+        let column = TimestampNanosecondArray::from_iter_values(
+            positions.into_iter().map(|p| nanos[p as usize]),
+        );
+        let field = Field::new(&d.name, time_type(), false);
+        return Ok((field, Arc::new(column)));
+    }
+    let field = Field::new(&d.name, DataType::UInt64, false);
+    Ok((field, Arc::new(UInt64Array::from(positions))))
+}
+
+/// Convert a variable to a long format record batch
+///
+/// There is one index column per dimension followed by a `Float64` column
+/// called `name` with the values. Index columns are `UInt64` positions,
+/// dictionary encoded labels for labeled dimensions or nanosecond timestamps
+/// for timed dimensions, which pandas reads as categorical and `datetime64[ns]`.
+/// The value column is marked as a `meillionen.units` column when the variable
+/// has units. In python `batch.to_pandas().set_index(dims).to_xarray()`
+/// recovers the array.
+pub fn to_recordbatch<V>(variable: &V, name: &str) -> arrow::error::Result<RecordBatch>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    let ndims = variable.dimensions().len();
+    let mut indices: Vec<Vec<u64>> = vec![Vec::with_capacity(variable.len()); ndims];
+    let mut values = Vec::with_capacity(variable.len());
+    for index in Indices::new(variable.shape()) {
+        for (column, &i) in indices.iter_mut().zip(&index) {
+            column.push(i as u64);
+        }
+        values.push(variable.get(&index).into());
+    }
+
+    let (mut fields, mut columns): (Vec<Field>, Vec<ArrayRef>) = indices
+        .into_iter()
+        .zip(variable.dimensions())
+        .map(|(positions, d)| index_column(d, positions))
+        .collect::<arrow::error::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let mut value = Field::new(name, DataType::Float64, true);
+    if let Some(units) = variable.attributes().and_then(|a| a.units.as_ref()) {
+        value = UnitsMeta::new(units).annotate(value);
+    }
+    fields.push(value);
+    columns.push(Arc::new(Float64Array::from(values)));
+
+    let meta = TensorStackMeta::new(variable.dimensions().to_vec());
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        DIMENSIONS_KEY.to_string(),
+        serde_json::to_string(&meta).expect("dimensions to serialize"),
+    );
+    RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(fields, metadata)),
+        columns,
+    )
+}
+
+/// Summarise the members of an ensemble as a long format record batch
+///
+/// The `member` dimension is reduced to a `meillionen.uncertainty` column
+/// called `name` with the min, mean and max of the members. The other
+/// dimensions are written as in `to_recordbatch`.
+pub fn to_summary_recordbatch<V>(
+    variable: &V,
+    member: &str,
+    name: &str,
+) -> arrow::error::Result<RecordBatch>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    let reduced = |r: Reduction| {
+        variable
+            .reduce(member, r)
+            .map_err(|e| ArrowError::InvalidArgumentError(e.to_string()))
+    };
+    let mean = reduced(Reduction::Mean)?;
+    let parts = vec![
+        reduced(Reduction::Min)?.to_vec(),
+        mean.to_vec(),
+        reduced(Reduction::Max)?.to_vec(),
+    ]
+    .into_iter()
+    .map(|p| p.into_iter().map(Into::into).collect())
+    .collect();
+    with_range(&mean, name, parts)
+}
+
+/// The record batch of `mean` with its column replaced by a range column of
+/// the min, mean and max
+pub(crate) fn with_range<V>(
+    mean: &V,
+    name: &str,
+    parts: Vec<Vec<f64>>,
+) -> arrow::error::Result<RecordBatch>
+where
+    V: Variable,
+    V::Elem: Float,
+{
+    let rb = to_recordbatch(mean, name)?;
+    let (field, column) = UncertaintyMeta::Range.column(name, parts)?;
+
+    let schema = rb.schema();
+    let last = rb.num_columns() - 1;
+    let mut fields = schema.fields()[..last].to_vec();
+    fields.push(field);
+    let mut columns = rb.columns()[..last].to_vec();
+    columns.push(column);
+    RecordBatch::try_new(
+        Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+        columns,
+    )
+}
+
+/// The positions of one index column along its dimension
+fn index_positions(
+    column: &ArrayRef,
+    name: &str,
+    known: Option<&DimMeta>,
+) -> Result<(DimMeta, Vec<usize>), TableError> {
+    if column.null_count() > 0 {
+        return Err(TableError::MissingIndex(name.to_string()));
+    }
+    match column.data_type() {
+        DataType::Utf8 | DataType::Dictionary(_, _) => {
+            let encoded = cast(column, &label_type())?;
+            let encoded = encoded
+                .as_any()
+                .downcast_ref::<DictionaryArray<Int32Type>>()
+                .expect("array type to match data type");
+            let values = encoded.values();
+            let values = values
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| {
+                    TableError::UnsupportedIndex(name.to_string(), column.data_type().clone())
+                })?;
+            let found: Vec<String> = values.iter().map(|v| v.unwrap_or("").to_string()).collect();
+            // keep the order of labels written by `to_recordbatch`, pandas
+            // may have reordered or dropped unused categories
+            let labels = match known.and_then(|d| d.labels.clone()) {
+                Some(labels) if found.iter().all(|f| labels.contains(f)) => labels,
+                _ => found.clone(),
+            };
+            let lookup: HashMap<&str, usize> = labels
+                .iter()
+                .enumerate()
+                .map(|(i, l)| (l.as_str(), i))
+                .collect();
+            let positions = encoded
+                .keys()
+                .values()
+                .iter()
+                .map(|k| lookup[found[*k as usize].as_str()])
+                .collect();
+            let mut dim = DimMeta::labeled(name, labels);
+            dim.description = known.and_then(|d| d.description.clone());
+            Ok((dim, positions))
+        }
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => {
+            // negative positions cast to null
+            let positions = cast(column, &DataType::UInt64)?;
+            if positions.null_count() > 0 {
+                return Err(TableError::UnsupportedIndex(
+                    name.to_string(),
+                    column.data_type().clone(),
+                ));
+            }
+            let positions: Vec<usize> = positions
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .expect("array type to match data type")
+                .values()
+                .iter()
+                .map(|p| *p as usize)
+                .collect();
+            let size = positions.iter().map(|p| p + 1).max().unwrap_or(0);
+            let dim = DimMeta {
+                name: name.to_string(),
+                size: known.map_or(size, |d| d.size.max(size)),
+                description: known.and_then(|d| d.description.clone()),
+                labels: None,
+                times: None,
+            };
+            Ok((dim, positions))
+        }
+        DataType::Timestamp(_, _) => {
+            let nanos = cast(column, &time_type())?;
+            let found: Vec<NaiveDateTime> = nanos
+                .as_any()
+                .downcast_ref::<TimestampNanosecondArray>()
+                .expect("array type to match data type")
+                .values()
+                .iter()
+                .map(|n| DateTime::from_timestamp_nanos(*n).naive_utc())
+                .collect();
+            let times = match known.and_then(|d| d.times.clone()) {
+                Some(times) if found.iter().all(|f| times.contains(f)) => times,
+                _ => found
+                    .iter()
+                    .map(|t| (*t, ()))
+                    .collect::<BTreeMap<_, _>>()
+                    .into_keys()
+                    .collect(),
+            };
+            let lookup: HashMap<NaiveDateTime, usize> =
+                times.iter().enumerate().map(|(i, t)| (*t, i)).collect();
+            let positions = found.iter().map(|t| lookup[t]).collect();
+            let mut dim = DimMeta::timed(name, times);
+            dim.description = known.and_then(|d| d.description.clone());
+            Ok((dim, positions))
+        }
+        t => Err(TableError::UnsupportedIndex(name.to_string(), t.clone())),
+    }
+}
+
+/// Convert a long format record batch back to a variable
+///
+/// This undoes `to_recordbatch`, and also reads frames built in pandas. Every
+/// column other than `name` is a dimension, either integer positions, string
+/// or categorical labels or timestamps. Timestamps without metadata are put in
+/// time order. Sizes and the order of labels and times come from the dimensions
+/// in the schema metadata when present. Cells without a row are NaN.
+pub fn from_recordbatch(rb: &RecordBatch, name: &str) -> Result<VecVariable<f64>, TableError> {
+    let schema = rb.schema();
+    let value_index = schema
+        .index_of(name)
+        .map_err(|_| TableError::MissingColumn(name.to_string()))?;
+    let values = numeric_values(rb, name).ok_or_else(|| {
+        TableError::NotNumeric(name.to_string(), rb.column(value_index).data_type().clone())
+    })?;
+    let known: Vec<Arc<DimMeta>> = schema
+        .metadata()
+        .get(DIMENSIONS_KEY)
+        .and_then(|m| serde_json::from_str::<TensorStackMeta>(m).ok())
+        .map(|m| m.dimensions().clone())
+        .unwrap_or_default();
+
+    let mut dimensions = vec![];
+    let mut positions = vec![];
+    for (i, field) in schema.fields().iter().enumerate() {
+        if i == value_index {
+            continue;
+        }
+        let known = known.iter().find(|d| &d.name == field.name());
+        let (dim, p) = index_positions(rb.column(i), field.name(), known.map(|d| d.as_ref()))?;
+        dimensions.push(Arc::new(dim));
+        positions.push(p);
+    }
+
+    let mut variable = VecVariable::full(dimensions, f64::NAN)?;
+    if let Some(units) = UnitsMeta::from_field(schema.field(value_index)) {
+        variable = variable.with_attributes(Attributes::default().with_units(&units.unit));
+    }
+    let mut index = vec![0; positions.len()];
+    for (row, value) in values.into_iter().enumerate() {
+        for (i, p) in index.iter_mut().zip(&positions) {
+            *i = p[row];
+        }
+        variable.set(&index, value);
+    }
+    Ok(variable)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::UInt64Array;
+
+    use std::sync::Arc;
+
+    use arrow::array::{
+        ArrayRef, DictionaryArray, Float32Array, Float64Array, StringArray, StructArray,
+        TimestampNanosecondArray, TimestampSecondArray,
+    };
+    use arrow::datatypes::{DataType, Field, Int32Type, Int8Type, Schema, TimeUnit};
+    use arrow::record_batch::RecordBatch;
+    use chrono::NaiveDate;
+
+    use crate::extension_columns::{DimMeta, UncertaintyMeta};
+    use crate::variable::table::{from_recordbatch, to_recordbatch, to_summary_recordbatch};
+    use crate::variable::{dim, Attributes, Variable, VecVariable};
+
+    #[test]
+    fn long_format() {
+        let v =
+            VecVariable::new(vec![dim("x", 2), dim("t", 2)], vec![1.0f32, 2.0, 3.0, 4.0]).unwrap();
+        let rb = to_recordbatch(&(&v).sum("t").unwrap(), "rainfall_total").unwrap();
+        assert_eq!(rb.num_rows(), 2);
+        assert_eq!(rb.schema().field(0).name(), "x");
+        assert_eq!(rb.schema().field(1).name(), "rainfall_total");
+        let x = rb.column(0).as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(x.values(), &[0, 1]);
+        let total = rb
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert_eq!(total.values(), &[3.0, 7.0]);
+    }
+
+    #[test]
+    fn labeled_round_trip() {
+        let cultivar = DimMeta::labeled("cultivar", vec!["A".to_string(), "B".to_string()]);
+        let v = VecVariable::new(
+            vec![Arc::new(cultivar), dim("t", 2)],
+            vec![1.0, 2.0, 3.0, 4.0],
+        )
+        .unwrap();
+        let rb = to_recordbatch(&v, "yield").unwrap();
+        let labels = rb
+            .column(0)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        assert_eq!(labels.values().len(), 2);
+        assert_eq!(labels.keys().values(), &[0, 0, 1, 1]);
+        let back = from_recordbatch(&rb, "yield").unwrap();
+        assert_eq!(back, v);
+
+        // a frame built in pandas, with sites in the order first seen
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("site", DataType::Utf8, false),
+            Field::new(
+                "scenario",
+                DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8)),
+                false,
+            ),
+            Field::new("yield", DataType::Float32, true),
+        ]));
+        let scenario: DictionaryArray<Int8Type> = vec!["wet", "dry", "wet"].into_iter().collect();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["b", "a", "a"])),
+            Arc::new(scenario),
+            Arc::new(Float32Array::from(vec![1.0, 2.0, 3.0])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let v = from_recordbatch(&rb, "yield").unwrap();
+        assert_eq!(v.dimension_names(), vec!["site", "scenario"]);
+        assert_eq!(
+            v.dimensions()[0].labels,
+            Some(vec!["b".to_string(), "a".to_string()])
+        );
+        assert_eq!(v.get(&[1, 1]), 2.0);
+        assert_eq!(v.get(&[1, 0]), 3.0);
+        assert!(v.get(&[0, 1]).is_nan());
+        assert!(from_recordbatch(&rb, "site").is_err());
+    }
+
+    #[test]
+    fn timed_round_trip() {
+        let day = |d| {
+            NaiveDate::from_ymd_opt(1990, 5, d)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        };
+        let time = DimMeta::timed("time", vec![day(2), day(3)]);
+        let v = VecVariable::new(vec![dim("x", 1), Arc::new(time)], vec![0.5, 0.75])
+            .unwrap()
+            .with_attributes(Attributes::default().with_units("m2/m2"));
+        let rb = to_recordbatch(&v, "plant_leaf_area_index").unwrap();
+        let times = rb
+            .column(1)
+            .as_any()
+            .downcast_ref::<TimestampNanosecondArray>()
+            .unwrap();
+        assert_eq!(times.value(1), 641_692_800_000_000_000);
+        assert!(rb.schema().field(2).metadata().is_some());
+        assert_eq!(from_recordbatch(&rb, "plant_leaf_area_index").unwrap(), v);
+
+        // seconds from pandas, out of order
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::Timestamp(TimeUnit::Second, None), false),
+            Field::new("rainfall", DataType::Float64, true),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampSecondArray::from(vec![641_692_800, 641_606_400])),
+            Arc::new(Float64Array::from(vec![1.0, 2.0])),
+        ];
+        let rb = RecordBatch::try_new(schema, columns).unwrap();
+        let v = from_recordbatch(&rb, "rainfall").unwrap();
+        assert_eq!(v.dimensions()[0].times, Some(vec![day(2), day(3)]));
+        assert_eq!(v.to_vec(), vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn ensemble_summary() {
+        let v = VecVariable::new(
+            vec![dim("member", 3), dim("t", 2)],
+            vec![1.0, 2.0, 3.0, 6.0, 5.0, 1.0],
+        )
+        .unwrap();
+        let rb = to_summary_recordbatch(&v, "member", "yield").unwrap();
+        assert_eq!(rb.num_columns(), 2);
+        assert_eq!(
+            UncertaintyMeta::from_field(rb.schema().field(1)),
+            Some(UncertaintyMeta::Range)
+        );
+        let range = rb.column(1).as_any().downcast_ref::<StructArray>().unwrap();
+        let part = |name| {
+            range
+                .column_by_name(name)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Float64Array>()
+                .unwrap()
+                .values()
+                .to_vec()
+        };
+        assert_eq!(part("min"), vec![1.0, 1.0]);
+        assert_eq!(part("mean"), vec![3.0, 3.0]);
+        assert_eq!(part("max"), vec![5.0, 6.0]);
+    }
+}
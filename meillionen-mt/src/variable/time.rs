@@ -0,0 +1,519 @@
+//! Time coordinates read from NetCDF files
+//!
+//! A CF time coordinate holds numbers with a `units` attribute such as
+//! `days since 1990-01-01` and a `calendar` attribute. Climate model forcing
+//! often uses a `noleap` or `360_day` calendar, so the numbers are decoded in
+//! that calendar to [`CfDateTime`]s, which have the fields of a cftime
+//! datetime. Those that exist in the real calendar convert to chrono types.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::variable::Attributes;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum TimeError {
+    #[error("calendar must be standard, gregorian, proleptic_gregorian, noleap, 365_day, all_leap, 366_day or 360_day but is {0}")]
+    UnknownCalendar(String),
+    #[error("time units must look like `days since 1990-01-01` but are {0}")]
+    InvalidUnits(String),
+    #[error("time coordinates need a units attribute")]
+    MissingUnits,
+    #[error("{date} is not a date in the {calendar} calendar")]
+    InvalidDate { date: String, calendar: Calendar },
+    #[error("time value {0} is not finite")]
+    NotFinite(f64),
+    #[error("{0} is before the standard calendar switches to gregorian on 1582-10-15, use proleptic_gregorian")]
+    BeforeGregorian(CfDateTime),
+    #[error("{date} in the {calendar} calendar has no chrono equivalent")]
+    NoChronoDate {
+        date: CfDateTime,
+        calendar: Calendar,
+    },
+}
+
+/// A CF calendar
+///
+/// `standard` is treated as proleptic gregorian, so dates before the switch
+/// from the julian calendar in 1582 are rejected rather than decoded wrongly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Calendar {
+    #[default]
+    Standard,
+    ProlepticGregorian,
+    /// Every year has 365 days
+    #[serde(alias = "365_day")]
+    Noleap,
+    /// Every year has 366 days
+    #[serde(alias = "366_day")]
+    AllLeap,
+    /// Every month has 30 days
+    #[serde(rename = "360_day")]
+    Day360,
+}
+
+impl FromStr for Calendar {
+    type Err = TimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "standard" | "gregorian" => Ok(Calendar::Standard),
+            "proleptic_gregorian" => Ok(Calendar::ProlepticGregorian),
+            "noleap" | "365_day" => Ok(Calendar::Noleap),
+            "all_leap" | "366_day" => Ok(Calendar::AllLeap),
+            "360_day" => Ok(Calendar::Day360),
+            _ => Err(TimeError::UnknownCalendar(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Calendar::Standard => "standard",
+            Calendar::ProlepticGregorian => "proleptic_gregorian",
+            Calendar::Noleap => "noleap",
+            Calendar::AllLeap => "all_leap",
+            Calendar::Day360 => "360_day",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Calendar {
+    pub fn is_leap(self, year: i32) -> bool {
+        match self {
+            Calendar::Standard | Calendar::ProlepticGregorian => {
+                year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+            }
+            Calendar::AllLeap => true,
+            Calendar::Noleap | Calendar::Day360 => false,
+        }
+    }
+
+    pub fn days_in_month(self, year: i32, month: u32) -> u32 {
+        match (self, month) {
+            (Calendar::Day360, _) => 30,
+            (_, 2) if self.is_leap(year) => 29,
+            (_, 2) => 28,
+            (_, 4) | (_, 6) | (_, 9) | (_, 11) => 30,
+            _ => 31,
+        }
+    }
+
+    /// Days in 400 years, used to guess the year of a day number
+    fn days_in_400_years(self) -> i64 {
+        match self {
+            Calendar::Standard | Calendar::ProlepticGregorian => 146_097,
+            Calendar::Noleap => 146_000,
+            Calendar::AllLeap => 146_400,
+            Calendar::Day360 => 144_000,
+        }
+    }
+
+    /// Days from the start of year 0 to the start of `year`
+    fn days_before_year(self, year: i32) -> i64 {
+        let y = year as i64;
+        match self {
+            Calendar::Standard | Calendar::ProlepticGregorian => {
+                365 * y + (y + 3).div_euclid(4) - (y + 99).div_euclid(100)
+                    + (y + 399).div_euclid(400)
+            }
+            Calendar::Noleap => 365 * y,
+            Calendar::AllLeap => 366 * y,
+            Calendar::Day360 => 360 * y,
+        }
+    }
+
+    /// Days from the start of year 0 to a date
+    fn day_number(self, year: i32, month: u32, day: u32) -> i64 {
+        let before_month: i64 = (1..month).map(|m| self.days_in_month(year, m) as i64).sum();
+        self.days_before_year(year) + before_month + day as i64 - 1
+    }
+
+    /// The date of a day number
+    fn date(self, day_number: i64) -> (i32, u32, u32) {
+        let mut year = (day_number * 400).div_euclid(self.days_in_400_years()) as i32;
+        while self.days_before_year(year) > day_number {
+            year -= 1;
+        }
+        while self.days_before_year(year + 1) <= day_number {
+            year += 1;
+        }
+        let mut rest = day_number - self.days_before_year(year);
+        let mut month = 1;
+        while rest >= self.days_in_month(year, month) as i64 {
+            rest -= self.days_in_month(year, month) as i64;
+            month += 1;
+        }
+        (year, month, rest as u32 + 1)
+    }
+}
+
+/// A date and time in some calendar, with the fields of a cftime datetime
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct CfDateTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl CfDateTime {
+    pub fn new(year: i32, month: u32, day: u32) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+
+    pub fn is_valid(&self, calendar: Calendar) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=calendar.days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// The day of the year, starting from 1, as crop calendars count days
+    pub fn day_of_year(&self, calendar: Calendar) -> u32 {
+        (calendar.day_number(self.year, self.month, self.day)
+            - calendar.days_before_year(self.year)) as u32
+            + 1
+    }
+
+    /// The same date in chrono, if it exists in the real calendar
+    ///
+    /// `noleap` dates all exist, but the 30th of February of a `360_day`
+    /// calendar does not.
+    pub fn to_naive(&self) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(self.year, self.month, self.day)?.and_hms_opt(
+            self.hour,
+            self.minute,
+            self.second,
+        )
+    }
+
+    fn seconds(&self, calendar: Calendar) -> i64 {
+        calendar.day_number(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+    }
+
+    fn from_seconds(seconds: i64, calendar: Calendar) -> Self {
+        let (year, month, day) = calendar.date(seconds.div_euclid(SECONDS_PER_DAY));
+        let rest = seconds.rem_euclid(SECONDS_PER_DAY) as u32;
+        Self {
+            year,
+            month,
+            day,
+            hour: rest / 3600,
+            minute: rest % 3600 / 60,
+            second: rest % 60,
+        }
+    }
+}
+
+impl fmt::Display for CfDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeUnit {
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+}
+
+impl TimeUnit {
+    pub fn seconds(self) -> i64 {
+        match self {
+            TimeUnit::Seconds => 1,
+            TimeUnit::Minutes => 60,
+            TimeUnit::Hours => 3600,
+            TimeUnit::Days => SECONDS_PER_DAY,
+        }
+    }
+}
+
+/// The `units` attribute of a time coordinate, such as
+/// `hours since 1990-01-01 06:00:00 +06:00`
+///
+/// A time zone offset after the reference time is taken off it, so decoded
+/// times are in UTC.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeUnits {
+    pub unit: TimeUnit,
+    /// The reference time as written, in its own time zone
+    pub since: CfDateTime,
+    /// The offset of that time zone from UTC in seconds
+    pub offset: i64,
+}
+
+/// Split off the longest prefix whose characters match `keep`
+fn split_while(s: &str, keep: impl Fn(char) -> bool) -> (&str, &str) {
+    let end = s.find(|c| !keep(c)).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// The `-`, `:` or empty separated parts of a date or time as numbers
+fn numbers(s: &str, sep: char) -> Option<Vec<f64>> {
+    s.split(sep)
+        .map(|p| p.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()
+}
+
+/// A UTC offset in seconds from `Z`, `UTC`, `+6`, `-06:00` or `+0530`
+fn parse_offset(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if s.is_empty() || ["Z", "UTC", "GMT"].contains(&s.to_uppercase().as_str()) {
+        return Some(0);
+    }
+    let (sign, rest) = match s.chars().next()? {
+        '+' => (1, &s[1..]),
+        '-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h.parse::<i64>().ok()?, m.parse::<i64>().ok()?),
+        None if rest.len() == 4 => (rest[..2].parse().ok()?, rest[2..].parse().ok()?),
+        None => (rest.parse().ok()?, 0),
+    };
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+impl FromStr for TimeUnits {
+    type Err = TimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || TimeError::InvalidUnits(s.to_string());
+        let (unit, reference) = s.trim().split_once(" since ").ok_or_else(invalid)?;
+        let unit = match unit.trim().to_lowercase().as_str() {
+            "seconds" | "second" | "secs" | "sec" | "s" => TimeUnit::Seconds,
+            "minutes" | "minute" | "mins" | "min" => TimeUnit::Minutes,
+            "hours" | "hour" | "hrs" | "hr" | "h" => TimeUnit::Hours,
+            "days" | "day" | "d" => TimeUnit::Days,
+            _ => return Err(invalid()),
+        };
+
+        let reference = reference.trim();
+        // a leading minus belongs to the year, not to a separator
+        let sign_len = usize::from(reference.starts_with('-'));
+        let (date, rest) = split_while(&reference[sign_len..], |c| c.is_ascii_digit() || c == '-');
+        let rest = rest.strip_prefix('T').unwrap_or(rest).trim_start();
+        let (time, zone) = split_while(rest, |c| c.is_ascii_digit() || c == ':' || c == '.');
+
+        let date = numbers(date, '-')
+            .filter(|d| d.len() == 3)
+            .ok_or_else(invalid)?;
+        let time = if time.is_empty() {
+            vec![]
+        } else {
+            numbers(time, ':')
+                .filter(|t| t.len() <= 3)
+                .ok_or_else(invalid)?
+        };
+        let part = |i: usize| time.get(i).copied().unwrap_or(0.0);
+        let year = if sign_len == 1 { -date[0] } else { date[0] };
+        let since = CfDateTime {
+            year: year as i32,
+            month: date[1] as u32,
+            day: date[2] as u32,
+            hour: part(0) as u32,
+            minute: part(1) as u32,
+            // fractions of a second are dropped
+            second: part(2) as u32,
+        };
+        Ok(Self {
+            unit,
+            since,
+            offset: parse_offset(zone).ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl TimeUnits {
+    /// The time of each value in a calendar
+    pub fn decode(&self, values: &[f64], calendar: Calendar) -> Result<Vec<CfDateTime>, TimeError> {
+        if !self.since.is_valid(calendar) {
+            return Err(TimeError::InvalidDate {
+                date: self.since.to_string(),
+                calendar,
+            });
+        }
+        let start = self.since.seconds(calendar) - self.offset;
+        let unit = self.unit.seconds() as f64;
+        let gregorian_start = CfDateTime::new(1582, 10, 15);
+        values
+            .iter()
+            .map(|v| {
+                if !v.is_finite() {
+                    return Err(TimeError::NotFinite(*v));
+                }
+                let time = CfDateTime::from_seconds(start + (v * unit).round() as i64, calendar);
+                if calendar == Calendar::Standard && time < gregorian_start {
+                    return Err(TimeError::BeforeGregorian(time));
+                }
+                Ok(time)
+            })
+            .collect()
+    }
+
+    /// The time of each value as chrono times, failing on dates the real
+    /// calendar does not have
+    pub fn decode_naive(
+        &self,
+        values: &[f64],
+        calendar: Calendar,
+    ) -> Result<Vec<NaiveDateTime>, TimeError> {
+        self.decode(values, calendar)?
+            .into_iter()
+            .map(|date| {
+                date.to_naive()
+                    .ok_or(TimeError::NoChronoDate { date, calendar })
+            })
+            .collect()
+    }
+}
+
+/// The units and calendar of a time coordinate from its attributes
+///
+/// A coordinate without a calendar attribute uses the standard calendar.
+pub fn time_units(attributes: &Attributes) -> Result<(TimeUnits, Calendar), TimeError> {
+    let units = attributes
+        .units
+        .as_deref()
+        .ok_or(TimeError::MissingUnits)?
+        .parse()?;
+    let calendar = match attributes.calendar.as_deref() {
+        Some(c) => c.parse()?,
+        None => Calendar::default(),
+    };
+    Ok((units, calendar))
+}
+
+/// Decode the values of a time coordinate with its `units` and `calendar`
+/// attributes
+pub fn decode_times(values: &[f64], attributes: &Attributes) -> Result<Vec<CfDateTime>, TimeError> {
+    let (units, calendar) = time_units(attributes)?;
+    units.decode(values, calendar)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::variable::time::{
+        decode_times, Calendar, CfDateTime, TimeError, TimeUnit, TimeUnits,
+    };
+    use crate::variable::Attributes;
+
+    #[test]
+    fn parse_units() {
+        let units: TimeUnits = "hours since 1990-1-1 06:30:00 +06:00".parse().unwrap();
+        assert_eq!(units.unit, TimeUnit::Hours);
+        assert_eq!(
+            units.since,
+            CfDateTime {
+                hour: 6,
+                minute: 30,
+                ..CfDateTime::new(1990, 1, 1)
+            }
+        );
+        assert_eq!(units.offset, 6 * 3600);
+        let iso: TimeUnits = "days since 2000-03-01T00:00:00Z".parse().unwrap();
+        assert_eq!((iso.unit, iso.offset), (TimeUnit::Days, 0));
+        assert_eq!(
+            "seconds since 1970-01-01 -0530"
+                .parse::<TimeUnits>()
+                .unwrap()
+                .offset,
+            -19800
+        );
+        assert!("days after 1990-01-01".parse::<TimeUnits>().is_err());
+        assert!("days since 1990-01".parse::<TimeUnits>().is_err());
+        assert!("fortnights since 1990-01-01".parse::<TimeUnits>().is_err());
+        assert_eq!("365_day".parse(), Ok(Calendar::Noleap));
+        assert_eq!(
+            serde_json::to_string(&Calendar::Day360).unwrap(),
+            r#""360_day""#
+        );
+    }
+
+    #[test]
+    fn calendars() {
+        let units: TimeUnits = "days since 2000-02-28".parse().unwrap();
+        let dates = |calendar| units.decode(&[0.0, 1.0, 2.0, 366.0], calendar).unwrap();
+        assert_eq!(
+            dates(Calendar::Standard),
+            vec![
+                CfDateTime::new(2000, 2, 28),
+                CfDateTime::new(2000, 2, 29),
+                CfDateTime::new(2000, 3, 1),
+                CfDateTime::new(2001, 2, 28),
+            ]
+        );
+        assert_eq!(dates(Calendar::Noleap)[1], CfDateTime::new(2000, 3, 1));
+        assert_eq!(dates(Calendar::Noleap)[3], CfDateTime::new(2001, 3, 1));
+        assert_eq!(dates(Calendar::AllLeap)[3], CfDateTime::new(2001, 2, 28));
+        assert_eq!(dates(Calendar::Day360)[2], CfDateTime::new(2000, 2, 30));
+        assert_eq!(dates(Calendar::Day360)[3], CfDateTime::new(2001, 3, 4));
+        assert_eq!(dates(Calendar::Day360)[2].day_of_year(Calendar::Day360), 60);
+        assert!(dates(Calendar::Day360)[2].to_naive().is_none());
+        assert!(matches!(
+            units.decode_naive(&[2.0], Calendar::Day360),
+            Err(TimeError::NoChronoDate { .. })
+        ));
+
+        let early: TimeUnits = "days since 1582-10-15".parse().unwrap();
+        assert!(matches!(
+            early.decode(&[-1.0], Calendar::Standard),
+            Err(TimeError::BeforeGregorian(_))
+        ));
+        assert_eq!(
+            early.decode(&[-1.0], Calendar::ProlepticGregorian).unwrap(),
+            vec![CfDateTime::new(1582, 10, 14)]
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let attrs = Attributes::default()
+            .with_units("hours since 1990-01-01 00:00:00 +06:00")
+            .with_calendar("noleap");
+        let times = decode_times(&[6.0, 30.0], &attrs).unwrap();
+        assert_eq!(times[0], CfDateTime::new(1990, 1, 1));
+        assert_eq!(
+            times[1].to_naive(),
+            NaiveDate::from_ymd_opt(1990, 1, 2).and_then(|d| d.and_hms_opt(0, 0, 0))
+        );
+        assert_eq!(
+            decode_times(&[0.0], &Attributes::default()),
+            Err(TimeError::MissingUnits)
+        );
+        assert!(decode_times(&[f64::NAN], &attrs.with_calendar("julian")).is_err());
+    }
+}
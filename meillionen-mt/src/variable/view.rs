@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::extension_columns::DimMeta;
+use crate::variable::{check_unique, Attributes, Variable, VariableError};
+
+/// Which target dimensions a view is allowed to broadcast
+///
+/// A broadcast dimension is either absent from the wrapped variable or has
+/// size one in it. Its index is ignored when reading, so the same value is
+/// repeated along it (a time only rainfall series applied to every cell).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum Broadcast {
+    /// The target dimensions must match the variable's dimensions exactly
+    #[default]
+    None,
+    /// Only the named dimensions may be broadcast
+    Dims(Vec<String>),
+    /// Any target dimension may be broadcast
+    All,
+}
+
+impl Broadcast {
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Broadcast::None => false,
+            Broadcast::Dims(names) => names.iter().any(|n| n == name),
+            Broadcast::All => true,
+        }
+    }
+}
+
+/// Presents a variable with its dimensions rearranged into a target order
+///
+/// Every dimension of the wrapped variable must appear in the target
+/// dimensions with the same size, unless it has size one and the target
+/// dimension is broadcast. `indice_map[i]` is the position in the wrapped
+/// variable of the `i`th target dimension or `None` if the target dimension
+/// is broadcast.
+#[derive(Clone, Debug)]
+pub struct VarView<V> {
+    variable: V,
+    dimensions: Vec<Arc<DimMeta>>,
+    indice_map: Vec<Option<usize>>,
+}
+
+impl<V: Variable> VarView<V> {
+    pub fn new(variable: V, dimensions: Vec<Arc<DimMeta>>) -> Result<Self, VariableError> {
+        Self::broadcast(variable, dimensions, &Broadcast::None)
+    }
+
+    pub fn broadcast(
+        variable: V,
+        dimensions: Vec<Arc<DimMeta>>,
+        broadcast: &Broadcast,
+    ) -> Result<Self, VariableError> {
+        check_unique(&dimensions)?;
+        let target_names: Vec<String> = dimensions.iter().map(|d| d.name.clone()).collect();
+        for d in variable.dimensions() {
+            let stretched = d.size == 1 && broadcast.allows(&d.name);
+            if !stretched && !target_names.contains(&d.name) {
+                return Err(VariableError::MissingDimension(
+                    d.name.clone(),
+                    target_names,
+                ));
+            }
+        }
+        let indice_map = dimensions
+            .iter()
+            .map(|target| {
+                let allowed = broadcast.allows(&target.name);
+                let pos = match variable.dimension_position(&target.name) {
+                    Some(pos) => pos,
+                    None if allowed => return Ok(None),
+                    None => {
+                        return Err(VariableError::MissingDimension(
+                            target.name.clone(),
+                            variable.dimension_names(),
+                        ))
+                    }
+                };
+                let size = variable.dimensions()[pos].size;
+                if size == target.size {
+                    Ok(Some(pos))
+                } else if size == 1 && allowed {
+                    Ok(None)
+                } else {
+                    Err(VariableError::SizeMismatch {
+                        name: target.name.clone(),
+                        expected: target.size,
+                        got: size,
+                    })
+                }
+            })
+            .collect::<Result<Vec<Option<usize>>, VariableError>>()?;
+        Ok(Self {
+            variable,
+            dimensions,
+            indice_map,
+        })
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.variable
+    }
+
+    pub fn into_inner(self) -> V {
+        self.variable
+    }
+}
+
+impl<V: Variable> Variable for VarView<V> {
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        let mut inner_index = vec![0; self.variable.dimensions().len()];
+        for (&i, pos) in index.iter().zip(&self.indice_map) {
+            if let Some(pos) = *pos {
+                inner_index[pos] = i;
+            }
+        }
+        self.variable.get(&inner_index)
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        self.variable.attributes()
+    }
+}
+
+/// A variable with one dimension fixed at an index
+///
+/// Slices let derived variables be evaluated one timestep at a time
+/// without materializing the whole grid.
+#[derive(Clone, Debug)]
+pub struct Slice<V> {
+    variable: V,
+    dimensions: Vec<Arc<DimMeta>>,
+    position: usize,
+    index: usize,
+}
+
+impl<V: Variable> Slice<V> {
+    pub fn new(variable: V, name: &str, index: usize) -> Result<Self, VariableError> {
+        let position = variable.dimension_position(name).ok_or_else(|| {
+            VariableError::MissingDimension(name.to_string(), variable.dimension_names())
+        })?;
+        let size = variable.dimensions()[position].size;
+        if index >= size {
+            return Err(VariableError::OutOfBounds {
+                name: name.to_string(),
+                index,
+                size,
+            });
+        }
+        let mut dimensions = variable.dimensions().to_vec();
+        dimensions.remove(position);
+        Ok(Self {
+            variable,
+            dimensions,
+            position,
+            index,
+        })
+    }
+}
+
+impl<V: Variable> Variable for Slice<V> {
+    type Elem = V::Elem;
+
+    fn dimensions(&self) -> &[Arc<DimMeta>] {
+        &self.dimensions
+    }
+
+    fn get(&self, index: &[usize]) -> Self::Elem {
+        let mut inner_index = Vec::with_capacity(index.len() + 1);
+        inner_index.extend_from_slice(&index[..self.position]);
+        inner_index.push(self.index);
+        inner_index.extend_from_slice(&index[self.position..]);
+        self.variable.get(&inner_index)
+    }
+
+    fn attributes(&self) -> Option<&Attributes> {
+        self.variable.attributes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::variable::view::Broadcast;
+    use crate::variable::{dim, VarView, Variable, VariableError, VecVariable};
+
+    fn grid() -> VecVariable<i32> {
+        // t x y with value 100*t + 10*x + y
+        let data = (0..2)
+            .flat_map(|t| (0..3).flat_map(move |x| (0..4).map(move |y| 100 * t + 10 * x + y)))
+            .collect();
+        VecVariable::new(vec![dim("t", 2), dim("x", 3), dim("y", 4)], data).unwrap()
+    }
+
+    #[test]
+    fn transposed() {
+        let v = grid();
+        let view = VarView::new(&v, vec![dim("y", 4), dim("x", 3), dim("t", 2)]).unwrap();
+        assert_eq!(view.shape(), vec![4, 3, 2]);
+        assert_eq!(view.get(&[3, 1, 0]), 13);
+        assert_eq!(view.get(&[0, 2, 1]), 120);
+        assert_eq!(view.get(&[2, 0, 1]), 102);
+    }
+
+    #[test]
+    fn identity() {
+        let v = grid();
+        let view = VarView::new(&v, vec![dim("t", 2), dim("x", 3), dim("y", 4)]).unwrap();
+        assert_eq!(view.to_vec(), v.to_vec());
+    }
+
+    #[test]
+    fn missing_dimensions() {
+        let v = grid();
+        let err = VarView::new(&v, vec![dim("x", 3), dim("y", 4)]).unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::MissingDimension(
+                "t".to_string(),
+                vec!["x".to_string(), "y".to_string()]
+            )
+        );
+
+        let err =
+            VarView::new(&v, vec![dim("t", 2), dim("x", 3), dim("y", 4), dim("z", 1)]).unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::MissingDimension(
+                "z".to_string(),
+                vec!["t".to_string(), "x".to_string(), "y".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let v = grid();
+        let err = VarView::new(&v, vec![dim("t", 2), dim("y", 4), dim("x", 5)]).unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::SizeMismatch {
+                name: "x".to_string(),
+                expected: 5,
+                got: 3
+            }
+        );
+    }
+
+    #[test]
+    fn broadcast_missing_dimensions() {
+        let rainfall = VecVariable::new(vec![dim("t", 3)], vec![1.0, 2.0, 3.0]).unwrap();
+        let target = vec![dim("x", 2), dim("t", 3), dim("y", 2)];
+        let err = VarView::new(&rainfall, target.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::MissingDimension("x".to_string(), vec!["t".to_string()])
+        );
+        let err = VarView::broadcast(
+            &rainfall,
+            target.clone(),
+            &Broadcast::Dims(vec!["x".to_string()]),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::MissingDimension("y".to_string(), vec!["t".to_string()])
+        );
+
+        let view = VarView::broadcast(
+            &rainfall,
+            target,
+            &Broadcast::Dims(vec!["x".to_string(), "y".to_string()]),
+        )
+        .unwrap();
+        assert_eq!(view.shape(), vec![2, 3, 2]);
+        assert_eq!(view.get(&[0, 1, 0]), 2.0);
+        assert_eq!(view.get(&[1, 2, 1]), 3.0);
+    }
+
+    #[test]
+    fn broadcast_unit_dimensions() {
+        let soil = VecVariable::new(vec![dim("t", 1), dim("x", 2)], vec![5, 6]).unwrap();
+        let target = vec![dim("t", 4), dim("x", 2)];
+        let err = VarView::new(&soil, target.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            VariableError::SizeMismatch {
+                name: "t".to_string(),
+                expected: 4,
+                got: 1
+            }
+        );
+        let view = VarView::broadcast(&soil, target, &Broadcast::All).unwrap();
+        assert_eq!(view.to_vec(), vec![5, 6, 5, 6, 5, 6, 5, 6]);
+
+        let view = VarView::broadcast(&soil, vec![dim("x", 2)], &Broadcast::All).unwrap();
+        assert_eq!(view.to_vec(), vec![5, 6]);
+    }
+
+    #[test]
+    fn slice() {
+        let v = grid();
+        let s = (&v).slice("x", 2).unwrap();
+        assert_eq!(s.dimension_names(), vec!["t".to_string(), "y".to_string()]);
+        assert_eq!(s.get(&[1, 3]), 123);
+        assert_eq!(
+            (&v).slice("x", 3).err().unwrap(),
+            VariableError::OutOfBounds {
+                name: "x".to_string(),
+                index: 3,
+                size: 3
+            }
+        );
+    }
+}
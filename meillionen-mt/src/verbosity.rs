@@ -0,0 +1,119 @@
+//! How much wrappers and the runner print to the console
+//!
+//! Diagnostics are `tracing` events. [`set_verbosity`] installs a subscriber
+//! writing them to stderr the first time it is called and changes the level
+//! shown afterwards, so a notebook can turn output up or down between cells.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Once;
+
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{Level, Metadata};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::prelude::*;
+
+#[derive(Clone, Debug, Error, PartialEq)]
+#[error("verbosity must be quiet, normal, verbose or debug but is {0}")]
+pub struct VerbosityError(String);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verbosity {
+    /// Errors only
+    Quiet,
+    /// Errors, warnings and progress such as each trial that runs
+    #[default]
+    Normal,
+    /// Details such as the programs and files models are called with
+    Verbose,
+    /// Everything, including the arrays passed to models
+    Debug,
+}
+
+impl Verbosity {
+    fn allows(self, level: &Level) -> bool {
+        let most = match self {
+            Verbosity::Quiet => Level::ERROR,
+            Verbosity::Normal => Level::INFO,
+            Verbosity::Verbose => Level::DEBUG,
+            Verbosity::Debug => Level::TRACE,
+        };
+        *level <= most
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Verbosity::Quiet,
+            1 => Verbosity::Normal,
+            2 => Verbosity::Verbose,
+            _ => Verbosity::Debug,
+        }
+    }
+
+    /// The verbosity of `-q` and `-v` flags given `quiet` and `verbose`
+    /// times, starting from normal
+    pub fn from_flags(quiet: u64, verbose: u64) -> Self {
+        Self::from_u8((1 + verbose).saturating_sub(quiet).min(3) as u8)
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = VerbosityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quiet" => Ok(Verbosity::Quiet),
+            "normal" => Ok(Verbosity::Normal),
+            "verbose" => Ok(Verbosity::Verbose),
+            "debug" => Ok(Verbosity::Debug),
+            _ => Err(VerbosityError(s.to_string())),
+        }
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static SUBSCRIBER: Once = Once::new();
+
+pub fn verbosity() -> Verbosity {
+    Verbosity::from_u8(VERBOSITY.load(Ordering::Relaxed))
+}
+
+/// Show diagnostics up to `verbosity` on stderr from now on
+///
+/// If the program installed its own tracing subscriber that one is left in
+/// place and decides what is shown.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    SUBSCRIBER.call_once(|| {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_target(false)
+            .with_filter(filter_fn(|metadata: &Metadata<'_>| {
+                self::verbosity().allows(metadata.level())
+            }));
+        let _ = tracing_subscriber::registry().with(layer).try_init();
+    });
+    // the filter is evaluated once per callsite and cached
+    tracing::callsite::rebuild_interest_cache();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::verbosity::Verbosity;
+    use tracing::Level;
+
+    #[test]
+    fn levels() {
+        assert_eq!(Verbosity::from_flags(0, 0), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(2, 0), Verbosity::Quiet);
+        assert_eq!(Verbosity::from_flags(0, 5), Verbosity::Debug);
+        assert!(Verbosity::Quiet.allows(&Level::ERROR));
+        assert!(!Verbosity::Quiet.allows(&Level::INFO));
+        assert!(!Verbosity::Normal.allows(&Level::DEBUG));
+        assert!(Verbosity::Verbose.allows(&Level::DEBUG));
+        assert_eq!("debug".parse::<Verbosity>().unwrap(), Verbosity::Debug);
+        assert!("loud".parse::<Verbosity>().is_err());
+    }
+}
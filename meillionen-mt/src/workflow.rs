@@ -0,0 +1,370 @@
+//! Descriptions of a model for workflow engines
+//!
+//! `meillionen interface --format cwl`, `nextflow` or `snakemake` turns the
+//! sources and sinks a model declares into a CWL CommandLineTool, a Nextflow
+//! process or a Snakemake rule. Each runs the model with `meillionen call`,
+//! which takes one file for each source and sink, so the engine tracks the
+//! files the model reads and writes.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::aliases::Aliases;
+use crate::arg::resource::{FeatherResource, FileResource, NetCDFResource, ParquetResource};
+use crate::experiment::{ResourceConfig, TrialConfig};
+use crate::model::InterfaceArg;
+use crate::transforms::Transforms;
+
+#[derive(Debug, Error)]
+pub enum WorkflowError {
+    #[error("workflow format must be cwl, nextflow or snakemake but is {0}")]
+    UnknownFormat(String),
+    #[error("{0} must be name=path")]
+    InvalidBinding(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorkflowFormat {
+    Cwl,
+    Nextflow,
+    Snakemake,
+}
+
+impl FromStr for WorkflowFormat {
+    type Err = WorkflowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cwl" => Ok(WorkflowFormat::Cwl),
+            "nextflow" => Ok(WorkflowFormat::Nextflow),
+            "snakemake" => Ok(WorkflowFormat::Snakemake),
+            _ => Err(WorkflowError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// The resource kept in the file at `path`, picked by its extension
+///
+/// NetCDF files are read and written as the variable named like the source
+/// or sink.
+pub fn resource_for_path(name: &str, path: &str) -> ResourceConfig {
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let path = path.to_string();
+    match extension.as_deref() {
+        Some("feather") | Some("arrow") => ResourceConfig::Feather(FeatherResource { path }),
+        Some("parquet") => ResourceConfig::Parquet(ParquetResource { path }),
+        Some("nc") => ResourceConfig::Netcdf(NetCDFResource {
+            path,
+            variable: name.to_string(),
+            compression: None,
+        }),
+        _ => ResourceConfig::File(FileResource { path }),
+    }
+}
+
+/// Split a `name=path` argument of `meillionen call`
+pub fn parse_binding(s: &str) -> Result<(String, String), WorkflowError> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), path.to_string()))
+        }
+        _ => Err(WorkflowError::InvalidBinding(s.to_string())),
+    }
+}
+
+/// A trial running `model` on the files bound to its sources and sinks
+pub fn call_trial(
+    model: &str,
+    sources: &[(String, String)],
+    sinks: &[(String, String)],
+) -> TrialConfig {
+    let resources = |bindings: &[(String, String)]| -> BTreeMap<String, ResourceConfig> {
+        bindings
+            .iter()
+            .map(|(name, path)| (name.clone(), resource_for_path(name, path)))
+            .collect()
+    };
+    TrialConfig {
+        name: "call".to_string(),
+        model: model.to_string(),
+        sources: resources(sources),
+        sinks: resources(sinks),
+        args: vec![],
+        env: BTreeMap::new(),
+        rules: BTreeMap::new(),
+        outputs: vec![],
+        aliases: Aliases::default(),
+        transforms: Transforms::default(),
+        tags: BTreeMap::new(),
+        cost: None,
+    }
+}
+
+/// The file an argument is written to or read from unless the workflow
+/// picks another, named after the first resource the model accepts for it
+fn default_file(arg: &InterfaceArg) -> String {
+    let resource = arg.schema["resources"][0].as_str().unwrap_or("");
+    let extension = match resource.rsplit("::").next().unwrap_or("") {
+        "NetCDFResource" | "MultiNetCDFResource" => ".nc",
+        "FeatherResource" => ".feather",
+        "ParquetResource" => ".parquet",
+        _ => "",
+    };
+    format!("{}{}", arg.name, extension)
+}
+
+fn description(arg: &InterfaceArg) -> Option<&str> {
+    arg.schema["description"].as_str().filter(|d| !d.is_empty())
+}
+
+/// A name usable as an identifier in every engine
+fn identifier(name: &str) -> String {
+    let mut id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !id.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        id.insert(0, '_');
+    }
+    id
+}
+
+/// A double quoted string, which CWL, Nextflow and Snakemake all read
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).expect("strings to serialize")
+}
+
+/// Describe `model` for a workflow engine from the arguments of its interface
+pub fn describe(model: &str, args: &[InterfaceArg], format: WorkflowFormat) -> String {
+    let stem = Path::new(model)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| model.to_string());
+    let name = identifier(&stem);
+    let sources: Vec<&InterfaceArg> = args.iter().filter(|a| a.field == "source").collect();
+    let sinks: Vec<&InterfaceArg> = args.iter().filter(|a| a.field == "sink").collect();
+    match format {
+        WorkflowFormat::Cwl => cwl(model, &stem, &sources, &sinks),
+        WorkflowFormat::Nextflow => nextflow(model, &name, &sources, &sinks),
+        WorkflowFormat::Snakemake => snakemake(model, &name, &sources, &sinks),
+    }
+}
+
+fn cwl(model: &str, label: &str, sources: &[&InterfaceArg], sinks: &[&InterfaceArg]) -> String {
+    let mut out = String::new();
+    writeln!(out, "cwlVersion: v1.2").unwrap();
+    writeln!(out, "class: CommandLineTool").unwrap();
+    writeln!(out, "label: {}", quote(label)).unwrap();
+    writeln!(out, "baseCommand: [meillionen, call, {}]", quote(model)).unwrap();
+    // an empty section must be written as a list rather than left null
+    let empty = |args: &[&InterfaceArg]| if args.is_empty() { " []" } else { "" };
+    writeln!(out, "inputs:{}", empty(&[sources, sinks].concat())).unwrap();
+    for arg in sources {
+        writeln!(out, "  {}:", identifier(&arg.name)).unwrap();
+        writeln!(out, "    type: File").unwrap();
+        if let Some(d) = description(arg) {
+            writeln!(out, "    doc: {}", quote(d)).unwrap();
+        }
+        writeln!(out, "    inputBinding:").unwrap();
+        writeln!(out, "      prefix: --source").unwrap();
+        writeln!(
+            out,
+            "      valueFrom: {}",
+            quote(&format!("{}=$(self.path)", arg.name))
+        )
+        .unwrap();
+    }
+    for arg in sinks {
+        writeln!(out, "  {}_path:", identifier(&arg.name)).unwrap();
+        writeln!(out, "    type: string").unwrap();
+        writeln!(out, "    default: {}", quote(&default_file(arg))).unwrap();
+        writeln!(out, "    inputBinding:").unwrap();
+        writeln!(out, "      prefix: --sink").unwrap();
+        writeln!(
+            out,
+            "      valueFrom: {}",
+            quote(&format!("{}=$(self)", arg.name))
+        )
+        .unwrap();
+    }
+    writeln!(out, "outputs:{}", empty(sinks)).unwrap();
+    for arg in sinks {
+        let id = identifier(&arg.name);
+        writeln!(out, "  {}:", id).unwrap();
+        writeln!(out, "    type: File").unwrap();
+        if let Some(d) = description(arg) {
+            writeln!(out, "    doc: {}", quote(d)).unwrap();
+        }
+        writeln!(out, "    outputBinding:").unwrap();
+        writeln!(out, "      glob: $(inputs.{}_path)", id).unwrap();
+    }
+    out
+}
+
+fn nextflow(model: &str, name: &str, sources: &[&InterfaceArg], sinks: &[&InterfaceArg]) -> String {
+    let mut out = String::new();
+    for arg in sources.iter().chain(sinks.iter()) {
+        if let Some(d) = description(arg) {
+            writeln!(out, "// {} {}: {}", arg.field, arg.name, d).unwrap();
+        }
+    }
+    writeln!(out, "process {} {{", name).unwrap();
+    writeln!(out, "    input:").unwrap();
+    for arg in sources {
+        writeln!(out, "    path {}", identifier(&arg.name)).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "    output:").unwrap();
+    for arg in sinks {
+        writeln!(
+            out,
+            "    path {}, emit: {}",
+            quote(&default_file(arg)),
+            identifier(&arg.name)
+        )
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "    script:").unwrap();
+    writeln!(out, "    \"\"\"").unwrap();
+    let mut command = format!("meillionen call {}", model);
+    for arg in sources {
+        write!(
+            command,
+            " --source {}=${{{}}}",
+            arg.name,
+            identifier(&arg.name)
+        )
+        .unwrap();
+    }
+    for arg in sinks {
+        write!(command, " --sink {}={}", arg.name, default_file(arg)).unwrap();
+    }
+    writeln!(out, "    {}", command).unwrap();
+    writeln!(out, "    \"\"\"").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+fn snakemake(
+    model: &str,
+    name: &str,
+    sources: &[&InterfaceArg],
+    sinks: &[&InterfaceArg],
+) -> String {
+    let mut out = String::new();
+    for arg in sources.iter().chain(sinks.iter()) {
+        if let Some(d) = description(arg) {
+            writeln!(out, "# {} {}: {}", arg.field, arg.name, d).unwrap();
+        }
+    }
+    writeln!(out, "rule {}:", name).unwrap();
+    let mut command = format!("meillionen call {}", model);
+    for (section, flag, args) in [("input", "source", sources), ("output", "sink", sinks)] {
+        if args.is_empty() {
+            continue;
+        }
+        writeln!(out, "    {}:", section).unwrap();
+        for arg in args {
+            let id = identifier(&arg.name);
+            writeln!(out, "        {}={},", id, quote(&default_file(arg))).unwrap();
+            write!(command, " --{} {}={{{}.{}}}", flag, arg.name, section, id).unwrap();
+        }
+    }
+    writeln!(out, "    shell:").unwrap();
+    writeln!(out, "        {}", quote(&command)).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::experiment::ResourceConfig;
+    use crate::model::InterfaceArg;
+    use crate::workflow::{call_trial, describe, parse_binding, resource_for_path, WorkflowFormat};
+
+    fn args() -> Vec<InterfaceArg> {
+        vec![
+            InterfaceArg {
+                field: "source".to_string(),
+                name: "daily".to_string(),
+                schema_type: "meillionen::DataFrameSchema".to_string(),
+                schema: json!({"description": "daily weather", "resources": ["meillionen::FeatherResource"]}),
+            },
+            InterfaceArg {
+                field: "sink".to_string(),
+                name: "soil-water".to_string(),
+                schema_type: "meillionen::TensorSchema".to_string(),
+                schema: json!({"resources": ["meillionen::NetCDFResource"]}),
+            },
+        ]
+    }
+
+    #[test]
+    fn snakemake_rule() {
+        assert_eq!(
+            describe("models/simplecrop.py", &args(), WorkflowFormat::Snakemake),
+            "# source daily: daily weather
+rule simplecrop:
+    input:
+        daily=\"daily.feather\",
+    output:
+        soil_water=\"soil-water.nc\",
+    shell:
+        \"meillionen call models/simplecrop.py --source daily={input.daily} --sink soil-water={output.soil_water}\"
+"
+        );
+    }
+
+    #[test]
+    fn cwl_and_nextflow() {
+        let cwl = describe("simplecrop", &args(), WorkflowFormat::Cwl);
+        assert!(cwl.contains("baseCommand: [meillionen, call, \"simplecrop\"]"));
+        assert!(cwl.contains("      valueFrom: \"daily=$(self.path)\"\n"));
+        assert!(
+            cwl.contains("  soil_water_path:\n    type: string\n    default: \"soil-water.nc\"")
+        );
+        assert!(cwl.contains("      glob: $(inputs.soil_water_path)\n"));
+
+        let nextflow = describe("simplecrop", &args(), WorkflowFormat::Nextflow);
+        assert!(nextflow.contains("process simplecrop {\n    input:\n    path daily\n"));
+        assert!(nextflow.contains("    path \"soil-water.nc\", emit: soil_water\n"));
+        assert!(nextflow.contains(
+            "    meillionen call simplecrop --source daily=${daily} --sink soil-water=soil-water.nc\n"
+        ));
+    }
+
+    #[test]
+    fn call_bindings() {
+        assert_eq!(
+            parse_binding("daily=in/daily.feather").unwrap(),
+            ("daily".to_string(), "in/daily.feather".to_string())
+        );
+        assert!(parse_binding("daily").is_err());
+        assert!(parse_binding("=daily.feather").is_err());
+        assert!(matches!(
+            resource_for_path("yearly", "out/yearly.PARQUET"),
+            ResourceConfig::Parquet(_)
+        ));
+        assert!(matches!(
+            resource_for_path("x", "x.csv"),
+            ResourceConfig::File(_)
+        ));
+
+        let sources = [("daily".to_string(), "daily.feather".to_string())];
+        let sinks = [("soil".to_string(), "soil.nc".to_string())];
+        let trial = call_trial("simplecrop", &sources, &sinks);
+        match &trial.sinks["soil"] {
+            ResourceConfig::Netcdf(r) => assert_eq!(r.variable, "soil"),
+            r => panic!("{:?} is not a netcdf resource", r),
+        }
+        assert!(matches!(trial.sources["daily"], ResourceConfig::Feather(_)));
+    }
+}
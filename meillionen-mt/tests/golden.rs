@@ -0,0 +1,137 @@
+//! The deterministic mode maths against bits recorded on x86_64 linux
+//!
+//! Run with `cargo test -p meillionen-mt --features golden --test golden` on
+//! another platform to check it computes the same numbers.
+
+use meillionen_mt::determinism::{
+    cos, exp, expf, format_f64, ln, lnf, powf, powff, powi, powif, set_deterministic,
+};
+
+const EXP: [(f64, u64); 9] = [
+    (-20.5, 0x3e157a3afeed00ab),
+    (-0.7, 0x3fdfc80db9dd5542),
+    (0.1, 0x3ff1aec7b35a00d4),
+    (0.5, 0x3ffa61298e1e069c),
+    (1.0, 0x4005bf0a8b14576a),
+    (std::f64::consts::LN_10, 0x4024000000000001),
+    (3.75, 0x404542b2d0a266e7),
+    (88.7, 0x47ef4705bbffae5c),
+    (700.0, 0x7f0d945df4f8ec8e),
+];
+
+const LN: [(f64, u64); 8] = [
+    (1e-300, 0xc085963447f87fb5),
+    (0.05, 0xc007f7427b73e391),
+    (0.1, 0xc0026bb1bbb55515),
+    (0.5, 0xbfe62e42fefa39ef),
+    (2.0, 0x3fe62e42fefa39ef),
+    (33.0, 0x400bf8d8f4d5b8d1),
+    (1500.0, 0x401d40bcd85bf925),
+    (1e300, 0x4085963447f87fb5),
+];
+
+const COS: [(f64, u64); 6] = [
+    (0.0, 0x3ff0000000000000),
+    (0.1, 0x3fefd712f9a817c1),
+    (1.0, 0x3fe14a280fb5068c),
+    (std::f64::consts::PI, 0xbff0000000000000),
+    (6.0, 0x3feeb9b7097822f5),
+    (1000000.0, 0x3fedf9df9906d32c),
+];
+
+const POWF: [(f64, f64, u64); 5] = [
+    (2.0, 0.5, 0x3ff6a09e667f3bcd),
+    (10.0, 1.54, 0x4041563b4fc29554),
+    (0.3, 2.7, 0x3fa3d6858f50590e),
+    (0.9, 1.0 / 21.0, 0x3fefd7009684b56b),
+    (1.7, -3.3, 0x3fc6381ddcf93584),
+];
+
+const POWI: [(f64, i32, u64); 4] = [
+    (1.1, 3, 0x3ff54bc6a7ef9db4),
+    (0.37, 2, 0x3fc185f06f694467),
+    (-2.5, 7, 0xc08312d000000000),
+    (1.0001, 100, 0x3ff02929d5a06561),
+];
+
+const EXPF: [(f32, u32); 7] = [
+    (-20.5, 0x30abd1d8),
+    (-0.7, 0x3efe406e),
+    (-0.35, 0x3f346670),
+    (0.1, 0x3f8d763e),
+    (1.0, 0x402df854),
+    (5.4, 0x435d680c),
+    (80.0, 0x792abbce),
+];
+
+const LNF: [(f32, u32); 5] = [
+    (0.05, 0xc03fba14),
+    (0.5, 0xbf317218),
+    (2.0, 0x3f317218),
+    (33.0, 0x405fc6c8),
+    (1500.0, 0x40ea05e7),
+];
+
+const POWFF: [(f32, f32, u32); 4] = [
+    (3.6, 0.1, 0x3f917e0c),
+    (0.36, 0.1, 0x3f67232c),
+    (10.0, 1.54, 0x420ab1da),
+    (0.3, 2.7, 0x3d1eb42d),
+];
+
+const POWIF: [(f32, i32, u32); 3] = [
+    (1.1, 3, 0x3faa5e36),
+    (0.6, 2, 0x3eb851ec),
+    (-12.3, 2, 0x43174a3e),
+];
+
+/// Values written by deterministic mode, which must read back bit for bit
+const TEXT: [(f64, &str); 6] = [
+    (0.1 + 0.2, "0.30000000000000004"),
+    (1.0 / 3.0, "0.3333333333333333"),
+    (5e-324, "5e-324"),
+    (1e21, "1e21"),
+    (-0.0, "-0.0"),
+    (273.15, "273.15"),
+];
+
+#[test]
+fn deterministic_maths() {
+    set_deterministic(true);
+    for (x, bits) in EXP.iter() {
+        assert_eq!(exp(*x).to_bits(), *bits, "exp({})", x);
+    }
+    for (x, bits) in LN.iter() {
+        assert_eq!(ln(*x).to_bits(), *bits, "ln({})", x);
+    }
+    for (x, bits) in COS.iter() {
+        assert_eq!(cos(*x).to_bits(), *bits, "cos({})", x);
+    }
+    for (x, y, bits) in POWF.iter() {
+        assert_eq!(powf(*x, *y).to_bits(), *bits, "powf({}, {})", x, y);
+    }
+    for (x, n, bits) in POWI.iter() {
+        assert_eq!(powi(*x, *n).to_bits(), *bits, "powi({}, {})", x, n);
+    }
+    for (x, bits) in EXPF.iter() {
+        assert_eq!(expf(*x).to_bits(), *bits, "expf({})", x);
+    }
+    for (x, bits) in LNF.iter() {
+        assert_eq!(lnf(*x).to_bits(), *bits, "lnf({})", x);
+    }
+    for (x, y, bits) in POWFF.iter() {
+        assert_eq!(powff(*x, *y).to_bits(), *bits, "powff({}, {})", x, y);
+    }
+    for (x, n, bits) in POWIF.iter() {
+        assert_eq!(powif(*x, *n).to_bits(), *bits, "powif({}, {})", x, n);
+    }
+}
+
+#[test]
+fn exact_text() {
+    set_deterministic(true);
+    for (x, text) in TEXT.iter() {
+        assert_eq!(format_f64(*x, 4), *text);
+        assert_eq!(text.parse::<f64>().unwrap().to_bits(), x.to_bits());
+    }
+}